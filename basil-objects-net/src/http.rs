@@ -8,6 +8,7 @@ use std::time::Duration;
 
 use basil_common::{BasilError, Result};
 use basil_bytecode::{BasicObject, MethodDesc, ObjectDescriptor, PropDesc, Value};
+use basil_bytecode::promise::{promise, PromiseValue};
 
 use crate::runtime::TOKIO_RT;
 use base64::Engine;
@@ -184,6 +185,44 @@ impl HttpObj {
         }
     }
 
+    // Async variant of do_request_body: the request runs on the shared Tokio runtime and the
+    // returned PROMISE settles with the body. Last* properties are not updated by async calls.
+    fn spawn_request_body(&mut self, method: reqwest::Method, url: String, body: Option<String>, json_ct: bool, timeout: Option<Duration>) -> Result<Value> {
+        self.ensure_client()?;
+        let client = self.client.as_ref().unwrap().clone();
+        let q = self.default_query.clone();
+        let raise_for_status = self.raise_for_status;
+        let (obj, resolver) = promise();
+        TOKIO_RT.spawn(async move {
+            let mut rb = client.request(method, &url).query(&q);
+            if let Some(d) = timeout { rb = rb.timeout(d); }
+            if let Some(b) = body {
+                if json_ct { rb = rb.header(reqwest::header::CONTENT_TYPE, "application/json"); }
+                rb = rb.body(b);
+            }
+            let resp = match rb.send().await {
+                Ok(r) => r,
+                Err(e) => { resolver.reject(format!("HTTP RequestFailed: {} at {}", e, url)); return; }
+            };
+            let status = resp.status();
+            let final_url = resp.url().to_string();
+            match resp.bytes().await {
+                Ok(bytes) => {
+                    let body_str = String::from_utf8_lossy(&bytes).to_string();
+                    if raise_for_status && (status.is_client_error() || status.is_server_error()) {
+                        let mut snippet = body_str.clone();
+                        if snippet.len() > 512 { snippet.truncate(512); }
+                        resolver.reject(format!("HTTP {} {} at {} — {}", status.as_u16(), status.canonical_reason().unwrap_or(""), final_url, snippet));
+                    } else {
+                        resolver.resolve(PromiseValue::Str(body_str));
+                    }
+                }
+                Err(e) => resolver.reject(format!("HTTP.ReadBody: {}", e)),
+            }
+        });
+        Ok(Value::Object(obj))
+    }
+
     fn do_request_head(&mut self, url: String, timeout: Option<Duration>) -> Result<i64> {
         self.ensure_client()?;
         let client = self.client.as_ref().unwrap().clone();
@@ -486,6 +525,26 @@ impl BasicObject for HttpObj {
                 Ok(Value::Str(body))
             }
 
+            // Async variants: return a PROMISE immediately; AWAIT it for the body
+            ,"GETASYNC"|"DELETEASYNC" => {
+                if !(args.len()==1 || args.len()==2) { return Err(BasilError(format!("HTTP.{} expects url$, timeout_ms%?", method))); }
+                let input_url = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
+                let url = self.resolve_url(&input_url);
+                let to = Self::parse_timeout_override(args, 1);
+                let m = if method.eq_ignore_ascii_case("GETASYNC") { reqwest::Method::GET } else { reqwest::Method::DELETE };
+                self.spawn_request_body(m, url, None, false, to)
+            }
+            ,"POSTASYNC"|"PUTASYNC"|"PATCHASYNC"|"POSTJSONASYNC"|"PUTJSONASYNC"|"PATCHJSONASYNC" => {
+                if !(args.len()==2 || args.len()==3) { return Err(BasilError(format!("HTTP.{} expects url$, body$, timeout_ms%?", method))); }
+                let input_url = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
+                let url = self.resolve_url(&input_url);
+                let body = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
+                let to = Self::parse_timeout_override(args, 2);
+                let up = method.to_ascii_uppercase();
+                let m = if up.starts_with("POST") { reqwest::Method::POST } else if up.starts_with("PUT") { reqwest::Method::PUT } else { reqwest::Method::PATCH };
+                self.spawn_request_body(m, url, Some(body), up.contains("JSON"), to)
+            }
+
             // File I/O
             ,"DOWNLOADTOFILE" => {
                 if !(args.len()==2 || args.len()==3) { return Err(BasilError("HTTP.DownloadToFile expects url$, out_path$, timeout_ms%?".into())); }
@@ -556,6 +615,15 @@ fn descriptor_static() -> ObjectDescriptor {
             MethodDesc { name: "PutJson$".into(), arity: 3, arg_names: vec!["url$".into(), "json$".into(), "timeout_ms%?".into()], return_type: "String (body$)".into() },
             MethodDesc { name: "PatchJson$".into(), arity: 3, arg_names: vec!["url$".into(), "json$".into(), "timeout_ms%?".into()], return_type: "String (body$)".into() },
 
+            MethodDesc { name: "GetAsync".into(), arity: 2, arg_names: vec!["url$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "DeleteAsync".into(), arity: 2, arg_names: vec!["url$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PostAsync".into(), arity: 3, arg_names: vec!["url$".into(), "body$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PutAsync".into(), arity: 3, arg_names: vec!["url$".into(), "body$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PatchAsync".into(), arity: 3, arg_names: vec!["url$".into(), "body$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PostJsonAsync".into(), arity: 3, arg_names: vec!["url$".into(), "json$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PutJsonAsync".into(), arity: 3, arg_names: vec!["url$".into(), "json$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+            MethodDesc { name: "PatchJsonAsync".into(), arity: 3, arg_names: vec!["url$".into(), "json$".into(), "timeout_ms%?".into()], return_type: "PROMISE (body$)".into() },
+
             MethodDesc { name: "DownloadToFile".into(), arity: 3, arg_names: vec!["url$".into(), "out_path$".into(), "timeout_ms%?".into()], return_type: "Int (ok%)".into() },
             MethodDesc { name: "UploadFile$".into(), arity: 4, arg_names: vec!["url$".into(), "file_path$".into(), "field_name$?".into(), "content_type$?".into()], return_type: "String (body$)".into() },
         ],
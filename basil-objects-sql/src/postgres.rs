@@ -8,6 +8,7 @@ use basil_bytecode::{ArrayObj, BasicObject, ElemType, MethodDesc, ObjectDescript
 use sqlx::{Row, Column};
 
 use crate::runtime::TOKIO_RT;
+use basil_bytecode::promise::{promise, PromiseValue};
 use base64::Engine;
 
 fn make_str_array(items: Vec<String>) -> Value {
@@ -75,6 +76,8 @@ impl PgObj {
                 MethodDesc { name: "Execute".into(), arity: 2, arg_names: vec!["sql$".into(), "params$[]?".into()], return_type: "Int (rows%)".into() },
                 MethodDesc { name: "Query$".into(), arity: 2, arg_names: vec!["sql$".into(), "params$[]?".into()], return_type: "String (json$)".into() },
                 MethodDesc { name: "QueryTable$".into(), arity: 2, arg_names: vec!["sql$".into(), "params$[]?".into()], return_type: "String[]".into() },
+                MethodDesc { name: "QueryAsync".into(), arity: 2, arg_names: vec!["sql$".into(), "params$[]?".into()], return_type: "PROMISE (json$)".into() },
+                MethodDesc { name: "ExecuteAsync".into(), arity: 2, arg_names: vec!["sql$".into(), "params$[]?".into()], return_type: "PROMISE (rows%)".into() },
                 MethodDesc { name: "Begin".into(), arity: 0, arg_names: vec![], return_type: "Int (ok%)".into() },
                 MethodDesc { name: "Commit".into(), arity: 0, arg_names: vec![], return_type: "Int (ok%)".into() },
                 MethodDesc { name: "Rollback".into(), arity: 0, arg_names: vec![], return_type: "Int (ok%)".into() },
@@ -351,6 +354,36 @@ impl BasicObject for PgObj {
                     match res { Ok(_)=> Ok(Value::Int(1)), Err(e)=> Err(err("Rollback", e)) }
                 } else { Ok(Value::Int(1)) }
             }
+            // Async variants run on the pool in the background and return a PROMISE right away
+            ,"QUERYASYNC"|"EXECUTEASYNC" => {
+                if args.is_empty() { return Err(bad_arity(method, 1, args.len())); }
+                if self.txn_conn.is_some() { return Err(BasilError(format!("DB_POSTGRES.{}: async calls cannot run inside a transaction; Commit or Rollback first", method))); }
+                let sql = str_arg(&args[0]);
+                let params = Self::parse_params(args);
+                let timeout = self.command_timeout_ms;
+                let pool = self.ensure_pool()?;
+                let is_query = method.eq_ignore_ascii_case("QUERYASYNC");
+                let (obj, resolver) = promise();
+                TOKIO_RT.spawn(async move {
+                    let qb = Self::bind_pg(sqlx::query(&sql), &params);
+                    let limit = Duration::from_millis(timeout);
+                    let r = if is_query {
+                        match tokio::time::timeout(limit, qb.fetch_all(&pool)).await {
+                            Err(_) => Err("SQL(Postgres) Query(Timeout): command timeout".to_string()),
+                            Ok(Err(e)) => Err(format!("SQL(Postgres) Query: {}", e)),
+                            Ok(Ok(rows)) => Ok(PromiseValue::Str(Self::to_json_rows(rows))),
+                        }
+                    } else {
+                        match tokio::time::timeout(limit, qb.execute(&pool)).await {
+                            Err(_) => Err("SQL(Postgres) Execute(Timeout): command timeout".to_string()),
+                            Ok(Err(e)) => Err(format!("SQL(Postgres) Execute: {}", e)),
+                            Ok(Ok(res)) => Ok(PromiseValue::Int(res.rows_affected() as i64)),
+                        }
+                    };
+                    match r { Ok(v) => resolver.resolve(v), Err(e) => resolver.reject(e) }
+                });
+                Ok(Value::Object(obj))
+            }
             ,other => Err(BasilError(format!("Unknown method '{}' on DB_POSTGRES", other)))
         }
     }
@@ -0,0 +1,406 @@
+//! `basilc fcgi`: FastCGI responder for Apache (mod_proxy_fcgi) and nginx (fastcgi_pass).
+//!
+//! One long-lived process listens on a Unix socket (`--socket`) or TCP address (`--bind`) and
+//! answers requests with a pool of worker threads, so pages are compiled once per worker instead
+//! of once per hit. Each connection carries one request at a time (FCGI_MPXS_CONNS = 0); the web
+//! server's params become the page's CGI variables and its output is streamed back as
+//! FCGI_STDOUT records while the page runs.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::web;
+
+pub const FCGI_VERSION_1: u8 = 1;
+pub const FCGI_BEGIN_REQUEST: u8 = 1;
+pub const FCGI_ABORT_REQUEST: u8 = 2;
+pub const FCGI_END_REQUEST: u8 = 3;
+pub const FCGI_PARAMS: u8 = 4;
+pub const FCGI_STDIN: u8 = 5;
+pub const FCGI_STDOUT: u8 = 6;
+pub const FCGI_STDERR: u8 = 7;
+pub const FCGI_DATA: u8 = 8;
+pub const FCGI_GET_VALUES: u8 = 9;
+pub const FCGI_GET_VALUES_RESULT: u8 = 10;
+pub const FCGI_UNKNOWN_TYPE: u8 = 11;
+
+const FCGI_RESPONDER: u16 = 1;
+const FCGI_KEEP_CONN: u8 = 1;
+// protocolStatus values of FCGI_END_REQUEST
+const FCGI_REQUEST_COMPLETE: u8 = 0;
+const FCGI_CANT_MPX_CONN: u8 = 1;
+const FCGI_UNKNOWN_ROLE: u8 = 3;
+
+const MAX_CONTENT: usize = 65_535;
+// Page output is sent once this much is buffered; a page that fails before then still gets a 500
+const STREAM_AT: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// One FastCGI record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub kind: u8,
+    pub request_id: u16,
+    pub content: Vec<u8>,
+}
+
+/// Read one record; `Ok(None)` on a clean end of stream.
+pub fn read_record(r: &mut impl Read) -> io::Result<Option<Record>> {
+    let mut hdr = [0u8; 8];
+    match r.read_exact(&mut hdr) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if hdr[0] != FCGI_VERSION_1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported FastCGI version {}", hdr[0])));
+    }
+    let len = u16::from_be_bytes([hdr[4], hdr[5]]) as usize;
+    let mut content = vec![0u8; len + hdr[6] as usize];
+    r.read_exact(&mut content)?;
+    content.truncate(len);
+    Ok(Some(Record { kind: hdr[1], request_id: u16::from_be_bytes([hdr[2], hdr[3]]), content }))
+}
+
+/// Write `content` as one or more records of type `kind` (an empty slice writes one empty record,
+/// which ends a stream).
+pub fn write_record(w: &mut impl Write, kind: u8, request_id: u16, content: &[u8]) -> io::Result<()> {
+    let mut chunks: Vec<&[u8]> = content.chunks(MAX_CONTENT).collect();
+    if chunks.is_empty() { chunks.push(&[]); }
+    for c in chunks {
+        let pad = (8 - c.len() % 8) % 8;
+        let id = request_id.to_be_bytes();
+        let len = (c.len() as u16).to_be_bytes();
+        w.write_all(&[FCGI_VERSION_1, kind, id[0], id[1], len[0], len[1], pad as u8, 0])?;
+        w.write_all(c)?;
+        w.write_all(&[0u8; 8][..pad])?;
+    }
+    Ok(())
+}
+
+/// Decode FastCGI name-value pairs (PARAMS and GET_VALUES bodies).
+pub fn decode_pairs(mut b: &[u8]) -> Vec<(String, String)> {
+    fn len(b: &mut &[u8]) -> Option<usize> {
+        let first = *b.first()?;
+        if first & 0x80 == 0 { *b = &b[1..]; return Some(first as usize); }
+        if b.len() < 4 { return None; }
+        let n = u32::from_be_bytes([first & 0x7f, b[1], b[2], b[3]]) as usize;
+        *b = &b[4..];
+        Some(n)
+    }
+    let mut out = Vec::new();
+    while !b.is_empty() {
+        let (Some(nl), Some(vl)) = (len(&mut b), len(&mut b)) else { break };
+        if b.len() < nl + vl { break; }
+        out.push((String::from_utf8_lossy(&b[..nl]).into_owned(), String::from_utf8_lossy(&b[nl..nl + vl]).into_owned()));
+        b = &b[nl + vl..];
+    }
+    out
+}
+
+/// Encode name-value pairs in FastCGI form.
+pub fn encode_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    fn len(out: &mut Vec<u8>, n: usize) {
+        if n < 128 { out.push(n as u8); } else { out.extend_from_slice(&(n as u32 | 0x8000_0000).to_be_bytes()); }
+    }
+    let mut out = Vec::new();
+    for (n, v) in pairs {
+        len(&mut out, n.len());
+        len(&mut out, v.len());
+        out.extend_from_slice(n.as_bytes());
+        out.extend_from_slice(v.as_bytes());
+    }
+    out
+}
+
+/// A connection from the web server: Unix socket or TCP.
+pub trait Conn: Read + Write + Send {
+    fn duplicate(&self) -> io::Result<Box<dyn Conn>>;
+}
+
+impl Conn for std::net::TcpStream {
+    fn duplicate(&self) -> io::Result<Box<dyn Conn>> { Ok(Box::new(self.try_clone()?)) }
+}
+
+#[cfg(unix)]
+impl Conn for std::os::unix::net::UnixStream {
+    fn duplicate(&self) -> io::Result<Box<dyn Conn>> { Ok(Box::new(self.try_clone()?)) }
+}
+
+struct FcgiOptions {
+    socket: Option<String>,
+    bind: Option<String>,
+    workers: usize,
+}
+
+fn parse_fcgi_args(args: &[String]) -> Result<FcgiOptions, String> {
+    let mut opts = FcgiOptions {
+        socket: None,
+        bind: None,
+        workers: std::thread::available_parallelism().map(|n| n.get() * 2).unwrap_or(8),
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let a = args[i].as_str();
+        let mut value = || { i += 1; args.get(i).cloned().ok_or_else(|| format!("{} needs a value", a)) };
+        match a {
+            "--socket" | "-s" => opts.socket = Some(value()?),
+            "--bind" | "-b" => opts.bind = Some(value()?),
+            "--workers" => opts.workers = value()?.parse().map_err(|_| "--workers expects a number".to_string())?,
+            other => return Err(format!("unknown option {}", other)),
+        }
+        i += 1;
+    }
+    if opts.socket.is_some() == opts.bind.is_some() {
+        return Err("give exactly one of --socket <path> or --bind <host:port>".into());
+    }
+    opts.workers = opts.workers.max(1);
+    Ok(opts)
+}
+
+pub fn cmd_fcgi(args: &[String]) {
+    let opts = match parse_fcgi_args(args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: basilc fcgi (--socket <path> | --bind <host:port>) [--workers <n>]");
+            std::process::exit(2);
+        }
+    };
+    let workers = opts.workers;
+    let tx = web::spawn_workers(workers, "basil-fcgi", move |conn: Box<dyn Conn>| handle_connection(conn, workers));
+
+    if let Some(addr) = &opts.bind {
+        let listener = match TcpListener::bind(addr.as_str()) {
+            Ok(l) => l,
+            Err(e) => { eprintln!("cannot listen on {}: {}", addr, e); std::process::exit(1); }
+        };
+        let local = listener.local_addr().map(|a| a.to_string()).unwrap_or_else(|_| addr.clone());
+        println!("FastCGI responder listening on {} ({} workers)", local, opts.workers);
+        let _ = io::stdout().flush();
+        for conn in listener.incoming() {
+            match conn {
+                Ok(s) => { if tx.send(Box::new(s)).is_err() { break; } }
+                Err(e) => eprintln!("accept: {}", e),
+            }
+        }
+        return;
+    }
+
+    let path = opts.socket.unwrap_or_default();
+    #[cfg(unix)]
+    {
+        use std::os::unix::net::UnixListener;
+        // A socket file left behind by a previous run would make bind fail
+        if Path::new(&path).exists() { let _ = std::fs::remove_file(&path); }
+        let listener = match UnixListener::bind(&path) {
+            Ok(l) => l,
+            Err(e) => { eprintln!("cannot listen on {}: {}", path, e); std::process::exit(1); }
+        };
+        println!("FastCGI responder listening on {} ({} workers)", path, opts.workers);
+        let _ = io::stdout().flush();
+        for conn in listener.incoming() {
+            match conn {
+                Ok(s) => { if tx.send(Box::new(s)).is_err() { break; } }
+                Err(e) => eprintln!("accept: {}", e),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        eprintln!("Unix sockets are not available on this platform ({}); use --bind <host:port>", path);
+        std::process::exit(2);
+    }
+}
+
+struct Pending {
+    id: u16,
+    keep_conn: bool,
+    params: Vec<u8>,
+    stdin: Vec<u8>,
+    params_done: bool,
+    stdin_done: bool,
+}
+
+fn handle_connection(conn: Box<dyn Conn>, workers: usize) {
+    let Ok(out) = conn.duplicate() else { return };
+    let out = Rc::new(RefCell::new(BufWriter::new(out)));
+    let mut reader = BufReader::new(conn);
+    let mut pending: Option<Pending> = None;
+    loop {
+        let rec = match read_record(&mut reader) {
+            Ok(Some(r)) => r,
+            Ok(None) => return,
+            Err(e) => { eprintln!("fcgi: {}", e); return; }
+        };
+        let mut w = out.borrow_mut();
+        let res = match rec.kind {
+            FCGI_GET_VALUES if rec.request_id == 0 => {
+                let workers = workers.to_string();
+                let answers: Vec<(String, String)> = decode_pairs(&rec.content).into_iter().filter_map(|(name, _)| {
+                    let v = match name.as_str() {
+                        "FCGI_MAX_CONNS" | "FCGI_MAX_REQS" => workers.clone(),
+                        "FCGI_MPXS_CONNS" => "0".into(),
+                        _ => return None,
+                    };
+                    Some((name, v))
+                }).collect();
+                let body = encode_pairs(answers.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+                write_record(&mut *w, FCGI_GET_VALUES_RESULT, 0, &body).and_then(|_| w.flush())
+            }
+            FCGI_BEGIN_REQUEST if rec.content.len() >= 8 => {
+                let role = u16::from_be_bytes([rec.content[0], rec.content[1]]);
+                let keep_conn = rec.content[2] & FCGI_KEEP_CONN != 0;
+                if pending.is_some() {
+                    end_request(&mut *w, rec.request_id, 0, FCGI_CANT_MPX_CONN)
+                } else if role != FCGI_RESPONDER {
+                    end_request(&mut *w, rec.request_id, 0, FCGI_UNKNOWN_ROLE)
+                } else {
+                    pending = Some(Pending { id: rec.request_id, keep_conn, params: Vec::new(), stdin: Vec::new(), params_done: false, stdin_done: false });
+                    Ok(())
+                }
+            }
+            FCGI_ABORT_REQUEST => match pending.take() {
+                Some(p) if p.id == rec.request_id => {
+                    let r = end_request(&mut *w, p.id, 0, FCGI_REQUEST_COMPLETE);
+                    if !p.keep_conn { let _ = w.flush(); return; }
+                    r
+                }
+                other => { pending = other; Ok(()) }
+            },
+            FCGI_PARAMS | FCGI_STDIN | FCGI_DATA => {
+                if let Some(p) = pending.as_mut().filter(|p| p.id == rec.request_id) {
+                    match rec.kind {
+                        FCGI_PARAMS if rec.content.is_empty() => p.params_done = true,
+                        FCGI_PARAMS => p.params.extend_from_slice(&rec.content),
+                        FCGI_STDIN if rec.content.is_empty() => p.stdin_done = true,
+                        FCGI_STDIN if p.stdin.len() + rec.content.len() <= MAX_BODY_BYTES => p.stdin.extend_from_slice(&rec.content),
+                        _ => {} // FCGI_DATA is only used by the Filter role; oversized bodies are cut off
+                    }
+                }
+                Ok(())
+            }
+            kind if rec.request_id == 0 => {
+                write_record(&mut *w, FCGI_UNKNOWN_TYPE, 0, &[kind, 0, 0, 0, 0, 0, 0, 0]).and_then(|_| w.flush())
+            }
+            _ => Ok(()),
+        };
+        drop(w);
+        if res.is_err() { return; }
+
+        if pending.as_ref().map(|p| p.params_done && p.stdin_done).unwrap_or(false) {
+            let p = pending.take().expect("pending request");
+            let ok = respond(&out, p.id, p.params, p.stdin).is_ok();
+            if !ok || !p.keep_conn { return; }
+        }
+    }
+}
+
+fn end_request(w: &mut impl Write, id: u16, app_status: u32, protocol_status: u8) -> io::Result<()> {
+    let s = app_status.to_be_bytes();
+    write_record(w, FCGI_END_REQUEST, id, &[s[0], s[1], s[2], s[3], protocol_status, 0, 0, 0])?;
+    w.flush()
+}
+
+type Out = Rc<RefCell<BufWriter<Box<dyn Conn>>>>;
+
+// FCGI_STDOUT writer for the VM: buffers the start of the page so an early error can still be
+// turned into a 500, then streams records as output accumulates.
+struct StdoutStream {
+    out: Out,
+    id: u16,
+    state: Rc<RefCell<StreamState>>,
+}
+
+#[derive(Default)]
+struct StreamState {
+    buf: Vec<u8>,
+    streaming: bool,
+    failed: bool,
+}
+
+impl Write for StdoutStream {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut st = self.state.borrow_mut();
+        st.buf.extend_from_slice(data);
+        if st.buf.len() >= STREAM_AT && !st.failed {
+            st.streaming = true;
+            let chunk = std::mem::take(&mut st.buf);
+            let mut out = self.out.borrow_mut();
+            // A broken connection only stops the output; the page itself runs to completion
+            if write_record(&mut *out, FCGI_STDOUT, self.id, &chunk).and_then(|_| out.flush()).is_err() { st.failed = true; }
+        }
+        Ok(data.len())
+    }
+    // Only SSE_SEND flushes: send what is buffered now, and report a client that went away
+    fn flush(&mut self) -> io::Result<()> {
+        let mut st = self.state.borrow_mut();
+        if !st.buf.is_empty() && !st.failed {
+            st.streaming = true;
+            let chunk = std::mem::take(&mut st.buf);
+            let mut out = self.out.borrow_mut();
+            if write_record(&mut *out, FCGI_STDOUT, self.id, &chunk).and_then(|_| out.flush()).is_err() { st.failed = true; }
+        }
+        if st.failed { Err(io::Error::new(io::ErrorKind::BrokenPipe, "client went away")) } else { Ok(()) }
+    }
+}
+
+fn cgi_error(status: &str, msg: &str) -> Vec<u8> {
+    format!("Status: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\n", status, msg).into_bytes()
+}
+
+fn respond(out: &Out, id: u16, params: Vec<u8>, stdin: Vec<u8>) -> io::Result<()> {
+    let vars: HashMap<String, String> = decode_pairs(&params).into_iter().collect();
+    let state = Rc::new(RefCell::new(StreamState::default()));
+    let mut errors = String::new();
+
+    match web::resolve_script_with(|name| vars.get(name).filter(|v| !v.is_empty()).cloned()) {
+        None => {
+            let wanted = vars.get("SCRIPT_FILENAME").cloned().unwrap_or_default();
+            state.borrow_mut().buf = cgi_error("404 Not Found", &format!("Basil file not found: {}", wanted));
+        }
+        Some(script) => {
+            let path = Path::new(&script);
+            match web::load_page(path) {
+                Err(e) => {
+                    errors = format!("{}: {}", path.display(), e);
+                    state.borrow_mut().buf = cgi_error("500 Internal Server Error", "500 Internal Server Error");
+                }
+                Ok(page) => {
+                    // The VM writes the header block itself unless the page uses #CGI_NO_HEADER
+                    let sink = StdoutStream { out: out.clone(), id, state: state.clone() };
+                    let no_header = page.directives.cgi_no_header;
+                    if let Err(e) = web::run_program(path, page, vars.clone(), stdin, Box::new(sink)) {
+                        errors = e;
+                        let mut st = state.borrow_mut();
+                        if !st.streaming { st.buf = cgi_error("500 Internal Server Error", "500 Internal Server Error"); }
+                    } else if no_header {
+                        let mut st = state.borrow_mut();
+                        let has_blank = web::header_end(&st.buf).is_some();
+                        if !st.streaming && !has_blank {
+                            st.buf = cgi_error("500 Internal Server Error", "No CGI header sent. Add headers or remove #CGI_NO_HEADER.");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let st = state.borrow();
+    let mut w = out.borrow_mut();
+    if !errors.is_empty() {
+        // The web server copies FCGI_STDERR into its error log
+        eprintln!("{}", errors);
+        write_record(&mut *w, FCGI_STDERR, id, format!("{}\n", errors).as_bytes())?;
+        write_record(&mut *w, FCGI_STDERR, id, &[])?;
+    }
+    if !st.failed {
+        if !st.buf.is_empty() { write_record(&mut *w, FCGI_STDOUT, id, &st.buf)?; }
+        write_record(&mut *w, FCGI_STDOUT, id, &[])?;
+    }
+    end_request(&mut *w, id, if errors.is_empty() { 0 } else { 1 }, FCGI_REQUEST_COMPLETE)
+}
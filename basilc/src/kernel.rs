@@ -0,0 +1,396 @@
+//! `basilc kernel`: a Jupyter kernel, so notebooks can run Basil.
+//!
+//! Jupyter starts `basilc kernel <connection-file>`; the file names the five ZeroMQ ports to bind
+//! and the key messages are signed with (HMAC-SHA256). Cells run in one `repl::Session`, so
+//! globals carry over from cell to cell as they do at the OK prompt. PRINT output goes to the
+//! notebook as stdout, a cell that is a single expression shows its value, and one that is a
+//! call of HTML$ shows as HTML. Tab completion and Shift-Tab help come from the same tables as
+//! the prompt's, with objects described by their `ObjectDescriptor`.
+//!
+//! `basilc kernel install` registers the kernel with Jupyter (kernels/basil/kernel.json).
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use basil_bytecode::{ObjectDescriptor, Value};
+use basil_objects::{register_objects, Registry};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+use crate::repl::{CellKind, Session, SessionSettings};
+use crate::repl_helper::{describe_object, member_lines, needs_more, ReplHelper};
+use crate::runtime::TOKIO_MAIN_RT;
+
+const PROTOCOL_VERSION: &str = "5.3";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// The connection file Jupyter writes for the kernel
+#[derive(Deserialize)]
+struct Connection {
+    #[serde(default = "default_transport")]
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    #[serde(default)]
+    key: String,
+    #[serde(default)]
+    signature_scheme: String,
+}
+
+fn default_transport() -> String { "tcp".to_string() }
+
+impl Connection {
+    fn endpoint(&self, port: u16) -> String {
+        if self.transport == "ipc" { format!("ipc://{}-{}", self.ip, port) } else { format!("{}://{}:{}", self.transport, self.ip, port) }
+    }
+}
+
+// HMAC-SHA256 over a message's header, parent header, metadata and content; no key, no signature
+struct Signer(Vec<u8>);
+
+impl Signer {
+    fn mac(&self, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes any key length");
+        for p in parts { mac.update(p); }
+        mac
+    }
+
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        if self.0.is_empty() { return String::new(); }
+        hex::encode(self.mac(parts).finalize().into_bytes())
+    }
+
+    // Checks a hex signature in constant time
+    fn verify(&self, parts: &[&[u8]], signature: &[u8]) -> bool {
+        if self.0.is_empty() { return true; }
+        hex::decode(signature).is_ok_and(|sig| self.mac(parts).verify_slice(&sig).is_ok())
+    }
+}
+
+/// One message of the Jupyter wire protocol, with the ZeroMQ identities it came from
+struct Message {
+    identities: Vec<Bytes>,
+    header: Json,
+    parent_header: Json,
+    content: Json,
+}
+
+impl Message {
+    fn msg_type(&self) -> &str { self.header["msg_type"].as_str().unwrap_or("") }
+
+    fn decode(msg: ZmqMessage, signer: &Signer) -> Result<Message, String> {
+        let frames = msg.into_vec();
+        let split = frames.iter().position(|f| f.as_ref() == DELIMITER).ok_or("message without <IDS|MSG> delimiter")?;
+        let parts = &frames[split + 1..];
+        if parts.len() < 5 { return Err("message with missing parts".to_string()); }
+        let signed: Vec<&[u8]> = parts[1..5].iter().map(|p| p.as_ref()).collect();
+        if !signer.verify(&signed, &parts[0]) {
+            return Err("message with a bad signature".to_string());
+        }
+        let json = |b: &Bytes| serde_json::from_slice::<Json>(b).map_err(|e| format!("message JSON: {}", e));
+        Ok(Message { identities: frames[..split].to_vec(), header: json(&parts[1])?, parent_header: json(&parts[2])?, content: json(&parts[4])? })
+    }
+
+    fn encode(&self, signer: &Signer) -> ZmqMessage {
+        let parts: Vec<Vec<u8>> = [&self.header, &self.parent_header, &json!({}), &self.content].iter().map(|j| j.to_string().into_bytes()).collect();
+        let signature = signer.sign(&parts.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        let mut frames = self.identities.clone();
+        frames.push(Bytes::from_static(DELIMITER));
+        frames.push(Bytes::from(signature));
+        frames.extend(parts.into_iter().map(Bytes::from));
+        ZmqMessage::try_from(frames).expect("a message has frames")
+    }
+}
+
+// PRINT output of a cell
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().extend_from_slice(buf); Ok(buf.len()) }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+struct Kernel {
+    session: Session,
+    registry: Rc<Registry>,
+    helper: ReplHelper,
+    execution_count: u64,
+    signer: Signer,
+    session_id: String,
+    iopub: PubSocket,
+}
+
+impl Kernel {
+    fn header(&self, msg_type: &str) -> Json {
+        json!({
+            "msg_id": uuid::Uuid::new_v4().to_string(),
+            "session": self.session_id,
+            "username": "basil",
+            "date": chrono::Utc::now().to_rfc3339(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        })
+    }
+
+    fn reply(&self, parent: &Message, msg_type: &str, content: Json) -> Message {
+        Message { identities: parent.identities.clone(), header: self.header(msg_type), parent_header: parent.header.clone(), content }
+    }
+
+    async fn publish(&mut self, parent: &Message, msg_type: &str, content: Json) {
+        let mut msg = self.reply(parent, msg_type, content);
+        msg.identities = vec![Bytes::from(msg_type.to_string())];
+        if let Err(e) = self.iopub.send(msg.encode(&self.signer)).await { eprintln!("kernel: iopub: {}", e); }
+    }
+
+    async fn status(&mut self, parent: &Message, state: &str) {
+        self.publish(parent, "status", json!({ "execution_state": state })).await;
+    }
+
+    /// The reply to a request (None for messages that get none), and whether to shut down
+    async fn handle(&mut self, msg: &Message) -> (Option<Message>, bool) {
+        let content = &msg.content;
+        let (msg_type, reply) = match msg.msg_type() {
+            "kernel_info_request" => ("kernel_info_reply", json!({
+                "status": "ok",
+                "protocol_version": PROTOCOL_VERSION,
+                "implementation": "basil",
+                "implementation_version": env!("CARGO_PKG_VERSION"),
+                "language_info": {
+                    "name": "basil",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "mimetype": "text/x-basil",
+                    "file_extension": ".basil",
+                },
+                "banner": "BASIL - A BASIC Bytecode Interpreter and Compiler",
+                "help_links": [],
+            })),
+            "execute_request" => ("execute_reply", self.execute(msg).await),
+            "complete_request" => ("complete_reply", self.complete(content["code"].as_str().unwrap_or(""), cursor(content))),
+            "inspect_request" => ("inspect_reply", self.inspect(content["code"].as_str().unwrap_or(""), cursor(content))),
+            "is_complete_request" => {
+                let status = if needs_more(content["code"].as_str().unwrap_or("")) { "incomplete" } else { "complete" };
+                ("is_complete_reply", json!({ "status": status, "indent": "" }))
+            }
+            "history_request" => ("history_reply", json!({ "status": "ok", "history": [] })),
+            "comm_info_request" => ("comm_info_reply", json!({ "status": "ok", "comms": {} })),
+            "shutdown_request" => {
+                let restart = content["restart"].as_bool().unwrap_or(false);
+                return (Some(self.reply(msg, "shutdown_reply", json!({ "status": "ok", "restart": restart }))), true);
+            }
+            other => {
+                eprintln!("kernel: ignoring {}", other);
+                return (None, false);
+            }
+        };
+        (Some(self.reply(msg, msg_type, reply)), false)
+    }
+
+    async fn execute(&mut self, msg: &Message) -> Json {
+        let code = msg.content["code"].as_str().unwrap_or("").to_string();
+        let silent = msg.content["silent"].as_bool().unwrap_or(false);
+        if !silent && msg.content["store_history"].as_bool().unwrap_or(true) { self.execution_count += 1; }
+        let count = self.execution_count;
+        if !silent { self.publish(msg, "execute_input", json!({ "code": code, "execution_count": count })).await; }
+
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let result = if code.trim().is_empty() { Ok(CellKind::Statements) } else { self.session.eval_cell(&code, Box::new(Capture(out.clone()))) };
+        let text = String::from_utf8_lossy(&out.borrow()).into_owned();
+        match result {
+            Ok(kind) => {
+                if !silent && !text.is_empty() {
+                    match kind {
+                        CellKind::Statements => self.publish(msg, "stream", json!({ "name": "stdout", "text": text })).await,
+                        CellKind::Expression => self.publish(msg, "execute_result", json!({
+                            "execution_count": count, "data": { "text/plain": text }, "metadata": {},
+                        })).await,
+                        CellKind::Html => self.publish(msg, "execute_result", json!({
+                            "execution_count": count, "data": { "text/html": text, "text/plain": text }, "metadata": {},
+                        })).await,
+                    }
+                }
+                json!({ "status": "ok", "execution_count": count, "payload": [], "user_expressions": {} })
+            }
+            Err(e) => {
+                if !silent && !text.is_empty() { self.publish(msg, "stream", json!({ "name": "stdout", "text": text })).await; }
+                // "runtime error: ..." → ename "runtime error", evalue the rest
+                let (ename, evalue) = e.split_once(": ").unwrap_or(("error", e.as_str()));
+                let error = json!({ "ename": ename, "evalue": evalue, "traceback": [e] });
+                if !silent { self.publish(msg, "error", error.clone()).await; }
+                let mut reply = json!({ "status": "error", "execution_count": count });
+                reply.as_object_mut().unwrap().extend(error.as_object().unwrap().clone());
+                reply
+            }
+        }
+    }
+
+    fn complete(&mut self, code: &str, cursor: usize) -> Json {
+        self.helper.refresh(&self.session);
+        let pos = byte_offset(code, cursor);
+        let (start, found) = self.helper.candidates(code, pos);
+        let mut matches: Vec<String> = found.into_iter().map(|p| p.replacement).collect();
+        matches.dedup();
+        json!({ "status": "ok", "matches": matches, "cursor_start": code[..start].chars().count(), "cursor_end": cursor, "metadata": {} })
+    }
+
+    fn inspect(&self, code: &str, cursor: usize) -> Json {
+        let pos = byte_offset(code, cursor);
+        let start = code[..pos].rfind(|c: char| !is_ident_char(c)).map_or(0, |i| i + 1);
+        let end = code[pos..].find(|c: char| !is_ident_char(c)).map_or(code.len(), |i| pos + i);
+        let word = &code[start..end];
+        let text = if word.is_empty() { None } else {
+            match self.session.globals().find(|(n, _)| n.eq_ignore_ascii_case(word)) {
+                Some((name, v)) => Some(describe_object(&self.registry, v).map_or_else(|| describe_value(name, v), |d| describe_type(&d))),
+                None => self.registry.describe_type(word).ok().map(|d| describe_type(&d)),
+            }
+        };
+        match text {
+            Some(t) => json!({ "status": "ok", "found": true, "data": { "text/plain": t }, "metadata": {} }),
+            None => json!({ "status": "ok", "found": false, "data": {}, "metadata": {} }),
+        }
+    }
+}
+
+fn cursor(content: &Json) -> usize { content["cursor_pos"].as_u64().unwrap_or(0) as usize }
+
+// Jupyter counts cursor positions in characters
+fn byte_offset(s: &str, chars: usize) -> usize { s.char_indices().nth(chars).map_or(s.len(), |(i, _)| i) }
+
+fn is_ident_char(c: char) -> bool { c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '%' | '@' | '&') }
+
+fn describe_type(d: &ObjectDescriptor) -> String {
+    let mut out = format!("{} {}", d.type_name, d.version).trim_end().to_string();
+    if !d.summary.is_empty() { out.push_str(&format!(" — {}", d.summary)); }
+    for l in member_lines(d) { out.push('\n'); out.push_str(&l); }
+    if !d.examples.is_empty() {
+        out.push_str("\nExamples:");
+        for e in &d.examples { out.push_str(&format!("\n  {}", e)); }
+    }
+    out
+}
+
+fn describe_value(name: &str, v: &Value) -> String { format!("{} = {}", name, v) }
+
+/// basilc kernel <connection-file>  |  basilc kernel install
+pub fn cmd_kernel(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("install") => match install() {
+            Ok(path) => println!("Installed the Basil kernel in {}", path.display()),
+            Err(e) => { eprintln!("kernel install: {}", e); std::process::exit(1); }
+        },
+        Some(file) => {
+            let conn: Connection = match fs::read_to_string(file).map_err(|e| e.to_string()).and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string())) {
+                Ok(c) => c,
+                Err(e) => { eprintln!("kernel: connection file {}: {}", file, e); std::process::exit(1); }
+            };
+            if !conn.key.is_empty() && conn.signature_scheme != "hmac-sha256" {
+                eprintln!("kernel: unsupported signature scheme '{}'", conn.signature_scheme);
+                std::process::exit(1);
+            }
+            if let Err(e) = TOKIO_MAIN_RT.block_on(serve(conn)) { eprintln!("kernel: {}", e); std::process::exit(1); }
+        }
+        None => { eprintln!("usage: basilc kernel <connection-file> | basilc kernel install"); std::process::exit(2); }
+    }
+}
+
+async fn serve(conn: Connection) -> Result<(), String> {
+    let bind_err = |port: u16| move |e: zeromq::ZmqError| format!("bind {}: {}", port, e);
+    let mut shell = RouterSocket::new();
+    shell.bind(&conn.endpoint(conn.shell_port)).await.map_err(bind_err(conn.shell_port))?;
+    let mut control = RouterSocket::new();
+    control.bind(&conn.endpoint(conn.control_port)).await.map_err(bind_err(conn.control_port))?;
+    // INPUT reads the kernel's own stdin, so nothing is asked for on this one
+    let mut stdin = RouterSocket::new();
+    stdin.bind(&conn.endpoint(conn.stdin_port)).await.map_err(bind_err(conn.stdin_port))?;
+    let mut iopub = PubSocket::new();
+    iopub.bind(&conn.endpoint(conn.iopub_port)).await.map_err(bind_err(conn.iopub_port))?;
+    let mut hb = RepSocket::new();
+    hb.bind(&conn.endpoint(conn.hb_port)).await.map_err(bind_err(conn.hb_port))?;
+    // Heartbeat: echo every ping, even while a cell runs
+    tokio::spawn(async move {
+        while let Ok(ping) = hb.recv().await {
+            if hb.send(ping).await.is_err() { break; }
+        }
+    });
+
+    let registry = Rc::new({ let mut r = Registry::new(); register_objects(&mut r); r });
+    let mut kernel = Kernel {
+        session: Session::new(SessionSettings::default()),
+        helper: ReplHelper::for_notebook(registry.clone()),
+        registry,
+        execution_count: 0,
+        signer: Signer(conn.key.into_bytes()),
+        session_id: uuid::Uuid::new_v4().to_string(),
+        iopub,
+    };
+    loop {
+        let (received, on_control) = tokio::select! {
+            m = shell.recv() => (m, false),
+            m = control.recv() => (m, true),
+        };
+        let msg = match received.map_err(|e| e.to_string()).and_then(|m| Message::decode(m, &kernel.signer)) {
+            Ok(m) => m,
+            Err(e) => { eprintln!("kernel: {}", e); continue; }
+        };
+        kernel.status(&msg, "busy").await;
+        let (reply, stop) = kernel.handle(&msg).await;
+        if let Some(reply) = reply {
+            let socket = if on_control { &mut control } else { &mut shell };
+            if let Err(e) = socket.send(reply.encode(&kernel.signer)).await { eprintln!("kernel: reply: {}", e); }
+        }
+        kernel.status(&msg, "idle").await;
+        if stop { return Ok(()); }
+    }
+}
+
+// Jupyter's per-user data directory: JUPYTER_DATA_DIR, else the platform's default
+fn jupyter_data_dir() -> Option<PathBuf> {
+    if let Some(d) = env::var_os("JUPYTER_DATA_DIR").filter(|d| !d.is_empty()) { return Some(PathBuf::from(d)); }
+    if cfg!(windows) { return env::var_os("APPDATA").map(|a| PathBuf::from(a).join("jupyter")); }
+    let home = PathBuf::from(env::var_os("HOME")?);
+    if cfg!(target_os = "macos") { return Some(home.join("Library").join("Jupyter")); }
+    let data = env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()).map_or_else(|| home.join(".local").join("share"), PathBuf::from);
+    Some(data.join("jupyter"))
+}
+
+/// Write kernels/basil/kernel.json, pointing Jupyter at this executable
+fn install() -> Result<PathBuf, String> {
+    let dir = jupyter_data_dir().ok_or("no home directory")?.join("kernels").join("basil");
+    let exe = env::current_exe().map_err(|e| format!("locate basilc: {}", e))?;
+    let spec = json!({
+        "argv": [exe.to_string_lossy(), "kernel", "{connection_file}"],
+        "display_name": "Basil",
+        "language": "basil",
+    });
+    fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let file = dir.join("kernel.json");
+    fs::write(&file, serde_json::to_string_pretty(&spec).unwrap_or_default()).map_err(|e| format!("{}: {}", file.display(), e))?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_checked() {
+        let signer = Signer(b"key".to_vec());
+        let parts: [&[u8]; 2] = [b"{}", b"{\"a\":1}"];
+        let sig = signer.sign(&parts);
+        assert!(signer.verify(&parts, sig.as_bytes()));
+        assert!(!signer.verify(&[b"{}", b"{\"a\":2}"], sig.as_bytes()));
+        assert!(!signer.verify(&parts, b"not hex"));
+        assert!(Signer(Vec::new()).verify(&parts, b""));
+    }
+}
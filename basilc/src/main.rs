@@ -48,7 +48,7 @@ use std::time::UNIX_EPOCH;
 use basil_parser::parse;
 use basil_compiler::compile;
 use basil_compiler::service::{analyze_source, CompilerDiagnostics};
-use basil_vm::{VM, MockInputProvider};
+use basil_vm::VM;
 use basil_vm::debug::{Debugger, DebugEvent};
 use basil_lexer::Lexer; // add this near the other use lines
 use basil_bytecode::{serialize_program, deserialize_program};
@@ -57,8 +57,16 @@ use serde_json;
 
 mod template;
 mod repl;
+mod repl_helper;
+mod program_buffer;
 mod runtime;
-use template::{precompile_template, parse_directives_and_bom, Directives};
+mod fcgi;
+#[cfg(feature = "jupyter")]
+mod kernel;
+mod serve;
+mod web;
+mod testing;
+use template::{precompile_template_file, parse_directives_and_bom, Directives};
 
 fn cmd_analyze(path: String, json: bool) {
     let src = match std::fs::read_to_string(&path) {
@@ -94,9 +102,14 @@ fn cmd_debug(path: Option<String>) {
     };
     let abs_path: PathBuf = match fs::canonicalize(&input_path) { Ok(p)=>p, Err(_)=>PathBuf::from(&input_path) };
     let src = match std::fs::read_to_string(&abs_path) { Ok(s)=>s, Err(e)=>{ eprintln!("{}", e); std::process::exit(1);} };
-    let pre = template::PrecompileResult { basil_source: src.clone(), directives: Directives::default() };
-    let ast = match parse(&pre.basil_source) { Ok(a)=>a, Err(e)=>{ eprintln!("parse error: {}", e); std::process::exit(1);} };
-    let program = match compile(&ast) { Ok(p)=>p, Err(e)=>{ eprintln!("compile error: {}", e); std::process::exit(1);} };
+    // Templates are debugged in template coordinates: breakpoints and stops use the template file:line
+    let pre = if src.contains("<?") {
+        match precompile_template_file(&abs_path, &src) { Ok(r)=>r, Err(e)=>{ eprintln!("template error: {}", e); std::process::exit(1); } }
+    } else {
+        template::PrecompileResult { basil_source: src.clone(), directives: Directives::default(), dependencies: Vec::new(), source_map: Default::default() }
+    };
+    let ast = match parse(&pre.basil_source) { Ok(a)=>a, Err(e)=>{ eprintln!("parse error: {}", pre.map_error(e)); std::process::exit(1);} };
+    let program = match compile(&ast) { Ok(p)=>p, Err(e)=>{ eprintln!("compile error: {}", pre.map_error(e)); std::process::exit(1);} };
     let dbg = Debugger::new();
     let rx = dbg.subscribe();
     // Spawn a thread to print JSON events
@@ -115,9 +128,10 @@ fn cmd_debug(path: Option<String>) {
     let mut vm = VM::new(program);
     vm.set_script_path(abs_path.to_string_lossy().to_string());
     vm.set_debugger(dbg);
+    if !pre.source_map.lines.is_empty() { vm.set_source_map(pre.source_map.clone()); }
     if let Err(e) = vm.run() {
         let line = vm.current_line();
-        if line > 0 { eprintln!("runtime error at line {}: {}", line, e); }
+        if line > 0 { eprintln!("runtime error at {}: {}", pre.location(line), e); }
         else { eprintln!("runtime error: {}", e); }
         std::process::exit(1);
     }
@@ -138,7 +152,10 @@ fn canonicalize(cmd: &str) -> &str {
         "clean" => "clean",
         "dev" => "dev",
         "serve" => "serve",
+        "fcgi" => "fcgi",
+        "kernel" => "kernel",
         "doc" => "doc",
+        "resume" => "resume",
         // punny
         "seed" => "init",
         "sprout" => "run",
@@ -149,7 +166,9 @@ fn canonicalize(cmd: &str) -> &str {
         "compost" => "clean",
         "steep" => "dev",
         "greenhouse" => "serve",
+        "simmer" => "fcgi",
         "bouquet" => "doc",
+        "regrow" => "resume",
         "lex" => "lex",
         "chop" => "lex",   // fun alias
         _ => cmd,
@@ -160,7 +179,11 @@ fn print_help() {
     println!("Basil CLI (80's version)\n");
     println!("Commands (aliases in parentheses):");
     println!("  run  (sprout)      Parse → compile → run a .basil file");
+    println!("  resume (regrow)    Continue a program saved by STOP (.basilsnap)");
     println!("  test (cultivate)   Run program in test mode with auto-mocked input");
+    println!("  serve (greenhouse) Serve a site of .basil pages and static files over HTTP");
+    println!("  fcgi (simmer)      FastCGI responder for Apache/nginx (--socket <path> | --bind <host:port>)");
+    println!("  kernel             Jupyter kernel (kernel <connection-file>; kernel install registers it)");
     println!("  lex  (chop)        Dump tokens from a .basil file (debug)");
     //println!("  init (seed)        Create a new Basil project");
     //println!("  build (harvest)    Build project (stub)");
@@ -168,7 +191,6 @@ fn print_help() {
     //println!("  add  (infuse)      Add dependency (stub)");
     //println!("  clean (compost)    Remove build artifacts (stub)");
     //println!("  dev  (steep)       Start dev mode (stub)");
-    //println!("  serve (greenhouse) Serve local HTTP (stub)");
     //println!("  doc  (bouquet)     Generate docs (stub)\n");
     //println!("  --ai               Start AI REPL (streaming chat)");
     println!("  --analyze <file> [--json]  Run compiler analysis and print diagnostics/symbols");
@@ -177,8 +199,10 @@ fn print_help() {
     println!("  basilc <command> [args]\n");
     println!("Examples:");
     println!("  basilc run examples/hello.basil");
+    println!("  basilc run examples/hello.basil --profile");
     println!("  basilc lex examples/hello.basil");
     println!("  basilc test testprogs/bigtest.basil");
+    println!("  basilc serve --root ./site --port 8080");
     println!("  basilc --analyze examples/hello.basil --json");
     println!("  basilc --debug examples/hello.basil");
     //println!("  basilc --ai");
@@ -223,12 +247,53 @@ fn cmd_lex(path: Option<String>) {
     }
 }
 
-fn cmd_run(path: Option<String>) {
+/// Flags accepted by `basilc run` after the command name.
+#[derive(Default)]
+struct RunOptions {
+    profile: bool,
+    profile_out: Option<PathBuf>,
+    record: Option<PathBuf>,
+}
+
+fn parse_run_args(args: &[String]) -> (Option<String>, RunOptions) {
+    let mut path = None;
+    let mut opts = RunOptions::default();
+    let mut i = 0;
+    while i < args.len() {
+        let a = &args[i];
+        if a == "--profile" {
+            opts.profile = true;
+        } else if a == "--profile-out" {
+            if i + 1 >= args.len() { eprintln!("--profile-out requires a file name"); std::process::exit(2); }
+            opts.profile = true;
+            opts.profile_out = Some(PathBuf::from(&args[i + 1]));
+            i += 1;
+        } else if let Some(v) = a.strip_prefix("--profile-out=") {
+            opts.profile = true;
+            opts.profile_out = Some(PathBuf::from(v));
+        } else if a == "--record" {
+            if i + 1 >= args.len() { eprintln!("--record requires a file name"); std::process::exit(2); }
+            opts.record = Some(PathBuf::from(&args[i + 1]));
+            i += 1;
+        } else if let Some(v) = a.strip_prefix("--record=") {
+            opts.record = Some(PathBuf::from(v));
+        } else if a.starts_with("--") {
+            eprintln!("unknown option for run: {}", a);
+            std::process::exit(2);
+        } else if path.is_none() {
+            path = Some(a.clone());
+        }
+        i += 1;
+    }
+    (path, opts)
+}
+
+fn cmd_run(path: Option<String>, opts: RunOptions) {
     // Require a path
     let input_path = match path {
         Some(p) => p,
         None => {
-            eprintln!("usage: basilc run <file.basil>");
+            eprintln!("usage: basilc run <file.basil> [--profile] [--profile-out <file.folded>] [--record <file.inputs>]");
             std::process::exit(2);
         }
     };
@@ -244,6 +309,13 @@ fn cmd_run(path: Option<String>) {
         Ok(p) => p,
         Err(_) => PathBuf::from(&input_path),
     };
+    // Resolve the profile output before we change directory; default is <script>.folded
+    let profile_out = opts.profile.then(|| match &opts.profile_out {
+        Some(p) if p.is_relative() => env::current_dir().map(|d| d.join(p)).unwrap_or_else(|_| p.clone()),
+        Some(p) => p.clone(),
+        None => abs_path.with_extension("folded"),
+    });
+    let record_out = opts.record.as_ref().map(|p| env::current_dir().map(|d| d.join(p)).unwrap_or_else(|_| p.clone()));
     // IMPORTANT (Windows): Do not use canonicalized path for CWD, because it may contain the \\?\ prefix that cmd.exe rejects.
     let script_dir_for_cwd = Path::new(&input_path)
         .parent()
@@ -275,13 +347,13 @@ fn cmd_run(path: Option<String>) {
     }
     let pre = if looks_like_template {
         if env::var("BASIL_DEBUG").ok().as_deref() == Some("1") { eprintln!("[basilc] Using template precompiler in CLI"); }
-        match precompile_template(&src) {
+        match precompile_template_file(&abs_path, &src) {
             Ok(r) => r,
             Err(e) => { eprintln!("template error: {}", e); std::process::exit(1); }
         }
     } else {
         if env::var("BASIL_DEBUG").ok().as_deref() == Some("1") { eprintln!("[basilc] Treating as plain Basil"); }
-        template::PrecompileResult { basil_source: src.clone(), directives: Directives::default() }
+        template::PrecompileResult { basil_source: src.clone(), directives: Directives::default(), dependencies: Vec::new(), source_map: Default::default() }
     };
 
     // Prepare cache fingerprint
@@ -290,7 +362,8 @@ fn cmd_run(path: Option<String>) {
     let source_mtime_ns: u64 = meta.modified().ok()
         .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
         .map(|d| d.as_nanos() as u64)
-        .unwrap_or(0);
+        .unwrap_or(0)
+        ^ pre.dependency_stamp(); // included templates invalidate the cache too
     // Flags for cache: bit0 = short_tags_on, bit1 = templating_used
     let templating_used = src.contains("<?");
     let flags: u32 = (if pre.directives.short_tags_on { 1u32 } else { 0u32 })
@@ -318,8 +391,8 @@ fn cmd_run(path: Option<String>) {
 
     let program = if let Some(p) = program_opt { p } else {
         // Parse → compile the precompiled Basil source
-        let ast = match parse(&pre.basil_source) { Ok(a)=>a, Err(e)=>{ eprintln!("parse error: {}", e); std::process::exit(1);} };
-        let prog = match compile(&ast) { Ok(p)=>p, Err(e)=>{ eprintln!("compile error: {}", e); std::process::exit(1);} };
+        let ast = match parse(&pre.basil_source) { Ok(a)=>a, Err(e)=>{ eprintln!("parse error: {}", pre.map_error(e)); std::process::exit(1);} };
+        let prog = match compile(&ast) { Ok(p)=>p, Err(e)=>{ eprintln!("compile error: {}", pre.map_error(e)); std::process::exit(1);} };
         // Write cache atomically
         let body = serialize_program(&prog);
         let mut hdr = Vec::with_capacity(32 + body.len());
@@ -343,14 +416,74 @@ fn cmd_run(path: Option<String>) {
     let mut vm = VM::new(program);
     // Provide script path so CLASS() can resolve relative class files
     vm.set_script_path(abs_path.to_string_lossy().to_string());
-    if let Err(e) = vm.run() {
+    if opts.profile { vm.enable_profiler(); }
+    if let Some(out) = &record_out {
+        match basil_vm::input::Recorder::create(out, &input_path) {
+            Ok(r) => vm.record_inputs(r),
+            Err(e) => { eprintln!("{}", e); std::process::exit(1); }
+        }
+    }
+    // Child of the CGI front end: the VM sends the header block before the first output
+    let cgi_header = env::var("BASIL_CGI_HEADER").ok();
+    if let Some(h) = &cgi_header { vm.enable_cgi_headers(h); }
+    if cgi_header.is_some() && web::is_router_script(&abs_path) { vm.enable_routing(); }
+    let result = vm.run();
+    if let Some(out) = &profile_out {
+        write_profile(&mut vm, out);
+    }
+    if let Err(e) = result {
+        let line = vm.current_line();
+        if line > 0 { eprintln!("runtime error at {}: {}", pre.location(line), e); }
+        else { eprintln!("runtime error: {}", e); }
+        std::process::exit(1);
+    } else if vm.is_suspended() && cgi_header.is_none() {
+        // In RUN mode, STOP saves the program state next to the script so `basilc resume` can continue it.
+        let snap_path = abs_path.with_extension("basilsnap");
+        save_snapshot_or_wait(&vm, &snap_path);
+    }
+}
+
+/// Print the profile summary to stderr (so program output stays clean) and save collapsed stacks.
+fn write_profile(vm: &mut VM, out: &Path) {
+    let Some(report) = vm.take_profile() else { return };
+    eprint!("{}", report.text(20));
+    match fs::write(out, report.collapsed()) {
+        Ok(()) => eprintln!("\nCollapsed stacks written to {} (feed to flamegraph.pl or inferno-flamegraph)", out.display()),
+        Err(e) => eprintln!("\nwarning: could not write {}: {}", out.display(), e),
+    }
+}
+
+/// Write a STOPped program to `path` and exit. If it cannot be saved (open files, live objects),
+/// fall back to the old behaviour of staying suspended in-process.
+fn save_snapshot_or_wait(vm: &VM, path: &Path) {
+    match vm.snapshot().and_then(|bytes| fs::write(path, bytes).map_err(|e| basil_common::BasilError(e.to_string()))) {
+        Ok(()) => {
+            eprintln!("Program stopped; state saved to {} (continue with: basilc resume {})", path.display(), path.display());
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Program stopped; could not save state: {}", e);
+            loop { std::thread::sleep(std::time::Duration::from_secs(3600)); }
+        }
+    }
+}
+
+fn cmd_resume(path: Option<String>) {
+    let path = match path { Some(p) => PathBuf::from(p), None => { eprintln!("usage: basilc resume <file.basilsnap>"); std::process::exit(2); } };
+    let bytes = match fs::read(&path) { Ok(b) => b, Err(e) => { eprintln!("cannot read {}: {}", path.display(), e); std::process::exit(1); } };
+    let mut vm = match VM::restore(&bytes) { Ok(vm) => vm, Err(e) => { eprintln!("cannot resume {}: {}", path.display(), e); std::process::exit(1); } };
+    let res = if vm.is_suspended() { vm.resume() } else { vm.run() };
+    if let Err(e) = res {
         let line = vm.current_line();
         if line > 0 { eprintln!("runtime error at line {}: {}", line, e); }
         else { eprintln!("runtime error: {}", e); }
         std::process::exit(1);
     } else if vm.is_suspended() {
-        // In RUN mode, when STOP is encountered, remain suspended with no prompt.
-        loop { std::thread::sleep(std::time::Duration::from_secs(3600)); }
+        // Checkpoint again: overwrite the snapshot we resumed from
+        save_snapshot_or_wait(&vm, &path);
+    } else {
+        // Finished; a stale snapshot would replay the tail of the program if resumed again
+        let _ = fs::remove_file(&path);
     }
 }
 
@@ -400,7 +533,11 @@ fn cli_main() {
             }
         }
         "run" => {
-            cmd_run(args.get(0).cloned());
+            let (path, opts) = parse_run_args(&args);
+            cmd_run(path, opts);
+        }
+        "resume" => {
+            cmd_resume(args.first().cloned());
         }
         "cli" => {
             // basilc cli [path]
@@ -411,7 +548,22 @@ fn cli_main() {
         "test" => {
             cmd_test(args);
         }
-        "build" | "fmt" | "add" | "clean" | "dev" | "serve" | "doc" => {
+        "serve" => {
+            serve::cmd_serve(&args);
+        }
+        "fcgi" => {
+            fcgi::cmd_fcgi(&args);
+        }
+        "kernel" => {
+            #[cfg(feature = "jupyter")]
+            kernel::cmd_kernel(&args);
+            #[cfg(not(feature = "jupyter"))]
+            {
+                eprintln!("kernel: this basilc was built without Jupyter support (build with --features jupyter)");
+                std::process::exit(2);
+            }
+        }
+        "build" | "fmt" | "add" | "clean" | "dev" | "doc" => {
             println!("[stub] '{}' not implemented yet in the prototype", cmd);
         }
         "lex" => { cmd_lex(args.get(0).cloned()); }
@@ -455,6 +607,12 @@ fn cgi_main() {
         stdin.take(clen as u64).read_to_end(&mut body).ok();
     }
 
+    // Directives decide the header policy: in automatic mode the child's VM writes the header
+    // block (default header plus RESPONSE@ changes) before the first byte of output
+    let src_for_dirs = fs::read_to_string(&script_path).unwrap_or_default();
+    let (dirs, _) = parse_directives_and_bom(&src_for_dirs);
+    let header = if let Some(h) = dirs.cgi_default_header.clone() { h } else { "Content-Type: text/html; charset=utf-8".to_string() };
+
     // 3) Spawn *this* binary in CLI mode to run the script
     //    We force CLI mode so the child doesn't enter cgi_main() again.
     let self_exe = match env::current_exe() {
@@ -468,7 +626,9 @@ fn cgi_main() {
         }
     };
 
-    let mut child = match Command::new(self_exe)
+    let mut cmd = Command::new(self_exe);
+    if !dirs.cgi_no_header { cmd.env("BASIL_CGI_HEADER", &header); }
+    let mut child = match cmd
         .arg("run")
         .arg(&script_path)
         .env("BASIL_FORCE_MODE", "cli")       // <- prevents recursion
@@ -516,10 +676,6 @@ fn cgi_main() {
         eprintln!("{}", String::from_utf8_lossy(&output.stderr));
     }
 
-    // Parse directives from the source to determine header policy
-    let src_for_dirs = match fs::read_to_string(&script_path) { Ok(s)=>s, Err(_)=>String::new() };
-    let (dirs, _) = parse_directives_and_bom(&src_for_dirs);
-
     let stdout = output.stdout;
 
     if dirs.cgi_no_header {
@@ -536,10 +692,14 @@ fn cgi_main() {
         return;
     }
 
-    // Automatic header mode: send default header (override if provided) right before body
-    let header = if let Some(h) = dirs.cgi_default_header { h } else { "Content-Type: text/html; charset=utf-8".to_string() };
-    println!("{}", header);
-    println!("");
+    // Automatic header mode: the child already sent its headers, unless it failed before any output
+    if stdout.is_empty() && !output.status.success() {
+        println!("Status: 500 Internal Server Error");
+        println!("Content-Type: text/plain; charset=utf-8");
+        println!();
+        println!("500 Internal Server Error");
+        return;
+    }
     io::stdout().write_all(&stdout).ok();
 }
 
@@ -547,53 +707,27 @@ fn cgi_main() {
 // use std::path::{Path, PathBuf};
 
 fn url_decode(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
+    // Decode to bytes first so multi-byte UTF-8 sequences (%C3%A9) survive
     let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
     let mut i = 0;
     while i < bytes.len() {
         if bytes[i] == b'%' && i + 2 < bytes.len() {
-            if let (Ok(h), Ok(l)) = (u8::from_str_radix(&s[i+1..i+2], 16), u8::from_str_radix(&s[i+2..i+3], 16)) {
-                out.push((h << 4 | l) as char);
+            if let (Some(h), Some(l)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push(h << 4 | l);
                 i += 3;
                 continue;
             }
         }
-        out.push(bytes[i] as char);
+        out.push(bytes[i]);
         i += 1;
     }
-    out
+    String::from_utf8_lossy(&out).into_owned()
 }
 
 fn resolve_script_path() -> Option<String> {
-    // 1) Prefer SCRIPT_FILENAME if it points to a .basil file
-    if let Ok(sf) = env::var("SCRIPT_FILENAME") {
-        if sf.ends_with(".basil") && Path::new(&sf).is_file() {
-            return Some(sf);
-        }
-    }
-    // 2) PATH_TRANSLATED is often correct under Action
-    if let Ok(pt) = env::var("PATH_TRANSLATED") {
-        if pt.ends_with(".basil") && Path::new(&pt).is_file() {
-            return Some(pt);
-        }
-    }
-    // 3) Try DOCUMENT_ROOT + PATH_INFO
-    if let (Ok(docroot), Ok(pi)) = (env::var("DOCUMENT_ROOT"), env::var("PATH_INFO")) {
-        let cand = PathBuf::from(docroot).join(pi.trim_start_matches('/'));
-        if cand.extension().and_then(|e| e.to_str()) == Some("basil") && cand.is_file() {
-            return Some(cand.to_string_lossy().into_owned());
-        }
-    }
-    // 4) Try DOCUMENT_ROOT + REQUEST_URI (strip query)
-    if let (Ok(docroot), Ok(uri)) = (env::var("DOCUMENT_ROOT"), env::var("REQUEST_URI")) {
-        let path_part = uri.split('?').next().unwrap_or("");
-        let dec = url_decode(path_part);
-        let cand = PathBuf::from(docroot).join(dec.trim_start_matches('/'));
-        if cand.extension().and_then(|e| e.to_str()) == Some("basil") && cand.is_file() {
-            return Some(cand.to_string_lossy().into_owned());
-        }
-    }
-    None
+    web::resolve_script_with(|name| env::var(name).ok())
 }
 
 
@@ -689,79 +823,117 @@ fn extract_comments_map(src: &str) -> HashMap<u32, Vec<String>> {
     map
 }
 
-fn cmd_test(mut args: Vec<String>) {
+fn cmd_test(args: Vec<String>) {
     if args.is_empty() {
-        eprintln!("usage: basilc test <file.basil> [--seed <u64>] [--max-inputs <n>] [--trace]");
+        eprintln!("usage: basilc test <file.basil|dir>... [--seed <u64>] [--inputs <file.inputs>] [--max-inputs <n>] [--trace] [--filter <text>] [--junit <file.xml>] [--tap] [--update-expected] [--coverage] [--coverage-out <lcov.info>] [--coverage-min <pct>]");
         std::process::exit(2);
     }
-    let mut path = args.remove(0);
-    if !(path.ends_with(".basil") || path.ends_with(".bas")) {
-        eprintln!("Refusing to test a non-.basil/.bas file: {}", path);
-        std::process::exit(2);
+    let mut opts = testing::TestOptions::default();
+    let mut paths: Vec<String> = Vec::new();
+    // Flags that take a value accept both "--flag value" and "--flag=value"
+    let mut i = 0usize;
+    while i < args.len() {
+        let a = &args[i];
+        let (flag, inline) = match a.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f, Some(v.to_string())),
+            _ => (a.as_str(), None),
+        };
+        let takes_value = ["--seed", "--max-inputs", "--coverage-out", "--coverage-min", "--filter", "--junit", "--inputs"].contains(&flag);
+        let value = if !takes_value { None } else if inline.is_some() { inline } else {
+            if i + 1 >= args.len() { eprintln!("{} requires a value", flag); std::process::exit(2); }
+            i += 1;
+            Some(args[i].clone())
+        };
+        match (flag, value) {
+            ("--seed", Some(v)) => opts.seed = v.parse::<u64>().ok(),
+            ("--max-inputs", Some(v)) => opts.max_inputs = v.parse::<usize>().ok(),
+            ("--coverage-out", Some(v)) => { opts.coverage = true; opts.coverage_out = PathBuf::from(v); }
+            ("--coverage-min", Some(v)) => {
+                // A typo must not switch the CI gate off
+                match v.trim_end_matches('%').parse::<f64>() {
+                    Ok(pct) if (0.0..=100.0).contains(&pct) => { opts.coverage = true; opts.coverage_min = Some(pct); }
+                    _ => { eprintln!("--coverage-min expects a percentage from 0 to 100, got '{}'", v); std::process::exit(2); }
+                }
+            }
+            ("--filter", Some(v)) => opts.filter = Some(v),
+            ("--junit", Some(v)) => opts.junit = Some(PathBuf::from(v)),
+            ("--inputs", Some(v)) => opts.inputs = Some(PathBuf::from(v)),
+            ("--trace", _) => opts.trace = true,
+            ("--coverage", _) => opts.coverage = true,
+            ("--tap", _) => opts.tap = true,
+            ("--update-expected", _) => opts.update_expected = true,
+            _ if !a.starts_with("--") => paths.push(a.clone()),
+            // Unknown flag; ignore
+            _ => {}
+        }
+        i += 1;
     }
-    // If a .bas file was provided but doesn't exist, try .basil fallback
-    if !std::path::Path::new(&path).exists() {
-        if path.to_ascii_lowercase().ends_with(".bas") {
-            let mut p = std::path::PathBuf::from(&path);
-            p.set_extension("basil");
+
+    let mut files: Vec<String> = Vec::new();
+    for path in &paths {
+        if Path::new(path).is_dir() {
+            files.extend(testing::discover(Path::new(path)));
+            continue;
+        }
+        let mut path = path.clone();
+        if !(path.ends_with(".basil") || path.ends_with(".bas")) {
+            eprintln!("Refusing to test a non-.basil/.bas file: {}", path);
+            std::process::exit(2);
+        }
+        // If a .bas file was provided but doesn't exist, try .basil fallback
+        if !Path::new(&path).exists() && path.to_ascii_lowercase().ends_with(".bas") {
+            let p = Path::new(&path).with_extension("basil");
             if p.exists() { path = p.to_string_lossy().to_string(); }
         }
+        files.push(path);
     }
 
-    let mut seed_opt: Option<u64> = None;
-    let mut max_inputs: Option<usize> = None;
-    let mut trace = false;
+    // A single script without TEST blocks or an .expected file gets the plain mock-input run
+    if let [only] = &files[..] {
+        if paths.len() == 1 && !Path::new(&paths[0]).is_dir() && opts.junit.is_none() && !opts.tap
+            && !opts.update_expected && !testing::is_suite(Path::new(only)) {
+            run_mocked(only, &opts);
+        }
+    }
+    if files.is_empty() {
+        eprintln!("no tests found in {}", paths.join(", "));
+        return;
+    }
+    let ok = testing::run_suites(&files, &opts);
+    std::process::exit(if ok { 0 } else { 1 });
+}
 
-    let mut i = 0usize;
-    while i < args.len() {
-        let a = &args[i];
-        if a == "--seed" {
-            if i + 1 >= args.len() { eprintln!("--seed requires a value"); std::process::exit(2); }
-            seed_opt = args[i+1].parse::<u64>().ok();
-            i += 2; continue;
-        } else if a.starts_with("--seed=") {
-            let v = &a[7..]; seed_opt = v.parse::<u64>().ok(); i += 1; continue;
-        } else if a == "--max-inputs" {
-            if i + 1 >= args.len() { eprintln!("--max-inputs requires a value"); std::process::exit(2); }
-            max_inputs = args[i+1].parse::<usize>().ok(); i += 2; continue;
-        } else if a.starts_with("--max-inputs=") {
-            let v = &a[13..]; max_inputs = v.parse::<usize>().ok(); i += 1; continue;
-        } else if a == "--trace" { trace = true; i += 1; continue; }
-        else {
-            // Unknown or extra arg; ignore
-            i += 1; continue;
-        }
-    }
-
-    // Read source like cmd_run
-    let src = match std::fs::read_to_string(&path) {
+/// Read and compile a script for `basilc test`, reusing and refreshing its .basilx cache like
+/// `basilc run`. The error carries the exit code `basilc test` uses for it.
+pub(crate) fn load_test_program(path: &str) -> Result<(basil_bytecode::Program, template::PrecompileResult), (i32, String)> {
+    let src = match std::fs::read_to_string(path) {
         Ok(s) => s,
-        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => { eprintln!("File is not UTF-8 text: {}", path); std::process::exit(3); }
-        Err(e) => { eprintln!("Failed to read {}: {}", path, e); std::process::exit(1); }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => return Err((3, format!("File is not UTF-8 text: {}", path))),
+        Err(e) => return Err((1, format!("Failed to read {}: {}", path, e))),
     };
 
     let looks_like_template = src.contains("<?");
     let pre = if looks_like_template {
-        match precompile_template(&src) { Ok(r)=>r, Err(e)=>{ eprintln!("template error: {}", e); std::process::exit(1); } }
+        precompile_template_file(Path::new(path), &src).map_err(|e| (1, format!("template error: {}", e)))?
     } else {
-        template::PrecompileResult { basil_source: src.clone(), directives: Directives::default() }
+        template::PrecompileResult { basil_source: src.clone(), directives: Directives::default(), dependencies: Vec::new(), source_map: Default::default() }
     };
 
     // Cache path and fingerprint like cmd_run
-    let meta = match fs::metadata(&path) { Ok(m)=>m, Err(e)=>{ eprintln!("stat {}: {}", path, e); std::process::exit(1);} };
+    let meta = fs::metadata(path).map_err(|e| (1, format!("stat {}: {}", path, e)))?;
     let source_size = meta.len();
     let source_mtime_ns: u64 = meta.modified().ok()
         .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
         .map(|d| d.as_nanos() as u64)
-        .unwrap_or(0);
+        .unwrap_or(0)
+        ^ pre.dependency_stamp(); // included templates invalidate the cache too
     let templating_used = src.contains("<?");
     let flags: u32 = (if pre.directives.short_tags_on { 1u32 } else { 0u32 })
                    | (if templating_used { 2u32 } else { 0u32 });
 
-    let mut cache_path = PathBuf::from(&path);
+    let mut cache_path = PathBuf::from(path);
     cache_path.set_extension("basilx");
 
-    let mut program_opt: Option<basil_bytecode::Program> = None;
     if let Ok(bytes) = fs::read(&cache_path) {
         if bytes.len() > 32 && &bytes[0..4] == b"BSLX" {
             let fmt_ver = u32::from_le_bytes([bytes[4],bytes[5],bytes[6],bytes[7]]);
@@ -771,38 +943,82 @@ fn cmd_test(mut args: Vec<String>) {
             let mt = u64::from_le_bytes(bytes[24..32].try_into().unwrap());
             if fmt_ver == 3 && abi_ver == 2 && flags_stored == flags && sz == source_size && mt == source_mtime_ns {
                 let prog_bytes = &bytes[32..];
-                if let Ok(p) = deserialize_program(prog_bytes) { program_opt = Some(p); }
+                if let Ok(p) = deserialize_program(prog_bytes) { return Ok((p, pre)); }
             }
         }
     }
-    let program = if let Some(p) = program_opt { p } else {
-        let ast = match parse(&pre.basil_source) { Ok(a)=>a, Err(e)=>{ eprintln!("parse error: {}", e); std::process::exit(1);} };
-        match compile(&ast) { Ok(p)=>{
-            let body = serialize_program(&p);
-            let mut hdr = Vec::with_capacity(32 + body.len());
-            hdr.extend_from_slice(b"BSLX");
-            hdr.extend_from_slice(&3u32.to_le_bytes());
-            hdr.extend_from_slice(&1u32.to_le_bytes());
-            hdr.extend_from_slice(&flags.to_le_bytes());
-            hdr.extend_from_slice(&source_size.to_le_bytes());
-            hdr.extend_from_slice(&source_mtime_ns.to_le_bytes());
-            hdr.extend_from_slice(&body);
-            let tmp = cache_path.with_extension("basilx.tmp");
-            if let Ok(mut f) = File::create(&tmp) { let _ = f.write_all(&hdr); let _ = f.sync_all(); let _ = fs::rename(&tmp, &cache_path); }
-            p
-        }, Err(e)=>{ eprintln!("compile error: {}", e); std::process::exit(1)} }
-    };
+    let ast = parse(&pre.basil_source).map_err(|e| (1, format!("parse error: {}", pre.map_error(e))))?;
+    let p = compile(&ast).map_err(|e| (1, format!("compile error: {}", pre.map_error(e))))?;
+    let body = serialize_program(&p);
+    let mut hdr = Vec::with_capacity(32 + body.len());
+    hdr.extend_from_slice(b"BSLX");
+    hdr.extend_from_slice(&3u32.to_le_bytes());
+    hdr.extend_from_slice(&1u32.to_le_bytes());
+    hdr.extend_from_slice(&flags.to_le_bytes());
+    hdr.extend_from_slice(&source_size.to_le_bytes());
+    hdr.extend_from_slice(&source_mtime_ns.to_le_bytes());
+    hdr.extend_from_slice(&body);
+    let tmp = cache_path.with_extension("basilx.tmp");
+    if let Ok(mut f) = File::create(&tmp) { let _ = f.write_all(&hdr); let _ = f.sync_all(); let _ = fs::rename(&tmp, &cache_path); }
+    Ok((p, pre))
+}
 
+// The original `basilc test`: run one script with mocked input, straight to stdout
+fn run_mocked(path: &str, opts: &testing::TestOptions) -> ! {
+    let (program, pre) = match load_test_program(path) {
+        Ok(r) => r,
+        Err((code, msg)) => { eprintln!("{}", msg); std::process::exit(code); }
+    };
     let comments_map = extract_comments_map(&pre.basil_source);
-    let seed: u64 = seed_opt.unwrap_or_else(|| {
+    let seed: u64 = opts.seed.unwrap_or_else(|| {
         std::time::SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_nanos() as u64).unwrap_or(0)
     });
-    let mock = MockInputProvider::new(seed);
-    let mut vm = VM::new_with_test(program, mock, trace, Some(path.clone()), Some(comments_map), max_inputs);
-    if let Err(e) = vm.run() {
+    let mock = match testing::input_provider(opts, Path::new(path), seed) {
+        Ok(p) => p,
+        Err(msg) => { eprintln!("{}", msg); std::process::exit(1); }
+    };
+    let mut vm = VM::new_with_test(program, mock, opts.trace, Some(path.to_string()), Some(comments_map), opts.max_inputs);
+    if opts.coverage { vm.enable_coverage(); }
+    let result = vm.run();
+    let below_min = opts.coverage && !write_coverage(&mut vm, path, &opts.coverage_out, opts.coverage_min);
+    if let Err(e) = result {
         let line = vm.current_line();
-        if line > 0 { eprintln!("runtime error at line {}: {}", line, e); }
+        if line > 0 { eprintln!("runtime error at {}: {}", pre.location(line), e); }
         else { eprintln!("runtime error: {}", e); }
         std::process::exit(1);
     }
+    if below_min { std::process::exit(1); }
+    std::process::exit(vm.exit_code().unwrap_or(0));
+}
+
+/// Merge this run into the lcov file and print a summary. Returns false if line coverage
+/// (across everything in the lcov file) is below `min_pct`.
+fn write_coverage(vm: &mut VM, script: &str, out: &Path, min_pct: Option<f64>) -> bool {
+    use basil_vm::coverage::Lcov;
+    let Some(fc) = vm.take_coverage() else { return true };
+    let sf = fs::canonicalize(script).map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|_| script.to_string());
+    let mut lcov = fs::read_to_string(out).map(|t| Lcov::parse(&t)).unwrap_or_default();
+    lcov.add(&sf, &fc);
+    if let Err(e) = fs::write(out, lcov.to_string()) {
+        eprintln!("warning: could not write {}: {}", out.display(), e);
+    }
+    let pct = |hit: usize, found: usize| if found == 0 { 100.0 } else { 100.0 * hit as f64 / found as f64 };
+    let this = &lcov.files[&sf];
+    eprintln!("Coverage for {}: lines {}/{} ({:.1}%), branches {}/{} ({:.1}%)",
+        script, this.lines_hit(), this.lines_found(), pct(this.lines_hit(), this.lines_found()),
+        this.branches_hit(), this.branches_found(), pct(this.branches_hit(), this.branches_found()));
+    let missed = this.missed_ranges();
+    if !missed.is_empty() { eprintln!("  not run: {}", missed); }
+    let (hit, found) = lcov.files.values().fold((0, 0), |(h, f), fc| (h + fc.lines_hit(), f + fc.lines_found()));
+    let total = pct(hit, found);
+    if lcov.files.len() > 1 {
+        eprintln!("Total over {} files in {}: lines {}/{} ({:.1}%)", lcov.files.len(), out.display(), hit, found, total);
+    }
+    match min_pct {
+        Some(min) if total < min => {
+            eprintln!("coverage {:.1}% is below the required {:.1}%", total, min);
+            false
+        }
+        _ => true,
+    }
 }
@@ -0,0 +1,231 @@
+//! The OK prompt's numbered program: classic LIST/DELETE/RENUM editing on a map of line numbers
+//! to code, saved and loaded as a numbered listing (`10 PRINTLN "HI"`) that `basilc run` also runs.
+//! A program loaded from a plain file keeps its line numbers as file line positions and is saved
+//! back the same way, until RENUM moves its lines.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+#[derive(Default)]
+pub struct ProgramBuffer {
+    lines: BTreeMap<usize, String>,
+    // Line numbers are file line positions (the program came from an unnumbered file)
+    plain: bool,
+}
+
+/// `10 PRINTLN "HI"` → (10, `PRINTLN "HI"`): a line that starts with a line number
+pub fn split_line_number(line: &str) -> Option<(usize, &str)> {
+    let t = line.trim();
+    let digits = t.find(|c: char| !c.is_ascii_digit()).unwrap_or(t.len());
+    if digits == 0 || !t[digits..].chars().next().is_none_or(char::is_whitespace) { return None; }
+    Some((t[..digits].parse().ok()?, t[digits..].trim_start()))
+}
+
+/// A LIST or DELETE range: `N`, `N-M`, `-M`, `N-` (or with `,`), and all lines when empty
+pub fn parse_range(arg: &str) -> Option<RangeInclusive<usize>> {
+    let arg = arg.trim();
+    if arg.is_empty() { return Some(0..=usize::MAX); }
+    let bound = |s: &str, default: usize| if s.trim().is_empty() { Some(default) } else { s.trim().parse().ok() };
+    match arg.split_once(['-', ',']) {
+        Some((from, to)) => Some(bound(from, 0)?..=bound(to, usize::MAX)?),
+        None => { let n = arg.parse().ok()?; Some(n..=n) }
+    }
+}
+
+/// RENUM and AUTO arguments: `[start][,step]`, each defaulting to 10
+pub fn parse_start_step(arg: &str) -> Option<(usize, usize)> {
+    let arg = arg.trim();
+    let (start, step) = arg.split_once([',', ' ']).unwrap_or((arg, ""));
+    let num = |s: &str| if s.trim().is_empty() { Some(10) } else { s.trim().parse().ok() };
+    let (start, step) = (num(start)?, num(step)?);
+    (step > 0).then_some((start, step))
+}
+
+impl ProgramBuffer {
+    pub fn clear(&mut self) { self.lines.clear(); self.plain = false; }
+
+    /// Store `code` as line `n`; no code deletes the line
+    pub fn set(&mut self, n: usize, code: &str) {
+        if code.trim().is_empty() { self.lines.remove(&n); } else { self.lines.insert(n, code.to_string()); }
+    }
+
+    /// The lines in `range`, as LIST prints them
+    pub fn listing(&self, range: RangeInclusive<usize>) -> Vec<String> {
+        self.lines.range(range).map(|(n, code)| format!("{} {}", n, code)).collect()
+    }
+
+    /// Remove the lines in `range`, returning how many there were
+    pub fn delete(&mut self, range: RangeInclusive<usize>) -> usize {
+        let doomed: Vec<usize> = self.lines.range(range).map(|(n, _)| *n).collect();
+        for n in &doomed { self.lines.remove(n); }
+        doomed.len()
+    }
+
+    /// The whole program for SAVE and RUN: a numbered listing, or for a plain program each line
+    /// at its position with blank lines between
+    pub fn to_source(&self) -> String {
+        if !self.plain { return self.listing(0..=usize::MAX).iter().map(|l| format!("{}\n", l)).collect(); }
+        let last = self.lines.keys().next_back().copied().unwrap_or(0);
+        (1..=last).map(|n| format!("{}\n", self.lines.get(&n).map_or("", String::as_str))).collect()
+    }
+
+    /// Replace the program with a file's lines. A numbered listing keeps its numbers; any other
+    /// file numbers each nonblank line by its position in the file.
+    pub fn load_source(&mut self, src: &str) {
+        self.lines.clear();
+        let numbered = src.lines().filter(|l| !l.trim().is_empty()).all(|l| split_line_number(l).is_some());
+        self.plain = !numbered;
+        for (i, line) in src.lines().enumerate() {
+            if line.trim().is_empty() { continue; }
+            match split_line_number(line).filter(|_| numbered) {
+                Some((n, code)) => self.set(n, code),
+                None => self.set(i + 1, line.trim_end()),
+            }
+        }
+    }
+
+    /// Renumber from `start` by `step`, rewriting GOTO, GOSUB and RETURN TO targets. Returns a
+    /// warning for each target that names no line (it is left as it was).
+    pub fn renumber(&mut self, start: usize, step: usize) -> Result<Vec<String>, String> {
+        let mut map = BTreeMap::new();
+        for (k, old) in self.lines.keys().enumerate() {
+            let new = k.checked_mul(step).and_then(|o| o.checked_add(start)).ok_or("RENUM: line numbers out of range")?;
+            map.insert(*old, new);
+        }
+        let mut warnings = Vec::new();
+        let mut renumbered = BTreeMap::new();
+        for (old, code) in &self.lines {
+            let new = map[old];
+            let mut missing = Vec::new();
+            renumbered.insert(new, rewrite_targets(code, &map, &mut missing));
+            warnings.extend(missing.into_iter().map(|n| format!("undefined line {} in {}", n, new)));
+        }
+        self.lines = renumbered;
+        self.plain = false;
+        Ok(warnings)
+    }
+}
+
+fn is_word_char(c: char) -> bool { c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '%' | '@' | '&') }
+
+// `code` with the line numbers after GOTO, GOSUB and RETURN TO replaced through `map`; strings
+// and comments are left alone. Numbers not in `map` go to `missing`.
+fn rewrite_targets(code: &str, map: &BTreeMap<usize, usize>, missing: &mut Vec<usize>) -> String {
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    let (mut target_next, mut after_return) = (false, false);
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '"' {
+            let mut escaped = false;
+            rest[1..].char_indices().find(|&(_, ch)| { let end = ch == '"' && !escaped; escaped = ch == '\\' && !escaped; end })
+                .map_or(rest.len(), |(i, _)| i + 2)
+        } else if c == '\'' || c == '#' || rest.starts_with("//") {
+            rest.len()
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            rest.find(|ch: char| !is_word_char(ch)).unwrap_or(rest.len())
+        } else if c.is_ascii_digit() {
+            rest.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        let (text, tail) = rest.split_at(len);
+        rest = tail;
+        if c.is_whitespace() { out.push_str(text); continue; }
+        if c.is_ascii_digit() && target_next {
+            let old: usize = text.parse().unwrap_or(usize::MAX);
+            match map.get(&old) {
+                Some(new) => out.push_str(&new.to_string()),
+                None => { missing.push(old); out.push_str(text); }
+            }
+        } else {
+            out.push_str(text);
+        }
+        let word = if c.is_ascii_alphabetic() { text.to_ascii_uppercase() } else { String::new() };
+        if word == "REM" { out.push_str(rest); break; }
+        target_next = word == "GOTO" || word == "GOSUB" || (after_return && word == "TO");
+        after_return = word == "RETURN";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[(usize, &str)]) -> ProgramBuffer {
+        let mut b = ProgramBuffer::default();
+        for (n, code) in lines { b.set(*n, code); }
+        b
+    }
+
+    #[test]
+    fn ranges_and_arguments() {
+        assert_eq!(parse_range(""), Some(0..=usize::MAX));
+        assert_eq!(parse_range("30"), Some(30..=30));
+        assert_eq!(parse_range("10-40"), Some(10..=40));
+        assert_eq!(parse_range("-40"), Some(0..=40));
+        assert_eq!(parse_range("100-"), Some(100..=usize::MAX));
+        assert_eq!(parse_range("10,40"), Some(10..=40));
+        assert_eq!(parse_range("abc"), None);
+        assert_eq!(parse_start_step(""), Some((10, 10)));
+        assert_eq!(parse_start_step("100"), Some((100, 10)));
+        assert_eq!(parse_start_step("100,5"), Some((100, 5)));
+        assert_eq!(parse_start_step(",5"), Some((10, 5)));
+        assert_eq!(parse_start_step("100,0"), None);
+        assert_eq!(split_line_number("20 PRINTLN 1"), Some((20, "PRINTLN 1")));
+        assert_eq!(split_line_number("20"), Some((20, "")));
+        assert_eq!(split_line_number("1 + 2"), Some((1, "+ 2")));
+        assert_eq!(split_line_number("20x"), None);
+        assert_eq!(split_line_number("PRINTLN 20"), None);
+    }
+
+    #[test]
+    fn list_and_delete_ranges() {
+        let mut b = buffer(&[(10, "A"), (20, "B"), (30, "C"), (40, "D")]);
+        assert_eq!(b.listing(parse_range("20-30").unwrap()), vec!["20 B", "30 C"]);
+        assert_eq!(b.delete(parse_range("-20").unwrap()), 2);
+        assert_eq!(b.listing(parse_range("").unwrap()), vec!["30 C", "40 D"]);
+        b.set(30, "");
+        assert_eq!(b.to_source(), "40 D\n");
+    }
+
+    #[test]
+    fn renum_rewrites_jump_targets() {
+        let mut b = buffer(&[
+            (5, "LET i% = 0"),
+            (7, "LET i% = i% + 1"),
+            (12, "IF i% < 3 THEN GOTO 7"),
+            (15, "GOSUB 100 ' not GOTO 5"),
+            (18, "PRINTLN \"GOTO 7\", i%"),
+            (19, "GOTO 99"),
+            (100, "RETURN TO 18"),
+        ]);
+        let warnings = b.renumber(100, 10).unwrap();
+        assert_eq!(b.to_source(), "\
+100 LET i% = 0
+110 LET i% = i% + 1
+120 IF i% < 3 THEN GOTO 110
+130 GOSUB 160 ' not GOTO 5
+140 PRINTLN \"GOTO 7\", i%
+150 GOTO 99
+160 RETURN TO 140
+");
+        assert_eq!(warnings, vec!["undefined line 99 in 150"]);
+        assert!(buffer(&[(1, "A"), (2, "B")]).renumber(usize::MAX, 10).is_err());
+    }
+
+    #[test]
+    fn numbered_files_round_trip() {
+        let src = "10 PRINTLN \"HI\"\n\n25 GOTO 10\n";
+        let mut b = ProgramBuffer::default();
+        b.load_source(src);
+        assert_eq!(b.listing(0..=usize::MAX), vec!["10 PRINTLN \"HI\"", "25 GOTO 10"]);
+        assert_eq!(b.to_source(), "10 PRINTLN \"HI\"\n25 GOTO 10\n");
+        // A plain program is numbered by line and saved as it was
+        b.load_source("PRINTLN 1\n\n  PRINTLN 2\n");
+        assert_eq!(b.listing(0..=usize::MAX), vec!["1 PRINTLN 1", "3   PRINTLN 2"]);
+        assert_eq!(b.to_source(), "PRINTLN 1\n\n  PRINTLN 2\n");
+        b.renumber(10, 10).unwrap();
+        assert_eq!(b.to_source(), "10 PRINTLN 1\n20   PRINTLN 2\n");
+    }
+}
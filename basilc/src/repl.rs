@@ -1,16 +1,34 @@
-use std::collections::{HashMap, BTreeMap};
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use basil_parser::parse;
 use basil_compiler::compile;
 use basil_bytecode::Value;
 use basil_vm::VM;
+use basil_ast::Expr;
 
-use crate::template::{precompile_template, Directives};
+use crate::template::{precompile_template_file, Directives};
 use basil_bytecode::{serialize_program, deserialize_program};
 use std::time::UNIX_EPOCH;
+use std::rc::Rc;
+
+use basil_objects::{register_objects, Registry};
+use rustyline::history::DefaultHistory;
+use rustyline::{Config, Editor};
+
+use crate::program_buffer::{parse_range, parse_start_step, split_line_number, ProgramBuffer};
+use crate::repl_helper::{describe_object, history_path, member_lines, ReplHelper};
+
+/// What a cell evaluated by `Session::eval_cell` was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellKind {
+    Statements,
+    Expression,
+    /// A call of HTML$ (or HTML), whose value is shown as HTML
+    Html,
+}
 
 #[derive(Default)]
 pub struct SessionSettings {
@@ -42,16 +60,17 @@ impl Session {
         let src = fs::read_to_string(path).map_err(|e| format!("read {}: {}", path, e))?;
         let looks_like_template = src.contains("<?");
         let pre = if looks_like_template {
-            precompile_template(&src).map_err(|e| format!("template error: {}", e))?
+            precompile_template_file(Path::new(path), &src).map_err(|e| format!("template error: {}", e))?
         } else {
-            crate::template::PrecompileResult { basil_source: src.clone(), directives: Directives::default() }
+            crate::template::PrecompileResult { basil_source: src.clone(), directives: Directives::default(), dependencies: Vec::new(), source_map: Default::default() }
         };
         let meta = fs::metadata(path).map_err(|e| format!("stat {}: {}", path, e))?;
         let source_size = meta.len();
         let source_mtime_ns: u64 = meta.modified().ok()
             .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
             .map(|d| d.as_nanos() as u64)
-            .unwrap_or(0);
+            .unwrap_or(0)
+            ^ pre.dependency_stamp();
         let templating_used = src.contains("<?");
         let flags: u32 = (if pre.directives.short_tags_on { 1u32 } else { 0u32 })
                        | (if templating_used { 2u32 } else { 0u32 });
@@ -72,8 +91,8 @@ impl Session {
             }
         }
         let program = if let Some(p) = program_opt { p } else {
-            let ast = parse(&pre.basil_source).map_err(|e| format!("parse error: {}", e))?;
-            let prog = compile(&ast).map_err(|e| format!("compile error: {}", e))?;
+            let ast = parse(&pre.basil_source).map_err(|e| format!("parse error: {}", pre.map_error(e)))?;
+            let prog = compile(&ast).map_err(|e| format!("compile error: {}", pre.map_error(e)))?;
             let body = serialize_program(&prog);
             let mut hdr = Vec::with_capacity(32 + body.len());
             hdr.extend_from_slice(b"BSLX");
@@ -103,7 +122,7 @@ impl Session {
         let run_res = vm.run();
         if let Err(e) = run_res {
             let line = vm.current_line();
-            let msg = if self.settings.show_backtraces { format!("runtime error at line {}: {}", line, e) }
+            let msg = if self.settings.show_backtraces { format!("runtime error at {}: {}", pre.location(line), e) }
                       else { format!("runtime error: {}", e) };
             return Err(msg);
         }
@@ -129,6 +148,11 @@ impl Session {
         }
     }
 
+    /// Globals in the order they were first defined
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> + '_ {
+        self.order.iter().filter_map(|name| self.globals.get(name).map(|v| (name.as_str(), v)))
+    }
+
     pub fn list_globals(&self, filter: Option<&str>) -> Vec<(String, String)> {
         let mut out = Vec::new();
         let filt = filter.map(|s| s.to_ascii_uppercase());
@@ -144,17 +168,34 @@ impl Session {
     }
 
     pub fn eval_snippet(&mut self, src: &str) -> Result<(), String> {
+        self.eval(src, None).map(|_| ())
+    }
+
+    /// Run a notebook cell like a snippet, with its PRINT output going to `out`. Returns what the
+    /// cell was: a single expression, whose value is all it printed, or statements.
+    #[cfg(feature = "jupyter")]
+    pub fn eval_cell(&mut self, src: &str, out: Box<dyn Write>) -> Result<CellKind, String> {
+        self.eval(src, Some(out))
+    }
+
+    fn eval(&mut self, src: &str, out: Option<Box<dyn Write>>) -> Result<CellKind, String> {
         let ast = parse(src).map_err(|e| format!("parse error: {}", e))?;
         // Detect single expression: ignoring line markers
         let mut real_stmts = Vec::new();
         for s in &ast { if !matches!(s, basil_ast::Stmt::Line(_)) { real_stmts.push(s.clone()); } }
+        let mut kind = CellKind::Statements;
         let ast2 = if real_stmts.len() == 1 {
             if let basil_ast::Stmt::ExprStmt(e) = real_stmts.remove(0) {
+                kind = match &e {
+                    Expr::Call { callee, .. } if matches!(&**callee, Expr::Var(n) if n.eq_ignore_ascii_case("HTML$") || n.eq_ignore_ascii_case("HTML")) => CellKind::Html,
+                    _ => CellKind::Expression,
+                };
                 vec![basil_ast::Stmt::Print { expr: e }]
             } else { ast.clone() }
         } else { ast.clone() };
         let prog = compile(&ast2).map_err(|e| format!("compile error: {}", e))?;
         let mut vm = VM::new(prog);
+        if let Some(out) = out { vm.set_output(out); }
         if let Some(p) = &self.script_path { vm.set_script_path(p.clone()); }
         // Seed known globals into this snippet VM
         for name in vm.globals_snapshot().0.iter() { // get names cheaply
@@ -170,7 +211,7 @@ impl Session {
         }
         let (names, values) = vm.globals_snapshot();
         self.merge_globals(&names, &values, Some("<repl>"));
-        Ok(())
+        Ok(kind)
     }
 }
 
@@ -183,7 +224,14 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
     }
 
     // Try rustyline; fallback to stdio
-    let mut rl: Option<rustyline::DefaultEditor> = rustyline::DefaultEditor::new().ok();
+    let registry = Rc::new({ let mut r = Registry::new(); register_objects(&mut r); r });
+    let history_file = history_path();
+    let config = Config::builder().max_history_size(1000).map(|c| c.build()).unwrap_or_default();
+    let mut rl: Option<Editor<ReplHelper, DefaultHistory>> = Editor::with_config(config).ok().map(|mut ed| {
+        ed.set_helper(Some(ReplHelper::new(registry.clone())));
+        if let Some(path) = &history_file { let _ = ed.load_history(path); }
+        ed
+    });
 
     // Old-school banner
     println!("BASIL - A BASIC Bytecode Interpreter and Compiler");
@@ -193,7 +241,7 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
     println!("Enter a command or :help or quit");
 
     // Line-numbered program buffer and last-used filename
-    let mut program_buf: BTreeMap<usize, String> = BTreeMap::new();
+    let mut program = ProgramBuffer::default();
     let mut last_file: String = "_.basil".to_string();
 
     let mut buffer = String::new();
@@ -205,7 +253,17 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
             let _ = io::stdout().flush();
         }
         let line = if let Some(editor) = rl.as_mut() {
-            match editor.readline("") { Ok(l)=>{ if !l.trim().is_empty() { let _=editor.add_history_entry(l.as_str()); } l }, Err(_)=> break }
+            if let Some(helper) = editor.helper_mut() { helper.refresh(&sess); }
+            match editor.readline("") {
+                Ok(l) => {
+                    // Saved as it's entered, since a snippet may EXIT the process
+                    if !l.trim().is_empty() && editor.add_history_entry(l.as_str()).unwrap_or(false) {
+                        if let Some(path) = &history_file { let _ = editor.append_history(path); }
+                    }
+                    l
+                }
+                Err(_) => break,
+            }
         } else {
             // No prompt in stdio mode; just read a line
             let mut l = String::new(); if io::stdin().read_line(&mut l).is_err() { break; } l
@@ -219,53 +277,81 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
 
         // Line-numbered program entry (only when not buffering a snippet)
         if buffer.is_empty() {
-            // Detect leading integer line number followed by optional code
-            let mut di = 0usize;
-            for ch in trimmed.chars() { if ch.is_ascii_digit() { di += ch.len_utf8(); } else { break; } }
-            if di > 0 && (di == trimmed.len() || trimmed[di..].chars().next().map(|c| c.is_whitespace()).unwrap_or(true)) {
-                if let Ok(ln) = trimmed[..di].parse::<usize>() {
-                    let code = trimmed[di..].trim_start();
-                    if code.is_empty() { program_buf.remove(&ln); } else { program_buf.insert(ln, code.to_string()); }
-                    continue;
-                }
+            if let Some((ln, code)) = split_line_number(trimmed) {
+                program.set(ln, code);
+                continue;
             }
-            // CLI-only commands: LIST, CLEAR, RUN, LOAD, SAVE
+            // CLI-only commands: LIST, CLEAR, NEW, RUN, LOAD, SAVE, RENUM, AUTO, DELETE
             let upper = trimmed.to_ascii_uppercase();
-            if upper == "LIST" {
-                for (ln, txt) in &program_buf {
-                    println!("{} {}", ln, txt);
+            let arg = trimmed.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim());
+            if upper == "LIST" || upper.starts_with("LIST ") {
+                match parse_range(arg) {
+                    Some(range) => for l in program.listing(range) { println!("{}", l); },
+                    None => println!("usage: LIST [from-to]"),
                 }
                 continue;
             } else if upper == "CLEAR" {
-                program_buf.clear();
+                program.clear();
+                continue;
+            } else if upper == "NEW" {
+                program.clear();
+                last_file = "_.basil".to_string();
+                continue;
+            } else if let Some(range) = upper.starts_with("DELETE ").then(|| parse_range(arg)).flatten() {
+                // DELETE 10-40 (DELETE("file") is still the builtin)
+                if program.delete(range) == 0 { println!("no lines in {}", arg); }
+                continue;
+            } else if upper == "RENUM" || upper.starts_with("RENUM ") {
+                let Some((start, step)) = parse_start_step(arg) else { println!("usage: RENUM [start][,step]"); continue; };
+                match program.renumber(start, step) {
+                    Ok(warnings) => for w in warnings { eprintln!("{}", w); },
+                    Err(e) => eprintln!("{}", e),
+                }
+                continue;
+            } else if upper == "AUTO" || upper.starts_with("AUTO ") {
+                let Some((mut ln, step)) = parse_start_step(arg) else { println!("usage: AUTO [start][,step]"); continue; };
+                // Offer numbered lines until an empty one (or just the number) is entered
+                loop {
+                    let entry = if let Some(editor) = rl.as_mut() {
+                        match editor.readline_with_initial("", (&format!("{} ", ln), "")) {
+                            Ok(l) => l,
+                            Err(_) => break,
+                        }
+                    } else {
+                        print!("{} ", ln);
+                        let _ = io::stdout().flush();
+                        let mut l = String::new();
+                        if io::stdin().read_line(&mut l).unwrap_or(0) == 0 { break; }
+                        format!("{} {}", ln, l.trim_end_matches(['\r', '\n']))
+                    };
+                    // Without a terminal the offered number isn't part of the entry
+                    match split_line_number(&entry).or(Some((ln, entry.trim()))) {
+                        Some((n, code)) if !code.trim().is_empty() => {
+                            program.set(n, code);
+                            ln = n.saturating_add(step);
+                        }
+                        _ => break,
+                    }
+                }
                 continue;
             } else if upper.starts_with("RUN") {
                 // Extract optional filename (no spaces supported inside path)
                 let parts: Vec<&str> = trimmed.split_whitespace().collect();
                 if parts.len() >= 2 {
                     let file = parts[1];
-                    // Clear buffer and load file (but also run it)
-                    program_buf.clear();
+                    // Load the file into the program (and run it)
                     match fs::read_to_string(file) {
                         Ok(s) => {
-                            for (i, line) in s.lines().enumerate() {
-                                let ln = i + 1;
-                                if !line.trim_end().is_empty() { program_buf.insert(ln, line.to_string()); }
-                            }
+                            program.load_source(&s);
                             last_file = file.to_string();
                         }
                         Err(e) => { eprintln!("load error: {}", e); continue; }
                     }
                     if let Err(e) = sess.run_program(file) { eprintln!("Error running program: {}", e); }
                 } else {
-                    // Save program_buf to last_file (default _.basil) with blank-line gaps, then run
+                    // Save the program to last_file (default _.basil), then run
                     let path = last_file.clone();
-                    let max_ln = program_buf.keys().copied().max().unwrap_or(0);
-                    let mut out = String::new();
-                    if max_ln > 0 {
-                        for i in 1..=max_ln { if let Some(t) = program_buf.get(&i) { out.push_str(t); out.push('\n'); } else { out.push('\n'); } }
-                    }
-                    if let Err(e) = fs::write(&path, out) { eprintln!("save error: {}", e); continue; }
+                    if let Err(e) = fs::write(&path, program.to_source()) { eprintln!("save error: {}", e); continue; }
                     if let Err(e) = sess.run_program(&path) { eprintln!("Error running program: {}", e); }
                 }
                 continue;
@@ -273,13 +359,9 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
                 let parts: Vec<&str> = trimmed.split_whitespace().collect();
                 if parts.len() < 2 { println!("usage: LOAD <filename>"); continue; }
                 let file = parts[1];
-                program_buf.clear();
                 match fs::read_to_string(file) {
                     Ok(s) => {
-                        for (i, line) in s.lines().enumerate() {
-                            let ln = i + 1;
-                            if !line.trim_end().is_empty() { program_buf.insert(ln, line.to_string()); }
-                        }
+                        program.load_source(&s);
                         last_file = file.to_string();
                     }
                     Err(e) => { eprintln!("load error: {}", e); }
@@ -289,18 +371,11 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
                 let parts: Vec<&str> = trimmed.split_whitespace().collect();
                 if parts.len() >= 2 { last_file = parts[1].to_string(); }
                 let path = last_file.clone();
-                let max_ln = program_buf.keys().copied().max().unwrap_or(0);
-                let mut out = String::new();
-                if max_ln > 0 {
-                    for i in 1..=max_ln { if let Some(t) = program_buf.get(&i) { out.push_str(t); out.push('\n'); } else { out.push('\n'); } }
-                }
-                if let Err(e) = fs::write(&path, out) { eprintln!("save error: {}", e); }
+                if let Err(e) = fs::write(&path, program.to_source()) { eprintln!("save error: {}", e); }
                 continue;
             } else if upper == "STATUS" {
                 // Program listing (like LIST)
-                for (ln, txt) in &program_buf {
-                    println!("{} {}", ln, txt);
-                }
+                for l in program.listing(0..=usize::MAX) { println!("{}", l); }
 
                 // Mods compiled into this basilc binary
                 println!("-- MODS --");
@@ -441,7 +516,16 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
                     }
                 }
                 ":methods" => {
-                    println!("methods: reflection metadata not available in this build");
+                    if let Some(name) = parts.get(1) {
+                        let found = sess.globals().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v);
+                        match found.and_then(|v| describe_object(&registry, v)) {
+                            Some(d) => {
+                                println!("{}", d.type_name);
+                                for l in member_lines(&d) { println!("{}", l); }
+                            }
+                            None => println!("not found or not an object"),
+                        }
+                    } else { println!("usage: :methods <var>"); }
                 }
                 ":disasm" => {
                     if let Some(name) = parts.get(1) {
@@ -454,7 +538,12 @@ pub fn start_repl(mut sess: Session, maybe_path: Option<String>) {
                     } else { println!("usage: :disasm <name>"); }
                 }
                 ":history" => {
-                    for (i, h) in sess.history.iter().enumerate() { let first = h.lines().next().unwrap_or(""); println!("{:>4}: {}", i+1, first); }
+                    // The line editor's history includes earlier sessions (~/.basil_history)
+                    let entries: Vec<String> = match rl.as_ref() {
+                        Some(editor) => editor.history().iter().cloned().collect(),
+                        None => sess.history.clone(),
+                    };
+                    for (i, h) in entries.iter().enumerate() { let first = h.lines().next().unwrap_or(""); println!("{:>4}: {}", i+1, first); }
                 }
                 ":save" => {
                     if let Some(file) = parts.get(1) {
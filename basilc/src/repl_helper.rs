@@ -0,0 +1,336 @@
+//! Line editing for the OK prompt: tab completion, multi-line blocks, highlighting and history.
+
+use std::borrow::Cow;
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use basil_bytecode::{ObjectDescriptor, Value};
+use basil_lexer::{keyword_kind, Lexer, TokenKind, KEYWORDS};
+use basil_objects::Registry;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+
+use crate::repl::Session;
+
+// Handled by the prompt itself rather than compiled
+const PROMPT_COMMANDS: &[&str] = &["LIST", "CLEAR", "NEW", "RUN", "LOAD", "SAVE", "RENUM", "AUTO", "DELETE", "STATUS", "RESUME", "QUIT", "SYSTEM"];
+const META_COMMANDS: &[&str] = &[":help", ":vars", ":types", ":methods", ":disasm", ":history", ":save", ":load", ":bt", ":env", ":exit"];
+
+const KEYWORD_COLOR: &str = "\x1b[1;34m";
+const BUILTIN_COLOR: &str = "\x1b[36m";
+const STRING_COLOR: &str = "\x1b[32m";
+const NUMBER_COLOR: &str = "\x1b[33m";
+const COMMENT_COLOR: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// `~/.basil_history`, where the prompt keeps its history between sessions
+pub fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).filter(|h| !h.is_empty())?;
+    Some(PathBuf::from(home).join(".basil_history"))
+}
+
+/// What the object in `v` offers, as the registry describes its type (else as it describes itself)
+pub fn describe_object(registry: &Registry, v: &Value) -> Option<ObjectDescriptor> {
+    let Value::Object(obj) = v else { return None };
+    let obj = obj.borrow();
+    Some(registry.describe_type(obj.type_name()).unwrap_or_else(|_| obj.descriptor()))
+}
+
+struct Global {
+    name: String,
+    // (shown, inserted) for each method and property, when the global holds an object
+    members: Vec<(String, String)>,
+}
+
+/// An object's properties and methods, a line each, as `:methods` lists them
+pub fn member_lines(d: &ObjectDescriptor) -> Vec<String> {
+    let props = d.properties.iter().map(|p| format!("  {} : {}{}", p.name, p.type_name, if p.writable { "" } else { " (read-only)" }));
+    props.chain(d.methods.iter().map(|m| format!("  {}({}) : {}", m.name, m.arg_names.join(", "), m.return_type))).collect()
+}
+
+pub struct ReplHelper {
+    registry: Rc<Registry>,
+    globals: Vec<Global>,
+    color: bool,
+    // Complete the prompt's own commands (LIST, :vars...), which a notebook doesn't have
+    prompt: bool,
+}
+
+impl ReplHelper {
+    pub fn new(registry: Rc<Registry>) -> Self {
+        Self { registry, globals: Vec::new(), color: env::var_os("NO_COLOR").is_none(), prompt: true }
+    }
+
+    /// Completion for notebook cells: Basil only, no prompt commands
+    #[cfg(feature = "jupyter")]
+    pub fn for_notebook(registry: Rc<Registry>) -> Self {
+        Self { prompt: false, color: false, ..Self::new(registry) }
+    }
+
+    /// Pick up the session's globals, so they complete at the next prompt
+    pub fn refresh(&mut self, sess: &Session) {
+        self.globals = sess.globals().map(|(name, v)| {
+            let members = describe_object(&self.registry, v).map(|d| {
+                let methods = d.methods.iter().map(|m| (format!("{}({})", m.name, m.arg_names.join(", ")), format!("{}(", m.name)));
+                methods.chain(d.properties.iter().map(|p| (p.name.clone(), p.name.clone()))).collect()
+            }).unwrap_or_default();
+            Global { name: name.to_string(), members }
+        }).collect();
+    }
+
+    /// Where the word at byte `pos` of `line` starts, and what it could complete to
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let before = &line[..pos];
+        if self.prompt && before.starts_with(':') && !before.contains(char::is_whitespace) {
+            let found = META_COMMANDS.iter().filter(|c| c.starts_with(before)).map(|c| pair(c, c)).collect();
+            return (0, found);
+        }
+        let start = before.rfind(|c: char| !(is_ident_char(c) || c == '.')).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) { return (pos, Vec::new()); }
+
+        // obj@.Met → the object's methods and properties
+        if let Some(dot) = word.rfind('.') {
+            let (var, part) = (&word[..dot], &word[dot + 1..]);
+            let found = self.globals.iter().filter(|g| g.name.eq_ignore_ascii_case(var))
+                .flat_map(|g| g.members.iter())
+                .filter(|(_, insert)| starts_with_ci(insert, part))
+                .map(|(show, insert)| pair(show, insert))
+                .collect();
+            return (start + dot + 1, found);
+        }
+
+        // Keywords and builtins follow the case being typed
+        let lower = !word.chars().any(|c| c.is_ascii_uppercase());
+        let cased = |w: &str| if lower { w.to_ascii_lowercase() } else { w.to_string() };
+        let mut words: Vec<String> = KEYWORDS.iter().map(|w| cased(w))
+            .chain(basil_compiler::BUILTINS.iter().map(|(w, _)| cased(w)))
+            .collect();
+        if self.prompt && before[..start].trim().is_empty() { words.extend(PROMPT_COMMANDS.iter().map(|w| cased(w))); }
+        words.extend(self.globals.iter().map(|g| g.name.clone()));
+        words.retain(|w| starts_with_ci(w, word));
+        words.sort();
+        words.dedup();
+        (start, words.iter().map(|w| pair(w, w)).collect())
+    }
+}
+
+fn pair(show: &str, insert: &str) -> Pair { Pair { display: show.to_string(), replacement: insert.to_string() } }
+
+fn starts_with_ci(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len() && s.is_char_boundary(prefix.len()) && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn is_ident_char(c: char) -> bool { c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '%' | '@' | '&') }
+
+/// Whether `src` opens a block or bracket it doesn't close, so Enter should continue it on a new
+/// line instead of submitting it. Prompt commands, numbered lines and `;;` always submit.
+pub fn needs_more(src: &str) -> bool {
+    let t = src.trim();
+    if t.is_empty() || t.ends_with(";;") || t.starts_with([':', '!']) || t.starts_with(|c: char| c.is_ascii_digit()) { return false; }
+    let first = t.split_whitespace().next().unwrap_or("");
+    if PROMPT_COMMANDS.iter().any(|c| c.eq_ignore_ascii_case(first)) { return false; }
+    // Unterminated strings and the like are for the parser to report
+    let Ok(tokens) = Lexer::new(src).tokenize() else { return false };
+
+    let mut depth = 0i32;
+    let mut brackets = 0i32;
+    let mut at_start = true;
+    let mut after_end = false;
+    // A FUNC or TYPE header opens the block itself; its BEGIN or { doesn't open another
+    let mut header = false;
+    let mut last = TokenKind::Semicolon;
+    for (i, tok) in tokens.iter().enumerate() {
+        let kind = tok.kind.clone();
+        if kind == TokenKind::Eof { break; }
+        if kind == TokenKind::Semicolon { at_start = true; after_end = false; last = kind; continue; }
+        let stmt_start = std::mem::replace(&mut at_start, false);
+        // END IF, END TRY, END TEST...
+        if std::mem::take(&mut after_end) && matches!(kind, TokenKind::If | TokenKind::Func | TokenKind::While | TokenKind::Select | TokenKind::Try | TokenKind::Type | TokenKind::With | TokenKind::Ident) {
+            last = kind;
+            continue;
+        }
+        if stmt_start && header && !matches!(kind, TokenKind::Begin | TokenKind::LBrace) { header = false; }
+        let next = tokens.get(i + 1).map(|t| &t.kind);
+        match kind {
+            TokenKind::LParen | TokenKind::LBracket => brackets += 1,
+            TokenKind::RParen | TokenKind::RBracket => brackets -= 1,
+            TokenKind::Begin | TokenKind::LBrace if header => header = false,
+            TokenKind::Begin | TokenKind::LBrace => depth += 1,
+            TokenKind::RBrace | TokenKind::Next => depth -= 1,
+            TokenKind::End => { depth -= 1; after_end = true; }
+            TokenKind::Func | TokenKind::Type if stmt_start => { depth += 1; header = true; }
+            TokenKind::For | TokenKind::Foreach | TokenKind::Select | TokenKind::Try | TokenKind::With if stmt_start => depth += 1,
+            TokenKind::Ident if stmt_start => {
+                let word = tok.lexeme.to_ascii_uppercase();
+                let opens = match word.as_str() {
+                    "TEST" => next == Some(&TokenKind::String),
+                    "SETUP" | "TEARDOWN" => matches!(next, Some(TokenKind::Semicolon | TokenKind::Eof)),
+                    _ => false,
+                };
+                if opens { depth += 1; }
+            }
+            _ => {}
+        }
+        depth = depth.max(0);
+        last = kind;
+    }
+    depth > 0 || brackets > 0 || matches!(last, TokenKind::Then | TokenKind::Else)
+}
+
+/// `src` with ANSI colors for keywords, builtins, strings, numbers and comments
+pub fn highlight_basil(src: &str) -> String {
+    let mut out = String::with_capacity(src.len() * 2);
+    let mut rest = src;
+    while let Some(c) = rest.chars().next() {
+        let (len, color) = if c == '"' {
+            // to the closing quote, or the end while it's still being typed
+            let mut escaped = false;
+            let end = rest[1..].char_indices().find(|&(_, ch)| { let end = ch == '"' && !escaped; escaped = ch == '\\' && !escaped; end });
+            (end.map_or(rest.len(), |(i, _)| i + 2), Some(STRING_COLOR))
+        } else if c == '\'' || c == '#' || rest.starts_with("//") || (starts_with_ci(rest, "REM") && !rest[3..].starts_with(is_ident_char)) {
+            (rest.find('\n').unwrap_or(rest.len()), Some(COMMENT_COLOR))
+        } else if c.is_ascii_digit() {
+            (rest.find(|ch: char| !(ch.is_ascii_digit() || ch == '.')).unwrap_or(rest.len()), Some(NUMBER_COLOR))
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|ch: char| !is_ident_char(ch)).unwrap_or(rest.len());
+            let upper = rest[..len].to_ascii_uppercase();
+            let color = if keyword_kind(&upper).is_some() { Some(KEYWORD_COLOR) }
+                else if basil_compiler::BUILTINS.iter().any(|(w, _)| *w == upper) { Some(BUILTIN_COLOR) }
+                else { None };
+            (len, color)
+        } else {
+            (c.len_utf8(), None)
+        };
+        let (text, tail) = rest.split_at(len);
+        match color {
+            Some(color) => { out.push_str(color); out.push_str(text); out.push_str(RESET); }
+            None => out.push_str(text),
+        }
+        rest = tail;
+    }
+    out
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if !self.color || line.starts_with([':', '!']) { return Cow::Borrowed(line); }
+        Cow::Owned(highlight_basil(line))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool { self.color }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if needs_more(ctx.input()) { ValidationResult::Incomplete } else { ValidationResult::Valid(None) })
+    }
+}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_blocks_continue_on_the_next_line() {
+        for src in [
+            "WHILE x% < 3 BEGIN",
+            "FUNC Add%(a%, b%)",
+            "FUNC Add%(a%, b%)\nBEGIN",
+            "IF x% > 1 THEN",
+            "IF x% > 1 THEN BEGIN\n  PRINTLN 1;\nELSE",
+            "FOR i% = 1 TO 3",
+            "SELECT CASE x%\n  CASE 1: PRINTLN \"one\";",
+            "TRY\n  RAISE \"x\";\nCATCH e$",
+            "TYPE Person\n  DIM Name$",
+            "WITH r@",
+            "TEST \"adds\"",
+            "PRINTLN LEN(\"ab\" +",
+        ] {
+            assert!(needs_more(src), "should continue: {:?}", src);
+        }
+    }
+
+    #[test]
+    fn complete_input_is_submitted() {
+        for src in [
+            "PRINTLN 1 + 2",
+            "WHILE x% < 3 BEGIN\n  LET x% = x% + 1;\nEND",
+            "FUNC Add%(a%, b%)\nBEGIN\n  RETURN a% + b%;\nEND",
+            "FUNC Add%(a%, b%) BEGIN RETURN a% + b%; END",
+            "FUNC Twice%(a%)\n  RETURN a% * 2;\nEND FUNC",
+            "IF x% > 1 THEN BEGIN\n  PRINTLN 1;\nELSE\n  PRINTLN 2;\nEND",
+            "FOR i% = 1 TO 3 BEGIN\n  PRINTLN i%;\nEND\nNEXT",
+            "FOR i% = 1 TO 3\n  PRINTLN i%;\nNEXT i%",
+            "SELECT CASE x%\n  CASE 1: PRINTLN \"one\";\nEND SELECT",
+            "TRY\n  RAISE \"x\";\nCATCH e$\n  PRINTLN e$;\nEND TRY",
+            "TYPE Person\n  DIM Name$\nEND TYPE",
+        ] {
+            assert!(!needs_more(src), "should submit: {:?}", src);
+            basil_parser::parse(src).unwrap_or_else(|e| panic!("{:?} doesn't parse: {}", src, e));
+        }
+        // The prompt's own input, and what only the parser can judge
+        for src in ["FOR i% = 1 TO 3 ;;", "10 FOR i% = 1 TO 3", "RUN", ":vars", "!ls", "PRINTLN \"abc", "END"] {
+            assert!(!needs_more(src), "should submit: {:?}", src);
+        }
+    }
+
+    fn replacements(helper: &ReplHelper, line: &str) -> (usize, Vec<String>) {
+        let (start, found) = helper.candidates(line, line.len());
+        (start, found.into_iter().map(|p| p.replacement).collect())
+    }
+
+    #[test]
+    fn completes_keywords_builtins_and_commands() {
+        let helper = ReplHelper::new(Rc::new(Registry::new()));
+        assert_eq!(replacements(&helper, "PRINTL"), (0, vec!["PRINTLN".to_string()]));
+        assert_eq!(replacements(&helper, "LET n% = len"), (9, vec!["len".to_string()]));
+        let (_, found) = replacements(&helper, "REA");
+        assert!(found.contains(&"READFILE$".to_string()), "{:?}", found);
+        // Prompt commands only where a command can start
+        assert!(replacements(&helper, "STAT").1.contains(&"STATUS".to_string()));
+        assert!(!replacements(&helper, "PRINTLN STAT").1.contains(&"STATUS".to_string()));
+        assert_eq!(replacements(&helper, ":hi"), (0, vec![":history".to_string()]));
+        assert_eq!(replacements(&helper, "PRINTLN 12").1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn completes_session_globals() {
+        let mut sess = Session::new(Default::default());
+        sess.eval_snippet("LET total% = 3; LET title$ = \"x\";").expect("snippet");
+        let mut helper = ReplHelper::new(Rc::new(Registry::new()));
+        helper.refresh(&sess);
+        assert_eq!(replacements(&helper, "PRINTLN tot"), (8, vec!["total%".to_string()]));
+        assert_eq!(replacements(&helper, "PRINTLN ti").1, vec!["title$".to_string()]);
+    }
+
+    #[test]
+    fn highlights_by_token_kind() {
+        let out = highlight_basil("PRINTLN LEN(\"ab\") + 1 REM done");
+        assert_eq!(out, format!(
+            "{k}PRINTLN{r} {b}LEN{r}({s}\"ab\"{r}) + {n}1{r} {c}REM done{r}",
+            k = KEYWORD_COLOR, b = BUILTIN_COLOR, s = STRING_COLOR, n = NUMBER_COLOR, c = COMMENT_COLOR, r = RESET,
+        ));
+        // A string still being typed runs to the end; names that only start like REM aren't comments
+        assert_eq!(highlight_basil("x$ = \"ab"), format!("x$ = {}\"ab{}", STRING_COLOR, RESET));
+        assert_eq!(highlight_basil("remain%"), "remain%");
+    }
+}
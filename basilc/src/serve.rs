@@ -0,0 +1,572 @@
+//! `basilc serve`: a small HTTP/1.1 server for Basil web pages.
+//!
+//! URLs map to files under the site root. `.basil` pages run in-process (see `web.rs`), anything
+//! else is served as a static file. Connections are handled by a fixed pool of worker threads,
+//! keep-alive is supported, and every request is written to stdout as a combined-format log line.
+//! A page can also hold its connection: WebSocket upgrades hand the socket to WEBSOCKET@, and
+//! `Accept: text/event-stream` requests stream the page's output as it is printed (SSE_SEND).
+//! It is meant for development and for running behind a reverse proxy, not for the open internet.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::web::{self, PageResponse};
+
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+const INDEX_FILES: &[&str] = &["index.basil", "index.html", "index.htm"];
+
+pub struct ServeOptions {
+    pub root: PathBuf,
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+    pub quiet: bool,
+}
+
+pub fn parse_serve_args(args: &[String]) -> Result<ServeOptions, String> {
+    let mut opts = ServeOptions {
+        root: PathBuf::from("."),
+        host: "127.0.0.1".into(),
+        port: 8080,
+        workers: std::thread::available_parallelism().map(|n| n.get() * 2).unwrap_or(8),
+        quiet: false,
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let a = args[i].as_str();
+        let mut value = || { i += 1; args.get(i).cloned().ok_or_else(|| format!("{} needs a value", a)) };
+        match a {
+            "--root" | "-r" => opts.root = PathBuf::from(value()?),
+            "--host" => opts.host = value()?,
+            "--port" | "-p" => opts.port = value()?.parse().map_err(|_| "--port expects a number".to_string())?,
+            "--workers" => opts.workers = value()?.parse().map_err(|_| "--workers expects a number".to_string())?,
+            "--quiet" | "-q" => opts.quiet = true,
+            other if !other.starts_with('-') => opts.root = PathBuf::from(other),
+            other => return Err(format!("unknown option {}", other)),
+        }
+        i += 1;
+    }
+    opts.workers = opts.workers.max(1);
+    Ok(opts)
+}
+
+struct Site {
+    root: PathBuf,
+    local: SocketAddr,
+    quiet: bool,
+}
+
+pub fn cmd_serve(args: &[String]) {
+    let opts = match parse_serve_args(args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: basilc serve [--root <dir>] [--port <n>] [--host <addr>] [--workers <n>] [--quiet]");
+            std::process::exit(2);
+        }
+    };
+    let root = match fs::canonicalize(&opts.root) {
+        Ok(r) if r.is_dir() => r,
+        _ => { eprintln!("site root is not a directory: {}", opts.root.display()); std::process::exit(1); }
+    };
+    // Pages open files relative to the site root; the working directory is process-wide, so it is
+    // set once here rather than per script as `basilc run` does.
+    if let Err(e) = std::env::set_current_dir(&root) {
+        eprintln!("warning: failed to set current dir to {}: {}", root.display(), e);
+    }
+    let listener = match TcpListener::bind((opts.host.as_str(), opts.port)) {
+        Ok(l) => l,
+        Err(e) => { eprintln!("cannot listen on {}:{}: {}", opts.host, opts.port, e); std::process::exit(1); }
+    };
+    let local = listener.local_addr().expect("listener address");
+    let site = Site { root, local, quiet: opts.quiet };
+    println!("Serving {} at http://{}/ ({} workers, Ctrl+C to stop)", site.root.display(), local, opts.workers);
+    let _ = io::stdout().flush();
+
+    let tx = web::spawn_workers(opts.workers, "basil-serve", move |(stream, peer): (TcpStream, SocketAddr)| {
+        handle_connection(&site, stream, peer)
+    });
+    for conn in listener.incoming() {
+        match conn {
+            Ok(stream) => {
+                let peer = stream.peer_addr().unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+                if tx.send((stream, peer)).is_err() { break; }
+            }
+            Err(e) => eprintln!("accept: {}", e),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+    fn keep_alive(&self) -> bool {
+        let conn = self.header("Connection").unwrap_or("").to_ascii_lowercase();
+        if self.version == "HTTP/1.0" { conn.contains("keep-alive") } else { !conn.contains("close") }
+    }
+    fn is_websocket(&self) -> bool {
+        self.header("Upgrade").is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+    }
+    fn wants_event_stream(&self) -> bool {
+        self.method == "GET" && self.header("Accept").is_some_and(|a| a.to_ascii_lowercase().contains("text/event-stream"))
+    }
+}
+
+fn handle_connection(site: &Site, stream: TcpStream, peer: SocketAddr) {
+    let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+    let Ok(mut writer) = stream.try_clone() else { return };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let req = match read_request(&mut reader, &mut writer) {
+            Ok(Some(r)) => r,
+            Ok(None) => return,
+            Err(status) => {
+                let _ = write_response(&mut writer, &error_page(status), false, false);
+                return;
+            }
+        };
+        let started = Instant::now();
+        let resp = match resolve(site, &req) {
+            Target::Page(m) if req.is_websocket() => {
+                let status = upgrade(site, &req, peer, &m, reader, writer);
+                if !site.quiet { log_request(&req, status, 0, peer, started); }
+                return;
+            }
+            Target::Page(m) if req.wants_event_stream() => {
+                let (status, size) = stream_page(site, &req, peer, &m, &mut writer);
+                if !site.quiet { log_request(&req, status, size, peer, started); }
+                return;
+            }
+            Target::Page(m) => web::run_page(&m.script, cgi_vars(site, &req, peer, &m), req.body.clone()),
+            Target::Done(resp) => resp,
+        };
+        let keep = req.keep_alive();
+        let head_only = req.method == "HEAD";
+        let sent = write_response(&mut writer, &resp, head_only, keep);
+        if !site.quiet { log_request(&req, resp.status, if head_only { 0 } else { resp.body.len() }, peer, started); }
+        if sent.is_err() || !keep { return; }
+    }
+}
+
+fn error_page(status: u16) -> PageResponse {
+    PageResponse::text(status, &format!("{} {}\n", status, reason(status)))
+}
+
+// Complete a WebSocket handshake and run the page on the upgraded connection until it closes.
+// Returns the status for the log.
+fn upgrade(site: &Site, req: &Request, peer: SocketAddr, m: &web::ScriptMatch, reader: BufReader<TcpStream>, mut writer: TcpStream) -> u16 {
+    let upgrade_requested = req.header("Connection").is_some_and(|c| c.to_ascii_lowercase().contains("upgrade"));
+    let key = req.header("Sec-WebSocket-Key").map(str::trim).filter(|k| !k.is_empty());
+    let (Some(key), true, true, Some("13")) = (key, upgrade_requested, req.method == "GET", req.header("Sec-WebSocket-Version").map(str::trim)) else {
+        let mut resp = error_page(400);
+        resp.headers.push(("Sec-WebSocket-Version".into(), "13".into()));
+        let _ = write_response(&mut writer, &resp, false, false);
+        return 400;
+    };
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        basil_vm::push::accept_key(key),
+    );
+    if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() { return 101; }
+    // Frames the client sent right after the handshake may already be in the read buffer
+    let buffered = reader.buffer().to_vec();
+    let stream = reader.into_inner();
+    if let Err(e) = web::run_websocket(&m.script, cgi_vars(site, req, peer, m), stream, buffered) {
+        eprintln!("{}", e);
+    }
+    101
+}
+
+// Run a page whose output is sent as it is printed, for Server-Sent Events. The response has no
+// Content-Length, so the connection closes when the page ends. Returns status and body size.
+fn stream_page(site: &Site, req: &Request, peer: SocketAddr, m: &web::ScriptMatch, writer: &mut TcpStream) -> (u16, usize) {
+    let page = match web::load_page(&m.script) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}: {}", m.script.display(), e);
+            let _ = write_response(writer, &error_page(500), false, false);
+            return (500, 0);
+        }
+    };
+    let Ok(conn) = writer.try_clone() else { return (500, 0) };
+    let state = Rc::new(RefCell::new(StreamState { head: Vec::new(), status: None, size: 0 }));
+    let sink = StreamSink { conn, state: state.clone() };
+    let result = web::run_program(&m.script, page, cgi_vars(site, req, peer, m), req.body.clone(), Box::new(sink));
+    let mut state = state.borrow_mut();
+    if let Some(status) = state.status {
+        if let Err(e) = result { eprintln!("{}", e); }
+        return (status, state.size);
+    }
+    // The header block never completed: answer like an ordinary page
+    let resp = match result {
+        Ok(()) => web::finish_output(std::mem::take(&mut state.head)),
+        Err(e) => { eprintln!("{}", e); error_page(500) }
+    };
+    let _ = write_response(writer, &resp, false, false);
+    (resp.status, resp.body.len())
+}
+
+struct StreamState {
+    // CGI header block collected until its blank line
+    head: Vec<u8>,
+    // Set once the HTTP head has been sent
+    status: Option<u16>,
+    size: usize,
+}
+
+// PRINT sink of a streamed page: buffers the CGI header block, then writes straight to the socket
+struct StreamSink {
+    conn: TcpStream,
+    state: Rc<RefCell<StreamState>>,
+}
+
+impl Write for StreamSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if state.status.is_some() {
+            self.conn.write_all(buf)?;
+            state.size += buf.len();
+            return Ok(buf.len());
+        }
+        state.head.extend_from_slice(buf);
+        if web::header_end(&state.head).is_some() {
+            let resp = web::finish_output(std::mem::take(&mut state.head));
+            write_head(&mut self.conn, resp.status, &resp.headers, None, false)?;
+            self.conn.write_all(&resp.body)?;
+            state.status = Some(resp.status);
+            state.size = resp.body.len();
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.state.borrow().status.is_some() { self.conn.flush() } else { Ok(()) }
+    }
+}
+
+// Ok(None) means the client closed the connection (or went idle) between requests.
+fn read_request(reader: &mut BufReader<TcpStream>, writer: &mut TcpStream) -> Result<Option<Request>, u16> {
+    let mut line = String::new();
+    let mut total = 0usize;
+    // Tolerate stray blank lines between requests
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(n) => total += n,
+        }
+        if !line.trim().is_empty() { break; }
+        if total > MAX_HEADER_BYTES { return Err(431); }
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else { return Err(400) };
+    if !version.starts_with("HTTP/1.") { return Err(505); }
+    let mut req = Request {
+        method: method.to_ascii_uppercase(),
+        target: target.to_string(),
+        version: version.to_string(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return Err(400),
+            Ok(n) => total += n,
+        }
+        if total > MAX_HEADER_BYTES { return Err(431); }
+        let l = line.trim_end_matches(['\r', '\n']);
+        if l.is_empty() { break; }
+        let Some((name, value)) = l.split_once(':') else { return Err(400) };
+        req.headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let chunked = req.header("Transfer-Encoding").map(|v| v.to_ascii_lowercase().contains("chunked")).unwrap_or(false);
+    let clen = match req.header("Content-Length") {
+        Some(v) => Some(v.parse::<usize>().map_err(|_| 400u16)?),
+        None => None,
+    };
+    if !chunked && clen.unwrap_or(0) == 0 { return Ok(Some(req)); }
+    if clen.unwrap_or(0) > MAX_BODY_BYTES { return Err(413); }
+    if req.header("Expect").map(|v| v.eq_ignore_ascii_case("100-continue")).unwrap_or(false) {
+        let _ = writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+    if chunked {
+        req.body = read_chunked(reader)?;
+    } else {
+        let mut body = vec![0u8; clen.unwrap_or(0)];
+        reader.read_exact(&mut body).map_err(|_| 400u16)?;
+        req.body = body;
+    }
+    Ok(Some(req))
+}
+
+fn read_chunked(reader: &mut BufReader<TcpStream>) -> Result<Vec<u8>, u16> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(|_| 400u16)?;
+        let size_str = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| 400u16)?;
+        if size == 0 { break; }
+        if body.len() + size > MAX_BODY_BYTES { return Err(413); }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).map_err(|_| 400u16)?;
+        line.clear();
+        reader.read_line(&mut line).map_err(|_| 400u16)?;
+    }
+    // Skip trailers up to the terminating blank line
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) if line.trim().is_empty() => break,
+            Ok(_) => {}
+        }
+    }
+    Ok(body)
+}
+
+// What a request maps to: a page to run, or a response that is ready to send
+enum Target {
+    Page(web::ScriptMatch),
+    Done(PageResponse),
+}
+
+fn split_target(req: &Request) -> (&str, &str) {
+    req.target.split_once('?').unwrap_or((req.target.as_str(), ""))
+}
+
+fn resolve(site: &Site, req: &Request) -> Target {
+    let (raw_path, query) = split_target(req);
+    let path = crate::url_decode(raw_path);
+    if !path.starts_with('/') || path.split('/').any(|s| s == "..") || path.contains('\0') {
+        return Target::Done(PageResponse::text(400, "400 Bad Request\n"));
+    }
+    let page = |m: web::ScriptMatch| if inside_root(&site.root, &m.script) { Target::Page(m) } else { Target::Done(not_found()) };
+
+    if let Some(m) = web::map_url_to_script(&site.root, &path) { return page(m); }
+
+    // Everything that is not a page or a file goes through the site's ROUTEs, if it has any
+    let routes = site.root.join(web::ROUTES_FILE);
+    let via_routes = || {
+        if !routes.is_file() { return Target::Done(not_found()); }
+        Target::Page(web::ScriptMatch { script: routes.clone(), script_name: String::new(), path_info: path.clone() })
+    };
+    let mut file = site.root.join(path.trim_start_matches('/'));
+    if !file.exists() { return via_routes(); }
+    if file.is_dir() {
+        if !path.ends_with('/') {
+            // Relative links in an index page need the trailing slash
+            let loc = if query.is_empty() { format!("{}/", raw_path) } else { format!("{}/?{}", raw_path, query) };
+            let mut resp = PageResponse::text(301, "301 Moved Permanently\n");
+            resp.headers.push(("Location".into(), loc));
+            return Target::Done(resp);
+        }
+        let Some(index) = INDEX_FILES.iter().map(|f| file.join(f)).find(|f| f.is_file()) else { return via_routes() };
+        if index.extension().and_then(|e| e.to_str()) == Some("basil") {
+            return page(web::ScriptMatch { script: index, script_name: format!("{}index.basil", path), path_info: String::new() });
+        }
+        file = index;
+    }
+    if !file.is_file() || !inside_root(&site.root, &file) { return Target::Done(not_found()); }
+    Target::Done(static_file(req, &file))
+}
+
+fn not_found() -> PageResponse { PageResponse::text(404, "404 Not Found\n") }
+
+// Symlinks may point outside the site root; only serve what really lives under it
+fn inside_root(root: &Path, file: &Path) -> bool {
+    fs::canonicalize(file).map(|f| f.starts_with(root)).unwrap_or(false)
+}
+
+fn cgi_vars(site: &Site, req: &Request, peer: SocketAddr, m: &web::ScriptMatch) -> HashMap<String, String> {
+    let query = split_target(req).1;
+    let mut v = HashMap::new();
+    let host = req.header("Host").unwrap_or("");
+    let server_name = host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host);
+    v.insert("GATEWAY_INTERFACE".into(), "CGI/1.1".into());
+    v.insert("SERVER_SOFTWARE".into(), format!("basilc/{}", env!("CARGO_PKG_VERSION")));
+    v.insert("SERVER_NAME".into(), if server_name.is_empty() { site.local.ip().to_string() } else { server_name.to_string() });
+    v.insert("SERVER_PORT".into(), site.local.port().to_string());
+    v.insert("SERVER_PROTOCOL".into(), req.version.clone());
+    v.insert("REQUEST_METHOD".into(), req.method.clone());
+    v.insert("REQUEST_URI".into(), req.target.clone());
+    v.insert("QUERY_STRING".into(), query.to_string());
+    v.insert("SCRIPT_NAME".into(), m.script_name.clone());
+    v.insert("SCRIPT_FILENAME".into(), m.script.to_string_lossy().into_owned());
+    v.insert("PATH_INFO".into(), m.path_info.clone());
+    v.insert("DOCUMENT_ROOT".into(), site.root.to_string_lossy().into_owned());
+    v.insert("REMOTE_ADDR".into(), peer.ip().to_string());
+    v.insert("REMOTE_PORT".into(), peer.port().to_string());
+    v.insert("CONTENT_LENGTH".into(), req.body.len().to_string());
+    v.insert("CONTENT_TYPE".into(), req.header("Content-Type").unwrap_or("").to_string());
+    for (name, value) in &req.headers {
+        if name.eq_ignore_ascii_case("Content-Type") || name.eq_ignore_ascii_case("Content-Length") { continue; }
+        let key = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        // Repeated headers are joined the way CGI servers do
+        v.entry(key).and_modify(|e: &mut String| { e.push_str(", "); e.push_str(value); }).or_insert_with(|| value.clone());
+    }
+    v
+}
+
+fn static_file(req: &Request, file: &Path) -> PageResponse {
+    if req.method != "GET" && req.method != "HEAD" {
+        let mut resp = PageResponse::text(405, "405 Method Not Allowed\n");
+        resp.headers.push(("Allow".into(), "GET, HEAD".into()));
+        return resp;
+    }
+    let modified = fs::metadata(file).and_then(|m| m.modified()).ok().map(http_date);
+    if let (Some(lm), Some(since)) = (&modified, req.header("If-Modified-Since")) {
+        if lm == since {
+            return PageResponse { status: 304, headers: vec![("Last-Modified".into(), lm.clone())], body: Vec::new() };
+        }
+    }
+    match fs::read(file) {
+        Ok(body) => {
+            let mut headers = vec![("Content-Type".into(), mime_type(file).to_string())];
+            if let Some(lm) = modified { headers.push(("Last-Modified".into(), lm)); }
+            PageResponse { status: 200, headers, body }
+        }
+        Err(e) => {
+            eprintln!("{}: {}", file.display(), e);
+            PageResponse::text(403, "403 Forbidden\n")
+        }
+    }
+}
+
+fn mime_type(file: &Path) -> &'static str {
+    let ext = file.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_response(w: &mut TcpStream, resp: &PageResponse, head_only: bool, keep_alive: bool) -> io::Result<()> {
+    let length = if resp.status == 304 { None } else { Some(resp.body.len()) };
+    write_head(w, resp.status, &resp.headers, length, keep_alive)?;
+    if !head_only && resp.status != 304 { w.write_all(&resp.body)?; }
+    w.flush()
+}
+
+// Without a length the body runs until the connection closes
+fn write_head(w: &mut TcpStream, status: u16, headers: &[(String, String)], length: Option<usize>, keep_alive: bool) -> io::Result<()> {
+    let mut out = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    out.push_str(&format!("Date: {}\r\n", http_date(SystemTime::now())));
+    out.push_str("Server: basilc\r\n");
+    for (name, value) in headers {
+        // Framing is ours to decide
+        if ["Content-Length", "Connection", "Transfer-Encoding"].iter().any(|h| name.eq_ignore_ascii_case(h)) { continue; }
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(length) = length { out.push_str(&format!("Content-Length: {}\r\n", length)); }
+    out.push_str(if keep_alive { "Connection: keep-alive\r\n\r\n" } else { "Connection: close\r\n\r\n" });
+    w.write_all(out.as_bytes())
+}
+
+fn log_request(req: &Request, status: u16, size: usize, peer: SocketAddr, started: Instant) {
+    println!(
+        "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {:.1}ms",
+        peer.ip(), clf_date(SystemTime::now()), req.method, req.target, req.version, status, size,
+        req.header("Referer").unwrap_or("-"), req.header("User-Agent").unwrap_or("-"),
+        started.elapsed().as_secs_f64() * 1000.0,
+    );
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+// (year, month, day, hour, minute, second, weekday with 0 = Sunday) in UTC
+fn utc_parts(t: SystemTime) -> (i64, u32, u32, u64, u64, u64, usize) {
+    let secs = t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let weekday = ((days + 4).rem_euclid(7)) as usize;
+    (year, month, day, rem / 3600, rem / 60 % 60, rem % 60, weekday)
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// RFC 7231 date, e.g. "Sun, 06 Nov 1994 08:49:37 GMT".
+fn http_date(t: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    let (y, mo, d, h, mi, s, wd) = utc_parts(t);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT", DAYS[wd], d, MONTHS[mo as usize - 1], y, h, mi, s)
+}
+
+/// Access-log date, e.g. "10/Oct/2000:13:55:36 +0000".
+fn clf_date(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s, _) = utc_parts(t);
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", d, MONTHS[mo as usize - 1], y, h, mi, s)
+}
@@ -1,4 +1,9 @@
+use basil_common::{MappedLine, SourceMap};
+use std::collections::HashMap;
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 #[derive(Debug, Clone, Default)]
 pub struct Directives {
@@ -7,13 +12,43 @@ pub struct Directives {
     pub short_tags_on: bool,
     pub reserved_basil_dev: bool,
     pub reserved_basil_debug: bool,
+    /// `#TEMPLATE_AUTOESCAPE [ON|OFF]`: HTML-escape `<?= ?>` output (None = not set in this file)
+    pub template_autoescape: Option<bool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PrecompileResult {
     pub basil_source: String,
     pub directives: Directives,
-    // future: source map entries
+    /// Files pulled in with `include` / `extends`, for cache invalidation
+    pub dependencies: Vec<PathBuf>,
+    /// Template file:line:col of each generated line (empty for plain Basil)
+    pub source_map: SourceMap,
+}
+
+impl PrecompileResult {
+    /// A value that changes when any included file changes (0 without includes); fold it into cache keys.
+    pub fn dependency_stamp(&self) -> u64 { dependency_stamp(&self.dependencies) }
+
+    /// "file:line:col" in the template for generated line `line`, or "line N" for plain Basil.
+    pub fn location(&self, line: u32) -> String {
+        match self.source_map.lookup(line) {
+            Some(loc) => loc.to_string(),
+            None => format!("line {}", line),
+        }
+    }
+
+    /// A parse or compile error with its "line N" references pointed at the template.
+    pub fn map_error(&self, e: impl fmt::Display) -> String { self.source_map.rewrite_lines(&e.to_string()) }
+}
+
+/// Combined size/mtime stamp of `paths`; a missing file also changes it.
+pub fn dependency_stamp(paths: &[PathBuf]) -> u64 {
+    paths.iter().fold(0u64, |acc, p| {
+        let Ok(m) = fs::metadata(p) else { return acc.rotate_left(7) ^ u64::MAX };
+        let mt = m.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        acc.rotate_left(7) ^ mt ^ m.len()
+    })
 }
 
 #[derive(Debug)]
@@ -53,6 +88,10 @@ pub fn parse_directives_and_bom(src: &str) -> (Directives, usize) {
         else if line.starts_with("#CGI_SHORT_TAGS_ON") { dir.short_tags_on = true; }
         else if line.starts_with("#BASIL_DEV") { dir.reserved_basil_dev = true; }
         else if line.starts_with("#BASIL_DEBUG") { dir.reserved_basil_debug = true; }
+        else if let Some(rest) = line.strip_prefix("#TEMPLATE_AUTOESCAPE") {
+            let v = rest.trim().to_ascii_uppercase();
+            dir.template_autoescape = Some(!matches!(v.as_str(), "OFF" | "FALSE" | "0"));
+        }
         else {
             // Unknown # line at prelude: ignore (kept as prelude semantics)
         }
@@ -61,93 +100,354 @@ pub fn parse_directives_and_bom(src: &str) -> (Directives, usize) {
     (dir, i)
 }
 
+/// Precompile a template held in memory; `include`/`extends` paths resolve against the current directory.
+#[allow(dead_code)]
 pub fn precompile_template(src: &str) -> Result<PrecompileResult, TplError> {
-    let (directives, mut i) = parse_directives_and_bom(src);
-    let bytes = src.as_bytes();
-    let mut out = String::new();
-
-
-    // Helper to append PRINT of raw text
-    let emit_text = |text: &str, out: &mut String| {
-        if text.is_empty() { return; }
-        let mut s = String::with_capacity(text.len()+2);
-        s.push('"');
-        for ch in text.chars() {
-            match ch {
-                '"' => s.push_str("\\\""),
-                '\\' => s.push_str("\\\\"),
-                '\n' => s.push_str("\\n"),
-                '\r' => s.push_str("\\r"),
-                '\t' => s.push_str("\\t"),
-                _ => s.push(ch),
+    precompile(src, None)
+}
+
+/// Precompile the template file at `path` (already read into `src`); `include`/`extends` paths
+/// resolve against the including file's folder.
+pub fn precompile_template_file(path: &Path, src: &str) -> Result<PrecompileResult, TplError> {
+    precompile(src, Some(path))
+}
+
+// --- Template structure ---
+
+// 1-based line and column in a template file
+#[derive(Debug, Clone, Copy)]
+struct Pos { line: u32, col: u32 }
+
+fn pos_at(src: &str, at: usize) -> Pos {
+    let before = &src[..at];
+    let line_start = before.rfind('\n').map(|n| n + 1).unwrap_or(0);
+    Pos { line: before.matches('\n').count() as u32 + 1, col: src[line_start..at].chars().count() as u32 + 1 }
+}
+
+// Position of byte `at` in `text`, which itself starts at `base`
+fn offset_pos(base: Pos, text: &str, at: usize) -> Pos {
+    let p = pos_at(text, at);
+    if p.line == 1 { Pos { line: base.line, col: base.col + p.col - 1 } } else { Pos { line: base.line + p.line - 1, col: p.col } }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text { text: String, pos: Pos },
+    Echo { expr: String, raw: bool, pos: Pos },
+    Code { code: String, pos: Pos },
+    Include { path: String, bindings: Vec<String>, pos: Pos },
+    Block { name: String, body: Vec<Node> },
+}
+
+// One template file being processed
+#[derive(Clone)]
+struct Unit {
+    label: String,
+    dir: PathBuf,
+}
+
+// A child page's block body, compiled with the child's settings
+#[derive(Clone)]
+struct Override {
+    body: Vec<Node>,
+    autoescape: bool,
+    unit: Unit,
+}
+
+struct Ctx {
+    short_tags: bool,
+    stack: Vec<PathBuf>,
+    dependencies: Vec<PathBuf>,
+    // Block names being expanded (an override may not contain itself)
+    expanding: Vec<String>,
+}
+
+// Generated Basil source plus where each of its lines came from
+#[derive(Default)]
+struct Out {
+    src: String,
+    map: SourceMap,
+    line_open: bool,
+}
+
+impl Out {
+    // Append `text` that originates at `pos` in `unit`; later lines of `text` map to later template lines
+    fn emit(&mut self, text: &str, unit: &Unit, mut pos: Pos) {
+        for piece in text.split_inclusive('\n') {
+            if !self.line_open {
+                let file = self.map.file_index(&unit.label);
+                // Continuation lines are copied verbatim, so point at their first non-blank character
+                let col = if pos.col == 1 { piece.len() - piece.trim_start_matches([' ', '\t']).len() } else { 0 };
+                self.map.lines.push(MappedLine { file, line: pos.line, col: pos.col + col as u32 });
+                self.line_open = true;
+            }
+            self.src.push_str(piece);
+            if piece.ends_with('\n') {
+                self.line_open = false;
+                pos = Pos { line: pos.line + 1, col: 1 };
             }
         }
-        s.push('"');
-        out.push_str("PRINT ");
-        out.push_str(&s);
-        out.push_str(";\n");
+    }
+}
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+fn precompile(src: &str, path: Option<&Path>) -> Result<PrecompileResult, TplError> {
+    let (directives, _) = parse_directives_and_bom(src);
+    let unit = match path {
+        Some(p) => Unit { label: p.display().to_string(), dir: p.parent().map(Path::to_path_buf).unwrap_or_default() },
+        None => Unit { label: "template".into(), dir: PathBuf::from(".") },
     };
+    let mut ctx = Ctx { short_tags: directives.short_tags_on, stack: Vec::new(), dependencies: Vec::new(), expanding: Vec::new() };
+    if let Some(p) = path { ctx.stack.push(p.canonicalize().unwrap_or_else(|_| p.to_path_buf())); }
+    let mut out = Out::default();
+    let mut overrides = HashMap::new();
+    process(src, &unit, false, &mut overrides, &mut ctx, &mut out)?;
+    Ok(PrecompileResult { basil_source: out.src, directives, dependencies: ctx.dependencies, source_map: out.map })
+}
 
-    // Scan TEXT/ECHO/CODE
+fn err(unit: &Unit, pos: Pos, msg: impl fmt::Display) -> TplError {
+    TplError::Msg(format!("{}:{}:{}: {}", unit.label, pos.line, pos.col, msg))
+}
+
+// Compile one template file into `out`. If it extends a layout, its blocks become overrides and
+// the layout is compiled in its place.
+fn process(src: &str, unit: &Unit, inherited_escape: bool, overrides: &mut HashMap<String, Override>, ctx: &mut Ctx, out: &mut Out) -> Result<(), TplError> {
+    let (dirs, start) = parse_directives_and_bom(src);
+    let autoescape = dirs.template_autoescape.unwrap_or(inherited_escape);
+    let short_tags = ctx.short_tags || dirs.short_tags_on;
+    let (nodes, extends) = parse_nodes(src, start, unit, short_tags)?;
+    let Some((layout, pos)) = extends else {
+        return emit_nodes(&nodes, autoescape, unit, overrides, ctx, out);
+    };
+    for node in &nodes {
+        match node {
+            // The most derived page wins, so only fill blocks no child has claimed yet
+            Node::Block { .. } => collect_blocks(node, autoescape, unit, overrides),
+            Node::Text { text, .. } if text.trim().is_empty() => {}
+            // Setup code (variables for the layout, includes) runs before the layout
+            Node::Code { .. } | Node::Include { .. } => emit_nodes(std::slice::from_ref(node), autoescape, unit, overrides, ctx, out)?,
+            Node::Text { text, pos } => {
+                let at = offset_pos(*pos, text, text.len() - text.trim_start().len());
+                return Err(err(unit, at, "a page that extends a layout may only contain blocks and code outside them"));
+            }
+            Node::Echo { pos, .. } => return Err(err(unit, *pos, "a page that extends a layout may only contain blocks and code outside them")),
+        }
+    }
+    with_file(&layout, pos, unit, ctx, |lsrc, lunit, ctx| process(lsrc, lunit, autoescape, overrides, ctx, out))
+}
+
+fn collect_blocks(node: &Node, autoescape: bool, unit: &Unit, overrides: &mut HashMap<String, Override>) {
+    if let Node::Block { name, body } = node {
+        overrides.entry(name.clone()).or_insert_with(|| Override { body: body.clone(), autoescape, unit: unit.clone() });
+        for n in body { collect_blocks(n, autoescape, unit, overrides); }
+    }
+}
+
+// Read an included or extended file and run `f` on it, guarding against cycles
+fn with_file(rel: &str, pos: Pos, unit: &Unit, ctx: &mut Ctx, f: impl FnOnce(&str, &Unit, &mut Ctx) -> Result<(), TplError>) -> Result<(), TplError> {
+    let path = unit.dir.join(rel);
+    let canon = path.canonicalize().map_err(|e| err(unit, pos, format!("cannot open '{}': {}", path.display(), e)))?;
+    if ctx.stack.contains(&canon) { return Err(err(unit, pos, format!("'{}' includes itself", rel))); }
+    if ctx.stack.len() >= MAX_INCLUDE_DEPTH { return Err(err(unit, pos, "includes are nested too deeply")); }
+    let src = fs::read_to_string(&canon).map_err(|e| err(unit, pos, format!("cannot read '{}': {}", path.display(), e)))?;
+    if !ctx.dependencies.contains(&canon) { ctx.dependencies.push(canon.clone()); }
+    ctx.stack.push(canon);
+    let sub = Unit { label: path.display().to_string(), dir: path.parent().map(Path::to_path_buf).unwrap_or_default() };
+    let res = f(&src, &sub, ctx);
+    ctx.stack.pop();
+    res
+}
+
+fn emit_nodes(nodes: &[Node], autoescape: bool, unit: &Unit, overrides: &mut HashMap<String, Override>, ctx: &mut Ctx, out: &mut Out) -> Result<(), TplError> {
+    for node in nodes {
+        match node {
+            Node::Text { text, pos } => emit_text(text, unit, *pos, out),
+            Node::Echo { expr, raw, pos } => {
+                let open = if autoescape && !raw { "PRINT HTML$(" } else { "PRINT (" };
+                out.emit(&format!("{}{});\n", open, expr), unit, *pos);
+            }
+            Node::Code { code, pos } => {
+                let code_trim = code.trim_end();
+                if !code_trim.ends_with(';') && !code_trim.is_empty() { out.emit(&format!("{};\n", code), unit, *pos); }
+                else { out.emit(&format!("{}\n", code), unit, *pos); }
+            }
+            Node::Include { path, bindings, pos } => {
+                for b in bindings { out.emit(&format!("LET {};\n", b), unit, *pos); }
+                with_file(path, *pos, unit, ctx, |isrc, iunit, ctx| process(isrc, iunit, autoescape, overrides, ctx, out))?;
+            }
+            Node::Block { name, body } => {
+                let chosen = if ctx.expanding.contains(name) { None } else { overrides.get(name).cloned() };
+                ctx.expanding.push(name.clone());
+                let res = match chosen {
+                    Some(o) => emit_nodes(&o.body, o.autoescape, &o.unit, overrides, ctx, out),
+                    None => emit_nodes(body, autoescape, unit, overrides, ctx, out),
+                };
+                ctx.expanding.pop();
+                res?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Append PRINT of raw text
+fn emit_text(text: &str, unit: &Unit, pos: Pos, out: &mut Out) {
+    if text.is_empty() { return; }
+    let mut s = String::with_capacity(text.len()+2);
+    s.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            _ => s.push(ch),
+        }
+    }
+    s.push('"');
+    out.emit(&format!("PRINT {};\n", s), unit, pos);
+}
+
+// --- Scanner: TEXT / ECHO / CODE / statement tags ---
+
+// A file's nodes plus its `extends` target and position, if any
+type Parsed = (Vec<Node>, Option<(String, Pos)>);
+
+// Split the file into nodes; `extends` (with its position) is returned separately.
+fn parse_nodes(src: &str, start: usize, unit: &Unit, short_tags: bool) -> Result<Parsed, TplError> {
+    let bytes = src.as_bytes();
+    // Open blocks: (name, position, nodes collected before the block)
+    let mut open: Vec<(String, Pos, Vec<Node>)> = Vec::new();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut extends = None;
+    let mut i = start;
     let mut text_start = i; // start of current TEXT segment
     while i < bytes.len() {
         // find next '<?' by scanning forward
-        let mut scan = i;
-        let mut ltq_opt: Option<usize> = None;
-        while scan + 1 < bytes.len() {
-            if bytes[scan] == b'<' && bytes[scan+1] == b'?' { ltq_opt = Some(scan); break; }
-            scan += 1;
-        }
-        let Some(ltq) = ltq_opt else { break; };
+        let Some(ltq) = src[i..].find("<?").map(|p| p + i) else { break };
         // Emit text up to ltq
-        if ltq > text_start {
-            let text = &src[text_start..ltq];
-            emit_text(text, &mut out);
-        }
-        // Determine tag type
-        if ltq + 2 > bytes.len() { return Err(TplError::Msg("unterminated tag opener".into())); }
+        if ltq > text_start { nodes.push(Node::Text { text: src[text_start..ltq].to_string(), pos: pos_at(src, text_start) }); }
+        let pos = pos_at(src, ltq);
         let after = ltq + 2;
-        if after >= bytes.len() { return Err(TplError::Msg("unterminated tag".into())); }
-        // Echo shorthand
-        if bytes.get(after) == Some(&b'=') {
+        if after >= bytes.len() { return Err(err(unit, pos, "unterminated tag")); }
+        let rest = &src[after..];
+        // Echo shorthand (<?= escaped when autoescape is on, <?! always raw)
+        if bytes[after] == b'=' || bytes[after] == b'!' {
             // Find closing '?>' honoring Basil string/comment syntax
-            let (end, _) = find_closing(src, after+1)?; // start after '='<
+            let end = find_closing(src, after + 1).map_err(|e| err(unit, pos, e))?.0;
             let expr = &src[after+1 .. end];
             // Basic validation: no semicolons or block keywords
             if expr.contains(';') || contains_kw(expr, &["BEGIN","END","WHILE","FOR","IF","ELSE","FUNC"]) {
-                return Err(TplError::Msg("Echo block accepts a single expression only".into()));
+                return Err(err(unit, pos, "Echo block accepts a single expression only"));
             }
-            let expr_trim = expr.trim();
-            out.push_str("PRINT ("); out.push_str(expr_trim); out.push_str(");\n");
+            let lead = expr.len() - expr.trim_start().len();
+            nodes.push(Node::Echo { expr: expr.trim().to_string(), raw: bytes[after] == b'!', pos: pos_at(src, after + 1 + lead) });
             i = end + 2; // skip '?>'
             text_start = i;
             continue;
         }
         // Code block <?basil ... ?> or short <?bas ... ?> if enabled
-        let rest = &src[after..];
-        if rest.starts_with("basil") || (directives.short_tags_on && rest.starts_with("bas")) {
+        if rest.starts_with("basil") || (short_tags && rest.starts_with("bas")) {
             // compute start of code content
             let code_start = if rest.starts_with("basil") { after + 5 } else { after + 3 };
             // skip optional whitespace
             let mut cs = code_start;
-            while cs < src.len() && src.as_bytes()[cs].is_ascii_whitespace() { cs += 1; }
+            while cs < src.len() && bytes[cs].is_ascii_whitespace() { cs += 1; }
             // Find '?>' honoring strings/comments
-            let (end, _) = find_closing(src, cs)?;
-            let code = &src[cs..end];
-            out.push_str(code);
-            let code_trim = code.trim_end();
-            if !code_trim.ends_with(';') && !code_trim.is_empty() { out.push_str(";\n"); }
-            else { out.push('\n'); }
+            let end = find_closing(src, cs).map_err(|e| err(unit, pos, e))?.0;
+            nodes.push(Node::Code { code: src[cs..end].to_string(), pos: pos_at(src, cs) });
+            i = end + 2; text_start = i;
+            continue;
+        }
+        // Statement tags: <? include "x" ?>, <? extends "x" ?>, <? block name ?>, <? endblock ?>
+        if rest.starts_with(|c: char| c.is_ascii_whitespace()) {
+            let end = find_closing(src, after).map_err(|e| err(unit, pos, e))?.0;
+            let stmt = src[after..end].trim();
+            let (kw, arg) = stmt.split_once(|c: char| c.is_ascii_whitespace()).unwrap_or((stmt, ""));
+            let arg = arg.trim();
+            match kw.to_ascii_lowercase().as_str() {
+                "include" => {
+                    let (path, rest) = quoted(arg).ok_or_else(|| err(unit, pos, "include expects a quoted file name"))?;
+                    let rest = rest.trim();
+                    let bindings = if rest.is_empty() { Vec::new() } else {
+                        let with = rest.get(..4).filter(|w| w.eq_ignore_ascii_case("with")).ok_or_else(|| err(unit, pos, "expected WITH name = value, ... after the include file"))?;
+                        split_top_level(&rest[with.len()..]).into_iter().map(|b| {
+                            if b.contains('=') { Ok(b) } else { Err(err(unit, pos, format!("expected name = value in include WITH, got '{}'", b))) }
+                        }).collect::<Result<Vec<_>, _>>()?
+                    };
+                    nodes.push(Node::Include { path, bindings, pos });
+                }
+                "extends" => {
+                    let (path, rest) = quoted(arg).ok_or_else(|| err(unit, pos, "extends expects a quoted file name"))?;
+                    if !rest.trim().is_empty() { return Err(err(unit, pos, "unexpected text after the extends file name")); }
+                    let only_text_before = open.is_empty() && nodes.iter().all(|n| matches!(n, Node::Text { text, .. } if text.trim().is_empty()));
+                    if extends.is_some() || !only_text_before { return Err(err(unit, pos, "extends must come first in the file, and only once")); }
+                    extends = Some((path, pos));
+                }
+                "block" => {
+                    if arg.is_empty() || !arg.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        return Err(err(unit, pos, format!("invalid block name '{}'", arg)));
+                    }
+                    open.push((arg.to_string(), pos, std::mem::take(&mut nodes)));
+                }
+                "endblock" => {
+                    let (name, _, outer) = open.pop().ok_or_else(|| err(unit, pos, "endblock without block"))?;
+                    if !arg.is_empty() && !arg.eq_ignore_ascii_case(&name) {
+                        return Err(err(unit, pos, format!("endblock {} closes block {}", arg, name)));
+                    }
+                    let body = std::mem::replace(&mut nodes, outer);
+                    nodes.push(Node::Block { name, body });
+                }
+                _ => return Err(err(unit, pos, format!("unknown template statement '{}' (expected include, extends, block or endblock)", kw))),
+            }
             i = end + 2; text_start = i;
             continue;
         }
         // Illegal bare '<?...'
-        return Err(TplError::Msg("Illegal bare '<? ... ?>'. Use <?basil ... ?>, <?bas ... ?> (with #CGI_SHORT_TAGS_ON), or <?= expr ?>.".into()));
+        return Err(err(unit, pos, "Illegal bare '<? ... ?>'. Use <?basil ... ?>, <?bas ... ?> (with #CGI_SHORT_TAGS_ON), <?= expr ?>, <?! expr ?> or <? include/extends/block ... ?>."));
     }
     // Tail text
-    if text_start < src.len() { emit_text(&src[text_start..], &mut out); }
+    if text_start < src.len() { nodes.push(Node::Text { text: src[text_start..].to_string(), pos: pos_at(src, text_start) }); }
+    if let Some((name, pos, _)) = open.last() { return Err(err(unit, *pos, format!("block {} is never closed (missing <? endblock ?>)", name))); }
+    let mut names = Vec::new();
+    for n in &nodes { block_names(n, &mut names); }
+    names.sort();
+    if let Some(w) = names.windows(2).find(|w| w[0] == w[1]) { return Err(err(unit, Pos { line: 1, col: 1 }, format!("block {} is defined twice", w[0]))); }
+    Ok((nodes, extends))
+}
 
-    Ok(PrecompileResult { basil_source: out, directives })
+fn block_names(n: &Node, names: &mut Vec<String>) {
+    if let Node::Block { name, body } = n {
+        names.push(name.clone());
+        for b in body { block_names(b, names); }
+    }
+}
+
+// A leading "double-quoted" string and the text after it
+fn quoted(s: &str) -> Option<(String, &str)> {
+    let rest = s.strip_prefix('"')?;
+    let end = rest.find('"')?;
+    Some((rest[..end].to_string(), &rest[end + 1..]))
+}
+
+// Split `a = 1, b$ = F(2, 3)` on commas outside strings and parentheses
+fn split_top_level(s: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let (mut depth, mut in_str, mut cur) = (0i32, false, String::new());
+    for c in s.chars() {
+        match c {
+            '"' => in_str = !in_str,
+            '(' | '[' if !in_str => depth += 1,
+            ')' | ']' if !in_str => depth -= 1,
+            ',' if !in_str && depth == 0 => { parts.push(cur.trim().to_string()); cur.clear(); continue; }
+            _ => {}
+        }
+        cur.push(c);
+    }
+    if !cur.trim().is_empty() { parts.push(cur.trim().to_string()); }
+    parts
 }
 
 fn contains_kw(s: &str, kws: &[&str]) -> bool {
@@ -264,4 +564,94 @@ mod tests {
         assert!(pre.basil_source.contains("NEXT"));
     }
 
+    // Write `files` into a fresh temp folder and return its path
+    fn tpl_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("basil-tpl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (f, body) in files {
+            let p = dir.join(f);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(p, body).unwrap();
+        }
+        dir
+    }
+
+    fn compile_file(dir: &Path, name: &str) -> Result<PrecompileResult, TplError> {
+        let path = dir.join(name);
+        precompile_template_file(&path, &fs::read_to_string(&path).unwrap())
+    }
+
+    #[test]
+    fn include_with_bindings() {
+        let dir = tpl_dir("include", &[
+            ("page.basil", "<? include \"partials/nav.basil\" WITH active$ = \"home\", n% = F(1, 2) ?>end"),
+            ("partials/nav.basil", "<nav><?= active$ ?></nav>"),
+        ]);
+        let pre = compile_file(&dir, "page.basil").unwrap();
+        let src = &pre.basil_source;
+        let nav = src.find("PRINT (active$);").unwrap();
+        assert!(src.find("LET active$ = \"home\";").unwrap() < nav);
+        assert!(src.contains("LET n% = F(1, 2);"));
+        assert!(src.find("PRINT \"end\";").unwrap() > nav);
+        assert_eq!(pre.dependencies.len(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn layout_blocks_are_overridden() {
+        let dir = tpl_dir("layout", &[
+            ("layout.basil", "<title><? block title ?>Default<? endblock ?></title><? block body ?>Empty<? endblock ?>"),
+            ("page.basil", "<? extends \"layout.basil\" ?>\n<?basil LET x$ = \"hi\" ?>\n<? block body ?>Body <?= x$ ?><? endblock ?>\n"),
+        ]);
+        let src = compile_file(&dir, "page.basil").unwrap().basil_source;
+        assert!(src.contains("PRINT \"Default\";"));
+        assert!(src.contains("PRINT \"Body \";"));
+        assert!(!src.contains("Empty"));
+        // Setup code runs before the layout prints anything
+        assert!(src.find("LET x$ = \"hi\"").unwrap() < src.find("<title>").unwrap());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn autoescape_and_raw_echo() {
+        let tpl = "#TEMPLATE_AUTOESCAPE\n<?= a$ ?><?! b$ ?>";
+        let src = precompile_template(tpl).unwrap().basil_source;
+        assert!(src.contains("PRINT HTML$(a$);"));
+        assert!(src.contains("PRINT (b$);"));
+        let off = precompile_template("#TEMPLATE_AUTOESCAPE OFF\n<?= a$ ?>").unwrap().basil_source;
+        assert!(off.contains("PRINT (a$);"));
+    }
+
+    #[test]
+    fn errors_report_template_lines() {
+        let dir = tpl_dir("errors", &[
+            ("page.basil", "one\ntwo\n<? include \"missing.basil\" ?>"),
+            ("cycle.basil", "x\n<? include \"cycle.basil\" ?>"),
+            ("open.basil", "a\n<? block main ?>\nb"),
+            ("stray.basil", "<? extends \"open.basil\" ?>\n<p>lost</p>"),
+        ]);
+        let msg = |f: &str| compile_file(&dir, f).unwrap_err().to_string();
+        assert!(msg("page.basil").contains("page.basil:3:1: cannot open"));
+        assert!(msg("cycle.basil").contains("cycle.basil:2:1: 'cycle.basil' includes itself"));
+        assert!(msg("open.basil").contains("open.basil:2:1: block main is never closed"));
+        assert!(msg("stray.basil").contains("stray.basil:2:1: a page that extends a layout may only contain blocks"));
+        let bare = precompile_template("a\nb\n<?bogus ?>").unwrap_err().to_string();
+        assert!(bare.starts_with("template:3:1:"));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn source_map_points_at_template_lines() {
+        let tpl = "<h1>Hi</h1>\n<p><?= name$ ?></p>\n<?basil\n  LET a = 1\n  LET b = 2\n?>";
+        let pre = precompile_template(tpl).unwrap();
+        let gen = |needle: &str| pre.basil_source.lines().position(|l| l.contains(needle)).unwrap() as u32 + 1;
+        assert_eq!(pre.location(gen("PRINT \"<h1>")), "template:1:1");
+        assert_eq!(pre.location(gen("PRINT (name$)")), "template:2:8");
+        assert_eq!(pre.location(gen("LET a")), "template:4:3");
+        assert_eq!(pre.location(gen("LET b")), "template:5:3");
+        let msg = format!("parse error at line {}: oops", gen("LET b"));
+        assert_eq!(pre.map_error(msg), "parse error at template:5:3: oops");
+        assert_eq!(pre.location(999), "line 999");
+    }
+
 }
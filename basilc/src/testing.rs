@@ -0,0 +1,389 @@
+//! `basilc test` for test suites: TEST blocks, golden output files and CI reports.
+//!
+//! A script takes part when it has TEST blocks or an `.expected` file next to it. Each one runs
+//! in its own VM with mocked input and captured output: first the top level, whose output is
+//! compared with `name.expected`, then every TEST block (see `basil_vm::testing`). Results are
+//! printed as a readable list or as TAP, and can also be written as JUnit XML.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use basil_vm::testing::TestStatus;
+use basil_vm::{InputProvider, MockInputProvider, VM};
+
+use crate::template::PrecompileResult;
+
+pub struct TestOptions {
+    pub seed: Option<u64>,
+    pub max_inputs: Option<usize>,
+    pub trace: bool,
+    pub coverage: bool,
+    pub coverage_out: PathBuf,
+    pub coverage_min: Option<f64>,
+    pub filter: Option<String>,
+    pub junit: Option<PathBuf>,
+    pub tap: bool,
+    pub update_expected: bool,
+    pub inputs: Option<PathBuf>,
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        TestOptions {
+            seed: None,
+            max_inputs: None,
+            trace: false,
+            coverage: false,
+            coverage_out: PathBuf::from("lcov.info"),
+            coverage_min: None,
+            filter: None,
+            junit: None,
+            tap: false,
+            update_expected: false,
+            inputs: None,
+        }
+    }
+}
+
+/// One reported result: a TEST block, the `.expected` comparison, or a script that didn't run.
+struct Case {
+    name: String,
+    status: TestStatus,
+    // "file:line" (or the template location) of a failure
+    location: String,
+    time: Duration,
+    output: String,
+}
+
+struct FileReport {
+    path: String,
+    cases: Vec<Case>,
+    time: Duration,
+}
+
+fn expected_path(script: &Path) -> PathBuf { script.with_extension("expected") }
+
+/// Answers for a script's INPUT$ and INKEY$ calls: `--inputs`, else `name.inputs` next to the
+/// script, else mocked answers from `seed`.
+pub fn input_provider(opts: &TestOptions, script: &Path, seed: u64) -> Result<Box<dyn InputProvider>, String> {
+    let sidecar = script.with_extension("inputs");
+    match &opts.inputs {
+        Some(file) => basil_vm::input::load_inputs(file).map_err(|e| e.0),
+        None if sidecar.is_file() => basil_vm::input::load_inputs(&sidecar).map_err(|e| e.0),
+        None => Ok(Box::new(MockInputProvider::new(seed))),
+    }
+}
+
+fn has_test_blocks(src: &str) -> bool {
+    src.lines().any(|l| {
+        let l = l.trim_start();
+        l.get(..4).is_some_and(|w| w.eq_ignore_ascii_case("TEST")) && l[4..].trim_start().starts_with('"')
+    })
+}
+
+/// True if `script` has TEST blocks or an `.expected` file.
+pub fn is_suite(script: &Path) -> bool {
+    expected_path(script).is_file() || fs::read_to_string(script).is_ok_and(|src| has_test_blocks(&src))
+}
+
+/// The test scripts under `dir`, in a stable order. Hidden directories are skipped.
+pub fn discover(dir: &Path) -> Vec<String> {
+    let mut found = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else { return found };
+    let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    entries.sort();
+    for path in entries {
+        let hidden = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.'));
+        if path.is_dir() {
+            if !hidden { found.extend(discover(&path)); }
+        } else if path.extension().is_some_and(|e| e == "basil") && is_suite(&path) {
+            found.push(path.to_string_lossy().into_owned());
+        }
+    }
+    found
+}
+
+// PRINT sink that the runner drains after the top level and after each test
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn take(buf: &Rc<RefCell<Vec<u8>>>) -> String {
+    String::from_utf8_lossy(&std::mem::take(&mut *buf.borrow_mut())).into_owned()
+}
+
+/// Run every script and report. Returns false if anything failed (or coverage is too low).
+pub fn run_suites(files: &[String], opts: &TestOptions) -> bool {
+    let started = Instant::now();
+    let mut tap = opts.tap.then(|| Tap { out: io::stdout(), count: 0 });
+    if let Some(t) = tap.as_mut() { t.header(); }
+    let mut reports = Vec::new();
+    let mut coverage_ok = true;
+    for (i, file) in files.iter().enumerate() {
+        // --coverage-min applies to the merged total, so only the last file checks it
+        let min = if i + 1 == files.len() { opts.coverage_min } else { None };
+        let report = run_file(file, opts, min, &mut coverage_ok);
+        if report.cases.is_empty() && opts.filter.is_some() { continue; }
+        match tap.as_mut() {
+            Some(t) => t.file(&report),
+            None => print_file(&report),
+        }
+        reports.push(report);
+    }
+    let count = |f: fn(&TestStatus) -> bool| reports.iter().flat_map(|r| &r.cases).filter(|c| f(&c.status)).count();
+    let passed = count(|s| matches!(s, TestStatus::Passed));
+    let failed = count(|s| matches!(s, TestStatus::Failed { .. }));
+    let errors = count(|s| matches!(s, TestStatus::Error { .. }));
+    match tap.as_mut() {
+        Some(t) => t.plan(),
+        None => println!(
+            "\n{} tests in {} files: {} passed, {} failed, {} errors ({:.1} ms)",
+            passed + failed + errors, reports.len(), passed, failed, errors, started.elapsed().as_secs_f64() * 1000.0,
+        ),
+    }
+    if let Some(path) = &opts.junit {
+        if let Err(e) = fs::write(path, junit(&reports, started.elapsed())) {
+            eprintln!("warning: could not write {}: {}", path.display(), e);
+        }
+    }
+    failed == 0 && errors == 0 && coverage_ok
+}
+
+fn selected(opts: &TestOptions, id: &str) -> bool {
+    match &opts.filter {
+        Some(f) => id.to_ascii_lowercase().contains(&f.to_ascii_lowercase()),
+        None => true,
+    }
+}
+
+fn run_file(path: &str, opts: &TestOptions, coverage_min: Option<f64>, coverage_ok: &mut bool) -> FileReport {
+    let started = Instant::now();
+    let mut report = FileReport { path: path.to_string(), cases: Vec::new(), time: Duration::ZERO };
+    let broken = |message: String, location: String| Case {
+        name: "(script)".to_string(),
+        status: TestStatus::Error { message, line: 0 },
+        location,
+        time: Duration::ZERO,
+        output: String::new(),
+    };
+    let (program, pre) = match crate::load_test_program(path) {
+        Ok(r) => r,
+        Err((_, message)) => {
+            report.cases.push(broken(message, path.to_string()));
+            return report;
+        }
+    };
+    let comments_map = crate::extract_comments_map(&pre.basil_source);
+    // A fixed default seed keeps mocked input, and so the output, the same on every run
+    let mock = match input_provider(opts, Path::new(path), opts.seed.unwrap_or(0)) {
+        Ok(p) => p,
+        Err(message) => {
+            report.cases.push(broken(message, path.to_string()));
+            return report;
+        }
+    };
+    let mut vm = VM::new_with_test(program, mock, opts.trace, Some(path.to_string()), Some(comments_map), opts.max_inputs);
+    if opts.coverage { vm.enable_coverage(); }
+    let buf = Rc::new(RefCell::new(Vec::new()));
+    vm.set_output(Box::new(Capture(buf.clone())));
+
+    let top = vm.run();
+    let output = take(&buf);
+    if let Err(e) = top {
+        let mut case = broken(format!("runtime error: {}", e), location(path, &pre, vm.current_line()));
+        case.output = output;
+        report.cases.push(case);
+    } else {
+        let expected = expected_path(Path::new(path));
+        if (expected.is_file() || opts.update_expected) && selected(opts, path) {
+            report.cases.push(compare_output(&expected, &output, opts.update_expected));
+        }
+        for (i, test) in vm.tests().to_vec().into_iter().enumerate() {
+            if !selected(opts, &format!("{}::{}", path, test.name)) { continue; }
+            let started = Instant::now();
+            let status = vm.run_test(i);
+            let line = match &status {
+                TestStatus::Failed { line, .. } | TestStatus::Error { line, .. } => *line,
+                TestStatus::Passed => test.line,
+            };
+            report.cases.push(Case {
+                name: test.name,
+                status,
+                location: location(path, &pre, line),
+                time: started.elapsed(),
+                output: take(&buf),
+            });
+        }
+    }
+    if opts.coverage && !crate::write_coverage(&mut vm, path, &opts.coverage_out, coverage_min) { *coverage_ok = false; }
+    report.time = started.elapsed();
+    report
+}
+
+fn location(path: &str, pre: &PrecompileResult, line: u32) -> String {
+    if line == 0 { return path.to_string(); }
+    match pre.source_map.lookup(line) {
+        Some(loc) => loc.to_string(),
+        None => format!("{}:{}", path, line),
+    }
+}
+
+// The top level's output against `name.expected`, ignoring CRLF vs LF
+fn compare_output(expected: &Path, actual: &str, update: bool) -> Case {
+    let started = Instant::now();
+    let actual = actual.replace("\r\n", "\n");
+    let want = fs::read_to_string(expected).map(|s| s.replace("\r\n", "\n"));
+    let name = "expected output".to_string();
+    let location = expected.display().to_string();
+    let status = match want {
+        Ok(want) if want == actual => TestStatus::Passed,
+        _ if update => match fs::write(expected, &actual) {
+            Ok(()) => TestStatus::Passed,
+            Err(e) => TestStatus::Error { message: format!("could not write {}: {}", location, e), line: 0 },
+        },
+        Ok(want) => TestStatus::Failed { message: first_difference(&want, &actual), line: 0 },
+        Err(e) => TestStatus::Error { message: format!("could not read {}: {}", location, e), line: 0 },
+    };
+    let output = if matches!(status, TestStatus::Failed { .. }) { actual } else { String::new() };
+    Case { name, status, location, time: started.elapsed(), output }
+}
+
+fn first_difference(want: &str, got: &str) -> String {
+    let (mut w, mut g) = (want.lines(), got.lines());
+    let mut line = 1;
+    loop {
+        match (w.next(), g.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => {
+                let show = |l: Option<&str>| l.map(|l| format!("{:?}", l)).unwrap_or_else(|| "end of output".to_string());
+                return format!("output differs at line {}: expected {}, got {}", line, show(a), show(b));
+            }
+        }
+        // Same lines, so the difference is a trailing newline
+        if line > want.lines().count().max(got.lines().count()) {
+            return "output differs in its trailing newline".to_string();
+        }
+    }
+}
+
+fn print_file(report: &FileReport) {
+    println!("{}", report.path);
+    for case in &report.cases {
+        let ms = case.time.as_secs_f64() * 1000.0;
+        let (label, detail) = match &case.status {
+            TestStatus::Passed => ("ok   ", None),
+            TestStatus::Failed { message, .. } => ("FAIL ", Some(message)),
+            TestStatus::Error { message, .. } => ("ERROR", Some(message)),
+        };
+        println!("  {} {} ({:.1} ms)", label, case.name, ms);
+        if let Some(message) = detail {
+            println!("        {} at {}", message, case.location);
+            if !case.output.is_empty() {
+                println!("        output:");
+                for l in case.output.lines() { println!("          {}", l); }
+            }
+        }
+    }
+}
+
+struct Tap {
+    out: io::Stdout,
+    count: usize,
+}
+
+impl Tap {
+    fn header(&mut self) { let _ = writeln!(self.out, "TAP version 13"); }
+
+    fn file(&mut self, report: &FileReport) {
+        for case in &report.cases {
+            self.count += 1;
+            let desc = format!("{} :: {}", report.path, case.name).replace('#', "\\#");
+            let (message, severity) = match &case.status {
+                TestStatus::Passed => { let _ = writeln!(self.out, "ok {} - {}", self.count, desc); continue; }
+                TestStatus::Failed { message, .. } => (message, "fail"),
+                TestStatus::Error { message, .. } => (message, "error"),
+            };
+            let _ = writeln!(self.out, "not ok {} - {}", self.count, desc);
+            let _ = writeln!(self.out, "  ---");
+            let _ = writeln!(self.out, "  message: {:?}", message);
+            let _ = writeln!(self.out, "  severity: {}", severity);
+            let _ = writeln!(self.out, "  at: {:?}", case.location);
+            if !case.output.is_empty() {
+                let _ = writeln!(self.out, "  output: |");
+                for l in case.output.lines() { let _ = writeln!(self.out, "    {}", l); }
+            }
+            let _ = writeln!(self.out, "  ...");
+        }
+        let _ = self.out.flush();
+    }
+
+    fn plan(&mut self) { let _ = writeln!(self.out, "1..{}", self.count); }
+}
+
+fn xml(s: &str) -> String {
+    s.chars().filter(|&c| c == '\t' || c == '\n' || c == '\r' || c >= ' ').fold(String::new(), |mut out, c| {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+        out
+    })
+}
+
+fn junit(reports: &[FileReport], total: Duration) -> String {
+    let count = |cases: &mut dyn Iterator<Item = &Case>, f: fn(&TestStatus) -> bool| cases.filter(|c| f(&c.status)).count();
+    let failed = |s: &TestStatus| matches!(s, TestStatus::Failed { .. });
+    let errored = |s: &TestStatus| matches!(s, TestStatus::Error { .. });
+    let all = || reports.iter().flat_map(|r| &r.cases);
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"basilc test\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        all().count(), count(&mut all(), failed), count(&mut all(), errored), total.as_secs_f64(),
+    ));
+    for r in reports {
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            xml(&r.path), r.cases.len(), count(&mut r.cases.iter(), failed), count(&mut r.cases.iter(), errored), r.time.as_secs_f64(),
+        ));
+        for c in &r.cases {
+            out.push_str(&format!("    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"", xml(&r.path), xml(&c.name), c.time.as_secs_f64()));
+            let detail = match &c.status {
+                TestStatus::Passed => None,
+                TestStatus::Failed { message, .. } => Some(("failure", message)),
+                TestStatus::Error { message, .. } => Some(("error", message)),
+            };
+            let Some((tag, message)) = detail else { out.push_str("/>\n"); continue };
+            out.push_str(">\n");
+            out.push_str(&format!("      <{} message=\"{}\">{} at {}</{}>\n", tag, xml(message), xml(message), xml(&c.location), tag));
+            if !c.output.is_empty() { out.push_str(&format!("      <system-out>{}</system-out>\n", xml(&c.output))); }
+            out.push_str("    </testcase>\n");
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_are_found_past_non_ascii_lines() {
+        assert!(has_test_blocks("Café = 1\nTEST \"adds\"\n  ASSERT 1 + 1 = 2\nEND\n"));
+        assert!(!has_test_blocks("Café\nTESTING = 1\n€\n"));
+    }
+}
@@ -0,0 +1,323 @@
+//! In-process execution of Basil web pages (used by `basilc serve` and `basilc fcgi`).
+//!
+//! A page runs in a fresh VM per request, but the compiled Program is cached per worker thread
+//! and reused until the source file changes. The VM gets its CGI variables, POST body and output
+//! buffer from the request instead of the process environment, stdin and stdout.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::SystemTime;
+
+use basil_bytecode::Program;
+use basil_common::SourceMap;
+use basil_compiler::compile;
+use basil_parser::parse;
+use basil_vm::VM;
+
+use crate::template::{self, parse_directives_and_bom, precompile_template_file, Directives};
+
+/// A finished page: status, headers and body ready to be written as an HTTP response.
+pub struct PageResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl PageResponse {
+    pub fn text(status: u16, msg: &str) -> Self {
+        PageResponse {
+            status,
+            headers: vec![("Content-Type".into(), "text/plain; charset=utf-8".into())],
+            body: msg.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// The script a URL path maps to, plus the rest of the path (CGI PATH_INFO).
+pub struct ScriptMatch {
+    pub script: PathBuf,
+    pub script_name: String,
+    pub path_info: String,
+}
+
+/// Find the `.basil` file a (decoded) URL path refers to under `docroot`. The first path segment
+/// that names an existing `.basil` file wins, so `/app.basil/users/7` runs `app.basil` with
+/// PATH_INFO `/users/7`.
+pub fn map_url_to_script(docroot: &Path, url_path: &str) -> Option<ScriptMatch> {
+    let mut cand = docroot.to_path_buf();
+    let mut script_name = String::new();
+    let segs: Vec<&str> = url_path.split('/').filter(|s| !s.is_empty()).collect();
+    for (i, seg) in segs.iter().enumerate() {
+        if *seg == ".." { return None; }
+        cand.push(seg);
+        script_name.push('/');
+        script_name.push_str(seg);
+        if seg.ends_with(".basil") && cand.is_file() {
+            let rest = &segs[i + 1..];
+            let path_info = if rest.is_empty() { String::new() } else { format!("/{}", rest.join("/")) };
+            return Some(ScriptMatch { script: cand, script_name, path_info });
+        }
+    }
+    None
+}
+
+/// A compiled page: its program, directives and the template source map for error messages.
+#[derive(Clone)]
+pub struct Page {
+    pub program: Program,
+    pub directives: Directives,
+    pub source_map: Rc<SourceMap>,
+}
+
+struct CachedPage {
+    modified: Option<SystemTime>,
+    size: u64,
+    page: Page,
+    // Included templates and layouts, with the stamp they had when compiled
+    dependencies: Vec<PathBuf>,
+    dependency_stamp: u64,
+}
+
+thread_local! {
+    // Programs hold Rc'd chunks, so each worker thread keeps its own cache
+    static PAGES: RefCell<HashMap<PathBuf, CachedPage>> = RefCell::new(HashMap::new());
+}
+
+/// Compile `path` (a template or plain Basil), reusing this thread's cached Program when the
+/// file's size and modification time, and those of the files it includes, are unchanged.
+pub fn load_page(path: &Path) -> Result<Page, String> {
+    let meta = fs::metadata(path).map_err(|e| format!("stat {}: {}", path.display(), e))?;
+    let modified = meta.modified().ok();
+    let hit = PAGES.with(|c| {
+        c.borrow().get(path)
+            .filter(|p| p.modified == modified && p.size == meta.len())
+            .filter(|p| p.dependencies.is_empty() || template::dependency_stamp(&p.dependencies) == p.dependency_stamp)
+            .map(|p| p.page.clone())
+    });
+    if let Some(hit) = hit { return Ok(hit); }
+
+    let src = fs::read_to_string(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    // Same rule as `basilc run`: only explicit template markers switch on the precompiler
+    let pre = if src.contains("<?") {
+        precompile_template_file(path, &src).map_err(|e| format!("template error: {}", e))?
+    } else {
+        let (directives, _) = parse_directives_and_bom(&src);
+        template::PrecompileResult { basil_source: src.clone(), directives, dependencies: Vec::new(), source_map: Default::default() }
+    };
+    let ast = parse(&pre.basil_source).map_err(|e| format!("parse error: {}", pre.map_error(e)))?;
+    let program = compile(&ast).map_err(|e| format!("compile error: {}", pre.map_error(e)))?;
+    let dependency_stamp = pre.dependency_stamp();
+    let page = Page { program, directives: pre.directives, source_map: Rc::new(pre.source_map) };
+    PAGES.with(|c| {
+        c.borrow_mut().insert(path.to_path_buf(), CachedPage {
+            modified,
+            size: meta.len(),
+            page: page.clone(),
+            dependencies: pre.dependencies,
+            dependency_stamp,
+        })
+    });
+    Ok(page)
+}
+
+// PRINT sink shared between the VM and the caller
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Run the page at `path` for one request. `vars` are the CGI variables the page sees
+/// (QUERY_STRING, REQUEST_METHOD, HTTP_*, ...), `body` is the raw request body.
+/// Errors are logged to stderr and answered with a 500 so details never reach the browser.
+pub fn run_page(path: &Path, vars: HashMap<String, String>, body: Vec<u8>) -> PageResponse {
+    let page = match load_page(path) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return PageResponse::text(500, "500 Internal Server Error\n");
+        }
+    };
+    let buf = Rc::new(RefCell::new(Vec::new()));
+    if let Err(e) = run_program(path, page, vars, body, Box::new(SharedBuf(buf.clone()))) {
+        eprintln!("{}", e);
+        return PageResponse::text(500, "500 Internal Server Error\n");
+    }
+    let out = std::mem::take(&mut *buf.borrow_mut());
+    finish_output(out)
+}
+
+/// Run a compiled page with its PRINT output going to `out`. Unless the page uses `#CGI_NO_HEADER`,
+/// the VM writes the CGI header block (default header plus RESPONSE@ changes) before the body.
+/// The error names the script and line (the template file:line:col for templates).
+pub fn run_program(path: &Path, page: Page, vars: HashMap<String, String>, body: Vec<u8>, out: Box<dyn Write>) -> Result<(), String> {
+    let mut vm = page_vm(path, page.program, vars, body);
+    vm.set_output(out);
+    if let Some(header) = default_header(&page.directives) { vm.enable_cgi_headers(&header); }
+    run_vm(path, &page.source_map, vm)
+}
+
+/// Run the page at `path` for a WebSocket request, after the server sent `101 Switching
+/// Protocols` on `stream`. The page talks through WEBSOCKET@; what it PRINTs is discarded.
+pub fn run_websocket(path: &Path, vars: HashMap<String, String>, stream: TcpStream, buffered: Vec<u8>) -> Result<(), String> {
+    let page = load_page(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let header = default_header(&page.directives).unwrap_or_default();
+    let mut vm = page_vm(path, page.program, vars, Vec::new());
+    vm.set_output(Box::new(io::sink()));
+    // Headers are never sent, but RESPONSE@ and SESSION@ work as on any other request
+    vm.enable_cgi_headers(&header);
+    vm.set_websocket(stream, buffered);
+    run_vm(path, &page.source_map, vm)
+}
+
+fn page_vm(path: &Path, program: Program, vars: HashMap<String, String>, body: Vec<u8>) -> VM {
+    let mut vm = VM::new(program);
+    vm.set_script_path(path.to_string_lossy().to_string());
+    vm.set_web_request(vars, body);
+    if is_router_script(path) { vm.enable_routing(); }
+    vm
+}
+
+fn run_vm(path: &Path, source_map: &SourceMap, mut vm: VM) -> Result<(), String> {
+    vm.run().map_err(|e| {
+        let line = vm.current_line();
+        if let Some(loc) = source_map.lookup(line) { format!("{}: runtime error at {}: {}", path.display(), loc, e) }
+        else if line > 0 { format!("{}: runtime error at line {}: {}", path.display(), line, e) }
+        else { format!("{}: runtime error: {}", path.display(), e) }
+    })
+}
+
+/// Find the Basil script a CGI-style request refers to, looking variables up with `var`:
+/// SCRIPT_FILENAME, then PATH_TRANSLATED, then DOCUMENT_ROOT + PATH_INFO or REQUEST_URI.
+pub fn resolve_script_with(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // 1) Prefer SCRIPT_FILENAME if it points to a .basil file
+    if let Some(sf) = var("SCRIPT_FILENAME") {
+        if sf.ends_with(".basil") && Path::new(&sf).is_file() {
+            return Some(sf);
+        }
+    }
+    // 2) PATH_TRANSLATED is often correct under Action
+    if let Some(pt) = var("PATH_TRANSLATED") {
+        if pt.ends_with(".basil") && Path::new(&pt).is_file() {
+            return Some(pt);
+        }
+    }
+    // 3) Try DOCUMENT_ROOT + PATH_INFO (same URL mapping as `basilc serve`)
+    if let (Some(docroot), Some(pi)) = (var("DOCUMENT_ROOT"), var("PATH_INFO")) {
+        if let Some(m) = map_url_to_script(Path::new(&docroot), &pi) {
+            return Some(m.script.to_string_lossy().into_owned());
+        }
+    }
+    // 4) Try DOCUMENT_ROOT + REQUEST_URI (strip query)
+    if let (Some(docroot), Some(uri)) = (var("DOCUMENT_ROOT"), var("REQUEST_URI")) {
+        let path_part = uri.split('?').next().unwrap_or("");
+        if let Some(m) = map_url_to_script(Path::new(&docroot), &crate::url_decode(path_part)) {
+            return Some(m.script.to_string_lossy().into_owned());
+        }
+    }
+    // 5) A site with DOCUMENT_ROOT/routes.basil answers every other URL through its routes
+    if let Some(docroot) = var("DOCUMENT_ROOT") {
+        let routes = Path::new(&docroot).join(ROUTES_FILE);
+        if routes.is_file() { return Some(routes.to_string_lossy().into_owned()); }
+    }
+    None
+}
+
+/// The router script of a site: URLs that map to no page or file are dispatched through its ROUTEs.
+pub const ROUTES_FILE: &str = "routes.basil";
+
+/// True for a site's `routes.basil`, which runs its top level and then dispatches the request.
+pub fn is_router_script(path: &Path) -> bool {
+    path.file_name().and_then(|n| n.to_str()) == Some(ROUTES_FILE)
+}
+
+/// Start `n` worker threads that pass each job sent on the returned channel to `handle`.
+/// Workers run inside the CLI's Tokio context, and a panicking page only loses its own job.
+pub fn spawn_workers<T: Send + 'static>(n: usize, name: &str, handle: impl Fn(T) + Send + Sync + 'static) -> mpsc::Sender<T> {
+    let (tx, rx) = mpsc::channel::<T>();
+    let rx = Arc::new(Mutex::new(rx));
+    let handle = Arc::new(handle);
+    let name = name.to_string();
+    for i in 0..n.max(1) {
+        let rx = rx.clone();
+        let handle = handle.clone();
+        let name = name.clone();
+        std::thread::Builder::new().name(format!("{}-{}", name, i)).spawn(move || {
+            let _rt = crate::runtime::TOKIO_MAIN_RT.enter();
+            loop {
+                let next = match rx.lock() { Ok(r) => r.recv(), Err(_) => break };
+                let Ok(job) = next else { break };
+                if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handle(job))).is_err() {
+                    eprintln!("{}: request handler panicked", name);
+                }
+            }
+        }).expect("spawn worker thread");
+    }
+    tx
+}
+
+/// The header block the VM sends for a page, or None under `#CGI_NO_HEADER`.
+pub fn default_header(directives: &Directives) -> Option<String> {
+    if directives.cgi_no_header { return None; }
+    Some(directives.cgi_default_header.clone().unwrap_or_else(|| "Content-Type: text/html; charset=utf-8".to_string()))
+}
+
+/// Where a CGI header block ends: (end of the headers, start of the body), at whichever blank
+/// line, `\r\n\r\n` or `\n\n`, comes first.
+pub fn header_end(out: &[u8]) -> Option<(usize, usize)> {
+    (0..out.len()).find_map(|i| {
+        let rest = &out[i..];
+        if rest.starts_with(b"\r\n\r\n") { Some((i, i + 4)) } else if rest.starts_with(b"\n\n") { Some((i, i + 2)) } else { None }
+    })
+}
+
+/// Split a page's CGI output into status, headers and body. A `Status:` line sets the status and a
+/// Location header without one means a redirect, as in CGI.
+pub fn finish_output(out: Vec<u8>) -> PageResponse {
+    let Some((end, body_start)) = header_end(&out) else {
+        eprintln!("page sent no CGI header block (#CGI_NO_HEADER is set)");
+        return PageResponse::text(500, "No CGI header sent. Add headers or remove #CGI_NO_HEADER.\n");
+    };
+    let mut status = 200;
+    let mut headers = Vec::new();
+    for (name, value) in String::from_utf8_lossy(&out[..end]).lines().filter_map(split_header) {
+        if name.eq_ignore_ascii_case("Status") {
+            status = value.split_whitespace().next().and_then(|c| c.parse().ok()).unwrap_or(200);
+        } else {
+            if name.eq_ignore_ascii_case("Location") && status == 200 { status = 302; }
+            headers.push((name, value));
+        }
+    }
+    PageResponse { status, headers, body: out[body_start..].to_vec() }
+}
+
+fn split_header(line: &str) -> Option<(String, String)> {
+    let (name, value) = line.split_once(':')?;
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_block_ends_at_the_first_blank_line() {
+        let resp = finish_output(b"Status: 201 Created\r\nX-A: 1\r\n\r\nbody\n\nmore".to_vec());
+        assert_eq!((resp.status, resp.body.as_slice()), (201, &b"body\n\nmore"[..]));
+        // Headers ended by "\n\n", then a body with CRLF CRLF in it
+        let resp = finish_output(b"Content-Type: text/plain\n\nline\r\n\r\nX-Not: header\n".to_vec());
+        assert_eq!(resp.headers, vec![("Content-Type".to_string(), "text/plain".to_string())]);
+        assert_eq!(resp.body, b"line\r\n\r\nX-Not: header\n");
+        assert_eq!(header_end(b"X: 1\n"), None);
+    }
+}
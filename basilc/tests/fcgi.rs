@@ -0,0 +1,213 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+// --- Minimal FastCGI client harness (plays the web server's side) ---
+
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const GET_VALUES: u8 = 9;
+const GET_VALUES_RESULT: u8 = 10;
+
+fn record(kind: u8, id: u16, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![1, kind, (id >> 8) as u8, id as u8, (content.len() >> 8) as u8, content.len() as u8, 0, 0];
+    out.extend_from_slice(content);
+    out
+}
+
+fn pairs(p: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (n, v) in p {
+        for len in [n.len(), v.len()] {
+            if len < 128 { out.push(len as u8); } else { out.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes()); }
+        }
+        out.extend_from_slice(n.as_bytes());
+        out.extend_from_slice(v.as_bytes());
+    }
+    out
+}
+
+fn read_record(r: &mut impl Read) -> (u8, u16, Vec<u8>) {
+    let mut h = [0u8; 8];
+    r.read_exact(&mut h).expect("record header");
+    let len = u16::from_be_bytes([h[4], h[5]]) as usize;
+    let mut c = vec![0u8; len + h[6] as usize];
+    r.read_exact(&mut c).expect("record body");
+    c.truncate(len);
+    (h[1], u16::from_be_bytes([h[2], h[3]]), c)
+}
+
+struct Reply { stdout: String, stderr: String, app_status: u32, protocol_status: u8 }
+
+/// Send one Responder request on `conn` and collect the reply.
+fn send_request<S: Read + Write>(conn: &mut S, id: u16, keep: bool, params: &[(&str, &str)], body: &[u8]) -> Reply {
+    let mut msg = record(BEGIN_REQUEST, id, &[0, 1, keep as u8, 0, 0, 0, 0, 0]);
+    let p = pairs(params);
+    // Split the params across two records, as web servers may do
+    let mid = p.len() / 2;
+    msg.extend(record(PARAMS, id, &p[..mid]));
+    msg.extend(record(PARAMS, id, &p[mid..]));
+    msg.extend(record(PARAMS, id, &[]));
+    if !body.is_empty() { msg.extend(record(STDIN, id, body)); }
+    msg.extend(record(STDIN, id, &[]));
+    conn.write_all(&msg).unwrap();
+    let mut reply = Reply { stdout: String::new(), stderr: String::new(), app_status: 0, protocol_status: 255 };
+    loop {
+        let (kind, rid, c) = read_record(conn);
+        assert_eq!(rid, id);
+        match kind {
+            STDOUT => reply.stdout.push_str(&String::from_utf8_lossy(&c)),
+            STDERR => reply.stderr.push_str(&String::from_utf8_lossy(&c)),
+            END_REQUEST => {
+                reply.app_status = u32::from_be_bytes([c[0], c[1], c[2], c[3]]);
+                reply.protocol_status = c[4];
+                return reply;
+            }
+            other => panic!("unexpected record type {}", other),
+        }
+    }
+}
+
+struct Responder { child: Child, port: u16 }
+
+impl Drop for Responder {
+    fn drop(&mut self) { let _ = self.child.kill(); let _ = self.child.wait(); }
+}
+
+fn start_responder() -> Option<Responder> {
+    let exe = PathBuf::from(env::var("CARGO_BIN_EXE_basilc").ok()?);
+    let mut child = Command::new(exe)
+        .args(["fcgi", "--bind", "127.0.0.1:0", "--workers", "2"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("start basilc fcgi");
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).expect("read banner");
+    let port = line.split("127.0.0.1:").nth(1).and_then(|r| r.split_whitespace().next()).and_then(|p| p.parse().ok())
+        .unwrap_or_else(|| panic!("no port in banner: {}", line));
+    Some(Responder { child, port })
+}
+
+fn script_params<'a>(script: &'a str, method: &'a str, query: &'a str, clen: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1"),
+        ("REQUEST_METHOD", method),
+        ("SCRIPT_FILENAME", script),
+        ("QUERY_STRING", query),
+        ("CONTENT_TYPE", "application/x-www-form-urlencoded"),
+        ("CONTENT_LENGTH", clen),
+        ("HTTP_USER_AGENT", "fcgi-harness"),
+    ]
+}
+
+fn write_page(dir: &Path, name: &str, src: &str) -> String {
+    let p = dir.join(name);
+    fs::write(&p, src).unwrap();
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn basilc_fcgi_responder() {
+    let mut dir = env::temp_dir();
+    dir.push(format!("fcgi_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&dir).unwrap();
+    let page = write_page(&dir, "page.basil", "<p><?basil FOR EACH p$ IN REQUEST$()\n PRINT p$ + \";\";\nNEXT ?></p><?basil PRINT ENV$(\"HTTP_USER_AGENT\"); ?>\n");
+    let manual = write_page(&dir, "manual.basil", "#CGI_NO_HEADER\nPRINT \"Status: 201 Created\\r\\nContent-Type: text/plain\\r\\n\\r\\nmade\";\n");
+    let broken = write_page(&dir, "broken.basil", "PRINT \"partial\";\nPRINT NoSuchFunc(1);\n");
+    let big = write_page(&dir, "big.basil", "FOR i% = 1 TO 3000 {\n PRINT \"0123456789\";\n}\nNEXT\n");
+    let Some(r) = start_responder() else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let mut conn = TcpStream::connect(("127.0.0.1", r.port)).unwrap();
+
+    // Management record: capabilities
+    conn.write_all(&record(GET_VALUES, 0, &pairs(&[("FCGI_MPXS_CONNS", ""), ("FCGI_MAX_REQS", "")]))).unwrap();
+    let (kind, _, c) = read_record(&mut conn);
+    assert_eq!(kind, GET_VALUES_RESULT);
+    assert!(String::from_utf8_lossy(&c).contains("FCGI_MPXS_CONNS0"));
+
+    // GET with a query string; the connection is kept for the next requests
+    let reply = send_request(&mut conn, 1, true, &script_params(&page, "GET", "a=1&b=two", "0"), b"");
+    assert_eq!((reply.app_status, reply.protocol_status), (0, 0));
+    assert!(reply.stdout.starts_with("Content-Type: text/html; charset=utf-8\r\n\r\n"), "{}", reply.stdout);
+    assert!(reply.stdout.contains("<p>a=1;b=two;</p>fcgi-harness"), "{}", reply.stdout);
+
+    // POST body arrives through FCGI_STDIN
+    let body = b"name=basil";
+    let reply = send_request(&mut conn, 2, true, &script_params(&page, "POST", "", "10"), body);
+    assert!(reply.stdout.contains("name=basil;"), "{}", reply.stdout);
+
+    // Manual headers pass through untouched
+    let reply = send_request(&mut conn, 3, true, &script_params(&manual, "GET", "", "0"), b"");
+    assert_eq!(reply.stdout, "Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nmade");
+
+    // Runtime errors become a 500 on stdout and the details go to FCGI_STDERR
+    let reply = send_request(&mut conn, 4, true, &script_params(&broken, "GET", "", "0"), b"");
+    assert!(reply.stdout.starts_with("Status: 500"), "{}", reply.stdout);
+    assert!(!reply.stdout.contains("partial"));
+    assert!(reply.stderr.contains("runtime error at line 2"), "{}", reply.stderr);
+    assert_eq!(reply.app_status, 1);
+
+    // Output larger than one record is streamed in several
+    let reply = send_request(&mut conn, 5, true, &script_params(&big, "GET", "", "0"), b"");
+    assert_eq!(reply.stdout.matches("0123456789").count(), 3000);
+
+    // Missing script
+    let missing = dir.join("nope.basil").to_string_lossy().into_owned();
+    let reply = send_request(&mut conn, 6, true, &script_params(&missing, "GET", "", "0"), b"");
+    assert!(reply.stdout.starts_with("Status: 404"), "{}", reply.stdout);
+
+    // Unknown role is refused
+    conn.write_all(&record(BEGIN_REQUEST, 7, &[0, 3, 1, 0, 0, 0, 0, 0])).unwrap();
+    let (kind, id, c) = read_record(&mut conn);
+    assert_eq!((kind, id, c[4]), (END_REQUEST, 7, 3));
+
+    // Without FCGI_KEEP_CONN the responder closes the connection after the reply
+    let mut once = TcpStream::connect(("127.0.0.1", r.port)).unwrap();
+    let reply = send_request(&mut once, 1, false, &script_params(&page, "GET", "x=1", "0"), b"");
+    assert!(reply.stdout.contains("x=1;"));
+    let mut rest = Vec::new();
+    assert_eq!(once.read_to_end(&mut rest).unwrap(), 0);
+
+    drop(r);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn basilc_fcgi_unix_socket() {
+    use std::os::unix::net::UnixStream;
+    let Ok(exe) = env::var("CARGO_BIN_EXE_basilc") else { return };
+    let mut dir = env::temp_dir();
+    dir.push(format!("fcgi_sock_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&dir).unwrap();
+    let page = write_page(&dir, "hi.basil", "PRINT \"hi from \" + ENV$(\"QUERY_STRING\");\n");
+    let sock = dir.join("basil.sock");
+    let child = Command::new(exe)
+        .args(["fcgi", "--socket"])
+        .arg(&sock)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("start basilc fcgi");
+    let mut r = Responder { child, port: 0 };
+    let mut line = String::new();
+    BufReader::new(r.child.stdout.take().unwrap()).read_line(&mut line).expect("read banner");
+    assert!(line.contains("basil.sock"), "{}", line);
+
+    let mut conn = UnixStream::connect(&sock).expect("connect to socket");
+    let reply = send_request(&mut conn, 1, true, &script_params(&page, "GET", "q=1", "0"), b"");
+    assert!(reply.stdout.ends_with("hi from q=1"), "{}", reply.stdout);
+
+    drop(r);
+    let _ = fs::remove_dir_all(&dir);
+}
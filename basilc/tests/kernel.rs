@@ -0,0 +1,210 @@
+#![cfg(feature = "jupyter")]
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use zeromq::{DealerSocket, ReqSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+// --- Minimal Jupyter client harness (plays the notebook's side) ---
+
+const KEY: &str = "a0436f6c-1916-498b-8eb9-e81ab9368e84";
+
+fn sign(parts: &[Vec<u8>]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(KEY.as_bytes()).unwrap();
+    for p in parts { mac.update(p); }
+    hex::encode(mac.finalize().into_bytes())
+}
+
+struct Reply { msg_type: String, parent_id: String, content: Value }
+
+fn decode(msg: ZmqMessage) -> Reply {
+    let frames = msg.into_vec();
+    let at = frames.iter().position(|f| f.as_ref() == b"<IDS|MSG>").expect("delimiter");
+    let parts: Vec<Vec<u8>> = frames[at + 2..at + 6].iter().map(|f| f.to_vec()).collect();
+    assert_eq!(frames[at + 1].as_ref(), sign(&parts).as_bytes(), "signature");
+    let json = |b: &[u8]| serde_json::from_slice::<Value>(b).unwrap();
+    let (header, parent) = (json(&parts[0]), json(&parts[1]));
+    Reply {
+        msg_type: header["msg_type"].as_str().unwrap().to_string(),
+        parent_id: parent["msg_id"].as_str().unwrap_or("").to_string(),
+        content: json(&parts[3]),
+    }
+}
+
+struct Client { shell: DealerSocket, control: DealerSocket, iopub: SubSocket, next_id: usize }
+
+impl Client {
+    // Send a request; returns its msg_id
+    async fn send(&mut self, on_control: bool, msg_type: &str, content: Value) -> String {
+        self.next_id += 1;
+        let id = format!("req-{}", self.next_id);
+        let header = json!({ "msg_id": id, "session": "harness", "username": "test", "msg_type": msg_type, "version": "5.3" });
+        let parts: Vec<Vec<u8>> = [header, json!({}), json!({}), content].iter().map(|j| j.to_string().into_bytes()).collect();
+        let mut frames = vec![Bytes::from_static(b"<IDS|MSG>"), Bytes::from(sign(&parts))];
+        frames.extend(parts.into_iter().map(Bytes::from));
+        let socket = if on_control { &mut self.control } else { &mut self.shell };
+        socket.send(ZmqMessage::try_from(frames).unwrap()).await.expect("send");
+        id
+    }
+
+    // The reply to `id`, and what iopub published for it up to the kernel going idle
+    async fn request(&mut self, msg_type: &str, content: Value) -> (Reply, Vec<Reply>) {
+        let id = self.send(false, msg_type, content).await;
+        let reply = decode(timeout(self.shell.recv()).await.expect("reply"));
+        assert_eq!(reply.parent_id, id);
+        (reply, self.published(&id).await)
+    }
+
+    async fn published(&mut self, id: &str) -> Vec<Reply> {
+        let mut out = Vec::new();
+        loop {
+            let m = decode(timeout(self.iopub.recv()).await.expect("iopub"));
+            if m.parent_id != id { continue; }
+            if m.msg_type == "status" {
+                if m.content["execution_state"] == "idle" { return out; }
+                continue;
+            }
+            out.push(m);
+        }
+    }
+
+    async fn execute(&mut self, code: &str) -> (Reply, Vec<Reply>) {
+        self.request("execute_request", json!({ "code": code, "silent": false, "store_history": true, "user_expressions": {}, "allow_stdin": false })).await
+    }
+}
+
+async fn timeout<T>(f: impl std::future::Future<Output = zeromq::ZmqResult<T>>) -> zeromq::ZmqResult<T> {
+    tokio::time::timeout(Duration::from_secs(20), f).await.expect("kernel timed out")
+}
+
+struct Kernel(Child);
+
+impl Drop for Kernel {
+    fn drop(&mut self) { let _ = self.0.kill(); let _ = self.0.wait(); }
+}
+
+fn free_port() -> u16 { TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port() }
+
+async fn connect<S: Socket>(mut socket: S, port: u16) -> S {
+    for _ in 0..100 {
+        if socket.connect(&format!("tcp://127.0.0.1:{}", port)).await.is_ok() { return socket; }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("kernel never listened on {}", port);
+}
+
+#[test]
+fn basilc_jupyter_kernel() {
+    let Ok(exe) = env::var("CARGO_BIN_EXE_basilc") else { return };
+    let ports: Vec<u16> = (0..5).map(|_| free_port()).collect();
+    let mut dir = env::temp_dir();
+    dir.push(format!("kernel_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&dir).unwrap();
+    let conn_file: PathBuf = dir.join("kernel.json");
+    fs::write(&conn_file, json!({
+        "transport": "tcp", "ip": "127.0.0.1",
+        "shell_port": ports[0], "iopub_port": ports[1], "stdin_port": ports[2], "control_port": ports[3], "hb_port": ports[4],
+        "key": KEY, "signature_scheme": "hmac-sha256", "kernel_name": "basil",
+    }).to_string()).unwrap();
+    let _kernel = Kernel(Command::new(exe).arg("kernel").arg(&conn_file).current_dir(&dir)
+        .stdout(Stdio::null()).stderr(Stdio::null()).spawn().expect("start basilc kernel"));
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut iopub = connect(SubSocket::new(), ports[1]).await;
+        iopub.subscribe("").await.unwrap();
+        let mut client = Client {
+            shell: connect(DealerSocket::new(), ports[0]).await,
+            control: connect(DealerSocket::new(), ports[3]).await,
+            iopub,
+            next_id: 0,
+        };
+        let mut hb = connect(ReqSocket::new(), ports[4]).await;
+        hb.send("ping".into()).await.unwrap();
+        assert_eq!(timeout(hb.recv()).await.unwrap().into_vec()[0].as_ref(), b"ping");
+
+        // kernel_info, repeated until iopub is subscribed and sees its status
+        let info = loop {
+            let id = client.send(false, "kernel_info_request", json!({})).await;
+            let reply = decode(timeout(client.shell.recv()).await.unwrap());
+            if let Ok(Ok(m)) = tokio::time::timeout(Duration::from_millis(500), client.iopub.recv()).await {
+                if decode(m).parent_id == id { let _ = client.published(&id).await; break reply; }
+            }
+        };
+        assert_eq!(info.msg_type, "kernel_info_reply");
+        assert_eq!(info.content["language_info"]["name"], "basil");
+        assert_eq!(info.content["protocol_version"], "5.3");
+
+        // PRINT output streams; globals carry over to the next cell
+        let (reply, published) = client.execute("LET greeting$ = \"Hi\"\nPRINTLN greeting$ + \", notebook\"").await;
+        assert_eq!(reply.msg_type, "execute_reply");
+        assert_eq!(reply.content["status"], "ok");
+        assert_eq!(reply.content["execution_count"], 1);
+        assert_eq!(published[0].msg_type, "execute_input");
+        assert_eq!(published[1].msg_type, "stream");
+        assert_eq!(published[1].content["name"], "stdout");
+        assert_eq!(published[1].content["text"], "Hi, notebook\n");
+
+        // An expression shows its value; HTML$ shows as HTML
+        let (_, published) = client.execute("greeting$ + \"!\"").await;
+        assert_eq!(published[1].msg_type, "execute_result");
+        assert_eq!(published[1].content["execution_count"], 2);
+        assert_eq!(published[1].content["data"]["text/plain"], "Hi!");
+        let (_, published) = client.execute("HTML$(\"<b>\" + greeting$ + \"</b>\")").await;
+        assert_eq!(published[1].content["data"]["text/html"], "&lt;b&gt;Hi&lt;/b&gt;");
+
+        // A runtime error: output so far, then the error
+        let (reply, published) = client.execute("LET xs@ = [1, 2]\nPRINTLN \"before\"\nPRINTLN xs@[5]").await;
+        assert_eq!(reply.content["status"], "error");
+        assert_eq!(reply.content["ename"], "runtime error");
+        assert_eq!(published[1].content["text"], "before\n");
+        assert_eq!(published[2].msg_type, "error");
+        let (reply, _) = client.execute("PRINTLN (").await;
+        assert_eq!(reply.content["ename"], "parse error");
+
+        // Completion of globals, keywords and builtins, from the cursor's word
+        let (reply, _) = client.request("complete_request", json!({ "code": "PRINTLN gre", "cursor_pos": 11 })).await;
+        assert_eq!(reply.content["matches"], json!(["greeting$"]));
+        assert_eq!(reply.content["cursor_start"], 8);
+        assert_eq!(reply.content["cursor_end"], 11);
+        let (reply, _) = client.request("complete_request", json!({ "code": "LET n% = REA\nPRINTLN n%", "cursor_pos": 12 })).await;
+        assert!(reply.content["matches"].as_array().unwrap().contains(&json!("READFILE$")), "{}", reply.content);
+        // No OK-prompt commands in a notebook
+        let (reply, _) = client.request("complete_request", json!({ "code": "STAT", "cursor_pos": 4 })).await;
+        assert_eq!(reply.content["matches"], json!([]));
+
+        // Inspection of the name under the cursor
+        let (reply, _) = client.request("inspect_request", json!({ "code": "PRINTLN greeting$", "cursor_pos": 10, "detail_level": 0 })).await;
+        assert_eq!(reply.content["found"], true);
+        assert_eq!(reply.content["data"]["text/plain"], "greeting$ = Hi");
+        let (reply, _) = client.request("inspect_request", json!({ "code": "nothing%", "cursor_pos": 3, "detail_level": 0 })).await;
+        assert_eq!(reply.content["found"], false);
+
+        // Objects are described by their type's descriptor
+        #[cfg(feature = "obj-bmx")]
+        {
+            client.execute("DIM r@ AS BMX_RIDER(\"Alice\", 17, \"Expert\", 12, 3)").await;
+            let (reply, _) = client.request("inspect_request", json!({ "code": "r@", "cursor_pos": 1, "detail_level": 0 })).await;
+            let text = reply.content["data"]["text/plain"].as_str().unwrap().to_string();
+            assert!(text.starts_with("BMX_RIDER") && text.contains("\n  Name$ : String\n"), "{:?}", text);
+            let (reply, _) = client.request("complete_request", json!({ "code": "r@.Na", "cursor_pos": 5 })).await;
+            assert_eq!((reply.content["matches"].clone(), reply.content["cursor_start"].clone()), (json!(["Name$"]), json!(3)));
+        }
+
+        let (reply, _) = client.request("is_complete_request", json!({ "code": "FOR i% = 1 TO 3" })).await;
+        assert_eq!(reply.content["status"], "incomplete");
+
+        let id = client.send(true, "shutdown_request", json!({ "restart": false })).await;
+        let reply = decode(timeout(client.control.recv()).await.unwrap());
+        assert_eq!((reply.msg_type.as_str(), reply.parent_id.as_str()), ("shutdown_reply", id.as_str()));
+    });
+    let _ = fs::remove_dir_all(&dir);
+}
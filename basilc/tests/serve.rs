@@ -0,0 +1,400 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+struct Server { child: Child, port: u16 }
+
+impl Drop for Server {
+    fn drop(&mut self) { let _ = self.child.kill(); let _ = self.child.wait(); }
+}
+
+fn start_server(root: &PathBuf) -> Option<Server> { start_server_with_env(root, &[]) }
+
+fn start_server_with_env(root: &PathBuf, vars: &[(&str, String)]) -> Option<Server> {
+    let exe = PathBuf::from(env::var("CARGO_BIN_EXE_basilc").ok()?);
+    let mut child = Command::new(exe)
+        .args(["serve", "--port", "0", "--workers", "4", "--root"])
+        .arg(root)
+        .envs(vars.iter().map(|(k, v)| (k, v)))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("start basilc serve");
+    // First line: "Serving <root> at http://127.0.0.1:<port>/ ..."
+    let mut out = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    out.read_line(&mut line).expect("read banner");
+    let port = line.split("127.0.0.1:").nth(1).and_then(|r| r.split('/').next()).and_then(|p| p.parse().ok())
+        .unwrap_or_else(|| panic!("no port in banner: {}", line));
+    // Keep draining the access log so the server never blocks on a full pipe
+    std::thread::spawn(move || { let mut sink = String::new(); let _ = out.read_to_string(&mut sink); });
+    Some(Server { child, port })
+}
+
+fn read_response(r: &mut BufReader<TcpStream>) -> (u16, String, String) {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        r.read_line(&mut line).expect("read header line");
+        if line == "\r\n" || line.is_empty() { break; }
+        head.push_str(&line);
+    }
+    let status = head.split_whitespace().nth(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+    let len: usize = head.lines()
+        .find_map(|l| l.strip_prefix("Content-Length: "))
+        .and_then(|v| v.trim().parse().ok()).unwrap_or(0);
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body).expect("read body");
+    (status, head, String::from_utf8_lossy(&body).into_owned())
+}
+
+fn request(port: u16, raw: &str) -> (u16, String, String) {
+    let mut s = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    s.write_all(raw.as_bytes()).unwrap();
+    read_response(&mut BufReader::new(s))
+}
+
+fn get(port: u16, target: &str) -> (u16, String, String) {
+    request(port, &format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", target))
+}
+
+#[test]
+fn basilc_serve_pages_static_files_and_errors() {
+    let mut root = env::temp_dir();
+    root.push(format!("serve_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("hello.basil"), "<h1><?basil FOR EACH p$ IN REQUEST$()\n PRINT p$ + \";\";\nNEXT ?></h1>\n<?basil PRINT ENV$(\"PATH_INFO\"); ?>\n").unwrap();
+    fs::write(root.join("docs/index.basil"), "PRINT \"docs index\";\n").unwrap();
+    fs::write(root.join("moved.basil"), "#CGI_NO_HEADER\nPRINT \"Status: 303 See Other\\r\\nLocation: /hello.basil\\r\\n\\r\\n\";\n").unwrap();
+    fs::write(root.join("quit.basil"), "PRINT \"bye\";\nEXIT(3);\n").unwrap();
+    fs::write(root.join("style.css"), "body { color: green }\n").unwrap();
+    let Some(server) = start_server(&root) else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let port = server.port;
+
+    let (status, head, body) = get(port, "/hello.basil/extra?name=basil");
+    assert_eq!(status, 200, "{}{}", head, body);
+    assert!(head.contains("Content-Type: text/html"), "{}", head);
+    assert!(body.contains("<h1>name=basil;</h1>") && body.contains("/extra"), "{}", body);
+
+    let form = "who=world";
+    let (status, _, body) = request(port, &format!(
+        "POST /hello.basil HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        form.len(), form));
+    assert_eq!(status, 200);
+    assert!(body.contains("who=world;"), "{}", body);
+
+    let (status, head, body) = get(port, "/style.css");
+    assert_eq!(status, 200);
+    assert!(head.contains("Content-Type: text/css"), "{}", head);
+    assert_eq!(body, "body { color: green }\n");
+
+    assert_eq!(get(port, "/docs").0, 301);
+    assert_eq!(get(port, "/docs/").2, "docs index");
+    assert_eq!(get(port, "/missing.html").0, 404);
+    assert_eq!(get(port, "/../etc/passwd").0, 400);
+
+    let (status, head, _) = get(port, "/moved.basil");
+    assert_eq!(status, 303);
+    assert!(head.contains("Location: /hello.basil"), "{}", head);
+
+    // EXIT ends the request, not the server
+    assert_eq!(get(port, "/quit.basil").2, "bye");
+    assert_eq!(get(port, "/style.css").0, 200);
+
+    // Two requests on one keep-alive connection
+    let s = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut w = s.try_clone().unwrap();
+    let mut r = BufReader::new(s);
+    w.write_all(b"GET /hello.basil?n=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    assert!(read_response(&mut r).2.contains("n=1;"));
+    w.write_all(b"GET /hello.basil?n=2 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    assert!(read_response(&mut r).2.contains("n=2;"));
+
+    // Concurrent requests
+    let handles: Vec<_> = (0..8).map(|i| std::thread::spawn(move || get(port, &format!("/hello.basil?i={}", i)))).collect();
+    for (i, h) in handles.into_iter().enumerate() {
+        let (status, _, body) = h.join().unwrap();
+        assert_eq!(status, 200);
+        assert!(body.contains(&format!("i={};", i)), "{}", body);
+    }
+
+    drop(server);
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn basilc_serve_request_and_response_objects() {
+    let mut root = env::temp_dir();
+    root.push(format!("serve_rr_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("login.basil"), concat!(
+        "RESPONSE@.SetCookie(\"sid\", \"a b\", 60);\n",
+        "IF REQUEST@.Query$(\"next\") <> \"\" THEN BEGIN\n",
+        "  RESPONSE@.Redirect(REQUEST@.Query$(\"next\"), 303);\n",
+        "END\n",
+        "RESPONSE@.SetHeader(\"X-Seen\", REQUEST@.Cookie$(\"seen\", \"no\"));\n",
+        "PRINT REQUEST@.Method$ + \" \" + REQUEST@.Header$(\"X-Test\");\n",
+    )).unwrap();
+    fs::write(root.join("late.basil"), "PRINT \"body\";\nRESPONSE@.SetStatus(404);\n").unwrap();
+    fs::write(root.join("upload.basil"), concat!(
+        "IF REQUEST@.HasFile(\"doc\") THEN BEGIN\n",
+        "  PRINT REQUEST@.FileName$(\"doc\") + \":\" + REQUEST@.FileSize%(\"doc\") + \":\" + REQUEST@.Form$(\"title\") + \":\";\n",
+        "  PRINT REQUEST@.SaveFile(\"doc\", \"saved.bin\");\n",
+        "END\n",
+    )).unwrap();
+    let Some(server) = start_server(&root) else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let port = server.port;
+
+    // Cookies and headers set before the first PRINT are sent with the response
+    let (status, head, body) = request(port, "GET /login.basil HTTP/1.1\r\nHost: x\r\nCookie: seen=yes\r\nX-Test: hi\r\nConnection: close\r\n\r\n");
+    assert_eq!(status, 200, "{}{}", head, body);
+    assert!(head.contains("Set-Cookie: sid=a%20b; Path=/; Max-Age=60; HttpOnly; SameSite=Lax"), "{}", head);
+    assert!(head.contains("X-Seen: yes") && head.contains("Content-Type: text/html"), "{}", head);
+    assert_eq!(body, "GET hi");
+
+    let (status, head, _) = get(port, "/login.basil?next=/home");
+    assert_eq!(status, 303);
+    assert!(head.contains("Location: /home") && head.contains("X-Seen: no"), "{}", head);
+
+    // Headers are final once output has started
+    let (status, _, _) = get(port, "/late.basil");
+    assert_eq!(status, 500);
+
+    // multipart/form-data upload
+    let body = "--BOUND\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nReport\r\n\
+--BOUND\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"r.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n\x01\x02\x03\r\n--BOUND--\r\n";
+    let raw = format!("POST /upload.basil HTTP/1.1\r\nHost: x\r\nContent-Type: multipart/form-data; boundary=BOUND\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+    let (status, head, resp) = request(port, &raw);
+    assert_eq!(status, 200, "{}{}", head, resp);
+    assert_eq!(resp, "r.bin:3:Report:3");
+    assert_eq!(fs::read(root.join("saved.bin")).unwrap(), b"\x01\x02\x03");
+
+    drop(server);
+    let _ = fs::remove_dir_all(&root);
+}
+
+// Value of the named cookie in a response's Set-Cookie headers
+fn set_cookie(head: &str, name: &str) -> Option<String> {
+    head.lines().filter_map(|l| l.strip_prefix("Set-Cookie: "))
+        .find_map(|c| c.strip_prefix(&format!("{}=", name)).map(|v| v.split(';').next().unwrap_or("").to_string()))
+}
+
+#[test]
+fn basilc_serve_sessions() {
+    let mut root = env::temp_dir();
+    root.push(format!("serve_sess_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("count.basil"), concat!(
+        "LET n% = SESSION@.Get(\"n\", 0) + 1\n",
+        "SESSION@.Set(\"n\", n%);\n",
+        "IF n% == 1 THEN BEGIN\n  SESSION@.Flash(\"notice\", \"welcome\");\nEND\n",
+        "PRINT \"n=\" + n%;\n",
+    )).unwrap();
+    fs::write(root.join("show.basil"), "PRINT SESSION@.GetFlash$(\"notice\", \"none\") + \" \" + SESSION@.Get(\"n\", 0);\n").unwrap();
+    fs::write(root.join("login.basil"), "SESSION@.Regenerate();\nSESSION@.Set(\"user\", \"ann\");\nPRINT \"ok\";\n").unwrap();
+    fs::write(root.join("escaped.basil"), "#TEMPLATE_AUTOESCAPE\n<?basil SESSION@.Start(); ?><?! SESSION@.CsrfField$() ?>|<?= \"<b>\" ?>").unwrap();
+    fs::write(root.join("form.basil"), "<?basil SESSION@.Start(); ?><?= SESSION@.CsrfField$() ?>|<?basil IF REQUEST@.IsPost THEN BEGIN PRINT SESSION@.VerifyCsrf(); END ?>").unwrap();
+    let store = root.join("sessions");
+    let vars = [("BASIL_SESSION_STORE", format!("files:{}", store.display())), ("BASIL_SESSION_SECRET", "test-secret".to_string())];
+    let Some(server) = start_server_with_env(&root, &vars) else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let port = server.port;
+    let with_cookie = |target: &str, cookie: &str| request(port, &format!("GET {} HTTP/1.1\r\nHost: x\r\nCookie: BASILSESSID={}\r\nConnection: close\r\n\r\n", target, cookie));
+
+    // First visit starts a session and sets a signed cookie
+    let (status, head, body) = get(port, "/count.basil");
+    assert_eq!((status, body.as_str()), (200, "n=1"), "{}", head);
+    let cookie = set_cookie(&head, "BASILSESSID").expect("session cookie");
+    assert!(head.contains("HttpOnly"), "{}", head);
+    assert_eq!(fs::read_dir(&store).unwrap().filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with("sess_")).count(), 1);
+
+    // The flash message is shown once, on the next request
+    assert_eq!(with_cookie("/show.basil", &cookie).2, "welcome 1");
+    assert_eq!(with_cookie("/show.basil", &cookie).2, "none 1");
+    assert_eq!(with_cookie("/count.basil", &cookie).2, "n=2");
+
+    // A tampered cookie is ignored
+    let swap = if &cookie[31..32] == "0" { "1" } else { "0" };
+    let forged = format!("{}{}{}", &cookie[..31], swap, &cookie[32..]);
+    assert_eq!(with_cookie("/count.basil", &forged).2, "n=1");
+
+    // Regenerate moves the data to a new ID and retires the old one
+    let (_, head, _) = with_cookie("/login.basil", &cookie);
+    let fresh = set_cookie(&head, "BASILSESSID").expect("new session cookie");
+    assert_ne!(fresh, cookie);
+    assert_eq!(with_cookie("/show.basil", &fresh).2, "none 2");
+    assert_eq!(with_cookie("/show.basil", &cookie).2, "none 0");
+
+    // CSRF token round trip
+    let form = with_cookie("/form.basil", &fresh).2;
+    let token = form.split("value=\"").nth(1).and_then(|r| r.split('"').next()).expect("csrf token").to_string();
+    let post = |tok: &str| {
+        let body = format!("_csrf={}", tok);
+        request(port, &format!("POST /form.basil HTTP/1.1\r\nHost: x\r\nCookie: BASILSESSID={}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", fresh, body.len(), body)).2
+    };
+    assert!(post(&token).ends_with("|true"));
+    assert!(post("forged").ends_with("|false"));
+
+    // Under autoescape the raw tag still renders the field as an input
+    let escaped = with_cookie("/escaped.basil", &fresh).2;
+    assert!(escaped.starts_with("<input type=\"hidden\" name=\"_csrf\" value=\"") && escaped.ends_with("|&lt;b&gt;"), "{}", escaped);
+
+    drop(server);
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn basilc_serve_routes() {
+    let mut root = env::temp_dir();
+    root.push(format!("serve_routes_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(root.join("controllers")).unwrap();
+    fs::write(root.join("routes.basil"), concat!(
+        "ROUTE \"GET\", \"/\", \"Home\"\n",
+        "ROUTE \"GET\", \"/users/:id\", \"Users.Show\"\n",
+        "ROUTE \"POST\", \"/users/:id\", \"Users.Update\"\n",
+        "ROUTER@.Get(\"/files/*rest\", \"Files\")\n",
+        "ROUTER@.Use(\"RequireToken\", \"/admin\")\n",
+        "ROUTER@.Get(\"/admin\", \"Admin\")\n",
+        "FUNC Home()\nBEGIN\n  RETURN \"home\";\nEND\n",
+        "FUNC Files(p@)\nBEGIN\n  RETURN \"file \" + p@[\"rest\"];\nEND\n",
+        "FUNC RequireToken()\nBEGIN\n",
+        "  IF REQUEST@.Query$(\"token\") <> \"s3cret\" THEN BEGIN\n    RESPONSE@.SetStatus(401);\n    RETURN FALSE;\n  END\n",
+        "  RETURN TRUE;\nEND\n",
+        "FUNC Admin()\nBEGIN\n  RETURN \"admin\";\nEND\n",
+    )).unwrap();
+    fs::write(root.join("controllers").join("Users.basil"), concat!(
+        "FUNC Show(p@)\nBEGIN\n  PRINT \"user \" + p@[\"id\"] + \" \" + REQUEST@.Route$(\"id\") + \" \" + REQUEST@.Query$(\"tab\", \"-\");\nEND\n",
+        "FUNC Update()\nBEGIN\n  RESPONSE@.Redirect(\"/users/\" + REQUEST@.Param$(\"id\"), 303);\nEND\n",
+    )).unwrap();
+    fs::write(root.join("page.basil"), "PRINT \"page\";\n").unwrap();
+    let Some(server) = start_server(&root) else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let port = server.port;
+
+    assert_eq!(get(port, "/").2, "home");
+    assert_eq!(get(port, "/page.basil").2, "page");
+    assert_eq!(get(port, "/users/7?tab=posts").2, "user 7 7 posts");
+    assert_eq!(get(port, "/files/a/b%20c.txt").2, "file a/b c.txt");
+
+    let (status, head, _) = request(port, "POST /users/9 HTTP/1.1\r\nHost: x\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    assert_eq!(status, 303);
+    assert!(head.contains("Location: /users/9"), "{}", head);
+
+    // Middleware that returns FALSE ends the request
+    assert_eq!(get(port, "/admin").0, 401);
+    assert_eq!(get(port, "/admin?token=s3cret").2, "admin");
+
+    let (status, head, _) = request(port, "DELETE /users/9 HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert_eq!(status, 405);
+    assert!(head.contains("Allow: GET, POST"), "{}", head);
+    assert_eq!(get(port, "/nothing/here").0, 404);
+
+    drop(server);
+    let _ = fs::remove_dir_all(&root);
+}
+
+// Client frames are masked (RFC 6455 5.3); the key here is fixed for readability
+fn ws_send(s: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    let mask = [0x37u8, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    s.write_all(&frame).unwrap();
+}
+
+fn ws_read(r: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut head = [0u8; 2];
+    r.read_exact(&mut head).expect("read frame header");
+    assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+    let len = match head[1] & 0x7f {
+        126 => { let mut b = [0u8; 2]; r.read_exact(&mut b).unwrap(); u16::from_be_bytes(b) as usize }
+        127 => { let mut b = [0u8; 8]; r.read_exact(&mut b).unwrap(); u64::from_be_bytes(b) as usize }
+        n => n as usize,
+    };
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload).expect("read frame payload");
+    (head[0] & 0x0f, payload)
+}
+
+#[test]
+fn basilc_serve_websocket_and_sse() {
+    let mut root = env::temp_dir();
+    root.push(format!("serve_push_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("ws.basil"), concat!(
+        "LET WEBSOCKET@.PingInterval% = 1;\n",
+        "WEBSOCKET@.OnMessage(\"Echo\");\n",
+        "FUNC Echo(m$)\nBEGIN\n  WEBSOCKET@.Send(\"echo: \" + m$ + \" \" + REQUEST@.Query$(\"room\"));\nEND\n",
+    )).unwrap();
+    fs::write(root.join("events.basil"), concat!(
+        "SSE_SEND(\"hello\", \"greeting\", \"1\");\n",
+        "SSE_SEND(\"two\" + CHR$(10) + \"lines\");\n",
+    )).unwrap();
+    let Some(server) = start_server(&root) else {
+        eprintln!("basilc binary not found; skipping test");
+        return;
+    };
+    let port = server.port;
+
+    // Handshake, with the RFC 6455 sample key
+    let mut s = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    s.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+    s.write_all(concat!(
+        "GET /ws.basil?room=lobby HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+        "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    ).as_bytes()).unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut b = [0u8; 1];
+        s.read_exact(&mut b).expect("read handshake");
+        head.push(b[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+
+    ws_send(&mut s, 0x1, b"hi");
+    assert_eq!(ws_read(&mut s), (0x1, b"echo: hi lobby".to_vec()));
+    ws_send(&mut s, 0x9, b"are you there");
+    assert_eq!(ws_read(&mut s), (0xA, b"are you there".to_vec()));
+    // PingInterval% = 1: an idle connection gets a ping, and the pong keeps it open
+    let (opcode, payload) = ws_read(&mut s);
+    assert_eq!(opcode, 0x9);
+    ws_send(&mut s, 0xA, &payload);
+    ws_send(&mut s, 0x1, b"still here");
+    assert_eq!(ws_read(&mut s), (0x1, b"echo: still here lobby".to_vec()));
+    ws_send(&mut s, 0x8, &1000u16.to_be_bytes());
+    assert_eq!(ws_read(&mut s), (0x8, 1000u16.to_be_bytes().to_vec()));
+
+    // An upgrade without a key is refused
+    let (status, _, _) = request(port, "GET /ws.basil HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\r\n");
+    assert_eq!(status, 400);
+
+    // Server-Sent Events stream until the page ends
+    let mut s = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    s.write_all(b"GET /events.basil HTTP/1.1\r\nHost: x\r\nAccept: text/event-stream\r\n\r\n").unwrap();
+    let mut out = String::new();
+    s.read_to_string(&mut out).unwrap();
+    let (head, body) = out.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 "), "{}", head);
+    assert!(head.contains("Content-Type: text/event-stream"), "{}", head);
+    assert!(head.contains("Cache-Control: no-cache"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert_eq!(body, "event: greeting\nid: 1\ndata: hello\n\ndata: two\ndata: lines\n\n");
+
+    drop(server);
+    let _ = fs::remove_dir_all(&root);
+}
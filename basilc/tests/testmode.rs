@@ -64,3 +64,126 @@ PRINT I%;
     // Clean up
     let _ = fs::remove_file(&src_path);
 }
+
+fn basilc_exe() -> Option<PathBuf> {
+    let p = PathBuf::from(env::var("CARGO_BIN_EXE_basilc").ok()?);
+    p.exists().then_some(p)
+}
+
+#[test]
+fn basilc_test_suites() {
+    let Some(exe) = basilc_exe() else { return };
+    let mut dir = env::temp_dir();
+    dir.push(format!("testsuites_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(dir.join("more")).unwrap();
+    fs::write(dir.join("math.basil"), r#"LET base% = 0;
+FUNC Add(a, b)
+BEGIN
+  RETURN a + b;
+END
+PRINTLN "loaded";
+SETUP
+  LET base% = 10;
+END SETUP
+TEST "adds"
+  ASSERT_EQ(Add(2, 3), 5);
+  ASSERT(base% == 10, "setup ran");
+END TEST
+TEST "raises"
+  ASSERT_RAISES(Add(1), "arity");
+END TEST
+"#).unwrap();
+    fs::write(dir.join("math.expected"), "loaded\n").unwrap();
+    fs::write(dir.join("more").join("broken.basil"), "TEST \"wrong sum\"\n  PRINT \"checking\";\n  ASSERT_EQ(1 + 1, 3);\nEND TEST\n").unwrap();
+    // No TEST blocks and no .expected file: not picked up from a directory
+    fs::write(dir.join("more").join("plain.basil"), "PRINT 1;\n").unwrap();
+
+    let run = |extra: &[&str]| Command::new(&exe).arg("test").arg(&dir).args(extra).output().expect("run basilc test");
+
+    let out = run(&[]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.status.code(), Some(1), "a failing test fails the run:\n{}", stdout);
+    assert!(stdout.contains("ok    adds") && stdout.contains("ok    raises") && stdout.contains("ok    expected output"), "{}", stdout);
+    assert!(stdout.contains("FAIL  wrong sum"), "{}", stdout);
+    assert!(stdout.contains("ASSERT_EQ failed: expected 3, got 2 at "), "{}", stdout);
+    assert!(stdout.contains("broken.basil:3") && stdout.contains("checking"), "failure location and output:\n{}", stdout);
+    assert!(!stdout.contains("plain.basil"), "{}", stdout);
+    assert!(stdout.contains("4 tests in 2 files: 3 passed, 1 failed, 0 errors"), "{}", stdout);
+
+    let out = run(&["--filter", "math.basil::"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+
+    let junit = dir.join("report.xml");
+    let out = run(&["--tap", "--junit", junit.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with("TAP version 13\n") && stdout.trim_end().ends_with("1..4"), "{}", stdout);
+    assert!(stdout.contains("not ok 4 - ") && stdout.contains("  severity: fail"), "{}", stdout);
+    let mut xml = String::new();
+    fs::File::open(&junit).unwrap().read_to_string(&mut xml).unwrap();
+    assert!(xml.contains("<testsuites name=\"basilc test\" tests=\"4\" failures=\"1\" errors=\"0\""), "{}", xml);
+    assert!(xml.contains("<failure message=\"ASSERT_EQ failed: expected 3, got 2\">"), "{}", xml);
+
+    // A bad --coverage-min is an error, not a gate that quietly never fails
+    let out = run(&["--coverage-min", "8O"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--coverage-min expects a percentage from 0 to 100, got '8O'"));
+    assert_eq!(run(&["--coverage-min=120%"]).status.code(), Some(2));
+
+    // A changed golden file fails until it is updated
+    fs::write(dir.join("math.expected"), "old\n").unwrap();
+    let out = run(&["--filter", "math"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("expected \"old\", got \"loaded\""));
+    assert!(run(&["--filter", "math", "--update-expected"]).status.success());
+    assert_eq!(fs::read_to_string(dir.join("math.expected")).unwrap(), "loaded\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn basilc_scripted_and_recorded_inputs() {
+    let Some(exe) = basilc_exe() else { return };
+    let mut dir = env::temp_dir();
+    dir.push(format!("testinputs_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("menu.basil");
+    fs::write(&script, r#"LET name$ = INPUT$("Your name? ");
+LET done% = 0;
+WHILE done% == 0 BEGIN
+  PRINTLN "1) Greet";
+  PRINTLN "0) Quit";
+  LET c$ = INPUT$("Choice: ");
+  IF c$ == "1" THEN BEGIN
+    PRINTLN "Hi, " + name$;
+  ELSE
+    LET done% = 1;
+  END
+END
+"#).unwrap();
+
+    // Rules answer by prompt; a rule's answers are used in turn
+    let rules = dir.join("rules.inputs");
+    fs::write(&rules, "? choice => 1 | 1 | 0\n? NAME => Ada\n").unwrap();
+    let out = Command::new(&exe).arg("test").arg(&script).arg("--inputs").arg(&rules).output().expect("run basilc test");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}{}", stdout, String::from_utf8_lossy(&out.stderr));
+    assert_eq!(stdout.matches("Hi, Ada").count(), 2, "{}", stdout);
+
+    // Running out of answers is an error rather than a hang
+    fs::write(&rules, "Ada\n1\n").unwrap();
+    let out = Command::new(&exe).arg("test").arg(&script).arg("--inputs").arg(&rules).output().expect("run basilc test");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no answer left for input #3 (prompt: \"Choice:\")"), "{}", String::from_utf8_lossy(&out.stderr));
+
+    // A recorded session replays through the name.inputs file next to the script
+    let mut child = Command::new(&exe).arg("run").arg(&script).arg("--record").arg(dir.join("menu.inputs"))
+        .stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped()).spawn().expect("run basilc run");
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), b"Grace\n1\n0\n").unwrap();
+    assert!(child.wait().unwrap().success());
+    let recorded = fs::read_to_string(dir.join("menu.inputs")).unwrap();
+    assert!(recorded.contains("# Your name?\nGrace\n# Choice:\n1\n# Choice:\n0\n"), "{}", recorded);
+    let out = Command::new(&exe).arg("test").arg(&script).output().expect("run basilc test");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success() && stdout.matches("Hi, Grace").count() == 1, "{}", stdout);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -124,6 +124,8 @@ pub enum Stmt {
     For { var: String, start: Expr, end: Expr, step: Option<Expr>, body: Box<Stmt> },
     // FOR EACH var IN expr ... NEXT
     ForEach { var: String, enumerable: Expr, body: Box<Stmt> },
+    // PARALLEL FOR EACH var IN expr ... NEXT (each iteration runs as its own task)
+    ParallelForEach { var: String, enumerable: Expr, body: Box<Stmt> },
     // SELECT CASE statement
     SelectCase { selector: Expr, arms: Vec<CaseArm>, else_body: Option<Vec<Stmt>> },
     // WITH block
@@ -156,6 +158,8 @@ pub type Program = Vec<Stmt>;
 pub enum FuncKind {
     Func,
     Sub,
+    // ASYNC FUNC: calls return a TASK handle to AWAIT instead of the result
+    AsyncFunc,
 }
 
 
@@ -0,0 +1,207 @@
+//! Builtins that wrap helpers in `basil-objects`: ZIP_*, HTTP_*, SQLITE_*, the terminal
+//! commands and the DAW one-liners. Each group needs its `obj-*` feature.
+
+#[cfg(any(feature = "obj-zip", feature = "obj-curl", feature = "obj-sqlite", feature = "obj-term", feature = "obj-daw"))]
+use basil_common::Result;
+#[cfg(any(feature = "obj-zip", feature = "obj-curl", feature = "obj-sqlite", feature = "obj-term", feature = "obj-daw"))]
+use basil_bytecode::Value;
+
+#[cfg(any(feature = "obj-zip", feature = "obj-curl", feature = "obj-sqlite", feature = "obj-term", feature = "obj-daw"))]
+use crate::arity;
+#[cfg(any(feature = "obj-zip", feature = "obj-curl", feature = "obj-sqlite", feature = "obj-daw"))]
+use crate::text_arg;
+#[cfg(any(feature = "obj-sqlite", feature = "obj-daw"))]
+use crate::to_i64;
+
+#[cfg(feature = "obj-zip")]
+use basil_objects::zip as zip_utils;
+#[cfg(feature = "obj-curl")]
+use basil_objects::curl as curl_utils;
+#[cfg(feature = "obj-sqlite")]
+use basil_objects::sqlite as sqlite_utils;
+#[cfg(feature = "obj-term")]
+use basil_objects::term;
+#[cfg(feature = "obj-daw")]
+use basil_objects::daw as daw_utils;
+
+/// ZIP_EXTRACT_ALL(zip_path$, dest_dir$)
+#[cfg(feature = "obj-zip")]
+pub fn zip_extract_all(args: &[Value]) -> Result<Value> {
+    arity("ZIP_EXTRACT_ALL", args.len() == 2, "2 arguments")?;
+    zip_utils::zip_extract_all(&text_arg(&args[0]), &text_arg(&args[1]))?;
+    Ok(Value::Str(String::new()))
+}
+
+/// ZIP_COMPRESS_FILE(src_path$, zip_path$ [, entry_name$])
+#[cfg(feature = "obj-zip")]
+pub fn zip_compress_file(args: &[Value]) -> Result<Value> {
+    arity("ZIP_COMPRESS_FILE", args.len() == 2 || args.len() == 3, "2 or 3 arguments")?;
+    let entry = args.get(2).map(text_arg);
+    zip_utils::zip_compress_file(&text_arg(&args[0]), &text_arg(&args[1]), entry.as_deref())?;
+    Ok(Value::Str(String::new()))
+}
+
+/// ZIP_COMPRESS_DIR(src_dir$, zip_path$)
+#[cfg(feature = "obj-zip")]
+pub fn zip_compress_dir(args: &[Value]) -> Result<Value> {
+    arity("ZIP_COMPRESS_DIR", args.len() == 2, "2 arguments")?;
+    zip_utils::zip_compress_dir(&text_arg(&args[0]), &text_arg(&args[1]))?;
+    Ok(Value::Str(String::new()))
+}
+
+/// ZIP_LIST$(zip_path$)
+#[cfg(feature = "obj-zip")]
+pub fn zip_list(args: &[Value]) -> Result<Value> {
+    arity("ZIP_LIST$", args.len() == 1, "1 argument")?;
+    Ok(Value::Str(zip_utils::zip_list(&text_arg(&args[0]))?))
+}
+
+/// HTTP_GET$(url$)
+#[cfg(feature = "obj-curl")]
+pub fn http_get(args: &[Value]) -> Result<Value> {
+    arity("HTTP_GET$", args.len() == 1, "1 argument")?;
+    Ok(Value::Str(curl_utils::http_get(&text_arg(&args[0]))?))
+}
+
+/// HTTP_POST$(url$, body$ [, content_type$])
+#[cfg(feature = "obj-curl")]
+pub fn http_post(args: &[Value]) -> Result<Value> {
+    arity("HTTP_POST$", args.len() == 2 || args.len() == 3, "2 or 3 arguments")?;
+    let ct = args.get(2).map(text_arg);
+    Ok(Value::Str(curl_utils::http_post(&text_arg(&args[0]), &text_arg(&args[1]), ct.as_deref())?))
+}
+
+/// SQLITE_OPEN%(path$)
+#[cfg(feature = "obj-sqlite")]
+pub fn sqlite_open(args: &[Value]) -> Result<Value> {
+    arity("SQLITE_OPEN%", args.len() == 1, "1 argument")?;
+    Ok(Value::Int(sqlite_utils::sqlite_open(&text_arg(&args[0]))))
+}
+
+/// SQLITE_CLOSE(handle%)
+#[cfg(feature = "obj-sqlite")]
+pub fn sqlite_close(args: &[Value]) -> Result<Value> {
+    arity("SQLITE_CLOSE", args.len() == 1, "1 argument")?;
+    sqlite_utils::sqlite_close(to_i64(&args[0])?);
+    Ok(Value::Null)
+}
+
+/// SQLITE_EXEC%(handle%, sql$)
+#[cfg(feature = "obj-sqlite")]
+pub fn sqlite_exec(args: &[Value]) -> Result<Value> {
+    arity("SQLITE_EXEC%", args.len() == 2, "2 arguments")?;
+    let h = to_i64(&args[0])?;
+    Ok(Value::Int(sqlite_utils::sqlite_exec(h, &text_arg(&args[1]))))
+}
+
+/// SQLITE_QUERY2D$(handle%, sql$)
+#[cfg(feature = "obj-sqlite")]
+pub fn sqlite_query2d(args: &[Value]) -> Result<Value> {
+    arity("SQLITE_QUERY2D$", args.len() == 2, "2 arguments")?;
+    let h = to_i64(&args[0])?;
+    sqlite_utils::sqlite_query2d(h, &text_arg(&args[1]))
+}
+
+/// SQLITE_LAST_INSERT_ID%(handle%)
+#[cfg(feature = "obj-sqlite")]
+pub fn sqlite_last_insert_id(args: &[Value]) -> Result<Value> {
+    arity("SQLITE_LAST_INSERT_ID%", args.len() == 1, "1 argument")?;
+    Ok(Value::Int(sqlite_utils::sqlite_last_insert_id(to_i64(&args[0])?)))
+}
+
+// Terminal commands return 0 on success; TERM_ERR$ has the last failure
+#[cfg(feature = "obj-term")]
+macro_rules! term_builtins {
+    ($($fn_name:ident, $basic:literal, $n:literal, $expects:literal => |$a:ident| $body:expr;)*) => {$(
+        #[doc = concat!("`", $basic, "`")]
+        pub fn $fn_name(args: &[Value]) -> Result<Value> {
+            arity($basic, args.len() == $n, $expects)?;
+            let $a = args;
+            Ok($body)
+        }
+    )*};
+}
+
+#[cfg(feature = "obj-term")]
+term_builtins! {
+    cls, "CLS", 0, "0 arguments" => |_a| Value::Int(term::cls());
+    locate, "LOCATE", 2, "2 arguments" => |a| Value::Int(term::locate(&a[0], &a[1]));
+    color, "COLOR", 2, "2 arguments" => |a| Value::Int(term::color(&a[0], &a[1]));
+    color_reset, "COLOR_RESET", 0, "0 arguments" => |_a| Value::Int(term::color_reset());
+    attr, "ATTR", 3, "3 arguments" => |a| Value::Int(term::attr(&a[0], &a[1], &a[2]));
+    attr_reset, "ATTR_RESET", 0, "0 arguments" => |_a| Value::Int(term::attr_reset());
+    cursor_save, "CURSOR_SAVE", 0, "0 arguments" => |_a| Value::Int(term::cursor_save());
+    cursor_restore, "CURSOR_RESTORE", 0, "0 arguments" => |_a| Value::Int(term::cursor_restore());
+    term_cols, "TERM_COLS%", 0, "0 arguments" => |_a| Value::Int(term::term_cols());
+    term_rows, "TERM_ROWS%", 0, "0 arguments" => |_a| Value::Int(term::term_rows());
+    cursor_hide, "CURSOR_HIDE", 0, "0 arguments" => |_a| Value::Int(term::cursor_hide());
+    cursor_show, "CURSOR_SHOW", 0, "0 arguments" => |_a| Value::Int(term::cursor_show());
+    term_err, "TERM_ERR$", 0, "0 arguments" => |_a| Value::Str(term::term_err());
+    term_init, "TERM.INIT", 0, "0 arguments" => |_a| Value::Int(term::term_init());
+    term_end, "TERM.END", 0, "0 arguments" => |_a| Value::Int(term::term_end());
+    term_raw, "TERM.RAW", 1, "1 argument (ON/OFF or 0/1)" => |a| Value::Int(term::term_raw(&a[0]));
+    altscreen_on, "ALTSCREEN_ON", 0, "0 arguments" => |_a| Value::Int(term::altscreen_on());
+    altscreen_off, "ALTSCREEN_OFF", 0, "0 arguments" => |_a| Value::Int(term::altscreen_off());
+    term_flush, "TERM.FLUSH", 0, "0 arguments" => |_a| Value::Int(term::term_flush());
+    term_pollkey, "TERM.POLLKEY$", 0, "0 arguments" => |_a| Value::Str(term::term_pollkey_s());
+}
+
+/// DAW_STOP()
+#[cfg(feature = "obj-daw")]
+pub fn daw_stop(args: &[Value]) -> Result<Value> {
+    arity("DAW_STOP", args.is_empty(), "0 arguments")?;
+    daw_utils::stop();
+    Ok(Value::Str(String::new()))
+}
+
+/// DAW_RESET()
+#[cfg(feature = "obj-daw")]
+pub fn daw_reset(args: &[Value]) -> Result<Value> {
+    arity("DAW_RESET", args.is_empty(), "0 arguments")?;
+    daw_utils::reset();
+    Ok(Value::Str(String::new()))
+}
+
+/// DAW_ERR$()
+#[cfg(feature = "obj-daw")]
+pub fn daw_err(args: &[Value]) -> Result<Value> {
+    arity("DAW_ERR$", args.is_empty(), "0 arguments")?;
+    Ok(Value::Str(daw_utils::get_err()))
+}
+
+/// AUDIO_RECORD%(inputSubstr$, outPath$, seconds%)
+#[cfg(feature = "obj-daw")]
+pub fn audio_record(args: &[Value]) -> Result<Value> {
+    arity("AUDIO_RECORD%", args.len() == 3, "3 arguments")?;
+    let secs = to_i64(&args[2])?;
+    Ok(Value::Int(daw_utils::audio_record(&text_arg(&args[0]), &text_arg(&args[1]), secs)))
+}
+
+/// AUDIO_PLAY%(outputSubstr$, filePath$)
+#[cfg(feature = "obj-daw")]
+pub fn audio_play(args: &[Value]) -> Result<Value> {
+    arity("AUDIO_PLAY%", args.len() == 2, "2 arguments")?;
+    Ok(Value::Int(daw_utils::audio_play(&text_arg(&args[0]), &text_arg(&args[1]))))
+}
+
+/// AUDIO_MONITOR%(inputSubstr$, outputSubstr$)
+#[cfg(feature = "obj-daw")]
+pub fn audio_monitor(args: &[Value]) -> Result<Value> {
+    arity("AUDIO_MONITOR%", args.len() == 2, "2 arguments")?;
+    Ok(Value::Int(daw_utils::audio_monitor(&text_arg(&args[0]), &text_arg(&args[1]))))
+}
+
+/// MIDI_CAPTURE%(portSubstr$, outJsonlPath$)
+#[cfg(feature = "obj-daw")]
+pub fn midi_capture(args: &[Value]) -> Result<Value> {
+    arity("MIDI_CAPTURE%", args.len() == 2, "2 arguments")?;
+    Ok(Value::Int(daw_utils::midi_capture(&text_arg(&args[0]), &text_arg(&args[1]))))
+}
+
+/// SYNTH_LIVE%(midiPortSubstr$, outputSubstr$, poly%)
+#[cfg(feature = "obj-daw")]
+pub fn synth_live(args: &[Value]) -> Result<Value> {
+    arity("SYNTH_LIVE%", args.len() == 3, "3 arguments")?;
+    let poly = to_i64(&args[2])?;
+    Ok(Value::Int(daw_utils::synth_live(&text_arg(&args[0]), &text_arg(&args[1]), poly)))
+}
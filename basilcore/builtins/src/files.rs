@@ -0,0 +1,373 @@
+//! File builtins: whole-file helpers (READFILE$, WRITEFILE, COPY, DIR$ ...) and the handle
+//! table behind FOPEN, FREADLINE$, FWRITE and the other handle-based calls.
+//!
+//! A program bundled by `bcc bundle` also carries files of its own (see [`embed`]): READFILE$,
+//! FOPEN for reading and CLASS() fall back to them when the path doesn't exist on disk.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use basil_common::{BasilError, Result};
+use basil_bytecode::Value;
+
+use crate::{arity, string_array, text_arg, to_i64};
+
+static EMBEDDED: OnceLock<HashMap<String, &'static [u8]>> = OnceLock::new();
+
+/// Register the files built into a bundled executable, keyed by the relative path the program
+/// opens them by. Only the first call has any effect.
+pub fn embed(files: &[(&str, &'static [u8])]) {
+    let _ = EMBEDDED.set(files.iter().map(|(name, data)| (embed_key(name), *data)).collect());
+}
+
+/// The contents of `path` if it was embedded with [`embed`].
+pub fn embedded(path: &str) -> Option<&'static [u8]> {
+    EMBEDDED.get()?.get(&embed_key(path)).copied()
+}
+
+/// The name an embedded file is stored under: forward slashes and no leading `./`.
+pub fn embed_key(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut rest = path.as_str();
+    while let Some(r) = rest.strip_prefix("./") { rest = r; }
+    rest.to_string()
+}
+
+/// READFILE$(path$)
+pub fn readfile(args: &[Value]) -> Result<Value> {
+    arity("READFILE$", args.len() == 1, "1 argument")?;
+    let path = text_arg(&args[0]);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => match embedded(&path) {
+            Some(data) if e.kind() == io::ErrorKind::NotFound => data.to_vec(),
+            _ => return Err(BasilError(format!("READFILE$ {}: {}", path, e))),
+        },
+    };
+    Ok(Value::Str(String::from_utf8_lossy(&data).to_string()))
+}
+
+fn write_with(args: &[Value], name: &str, opts: &OpenOptions) -> Result<Value> {
+    arity(name, args.len() == 2, "2 arguments")?;
+    let path = text_arg(&args[0]);
+    let data = text_arg(&args[1]);
+    let mut f = opts.open(&path).map_err(|e| BasilError(format!("{} {}: {}", name, path, e)))?;
+    f.write_all(data.as_bytes()).map_err(|e| BasilError(format!("{} {}: {}", name, path, e)))?;
+    f.flush().ok();
+    Ok(Value::Null)
+}
+
+/// WRITEFILE path$, data$
+pub fn writefile(args: &[Value]) -> Result<Value> {
+    write_with(args, "WRITEFILE", OpenOptions::new().write(true).create(true).truncate(true))
+}
+
+/// APPENDFILE path$, data$
+pub fn appendfile(args: &[Value]) -> Result<Value> {
+    write_with(args, "APPENDFILE", OpenOptions::new().create(true).append(true))
+}
+
+/// COPY src$, dst$
+pub fn copy(args: &[Value]) -> Result<Value> {
+    arity("COPY", args.len() == 2, "2 arguments")?;
+    let (src, dst) = (text_arg(&args[0]), text_arg(&args[1]));
+    fs::copy(&src, &dst).map_err(|e| BasilError(format!("COPY {} -> {}: {}", src, dst, e)))?;
+    Ok(Value::Null)
+}
+
+/// MOVE src$, dst$
+pub fn move_file(args: &[Value]) -> Result<Value> {
+    arity("MOVE", args.len() == 2, "2 arguments")?;
+    let (src, dst) = (text_arg(&args[0]), text_arg(&args[1]));
+    fs::rename(&src, &dst).map_err(|e| BasilError(format!("MOVE {} -> {}: {}", src, dst, e)))?;
+    Ok(Value::Null)
+}
+
+/// RENAME path$, newname$: the new name is relative to the file's directory.
+pub fn rename(args: &[Value]) -> Result<Value> {
+    arity("RENAME", args.len() == 2, "2 arguments")?;
+    let src = text_arg(&args[0]);
+    let dst = Path::new(&src).parent().unwrap_or(Path::new(".")).join(text_arg(&args[1]));
+    fs::rename(&src, &dst).map_err(|e| BasilError(format!("RENAME {} -> {}: {}", src, dst.display(), e)))?;
+    Ok(Value::Null)
+}
+
+/// DELETE path$
+pub fn delete(args: &[Value]) -> Result<Value> {
+    arity("DELETE", args.len() == 1, "1 argument")?;
+    let path = text_arg(&args[0]);
+    fs::remove_file(&path).map_err(|e| BasilError(format!("DELETE {}: {}", path, e)))?;
+    Ok(Value::Null)
+}
+
+/// DIR$(pattern$) -> sorted STRING[] of the files matching a `*`/`?` pattern.
+pub fn dir(args: &[Value]) -> Result<Value> {
+    arity("DIR$", args.len() == 1, "1 argument")?;
+    let patt = text_arg(&args[0]);
+    let p = Path::new(&patt);
+    let (dir, pat): (PathBuf, String) = if p.components().count() > 1 {
+        (p.parent().unwrap_or(Path::new(".")).to_path_buf(), p.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string())
+    } else { (PathBuf::from("."), patt.clone()) };
+    let mut names: Vec<String> = Vec::new();
+    for ent in fs::read_dir(&dir).map_err(|e| BasilError(format!("DIR$: {}: {}", dir.display(), e)))? {
+        let ent = ent.map_err(|e| BasilError(format!("DIR$: {}", e)))?;
+        let md = ent.metadata().map_err(|e| BasilError(format!("DIR$: {}", e)))?;
+        if !md.is_file() { continue; }
+        let name = ent.file_name().to_string_lossy().to_string();
+        if glob_match(&pat, &name) { names.push(name); }
+    }
+    names.sort();
+    Ok(string_array(names))
+}
+
+/// `*`/`?` wildcard match; case-insensitive on Windows.
+pub fn glob_match(pat: &str, name: &str) -> bool {
+    fn inner(p: &[u8], s: &[u8], fold: bool) -> bool {
+        match p.first() {
+            None => s.is_empty(),
+            Some(b'*') => (0..=s.len()).any(|i| inner(&p[1..], &s[i..], fold)),
+            Some(b'?') => !s.is_empty() && inner(&p[1..], &s[1..], fold),
+            Some(&c) => {
+                let same = if fold { c.eq_ignore_ascii_case(&s.first().copied().unwrap_or(0)) } else { s.first() == Some(&c) };
+                !s.is_empty() && same && inner(&p[1..], &s[1..], fold)
+            }
+        }
+    }
+    inner(pat.as_bytes(), name.as_bytes(), cfg!(windows))
+}
+
+// A handle's file on disk, or a read-only view of an embedded one
+enum Stream {
+    Disk(File),
+    Embedded(Cursor<&'static [u8]>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self { Stream::Disk(f) => f.read(buf), Stream::Embedded(c) => c.read(buf) }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Disk(f) => f.write(buf),
+            Stream::Embedded(_) => Err(io::Error::new(io::ErrorKind::PermissionDenied, "embedded files are read-only")),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self { Stream::Disk(f) => f.flush(), Stream::Embedded(_) => Ok(()) }
+    }
+}
+
+impl Seek for Stream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self { Stream::Disk(f) => f.seek(pos), Stream::Embedded(c) => c.seek(pos) }
+    }
+}
+
+struct Handle {
+    file: Stream,
+    readable: bool,
+    writable: bool,
+    owner: usize,
+}
+
+/// Open file handles of a running program, numbered from 1.
+///
+/// Each handle records the call depth that opened it so the owner can close a function's
+/// files when it returns (see [`FileTable::close_owned_by`]).
+pub struct FileTable {
+    handles: HashMap<i64, Handle>,
+    next: i64,
+}
+
+impl FileTable {
+    pub fn new() -> Self { FileTable { handles: HashMap::new(), next: 1 } }
+
+    pub fn is_empty(&self) -> bool { self.handles.is_empty() }
+
+    /// Run one of the handle builtins, or return `None` if `name` isn't one.
+    pub fn call(&mut self, name: &str, args: &[Value], owner: usize) -> Option<Result<Value>> {
+        Some(match name {
+            "FOPEN" => self.fopen(args, owner),
+            "FCLOSE" => self.fclose(args),
+            "FFLUSH" => self.fflush(args),
+            "FEOF" => self.feof(args),
+            "FTELL&" => self.ftell(args),
+            "FSEEK" => self.fseek(args),
+            "FREAD$" => self.fread(args),
+            "FREADLINE$" => self.freadline(args),
+            "FWRITE" => self.fwrite(args, false),
+            "FWRITELN" => self.fwrite(args, true),
+            _ => return None,
+        })
+    }
+
+    fn get(&mut self, h: i64) -> Result<&mut Handle> {
+        if !self.handles.contains_key(&h) {
+            let mut keys: Vec<i64> = self.handles.keys().copied().collect();
+            keys.sort();
+            return Err(BasilError(format!("InvalidHandle (wanted {}, have {:?})", h, keys)));
+        }
+        Ok(self.handles.get_mut(&h).expect("checked above"))
+    }
+
+    /// Flush and forget handle `h`; unknown handles are ignored.
+    pub fn close(&mut self, h: i64) -> Result<()> {
+        if let Some(mut e) = self.handles.remove(&h) {
+            e.file.flush().map_err(|er| BasilError(format!("FCLOSE flush error: {}", er)))?;
+        }
+        Ok(())
+    }
+
+    /// Close every handle opened at call depth `owner`.
+    pub fn close_owned_by(&mut self, owner: usize) {
+        let keys: Vec<i64> = self.handles.iter().filter(|(_, e)| e.owner == owner).map(|(k, _)| *k).collect();
+        for k in keys { let _ = self.close(k); }
+    }
+
+    pub fn close_all(&mut self) {
+        let keys: Vec<i64> = self.handles.keys().copied().collect();
+        for k in keys { let _ = self.close(k); }
+    }
+
+    /// FOPEN(path$, mode$) -> fh%, or -1 if the file can't be opened.
+    pub fn fopen(&mut self, args: &[Value], owner: usize) -> Result<Value> {
+        arity("FOPEN", args.len() == 2, "2 arguments")?;
+        let path = text_arg(&args[0]);
+        let mode = text_arg(&args[1]);
+        if path.contains('\u{0000}') { return Err(BasilError("FOPEN: invalid NUL in path".into())); }
+        let m = mode.to_ascii_lowercase();
+        let plus = m.contains('+');
+        let mut opts = OpenOptions::new();
+        let (readable, writable) = if m.starts_with('r') {
+            opts.read(true).write(plus);
+            (true, plus)
+        } else if m.starts_with('w') {
+            opts.write(true).create(true).truncate(true).read(plus);
+            (plus, true)
+        } else if m.starts_with('a') {
+            opts.append(true).create(true).read(plus);
+            (plus, true)
+        } else {
+            return Err(BasilError(format!("FOPEN: invalid mode '{}'; expected r/w/a variants", mode)));
+        };
+        let file = match opts.open(&path) {
+            Ok(file) => Stream::Disk(file),
+            Err(e) => match embedded(&path) {
+                Some(data) if e.kind() == io::ErrorKind::NotFound && !writable => Stream::Embedded(Cursor::new(data)),
+                // Non-throwing failure: -1 signals the open error
+                _ => return Ok(Value::Int(-1)),
+            },
+        };
+        let fh = self.next;
+        self.next += 1;
+        self.handles.insert(fh, Handle { file, readable, writable, owner });
+        Ok(Value::Int(fh))
+    }
+
+    /// FCLOSE fh%
+    pub fn fclose(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FCLOSE", args.len() == 1, "1 argument")?;
+        let _ = self.close(to_i64(&args[0])?);
+        Ok(Value::Bool(true))
+    }
+
+    /// FFLUSH fh%
+    pub fn fflush(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FFLUSH", args.len() == 1, "1 argument")?;
+        let e = self.get(to_i64(&args[0])?)?;
+        e.file.flush().map_err(|er| BasilError(format!("FFLUSH error: {}", er)))?;
+        Ok(Value::Bool(true))
+    }
+
+    /// FEOF(fh%) -> TRUE once nothing is left to read.
+    pub fn feof(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FEOF", args.len() == 1, "1 argument")?;
+        let e = self.get(to_i64(&args[0])?)?;
+        let cur = e.file.stream_position().map_err(|er| BasilError(format!("FEOF tell: {}", er)))?;
+        let mut b = [0u8; 1];
+        let n = e.file.read(&mut b).map_err(|er| BasilError(format!("FEOF read: {}", er)))?;
+        if n > 0 { let _ = e.file.seek(SeekFrom::Start(cur)); }
+        Ok(Value::Bool(n == 0))
+    }
+
+    /// FTELL&(fh%)
+    pub fn ftell(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FTELL&", args.len() == 1, "1 argument")?;
+        let e = self.get(to_i64(&args[0])?)?;
+        let pos = e.file.stream_position().map_err(|er| BasilError(format!("FTELL: {}", er)))?;
+        Ok(Value::Int(pos as i64))
+    }
+
+    /// FSEEK fh%, offset&, whence% (0 = start, 1 = current, 2 = end)
+    pub fn fseek(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FSEEK", args.len() == 3, "3 arguments")?;
+        let h = to_i64(&args[0])?;
+        let off = to_i64(&args[1])?;
+        let wh = to_i64(&args[2])?;
+        let e = self.get(h)?;
+        let whence = match wh {
+            0 => SeekFrom::Start(off as u64),
+            1 => SeekFrom::Current(off),
+            2 => SeekFrom::End(off),
+            _ => return Err(BasilError("FSEEK: whence must be 0,1,2".into())),
+        };
+        e.file.seek(whence).map_err(|er| BasilError(format!("FSEEK: {}", er)))?;
+        Ok(Value::Bool(true))
+    }
+
+    /// FREAD$(fh%, n&) -> up to `n` bytes.
+    pub fn fread(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FREAD$", args.len() == 2, "2 arguments")?;
+        let h = to_i64(&args[0])?;
+        let n = to_i64(&args[1])?;
+        if n <= 0 { return Ok(Value::Str(String::new())); }
+        let e = self.get(h)?;
+        if !e.readable { return Err(BasilError("FREAD$: handle not opened for reading".into())); }
+        let mut buf = vec![0u8; n as usize];
+        let got = e.file.read(&mut buf).map_err(|er| BasilError(format!("FREAD$: {}", er)))?;
+        buf.truncate(got);
+        Ok(Value::Str(String::from_utf8_lossy(&buf).to_string()))
+    }
+
+    /// FREADLINE$(fh%) -> the next line without its `\n` or `\r\n`.
+    pub fn freadline(&mut self, args: &[Value]) -> Result<Value> {
+        arity("FREADLINE$", args.len() == 1, "1 argument")?;
+        let e = self.get(to_i64(&args[0])?)?;
+        if !e.readable { return Err(BasilError("FREADLINE$: handle not opened for reading".into())); }
+        let mut out: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 1];
+        loop {
+            let n = e.file.read(&mut buf).map_err(|er| BasilError(format!("FREADLINE$: {}", er)))?;
+            if n == 0 || buf[0] == b'\n' { break; }
+            out.push(buf[0]);
+        }
+        if out.ends_with(b"\r") { out.pop(); }
+        Ok(Value::Str(String::from_utf8_lossy(&out).to_string()))
+    }
+
+    /// FWRITE fh%, s$ and FWRITELN fh%, s$
+    pub fn fwrite(&mut self, args: &[Value], newline: bool) -> Result<Value> {
+        let name = if newline { "FWRITELN" } else { "FWRITE" };
+        arity(name, args.len() == 2, "2 arguments")?;
+        let e = self.get(to_i64(&args[0])?)?;
+        if !e.writable { return Err(BasilError(format!("{}: handle not opened for writing", name))); }
+        let mut data = text_arg(&args[1]);
+        if newline { data.push('\n'); }
+        e.file.write_all(data.as_bytes()).map_err(|er| BasilError(format!("{}: {}", name, er)))?;
+        Ok(Value::Bool(true))
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> Self { Self::new() }
+}
+
+impl Drop for FileTable {
+    fn drop(&mut self) { self.close_all(); }
+}
@@ -0,0 +1,141 @@
+//! Data-format builtins: BASE64_*, JSON_* and CSV_*, each behind its `obj-*` feature.
+
+#[cfg(any(feature = "obj-base64", feature = "obj-json", feature = "obj-csv"))]
+use basil_common::{BasilError, Result};
+#[cfg(any(feature = "obj-base64", feature = "obj-json", feature = "obj-csv"))]
+use basil_bytecode::Value;
+#[cfg(any(feature = "obj-json", feature = "obj-csv"))]
+use serde_json::Value as JValue;
+
+#[cfg(any(feature = "obj-base64", feature = "obj-json", feature = "obj-csv"))]
+use crate::{arity, text_arg};
+
+/// BASE64_ENCODE$(text$)
+#[cfg(feature = "obj-base64")]
+pub fn base64_encode(args: &[Value]) -> Result<Value> {
+    use base64::{engine::general_purpose, Engine as _};
+    arity("BASE64_ENCODE$", args.len() == 1, "1 argument")?;
+    Ok(Value::Str(general_purpose::STANDARD.encode(text_arg(&args[0]).as_bytes())))
+}
+
+/// BASE64_DECODE$(text$); the decoded bytes must be UTF-8.
+#[cfg(feature = "obj-base64")]
+pub fn base64_decode(args: &[Value]) -> Result<Value> {
+    use base64::{engine::general_purpose, Engine as _};
+    arity("BASE64_DECODE$", args.len() == 1, "1 argument")?;
+    let bytes = general_purpose::STANDARD.decode(text_arg(&args[0])).map_err(|_| BasilError("BASE64_DECODE$: invalid Base64 string".into()))?;
+    String::from_utf8(bytes).map(Value::Str).map_err(|_| BasilError("BASE64_DECODE$: invalid UTF-8 in decoded data".into()))
+}
+
+/// JSON form of a value: arrays and lists become JSON arrays, dicts and objects (their readable
+/// properties) become JSON objects.
+#[cfg(feature = "obj-json")]
+pub fn value_to_json(v: &Value) -> Result<JValue> {
+    use serde_json::Map;
+    match v {
+        Value::Null => Ok(JValue::Null),
+        Value::Bool(b) => Ok(JValue::Bool(*b)),
+        Value::Int(i) => Ok(JValue::Number((*i).into())),
+        Value::Num(n) => serde_json::Number::from_f64(*n)
+            .map(JValue::Number)
+            .ok_or_else(|| BasilError("JSON_STRINGIFY$: NaN/Inf not representable".into())),
+        Value::Str(s) => Ok(JValue::String(s.clone())),
+        Value::Array(arr) => Ok(JValue::Array(arr.data.borrow().iter().map(value_to_json).collect::<Result<_>>()?)),
+        Value::Object(obj_rc) => {
+            let obj = obj_rc.borrow();
+            let mut map = Map::new();
+            for prop in obj.descriptor().properties.iter().filter(|p| p.readable) {
+                if let Ok(pv) = obj.get_prop(&prop.name) {
+                    map.insert(prop.name.clone(), value_to_json(&pv)?);
+                }
+            }
+            Ok(JValue::Object(map))
+        }
+        Value::List(items) => Ok(JValue::Array(items.borrow().iter().map(value_to_json).collect::<Result<_>>()?)),
+        Value::Dict(map) => {
+            let mut obj = Map::new();
+            for (k, v) in map.borrow().iter() {
+                obj.insert(k.clone(), value_to_json(v)?);
+            }
+            Ok(JValue::Object(obj))
+        }
+        Value::StrArray2D { rows, cols, data } => Ok(JValue::Array((0..*rows)
+            .map(|r| JValue::Array((0..*cols).map(|c| JValue::String(data[r * *cols + c].clone())).collect()))
+            .collect())),
+        Value::Func(_) => Err(BasilError("JSON_STRINGIFY$: cannot stringify a function".into())),
+    }
+}
+
+/// JSON_PARSE$(text$): validate JSON text and return it normalized.
+#[cfg(feature = "obj-json")]
+pub fn json_parse(args: &[Value]) -> Result<Value> {
+    arity("JSON_PARSE$", args.len() == 1, "1 argument")?;
+    let v: JValue = serde_json::from_str(&text_arg(&args[0])).map_err(|e| BasilError(format!("JSON_PARSE$: invalid JSON: {}", e)))?;
+    let out = serde_json::to_string(&v).map_err(|e| BasilError(format!("JSON_PARSE$: serialize failed: {}", e)))?;
+    Ok(Value::Str(out))
+}
+
+/// JSON_STRINGIFY$(value): strings that already hold JSON pass through normalized, other
+/// strings become JSON strings, everything else goes through [`value_to_json`].
+#[cfg(feature = "obj-json")]
+pub fn json_stringify(args: &[Value]) -> Result<Value> {
+    arity("JSON_STRINGIFY$", args.len() == 1, "1 argument")?;
+    let out = match &args[0] {
+        Value::Str(s) => match serde_json::from_str::<JValue>(s) {
+            Ok(v) => serde_json::to_string(&v).map_err(|e| BasilError(format!("JSON_STRINGIFY$: serialize failed: {}", e)))?,
+            Err(_) => serde_json::to_string(s).map_err(|e| BasilError(format!("JSON_STRINGIFY$: wrap failed: {}", e)))?,
+        },
+        other => serde_json::to_string(&value_to_json(other)?).map_err(|e| BasilError(format!("JSON_STRINGIFY$: serialize failed: {}", e)))?,
+    };
+    Ok(Value::Str(out))
+}
+
+/// CSV_PARSE$(csv_text$) -> JSON array with one object per row, keyed by the header line.
+#[cfg(feature = "obj-csv")]
+pub fn csv_parse(args: &[Value]) -> Result<Value> {
+    arity("CSV_PARSE$", args.len() == 1, "1 argument")?;
+    let s = text_arg(&args[0]);
+    let mut rdr = csv::ReaderBuilder::new().has_headers(true).from_reader(s.as_bytes());
+    let headers = rdr.headers().map_err(|e| BasilError(format!("CSV_PARSE$: read headers failed: {}", e)))?.clone();
+    let mut rows: Vec<JValue> = Vec::new();
+    for rec in rdr.records() {
+        let rec = rec.map_err(|e| BasilError(format!("CSV_PARSE$: read record failed: {}", e)))?;
+        let mut obj = serde_json::Map::new();
+        for (i, field) in rec.iter().enumerate() {
+            obj.insert(headers.get(i).unwrap_or("").to_string(), JValue::String(field.to_string()));
+        }
+        rows.push(JValue::Object(obj));
+    }
+    let out = serde_json::to_string(&rows).map_err(|e| BasilError(format!("CSV_PARSE$: serialize failed: {}", e)))?;
+    Ok(Value::Str(out))
+}
+
+/// CSV_WRITE$(rows_json$): a JSON array of objects back to CSV. Columns are the keys of the
+/// first row followed by any new keys of later rows.
+#[cfg(feature = "obj-csv")]
+pub fn csv_write(args: &[Value]) -> Result<Value> {
+    arity("CSV_WRITE$", args.len() == 1, "1 argument")?;
+    let rows: JValue = serde_json::from_str(&text_arg(&args[0])).map_err(|e| BasilError(format!("CSV_WRITE$: invalid JSON: {}", e)))?;
+    let arr = rows.as_array().ok_or_else(|| BasilError("CSV_WRITE$: expected JSON array of objects".into()))?;
+    let mut headers: Vec<String> = Vec::new();
+    for obj in arr.iter().filter_map(|v| v.as_object()) {
+        for k in obj.keys() {
+            if !headers.contains(k) { headers.push(k.clone()); }
+        }
+    }
+    let mut wtr = csv::WriterBuilder::new().from_writer(vec![]);
+    wtr.write_record(headers.iter()).map_err(|e| BasilError(format!("CSV_WRITE$: write headers failed: {}", e)))?;
+    for v in arr.iter() {
+        let obj = v.as_object().ok_or_else(|| BasilError("CSV_WRITE$: array items must be objects".into()))?;
+        let row: Vec<String> = headers.iter().map(|h| match obj.get(h) {
+            Some(JValue::String(s)) => s.clone(),
+            Some(JValue::Number(n)) => n.to_string(),
+            Some(JValue::Bool(b)) => b.to_string(),
+            Some(JValue::Null) | None => String::new(),
+            Some(other) => serde_json::to_string(other).unwrap_or_default(),
+        }).collect();
+        wtr.write_record(&row).map_err(|e| BasilError(format!("CSV_WRITE$: write row failed: {}", e)))?;
+    }
+    let bytes = wtr.into_inner().map_err(|e| BasilError(format!("CSV_WRITE$: finalize failed: {}", e)))?;
+    String::from_utf8(bytes).map(Value::Str).map_err(|e| BasilError(format!("CSV_WRITE$: utf8 failed: {}", e)))
+}
@@ -0,0 +1,292 @@
+/*
+
+ ▄▄▄▄    ██▓    ▄▄▄       ▄████▄   ██ ▄█▀ ██▀███   █    ██   ██████  ██░ ██
+▓█████▄ ▓██▒   ▒████▄    ▒██▀ ▀█   ██▄█▒ ▓██ ▒ ██▒ ██  ▓██▒▒██    ▒ ▓██░ ██▒
+▒██▒ ▄██▒██░   ▒██  ▀█▄  ▒▓█    ▄ ▓███▄░ ▓██ ░▄█ ▒▓██  ▒██░░ ▓██▄   ▒██▀▀██░
+▒██░█▀  ▒██░   ░██▄▄▄▄██ ▒▓▓▄ ▄██▒▓██ █▄ ▒██▀▀█▄  ▓▓█  ░██░  ▒   ██▒░▓█ ░██
+░▓█  ▀█▓░██████▒▓█   ▓██▒▒ ▓███▀ ░▒██▒ █▄░██▓ ▒██▒▒▒█████▓ ▒██████▒▒░▓█▒░██▓
+░▒▓███▀▒░ ▒░▓  ░▒▒   ▓▒█░░ ░▒ ▒  ░▒ ▒▒ ▓▒░ ▒▓ ░▒▓░░▒▓▒ ▒ ▒ ▒ ▒▓▒ ▒ ░ ▒ ░░▒░▒
+▒░▒   ░ ░ ░ ▒  ░ ▒   ▒▒ ░  ░  ▒   ░ ░▒ ▒░  ░▒ ░ ▒░░░▒░ ░ ░ ░ ░▒  ░ ░ ▒ ░▒░ ░
+ ░    ░   ░ ░    ░   ▒   ░        ░ ░░ ░   ░░   ░  ░░░ ░ ░ ░  ░  ░   ░  ░░ ░
+ ░          ░  ░     ░  ░░ ░      ░  ░      ░        ░           ░   ░  ░  ░
+      ░                  ░
+Copyright (C) 2026, Blackrush LLC
+Created by Erik Olson, Tarpon Springs, Florida
+For more information, visit BlackrushDrive.com
+
+MIT License
+
+Copyright (c) 2026 Erik Lee Olson for Blackrush, LLC
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+
+*/
+
+//! Builtin functions shared by the VM and by AOT-compiled programs (libbasilrt).
+//!
+//! Every builtin here is a plain function over evaluated arguments, so `basil-vm` can call it
+//! from `Op::Builtin` and the runtime behind `bcc` can call it by name, with the same results
+//! and the same error messages. Builtins that depend on interpreter state (INPUT$ in test
+//! mode, CGI variables, EXIT, SPAWN, TEST blocks) stay in the VM; open files live in a
+//! [`files::FileTable`] owned by whoever runs the program.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use basil_common::{BasilError, Result};
+use basil_bytecode::{ArrayObj, ElemType, Value};
+
+pub mod text;
+pub mod system;
+pub mod files;
+pub mod formats;
+pub mod ext;
+
+/// A builtin taking its arguments in call order.
+pub type Builtin = fn(&[Value]) -> Result<Value>;
+
+/// Stateless builtins by their BASIC name. File-handle builtins (FOPEN...) go through
+/// [`files::FileTable::call`] instead.
+pub static TABLE: &[(&str, Builtin)] = &[
+    ("LEN", text::len),
+    ("MID$", text::mid),
+    ("LEFT$", text::left),
+    ("RIGHT$", text::right),
+    ("INSTR", text::instr),
+    ("TYPE$", text::type_name),
+    ("HTML$", text::html),
+    ("HTML", text::html),
+    ("UCASE$", text::ucase),
+    ("LCASE$", text::lcase),
+    ("TRIM$", text::trim),
+    ("CHR$", text::chr),
+    ("ASC%", text::asc),
+    ("ESCAPE$", text::escape),
+    ("UNESCAPE$", text::unescape),
+    ("URLENCODE$", text::urlencode),
+    ("URLDECODE$", text::urldecode),
+    ("STRING$", text::string_of),
+    ("SLEEP", system::sleep),
+    ("ENV$", system::env),
+    ("SETENV", system::setenv),
+    ("SHELL", system::shell),
+    ("MKDIRS%", system::mkdirs),
+    ("LOADENV%", system::loadenv),
+    ("READFILE$", files::readfile),
+    ("WRITEFILE", files::writefile),
+    ("APPENDFILE", files::appendfile),
+    ("COPY", files::copy),
+    ("MOVE", files::move_file),
+    ("RENAME", files::rename),
+    ("DELETE", files::delete),
+    ("DIR$", files::dir),
+    ("ARRAY_ROWS%", array_rows),
+    ("ARRAY_COLS%", array_cols),
+    #[cfg(feature = "obj-base64")] ("BASE64_ENCODE$", formats::base64_encode),
+    #[cfg(feature = "obj-base64")] ("BASE64_DECODE$", formats::base64_decode),
+    #[cfg(feature = "obj-json")] ("JSON_PARSE$", formats::json_parse),
+    #[cfg(feature = "obj-json")] ("JSON_STRINGIFY$", formats::json_stringify),
+    #[cfg(feature = "obj-csv")] ("CSV_PARSE$", formats::csv_parse),
+    #[cfg(feature = "obj-csv")] ("CSV_WRITE$", formats::csv_write),
+    #[cfg(feature = "obj-zip")] ("ZIP_EXTRACT_ALL", ext::zip_extract_all),
+    #[cfg(feature = "obj-zip")] ("ZIP_COMPRESS_FILE", ext::zip_compress_file),
+    #[cfg(feature = "obj-zip")] ("ZIP_COMPRESS_DIR", ext::zip_compress_dir),
+    #[cfg(feature = "obj-zip")] ("ZIP_LIST$", ext::zip_list),
+    #[cfg(feature = "obj-curl")] ("HTTP_GET$", ext::http_get),
+    #[cfg(feature = "obj-curl")] ("HTTP_POST$", ext::http_post),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_OPEN%", ext::sqlite_open),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_CLOSE", ext::sqlite_close),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_EXEC%", ext::sqlite_exec),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_QUERY2D$", ext::sqlite_query2d),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_LAST_INSERT_ID%", ext::sqlite_last_insert_id),
+    #[cfg(feature = "obj-term")] ("CLS", ext::cls),
+    #[cfg(feature = "obj-term")] ("CLEAR", ext::cls),
+    #[cfg(feature = "obj-term")] ("HOME", ext::cls),
+    #[cfg(feature = "obj-term")] ("LOCATE", ext::locate),
+    #[cfg(feature = "obj-term")] ("COLOR", ext::color),
+    #[cfg(feature = "obj-term")] ("COLOR_RESET", ext::color_reset),
+    #[cfg(feature = "obj-term")] ("ATTR", ext::attr),
+    #[cfg(feature = "obj-term")] ("ATTR_RESET", ext::attr_reset),
+    #[cfg(feature = "obj-term")] ("CURSOR_SAVE", ext::cursor_save),
+    #[cfg(feature = "obj-term")] ("CURSOR_RESTORE", ext::cursor_restore),
+    #[cfg(feature = "obj-term")] ("TERM_COLS%", ext::term_cols),
+    #[cfg(feature = "obj-term")] ("TERM_ROWS%", ext::term_rows),
+    #[cfg(feature = "obj-term")] ("CURSOR_HIDE", ext::cursor_hide),
+    #[cfg(feature = "obj-term")] ("CURSOR_SHOW", ext::cursor_show),
+    #[cfg(feature = "obj-term")] ("TERM_ERR$", ext::term_err),
+    #[cfg(feature = "obj-term")] ("TERM.INIT", ext::term_init),
+    #[cfg(feature = "obj-term")] ("TERM.END", ext::term_end),
+    #[cfg(feature = "obj-term")] ("TERM.RAW", ext::term_raw),
+    #[cfg(feature = "obj-term")] ("ALTSCREEN_ON", ext::altscreen_on),
+    #[cfg(feature = "obj-term")] ("ALTSCREEN_OFF", ext::altscreen_off),
+    #[cfg(feature = "obj-term")] ("TERM.FLUSH", ext::term_flush),
+    #[cfg(feature = "obj-term")] ("TERM.POLLKEY$", ext::term_pollkey),
+    #[cfg(feature = "obj-daw")] ("DAW_STOP", ext::daw_stop),
+    #[cfg(feature = "obj-daw")] ("DAW_ERR$", ext::daw_err),
+    #[cfg(feature = "obj-daw")] ("AUDIO_RECORD%", ext::audio_record),
+    #[cfg(feature = "obj-daw")] ("AUDIO_PLAY%", ext::audio_play),
+    #[cfg(feature = "obj-daw")] ("AUDIO_MONITOR%", ext::audio_monitor),
+    #[cfg(feature = "obj-daw")] ("MIDI_CAPTURE%", ext::midi_capture),
+    #[cfg(feature = "obj-daw")] ("SYNTH_LIVE%", ext::synth_live),
+    #[cfg(feature = "obj-daw")] ("DAW_RESET", ext::daw_reset),
+];
+
+/// The builtin called `name` (upper-case), if this build has it.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    TABLE.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+/// TYPE$ of a value: INTEGER, STRING, FLOAT[], the object's type name and so on.
+pub fn type_of(v: &Value) -> String {
+    match v {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(_) => "BOOL".to_string(),
+        Value::Num(_) => "FLOAT".to_string(),
+        Value::Int(_) => "INTEGER".to_string(),
+        Value::Str(_) => "STRING".to_string(),
+        Value::Func(_) => "FUNCTION".to_string(),
+        Value::Array(arr_rc) => {
+            let arr = arr_rc.as_ref();
+            let base = match &arr.elem {
+                ElemType::Num => "FLOAT".to_string(),
+                ElemType::Int => "INTEGER".to_string(),
+                ElemType::Str => "STRING".to_string(),
+                ElemType::Obj(Some(tn)) => tn.clone(),
+                ElemType::Obj(None) => "OBJECT".to_string(),
+            };
+            format!("{}[]", base)
+        }
+        Value::Object(rc) => rc.borrow().type_name().to_string(),
+        Value::List(_) => "LIST".to_string(),
+        Value::Dict(_) => "DICT".to_string(),
+        Value::StrArray2D { .. } => "STRING[][]".to_string(),
+    }
+}
+
+/// Truth value used by IF, WHILE, NOT, AND and OR.
+pub fn is_truthy(v: &Value) -> bool {
+    match v {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Num(n) => *n != 0.0,
+        Value::Int(i) => *i != 0,
+        Value::Str(s) => !s.is_empty(),
+        Value::Func(_) | Value::Array(_) | Value::Object(_) => true,
+        Value::StrArray2D { rows, cols, data } => *rows > 0 && *cols > 0 && !data.is_empty(),
+        Value::List(rc) => !rc.borrow().is_empty(),
+        Value::Dict(rc) => !rc.borrow().is_empty(),
+    }
+}
+
+/// Integer value of a numeric argument, truncating floats.
+pub fn to_i64(v: &Value) -> Result<i64> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        Value::Num(n) => Ok(n.trunc() as i64),
+        other => Err(BasilError(format!("expected numeric value, got {}", type_of(other)))),
+    }
+}
+
+/// A `STRING[]` array holding `vals`, as returned by DIR$, GET$ and friends.
+pub fn string_array(vals: Vec<String>) -> Value {
+    let dims = vec![vals.len()];
+    let data: Vec<Value> = vals.into_iter().map(Value::Str).collect();
+    Value::Array(Rc::new(ArrayObj { elem: ElemType::Str, dims, data: RefCell::new(data) }))
+}
+
+/// Internal builtin 138: turn the rows x cols result of SQLITE_QUERY2D$ into a 2-D string array
+/// for `LET name$() = ...`.
+pub fn str2d_to_array(args: &[Value]) -> Result<Value> {
+    if args.len() != 1 { return Err(BasilError("internal builtin 138 expects 1 argument".into())); }
+    match &args[0] {
+        Value::StrArray2D { rows, cols, data } => {
+            let arr_data: Vec<Value> = data.iter().map(|s| Value::Str(s.clone())).collect();
+            Ok(Value::Array(Rc::new(ArrayObj { elem: ElemType::Str, dims: vec![*rows, *cols], data: RefCell::new(arr_data) })))
+        }
+        other => Err(BasilError(format!("builtin 138 expects StrArray2D, got {}", type_of(other)))),
+    }
+}
+
+/// ARRAY_ROWS%(arr$())
+pub fn array_rows(args: &[Value]) -> Result<Value> { array_dim(args, "ARRAY_ROWS%", 0) }
+
+/// ARRAY_COLS%(arr$())
+pub fn array_cols(args: &[Value]) -> Result<Value> { array_dim(args, "ARRAY_COLS%", 1) }
+
+fn array_dim(args: &[Value], name: &str, which: usize) -> Result<Value> {
+    if args.len() != 1 { return Err(BasilError(format!("{} expects 1 argument", name))); }
+    match &args[0] {
+        Value::Array(rc) => {
+            if rc.dims.len() != 2 { return Err(BasilError(format!("{}: expected 2-D array", name))); }
+            Ok(Value::Int(rc.dims[which] as i64))
+        }
+        _ => Err(BasilError(format!("{}: expected array", name))),
+    }
+}
+
+// Argument as text; non-strings use their PRINT form
+pub(crate) fn text_arg(v: &Value) -> String {
+    match v { Value::Str(s) => s.clone(), other => format!("{}", other) }
+}
+
+pub(crate) fn arity(name: &str, ok: bool, expects: &str) -> Result<()> {
+    if ok { Ok(()) } else { Err(BasilError(format!("{} expects {}", name, expects))) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_finds_shared_builtins_only() {
+        let len = lookup("LEN").expect("LEN");
+        assert!(matches!(len(&[Value::Str("abc".into())]), Ok(Value::Int(3))));
+        assert!(lookup("INPUT$").is_none(), "INPUT$ needs the host's stdin handling");
+        assert!(lookup("NOPE").is_none());
+    }
+
+    #[test]
+    fn form_encoding_round_trips() {
+        assert_eq!(text::form_encode("a b&c=é"), "a+b%26c%3D%C3%A9");
+        assert_eq!(text::form_decode("a+b%26c%3D%C3%A9"), "a b&c=é");
+        assert_eq!(text::form_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn glob_matches_star_and_question_mark() {
+        assert!(files::glob_match("*.basil", "hello.basil"));
+        assert!(files::glob_match("log?.txt", "log1.txt"));
+        assert!(!files::glob_match("log?.txt", "log12.txt"));
+        assert!(!files::glob_match("*.basil", "hello.basilx"));
+    }
+
+    #[test]
+    fn embedded_files_are_read_when_missing_on_disk() {
+        files::embed(&[("no_such_dir/words.txt", b"alpha\nbeta\n")]);
+        let path = Value::Str(".\\no_such_dir\\words.txt".into());
+        assert!(matches!(files::readfile(std::slice::from_ref(&path)), Ok(Value::Str(s)) if s == "alpha\nbeta\n"));
+
+        let mut table = files::FileTable::new();
+        let fh = table.fopen(&[path.clone(), Value::Str("r".into())], 0).unwrap();
+        assert!(matches!(table.freadline(std::slice::from_ref(&fh)), Ok(Value::Str(s)) if s == "alpha"));
+        assert!(table.fwrite(&[fh, Value::Str("x".into())], false).is_err());
+        // Writing would need a real file, which doesn't exist
+        assert!(matches!(table.fopen(&[path, Value::Str("r+".into())], 0), Ok(Value::Int(-1))));
+    }
+}
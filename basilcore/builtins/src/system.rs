@@ -0,0 +1,96 @@
+//! Process builtins: SLEEP, the environment (ENV$, SETENV, LOADENV%), SHELL and MKDIRS%.
+
+use std::env as std_env;
+use std::fs;
+
+use basil_common::Result;
+use basil_bytecode::Value;
+
+use crate::{arity, text_arg, to_i64};
+
+/// SLEEP(ms)
+pub fn sleep(args: &[Value]) -> Result<Value> {
+    arity("SLEEP", args.len() == 1, "1 argument")?;
+    let ms = to_i64(&args[0])?.max(0) as u64;
+    std::thread::sleep(std::time::Duration::from_millis(ms));
+    Ok(Value::Int(0))
+}
+
+/// ENV$(name$) from the process environment, "" when unset. The VM looks at the CGI
+/// variables of an in-process request first.
+pub fn env(args: &[Value]) -> Result<Value> {
+    arity("ENV$", args.len() == 1, "1 argument")?;
+    Ok(Value::Str(std_env::var(text_arg(&args[0])).unwrap_or_default()))
+}
+
+/// SETENV/EXPORTENV name$, value, exportFlag. Exporting only reaches beyond this process on
+/// Windows (via `setx`); elsewhere a child can't change its parent's environment.
+pub fn setenv(args: &[Value]) -> Result<Value> {
+    arity("SETENV", args.len() == 3, "3 arguments (name$, value, exportFlag)")?;
+    let name = text_arg(&args[0]);
+    let value = text_arg(&args[1]);
+    let export = match &args[2] {
+        Value::Bool(b) => *b,
+        Value::Int(i) => *i != 0,
+        Value::Num(n) => *n != 0.0,
+        _ => false,
+    };
+    std_env::set_var(&name, &value);
+    #[cfg(windows)]
+    if export {
+        let status = std::process::Command::new("cmd").args(["/C", "setx", &name, &value]).status();
+        return Ok(Value::Bool(status.map(|s| s.success()).unwrap_or(false)));
+    }
+    let _ = export;
+    Ok(Value::Bool(true))
+}
+
+/// SHELL(cmd$) -> exit code, -1 if the shell couldn't be started.
+pub fn shell(args: &[Value]) -> Result<Value> {
+    arity("SHELL", args.len() == 1, "1 argument")?;
+    let cmd = text_arg(&args[0]);
+    #[cfg(windows)]
+    let status = std::process::Command::new("cmd").args(["/C", &cmd]).status();
+    #[cfg(not(windows))]
+    let status = std::process::Command::new("sh").args(["-c", &cmd]).status();
+    Ok(Value::Int(status.map(|s| s.code().unwrap_or(-1) as i64).unwrap_or(-1)))
+}
+
+/// MKDIRS%(path$) -> 1 if the directory exists afterwards, else 0.
+pub fn mkdirs(args: &[Value]) -> Result<Value> {
+    arity("MKDIRS%", args.len() == 1, "1 argument")?;
+    Ok(Value::Int(if fs::create_dir_all(text_arg(&args[0])).is_ok() { 1 } else { 0 }))
+}
+
+/// LOADENV%([file$]) -> 1 on success, 0 if the file can't be read. Loads `name=value` lines
+/// (default file `.env`); bad lines are reported on stderr and skipped.
+pub fn loadenv(args: &[Value]) -> Result<Value> {
+    arity("LOADENV%", args.len() <= 1, "0 or 1 argument")?;
+    let file = match args.first().map(text_arg) {
+        Some(s) if !s.trim().is_empty() => s.trim().to_string(),
+        _ => ".env".to_string(),
+    };
+    let contents = match fs::read_to_string(&file) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("warning: LOADENV% could not read {}: {}", file, e);
+            return Ok(Value::Int(0));
+        }
+    };
+    for (i, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') { continue; }
+        let Some((key, val)) = trimmed.split_once('=') else {
+            eprintln!("warning: LOADENV% {}:{}: invalid line (expected name=value or comment)", file, i + 1);
+            continue;
+        };
+        let (key, val) = (key.trim(), val.trim());
+        if key.is_empty() {
+            eprintln!("warning: LOADENV% {}:{}: missing key before '='", file, i + 1);
+            continue;
+        }
+        let quoted = val.len() >= 2 && ((val.starts_with('"') && val.ends_with('"')) || (val.starts_with('\'') && val.ends_with('\'')));
+        std_env::set_var(key, if quoted { &val[1..val.len() - 1] } else { val });
+    }
+    Ok(Value::Int(1))
+}
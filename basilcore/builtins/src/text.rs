@@ -0,0 +1,190 @@
+//! String builtins: LEN, MID$, LEFT$, RIGHT$, INSTR, case and trimming, CHR$/ASC%, the
+//! escaping helpers (ESCAPE$, HTML$, URLENCODE$) and STRING$.
+
+use basil_common::{BasilError, Result};
+use basil_bytecode::Value;
+
+use crate::{arity, to_i64, type_of};
+
+fn str_arg(v: &Value, name: &str, what: &str) -> Result<String> {
+    match v { Value::Str(s) => Ok(s.clone()), _ => Err(BasilError(format!("{} {} must be string", name, what))) }
+}
+
+fn num_arg(v: &Value, name: &str, what: &str) -> Result<i64> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        Value::Num(n) => Ok(n.trunc() as i64),
+        _ => Err(BasilError(format!("{} {} must be numeric", name, what))),
+    }
+}
+
+/// LEN(x): characters in a string, elements in an array, list or dict.
+pub fn len(args: &[Value]) -> Result<Value> {
+    arity("LEN", args.len() == 1, "1 argument")?;
+    Ok(Value::Int(match &args[0] {
+        Value::Str(s) => s.chars().count() as i64,
+        Value::Array(arr) => arr.dims.iter().fold(1usize, |acc, d| acc.saturating_mul(*d)) as i64,
+        Value::List(rc) => rc.borrow().len() as i64,
+        Value::Dict(rc) => rc.borrow().len() as i64,
+        // Anything else counts the characters of its PRINT form
+        other => format!("{}", other).chars().count() as i64,
+    }))
+}
+
+/// MID$(s, start [, len]) with a 1-based start.
+pub fn mid(args: &[Value]) -> Result<Value> {
+    arity("MID$", args.len() == 2 || args.len() == 3, "2 or 3 arguments")?;
+    let s = str_arg(&args[0], "MID$", "arg 1")?;
+    let start = num_arg(&args[1], "MID$", "start")?;
+    let chars = s.chars().skip(if start <= 1 { 0 } else { start as usize - 1 });
+    if args.len() == 2 { return Ok(Value::Str(chars.collect())); }
+    let len = num_arg(&args[2], "MID$", "length")?;
+    Ok(Value::Str(if len <= 0 { String::new() } else { chars.take(len as usize).collect() }))
+}
+
+/// LEFT$(s, n)
+pub fn left(args: &[Value]) -> Result<Value> {
+    arity("LEFT$", args.len() == 2, "2 arguments")?;
+    let s = str_arg(&args[0], "LEFT$", "arg 1")?;
+    let n = num_arg(&args[1], "LEFT$", "count")?;
+    Ok(Value::Str(if n <= 0 { String::new() } else { s.chars().take(n as usize).collect() }))
+}
+
+/// RIGHT$(s, n)
+pub fn right(args: &[Value]) -> Result<Value> {
+    arity("RIGHT$", args.len() == 2, "2 arguments")?;
+    let s = str_arg(&args[0], "RIGHT$", "arg 1")?;
+    let n = num_arg(&args[1], "RIGHT$", "count")?;
+    if n <= 0 { return Ok(Value::Str(String::new())); }
+    let total = s.chars().count();
+    let take = (n as usize).min(total);
+    Ok(Value::Str(s.chars().skip(total - take).collect()))
+}
+
+/// INSTR(hay, needle [, start]): 0-based index of needle at or after start, or 0 if absent.
+pub fn instr(args: &[Value]) -> Result<Value> {
+    arity("INSTR", args.len() == 2 || args.len() == 3, "2 or 3 arguments")?;
+    let hay = str_arg(&args[0], "INSTR", "arg 1")?;
+    let needle = str_arg(&args[1], "INSTR", "arg 2")?;
+    let start = if args.len() == 3 { num_arg(&args[2], "INSTR", "start")?.max(0) as usize } else { 0 };
+    let total = hay.chars().count();
+    if needle.is_empty() { return Ok(Value::Int(start.min(total) as i64)); }
+    if start > total { return Ok(Value::Int(0)); }
+    let byte = hay.char_indices().nth(start).map(|(b, _)| b).unwrap_or(hay.len());
+    Ok(Value::Int(match hay[byte..].find(&needle) {
+        Some(rel) => hay[..byte + rel].chars().count() as i64,
+        None => 0,
+    }))
+}
+
+/// TYPE$(value)
+pub fn type_name(args: &[Value]) -> Result<Value> {
+    arity("TYPE$", args.len() == 1, "1 argument")?;
+    Ok(Value::Str(type_of(&args[0])))
+}
+
+/// HTML$(x): escape `& < > " '` for output inside a page.
+pub fn html(args: &[Value]) -> Result<Value> {
+    arity("HTML", args.len() == 1, "1 argument")?;
+    let s = format!("{}", args[0]);
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    Ok(Value::Str(out))
+}
+
+fn map_str(args: &[Value], name: &str, f: impl Fn(&str) -> String) -> Result<Value> {
+    arity(name, args.len() == 1, "1 argument")?;
+    let s = str_arg(&args[0], name, "arg")?;
+    Ok(Value::Str(f(&s)))
+}
+
+/// UCASE$(s)
+pub fn ucase(args: &[Value]) -> Result<Value> { map_str(args, "UCASE$", str::to_uppercase) }
+
+/// LCASE$(s)
+pub fn lcase(args: &[Value]) -> Result<Value> { map_str(args, "LCASE$", str::to_lowercase) }
+
+/// TRIM$(s)
+pub fn trim(args: &[Value]) -> Result<Value> { map_str(args, "TRIM$", |s| s.trim().to_string()) }
+
+/// ESCAPE$(s): SQL string literal escape (single quotes doubled).
+pub fn escape(args: &[Value]) -> Result<Value> { map_str(args, "ESCAPE$", |s| s.replace('\'', "''")) }
+
+/// UNESCAPE$(s): undo ESCAPE$ ('' -> ').
+pub fn unescape(args: &[Value]) -> Result<Value> { map_str(args, "UNESCAPE$", |s| s.replace("''", "'")) }
+
+/// URLENCODE$(s): application/x-www-form-urlencoded, spaces become '+'.
+pub fn urlencode(args: &[Value]) -> Result<Value> { map_str(args, "URLENCODE$", form_encode) }
+
+/// URLDECODE$(s): reverses URLENCODE$; '+' becomes a space.
+pub fn urldecode(args: &[Value]) -> Result<Value> { map_str(args, "URLDECODE$", form_decode) }
+
+/// CHR$(code): the character with that code point, or "" when out of range.
+pub fn chr(args: &[Value]) -> Result<Value> {
+    arity("CHR$", args.len() == 1, "1 argument")?;
+    let n = num_arg(&args[0], "CHR$", "arg")?;
+    Ok(Value::Str(if !(0..=0x10FFFF).contains(&n) { String::new() } else { char::from_u32(n as u32).map(|c| c.to_string()).unwrap_or_default() }))
+}
+
+/// ASC%(s): code point of the first character, 0 for "".
+pub fn asc(args: &[Value]) -> Result<Value> {
+    arity("ASC%", args.len() == 1, "1 argument")?;
+    let s = str_arg(&args[0], "ASC%", "arg")?;
+    Ok(Value::Int(s.chars().next().map(|c| c as i64).unwrap_or(0)))
+}
+
+/// STRING$(n, ch$ or code%): `n` copies of a string, or of the character with that code.
+pub fn string_of(args: &[Value]) -> Result<Value> {
+    arity("STRING$", args.len() == 2, "2 arguments")?;
+    let n = to_i64(&args[0])?;
+    let n = if n <= 0 { 0usize } else { (n as usize).min(1_000_000) };
+    let unit = match &args[1] {
+        Value::Str(s) => s.clone(),
+        other => char::from_u32((to_i64(other)? as u32) & 0xFF).unwrap_or('\u{0000}').to_string(),
+    };
+    Ok(Value::Str(unit.repeat(n)))
+}
+
+/// Percent-encode for a query string or form body.
+pub fn form_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Decode `+` and `%XX` escapes; malformed escapes are kept as written.
+pub fn form_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(h), Some(l)) => { out.push(h << 4 | l); i += 3; continue; }
+                    _ => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -45,6 +45,8 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use basil_common::Result;
 
+pub mod promise;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ElemType { Num, Int, Str, Obj(Option<String>) }
 
@@ -309,6 +311,43 @@ pub enum Op {
     Halt  = 255,
 }
 
+impl Op {
+    pub fn from_u8(byte: u8) -> Option<Op> {
+        Some(match byte {
+            1=>Op::Const, 2=>Op::LoadGlobal, 3=>Op::StoreGlobal,
+            11=>Op::LoadLocal, 12=>Op::StoreLocal,
+            20=>Op::Add, 21=>Op::Sub, 22=>Op::Mul, 23=>Op::Div, 24=>Op::Neg, 25=>Op::Mod,
+            30=>Op::Eq, 31=>Op::Ne, 32=>Op::Lt, 33=>Op::Le, 34=>Op::Gt, 35=>Op::Ge,
+            40=>Op::Jump, 41=>Op::JumpIfFalse, 42=>Op::JumpBack,
+            50=>Op::Call, 51=>Op::Ret,
+            60=>Op::Print, 61=>Op::Pop, 62=>Op::ToInt, 63=>Op::Builtin, 64=>Op::SetLine,
+            70=>Op::ArrMake, 71=>Op::ArrGet, 72=>Op::ArrSet,
+            80=>Op::NewObj, 81=>Op::GetProp, 82=>Op::SetProp, 83=>Op::CallMethod, 84=>Op::DescribeObj,
+            90=>Op::EnumNew, 91=>Op::EnumMoveNext, 92=>Op::EnumCurrent, 93=>Op::EnumDispose,
+            100=>Op::NewClass, 101=>Op::GetMember, 102=>Op::SetMember, 103=>Op::CallMember, 104=>Op::DestroyInstance,
+            105=>Op::ExecString, 106=>Op::EvalString,
+            110=>Op::Gosub, 111=>Op::GosubBack, 112=>Op::GosubRet, 113=>Op::GosubPop,
+            120=>Op::TryPush, 121=>Op::TryPop, 122=>Op::Raise, 123=>Op::Reraise, 124=>Op::Stop,
+            255=>Op::Halt,
+            _ => return None,
+        })
+    }
+
+    /// Number of inline operand bytes following the opcode (as read by the VM).
+    pub fn operand_len(self) -> usize {
+        match self {
+            Op::LoadGlobal | Op::StoreGlobal | Op::LoadLocal | Op::StoreLocal
+            | Op::Call | Op::ArrGet | Op::ArrSet => 1,
+            Op::Const | Op::Jump | Op::JumpIfFalse | Op::JumpBack | Op::Gosub | Op::GosubBack
+            | Op::SetLine | Op::GetProp | Op::SetProp | Op::GetMember | Op::SetMember => 2,
+            Op::Builtin => 2,
+            Op::NewObj | Op::CallMethod | Op::CallMember => 3,
+            Op::ArrMake | Op::TryPush => 4,
+            _ => 0,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Chunk {
     pub code:   Vec<u8>,
@@ -0,0 +1,199 @@
+//! PROMISE objects: the handle an async object method returns while its work runs elsewhere.
+//!
+//! An object method that starts background work (an HTTP request on the tokio runtime, a SQL
+//! query on the pool, ...) calls `promise()`, moves the `Resolver` into the background job and
+//! returns the PROMISE to the script right away. `AWAIT p@` blocks the VM until the job settles.
+//! Only plain data (`PromiseValue`) can travel back, because `Value` is not thread-safe.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use basil_common::{BasilError, Result};
+
+use crate::{BasicObject, MethodDesc, ObjectDescriptor, ObjectRef, PropDesc, Value};
+
+/// Thread-safe result payload of a promise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromiseValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Num(f64),
+    Str(String),
+    List(Vec<PromiseValue>),
+    Dict(Vec<(String, PromiseValue)>),
+}
+
+impl PromiseValue {
+    pub fn into_value(self) -> Value {
+        match self {
+            PromiseValue::Null => Value::Null,
+            PromiseValue::Bool(b) => Value::Bool(b),
+            PromiseValue::Int(i) => Value::Int(i),
+            PromiseValue::Num(n) => Value::Num(n),
+            PromiseValue::Str(s) => Value::Str(s),
+            PromiseValue::List(items) => Value::List(Rc::new(RefCell::new(items.into_iter().map(PromiseValue::into_value).collect()))),
+            PromiseValue::Dict(entries) => {
+                let map: HashMap<String, Value> = entries.into_iter().map(|(k, v)| (k, v.into_value())).collect();
+                Value::Dict(Rc::new(RefCell::new(map)))
+            }
+        }
+    }
+}
+
+type Outcome = Option<std::result::Result<PromiseValue, String>>;
+
+struct PromiseState {
+    outcome: Mutex<Outcome>,
+    cv: Condvar,
+}
+
+/// The settling side of a promise. Dropping it without settling rejects the promise.
+pub struct Resolver {
+    state: Arc<PromiseState>,
+}
+
+impl Resolver {
+    pub fn resolve(self, v: PromiseValue) { self.settle(Ok(v)); }
+    pub fn reject(self, msg: impl Into<String>) { self.settle(Err(msg.into())); }
+    /// Settle from a `Result`, the common shape of background jobs.
+    pub fn complete(self, r: Result<PromiseValue>) {
+        match r { Ok(v) => self.resolve(v), Err(e) => self.reject(e.0) }
+    }
+
+    fn settle(&self, r: std::result::Result<PromiseValue, String>) {
+        let mut st = self.state.outcome.lock().unwrap();
+        if st.is_none() { *st = Some(r); }
+        self.state.cv.notify_all();
+    }
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) { self.settle(Err("promise was abandoned before it settled".into())); }
+}
+
+/// Create a pending PROMISE object and the resolver that settles it.
+pub fn promise() -> (ObjectRef, Resolver) {
+    let state = Arc::new(PromiseState { outcome: Mutex::new(None), cv: Condvar::new() });
+    let obj: ObjectRef = Rc::new(RefCell::new(PromiseObj { state: state.clone(), timed_out: false }));
+    (obj, Resolver { state })
+}
+
+struct PromiseObj {
+    state: Arc<PromiseState>,
+    // whether the last Wait on this handle ran out of time
+    timed_out: bool,
+}
+
+impl PromiseObj {
+    // None = still pending when the timeout elapsed
+    fn wait(&self, timeout: Option<Duration>) -> Option<std::result::Result<PromiseValue, String>> {
+        let deadline = timeout.map(|d| Instant::now() + d);
+        let mut st = self.state.outcome.lock().unwrap();
+        loop {
+            if let Some(r) = st.as_ref() { return Some(r.clone()); }
+            match deadline {
+                None => { st = self.state.cv.wait(st).unwrap(); }
+                Some(dl) => {
+                    let now = Instant::now();
+                    if now >= dl { return None; }
+                    st = self.state.cv.wait_timeout(st, dl - now).unwrap().0;
+                }
+            }
+        }
+    }
+}
+
+pub fn descriptor_static() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "PROMISE".to_string(),
+        version: "1.0".to_string(),
+        summary: "Result of an async object method; AWAIT it to get the value".to_string(),
+        properties: vec![
+            PropDesc { name: "Done".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "Failed".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "Error$".to_string(), type_name: "String".to_string(), readable: true, writable: false },
+            PropDesc { name: "TimedOut".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+        ],
+        methods: vec![
+            MethodDesc { name: "Wait".to_string(), arity: 1, arg_names: vec!["[timeoutMs%]".to_string()], return_type: "Any".to_string() },
+        ],
+        examples: vec![
+            "LET p@ = http@.GetAsync(\"https://example.com\")".to_string(),
+            "PRINT AWAIT p@".to_string(),
+        ],
+    }
+}
+
+impl BasicObject for PromiseObj {
+    fn type_name(&self) -> &str { "PROMISE" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let st = self.state.outcome.lock().unwrap();
+        match name.to_ascii_uppercase().as_str() {
+            "DONE" => Ok(Value::Bool(st.is_some())),
+            "FAILED" => Ok(Value::Bool(matches!(*st, Some(Err(_))))),
+            "ERROR$" => Ok(Value::Str(match &*st { Some(Err(e)) => e.clone(), _ => String::new() })),
+            "TIMEDOUT" => Ok(Value::Bool(self.timed_out)),
+            other => Err(BasilError(format!("Unknown property '{}' on PROMISE", other))),
+        }
+    }
+    fn set_prop(&mut self, name: &str, _v: Value) -> Result<()> {
+        Err(BasilError(format!("Property '{}' on PROMISE is read-only", name)))
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        match method.to_ascii_uppercase().as_str() {
+            "WAIT" => {
+                let timeout = match args.first() {
+                    None => None,
+                    Some(Value::Int(ms)) => Some(Duration::from_millis((*ms).max(0) as u64)),
+                    Some(Value::Num(ms)) => Some(Duration::from_millis(ms.max(0.0) as u64)),
+                    Some(_) => return Err(BasilError("PROMISE.Wait timeout must be a number of milliseconds".into())),
+                };
+                let r = self.wait(timeout);
+                self.timed_out = r.is_none();
+                match r {
+                    Some(Ok(v)) => Ok(v.into_value()),
+                    Some(Err(e)) => Err(BasilError(e)),
+                    None => Ok(Value::Null),
+                }
+            }
+            other => Err(BasilError(format!("Unknown method '{}' on PROMISE", other))),
+        }
+    }
+    fn descriptor(&self) -> ObjectDescriptor { descriptor_static() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_across_threads() {
+        let (p, res) = promise();
+        std::thread::spawn(move || res.resolve(PromiseValue::Str("ok".into())));
+        assert_eq!(p.borrow_mut().call("WAIT", &[]).unwrap(), Value::Str("ok".into()));
+        assert_eq!(p.borrow().get_prop("Done").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn dropped_resolver_rejects() {
+        let (p, res) = promise();
+        drop(res);
+        let e = p.borrow_mut().call("WAIT", &[]).unwrap_err();
+        assert!(e.0.contains("abandoned"));
+        assert_eq!(p.borrow().get_prop("Failed").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn timed_out_wait_is_flagged() {
+        let (p, res) = promise();
+        assert_eq!(p.borrow_mut().call("WAIT", &[Value::Int(5)]).unwrap(), Value::Null);
+        assert_eq!(p.borrow().get_prop("TimedOut").unwrap(), Value::Bool(true));
+        res.resolve(PromiseValue::Null);
+        assert_eq!(p.borrow_mut().call("WAIT", &[Value::Int(5)]).unwrap(), Value::Null);
+        assert_eq!(p.borrow().get_prop("TimedOut").unwrap(), Value::Bool(false));
+    }
+}
@@ -48,4 +48,54 @@ impl std::fmt::Display for BasilError { fn fmt(&self, f:&mut std::fmt::Formatter
 impl std::error::Error for BasilError {}
 
 
-pub type Result<T> = std::result::Result<T, BasilError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, BasilError>;
+
+/// Maps lines of generated Basil source (e.g. a precompiled template) back to the files they came from.
+/// Entry N-1 describes generated line N.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    pub files: Vec<String>,
+    pub lines: Vec<MappedLine>,
+}
+
+/// Origin of one generated line: index into `SourceMap::files`, 1-based line and column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedLine { pub file: u32, pub line: u32, pub col: u32 }
+
+/// A resolved position in an original file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLoc<'a> { pub file: &'a str, pub line: u32, pub col: u32 }
+impl std::fmt::Display for SourceLoc<'_> { fn fmt(&self, f:&mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "{}:{}:{}", self.file, self.line, self.col) } }
+
+impl SourceMap {
+    /// Index of `file` in `files`, adding it if needed.
+    pub fn file_index(&mut self, file: &str) -> u32 {
+        match self.files.iter().position(|f| f == file) {
+            Some(i) => i as u32,
+            None => { self.files.push(file.to_string()); (self.files.len() - 1) as u32 }
+        }
+    }
+
+    /// Where generated line `line` (1-based) came from.
+    pub fn lookup(&self, line: u32) -> Option<SourceLoc<'_>> {
+        let m = self.lines.get((line as usize).checked_sub(1)?)?;
+        Some(SourceLoc { file: self.files.get(m.file as usize)?, line: m.line, col: m.col })
+    }
+
+    /// Rewrite every "line N" in an error message to the original "file:line:col".
+    pub fn rewrite_lines(&self, msg: &str) -> String {
+        let mut out = String::with_capacity(msg.len());
+        let mut rest = msg;
+        while let Some(p) = rest.find("line ") {
+            let after = &rest[p + 5..];
+            let digits = after.bytes().take_while(|b| b.is_ascii_digit()).count();
+            match after[..digits].parse::<u32>().ok().and_then(|n| self.lookup(n)) {
+                Some(loc) => { out.push_str(&rest[..p]); out.push_str(&loc.to_string()); }
+                None => out.push_str(&rest[..p + 5 + digits]),
+            }
+            rest = &after[digits..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
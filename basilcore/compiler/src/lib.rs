@@ -48,6 +48,156 @@ use basil_bytecode::{Chunk, Program as BCProgram, Value, Op, Function};
 
 pub mod service;
 
+/// Builtin functions callable by name, compiled to `Op::Builtin <id>`. Several names may share an id.
+pub static BUILTINS: &[(&str, u8)] = &[
+    ("LEN", 1),
+    ("MID$", 2),
+    ("LEFT$", 3),
+    ("RIGHT$", 4),
+    ("INSTR", 5),
+    ("INPUT$", 6),
+    ("INPUT", 6), // alias for convenience
+    ("INKEY$", 7),
+    ("INKEY%", 8),
+    ("TYPE$", 9),
+    ("HTML$", 10),
+    ("HTML", 10),
+    ("GET$", 11),
+    ("POST$", 12),
+    ("REQUEST$", 13),
+    ("UCASE$", 14),
+    ("LCASE$", 15),
+    ("TRIM$", 16),
+    ("CHR$", 17),
+    ("ASC%", 18),
+    ("INPUTC$", 19),
+    ("ESCAPE$", 20),
+    ("UNESCAPE$", 21),
+    ("URLENCODE$", 22),
+    ("URLDECODE$", 23),
+    ("STRING$", 26),
+    ("SLEEP", 24),
+    ("SSE_SEND", 27),
+    ("TEST_REGISTER", 28),
+    ("ASSERT", 29),
+    ("ASSERT_EQ", 30),
+    ("ASSERT_RAISES", 31),
+    ("SPAWN", 64),
+    ("WAIT", 65),
+    ("AWAIT", 65),
+    ("FOPEN", 40),
+    ("FCLOSE", 41),
+    ("FFLUSH", 42),
+    ("FEOF", 43),
+    ("FTELL&", 44),
+    ("FSEEK", 45),
+    ("FREAD$", 46),
+    ("FREADLINE$", 47),
+    ("FWRITE", 48),
+    ("FWRITELN", 49),
+    ("READFILE$", 50),
+    ("WRITEFILE", 51),
+    ("APPENDFILE", 52),
+    ("COPY", 53),
+    ("MOVE", 54),
+    ("RENAME", 55),
+    ("DELETE", 56),
+    ("DIR$", 57),
+    ("ENV$", 58),
+    ("LOADENV%", 63),
+    ("MKDIRS%", 62),
+    #[cfg(feature = "obj-base64")] ("BASE64_ENCODE$", 90),
+    #[cfg(feature = "obj-base64")] ("BASE64_DECODE$", 91),
+    #[cfg(feature = "obj-zip")] ("ZIP_EXTRACT_ALL", 120),
+    #[cfg(feature = "obj-zip")] ("ZIP_COMPRESS_FILE", 121),
+    #[cfg(feature = "obj-zip")] ("ZIP_COMPRESS_DIR", 122),
+    #[cfg(feature = "obj-zip")] ("ZIP_LIST$", 123),
+    #[cfg(feature = "obj-curl")] ("HTTP_GET$", 124),
+    #[cfg(feature = "obj-curl")] ("HTTP_POST$", 125),
+    #[cfg(feature = "obj-json")] ("JSON_PARSE$", 126),
+    #[cfg(feature = "obj-json")] ("JSON_STRINGIFY$", 127),
+    #[cfg(feature = "obj-csv")] ("CSV_PARSE$", 128),
+    #[cfg(feature = "obj-csv")] ("CSV_WRITE$", 129),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_OPEN%", 130),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_CLOSE", 131),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_EXEC%", 132),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_QUERY2D$", 133),
+    #[cfg(feature = "obj-sqlite")] ("SQLITE_LAST_INSERT_ID%", 134),
+    // --- Terminal builtins ---
+    #[cfg(feature = "obj-term")] ("CLS", 230),
+    #[cfg(feature = "obj-term")] ("CLEAR", 230),
+    #[cfg(feature = "obj-term")] ("HOME", 230),
+    #[cfg(feature = "obj-term")] ("LOCATE", 231),
+    #[cfg(feature = "obj-term")] ("COLOR", 232),
+    #[cfg(feature = "obj-term")] ("COLOR_RESET", 233),
+    #[cfg(feature = "obj-term")] ("ATTR", 234),
+    #[cfg(feature = "obj-term")] ("ATTR_RESET", 235),
+    #[cfg(feature = "obj-term")] ("CURSOR_SAVE", 236),
+    #[cfg(feature = "obj-term")] ("CURSOR_RESTORE", 237),
+    #[cfg(feature = "obj-term")] ("TERM_COLS%", 238),
+    #[cfg(feature = "obj-term")] ("TERM_ROWS%", 239),
+    #[cfg(feature = "obj-term")] ("CURSOR_HIDE", 241),
+    #[cfg(feature = "obj-term")] ("CURSOR_SHOW", 242),
+    #[cfg(feature = "obj-term")] ("TERM_ERR$", 243),
+    // Phase 2 additions
+    #[cfg(feature = "obj-term")] ("TERM.INIT", 244),
+    #[cfg(feature = "obj-term")] ("TERM.END", 245),
+    #[cfg(feature = "obj-term")] ("TERM.RAW", 246),
+    #[cfg(feature = "obj-term")] ("ALTSCREEN_ON", 247),
+    #[cfg(feature = "obj-term")] ("ALTSCREEN_OFF", 248),
+    #[cfg(feature = "obj-term")] ("TERM.FLUSH", 249),
+    #[cfg(feature = "obj-term")] ("TERM.POLLKEY$", 250),
+    // --- Audio/MIDI/DAW builtins ---
+    #[cfg(feature = "obj-daw")] ("DAW_STOP", 180),
+    #[cfg(feature = "obj-daw")] ("DAW_ERR$", 181),
+    #[cfg(feature = "obj-daw")] ("AUDIO_RECORD%", 182),
+    #[cfg(feature = "obj-daw")] ("AUDIO_PLAY%", 183),
+    #[cfg(feature = "obj-daw")] ("AUDIO_MONITOR%", 184),
+    #[cfg(feature = "obj-daw")] ("MIDI_CAPTURE%", 185),
+    #[cfg(feature = "obj-daw")] ("SYNTH_LIVE%", 186),
+    #[cfg(feature = "obj-daw")] ("DAW_RESET", 187),
+    #[cfg(feature = "obj-audio")] ("AUDIO_OUTPUTS$", 190),
+    #[cfg(feature = "obj-audio")] ("AUDIO_INPUTS$", 191),
+    #[cfg(feature = "obj-audio")] ("AUDIO_DEFAULT_RATE%", 192),
+    #[cfg(feature = "obj-audio")] ("AUDIO_DEFAULT_CHANS%", 193),
+    #[cfg(feature = "obj-audio")] ("AUDIO_OPEN_IN@", 194),
+    #[cfg(feature = "obj-audio")] ("AUDIO_OPEN_OUT@", 195),
+    #[cfg(feature = "obj-audio")] ("AUDIO_START%", 196),
+    #[cfg(feature = "obj-audio")] ("AUDIO_STOP%", 197),
+    #[cfg(feature = "obj-audio")] ("AUDIO_CLOSE%", 198),
+    #[cfg(feature = "obj-audio")] ("AUDIO_RING_CREATE@", 199),
+    #[cfg(feature = "obj-audio")] ("AUDIO_RING_PUSH%", 200),
+    #[cfg(feature = "obj-audio")] ("AUDIO_RING_POP%", 201),
+    #[cfg(feature = "obj-audio")] ("WAV_WRITER_OPEN@", 202),
+    #[cfg(feature = "obj-audio")] ("WAV_WRITER_WRITE%", 203),
+    #[cfg(feature = "obj-audio")] ("WAV_WRITER_CLOSE%", 204),
+    #[cfg(feature = "obj-audio")] ("WAV_READ_ALL![]", 205),
+    #[cfg(feature = "obj-audio")] ("AUDIO_CONNECT_IN_TO_RING%", 206),
+    #[cfg(feature = "obj-audio")] ("AUDIO_CONNECT_RING_TO_OUT%", 207),
+    #[cfg(feature = "obj-audio")] ("SYNTH_NEW@", 220),
+    #[cfg(feature = "obj-audio")] ("SYNTH_NOTE_ON%", 221),
+    #[cfg(feature = "obj-audio")] ("SYNTH_NOTE_OFF%", 222),
+    #[cfg(feature = "obj-audio")] ("SYNTH_RENDER%", 223),
+    #[cfg(feature = "obj-audio")] ("SYNTH_DELETE%", 224),
+    #[cfg(feature = "obj-midi")] ("MIDI_PORTS$", 210),
+    #[cfg(feature = "obj-midi")] ("MIDI_OPEN_IN@", 211),
+    #[cfg(feature = "obj-midi")] ("MIDI_POLL%", 212),
+    #[cfg(feature = "obj-midi")] ("MIDI_GET_EVENT$[]", 213),
+    #[cfg(feature = "obj-midi")] ("MIDI_CLOSE%", 214),
+    ("ARRAY_ROWS%", 139),
+    ("ARRAY_COLS%", 140),
+];
+
+/// Id of the builtin called `uname` (upper-case), if any.
+pub fn builtin_id(uname: &str) -> Option<u8> {
+    BUILTINS.iter().find(|(n, _)| *n == uname).map(|(_, id)| *id)
+}
+
+/// First name registered for a builtin id, for reports and diagnostics.
+pub fn builtin_name(id: u8) -> Option<&'static str> {
+    BUILTINS.iter().find(|(_, i)| *i == id).map(|(n, _)| *n)
+}
+
 pub fn compile(ast: &Program) -> Result<BCProgram> {
     let mut c = C::new();
     // Pre-scan to collect all routine names (FUNC/SUB) with arity and kind so calls can be resolved before definitions
@@ -55,7 +205,7 @@ pub fn compile(ast: &Program) -> Result<BCProgram> {
         if let Stmt::Func { kind, name, params, .. } = s {
             let uname = name.to_ascii_uppercase();
             c.fn_names.insert(uname.clone());
-            c.routines.insert(uname, RoutineInfo { arity: params.len(), is_sub: matches!(kind, basil_ast::FuncKind::Sub) });
+            c.routines.insert(uname, RoutineInfo { arity: params.len(), is_sub: matches!(kind, basil_ast::FuncKind::Sub), is_async: matches!(kind, basil_ast::FuncKind::AsyncFunc) });
         }
     }
     for s in ast {
@@ -98,7 +248,7 @@ pub fn compile(ast: &Program) -> Result<BCProgram> {
     Ok(BCProgram { chunk: c.chunk, globals: c.globals })
 }
 
-struct RoutineInfo { arity: usize, is_sub: bool }
+struct RoutineInfo { arity: usize, is_sub: bool, is_async: bool }
 
 fn expr_contains_sub_call(routines: &HashMap<String, RoutineInfo>, e: &Expr) -> bool {
     match e {
@@ -724,6 +874,13 @@ impl C {
             Stmt::Break => { return Err(BasilError("BREAK used outside of loop".into())); }
             Stmt::Continue => { return Err(BasilError("CONTINUE used outside of loop".into())); }
 
+            // PARALLEL FOR EACH at toplevel
+            Stmt::ParallelForEach { var, enumerable, body } => {
+                let mut chunk = std::mem::take(&mut self.chunk);
+                self.emit_parallel_foreach(&mut chunk, var, enumerable, body, None)?;
+                self.chunk = chunk;
+            }
+
             // FOR EACH at toplevel
             Stmt::ForEach { var, enumerable, body } => {
                 let mut chunk = std::mem::take(&mut self.chunk);
@@ -1374,6 +1531,9 @@ impl C {
                 for s2 in stmts { self.emit_stmt_func(chunk, s2, env)?; }
             }
             Stmt::Func { .. } => { /* no nested funcs in MVP */ }
+            Stmt::ParallelForEach { var, enumerable, body } => {
+                self.emit_parallel_foreach(chunk, var, enumerable, body, Some(env))?;
+            }
             Stmt::ForEach { var, enumerable, body } => {
                 // Evaluate enumerable and create enumerator
                 self.emit_expr_in(chunk, enumerable, Some(env))?;
@@ -1762,142 +1922,41 @@ impl C {
                         }
                         // Fallback: emit normal LEN builtin
                     }
-                    let bid = match &*uname {
-                        "LEN" => Some(1u8),
-                        "MID$" => Some(2u8),
-                        "LEFT$" => Some(3u8),
-                        "RIGHT$" => Some(4u8),
-                        "INSTR" => Some(5u8),
-                        "INPUT$" => Some(6u8),
-                        "INPUT" => Some(6u8), // alias for convenience
-                        "INKEY$" => Some(7u8),
-                        "INKEY%" => Some(8u8),
-                        "TYPE$" => Some(9u8),
-                        "HTML$" => Some(10u8),
-                        "HTML" => Some(10u8),
-                        "GET$" => Some(11u8),
-                        "POST$" => Some(12u8),
-                        "REQUEST$" => Some(13u8),
-                        "UCASE$" => Some(14u8),
-                        "LCASE$" => Some(15u8),
-                        "TRIM$"  => Some(16u8),
-                        "CHR$"   => Some(17u8),
-                        "ASC%"   => Some(18u8),
-                        "INPUTC$"=> Some(19u8),
-                        "ESCAPE$" => Some(20u8),
-                        "UNESCAPE$" => Some(21u8),
-                        "URLENCODE$" => Some(22u8),
-                        "URLDECODE$" => Some(23u8),
-                        "STRING$" => Some(26u8),
-                        "SLEEP" => Some(24u8),
-                        "FOPEN" => Some(40u8),
-                        "FCLOSE" => Some(41u8),
-                        "FFLUSH" => Some(42u8),
-                        "FEOF" => Some(43u8),
-                        "FTELL&" => Some(44u8),
-                        "FSEEK" => Some(45u8),
-                        "FREAD$" => Some(46u8),
-                        "FREADLINE$" => Some(47u8),
-                        "FWRITE" => Some(48u8),
-                        "FWRITELN" => Some(49u8),
-                        "READFILE$" => Some(50u8),
-                        "WRITEFILE" => Some(51u8),
-                        "APPENDFILE" => Some(52u8),
-                        "COPY" => Some(53u8),
-                        "MOVE" => Some(54u8),
-                        "RENAME" => Some(55u8),
-                        "DELETE" => Some(56u8),
-                        "DIR$" => Some(57u8),
-                        "ENV$" => Some(58u8),
-                        "LOADENV%" => Some(63u8),
-                        "MKDIRS%" => Some(62u8),
-                        #[cfg(feature = "obj-base64")] "BASE64_ENCODE$" => Some(90u8),
-                        #[cfg(feature = "obj-base64")] "BASE64_DECODE$" => Some(91u8),
-                        #[cfg(feature = "obj-zip")] "ZIP_EXTRACT_ALL" => Some(120u8),
-                        #[cfg(feature = "obj-zip")] "ZIP_COMPRESS_FILE" => Some(121u8),
-                        #[cfg(feature = "obj-zip")] "ZIP_COMPRESS_DIR" => Some(122u8),
-                        #[cfg(feature = "obj-zip")] "ZIP_LIST$" => Some(123u8),
-                        #[cfg(feature = "obj-curl")] "HTTP_GET$" => Some(124u8),
-                        #[cfg(feature = "obj-curl")] "HTTP_POST$" => Some(125u8),
-                        #[cfg(feature = "obj-json")] "JSON_PARSE$" => Some(126u8),
-                        #[cfg(feature = "obj-json")] "JSON_STRINGIFY$" => Some(127u8),
-                        #[cfg(feature = "obj-csv")] "CSV_PARSE$" => Some(128u8),
-                        #[cfg(feature = "obj-csv")] "CSV_WRITE$" => Some(129u8),
-                        #[cfg(feature = "obj-sqlite")] "SQLITE_OPEN%" => Some(130u8),
-                        #[cfg(feature = "obj-sqlite")] "SQLITE_CLOSE" => Some(131u8),
-                        #[cfg(feature = "obj-sqlite")] "SQLITE_EXEC%" => Some(132u8),
-                        #[cfg(feature = "obj-sqlite")] "SQLITE_QUERY2D$" => Some(133u8),
-                        #[cfg(feature = "obj-sqlite")] "SQLITE_LAST_INSERT_ID%" => Some(134u8),
-                        // --- Terminal builtins ---
-                        #[cfg(feature = "obj-term")] "CLS" => Some(230u8),
-                        #[cfg(feature = "obj-term")] "CLEAR" => Some(230u8),
-                        #[cfg(feature = "obj-term")] "HOME" => Some(230u8),
-                        #[cfg(feature = "obj-term")] "LOCATE" => Some(231u8),
-                        #[cfg(feature = "obj-term")] "COLOR" => Some(232u8),
-                        #[cfg(feature = "obj-term")] "COLOR_RESET" => Some(233u8),
-                        #[cfg(feature = "obj-term")] "ATTR" => Some(234u8),
-                        #[cfg(feature = "obj-term")] "ATTR_RESET" => Some(235u8),
-                        #[cfg(feature = "obj-term")] "CURSOR_SAVE" => Some(236u8),
-                        #[cfg(feature = "obj-term")] "CURSOR_RESTORE" => Some(237u8),
-                        #[cfg(feature = "obj-term")] "TERM_COLS%" => Some(238u8),
-                        #[cfg(feature = "obj-term")] "TERM_ROWS%" => Some(239u8),
-                        #[cfg(feature = "obj-term")] "CURSOR_HIDE" => Some(241u8),
-                        #[cfg(feature = "obj-term")] "CURSOR_SHOW" => Some(242u8),
-                        #[cfg(feature = "obj-term")] "TERM_ERR$" => Some(243u8),
-                        // Phase 2 additions
-                        #[cfg(feature = "obj-term")] "TERM.INIT" => Some(244u8),
-                        #[cfg(feature = "obj-term")] "TERM.END" => Some(245u8),
-                        #[cfg(feature = "obj-term")] "TERM.RAW" => Some(246u8),
-                        #[cfg(feature = "obj-term")] "ALTSCREEN_ON" => Some(247u8),
-                        #[cfg(feature = "obj-term")] "ALTSCREEN_OFF" => Some(248u8),
-                        #[cfg(feature = "obj-term")] "TERM.FLUSH" => Some(249u8),
-                        #[cfg(feature = "obj-term")] "TERM.POLLKEY$" => Some(250u8),
-                        // --- Audio/MIDI/DAW builtins ---
-                        #[cfg(feature = "obj-daw")] "DAW_STOP" => Some(180u8),
-                        #[cfg(feature = "obj-daw")] "DAW_ERR$" => Some(181u8),
-                        #[cfg(feature = "obj-daw")] "AUDIO_RECORD%" => Some(182u8),
-                        #[cfg(feature = "obj-daw")] "AUDIO_PLAY%" => Some(183u8),
-                        #[cfg(feature = "obj-daw")] "AUDIO_MONITOR%" => Some(184u8),
-                        #[cfg(feature = "obj-daw")] "MIDI_CAPTURE%" => Some(185u8),
-                        #[cfg(feature = "obj-daw")] "SYNTH_LIVE%" => Some(186u8),
-                        #[cfg(feature = "obj-daw")] "DAW_RESET" => Some(187u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_OUTPUTS$" => Some(190u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_INPUTS$" => Some(191u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_DEFAULT_RATE%" => Some(192u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_DEFAULT_CHANS%" => Some(193u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_OPEN_IN@" => Some(194u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_OPEN_OUT@" => Some(195u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_START%" => Some(196u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_STOP%" => Some(197u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_CLOSE%" => Some(198u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_RING_CREATE@" => Some(199u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_RING_PUSH%" => Some(200u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_RING_POP%" => Some(201u8),
-                        #[cfg(feature = "obj-audio")] "WAV_WRITER_OPEN@" => Some(202u8),
-                        #[cfg(feature = "obj-audio")] "WAV_WRITER_WRITE%" => Some(203u8),
-                        #[cfg(feature = "obj-audio")] "WAV_WRITER_CLOSE%" => Some(204u8),
-                        #[cfg(feature = "obj-audio")] "WAV_READ_ALL![]" => Some(205u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_CONNECT_IN_TO_RING%" => Some(206u8),
-                        #[cfg(feature = "obj-audio")] "AUDIO_CONNECT_RING_TO_OUT%" => Some(207u8),
-                        #[cfg(feature = "obj-audio")] "SYNTH_NEW@" => Some(220u8),
-                        #[cfg(feature = "obj-audio")] "SYNTH_NOTE_ON%" => Some(221u8),
-                        #[cfg(feature = "obj-audio")] "SYNTH_NOTE_OFF%" => Some(222u8),
-                        #[cfg(feature = "obj-audio")] "SYNTH_RENDER%" => Some(223u8),
-                        #[cfg(feature = "obj-audio")] "SYNTH_DELETE%" => Some(224u8),
-                        #[cfg(feature = "obj-midi")]  "MIDI_PORTS$" => Some(210u8),
-                        #[cfg(feature = "obj-midi")]  "MIDI_OPEN_IN@" => Some(211u8),
-                        #[cfg(feature = "obj-midi")]  "MIDI_POLL%" => Some(212u8),
-                        #[cfg(feature = "obj-midi")]  "MIDI_GET_EVENT$[]" => Some(213u8),
-                        #[cfg(feature = "obj-midi")]  "MIDI_CLOSE%" => Some(214u8),
-                        "ARRAY_ROWS%" => Some(139u8),
-                        "ARRAY_COLS%" => Some(140u8),
-                        _ => None,
-                    };
+                    // ASSERT_RAISES(expr [, expected$]): the expression is compiled into its own chunk,
+                    // which the VM runs on the caller's frame so it still sees the locals
+                    if uname == "ASSERT_RAISES" && !args.is_empty() && args.len() <= 2 {
+                        let mut thunk = Chunk::default();
+                        self.emit_expr_in(&mut thunk, &args[0], env)?;
+                        thunk.push_op(Op::Pop);
+                        thunk.push_op(Op::Halt);
+                        let f = Value::Func(Rc::new(Function { arity: 0, name: Some("ASSERT_RAISES".to_string()), chunk: Rc::new(thunk) }));
+                        let ci = chunk.add_const(f);
+                        chunk.push_op(Op::Const); chunk.push_u16(ci);
+                        for a in &args[1..] { self.emit_expr_in(chunk, a, env)?; }
+                        chunk.push_op(Op::Builtin); chunk.push_u8(31u8); chunk.push_u8(args.len() as u8);
+                        return Ok(());
+                    }
+                    // The task builtins came later than programs with their own WAIT/SPAWN routines; those win
+                    let own_routine = matches!(uname.as_str(), "SPAWN" | "WAIT" | "AWAIT") && self.routines.contains_key(&uname);
+                    let bid = builtin_id(&uname).filter(|_| !own_routine);
                     if let Some(id) = bid {
                         for a in args { self.emit_expr_in(chunk, a, env)?; }
                         chunk.push_op(Op::Builtin); chunk.push_u8(id); chunk.push_u8(args.len() as u8);
                         return Ok(());
                     }
+                    // Calling an ASYNC FUNC starts it as a task and yields a TASK handle (ASYNC_CALL builtin 67)
+                    if let Some(info) = self.routines.get(&uname) {
+                        if info.is_async {
+                            if info.arity != args.len() {
+                                return Err(BasilError(format!("ASYNC FUNC '{}' expects {} arguments but {} given", name, info.arity, args.len())));
+                            }
+                            let g = self.gslot(name);
+                            chunk.push_op(Op::LoadGlobal); chunk.push_u8(g);
+                            for a in args { self.emit_expr_in(chunk, a, env)?; }
+                            chunk.push_op(Op::Builtin); chunk.push_u8(67u8); chunk.push_u8((args.len() + 1) as u8);
+                            return Ok(());
+                        }
+                    }
                     // If not builtin, treat as array access when not a known function
                     if args.len() >= 1 && args.len() <= 4 {
                         let is_func = self.routines.contains_key(&uname);
@@ -2077,6 +2136,21 @@ impl LocalEnv {
 
 
 impl C {
+    // PARALLEL FOR EACH: the body becomes an anonymous one-parameter function that the VM
+    // runs once per element, each call in its own task VM (see PARALLEL_EACH builtin 66).
+    fn emit_parallel_foreach(&mut self, chunk: &mut Chunk, var: &str, enumerable: &Expr, body: &Stmt, env: Option<&LocalEnv>) -> Result<()> {
+        // compile_function resets function-scope labels; keep the enclosing function's intact
+        let saved = (std::mem::take(&mut self.fn_labels), std::mem::take(&mut self.fn_goto_fixups), std::mem::take(&mut self.fn_gosub_fixups));
+        let f = self.compile_function("PARALLEL FOR EACH".to_string(), vec![var.to_string()], &vec![body.clone()]);
+        (self.fn_labels, self.fn_goto_fixups, self.fn_gosub_fixups) = saved;
+        let fi = chunk.add_const(f);
+        chunk.push_op(Op::Const); chunk.push_u16(fi);
+        self.emit_expr_in(chunk, enumerable, env)?;
+        chunk.push_op(Op::Builtin); chunk.push_u8(66u8); chunk.push_u8(2u8);
+        chunk.push_op(Op::Pop);
+        Ok(())
+    }
+
     fn emit_if_tl_into(&mut self, chunk: &mut Chunk, cond: &Expr, then_s: &Stmt, else_s: &Option<Box<Stmt>>) -> Result<()> {
         self.emit_expr_in(chunk, cond, None)?;
         chunk.push_op(Op::JumpIfFalse);
@@ -2207,6 +2281,7 @@ impl C {
                 match indices {
                     None => {
                         self.emit_expr_in(chunk, init, None)?;
+                        if name.ends_with('%') { chunk.push_op(Op::ToInt); }
                         let g = self.gslot(name);
                         chunk.push_op(Op::StoreGlobal); chunk.push_u8(g);
                     }
@@ -2511,6 +2586,9 @@ impl C {
                 let g = self.gslot(name);
                 chunk.push_op(Op::StoreGlobal); chunk.push_u8(g);
             }
+            Stmt::ParallelForEach { var, enumerable, body } => {
+                self.emit_parallel_foreach(chunk, var, enumerable, body, None)?;
+            }
             Stmt::ForEach { var, enumerable, body } => {
                 // Evaluate enumerable and create enumerator
                 self.emit_expr_in(chunk, enumerable, None)?;
@@ -80,6 +80,87 @@ pub enum TokenKind {
     Eof,
 }
 
+// One table drives both the lexer's keyword match and the public word list
+macro_rules! keywords {
+    ($($word:literal => $kind:ident,)*) => {
+        /// Reserved words, upper case. Several spellings may share a token.
+        pub static KEYWORDS: &[&str] = &[$($word),*];
+
+        /// The token an upper-cased word lexes to, if it is reserved.
+        pub fn keyword_kind(upper: &str) -> Option<TokenKind> {
+            match upper {
+                $($word => Some(TokenKind::$kind),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+keywords! {
+    "FUNC"        => Func,
+    "FUNCTION"    => Func,
+    "SUB"         => Func,
+    "RETURN"      => Return,
+    "IF"          => If,
+    "THEN"        => Then,
+    "ELSE"        => Else,
+    "WHILE"       => While,
+    "DO"          => Do,
+    "BEGIN"       => Begin,
+    "END"         => End,
+    "ENDIF"       => End,
+    "ENDFUNC"     => End,
+    "ENDFUNCTION" => End,
+    "ENDSUB"      => End,
+    "ENDWHILE"    => End,
+    "ENDBLOCK"    => End,
+    "SELECT"      => Select,
+    "CASE"        => Case,
+    "IS"          => Is,
+    "BREAK"       => Break,
+    "CONTINUE"    => Continue,
+    "LET"         => Let,
+    "PRINT"       => Print,
+    "PRINTLN"     => Println,
+    "TRUE"        => True,
+    "FALSE"       => False,
+    "NULL"        => Null,
+    "AND"         => And,
+    "OR"          => Or,
+    "NOT"         => Not,
+    "AUTHOR"      => Author,
+    "FOR"         => For,
+    "TO"          => To,
+    "STEP"        => Step,
+    "NEXT"        => Next,
+    "EACH"        => Each,
+    "IN"          => In,
+    "FOREACH"     => Foreach,
+    "ENDFOR"      => Endfor,
+    "DIM"         => Dim,
+    "AS"          => As,
+    "DESCRIBE"    => Describe,
+    "NEW"         => New,
+    "CLASS"       => Class,
+    "WITH"        => With,
+    "TRY"         => Try,
+    "CATCH"       => Catch,
+    "FINALLY"     => Finally,
+    "RAISE"       => Raise,
+    "SETENV"      => Setenv,
+    "EXPORTENV"   => Exportenv,
+    "SHELL"       => Shell,
+    "EXIT"        => Exit,
+    "STOP"        => Stop,
+    "LABEL"       => Label,
+    "GOTO"        => Goto,
+    "GOSUB"       => Gosub,
+    "MOD"         => Mod,
+    "EXEC"        => Exec,
+    "EVAL"        => Eval,
+    "TYPE"        => Type,
+}
+
 #[derive(Debug, Clone)]
 pub enum Literal { Num(f64), Str(String) }
 
@@ -537,71 +618,8 @@ impl<'a> Lexer<'a> {
             }
         }
         let lex = &self.src[start..end];
-        let kind = match &*lex.to_ascii_uppercase() {
-            "FUNC"   => TokenKind::Func,
-            "FUNCTION" => TokenKind::Func,
-            "SUB"    => TokenKind::Func,
-            "RETURN" => TokenKind::Return,
-            "IF"     => TokenKind::If,
-            "THEN"   => TokenKind::Then,
-            "ELSE"   => TokenKind::Else,
-            "WHILE"  => TokenKind::While,
-            "DO"     => TokenKind::Do,
-            "BEGIN"  => TokenKind::Begin,
-            "END"    => TokenKind::End,
-            "ENDIF"  => TokenKind::End,
-            "ENDFUNC" => TokenKind::End,
-            "ENDFUNCTION" => TokenKind::End,
-            "ENDSUB" => TokenKind::End,
-            "ENDWHILE" => TokenKind::End,
-            "ENDBLOCK" => TokenKind::End,
-            "SELECT" => TokenKind::Select,
-            "CASE"   => TokenKind::Case,
-            "IS"     => TokenKind::Is,
-            "BREAK"  => TokenKind::Break,
-            "CONTINUE" => TokenKind::Continue,
-            "LET"    => TokenKind::Let,
-            "PRINT"  => TokenKind::Print,
-            "PRINTLN"=> TokenKind::Println,
-            "TRUE"   => TokenKind::True,
-            "FALSE"  => TokenKind::False,
-            "NULL"   => TokenKind::Null,
-            "AND"    => TokenKind::And,
-            "OR"     => TokenKind::Or,
-            "NOT"    => TokenKind::Not,
-            "AUTHOR" => TokenKind::Author,
-            "FOR"    => TokenKind::For,
-            "TO"     => TokenKind::To,
-            "STEP"   => TokenKind::Step,
-            "NEXT"   => TokenKind::Next,
-            "EACH"   => TokenKind::Each,
-            "IN"     => TokenKind::In,
-            "FOREACH"=> TokenKind::Foreach,
-            "ENDFOR" => TokenKind::Endfor,
-            "DIM"    => TokenKind::Dim,
-            "AS"     => TokenKind::As,
-            "DESCRIBE" => TokenKind::Describe,
-            "NEW"    => TokenKind::New,
-            "CLASS"  => TokenKind::Class,
-            "WITH"   => TokenKind::With,
-            "TRY"    => TokenKind::Try,
-            "CATCH"  => TokenKind::Catch,
-            "FINALLY"=> TokenKind::Finally,
-            "RAISE"  => TokenKind::Raise,
-            "SETENV" => TokenKind::Setenv,
-            "EXPORTENV" => TokenKind::Exportenv,
-            "SHELL"  => TokenKind::Shell,
-            "EXIT"   => TokenKind::Exit,
-            "STOP"   => TokenKind::Stop,
-            "LABEL"  => TokenKind::Label,
-            "GOTO"   => TokenKind::Goto,
-            "GOSUB"  => TokenKind::Gosub,
-            "MOD"    => TokenKind::Mod,
-            "EXEC"   => TokenKind::Exec,
-            "EVAL"   => TokenKind::Eval,
-            "TYPE"   => TokenKind::Type,
-            _        => TokenKind::Ident,
-        };
+        let upper = lex.to_ascii_uppercase();
+        let kind = keyword_kind(&upper).unwrap_or(TokenKind::Ident);
 
         // Explicit line continuation: a single '_' followed by optional spaces/comments to end-of-line
         if matches!(kind, TokenKind::Ident) && lex == "_" {
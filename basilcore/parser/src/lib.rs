@@ -46,13 +46,61 @@ use basil_ast::{Expr, Stmt, BinOp, Program};
 pub fn parse(src: &str) -> Result<Program> {
     let mut lx = Lexer::new(src);
     let tokens = lx.tokenize()?;
-    Parser::new(tokens).parse_program()
+    Parser::new(line_numbers_to_labels(tokens)).parse_program()
 }
 
-struct Parser { tokens: Vec<Token>, i: usize, with_depth: usize, catch_depth: usize }
+fn is_line_number(text: &str) -> bool { !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) }
+
+// Keywords a statement can begin with, as parse_stmt dispatches on them
+fn starts_stmt(kind: &TokenKind) -> bool {
+    matches!(kind, TokenKind::Ident | TokenKind::Label | TokenKind::Let | TokenKind::Print | TokenKind::Println
+        | TokenKind::If | TokenKind::While | TokenKind::For | TokenKind::Foreach | TokenKind::Func | TokenKind::Return
+        | TokenKind::Break | TokenKind::Continue | TokenKind::Dim | TokenKind::Describe | TokenKind::Select
+        | TokenKind::Try | TokenKind::Raise | TokenKind::Setenv | TokenKind::Exportenv | TokenKind::Shell
+        | TokenKind::Exit | TokenKind::Stop | TokenKind::Goto | TokenKind::Gosub | TokenKind::Exec
+        | TokenKind::Begin | TokenKind::Type | TokenKind::With)
+}
+
+// Keywords that only close or continue an enclosing block
+fn closes_block(kind: &TokenKind) -> bool {
+    matches!(kind, TokenKind::End | TokenKind::Next | TokenKind::Endfor | TokenKind::Else | TokenKind::Catch
+        | TokenKind::Finally | TokenKind::Case | TokenKind::RBrace)
+}
+
+/// Classic line numbers (`10 PRINT "HI"`) become labels named by the number, so `GOTO 10` finds
+/// them. A number starts a line when a statement follows it on the same line; once a program has
+/// one, a number alone on its line (`10 REM ...`) is a line number too, while one before a keyword
+/// that only closes a block (END, NEXT, ELSE...) is dropped. Programs without line numbers are
+/// left alone, so `5 + 3` is still an expression.
+fn line_numbers_to_labels(tokens: Vec<Token>) -> Vec<Token> {
+    let at_line_start = |i: usize| i == 0 || tokens[i - 1].kind == TokenKind::Semicolon;
+    let is_number = |t: &Token| t.kind == TokenKind::Number && is_line_number(&t.lexeme);
+    let follower = |i: usize| tokens.get(i + 1).filter(|n| n.line == tokens[i].line && n.kind != TokenKind::Semicolon).map(|n| &n.kind);
+    let numbered = (0..tokens.len()).any(|i| at_line_start(i) && is_number(&tokens[i]) && follower(i).is_some_and(starts_stmt));
+    if !numbered { return tokens; }
+
+    let mut out = Vec::with_capacity(tokens.len() * 2);
+    for (i, tok) in tokens.iter().enumerate() {
+        if at_line_start(i) && is_number(tok) {
+            match follower(i) {
+                Some(k) if closes_block(k) => continue,
+                Some(k) if !starts_stmt(k) => {}
+                _ => {
+                    out.push(Token { kind: TokenKind::Label, lexeme: tok.lexeme.clone(), literal: None, span: tok.span, line: tok.line });
+                    out.push(Token { kind: TokenKind::Semicolon, lexeme: String::new(), literal: None, span: tok.span, line: tok.line });
+                    continue;
+                }
+            }
+        }
+        out.push(tok.clone());
+    }
+    out
+}
+
+struct Parser { tokens: Vec<Token>, i: usize, with_depth: usize, catch_depth: usize, test_blocks: usize }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self { Self { tokens, i: 0, with_depth: 0, catch_depth: 0 } }
+    fn new(tokens: Vec<Token>) -> Self { Self { tokens, i: 0, with_depth: 0, catch_depth: 0, test_blocks: 0 } }
 
     fn parse_program(&mut self) -> Result<Program> {
         let mut stmts = Vec::new();
@@ -60,6 +108,7 @@ impl Parser {
             // Skip any stray semicolons (e.g., from newline insertion)
             while self.match_k(TokenKind::Semicolon) {}
             if self.check(TokenKind::Eof) { break; }
+            if let Some(block) = self.parse_test_block()? { stmts.extend(block); continue; }
             let line = self.peek_line();
             let s = self.parse_stmt()?;
             stmts.push(Stmt::Line(line));
@@ -68,6 +117,47 @@ impl Parser {
         Ok(stmts)
     }
 
+    // TEST "name" ... END TEST, SETUP ... END SETUP and TEARDOWN ... END TEARDOWN (top level only).
+    // Each becomes a SUB with a generated name plus TEST_REGISTER(kind$, name$, sub$), so a normal
+    // run skips the body and `basilc test` can call it later.
+    fn parse_test_block(&mut self) -> Result<Option<Vec<Stmt>>> {
+        let Some(t) = self.tokens.get(self.i).filter(|t| t.kind == TokenKind::Ident) else { return Ok(None) };
+        let kind = t.lexeme.to_ascii_uppercase();
+        let line = t.line;
+        let next = self.tokens.get(self.i + 1);
+        let name = match (kind.as_str(), next) {
+            ("TEST", Some(Token { kind: TokenKind::String, literal: Some(Literal::Str(s)), .. })) => s.clone(),
+            ("SETUP" | "TEARDOWN", Some(Token { kind: TokenKind::Semicolon | TokenKind::Eof, .. })) => kind.clone(),
+            _ => return Ok(None),
+        };
+        self.i += if kind == "TEST" { 2 } else { 1 };
+        let mut body = Vec::new();
+        loop {
+            while self.match_k(TokenKind::Semicolon) {}
+            if self.check(TokenKind::Eof) {
+                return Err(BasilError(format!("parse error at line {}: expected 'END {}' to close the {} block", line, kind, kind)));
+            }
+            let closes = self.check(TokenKind::End)
+                && self.tokens.get(self.i + 1).is_some_and(|t| t.kind == TokenKind::Ident && t.lexeme.eq_ignore_ascii_case(&kind));
+            if closes { self.i += 2; break; }
+            let l = self.peek_line();
+            let s = self.parse_stmt()?;
+            body.push(Stmt::Line(l));
+            body.push(s);
+        }
+        self.test_blocks += 1;
+        let sub = format!("__TEST_{}", self.test_blocks);
+        let register = Expr::Call {
+            callee: Box::new(Expr::Var("TEST_REGISTER".to_string())),
+            args: vec![Expr::Str(kind), Expr::Str(name), Expr::Str(sub.clone())],
+        };
+        Ok(Some(vec![
+            Stmt::Line(line),
+            Stmt::Func { kind: basil_ast::FuncKind::Sub, name: sub, params: Vec::new(), body },
+            Stmt::ExprStmt(register),
+        ]))
+    }
+
     fn parse_stmt(&mut self) -> Result<Stmt> {
         // Skip any leading semicolons (useful with newline-as-semicolon)
         while self.match_k(TokenKind::Semicolon) {}
@@ -374,15 +464,15 @@ impl Parser {
             self.terminate_stmt()?;
             return Ok(Stmt::Label(name));
         }
-        // GOTO name
+        // GOTO name  or  GOTO 100
         if self.match_k(TokenKind::Goto) {
-            let name = self.expect_ident()?;
+            let name = self.expect_label()?;
             self.terminate_stmt()?;
             return Ok(Stmt::Goto(name));
         }
-        // GOSUB name
+        // GOSUB name  or  GOSUB 100
         if self.match_k(TokenKind::Gosub) {
-            let name = self.expect_ident()?;
+            let name = self.expect_label()?;
             self.terminate_stmt()?;
             return Ok(Stmt::Gosub(name));
         }
@@ -534,7 +624,7 @@ impl Parser {
             // Distinguish GOSUB-return forms and function-return
             // RETURN TO <label> ;
             if self.match_k(TokenKind::To) {
-                let label = self.expect_ident()?;
+                let label = self.expect_label()?;
                 self.terminate_stmt()?;
                 return Ok(Stmt::ReturnFromGosub(Some(label)));
             }
@@ -637,7 +727,7 @@ impl Parser {
                         }
                         Some(Box::new(Stmt::Block(else_body)))
                     } else {
-                        let s = self.parse_stmt()?;
+                        let s = self.parse_single_stmt()?;
                         // After a single-statement ELSE, require END to close the IF
                         while self.match_k(TokenKind::Semicolon) {}
                         self.expect_end_any()?;
@@ -653,11 +743,11 @@ impl Parser {
             } else {
                 // Simple form: single statements for THEN and optional ELSE
                 let then_line = self.peek_line();
-                let then_stmt = self.parse_stmt()?;
+                let then_stmt = self.parse_single_stmt()?;
                 let then_s = Box::new(Stmt::Block(vec![Stmt::Line(then_line), then_stmt]));
                 let else_s = if self.match_k(TokenKind::Else) {
                     let else_line = self.peek_line();
-                    let es = self.parse_stmt()?;
+                    let es = self.parse_single_stmt()?;
                     Some(Box::new(Stmt::Block(vec![Stmt::Line(else_line), es])))
                 } else { None };
                 return Ok(Stmt::If { cond, then_branch: then_s, else_branch: else_s });
@@ -762,45 +852,7 @@ impl Parser {
         if self.match_k(TokenKind::For) {
             // Check FOR EACH form first
             if self.match_k(TokenKind::Each) {
-                // FOR EACH ident IN expr <body> NEXT [ident]
-                let var = self.expect_ident()?;
-                self.expect(TokenKind::In)?;
-                let enumerable = self.parse_expr_bp(0)?;
-                // Body: BEGIN..END, {..}, or single statement
-                let body: Stmt = if self.match_k(TokenKind::Begin) {
-                    let mut inner = Vec::new();
-                    loop {
-                        while self.match_k(TokenKind::Semicolon) {}
-                        if self.match_k(TokenKind::End) { break; }
-                        if self.check(TokenKind::Eof) { return Err(BasilError(format!("parse error at line {}: unterminated FOR EACH BEGIN/END", self.peek_line()))); }
-                        let line = self.peek_line();
-                        let s = self.parse_stmt()?;
-                        inner.push(Stmt::Line(line));
-                        inner.push(s);
-                    }
-                    Stmt::Block(inner)
-                } else if self.match_k(TokenKind::LBrace) {
-                    let mut inner = Vec::new();
-                    loop {
-                        while self.match_k(TokenKind::Semicolon) {}
-                        if self.check(TokenKind::RBrace) { let _ = self.next(); break; }
-                        if self.check(TokenKind::Eof) { return Err(BasilError(format!("parse error at line {}: unterminated FOR EACH {{ ... }}", self.peek_line()))); }
-                        let line = self.peek_line();
-                        let s = self.parse_stmt()?;
-                        inner.push(Stmt::Line(line));
-                        inner.push(s);
-                    }
-                    Stmt::Block(inner)
-                } else {
-                    let line = self.peek_line();
-                    let s = self.parse_stmt()?;
-                    Stmt::Block(vec![Stmt::Line(line), s])
-                };
-                // Expect NEXT [ident]
-                while self.match_k(TokenKind::Semicolon) {}
-                self.expect(TokenKind::Next)?;
-                if self.check(TokenKind::Ident) { let _ = self.next(); }
-                let _ = self.terminate_stmt();
+                let (var, enumerable, body) = self.parse_foreach_rest()?;
                 return Ok(Stmt::ForEach { var, enumerable, body: Box::new(body) });
             }
 
@@ -840,7 +892,7 @@ impl Parser {
             } else {
                 // Single statement body
                 let line = self.peek_line();
-                let s = self.parse_stmt()?;
+                let s = self.parse_single_stmt()?;
                 Stmt::Block(vec![Stmt::Line(line), s])
             };
 
@@ -982,6 +1034,38 @@ impl Parser {
                 self.terminate_stmt()?;
                 let call = Expr::Call { callee: Box::new(Expr::Var("SLEEP".to_string())), args: vec![arg] };
                 return Ok(Stmt::ExprStmt(call));
+            } else if (name.eq_ignore_ascii_case("WAIT") || name.eq_ignore_ascii_case("AWAIT")) && self.wait_operand_next() {
+                // WAIT task@ [, timeoutMs] — block until the task/promise (or LIST of them) settles
+                let mut args = vec![self.parse_expr_bp(0)?];
+                if self.match_k(TokenKind::Comma) { args.push(self.parse_expr_bp(0)?); }
+                self.terminate_stmt()?;
+                let call = Expr::Call { callee: Box::new(Expr::Var(name.to_ascii_uppercase())), args };
+                return Ok(Stmt::ExprStmt(call));
+            } else if name.eq_ignore_ascii_case("SPAWN") && self.check(TokenKind::Ident) {
+                // SPAWN FuncName(args) as a fire-and-forget statement
+                let call = self.parse_spawn_rest()?;
+                self.terminate_stmt()?;
+                return Ok(Stmt::ExprStmt(call));
+            } else if name.eq_ignore_ascii_case("ROUTE") && self.check(TokenKind::String) {
+                // ROUTE "GET", "/users/:id", "Users.Show" is ROUTER@.Add(...)
+                let mut args = vec![self.parse_expr_bp(0)?];
+                while self.match_k(TokenKind::Comma) { args.push(self.parse_expr_bp(0)?); }
+                self.terminate_stmt()?;
+                let call = Expr::MemberCall { target: Box::new(Expr::Var("ROUTER@".to_string())), method: "Add".to_string(), args };
+                return Ok(Stmt::ExprStmt(call));
+            } else if name.eq_ignore_ascii_case("ASYNC") && self.check(TokenKind::Func) {
+                // ASYNC FUNC name(params) block
+                let kw = self.next().unwrap();
+                if kw.lexeme.eq_ignore_ascii_case("SUB") {
+                    return Err(BasilError(format!("parse error at line {}: ASYNC applies to FUNC only; use ASYNC FUNC", kw.line)));
+                }
+                return self.parse_func(basil_ast::FuncKind::AsyncFunc);
+            } else if name.eq_ignore_ascii_case("PARALLEL") && self.check(TokenKind::For) {
+                // PARALLEL FOR EACH ident IN expr <body> NEXT [ident]
+                self.expect(TokenKind::For)?;
+                self.expect(TokenKind::Each)?;
+                let (var, enumerable, body) = self.parse_foreach_rest()?;
+                return Ok(Stmt::ParallelForEach { var, enumerable, body: Box::new(body) });
             } else {
                 // Support zero-arg terminal commands as bare statements without parentheses
                 // e.g., CLS; HOME; CLEAR; COLOR_RESET; ATTR_RESET; CURSOR_SAVE; CURSOR_RESTORE; CURSOR_HIDE; CURSOR_SHOW;
@@ -1089,6 +1173,72 @@ impl Parser {
         Err(BasilError(format!("parse error at line {}: expected Semicolon or Colon", self.peek_line())))
     }
 
+    // Parse the remainder of FOR EACH after the EACH keyword: ident IN expr <body> NEXT [ident]
+    fn parse_foreach_rest(&mut self) -> Result<(String, Expr, Stmt)> {
+        // ident IN expr <body> NEXT [ident]
+        let var = self.expect_ident()?;
+        self.expect(TokenKind::In)?;
+        let enumerable = self.parse_expr_bp(0)?;
+        // Body: BEGIN..END, {..}, or single statement
+        let body: Stmt = if self.match_k(TokenKind::Begin) {
+            let mut inner = Vec::new();
+            loop {
+                while self.match_k(TokenKind::Semicolon) {}
+                if self.match_k(TokenKind::End) { break; }
+                if self.check(TokenKind::Eof) { return Err(BasilError(format!("parse error at line {}: unterminated FOR EACH BEGIN/END", self.peek_line()))); }
+                let line = self.peek_line();
+                let s = self.parse_stmt()?;
+                inner.push(Stmt::Line(line));
+                inner.push(s);
+            }
+            Stmt::Block(inner)
+        } else if self.match_k(TokenKind::LBrace) {
+            let mut inner = Vec::new();
+            loop {
+                while self.match_k(TokenKind::Semicolon) {}
+                if self.check(TokenKind::RBrace) { let _ = self.next(); break; }
+                if self.check(TokenKind::Eof) { return Err(BasilError(format!("parse error at line {}: unterminated FOR EACH {{ ... }}", self.peek_line()))); }
+                let line = self.peek_line();
+                let s = self.parse_stmt()?;
+                inner.push(Stmt::Line(line));
+                inner.push(s);
+            }
+            Stmt::Block(inner)
+        } else {
+            let line = self.peek_line();
+            let s = self.parse_single_stmt()?;
+            Stmt::Block(vec![Stmt::Line(line), s])
+        };
+        // Expect NEXT [ident]
+        while self.match_k(TokenKind::Semicolon) {}
+        self.expect(TokenKind::Next)?;
+        if self.check(TokenKind::Ident) { let _ = self.next(); }
+        let _ = self.terminate_stmt();
+        Ok((var, enumerable, body))
+    }
+
+    // Parse the remainder of SPAWN: FuncName[(args)] → SPAWN(FuncName, args...)
+    fn parse_spawn_rest(&mut self) -> Result<Expr> {
+        let fname = self.expect_ident()?;
+        let mut args = vec![Expr::Var(fname)];
+        if self.match_k(TokenKind::LParen) {
+            if !self.check(TokenKind::RParen) {
+                loop {
+                    args.push(self.parse_expr_bp(0)?);
+                    if !self.match_k(TokenKind::Comma) { break; }
+                }
+            }
+            self.expect(TokenKind::RParen)?;
+        }
+        Ok(Expr::Call { callee: Box::new(Expr::Var("SPAWN".to_string())), args })
+    }
+
+    // WAIT/AWAIT are the task forms only when an operand follows, so a variable or routine of that
+    // name (`WAIT = 5`, `WAIT(n)`, `PRINTLN WAIT;`) parses as it did before
+    fn wait_operand_next(&self) -> bool {
+        matches!(self.peek_kind(), Some(TokenKind::Ident | TokenKind::LBracket | TokenKind::Number | TokenKind::String | TokenKind::New))
+    }
+
     // Pratt parser with postfix call and comparisons
     fn parse_expr_bp(&mut self, min_bp: u8) -> Result<Expr> {
         let mut lhs = self.parse_prefix()?;
@@ -1193,7 +1343,19 @@ impl Parser {
                 }
                 Ok(Expr::Str("Erik Olson".to_string()))
             }
-            Some(TokenKind::Ident) => Ok(Expr::Var(self.next().unwrap().lexeme)),
+            Some(TokenKind::Ident) => {
+                let t = self.next().unwrap();
+                // SPAWN FuncName(args) yields a TASK handle
+                if t.lexeme.eq_ignore_ascii_case("SPAWN") && self.check(TokenKind::Ident) {
+                    return self.parse_spawn_rest();
+                }
+                // WAIT task@ / AWAIT expr (without parentheses) yields the task's or promise's result
+                if (t.lexeme.eq_ignore_ascii_case("WAIT") || t.lexeme.eq_ignore_ascii_case("AWAIT")) && self.wait_operand_next() {
+                    let target = self.parse_expr_bp(80)?;
+                    return Ok(Expr::Call { callee: Box::new(Expr::Var(t.lexeme.to_ascii_uppercase())), args: vec![target] });
+                }
+                Ok(Expr::Var(t.lexeme))
+            }
             Some(TokenKind::New) => {
                 // NEW Type(args)
                 let _ = self.next().unwrap();
@@ -1373,6 +1535,23 @@ impl Parser {
     fn expect_ident(&mut self) -> Result<String> {
         if self.check(TokenKind::Ident) { Ok(self.next().unwrap().lexeme) } else { Err(BasilError(format!("parse error at line {}: expected identifier", self.peek_line()))) }
     }
+    // A label name, or a line number
+    fn expect_label(&mut self) -> Result<String> {
+        match self.tokens.get(self.i) {
+            Some(t) if t.kind == TokenKind::Number && is_line_number(&t.lexeme) => Ok(self.next().unwrap().lexeme),
+            Some(t) if t.kind == TokenKind::Ident => Ok(self.next().unwrap().lexeme),
+            _ => Err(BasilError(format!("parse error at line {}: expected label or line number", self.peek_line()))),
+        }
+    }
+    // The statement of a single-statement body, with the label of a line number before it
+    fn parse_single_stmt(&mut self) -> Result<Stmt> {
+        let s = self.parse_stmt()?;
+        if !matches!(&s, Stmt::Label(n) if is_line_number(n)) { return Ok(s); }
+        while self.match_k(TokenKind::Semicolon) {}
+        let line = self.peek_line();
+        let next = self.parse_stmt()?;
+        Ok(Stmt::Block(vec![s, Stmt::Line(line), next]))
+    }
     // Accept an identifier or a keyword token as a member name after '.'
     fn expect_member_name(&mut self) -> Result<String> {
         match self.peek_kind() {
@@ -0,0 +1,248 @@
+//! Line and branch coverage for `basilc test --coverage`.
+//!
+//! The VM counts `SetLine` hits and the outcome of every `JumpIfFalse`. Executable lines and
+//! branch sites are found by walking the compiled chunks, so lines that never ran still show up
+//! with a zero count. Results are written as lcov tracefiles and can be merged across runs.
+//!
+//! Comments can exclude code: a line whose comment contains `LCOV_EXCL_LINE` is skipped, and so
+//! is everything from a line commented `LCOV_EXCL_START` up to the line commented `LCOV_EXCL_STOP`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::rc::Rc;
+
+use basil_bytecode::{Chunk, Op, Value};
+
+/// Runtime counters, keyed by chunk address and instruction offset.
+pub(crate) struct Recorder {
+    top: Rc<Chunk>,
+    lines: HashMap<u32, u64>,
+    // [condition true (fell through), condition false (jumped)]
+    branches: HashMap<(usize, usize), [u64; 2]>,
+}
+
+impl Recorder {
+    pub(crate) fn new(top: Rc<Chunk>) -> Self {
+        Recorder { top, lines: HashMap::new(), branches: HashMap::new() }
+    }
+
+    #[inline]
+    pub(crate) fn line(&mut self, line: u32) { *self.lines.entry(line).or_insert(0) += 1; }
+
+    #[inline]
+    pub(crate) fn branch(&mut self, chunk: &Rc<Chunk>, ip: usize, taken_false: bool) {
+        self.branches.entry((Rc::as_ptr(chunk) as usize, ip)).or_insert([0, 0])[taken_false as usize] += 1;
+    }
+
+    /// Combine the counters with the static layout of the program.
+    pub(crate) fn finish(self, comments: Option<&HashMap<u32, Vec<String>>>) -> FileCoverage {
+        let mut cov = FileCoverage::default();
+        let mut seen = HashSet::new();
+        let mut todo = vec![self.top.clone()];
+        while let Some(chunk) = todo.pop() {
+            if !seen.insert(Rc::as_ptr(&chunk) as usize) { continue; }
+            for v in &chunk.consts {
+                if let Value::Func(f) = v { todo.push(f.chunk.clone()); }
+            }
+            let key = Rc::as_ptr(&chunk) as usize;
+            let mut line = 0u32;
+            let mut ip = 0usize;
+            while ip < chunk.code.len() {
+                let Some(op) = Op::from_u8(chunk.code[ip]) else { break };
+                match op {
+                    Op::SetLine if ip + 2 < chunk.code.len() => {
+                        line = u16::from_le_bytes([chunk.code[ip + 1], chunk.code[ip + 2]]) as u32;
+                        cov.lines.entry(line).or_insert(0);
+                    }
+                    Op::JumpIfFalse if line != 0 => {
+                        let hits = self.branches.get(&(key, ip)).copied();
+                        cov.branches.entry(line).or_default().push(hits);
+                    }
+                    _ => {}
+                }
+                ip += 1 + op.operand_len();
+            }
+        }
+        for (l, n) in &self.lines {
+            if let Some(c) = cov.lines.get_mut(l) { *c = *n; }
+        }
+        if let Some(comments) = comments { cov.exclude(comments); }
+        cov
+    }
+}
+
+/// Coverage of one source file: line hit counts, and per line the branch sites in code order
+/// (`None` when the condition was never evaluated).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub lines: BTreeMap<u32, u64>,
+    pub branches: BTreeMap<u32, Vec<Option<[u64; 2]>>>,
+}
+
+impl FileCoverage {
+    fn exclude(&mut self, comments: &HashMap<u32, Vec<String>>) {
+        let has = |line: u32, marker: &str| comments.get(&line).map(|c| c.iter().any(|t| t.contains(marker))).unwrap_or(false);
+        let mut excluding = false;
+        let mut drop = Vec::new();
+        for &line in self.lines.keys() {
+            if has(line, "LCOV_EXCL_STOP") { excluding = false; }
+            if has(line, "LCOV_EXCL_START") { excluding = true; }
+            if excluding || has(line, "LCOV_EXCL_LINE") { drop.push(line); }
+        }
+        for l in drop { self.lines.remove(&l); self.branches.remove(&l); }
+    }
+
+    pub fn lines_found(&self) -> usize { self.lines.len() }
+    pub fn lines_hit(&self) -> usize { self.lines.values().filter(|n| **n > 0).count() }
+    pub fn branches_found(&self) -> usize { self.branches.values().map(|v| v.len() * 2).sum() }
+    pub fn branches_hit(&self) -> usize {
+        self.branches.values().flatten().map(|b| b.map(|[t, f]| (t > 0) as usize + (f > 0) as usize).unwrap_or(0)).sum()
+    }
+
+    /// Add another run's counts for the same file.
+    pub fn merge(&mut self, other: &FileCoverage) {
+        for (l, n) in &other.lines { *self.lines.entry(*l).or_insert(0) += n; }
+        for (l, sites) in &other.branches {
+            let mine = self.branches.entry(*l).or_default();
+            if mine.len() < sites.len() { mine.resize(sites.len(), None); }
+            for (i, s) in sites.iter().enumerate() {
+                mine[i] = match (mine[i], s) {
+                    (Some([a, b]), Some([c, d])) => Some([a + c, b + d]),
+                    (x, None) => x,
+                    (None, y) => *y,
+                };
+            }
+        }
+    }
+
+    /// Uncovered lines as compact ranges, e.g. "12-14, 20".
+    pub fn missed_ranges(&self) -> String {
+        let mut out: Vec<String> = Vec::new();
+        let mut run: Option<(u32, u32)> = None;
+        for (&l, &n) in &self.lines {
+            if n > 0 {
+                if let Some((a, b)) = run.take() { out.push(range_str(a, b)); }
+                continue;
+            }
+            run = Some(match run { Some((a, _)) => (a, l), None => (l, l) });
+        }
+        if let Some((a, b)) = run { out.push(range_str(a, b)); }
+        out.join(", ")
+    }
+}
+
+fn range_str(a: u32, b: u32) -> String { if a == b { a.to_string() } else { format!("{}-{}", a, b) } }
+
+/// An lcov tracefile: coverage per source path.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Lcov {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+impl Lcov {
+    /// Parse the subset of lcov written by `to_string` (SF, DA, BRDA, end_of_record).
+    pub fn parse(text: &str) -> Lcov {
+        let mut lcov = Lcov::default();
+        let mut cur: Option<(String, FileCoverage)> = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(path) = line.strip_prefix("SF:") {
+                cur = Some((path.to_string(), FileCoverage::default()));
+            } else if let (Some(rest), Some((_, fc))) = (line.strip_prefix("DA:"), cur.as_mut()) {
+                let mut it = rest.split(',');
+                if let (Some(Ok(l)), Some(Ok(n))) = (it.next().map(str::parse::<u32>), it.next().map(str::parse::<u64>)) {
+                    *fc.lines.entry(l).or_insert(0) += n;
+                }
+            } else if let (Some(rest), Some((_, fc))) = (line.strip_prefix("BRDA:"), cur.as_mut()) {
+                let parts: Vec<&str> = rest.split(',').collect();
+                if parts.len() == 4 {
+                    if let (Ok(l), Ok(block), Ok(branch)) = (parts[0].parse::<u32>(), parts[1].parse::<usize>(), parts[2].parse::<usize>()) {
+                        let sites = fc.branches.entry(l).or_default();
+                        if sites.len() <= block { sites.resize(block + 1, None); }
+                        if let (Ok(n), true) = (parts[3].parse::<u64>(), branch < 2) {
+                            let mut v = sites[block].unwrap_or([0, 0]);
+                            v[branch] += n;
+                            sites[block] = Some(v);
+                        }
+                    }
+                }
+            } else if line == "end_of_record" {
+                if let Some((path, fc)) = cur.take() { lcov.add(&path, &fc); }
+            }
+        }
+        lcov
+    }
+
+    pub fn add(&mut self, path: &str, fc: &FileCoverage) {
+        self.files.entry(path.to_string()).or_default().merge(fc);
+    }
+}
+
+impl std::fmt::Display for Lcov {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = String::new();
+        for (path, fc) in &self.files {
+            let _ = writeln!(s, "TN:");
+            let _ = writeln!(s, "SF:{}", path);
+            for (l, n) in &fc.lines { let _ = writeln!(s, "DA:{},{}", l, n); }
+            let _ = writeln!(s, "LF:{}", fc.lines_found());
+            let _ = writeln!(s, "LH:{}", fc.lines_hit());
+            for (l, sites) in &fc.branches {
+                for (block, site) in sites.iter().enumerate() {
+                    for branch in 0..2 {
+                        match site {
+                            Some(v) => { let _ = writeln!(s, "BRDA:{},{},{},{}", l, block, branch, v[branch]); }
+                            None => { let _ = writeln!(s, "BRDA:{},{},{},-", l, block, branch); }
+                        }
+                    }
+                }
+            }
+            let _ = writeln!(s, "BRF:{}", fc.branches_found());
+            let _ = writeln!(s, "BRH:{}", fc.branches_hit());
+            let _ = writeln!(s, "end_of_record");
+        }
+        f.write_str(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockInputProvider, VM};
+    use basil_compiler::compile;
+    use basil_parser::parse;
+
+    fn cover(src: &str) -> FileCoverage {
+        let prog = compile(&parse(src).unwrap()).unwrap();
+        let mut vm = VM::new_with_test(prog, Box::new(MockInputProvider::new(1)), false, None, None, None);
+        vm.enable_coverage();
+        vm.run().unwrap();
+        vm.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn records_lines_and_branches() {
+        let src = "LET x = 1;\nIF x = 2 THEN BEGIN\n PRINTLN \"never\";\nEND\nIF x = 1 THEN PRINTLN \"yes\";\n";
+        let fc = cover(src);
+        assert_eq!(fc.lines.get(&1), Some(&1));
+        assert_eq!(fc.lines.get(&3), Some(&0));
+        assert!(fc.lines_hit() < fc.lines_found());
+        // IF x = 2 only ever jumped; IF x = 1 only ever fell through
+        assert_eq!(fc.branches[&2][0], Some([0, 1]));
+        assert_eq!(fc.branches[&5][0], Some([1, 0]));
+        assert_eq!(fc.missed_ranges(), "3");
+    }
+
+    #[test]
+    fn lcov_round_trips_and_merges() {
+        let fc = cover("LET x = 1;\nIF x = 1 THEN PRINTLN \"yes\";\n");
+        let mut lcov = Lcov::default();
+        lcov.add("a.basil", &fc);
+        let text = lcov.to_string();
+        assert!(text.contains("SF:a.basil") && text.contains("BRDA:2,0,0,1"), "{}", text);
+        let mut again = Lcov::parse(&text);
+        assert_eq!(again, lcov);
+        again.add("a.basil", &fc);
+        assert_eq!(again.files["a.basil"].lines[&1], 2);
+    }
+}
@@ -0,0 +1,247 @@
+//! Scripted input for `basilc test`: answers replayed from a `.inputs` file, answers chosen by
+//! matching the prompt, and recording a live session into a file that replays it.
+//!
+//! A `.inputs` file has one answer per line, used in order by INPUT$ (the whole line) and by
+//! INPUTC$, INKEY$ and INKEY% (the first character; a blank line is Enter). `#` starts a comment,
+//! `<ENTER>`, `<ESC>`, `<TAB>`, `<BACKSPACE>` and `<NOKEY>` stand for keys, and a leading `\`
+//! escapes a line that would otherwise start with `#`, `?` or `\`. Lines of the form
+//! `? prompt text => answer | answer ...` are rules: when the text the program printed since the
+//! previous input contains `prompt text` (ignoring case), the rule's next answer is used instead.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use basil_common::{BasilError, Result};
+
+use crate::InputProvider;
+
+const KEYS: [(&str, Option<char>); 5] = [
+    ("<ENTER>", Some('\r')),
+    ("<ESC>", Some('\u{1b}')),
+    ("<TAB>", Some('\t')),
+    ("<BACKSPACE>", Some('\u{8}')),
+    ("<NOKEY>", None),
+];
+
+/// One answer from a `.inputs` file, unescaped.
+#[derive(Debug, Clone, PartialEq)]
+enum Answer {
+    Text(String),
+    Key(Option<char>),
+}
+
+impl Answer {
+    fn parse(s: &str) -> Answer {
+        if let Some((_, key)) = KEYS.iter().find(|(name, _)| s.eq_ignore_ascii_case(name)) {
+            return Answer::Key(*key);
+        }
+        Answer::Text(s.strip_prefix('\\').unwrap_or(s).to_string())
+    }
+
+    fn line(&self) -> String {
+        match self {
+            Answer::Text(s) => s.clone(),
+            Answer::Key(Some(c)) if *c != '\r' => c.to_string(),
+            Answer::Key(_) => String::new(),
+        }
+    }
+
+    fn key(&self) -> Option<char> {
+        match self {
+            Answer::Text(s) => Some(s.chars().next().unwrap_or('\r')),
+            Answer::Key(k) => *k,
+        }
+    }
+}
+
+// The line a recorded answer is written as, so that `Answer::parse` gives it back
+fn encode_line(s: &str) -> String {
+    let special = s.starts_with(['#', '?', '\\']) || KEYS.iter().any(|(name, _)| s.eq_ignore_ascii_case(name));
+    if special { format!("\\{}", s) } else { s.to_string() }
+}
+
+fn encode_key(key: Option<char>) -> String {
+    match KEYS.iter().find(|(_, k)| *k == key) {
+        Some((name, _)) => name.to_string(),
+        None => encode_line(&key.map(String::from).unwrap_or_default()),
+    }
+}
+
+// The last line of the prompt with words in it (not just "> "), for messages and recordings
+fn last_line(prompt: &str) -> &str {
+    prompt.lines().rev().map(str::trim).find(|l| l.chars().any(char::is_alphanumeric)).unwrap_or("")
+}
+
+/// Answers from a `.inputs` file, used in order. Running out is an error, so a test can't loop
+/// forever on input it didn't expect.
+pub struct ReplayInputProvider {
+    source: String,
+    answers: Vec<Answer>,
+    next: usize,
+}
+
+impl ReplayInputProvider {
+    fn next(&mut self, prompt: &str) -> Result<&Answer> {
+        let Some(answer) = self.answers.get(self.next) else {
+            let at = last_line(prompt);
+            let at = if at.is_empty() { String::new() } else { format!(" (prompt: {:?})", at) };
+            return Err(BasilError(format!("{}: no answer left for input #{}{}", self.source, self.next + 1, at)));
+        };
+        self.next += 1;
+        Ok(answer)
+    }
+}
+
+impl InputProvider for ReplayInputProvider {
+    fn read_line(&mut self, prompt: &str) -> Result<String> { self.next(prompt).map(Answer::line) }
+    fn read_char(&mut self, prompt: &str) -> Result<Option<char>> { self.next(prompt).map(Answer::key) }
+}
+
+struct Rule {
+    pattern: String,
+    answers: Vec<Answer>,
+    used: usize,
+}
+
+/// Answers chosen by matching the prompt against `? text => answer` rules, in file order. A rule
+/// with several answers gives them in turn and then repeats the last. Prompts no rule matches
+/// take the file's plain answers in order.
+pub struct RuleInputProvider {
+    rules: Vec<Rule>,
+    fallback: ReplayInputProvider,
+}
+
+impl RuleInputProvider {
+    fn answer(&mut self, prompt: &str) -> Result<Answer> {
+        let text = prompt.to_lowercase();
+        if let Some(rule) = self.rules.iter_mut().find(|r| r.pattern == "*" || text.contains(&r.pattern)) {
+            let answer = rule.answers[rule.used.min(rule.answers.len() - 1)].clone();
+            rule.used += 1;
+            return Ok(answer);
+        }
+        if self.fallback.next >= self.fallback.answers.len() {
+            return Err(BasilError(format!("{}: no rule matches the prompt {:?} and no plain answers are left", self.fallback.source, last_line(prompt))));
+        }
+        self.fallback.next(prompt).cloned()
+    }
+}
+
+impl InputProvider for RuleInputProvider {
+    fn read_line(&mut self, prompt: &str) -> Result<String> { self.answer(prompt).map(|a| a.line()) }
+    fn read_char(&mut self, prompt: &str) -> Result<Option<char>> { self.answer(prompt).map(|a| a.key()) }
+}
+
+/// Parse a `.inputs` file. `source` names it in errors.
+pub fn parse_inputs(source: &str, text: &str) -> Result<Box<dyn InputProvider>> {
+    let mut answers = Vec::new();
+    let mut rules = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.starts_with('#') { continue; }
+        let Some(rule) = line.strip_prefix('?') else {
+            answers.push(Answer::parse(line));
+            continue;
+        };
+        let Some((pattern, list)) = rule.split_once("=>") else {
+            return Err(BasilError(format!("{}:{}: a rule is written \"? prompt text => answer\"", source, n + 1)));
+        };
+        let pattern = pattern.trim().to_lowercase();
+        if pattern.is_empty() {
+            return Err(BasilError(format!("{}:{}: rule has no prompt text (use * to match any prompt)", source, n + 1)));
+        }
+        rules.push(Rule { pattern, answers: list.split('|').map(|a| Answer::parse(a.trim())).collect(), used: 0 });
+    }
+    let replay = ReplayInputProvider { source: source.to_string(), answers, next: 0 };
+    if rules.is_empty() { Ok(Box::new(replay)) } else { Ok(Box::new(RuleInputProvider { rules, fallback: replay })) }
+}
+
+/// Load a `.inputs` file for `basilc test --inputs`.
+pub fn load_inputs(path: &Path) -> Result<Box<dyn InputProvider>> {
+    let text = std::fs::read_to_string(path).map_err(|e| BasilError(format!("cannot read {}: {}", path.display(), e)))?;
+    parse_inputs(&path.display().to_string(), &text)
+}
+
+/// Writes the answers of a live session as a `.inputs` file (`basilc run --record`). Each answer
+/// is preceded by the prompt it answered, as a comment. Keys polled with INKEY$/INKEY% are only
+/// recorded when one was pressed.
+pub struct Recorder {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, script: &str) -> Result<Recorder> {
+        let file = File::create(path).map_err(|e| BasilError(format!("cannot create {}: {}", path.display(), e)))?;
+        let mut rec = Recorder { path: path.to_path_buf(), out: BufWriter::new(file) };
+        rec.write(&format!("# Recorded from {} with basilc run --record; replay with basilc test --inputs", script))?;
+        Ok(rec)
+    }
+
+    pub(crate) fn line(&mut self, prompt: &str, answer: &str) -> Result<()> {
+        self.prompt(prompt)?;
+        self.write(&encode_line(answer))
+    }
+
+    pub(crate) fn key(&mut self, prompt: &str, key: Option<char>) -> Result<()> {
+        self.prompt(prompt)?;
+        self.write(&encode_key(key))
+    }
+
+    fn prompt(&mut self, prompt: &str) -> Result<()> {
+        match last_line(prompt) {
+            "" => Ok(()),
+            text => self.write(&format!("# {}", text)),
+        }
+    }
+
+    // Flushed line by line so a session ended with Ctrl+C is still saved
+    fn write(&mut self, line: &str) -> Result<()> {
+        writeln!(self.out, "{}", line)
+            .and_then(|_| self.out.flush())
+            .map_err(|e| BasilError(format!("cannot write {}: {}", self.path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_answers_and_rules() {
+        let mut p = parse_inputs("t.inputs", "# menu\n? name => Ada\n? choice => 1 | 2 | 0\n42\n\n<ESC>\n\\# not a comment\n").unwrap();
+        assert_eq!(p.read_line("What is your NAME?").unwrap(), "Ada");
+        assert_eq!(p.read_line("1) Add\n2) List\nChoice: ").unwrap(), "1");
+        assert_eq!(p.read_line("Choice: ").unwrap(), "2");
+        assert_eq!(p.read_line("Age?").unwrap(), "42");
+        assert_eq!(p.read_char("Press Enter").unwrap(), Some('\r'));
+        assert_eq!(p.read_char("").unwrap(), Some('\u{1b}'));
+        assert_eq!(p.read_line("").unwrap(), "# not a comment");
+        assert_eq!(p.read_line("Choice: ").unwrap(), "0");
+        assert_eq!(p.read_line("Choice: ").unwrap(), "0");
+        let err = p.read_line("Age?").unwrap_err();
+        assert!(err.0.contains("no rule matches the prompt \"Age?\""), "{}", err.0);
+    }
+
+    #[test]
+    fn recordings_replay() {
+        let path = std::env::temp_dir().join(format!("rec_{}.inputs", std::process::id()));
+        let mut rec = Recorder::create(&path, "demo.basil").unwrap();
+        rec.line("Name?\n> ", "#hash").unwrap();
+        rec.line("", "").unwrap();
+        rec.key("Press a key", Some('\r')).unwrap();
+        rec.key("", Some('x')).unwrap();
+        rec.line("", "<tab>").unwrap();
+        drop(rec);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# Name?\n\\#hash\n"), "{}", text);
+        let mut p = load_inputs(&path).unwrap();
+        assert_eq!(p.read_line("").unwrap(), "#hash");
+        assert_eq!(p.read_line("").unwrap(), "");
+        assert_eq!(p.read_char("").unwrap(), Some('\r'));
+        assert_eq!(p.read_char("").unwrap(), Some('x'));
+        assert_eq!(p.read_line("").unwrap(), "<tab>");
+        assert!(p.read_line("Again?").unwrap_err().0.contains("no answer left for input #6 (prompt: \"Again?\")"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
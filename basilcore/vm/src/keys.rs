@@ -0,0 +1,169 @@
+//! Single key reads for INKEY$, INKEY% and INPUTC$.
+//!
+//! On a terminal they put it in raw mode through crossterm. WASI has no terminal, so there
+//! INKEY$/INKEY% never see a key waiting and INPUTC$ takes the next character of stdin.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+    Other,
+}
+
+impl Key {
+    /// What INKEY$ returns for the key: a character or control code, else ""
+    pub fn text(self) -> String {
+        match self {
+            Key::Char(c) => c.to_string(),
+            Key::Enter => "\r".to_string(),
+            Key::Backspace => "\u{0008}".to_string(),
+            Key::Tab => "\t".to_string(),
+            Key::Esc => "\u{001B}".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// What INKEY% returns for the key: its character code, 1000+ for cursor keys, 1100+n for Fn
+    pub fn code(self) -> i64 {
+        match self {
+            Key::Char(c) => c as i64,
+            Key::Enter => 13,
+            Key::Backspace => 8,
+            Key::Tab => 9,
+            Key::Esc => 27,
+            Key::Up => 1000,
+            Key::Down => 1001,
+            Key::Left => 1002,
+            Key::Right => 1003,
+            Key::Home => 1004,
+            Key::End => 1005,
+            Key::PageUp => 1006,
+            Key::PageDown => 1007,
+            Key::Insert => 1008,
+            Key::Delete => 1009,
+            Key::F(n) => 1100 + n as i64,
+            Key::Other => 0,
+        }
+    }
+}
+
+pub use imp::{poll_key, wait_key};
+
+#[cfg(not(target_os = "wasi"))]
+mod imp {
+    use std::time::Duration;
+
+    use basil_common::{BasilError, Result};
+    use crossterm::event::{poll, read, Event, KeyCode, KeyEvent};
+    use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+    use super::Key;
+
+    fn key(code: KeyCode) -> Key {
+        match code {
+            KeyCode::Char(c) => Key::Char(c),
+            KeyCode::Enter => Key::Enter,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Tab => Key::Tab,
+            KeyCode::Esc => Key::Esc,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::Left => Key::Left,
+            KeyCode::Right => Key::Right,
+            KeyCode::Home => Key::Home,
+            KeyCode::End => Key::End,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            KeyCode::Insert => Key::Insert,
+            KeyCode::Delete => Key::Delete,
+            KeyCode::F(n) => Key::F(n),
+            _ => Key::Other,
+        }
+    }
+
+    fn raw<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+        enable_raw_mode().map_err(|e| BasilError(format!("enable_raw_mode: {}", e)))?;
+        let out = f();
+        let _ = disable_raw_mode();
+        out
+    }
+
+    fn ready() -> Result<bool> {
+        poll(Duration::from_millis(0)).map_err(|e| BasilError(format!("poll: {}", e)))
+    }
+
+    fn next_event() -> Result<Event> {
+        read().map_err(|e| BasilError(format!("read key: {}", e)))
+    }
+
+    /// The key pressed, if one is waiting (INKEY$, INKEY%)
+    pub fn poll_key() -> Result<Option<Key>> {
+        raw(|| {
+            if !ready()? { return Ok(None); }
+            match next_event()? {
+                Event::Key(KeyEvent { code, .. }) => Ok(Some(key(code))),
+                _ => Ok(None),
+            }
+        })
+    }
+
+    /// Wait for the next key, dropping any typed before the call (INPUTC$)
+    pub fn wait_key() -> Result<Key> {
+        raw(|| {
+            while ready()? { let _ = read(); }
+            loop {
+                if let Event::Key(KeyEvent { code, .. }) = next_event()? { return Ok(key(code)); }
+            }
+        })
+    }
+}
+
+#[cfg(target_os = "wasi")]
+mod imp {
+    use std::io::Read;
+
+    use basil_common::{BasilError, Result};
+
+    use super::Key;
+
+    pub fn poll_key() -> Result<Option<Key>> { Ok(None) }
+
+    /// The next character of stdin; end of input reads as Enter
+    pub fn wait_key() -> Result<Key> {
+        let mut buf = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            let n = std::io::stdin().read(&mut byte).map_err(|e| BasilError(format!("read key: {}", e)))?;
+            if n == 0 { return Ok(Key::Enter); }
+            buf.push(byte[0]);
+            match std::str::from_utf8(&buf) {
+                Ok(s) => {
+                    return Ok(match s.chars().next() {
+                        Some('\n') | Some('\r') => Key::Enter,
+                        Some('\t') => Key::Tab,
+                        Some('\u{8}') => Key::Backspace,
+                        Some('\u{1b}') => Key::Esc,
+                        Some(c) => Key::Char(c),
+                        None => Key::Other,
+                    });
+                }
+                Err(e) if e.error_len().is_some() => return Ok(Key::Other),
+                Err(_) => {}
+            }
+        }
+    }
+}
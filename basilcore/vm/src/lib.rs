@@ -40,37 +40,37 @@ SOFTWARE.
 
 //! Frame-based VM with calls, locals, jumps, comparisons
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::sync::Arc;
-use std::io::{self, Write, Read, Seek, SeekFrom};
-use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
+use std::io::{self, Write, Read};
 use std::env;
 use std::time::Duration;
-use crossterm::event::{read, Event, KeyEvent, KeyCode};
-use crossterm::event::poll;
 use std::collections::{HashMap, HashSet};
-use std::fs::{self, OpenOptions};
-use std::path::{Path, PathBuf};
 
 pub mod debug;
-
-use basil_common::{Result, BasilError};
-use basil_bytecode::{Program as BCProgram, Chunk, Value, Op, ElemType, ArrayObj, ObjectDescriptor, PropDesc, MethodDesc};
+pub mod tasks;
+pub mod snapshot;
+pub mod profile;
+pub mod coverage;
+pub mod web;
+pub mod session;
+pub mod router;
+pub mod push;
+pub mod testing;
+pub mod input;
+pub mod keys;
+
+use basil_common::{Result, BasilError, SourceMap};
+use basil_bytecode::{Program as BCProgram, Chunk, Value, Op, ElemType, ArrayObj, ObjectDescriptor, ObjectRef, PropDesc, MethodDesc};
 use basil_objects::{Registry, register_objects};
 use basil_parser::parse as parse_basil;
 use basil_compiler::compile as compile_basil;
 use basil_bytecode::{deserialize_program};
-#[cfg(feature = "obj-base64")]
-use base64::{engine::general_purpose, Engine as _};
-#[cfg(feature = "obj-zip")]
-use basil_objects::zip as zip_utils;
-#[cfg(feature = "obj-curl")]
-use basil_objects::curl as curl_utils;
-#[cfg(any(feature = "obj-json", feature = "obj-csv"))]
-use serde_json::{Value as JValue};
-#[cfg(feature = "obj-csv")]
-use csv::{ReaderBuilder, WriterBuilder};
-#[cfg(feature = "obj-sqlite")]
-use basil_objects::sqlite as sqlite_utils;
+use basil_builtins::{text, system, files, files::FileTable};
+#[cfg(any(feature = "obj-base64", feature = "obj-json", feature = "obj-csv"))]
+use basil_builtins::formats;
+#[cfg(any(feature = "obj-zip", feature = "obj-curl", feature = "obj-sqlite", feature = "obj-term", feature = "obj-daw"))]
+use basil_builtins::ext;
 #[cfg(feature = "obj-audio")]
 use basil_objects::audio as audio_utils;
 #[cfg(feature = "obj-midi")]
@@ -78,75 +78,11 @@ use basil_objects::midi as midi_utils;
 #[cfg(feature = "obj-daw")]
 use basil_objects::daw as daw_utils;
 
-#[cfg(feature = "obj-json")]
-fn value_to_jvalue(v: &Value) -> Result<JValue> {
-    use serde_json::Map;
-    match v {
-        Value::Null => Ok(JValue::Null),
-        Value::Bool(b) => Ok(JValue::Bool(*b)),
-        Value::Int(i) => Ok(JValue::Number((*i).into())),
-        Value::Num(n) => serde_json::Number::from_f64(*n)
-            .map(JValue::Number)
-            .ok_or_else(|| BasilError("JSON_STRINGIFY$: NaN/Inf not representable".into())),
-        Value::Str(s) => Ok(JValue::String(s.clone())),
-        Value::Array(arr_rc) => {
-            let arr = arr_rc.as_ref();
-            let data = arr.data.borrow();
-            let mut out = Vec::with_capacity(data.len());
-            for elem in data.iter() {
-                out.push(value_to_jvalue(elem)?);
-            }
-            Ok(JValue::Array(out))
-        }
-        Value::Object(obj_rc) => {
-            let obj_ref = obj_rc.borrow();
-            let desc = obj_ref.descriptor();
-            let mut map = Map::new();
-            for prop in desc.properties.iter().filter(|p| p.readable) {
-                // get_prop returns Result<Value>
-                if let Ok(pv) = obj_ref.get_prop(&prop.name) {
-                    let jv = value_to_jvalue(&pv)?;
-                    map.insert(prop.name.clone(), jv);
-                }
-            }
-            Ok(JValue::Object(map))
-        }
-        Value::List(items) => {
-            let v = items.borrow();
-            let mut out = Vec::with_capacity(v.len());
-            for it in v.iter() {
-                out.push(value_to_jvalue(it)?);
-            }
-            Ok(JValue::Array(out))
-        }
-        Value::Dict(map) => {
-            let m = map.borrow();
-            let mut obj = Map::new();
-            for (k, v) in m.iter() {
-                obj.insert(k.clone(), value_to_jvalue(v)?);
-            }
-            Ok(JValue::Object(obj))
-        }
-        Value::StrArray2D { rows, cols, data } => {
-            let mut out: Vec<JValue> = Vec::with_capacity(*rows);
-            for r in 0..*rows {
-                let mut row: Vec<JValue> = Vec::with_capacity(*cols);
-                for c in 0..*cols {
-                    let idx = r * *cols + c;
-                    row.push(JValue::String(data[idx].clone()));
-                }
-                out.push(JValue::Array(row));
-            }
-            Ok(JValue::Array(out))
-        }
-        Value::Func(_) => Err(BasilError("JSON_STRINGIFY$: cannot stringify a function".into())),
-    }
-}
-
 // --- Input provider abstraction for test mode ---
+// `prompt` is everything the program printed since the previous input (see input.rs)
 pub trait InputProvider {
-    fn read_line(&mut self) -> String;       // for INPUT/INPUT$
-    fn read_char(&mut self) -> Option<char>; // for INPUTC$/INKEY$/INKEY%
+    fn read_line(&mut self, prompt: &str) -> Result<String>;       // for INPUT/INPUT$
+    fn read_char(&mut self, prompt: &str) -> Result<Option<char>>; // for INPUTC$/INKEY$/INKEY%
 }
 
 // Deterministic mock input provider with simple PRNG-based cycling sequence
@@ -175,25 +111,25 @@ impl MockInputProvider {
     }
 }
 impl InputProvider for MockInputProvider {
-    fn read_line(&mut self) -> String {
-        match self.next_index() {
+    fn read_line(&mut self, _prompt: &str) -> Result<String> {
+        Ok(match self.next_index() {
             0 => "Y".to_string(),
             1 => "N".to_string(),
             2 => "0".to_string(),
             3 => "1".to_string(),
             4 => "9".to_string(),
             _ => String::new(), // blank
-        }
+        })
     }
-    fn read_char(&mut self) -> Option<char> {
-        match self.next_index() {
+    fn read_char(&mut self, _prompt: &str) -> Result<Option<char>> {
+        Ok(match self.next_index() {
             0 => Some('Y'),
             1 => Some('N'),
             2 => Some('0'),
             3 => Some('1'),
             4 => Some('9'),
             _ => Some('\r'), // Enter
-        }
+        })
     }
 }
 
@@ -209,15 +145,8 @@ struct ArrEnum {
     total: usize,  // total elements
 }
 
-struct FileHandleEntry {
-    file: std::fs::File,
-    text: bool,
-    readable: bool,
-    writable: bool,
-    owner_depth: usize,
-}
-
-struct HandlerEntry { handler_ip: usize }
+// A TRY handler, with the frame depth, stack height and call-line depth to unwind to on RAISE
+struct HandlerEntry { handler_ip: usize, depth: usize, stack_len: usize, call_lines: usize }
 
 // --- Struct type descriptors for pack/unpack ---
 #[derive(Clone)]
@@ -247,13 +176,21 @@ pub struct VM {
     comments_map: Option<HashMap<u32, Vec<String>>>,
     mocked_inputs: usize,
     max_mocked_inputs: Option<usize>,
-    mock: Option<MockInputProvider>,
+    mock: Option<Box<dyn InputProvider>>,
+    // Output since the last input, handed to the input provider / recorder as the prompt
+    prompt_text: String,
+    recorder: Option<input::Recorder>,
     // Caches for CGI params
     get_params_cache: Option<Vec<String>>,    // name=value pairs from QUERY_STRING
     post_params_cache: Option<Vec<String>>,   // name=value pairs from stdin (x-www-form-urlencoded)
+    // In-process web requests (basilc serve): CGI variables, request body and output sink
+    // used instead of the process environment, stdin and stdout
+    cgi_env: Option<HashMap<String, String>>,
+    request_body: Option<Vec<u8>>,
+    // Shared so CLASS instances created during a request print into the same response
+    output: Option<Rc<RefCell<Box<dyn Write>>>>,
     // File I/O
-    file_table: HashMap<i64, FileHandleEntry>,
-    next_fh: i64,
+    file_table: FileTable,
     // Control whether to auto-close handles on function return (used for class methods)
     close_handles_on_ret: bool,
     // GOSUB stack and safety cap
@@ -261,11 +198,42 @@ pub struct VM {
     gosub_max_depth: usize,
     // Optional debugger
     pub debugger: Option<Arc<debug::Debugger>>,
+    // Template lines for generated code, so the debugger works in template file:line
+    source_map: Option<SourceMap>,
     // Exceptions
     _handlers: Vec<HandlerEntry>,
     current_exception: Option<String>,
     // Struct type descriptor registry
     struct_types: HashMap<String, VMTypeDesc>,
+    // Outstanding ASYNC FUNC tasks and PROMISEs, joined before the program ends
+    pending_async: Vec<basil_bytecode::ObjectRef>,
+    // Optional execution profiler (basilc run --profile)
+    profiler: Option<Box<profile::Profiler>>,
+    // Optional line/branch coverage recorder (basilc test --coverage)
+    coverage: Option<Box<coverage::Recorder>>,
+    // Test mode: STOP/EXIT end run() with this code instead of exiting the process
+    exit_code: Option<i32>,
+    // Status/headers/cookies of a web response (shared with RESPONSE@); written before the first PRINT
+    response: Option<Rc<RefCell<web::ResponseState>>>,
+    // REQUEST@/RESPONSE@/SESSION@ have been seeded (run() is re-entered on resume)
+    web_seeded: bool,
+    // Parsed request, built on first use by REQUEST@ or SESSION@
+    request: Option<Rc<web::RequestData>>,
+    // SESSION@ state, saved when the program ends
+    session: Option<Rc<RefCell<session::SessionState>>>,
+    // Running a CLASS for an enclosing program: the outer VM sends headers and saves the session
+    nested: bool,
+    // ROUTER@ table, and whether run() dispatches the request through it (routes.basil)
+    router: Option<Rc<RefCell<router::RouterState>>>,
+    routing: bool,
+    // Controller classes loaded by the router, by class name
+    controllers: HashMap<String, basil_bytecode::ObjectRef>,
+    // WEBSOCKET@ connection; its message loop runs after the top level (basilc serve)
+    websocket: Option<Rc<RefCell<push::WsState>>>,
+    // TEST/SETUP/TEARDOWN blocks registered by the program, run by `basilc test`
+    suite: testing::Suite,
+    // (frame depth, caller's line) for each CALL, restored on RET
+    call_lines: Vec<(usize, u32)>,
 }
 
 // --- Lightweight Class Instance object ---
@@ -274,17 +242,18 @@ struct ClassInstance {
     values: Vec<Value>,
     name_to_index: HashMap<String, usize>,
     // Persist open file handles across method calls for this instance
-    file_table: HashMap<i64, FileHandleEntry>,
-    next_fh: i64,
+    file_table: FileTable,
+    // Request/response of the page that created the instance, so methods can use REQUEST@ etc.
+    web: Option<web::WebContext>,
 }
 
 impl ClassInstance {
-    fn new(globals_names: Vec<String>, values: Vec<Value>) -> Self {
+    fn new(globals_names: Vec<String>, values: Vec<Value>, web: Option<web::WebContext>) -> Self {
         let mut name_to_index = HashMap::new();
         for (i, n) in globals_names.iter().enumerate() {
             name_to_index.insert(n.to_ascii_uppercase(), i);
         }
-        Self { globals_names, values, name_to_index, file_table: HashMap::new(), next_fh: 1 }
+        Self { globals_names, values, name_to_index, file_table: FileTable::new(), web }
     }
 
     fn get_index(&self, name: &str) -> Option<usize> {
@@ -330,8 +299,8 @@ impl basil_bytecode::BasicObject for ClassInstance {
         let mut vm = VM::new(prog);
         // Move persistent file handles into inner VM and disable auto-close-on-ret for methods
         vm.file_table = std::mem::take(&mut self.file_table);
-        vm.next_fh = self.next_fh;
         vm.close_handles_on_ret = false;
+        if let Some(ctx) = &self.web { vm.adopt_web_context(ctx.clone()); }
         // Seed globals with our instance values
         vm.globals = self.values.clone();
         // Prepare stack: place arguments starting at base 0
@@ -341,7 +310,6 @@ impl basil_bytecode::BasicObject for ClassInstance {
         vm.frames.push(frame);
         vm.run()?;
         // Capture back persistent file handles into this instance
-        self.next_fh = vm.next_fh;
         self.file_table = std::mem::take(&mut vm.file_table);
         // Collect return value
         let ret = vm.stack.pop().unwrap_or(Value::Null);
@@ -381,6 +349,7 @@ impl VM {
         let frame = Frame { chunk: top_chunk, ip: 0, base: 0 };
         let mut registry = Registry::new();
         register_objects(&mut registry);
+        tasks::register(&mut registry);
         #[allow(unused_mut)]
         let mut s = Self {
             frames: vec![frame],
@@ -399,17 +368,37 @@ impl VM {
             mocked_inputs: 0,
             max_mocked_inputs: None,
             mock: None,
+            prompt_text: String::new(),
+            recorder: None,
             get_params_cache: None,
             post_params_cache: None,
-            file_table: HashMap::new(),
-            next_fh: 1,
+            cgi_env: None,
+            request_body: None,
+            output: None,
+            file_table: FileTable::new(),
             close_handles_on_ret: true,
             gosub_stack: Vec::new(),
             gosub_max_depth: 4096,
             debugger: None,
+            source_map: None,
             _handlers: Vec::new(),
             current_exception: None,
             struct_types: HashMap::new(),
+            pending_async: Vec::new(),
+            profiler: None,
+            coverage: None,
+            exit_code: None,
+            response: None,
+            web_seeded: false,
+            request: None,
+            session: None,
+            nested: false,
+            router: None,
+            routing: false,
+            controllers: HashMap::new(),
+            websocket: None,
+            suite: testing::Suite::default(),
+            call_lines: Vec::new(),
         };
         #[cfg(feature = "obj-ai")]
         {
@@ -424,7 +413,7 @@ impl VM {
         s
     }
 
-    pub fn new_with_test(p: BCProgram, mock: MockInputProvider, trace: bool, script_path: Option<String>, comments_map: Option<HashMap<u32, Vec<String>>>, max_mocked_inputs: Option<usize>) -> Self {
+    pub fn new_with_test(p: BCProgram, mock: Box<dyn InputProvider>, trace: bool, script_path: Option<String>, comments_map: Option<HashMap<u32, Vec<String>>>, max_mocked_inputs: Option<usize>) -> Self {
         let mut vm = VM::new(p);
         vm.test_mode = true;
         vm.trace = trace;
@@ -447,6 +436,164 @@ impl VM {
         self.run()
     }
 
+    /// Run as an in-process web request: `vars` replaces the CGI environment (QUERY_STRING,
+    /// CONTENT_TYPE, ...), `body` replaces stdin for POST data.
+    pub fn set_web_request(&mut self, vars: HashMap<String, String>, body: Vec<u8>) {
+        self.cgi_env = Some(vars);
+        self.request_body = Some(body);
+    }
+    /// Send PRINT output to `out` instead of stdout.
+    pub fn set_output(&mut self, out: Box<dyn Write>) { self.output = Some(Rc::new(RefCell::new(out))); }
+    /// Have the VM write the CGI header block (`default_header` plus anything set through
+    /// RESPONSE@) right before the first byte of output, or when the program ends.
+    pub fn enable_cgi_headers(&mut self, default_header: &str) {
+        self.response = Some(Rc::new(RefCell::new(web::ResponseState::new(Some(default_header)))));
+    }
+
+    // Seed REQUEST@, RESPONSE@ and SESSION@ if the program mentions them
+    fn seed_web_objects(&mut self) {
+        if self.web_seeded { return; }
+        self.web_seeded = true;
+        // Object variables keep their `@` sigil in global names
+        let name_of = |vm: &VM, n: &str| vm.global_names.iter().find(|g| g.trim_end_matches('@').eq_ignore_ascii_case(n)).cloned();
+        if let Some(name) = name_of(self, "REQUEST") {
+            let obj: ObjectRef = Rc::new(RefCell::new(web::RequestObj { data: self.request_data() }));
+            self.set_global_by_name(&name, Value::Object(obj));
+        }
+        if let Some(name) = name_of(self, "RESPONSE") {
+            let obj: ObjectRef = Rc::new(RefCell::new(web::ResponseObj { state: self.response_state() }));
+            self.set_global_by_name(&name, Value::Object(obj));
+        }
+        if let Some(name) = name_of(self, "SESSION") {
+            let obj: ObjectRef = Rc::new(RefCell::new(session::SessionObj { state: self.session_state() }));
+            self.set_global_by_name(&name, Value::Object(obj));
+        }
+        if let Some(name) = name_of(self, "WEBSOCKET") {
+            // Pages reached over plain HTTP get a WEBSOCKET@ that is never open
+            let state = self.websocket.get_or_insert_with(|| Rc::new(RefCell::new(push::WsState::new(None, Vec::new())))).clone();
+            let obj: ObjectRef = Rc::new(RefCell::new(push::WebSocketObj { state }));
+            self.set_global_by_name(&name, Value::Object(obj));
+        }
+        if let Some(name) = name_of(self, "ROUTER") {
+            let state = self.router.get_or_insert_with(Default::default).clone();
+            let obj: ObjectRef = Rc::new(RefCell::new(router::RouterObj { state }));
+            self.set_global_by_name(&name, Value::Object(obj));
+        }
+    }
+
+    fn session_state(&mut self) -> Rc<RefCell<session::SessionState>> {
+        if self.session.is_none() {
+            let (request, response) = (self.request_data(), self.response_state());
+            // Session settings may come from the request (web server config) or the server's environment
+            let var = |n: &str| Some(self.cgi_var(n)).filter(|v| !v.is_empty()).or_else(|| env::var(n).ok()).unwrap_or_default();
+            self.session = Some(Rc::new(RefCell::new(session::SessionState::new(var, request, response))));
+        }
+        self.session.clone().expect("session created")
+    }
+
+    // The request this VM is answering, for CLASS instances it creates; None outside web requests
+    fn web_context(&mut self) -> Option<web::WebContext> {
+        self.response.as_ref()?;
+        Some(web::WebContext {
+            cgi_env: self.cgi_env.clone(),
+            output: self.output.clone(),
+            request: self.request_data(),
+            response: self.response_state(),
+            session: self.session_state(),
+            websocket: self.websocket.clone(),
+        })
+    }
+
+    fn adopt_web_context(&mut self, ctx: web::WebContext) {
+        self.cgi_env = ctx.cgi_env;
+        self.output = ctx.output;
+        self.request = Some(ctx.request);
+        self.response = Some(ctx.response);
+        self.session = Some(ctx.session);
+        self.websocket = ctx.websocket;
+        self.nested = true;
+    }
+
+    // Run a CLASS file's top level in an inner VM and wrap its globals as an instance
+    fn instantiate_class(&mut self, prog: BCProgram, path: String) -> Result<basil_bytecode::ObjectRef> {
+        let web = self.web_context();
+        let mut inner = VM::new(prog.clone());
+        inner.set_script_path(path);
+        if let Some(ctx) = &web { inner.adopt_web_context(ctx.clone()); }
+        inner.run()?;
+        let inst = ClassInstance::new(prog.globals, inner.globals.clone(), web);
+        Ok(Rc::new(RefCell::new(inst)))
+    }
+
+    fn request_data(&mut self) -> Rc<web::RequestData> {
+        if self.request.is_none() {
+            let body = self.load_request_body();
+            self.request = Some(Rc::new(web::RequestData::new(|n| self.cgi_var(n), self.http_vars(), body)));
+        }
+        self.request.clone().expect("request parsed")
+    }
+
+    // Recorded only (not written) unless enable_cgi_headers was called
+    fn response_state(&mut self) -> Rc<RefCell<web::ResponseState>> {
+        self.response.get_or_insert_with(|| Rc::new(RefCell::new(web::ResponseState::new(None)))).clone()
+    }
+
+    // Write the pending CGI header block once, before any body bytes
+    fn send_headers(&mut self) {
+        let Some(state) = &self.response else { return };
+        let block = {
+            let mut st = state.borrow_mut();
+            if !st.emit || st.sent { return; }
+            st.sent = true;
+            st.header_block()
+        };
+        match &self.output {
+            Some(out) => { let _ = out.borrow_mut().write_all(block.as_bytes()); }
+            None => { print!("{}", block); let _ = io::stdout().flush(); }
+        }
+    }
+
+    // Test mode notes (mocked inputs, --trace comments) go with the program's output
+    fn test_log(&self, msg: &str) {
+        match &self.output {
+            Some(out) => { let _ = writeln!(out.borrow_mut(), "{}", msg); }
+            None => println!("{}", msg),
+        }
+    }
+
+    /// Save the answers typed during this run to a `.inputs` file (`basilc run --record`).
+    pub fn record_inputs(&mut self, recorder: input::Recorder) { self.recorder = Some(recorder); }
+
+    // INPUT$/INPUTC$ prompts go where PRINT goes
+    fn write_prompt(&mut self, prompt: &str) {
+        match &self.output {
+            Some(out) => { let _ = write!(out.borrow_mut(), "{}", prompt); }
+            None => { print!("{}", prompt); let _ = io::stdout().flush(); }
+        }
+        self.note_output(prompt);
+    }
+
+    // Remember recent output as the prompt for the next input (only needed when inputs are scripted or recorded)
+    fn note_output(&mut self, text: &str) {
+        if self.mock.is_none() && self.recorder.is_none() { return; }
+        self.prompt_text.push_str(text);
+        if self.prompt_text.len() > 4096 {
+            let mut cut = self.prompt_text.len() - 2048;
+            while !self.prompt_text.is_char_boundary(cut) { cut += 1; }
+            self.prompt_text.drain(..cut);
+        }
+    }
+
+    fn record_line(&mut self, answer: &str) -> Result<()> {
+        let prompt = std::mem::take(&mut self.prompt_text);
+        match self.recorder.as_mut() { Some(r) => r.line(&prompt, answer), None => Ok(()) }
+    }
+
+    fn record_key(&mut self, key: Option<char>) -> Result<()> {
+        let prompt = std::mem::take(&mut self.prompt_text);
+        match self.recorder.as_mut() { Some(r) => r.key(&prompt, key), None => Ok(()) }
+    }
+
     // Provide script path so CLASS() can resolve relative file names
     pub fn set_script_path(&mut self, p: String) { self.script_path = Some(p); }
 
@@ -466,38 +613,6 @@ impl VM {
     fn cur(&mut self) -> &mut Frame { self.frames.last_mut().expect("no frame") }
 
     // --- CGI param helpers ---
-    fn url_decode_form(&self, s: &str) -> String {
-        let bytes = s.as_bytes();
-        let mut out = String::with_capacity(bytes.len());
-        let mut i = 0usize;
-        while i < bytes.len() {
-            match bytes[i] {
-                b'+' => { out.push(' '); i += 1; }
-                b'%' if i + 2 < bytes.len() => {
-                    let h1 = bytes[i+1] as char; let h2 = bytes[i+2] as char;
-                    let hex = [h1, h2];
-                    let hv = u8::from_str_radix(&hex.iter().collect::<String>(), 16).ok();
-                    if let Some(b) = hv { out.push(b as char); i += 3; } else { out.push('%'); i += 1; }
-                }
-                b => { out.push(b as char); i += 1; }
-            }
-        }
-        out
-    }
-    fn url_encode_form(&self, s: &str) -> String {
-        let mut out = String::with_capacity(s.len());
-        for &b in s.as_bytes() {
-            match b {
-                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
-                b' ' => out.push('+'),
-                _ => {
-                    out.push('%');
-                    out.push_str(&format!("{:02X}", b));
-                }
-            }
-        }
-        out
-    }
 
     // --- Struct registry helpers ---
     fn struct_reg(&mut self, name: &str, spec: &str) -> Result<()> {
@@ -643,105 +758,88 @@ impl VM {
             let mut it = part.splitn(2, '=');
             let k = it.next().unwrap_or("");
             let v = it.next().unwrap_or("");
-            let kd = self.url_decode_form(k);
-            let vd = self.url_decode_form(v);
+            let kd = text::form_decode(k);
+            let vd = text::form_decode(v);
             out.push(format!("{}={}", kd, vd));
         }
         out
     }
     fn ensure_get_params(&mut self) {
         if self.get_params_cache.is_none() {
-            let q = env::var("QUERY_STRING").unwrap_or_default();
+            let q = self.cgi_var("QUERY_STRING");
             let v = self.parse_pairs(&q);
             self.get_params_cache = Some(v);
         }
     }
     fn ensure_post_params(&mut self) {
         if self.post_params_cache.is_some() { return; }
-        let clen: usize = env::var("CONTENT_LENGTH").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
-        if clen == 0 { self.post_params_cache = Some(Vec::new()); return; }
-        let ctype = env::var("CONTENT_TYPE").unwrap_or_default();
-        if !ctype.to_ascii_lowercase().starts_with("application/x-www-form-urlencoded") {
-            // unsupported type for now
-            self.post_params_cache = Some(Vec::new());
-            return;
+        let body = self.load_request_body();
+        let (fields, _) = web::body_params(&self.cgi_var("CONTENT_TYPE"), &body);
+        self.post_params_cache = Some(fields.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect());
+    }
+    // The request body: the in-process one, or CONTENT_LENGTH bytes of stdin (read once)
+    fn load_request_body(&mut self) -> Vec<u8> {
+        if self.request_body.is_none() {
+            let clen: usize = self.cgi_var("CONTENT_LENGTH").parse().unwrap_or(0);
+            let mut body = Vec::with_capacity(clen);
+            if clen > 0 { let _ = io::stdin().take(clen as u64).read_to_end(&mut body); }
+            self.request_body = Some(body);
         }
-        let mut body = Vec::with_capacity(clen);
-        let _ = io::stdin().take(clen as u64).read_to_end(&mut body);
-        let s = String::from_utf8_lossy(&body).to_string();
-        let v = self.parse_pairs(&s);
-        self.post_params_cache = Some(v);
+        self.request_body.clone().unwrap_or_default()
     }
-    fn make_string_array(vals: Vec<String>) -> Value {
-        use std::cell::RefCell;
-        let dims = vec![vals.len()];
-        let data: Vec<Value> = vals.into_iter().map(Value::Str).collect();
-        let arr = Rc::new(ArrayObj { elem: ElemType::Str, dims, data: RefCell::new(data) });
-        Value::Array(arr)
+    // All HTTP_* request header variables
+    fn http_vars(&self) -> Vec<(String, String)> {
+        let all: Vec<(String, String)> = match &self.cgi_env {
+            Some(vars) => vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            None => env::vars().collect(),
+        };
+        all.into_iter().filter(|(k, _)| k.starts_with("HTTP_")).collect()
     }
-
-    fn fh_get_mut(&mut self, h: i64) -> Result<&mut FileHandleEntry> {
-        if !self.file_table.contains_key(&h) {
-            let mut keys: Vec<i64> = self.file_table.keys().copied().collect();
-            keys.sort();
-            return Err(BasilError(format!("InvalidHandle (wanted {}, have {:?})", h, keys)));
+    // CGI variable from the in-process request if one is set, else from the environment
+    fn cgi_var(&self, name: &str) -> String {
+        match &self.cgi_env {
+            Some(vars) => vars.get(name).cloned().unwrap_or_default(),
+            None => env::var(name).unwrap_or_default(),
         }
-        Ok(self.file_table.get_mut(&h).unwrap())
     }
+    fn to_i64(&self, v: &Value) -> Result<i64> { basil_builtins::to_i64(v) }
 
-    fn fh_close(&mut self, h: i64) -> Result<()> {
-        if let Some(mut e) = self.file_table.remove(&h) {
-            e.file.flush().map_err(|er| BasilError(format!("FCLOSE flush error: {}", er)))?;
+    pub fn run(&mut self) -> Result<()> {
+        self.seed_web_objects();
+        self.exec()?;
+        if self.nested { return Ok(()); }
+        if !self.suspended && self.exit_code.is_none() {
+            if self.routing { self.dispatch_route()?; }
+            self.websocket_loop()?;
         }
+        if let Some(session) = &self.session { session.borrow_mut().finish()?; }
+        // A page that printed nothing still sends its headers
+        self.send_headers();
         Ok(())
     }
 
-    fn fh_close_owner_depth(&mut self, depth: usize) {
-        let keys: Vec<i64> = self.file_table.iter().filter_map(|(k,e)| if e.owner_depth == depth { Some(*k) } else { None }).collect();
-        for k in keys { let _ = self.fh_close(k); }
-    }
-
-    fn to_i64(&self, v: &Value) -> Result<i64> {
-        match v {
-            Value::Int(i) => Ok(*i),
-            Value::Num(n) => Ok(n.trunc() as i64),
-            other => Err(BasilError(format!("expected numeric value, got {}", self.type_of(other)))),
+    // Leave the frames a RAISE passes through and continue at the innermost live TRY handler.
+    // Handlers left behind by a FUNC that returned from inside its TRY are dropped on the way.
+    fn unwind_to_handler(&mut self) -> bool {
+        while self._handlers.last().is_some_and(|h| h.depth > self.frames.len()) { self._handlers.pop(); }
+        let Some(h) = self._handlers.last() else { return false };
+        let (target, depth, stack_len, call_lines) = (h.handler_ip, h.depth, h.stack_len, h.call_lines);
+        while self.frames.len() > depth {
+            self.frames.pop();
+            if let Some(p) = self.profiler.as_mut() { p.leave(); }
         }
+        self.stack.truncate(stack_len);
+        // Back on the line of the outermost call that was abandoned
+        if let Some(&(_, line)) = self.call_lines.get(call_lines) { self.current_line = line; }
+        self.call_lines.truncate(call_lines);
+        self.cur().ip = target;
+        true
     }
 
-    fn glob_match_simple(&self, pat: &str, name: &str) -> bool {
-        #[allow(clippy::collapsible_else_if)]
-        fn inner(p: &[u8], s: &[u8], case_insens: bool) -> bool {
-            if p.is_empty() { return s.is_empty(); }
-            match p[0] {
-                b'*' => {
-                    let mut i = 0usize;
-                    while i <= s.len() {
-                        if inner(&p[1..], &s[i..], case_insens) { return true; }
-                        i += 1;
-                    }
-                    false
-                }
-                b'?' => {
-                    if s.is_empty() { false } else { inner(&p[1..], &s[1..], case_insens) }
-                }
-                c => {
-                    if s.is_empty() { return false; }
-                    let mut pc = c;
-                    let mut sc = s[0];
-                    if case_insens { pc = (pc as char).to_ascii_lowercase() as u8; sc = (sc as char).to_ascii_lowercase() as u8; }
-                    if pc != sc { return false; }
-                    inner(&p[1..], &s[1..], case_insens)
-                }
-            }
-        }
-        let case_insens = cfg!(windows);
-        inner(pat.as_bytes(), name.as_bytes(), case_insens)
-    }
-
-    pub fn run(&mut self) -> Result<()> {
+    fn exec(&mut self) -> Result<()> {
         if let Some(dbg) = &self.debugger { dbg.emit(debug::DebugEvent::Started); }
         loop {
+            if let Some(p) = self.profiler.as_mut() { p.tick(); }
             let op = self.read_op()?;
             match op {
                 Op::Const => {
@@ -815,7 +913,12 @@ impl VM {
                 Op::JumpIfFalse => {
                     let off = self.read_u16()? as usize;
                     let cond = self.pop()?;
-                    if !is_truthy(&cond) { self.cur().ip += off; }
+                    let truthy = is_truthy(&cond);
+                    if let Some(c) = self.coverage.as_mut() {
+                        let f = self.frames.last().expect("frame");
+                        c.branch(&f.chunk, f.ip - 3, !truthy);
+                    }
+                    if !truthy { self.cur().ip += off; }
                 }
                 Op::JumpBack => {
                     let off = self.read_u16()? as usize;
@@ -848,7 +951,8 @@ impl VM {
                     let handler_off = self.read_u16()? as usize;
                     let _finally_off = self.read_u16()? as usize;
                     let target_ip = self.cur().ip + handler_off;
-                    self._handlers.push(HandlerEntry { handler_ip: target_ip });
+                    let entry = HandlerEntry { handler_ip: target_ip, depth: self.frames.len(), stack_len: self.stack.len(), call_lines: self.call_lines.len() };
+                    self._handlers.push(entry);
                 }
                 Op::TryPop => {
                     let _ = self._handlers.pop();
@@ -859,12 +963,10 @@ impl VM {
                     // Pop message, convert to string, then transfer to nearest handler or abort
                     let msg_v = self.pop()?;
                     let msg = format!("{}", msg_v);
-                    if let Some(h) = self._handlers.last() {
+                    if self.unwind_to_handler() {
                         // record message and jump to handler; also make it available on stack
                         self.current_exception = Some(msg.clone());
                         self.stack.push(Value::Str(msg));
-                        let target = h.handler_ip;
-                        self.cur().ip = target;
                     } else {
                         return Err(BasilError(msg));
                     }
@@ -874,17 +976,17 @@ impl VM {
                     let msg = match self.current_exception.clone() { Some(m) => m, None => return Err(BasilError("Reraise without active exception".into())) };
                     // Pop current handler if any
                     let _ = self._handlers.pop();
-                    if let Some(h) = self._handlers.last() {
+                    if self.unwind_to_handler() {
                         self.stack.push(Value::Str(msg));
-                        let target = h.handler_ip;
-                        self.cur().ip = target;
                     } else {
                         return Err(BasilError(msg));
                     }
                 }
                 Op::Stop => {
                     if self.test_mode {
-                        std::process::exit(0);
+                        // Let the test runner flush coverage before it exits
+                        self.exit_code = Some(0);
+                        return Ok(());
                     } else {
                         self.suspended = true;
                         return Ok(());
@@ -901,8 +1003,10 @@ impl VM {
                             if f.arity as usize != argc {
                                 return Err(BasilError(format!("arity mismatch: expected {}, got {}", f.arity, argc)));
                             }
+                            if let Some(p) = self.profiler.as_mut() { p.enter(f.name.as_deref()); }
                             let frame = Frame { chunk: f.chunk.clone(), ip: 0, base };
                             self.frames.push(frame);
+                            self.call_lines.push((self.frames.len(), self.current_line));
                         }
                         _ => return Err(BasilError("CALL target is not a function".into())),
                     }
@@ -911,17 +1015,19 @@ impl VM {
                 Op::SetLine => {
                     let line = self.read_u16()? as u32;
                     self.current_line = line;
+                    if let Some(p) = self.profiler.as_mut() { p.line(line); }
+                    if let Some(c) = self.coverage.as_mut() { c.line(line); }
                     if self.test_mode {
                         if let Some(map) = &self.comments_map {
                             if let Some(list) = map.get(&line) {
-                                for text in list { println!("COMMENT: {}", text); }
+                                for text in list { self.test_log(&format!("COMMENT: {}", text)); }
                             }
                         }
                     }
                     if let Some(dbg) = &self.debugger {
-                        let file = self.script_path.clone().unwrap_or_else(|| "<unknown>".into());
+                        let (file, line) = self.debug_location();
                         let cur_depth = self.frames.len();
-                        if dbg.check_pause_point(&file, line as usize, cur_depth) {
+                        if dbg.check_pause_point(&file, line, cur_depth) {
                             // Wait until resumed
                             loop {
                                 if let Ok(st) = dbg.state.lock() { if !st.paused { break; } }
@@ -935,16 +1041,31 @@ impl VM {
                     let retv = self.pop().unwrap_or(Value::Null);
                     let depth = self.frames.len();
                     let frame = self.frames.pop().ok_or_else(|| BasilError("RET with no frame".into()))?;
+                    // Back on the caller's line, so an error later in its statement reports that line
+                    while self.call_lines.last().is_some_and(|&(d, _)| d > depth) { self.call_lines.pop(); }
+                    if let Some(&(d, line)) = self.call_lines.last() {
+                        if d == depth { self.current_line = line; self.call_lines.pop(); }
+                    }
+                    if let Some(p) = self.profiler.as_mut() { p.leave(); }
                     self.stack.truncate(frame.base);
                     self.stack.push(retv);
                     // auto-close any file handles opened in this frame (unless suppressed for class methods)
                     if self.close_handles_on_ret {
-                        self.fh_close_owner_depth(depth);
+                        self.file_table.close_owned_by(depth);
                     }
                     if self.frames.is_empty() { break; }
                 }
 
-                Op::Print => { let v = self.pop()?; if let Some(dbg) = &self.debugger { dbg.emit(debug::DebugEvent::Output(format!("{}", v))); } print!("{}", v); let _ = io::stdout().flush(); }
+                Op::Print => {
+                    let v = self.pop()?;
+                    if let Some(dbg) = &self.debugger { dbg.emit(debug::DebugEvent::Output(format!("{}", v))); }
+                    self.send_headers();
+                    match &self.output {
+                        Some(out) => { let _ = write!(out.borrow_mut(), "{}", v); }
+                        None => { print!("{}", v); let _ = io::stdout().flush(); }
+                    }
+                    if self.mock.is_some() || self.recorder.is_some() { self.note_output(&v.to_string()); }
+                }
                 Op::Pop   => { let _ = self.pop()?; }
                 Op::ToInt => {
                     let v = self.pop()?;
@@ -1203,7 +1324,9 @@ impl VM {
                     let target = self.pop()?;
                     match target {
                         Value::Object(rc) => {
+                            if let Some(p) = self.profiler.as_mut() { p.method(rc.borrow().type_name(), &method); }
                             let v = rc.borrow_mut().call(&method, &args)?;
+                            self.track_async(&v);
                             self.stack.push(v);
                         }
                         _ => return Err(BasilError("CALLMETHOD on non-object".into())),
@@ -1251,13 +1374,7 @@ impl VM {
                     let fname_v = self.pop()?;
                     let fname = match fname_v { Value::Str(s)=>s, other=> return Err(BasilError(format!("CLASS(filename) expects a string, got {}", self.type_of(&other)))) };
                     let (prog, resolved_path) = self.load_class_program(&fname)?;
-                    // Run top-level of class program in an inner VM to initialize globals
-                    let mut inner = VM::new(prog.clone());
-                    inner.set_script_path(resolved_path.clone());
-                    inner.run()?;
-                    let class_vals = inner.globals.clone();
-                    let inst = ClassInstance::new(prog.globals.clone(), class_vals);
-                    let rc: basil_bytecode::ObjectRef = Rc::new(std::cell::RefCell::new(inst));
+                    let rc = self.instantiate_class(prog, resolved_path)?;
                     self.stack.push(Value::Object(rc));
                 }
                 Op::GetMember => {
@@ -1339,41 +1456,14 @@ impl VM {
                 Op::Builtin => {
                     let bid = self.read_u8()? as u8;
                     let argc = self.read_u8()? as usize;
+                    if let Some(p) = self.profiler.as_mut() { p.builtin(bid); }
                     // pop args in reverse then reverse to preserve call order
                     let mut args = Vec::with_capacity(argc);
                     for _ in 0..argc { args.push(self.pop()?); }
                     args.reverse();
 
                     match bid {
-                        1 => { // LEN(arg)
-                            if argc != 1 { return Err(BasilError("LEN expects 1 argument".into())); }
-                            match &args[0] {
-                                Value::Str(s) => {
-                                    let n = s.chars().count() as i64;
-                                    self.stack.push(Value::Int(n));
-                                }
-                                Value::Array(arr_rc) => {
-                                    let arr = arr_rc.as_ref();
-                                    let mut total: usize = 1;
-                                    for d in &arr.dims { total = total.saturating_mul(*d); }
-                                    self.stack.push(Value::Int(total as i64));
-                                }
-                                Value::List(rc) => {
-                                    let n = rc.borrow().len() as i64;
-                                    self.stack.push(Value::Int(n));
-                                }
-                                Value::Dict(rc) => {
-                                    let n = rc.borrow().len() as i64;
-                                    self.stack.push(Value::Int(n));
-                                }
-                                other => {
-                                    // Fallback: coerce to string via Display and count chars
-                                    let s = format!("{}", other);
-                                    let n = s.chars().count() as i64;
-                                    self.stack.push(Value::Int(n));
-                                }
-                            }
-                        }
+                        1 => self.stack.push(text::len(&args)?), // LEN(arg)
                         160 => { // FIXSTR_ENFORCE(value, n)
                             if argc != 2 { return Err(BasilError("FIXSTR_ENFORCE expects 2 arguments (value, N)".into())); }
                             // Coerce first arg to string if not already
@@ -1438,123 +1528,34 @@ impl VM {
                             let v = self.unpack_struct_from(&buf, &tname)?;
                             self.stack.push(v);
                         }
-                        2 => { // MID$(s, start [,len]) -- start is 1-based
-                            if !(argc == 2 || argc == 3) { return Err(BasilError("MID$ expects 2 or 3 arguments".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("MID$ arg 1 must be string".into())) };
-                            // convert numeric args to i64 via truncation
-                            let start_i = match &args[1] {
-                                Value::Int(i) => *i,
-                                Value::Num(n) => n.trunc() as i64,
-                                _ => return Err(BasilError("MID$ start must be numeric".into())),
-                            };
-                            let start_idx0 = if start_i <= 1 { 0usize } else { (start_i as usize) - 1 };
-                            let mut iter = s.chars();
-                            // drop start_idx0 chars
-                            for _ in 0..start_idx0 { if iter.next().is_none() { break; } }
-                            let res = if argc == 2 {
-                                iter.collect::<String>()
-                            } else {
-                                let len_i = match &args[2] {
-                                    Value::Int(i) => *i,
-                                    Value::Num(n) => n.trunc() as i64,
-                                    _ => return Err(BasilError("MID$ length must be numeric".into())),
-                                };
-                                if len_i <= 0 { String::new() } else { iter.take(len_i as usize).collect::<String>() }
-                            };
-                            self.stack.push(Value::Str(res));
-                        }
-                        3 => { // LEFT$(s, n)
-                            if argc != 2 { return Err(BasilError("LEFT$ expects 2 arguments".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("LEFT$ arg 1 must be string".into())) };
-                            let n = match &args[1] {
-                                Value::Int(i) => *i,
-                                Value::Num(n) => n.trunc() as i64,
-                                _ => return Err(BasilError("LEFT$ count must be numeric".into())),
-                            };
-                            if n <= 0 { self.stack.push(Value::Str(String::new())); }
-                            else {
-                                let res: String = s.chars().take(n as usize).collect();
-                                self.stack.push(Value::Str(res));
-                            }
-                        }
-                        4 => { // RIGHT$(s, n)
-                            if argc != 2 { return Err(BasilError("RIGHT$ expects 2 arguments".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("RIGHT$ arg 1 must be string".into())) };
-                            let n = match &args[1] {
-                                Value::Int(i) => *i,
-                                Value::Num(n) => n.trunc() as i64,
-                                _ => return Err(BasilError("RIGHT$ count must be numeric".into())),
-                            };
-                            if n <= 0 { self.stack.push(Value::Str(String::new())); }
-                            else {
-                                let total = s.chars().count() as i64;
-                                let take_n = if n > total { total } else { n } as usize;
-                                let skip_n = (total - take_n as i64) as usize;
-                                let res: String = s.chars().skip(skip_n).take(take_n).collect();
-                                self.stack.push(Value::Str(res));
-                            }
-                        }
-                        5 => { // INSTR(hay, needle [,start]) -- returns 0-based index or 0 if not found
-                            if !(argc == 2 || argc == 3) { return Err(BasilError("INSTR expects 2 or 3 arguments".into())); }
-                            let hay = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("INSTR arg 1 must be string".into())) };
-                            let needle = match &args[1] { Value::Str(s) => s.clone(), _ => return Err(BasilError("INSTR arg 2 must be string".into())) };
-                            let start = if argc == 3 {
-                                match &args[2] { Value::Int(i) => *i, Value::Num(n) => n.trunc() as i64, _ => return Err(BasilError("INSTR start must be numeric".into())) }
-                            } else { 0 };
-                            if needle.is_empty() {
-                                // empty needle: return start (already 0-based)
-                                let start_nonneg = if start < 0 { 0 } else { start as usize };
-                                let total_chars = hay.chars().count();
-                                let idx = if start_nonneg > total_chars { total_chars } else { start_nonneg };
-                                self.stack.push(Value::Int(idx as i64));
-                            } else {
-                                // map start (char) to byte index
-                                let start_nonneg = if start < 0 { 0 } else { start as usize };
-                                let mut byte_idx = 0usize;
-                                let mut seen = 0usize;
-                                for (bidx, _) in hay.char_indices() {
-                                    if seen == start_nonneg { byte_idx = bidx; break; }
-                                    seen += 1;
-                                    byte_idx = hay.len();
-                                }
-                                if start_nonneg == 0 { byte_idx = 0; }
-                                if start_nonneg > hay.chars().count() { self.stack.push(Value::Int(0)); }
-                                else {
-                                    let slice = &hay[byte_idx..];
-                                    if let Some(rel) = slice.find(&needle) {
-                                        let abs_byte = byte_idx + rel;
-                                        // convert abs_byte to char index
-                                        let idx = hay[..abs_byte].chars().count();
-                                        self.stack.push(Value::Int(idx as i64));
-                                    } else {
-                                        self.stack.push(Value::Int(0));
-                                    }
-                                }
-                            }
-                        }
+                        2 => self.stack.push(text::mid(&args)?), // MID$(s, start [,len]) -- start is 1-based
+                        3 => self.stack.push(text::left(&args)?), // LEFT$(s, n)
+                        4 => self.stack.push(text::right(&args)?), // RIGHT$(s, n)
+                        5 => self.stack.push(text::instr(&args)?), // INSTR(hay, needle [,start]) -- returns 0-based index or 0 if not found
                         6 => { // INPUT$([prompt])
                             if !(argc == 0 || argc == 1) { return Err(BasilError("INPUT$ expects 0 or 1 argument".into())); }
                             if argc == 1 {
                                 let prompt = match &args[0] { Value::Str(s) => s.clone(), other => format!("{}", other) };
-                                print!("{}", prompt);
-                                let _ = io::stdout().flush();
+                                self.write_prompt(&prompt);
                             }
                             if self.test_mode {
                                 // enforce max inputs
                                 self.mocked_inputs += 1;
                                 if let Some(maxn) = self.max_mocked_inputs { if self.mocked_inputs > maxn { let loc = if let Some(p) = &self.script_path { if self.current_line>0 { format!(" at {}:{}", std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p), self.current_line) } else { String::new() } } else { String::new() }; return Err(BasilError(format!("Hit --max-inputs={}{}", maxn, loc))); } }
-                                let val = if let Some(mock) = &mut self.mock { mock.read_line() } else { String::new() };
+                                let prompt = std::mem::take(&mut self.prompt_text);
+                                let val = match &mut self.mock { Some(mock) => mock.read_line(&prompt)?, None => String::new() };
                                 let shown = if val.is_empty() { "<BLANK+ENTER>".to_string() } else { val.clone() };
                                 let mut msg = format!("Mock input to INPUT given as {}", shown);
                                 if self.trace {
                                     if let Some(p) = &self.script_path { if self.current_line > 0 { let fname = std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p); msg.push_str(&format!(" (at {}:{})", fname, self.current_line)); } }
                                 }
-                                println!("{}", msg);
+                                self.test_log(&msg);
                                 self.stack.push(Value::Str(val));
                             } else {
                                 let mut input = String::new();
                                 io::stdin().read_line(&mut input).map_err(|e| BasilError(format!("INPUT$ read error: {}", e)))?;
                                 while input.ends_with('\n') || input.ends_with('\r') { input.pop(); }
+                                self.record_line(&input)?;
                                 self.stack.push(Value::Str(input));
                             }
                         }
@@ -1563,29 +1564,17 @@ impl VM {
                             if self.test_mode {
                                 self.mocked_inputs += 1;
                                 if let Some(maxn) = self.max_mocked_inputs { if self.mocked_inputs > maxn { let loc = if let Some(p) = &self.script_path { if self.current_line>0 { format!(" at {}:{}", std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p), self.current_line) } else { String::new() } } else { String::new() }; return Err(BasilError(format!("Hit --max-inputs={}{}", maxn, loc))); } }
-                                let ch = if let Some(mock) = &mut self.mock { mock.read_char() } else { None };
+                                let prompt = std::mem::take(&mut self.prompt_text);
+                                let ch = match &mut self.mock { Some(mock) => mock.read_char(&prompt)?, None => None };
                                 let s = match ch { Some('\r') => "\r".to_string(), Some(c) => c.to_string(), None => String::new() };
                                 let shown = match ch { Some('\r') => "<ENTER>".to_string(), Some(c) => c.to_string(), None => String::new() };
                                 let mut msg = format!("Mock input to INKEY$ given as {}", shown);
                                 if self.trace { if let Some(p) = &self.script_path { if self.current_line>0 { let fname = std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p); msg.push_str(&format!(" (at {}:{})", fname, self.current_line)); } } }
-                                println!("{}", msg);
+                                self.test_log(&msg);
                                 self.stack.push(Value::Str(s));
                             } else {
-                                enable_raw_mode().map_err(|e| BasilError(format!("enable_raw_mode: {}", e)))?;
-                                let s = if poll(Duration::from_millis(0)).map_err(|e| BasilError(format!("poll: {}", e)))? {
-                                    match read().map_err(|e| BasilError(format!("read key: {}", e)))? {
-                                        Event::Key(KeyEvent { code, .. }) => match code {
-                                            KeyCode::Char(c) => c.to_string(),
-                                            KeyCode::Enter => "\r".to_string(),
-                                            KeyCode::Backspace => "\u{0008}".to_string(),
-                                            KeyCode::Tab => "\t".to_string(),
-                                            KeyCode::Esc => "\u{001B}".to_string(),
-                                            _ => String::new(),
-                                        },
-                                        _ => String::new(),
-                                    }
-                                } else { String::new() };
-                                let _ = disable_raw_mode();
+                                let s = keys::poll_key()?.map(keys::Key::text).unwrap_or_default();
+                                if !s.is_empty() { self.record_key(s.chars().next())?; }
                                 self.stack.push(Value::Str(s));
                             }
                         }
@@ -1594,78 +1583,34 @@ impl VM {
                             if self.test_mode {
                                 self.mocked_inputs += 1;
                                 if let Some(maxn) = self.max_mocked_inputs { if self.mocked_inputs > maxn { let loc = if let Some(p) = &self.script_path { if self.current_line>0 { format!(" at {}:{}", std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p), self.current_line) } else { String::new() } } else { String::new() }; return Err(BasilError(format!("Hit --max-inputs={}{}", maxn, loc))); } }
-                                let ch = if let Some(mock) = &mut self.mock { mock.read_char() } else { None };
+                                let prompt = std::mem::take(&mut self.prompt_text);
+                                let ch = match &mut self.mock { Some(mock) => mock.read_char(&prompt)?, None => None };
                                 let code_i: i64 = match ch { Some('\r') => 13, Some(c) => c as i64, None => 0 };
                                 let shown = match ch { Some('\r') => "<ENTER>".to_string(), Some(c) => c.to_string(), None => String::new() };
                                 let mut msg = format!("Mock input to INKEY% given as {}", shown);
                                 if self.trace { if let Some(p) = &self.script_path { if self.current_line>0 { let fname = std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p); msg.push_str(&format!(" (at {}:{})", fname, self.current_line)); } } }
-                                println!("{}", msg);
+                                self.test_log(&msg);
                                 self.stack.push(Value::Int(code_i));
                             } else {
-                                enable_raw_mode().map_err(|e| BasilError(format!("enable_raw_mode: {}", e)))?;
-                                let code_i: i64 = if poll(Duration::from_millis(0)).map_err(|e| BasilError(format!("poll: {}", e)))? {
-                                    match read().map_err(|e| BasilError(format!("read key: {}", e)))? {
-                                        Event::Key(KeyEvent { code, .. }) => {
-                                            match code {
-                                                KeyCode::Char(c) => c as i64,
-                                                KeyCode::Enter => 13,
-                                                KeyCode::Backspace => 8,
-                                                KeyCode::Tab => 9,
-                                                KeyCode::Esc => 27,
-                                                KeyCode::Up => 1000,
-                                                KeyCode::Down => 1001,
-                                                KeyCode::Left => 1002,
-                                                KeyCode::Right => 1003,
-                                                KeyCode::Home => 1004,
-                                                KeyCode::End => 1005,
-                                                KeyCode::PageUp => 1006,
-                                                KeyCode::PageDown => 1007,
-                                                KeyCode::Insert => 1008,
-                                                KeyCode::Delete => 1009,
-                                                KeyCode::F(n) => 1100 + n as i64,
-                                                _ => 0,
-                                            }
-                                        }
-                                        _ => 0,
-                                    }
-                                } else { 0 };
-                                let _ = disable_raw_mode();
+                                let code_i = keys::poll_key()?.map(keys::Key::code).unwrap_or(0);
+                                if code_i != 0 { self.record_key(char::from_u32(code_i as u32))?; }
                                 self.stack.push(Value::Int(code_i));
                             }
                         }
-                        9 => { // TYPE$(value)
-                            if argc != 1 { return Err(BasilError("TYPE$ expects 1 argument".into())); }
-                            let s = self.type_of(&args[0]);
-                            self.stack.push(Value::Str(s));
-                        }
-                        10 => { // HTML/HTML$(x)
-                            if argc != 1 { return Err(BasilError("HTML expects 1 argument".into())); }
-                            let s = format!("{}", args[0]);
-                            let mut out = String::with_capacity(s.len());
-                            for ch in s.chars() {
-                                match ch {
-                                    '&' => out.push_str("&amp;"),
-                                    '<' => out.push_str("&lt;"),
-                                    '>' => out.push_str("&gt;"),
-                                    '"' => out.push_str("&quot;"),
-                                    '\'' => out.push_str("&#39;"),
-                                    _ => out.push(ch),
-                                }
-                            }
-                            self.stack.push(Value::Str(out));
-                        },
+                        9 => self.stack.push(text::type_name(&args)?), // TYPE$(value)
+                        10 => self.stack.push(text::html(&args)?), // HTML/HTML$(x)
                         11 => { // GET$()
                             if argc != 0 { return Err(BasilError("GET$ expects 0 arguments".into())); }
                             self.ensure_get_params();
                             let vals = self.get_params_cache.clone().unwrap_or_default();
-                            let arr = VM::make_string_array(vals);
+                            let arr = basil_builtins::string_array(vals);
                             self.stack.push(arr);
                         }
                         12 => { // POST$()
                             if argc != 0 { return Err(BasilError("POST$ expects 0 arguments".into())); }
                             self.ensure_post_params();
                             let vals = self.post_params_cache.clone().unwrap_or_default();
-                            let arr = VM::make_string_array(vals);
+                            let arr = basil_builtins::string_array(vals);
                             self.stack.push(arr);
                         }
                         13 => { // REQUEST$()
@@ -1674,673 +1619,212 @@ impl VM {
                             self.ensure_post_params();
                             let mut vals = self.get_params_cache.clone().unwrap_or_default();
                             if let Some(mut p) = self.post_params_cache.clone() { vals.append(&mut p); }
-                            let arr = VM::make_string_array(vals);
+                            let arr = basil_builtins::string_array(vals);
                             self.stack.push(arr);
                         }
-                        14 => { // UCASE$(s)
-                            if argc != 1 { return Err(BasilError("UCASE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("UCASE$ arg must be string".into())) };
-                            self.stack.push(Value::Str(s.to_uppercase()));
-                        }
-                        15 => { // LCASE$(s)
-                            if argc != 1 { return Err(BasilError("LCASE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("LCASE$ arg must be string".into())) };
-                            self.stack.push(Value::Str(s.to_lowercase()));
-                        }
-                        16 => { // TRIM$(s)
-                            if argc != 1 { return Err(BasilError("TRIM$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("TRIM$ arg must be string".into())) };
-                            self.stack.push(Value::Str(s.trim().to_string()));
-                        }
-                        17 => { // CHR$(n)
-                            if argc != 1 { return Err(BasilError("CHR$ expects 1 argument".into())); }
-                            let n = match &args[0] {
-                                Value::Int(i) => *i,
-                                Value::Num(f) => f.trunc() as i64,
-                                _ => return Err(BasilError("CHR$ arg must be numeric".into())),
-                            };
-                            let out = if n < 0 || n > 0x10FFFF { String::new() } else { std::char::from_u32(n as u32).map(|c| c.to_string()).unwrap_or_default() };
-                            self.stack.push(Value::Str(out));
-                        }
-                        18 => { // ASC%(s)
-                            if argc != 1 { return Err(BasilError("ASC% expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s, _ => return Err(BasilError("ASC% arg must be string".into())) };
-                            let code: i64 = s.chars().next().map(|c| c as u32 as i64).unwrap_or(0);
-                            self.stack.push(Value::Int(code));
-                        }
+                        14 => self.stack.push(text::ucase(&args)?), // UCASE$(s)
+                        15 => self.stack.push(text::lcase(&args)?), // LCASE$(s)
+                        16 => self.stack.push(text::trim(&args)?), // TRIM$(s)
+                        17 => self.stack.push(text::chr(&args)?), // CHR$(n)
+                        18 => self.stack.push(text::asc(&args)?), // ASC%(s)
                         19 => { // INPUTC$([prompt])
                             if !(argc == 0 || argc == 1) { return Err(BasilError("INPUTC$ expects 0 or 1 argument".into())); }
                             if argc == 1 {
                                 let prompt = match &args[0] { Value::Str(s) => s.clone(), other => format!("{}", other) };
-                                print!("{}", prompt);
-                                let _ = io::stdout().flush();
+                                self.write_prompt(&prompt);
                             }
                             if self.test_mode {
                                 self.mocked_inputs += 1;
                                 if let Some(maxn) = self.max_mocked_inputs { if self.mocked_inputs > maxn { let loc = if let Some(p) = &self.script_path { if self.current_line>0 { format!(" at {}:{}", std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p), self.current_line) } else { String::new() } } else { String::new() }; return Err(BasilError(format!("Hit --max-inputs={}{}", maxn, loc))); } }
-                                let ch = if let Some(mock) = &mut self.mock { mock.read_char() } else { None };
+                                let prompt = std::mem::take(&mut self.prompt_text);
+                                let ch = match &mut self.mock { Some(mock) => mock.read_char(&prompt)?, None => None };
                                 let s = match ch { Some('\r') => String::new(), Some(c) => c.to_string(), None => String::new() };
                                 if let Some(c) = ch { if c != '\r' { print!("{}", c); let _ = io::stdout().flush(); } }
                                 let shown = match ch { Some('\r') => "<ENTER>".to_string(), Some(c) => c.to_string(), None => String::new() };
                                 let mut msg = format!("Mock input to INPUTC$ given as {}", shown);
                                 if self.trace { if let Some(p) = &self.script_path { if self.current_line>0 { let fname = std::path::Path::new(p).file_name().and_then(|s| s.to_str()).unwrap_or(p); msg.push_str(&format!(" (at {}:{})", fname, self.current_line)); } } }
-                                println!("{}", msg);
+                                self.test_log(&msg);
                                 self.stack.push(Value::Str(s));
                             } else {
-                                // Enable raw mode and ensure we only capture a single key (no echo from console)
-                                enable_raw_mode().map_err(|e| BasilError(format!("enable_raw_mode: {}", e)))?;
-                                // Drain any pending events (typeahead) to avoid consuming earlier keys
-                                loop {
-                                    match poll(Duration::from_millis(0)) {
-                                        Ok(true) => { let _ = read(); }
-                                        Ok(false) => break,
-                                        Err(e) => { let _ = disable_raw_mode(); return Err(BasilError(format!("poll: {}", e))); }
-                                    }
-                                }
-                                // Wait for the next key event (any kind). Capture only ASCII chars; others => "".
-                                let s = loop {
-                                    match read().map_err(|e| BasilError(format!("read key: {}", e)))? {
-                                        Event::Key(KeyEvent { code, .. }) => {
-                                            let out = match code {
-                                                KeyCode::Char(c) if c.is_ascii() => c.to_string(),
-                                                _ => String::new(),
-                                            };
-                                            break out;
-                                        }
-                                        _ => { /* ignore non-key events */ }
-                                    }
+                                // A single key in raw mode (no echo from the console). Capture only ASCII chars; others => "".
+                                let s = match keys::wait_key()? {
+                                    keys::Key::Char(c) if c.is_ascii() => c.to_string(),
+                                    _ => String::new(),
                                 };
                                 // Echo the captured ASCII character exactly once
                                 if !s.is_empty() { print!("{}", s); let _ = io::stdout().flush(); }
-                                let _ = disable_raw_mode();
+                                self.record_key(s.chars().next())?;
                                 self.stack.push(Value::Str(s));
                             }
                         }
-                        20 => { // ESCAPE$(s) - SQL string literal escape (single quotes doubled)
-                            if argc != 1 { return Err(BasilError("ESCAPE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.clone(), _ => return Err(BasilError("ESCAPE$ arg must be string".into())) };
-                            let out = s.replace("'", "''");
-                            self.stack.push(Value::Str(out));
-                        }
-                        21 => { // UNESCAPE$(s) - reverse SQL string literal escaping ('' -> ')
-                            if argc != 1 { return Err(BasilError("UNESCAPE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.as_str(), _ => return Err(BasilError("UNESCAPE$ arg must be string".into())) };
-                            let mut out = String::with_capacity(s.len());
-                            let mut it = s.chars().peekable();
-                            while let Some(c) = it.next() {
-                                if c == '\'' {
-                                    if let Some('\'') = it.peek().copied() { it.next(); out.push('\''); } else { out.push('\''); }
-                                } else { out.push(c); }
-                            }
-                            self.stack.push(Value::Str(out));
-                        }
-                        22 => { // URLENCODE$(s) - application/x-www-form-urlencoded encode (spaces -> '+')
-                            if argc != 1 { return Err(BasilError("URLENCODE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.as_str(), _ => return Err(BasilError("URLENCODE$ arg must be string".into())) };
-                            let out = self.url_encode_form(s);
-                            self.stack.push(Value::Str(out));
-                        }
-                        23 => { // URLDECODE$(s) - application/x-www-form-urlencoded decode ('+' -> space)
-                            if argc != 1 { return Err(BasilError("URLDECODE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s) => s.as_str(), _ => return Err(BasilError("URLDECODE$ arg must be string".into())) };
-                            let out = self.url_decode_form(s);
-                            self.stack.push(Value::Str(out));
-                        }
-                        24 => { // SLEEP(ms)
-                            if argc != 1 { return Err(BasilError("SLEEP expects 1 argument".into())); }
-                            let ms = self.to_i64(&args[0])?;
-                            let msu = if ms < 0 { 0 } else { ms as u64 };
-                            std::thread::sleep(std::time::Duration::from_millis(msu));
-                            self.stack.push(Value::Int(0));
-                        }
-                        26 => { // STRING$(n, ch$ or code%)
-                            if argc != 2 { return Err(BasilError("STRING$ expects 2 arguments".into())); }
-                            let n = self.to_i64(&args[0])?;
-                            let n = if n <= 0 { 0usize } else { (n as usize).min(1_000_000) };
-                            let unit = match &args[1] {
-                                Value::Str(s) => s.clone(),
-                                other => {
-                                    let code = self.to_i64(other)?;
-                                    let ch = std::char::from_u32((code as u32) & 0xFF).unwrap_or('\u{0000}');
-                                    ch.to_string()
-                                }
-                            };
-                            let out = if unit.is_empty() || n == 0 { String::new() } else { unit.repeat(n) };
-                            self.stack.push(Value::Str(out));
+                        20 => self.stack.push(text::escape(&args)?), // ESCAPE$(s) - SQL string literal escape (single quotes doubled)
+                        21 => self.stack.push(text::unescape(&args)?), // UNESCAPE$(s) - reverse SQL string literal escaping ('' -> ')
+                        22 => self.stack.push(text::urlencode(&args)?), // URLENCODE$(s) - application/x-www-form-urlencoded encode (spaces -> '+')
+                        23 => self.stack.push(text::urldecode(&args)?), // URLDECODE$(s) - application/x-www-form-urlencoded decode ('+' -> space)
+                        24 => self.stack.push(system::sleep(&args)?), // SLEEP(ms)
+                        28 => { // TEST_REGISTER(kind$, name$, sub$), emitted for TEST/SETUP/TEARDOWN blocks
+                            if argc != 3 { return Err(BasilError("TEST_REGISTER expects 3 arguments".into())); }
+                            self.test_register(&args)?;
+                            self.stack.push(Value::Null);
                         }
-                        40 => { // FOPEN(path$, mode$) -> fh%
-                            if argc != 2 { return Err(BasilError("FOPEN expects 2 arguments".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let mode = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            if path.contains('\u{0000}') { return Err(BasilError("FOPEN: invalid NUL in path".into())); }
-                            let m = mode.to_ascii_lowercase();
-                            let text = !m.contains('b');
-                            let plus = m.contains('+');
-                            let mut opts = OpenOptions::new();
-                            let (mut readable, mut writable) = (false, false);
-                            if m.starts_with('r') { readable = true; opts.read(true); if plus { writable = true; opts.write(true); } }
-                            else if m.starts_with('w') { writable = true; opts.write(true).create(true).truncate(true); if plus { readable = true; opts.read(true); } }
-                            else if m.starts_with('a') { writable = true; opts.append(true).create(true); if plus { readable = true; opts.read(true); opts.write(true); } }
-                            else { return Err(BasilError(format!("FOPEN: invalid mode '{}'; expected r/w/a variants", mode))); }
-                            match opts.open(&path) {
-                                Ok(file) => {
-                                    let fh = self.next_fh; self.next_fh += 1;
-                                    let entry = FileHandleEntry { file, text, readable, writable, owner_depth: self.frames.len() };
-                                    self.file_table.insert(fh, entry);
-                                    self.stack.push(Value::Int(fh));
-                                }
-                                Err(_e) => {
-                                    // Non-throwing failure: return -1 to signal open error
-                                    self.stack.push(Value::Int(-1));
-                                }
-                            }
+                        29 => { // ASSERT(condition [, message$])
+                            if !(1..=2).contains(&argc) { return Err(BasilError("ASSERT expects 1 or 2 arguments".into())); }
+                            self.assert_true(&args)?;
+                            self.stack.push(Value::Null);
                         }
-                        41 => { // FCLOSE fh%
-                            if argc != 1 { return Err(BasilError("FCLOSE expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?; let _ = self.fh_close(h); self.stack.push(Value::Bool(true));
-                        }
-                        42 => { // FFLUSH fh%
-                            if argc != 1 { return Err(BasilError("FFLUSH expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; e.file.flush().map_err(|er| BasilError(format!("FFLUSH error: {}", er)))?; self.stack.push(Value::Bool(true));
-                        }
-                        43 => { // FEOF(fh%) -> BOOL
-                            if argc != 1 { return Err(BasilError("FEOF expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; let cur = e.file.stream_position().map_err(|er| BasilError(format!("FEOF tell: {}", er)))?; let mut b=[0u8;1]; let n = e.file.read(&mut b).map_err(|er| BasilError(format!("FEOF read: {}", er)))?; if n>0 { let _ = e.file.seek(SeekFrom::Start(cur)); }
-                            self.stack.push(Value::Bool(n==0));
-                        }
-                        44 => { // FTELL&(fh%) -> LONG
-                            if argc != 1 { return Err(BasilError("FTELL& expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; let pos = e.file.stream_position().map_err(|er| BasilError(format!("FTELL: {}", er)))?; self.stack.push(Value::Int(pos as i64));
-                        }
-                        45 => { // FSEEK fh%, offset&, whence%
-                            if argc != 3 { return Err(BasilError("FSEEK expects 3 arguments".into())); }
-                            let h = self.to_i64(&args[0])?; let off = self.to_i64(&args[1])?; let wh = self.to_i64(&args[2])?; let e = self.fh_get_mut(h)?;
-                            let whence = match wh { 0 => SeekFrom::Start(off as u64), 1 => SeekFrom::Current(off), 2 => SeekFrom::End(off), _ => return Err(BasilError("FSEEK: whence must be 0,1,2".into())) };
-                            let _ = e.file.seek(whence).map_err(|er| BasilError(format!("FSEEK: {}", er)))?; self.stack.push(Value::Bool(true));
-                        }
-                        46 => { // FREAD$(fh%, n&) -> STRING
-                            if argc != 2 { return Err(BasilError("FREAD$ expects 2 arguments".into())); }
-                            let h = self.to_i64(&args[0])?; let n = self.to_i64(&args[1])?; if n <= 0 { self.stack.push(Value::Str(String::new())); }
-                            else { let e = self.fh_get_mut(h)?; if !e.readable { return Err(BasilError("FREAD$: handle not opened for reading".into())); } let mut buf = vec![0u8; n as usize]; let got = e.file.read(&mut buf).map_err(|er| BasilError(format!("FREAD$: {}", er)))?; buf.truncate(got); let s = if e.text { String::from_utf8_lossy(&buf).to_string() } else { String::from_utf8_lossy(&buf).to_string() }; self.stack.push(Value::Str(s)); }
-                        }
-                        47 => { // FREADLINE$(fh%) -> STRING
-                            if argc != 1 { return Err(BasilError("FREADLINE$ expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; if !e.readable { return Err(BasilError("FREADLINE$: handle not opened for reading".into())); }
-                            let mut out: Vec<u8> = Vec::new();
-                            let mut buf = [0u8;1];
-                            loop {
-                                let n = e.file.read(&mut buf).map_err(|er| BasilError(format!("FREADLINE$: {}", er)))?;
-                                if n == 0 { break; }
-                                if buf[0] == b'\n' { break; }
-                                out.push(buf[0]);
-                            }
-                            if out.ends_with(&[b'\r']) { out.pop(); }
-                            let s = if e.text { String::from_utf8_lossy(&out).to_string() } else { String::from_utf8_lossy(&out).to_string() };
-                            self.stack.push(Value::Str(s));
+                        30 => { // ASSERT_EQ(actual, expected [, message$])
+                            if !(2..=3).contains(&argc) { return Err(BasilError("ASSERT_EQ expects 2 or 3 arguments".into())); }
+                            self.assert_eq(&args)?;
+                            self.stack.push(Value::Null);
                         }
-                        48 => { // FWRITE fh%, s$
-                            if argc != 2 { return Err(BasilError("FWRITE expects 2 arguments".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; if !e.writable { return Err(BasilError("FWRITE: handle not opened for writing".into())); }
-                            let s = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            e.file.write_all(s.as_bytes()).map_err(|er| BasilError(format!("FWRITE: {}", er)))?; self.stack.push(Value::Bool(true));
-                        }
-                        49 => { // FWRITELN fh%, s$
-                            if argc != 2 { return Err(BasilError("FWRITELN expects 2 arguments".into())); }
-                            let h = self.to_i64(&args[0])?; let e = self.fh_get_mut(h)?; if !e.writable { return Err(BasilError("FWRITELN: handle not opened for writing".into())); }
-                            let s = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            e.file.write_all(s.as_bytes()).map_err(|er| BasilError(format!("FWRITELN: {}", er)))?; e.file.write_all(b"\n").map_err(|er| BasilError(format!("FWRITELN: {}", er)))?; self.stack.push(Value::Bool(true));
-                        }
-                        50 => { // READFILE$(path$)
-                            if argc != 1 { return Err(BasilError("READFILE$ expects 1 argument".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let data = fs::read(&path).map_err(|e| BasilError(format!("READFILE$ {}: {}", path, e)))?; let s = String::from_utf8_lossy(&data).to_string(); self.stack.push(Value::Str(s));
-                        }
-                        51 => { // WRITEFILE path$, data$
-                            if argc != 2 { return Err(BasilError("WRITEFILE expects 2 arguments".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let s = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let mut f = OpenOptions::new().write(true).create(true).truncate(true).open(&path).map_err(|e| BasilError(format!("WRITEFILE {}: {}", path, e)))?; f.write_all(s.as_bytes()).map_err(|e| BasilError(format!("WRITEFILE {}: {}", path, e)))?; f.flush().ok(); self.stack.push(Value::Null);
-                        }
-                        52 => { // APPENDFILE path$, data$
-                            if argc != 2 { return Err(BasilError("APPENDFILE expects 2 arguments".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let s = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let mut f = OpenOptions::new().write(true).create(true).append(true).open(&path).map_err(|e| BasilError(format!("APPENDFILE {}: {}", path, e)))?; f.write_all(s.as_bytes()).map_err(|e| BasilError(format!("APPENDFILE {}: {}", path, e)))?; f.flush().ok(); self.stack.push(Value::Null);
-                        }
-                        53 => { // COPY src$, dst$
-                            if argc != 2 { return Err(BasilError("COPY expects 2 arguments".into())); }
-                            let src = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let dst = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let _ = fs::copy(&src, &dst).map_err(|e| BasilError(format!("COPY {} -> {}: {}", src, dst, e)))?; self.stack.push(Value::Null);
-                        }
-                        54 => { // MOVE src$, dst$
-                            if argc != 2 { return Err(BasilError("MOVE expects 2 arguments".into())); }
-                            let src = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let dst = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            fs::rename(&src, &dst).map_err(|e| BasilError(format!("MOVE {} -> {}: {}", src, dst, e)))?; self.stack.push(Value::Null);
-                        }
-                        55 => { // RENAME path$, newname$
-                            if argc != 2 { return Err(BasilError("RENAME expects 2 arguments".into())); }
-                            let src = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let newname = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let p = Path::new(&src); let dir = p.parent().unwrap_or(Path::new(".")); let dst = dir.join(newname);
-                            fs::rename(&src, &dst).map_err(|e| BasilError(format!("RENAME {} -> {}: {}", src, dst.display(), e)))?; self.stack.push(Value::Null);
-                        }
-                        56 => { // DELETE path$
-                            if argc != 1 { return Err(BasilError("DELETE expects 1 argument".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            fs::remove_file(&path).map_err(|e| BasilError(format!("DELETE {}: {}", path, e)))?; self.stack.push(Value::Null);
-                        }
-                        57 => { // DIR$(pattern$) -> STRING[]
-                            if argc != 1 { return Err(BasilError("DIR$ expects 1 argument".into())); }
-                            let patt = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let p = Path::new(&patt);
-                            let (dir, patstr): (PathBuf, String) = if p.components().count() > 1 {
-                                (p.parent().unwrap_or(Path::new(".")).to_path_buf(), p.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string())
-                            } else { (PathBuf::from("."), patt.clone()) };
-                            let mut names: Vec<String> = Vec::new();
-                            for ent in fs::read_dir(&dir).map_err(|e| BasilError(format!("DIR$: {}: {}", dir.display(), e)))? {
-                                let ent = ent.map_err(|e| BasilError(format!("DIR$: {}", e)))?;
-                                let md = ent.metadata().map_err(|e| BasilError(format!("DIR$: {}", e)))?;
-                                if !md.is_file() { continue; }
-                                let name = ent.file_name().to_string_lossy().to_string();
-                                if self.glob_match_simple(&patstr, &name) { names.push(name); }
-                            }
-                            names.sort();
-                            self.stack.push(VM::make_string_array(names));
+                        31 => { // ASSERT_RAISES(expr [, expected$])
+                            if !(1..=2).contains(&argc) { return Err(BasilError("ASSERT_RAISES expects 1 or 2 arguments".into())); }
+                            self.assert_raises(&args)?;
+                            self.stack.push(Value::Null);
                         }
+                        27 => { // SSE_SEND(data [, event$ [, id$]]) -> FALSE once the client is gone
+                            if !(1..=3).contains(&argc) { return Err(BasilError("SSE_SEND expects 1 to 3 arguments".into())); }
+                            let sent = self.sse_send(&args)?;
+                            self.stack.push(Value::Bool(sent));
+                        }
+                        26 => self.stack.push(text::string_of(&args)?), // STRING$(n, ch$ or code%)
+                        40 => { // FOPEN(path$, mode$) -> fh%
+                            let depth = self.frames.len();
+                            let fh = self.file_table.fopen(&args, depth)?;
+                            self.stack.push(fh);
+                        }
+                        41 => self.stack.push(self.file_table.fclose(&args)?), // FCLOSE fh%
+                        42 => self.stack.push(self.file_table.fflush(&args)?), // FFLUSH fh%
+                        43 => self.stack.push(self.file_table.feof(&args)?), // FEOF(fh%) -> BOOL
+                        44 => self.stack.push(self.file_table.ftell(&args)?), // FTELL&(fh%) -> LONG
+                        45 => self.stack.push(self.file_table.fseek(&args)?), // FSEEK fh%, offset&, whence%
+                        46 => self.stack.push(self.file_table.fread(&args)?), // FREAD$(fh%, n&) -> STRING
+                        47 => self.stack.push(self.file_table.freadline(&args)?), // FREADLINE$(fh%) -> STRING
+                        48 => self.stack.push(self.file_table.fwrite(&args, false)?), // FWRITE fh%, s$
+                        49 => self.stack.push(self.file_table.fwrite(&args, true)?), // FWRITELN fh%, s$
+                        50 => self.stack.push(files::readfile(&args)?), // READFILE$(path$)
+                        51 => self.stack.push(files::writefile(&args)?), // WRITEFILE path$, data$
+                        52 => self.stack.push(files::appendfile(&args)?), // APPENDFILE path$, data$
+                        53 => self.stack.push(files::copy(&args)?), // COPY src$, dst$
+                        54 => self.stack.push(files::move_file(&args)?), // MOVE src$, dst$
+                        55 => self.stack.push(files::rename(&args)?), // RENAME path$, newname$
+                        56 => self.stack.push(files::delete(&args)?), // DELETE path$
+                        57 => self.stack.push(files::dir(&args)?), // DIR$(pattern$) -> STRING[]
                         58 => { // ENV$(name$)
                             if argc != 1 { return Err(BasilError("ENV$ expects 1 argument".into())); }
                             let name = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let val = env::var(&name).unwrap_or_default();
+                            // In-process requests see their CGI variables first, then the server's environment
+                            let val = self.cgi_env.as_ref().and_then(|vars| vars.get(&name).cloned())
+                                .unwrap_or_else(|| env::var(&name).unwrap_or_default());
                             self.stack.push(Value::Str(val));
                         }
-                        59 => { // SETENV/EXPORTENV name$, value, exportFlag
-                            if argc != 3 { return Err(BasilError("SETENV expects 3 arguments (name$, value, exportFlag)".into())); }
-                            let name = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let value_str = match &args[1] {
-                                Value::Str(s) => s.clone(),
-                                Value::Int(i) => i.to_string(),
-                                Value::Num(n) => n.to_string(),
-                                Value::Bool(b) => b.to_string(),
-                                other => format!("{}", other),
-                            };
-                            let export = match &args[2] {
-                                Value::Bool(b) => *b,
-                                Value::Int(i) => *i != 0,
-                                Value::Num(n) => *n != 0.0,
-                                _ => false,
-                            };
-                            env::set_var(&name, &value_str);
-                            let mut ok = true;
-                            if export {
-                                #[cfg(windows)]
-                                {
-                                    use std::process::Command;
-                                    let status = Command::new("cmd").args(["/C", "setx", &name, &value_str]).status();
-                                    ok = status.map(|s| s.success()).unwrap_or(false);
-                                }
-                                #[cfg(not(windows))]
-                                {
-                                    // Cannot export to parent shell from child; consider process-local set sufficient
-                                    ok = true;
-                                }
-                            }
-                            self.stack.push(Value::Bool(ok));
-                        }
-                        60 => { // SHELL(cmd$) -> exit code
-                            if argc != 1 { return Err(BasilError("SHELL expects 1 argument".into())); }
-                            let cmd = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            #[cfg(windows)]
-                            let status = std::process::Command::new("cmd").args(["/C", &cmd]).status();
-                            #[cfg(not(windows))]
-                            let status = std::process::Command::new("sh").args(["-c", &cmd]).status();
-                            let code_i: i64 = match status {
-                                Ok(st) => st.code().unwrap_or(-1) as i64,
-                                Err(_) => -1,
-                            };
-                            self.stack.push(Value::Int(code_i));
-                        }
+                        59 => self.stack.push(system::setenv(&args)?), // SETENV/EXPORTENV name$, value, exportFlag
+                        60 => self.stack.push(system::shell(&args)?), // SHELL(cmd$) -> exit code
                         61 => { // EXIT(code)
                             if argc != 1 { return Err(BasilError("EXIT expects 1 argument".into())); }
                             let code = self.to_i64(&args[0])? as i32;
+                            // Test runs and in-process web requests must not take the host process down
+                            if self.test_mode || self.cgi_env.is_some() { self.exit_code = Some(code); return Ok(()); }
+                            self.send_headers();
                             std::process::exit(code);
                         }
-                        62 => { // MKDIRS%(path$) -> Int (1=ok,0=fail)
-                            if argc != 1 { return Err(BasilError("MKDIRS% expects 1 argument".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            match fs::create_dir_all(&path) {
-                                Ok(_) => self.stack.push(Value::Int(1)),
-                                Err(_e) => self.stack.push(Value::Int(0)),
-                            }
+                        62 => self.stack.push(system::mkdirs(&args)?), // MKDIRS%(path$) -> Int (1=ok,0=fail)
+                        63 => self.stack.push(system::loadenv(&args)?), // LOADENV%(filename$?) -> Int (1=ok,0=fail)
+                        64 => { // SPAWN(func, args...) -> TASK@
+                            if argc < 1 { return Err(BasilError("SPAWN expects a function".into())); }
+                            let task = self.spawn_task(&args[0], &args[1..])?;
+                            self.stack.push(task);
+                        }
+                        65 => { // WAIT/AWAIT(task@ | promise@ | list [, timeoutMs]) -> result(s)
+                            if argc != 1 && argc != 2 { return Err(BasilError("WAIT/AWAIT expects 1 or 2 arguments".into())); }
+                            let timeout = tasks::timeout_arg(args.get(1))?;
+                            let v = tasks::wait_value(&args[0], timeout)?;
+                            self.stack.push(v);
                         }
-                        63 => { // LOADENV%(filename$?) -> Int (1=ok,0=fail)
-                            if !(argc == 0 || argc == 1) { return Err(BasilError("LOADENV% expects 0 or 1 argument".into())); }
-                            let default_name = ".env".to_string();
-                            let file = if argc == 0 {
-                                default_name
-                            } else {
-                                let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                                let t = s.trim();
-                                if t.is_empty() { ".env".to_string() } else { t.to_string() }
-                            };
-                            match fs::read_to_string(&file) {
-                                Ok(contents) => {
-                                    for (i, line) in contents.lines().enumerate() {
-                                        let trimmed = line.trim();
-                                        if trimmed.is_empty() { continue; }
-                                        if trimmed.starts_with('#') || trimmed.starts_with(';') { continue; }
-                                        match trimmed.find('=') {
-                                            Some(eq) => {
-                                                let key = trimmed[..eq].trim();
-                                                let val_raw = trimmed[eq+1..].trim();
-                                                if key.is_empty() {
-                                                    eprintln!("warning: LOADENV% {}:{}: missing key before '='", file, i + 1);
-                                                    continue;
-                                                }
-                                                let unquoted = if (val_raw.starts_with('"') && val_raw.ends_with('"') && val_raw.len() >= 2) ||
-                                                                (val_raw.starts_with('\'') && val_raw.ends_with('\'') && val_raw.len() >= 2) {
-                                                    val_raw[1..val_raw.len()-1].to_string()
-                                                } else { val_raw.to_string() };
-                                                env::set_var(key, unquoted);
-                                            }
-                                            None => {
-                                                eprintln!("warning: LOADENV% {}:{}: invalid line (expected name=value or comment)", file, i + 1);
-                                            }
-                                        }
-                                    }
-                                    self.stack.push(Value::Int(1));
-                                }
-                                Err(e) => {
-                                    eprintln!("warning: LOADENV% could not read {}: {}", file, e);
-                                    self.stack.push(Value::Int(0));
+                        67 => { // ASYNC_CALL(func, args...) -> TASK@, joined at program end if never awaited
+                            if argc < 1 { return Err(BasilError("ASYNC call expects a function".into())); }
+                            let task = self.spawn_task(&args[0], &args[1..])?;
+                            self.track_async(&task);
+                            self.stack.push(task);
+                        }
+                        66 => { // PARALLEL_EACH(func, enumerable) — backs PARALLEL FOR EACH
+                            if argc != 2 { return Err(BasilError("PARALLEL_EACH expects 2 arguments".into())); }
+                            let items: Vec<Value> = match &args[1] {
+                                Value::Array(arr) => arr.data.borrow().clone(),
+                                Value::List(rc) => rc.borrow().clone(),
+                                Value::Dict(rc) => rc.borrow().keys().map(|k| Value::Str(k.clone())).collect(),
+                                other => {
+                                    let ty = self.type_of(other);
+                                    return Err(BasilError(format!("PARALLEL FOR EACH expects an array, list or dict after IN (got TYPE={}).", ty)));
                                 }
-                            }
+                            };
+                            self.parallel_each(&args[0], items)?;
+                            self.stack.push(Value::Null);
                         }
                         #[cfg(feature = "obj-base64")]
-                        90 => { // BASE64_ENCODE$(text$)
-                            if argc != 1 { return Err(BasilError("BASE64_ENCODE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let encoded = general_purpose::STANDARD.encode(s.as_bytes());
-                            self.stack.push(Value::Str(encoded));
-                        }
+                        90 => self.stack.push(formats::base64_encode(&args)?), // BASE64_ENCODE$(text$)
                         #[cfg(feature = "obj-base64")]
-                        91 => { // BASE64_DECODE$(text$)
-                            if argc != 1 { return Err(BasilError("BASE64_DECODE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            match general_purpose::STANDARD.decode(s) {
-                                Ok(bytes) => match String::from_utf8(bytes) {
-                                    Ok(txt) => self.stack.push(Value::Str(txt)),
-                                    Err(_) => return Err(BasilError("BASE64_DECODE$: invalid UTF-8 in decoded data".into())),
-                                },
-                                Err(_) => return Err(BasilError("BASE64_DECODE$: invalid Base64 string".into())),
-                            }
-                        }
+                        91 => self.stack.push(formats::base64_decode(&args)?), // BASE64_DECODE$(text$)
                         #[cfg(feature = "obj-zip")]
-                        120 => { // ZIP_EXTRACT_ALL(zip_path$, dest_dir$)
-                            if argc != 2 { return Err(BasilError("ZIP_EXTRACT_ALL expects 2 arguments".into())); }
-                            let zip_path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let dest_dir = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            zip_utils::zip_extract_all(&zip_path, &dest_dir)?;
-                            self.stack.push(Value::Str(String::new()));
-                        }
+                        120 => self.stack.push(ext::zip_extract_all(&args)?), // ZIP_EXTRACT_ALL(zip_path$, dest_dir$)
                         #[cfg(feature = "obj-zip")]
-                        121 => { // ZIP_COMPRESS_FILE(src_path$, zip_path$, entry_name$)
-                            if !(argc == 2 || argc == 3) { return Err(BasilError("ZIP_COMPRESS_FILE expects 2 or 3 arguments".into())); }
-                            let src_path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let zip_path = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let entry_opt: Option<String> = if argc == 3 { Some(match &args[2] { Value::Str(s)=>s.clone(), other=>format!("{}", other) }) } else { None };
-                            zip_utils::zip_compress_file(&src_path, &zip_path, entry_opt.as_deref())?;
-                            self.stack.push(Value::Str(String::new()));
-                        }
+                        121 => self.stack.push(ext::zip_compress_file(&args)?), // ZIP_COMPRESS_FILE(src_path$, zip_path$, entry_name$)
                         #[cfg(feature = "obj-zip")]
-                        122 => { // ZIP_COMPRESS_DIR(src_dir$, zip_path$)
-                            if argc != 2 { return Err(BasilError("ZIP_COMPRESS_DIR expects 2 arguments".into())); }
-                            let src_dir = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let zip_path = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            zip_utils::zip_compress_dir(&src_dir, &zip_path)?;
-                            self.stack.push(Value::Str(String::new()));
-                        }
+                        122 => self.stack.push(ext::zip_compress_dir(&args)?), // ZIP_COMPRESS_DIR(src_dir$, zip_path$)
                         #[cfg(feature = "obj-zip")]
-                        123 => { // ZIP_LIST$(zip_path$)
-                            if argc != 1 { return Err(BasilError("ZIP_LIST$ expects 1 argument".into())); }
-                            let zip_path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let listing = zip_utils::zip_list(&zip_path)?;
-                            self.stack.push(Value::Str(listing));
-                        }
+                        123 => self.stack.push(ext::zip_list(&args)?), // ZIP_LIST$(zip_path$)
                         #[cfg(feature = "obj-curl")]
-                        124 => { // HTTP_GET$(url$)
-                            if argc != 1 { return Err(BasilError("HTTP_GET$ expects 1 argument".into())); }
-                            let url = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let body = curl_utils::http_get(&url)?;
-                            self.stack.push(Value::Str(body));
-                        }
+                        124 => self.stack.push(ext::http_get(&args)?), // HTTP_GET$(url$)
                         #[cfg(feature = "obj-curl")]
-                        125 => { // HTTP_POST$(url$, body$[, content_type$])
-                            if !(argc == 2 || argc == 3) { return Err(BasilError("HTTP_POST$ expects 2 or 3 arguments".into())); }
-                            let url = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let body = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let ct_opt: Option<String> = if argc == 3 { Some(match &args[2] { Value::Str(s)=>s.clone(), other=>format!("{}", other) }) } else { None };
-                            let resp = curl_utils::http_post(&url, &body, ct_opt.as_deref())?;
-                            self.stack.push(Value::Str(resp));
-                        }
+                        125 => self.stack.push(ext::http_post(&args)?), // HTTP_POST$(url$, body$[, content_type$])
                         #[cfg(feature = "obj-json")]
-                        126 => { // JSON_PARSE$(text$)
-                            if argc != 1 { return Err(BasilError("JSON_PARSE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let v: JValue = serde_json::from_str(&s)
-                                .map_err(|e| BasilError(format!("JSON_PARSE$: invalid JSON: {}", e)))?;
-                            let out = serde_json::to_string(&v)
-                                .map_err(|e| BasilError(format!("JSON_PARSE$: serialize failed: {}", e)))?;
-                            self.stack.push(Value::Str(out));
-                        }
+                        126 => self.stack.push(formats::json_parse(&args)?), // JSON_PARSE$(text$)
                         #[cfg(feature = "obj-json")]
-                        127 => { // JSON_STRINGIFY$(value)
-                            if argc != 1 { return Err(BasilError("JSON_STRINGIFY$ expects 1 argument".into())); }
-                            match &args[0] {
-                                Value::Str(s) => {
-                                    if let Ok(v) = serde_json::from_str::<JValue>(&s) {
-                                        let out = serde_json::to_string(&v)
-                                            .map_err(|e| BasilError(format!("JSON_STRINGIFY$: serialize failed: {}", e)))?;
-                                        self.stack.push(Value::Str(out));
-                                    } else {
-                                        let out = serde_json::to_string(&s)
-                                            .map_err(|e| BasilError(format!("JSON_STRINGIFY$: wrap failed: {}", e)))?;
-                                        self.stack.push(Value::Str(out));
-                                    }
-                                }
-                                other => {
-                                    let v = value_to_jvalue(other)?;
-                                    let out = serde_json::to_string(&v)
-                                        .map_err(|e| BasilError(format!("JSON_STRINGIFY$: serialize failed: {}", e)))?;
-                                    self.stack.push(Value::Str(out));
-                                }
-                            }
-                        }
+                        127 => self.stack.push(formats::json_stringify(&args)?), // JSON_STRINGIFY$(value)
                         #[cfg(feature = "obj-csv")]
-                        128 => { // CSV_PARSE$(csv_text$)
-                            if argc != 1 { return Err(BasilError("CSV_PARSE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let mut rdr = ReaderBuilder::new()
-                                .has_headers(true)
-                                .from_reader(s.as_bytes());
-                            let headers = rdr.headers()
-                                .map_err(|e| BasilError(format!("CSV_PARSE$: read headers failed: {}", e)))?
-                                .clone();
-                            let mut rows: Vec<JValue> = Vec::new();
-                            for rec in rdr.records() {
-                                let rec = rec.map_err(|e| BasilError(format!("CSV_PARSE$: read record failed: {}", e)))?;
-                                let mut obj = serde_json::Map::new();
-                                for (i, field) in rec.iter().enumerate() {
-                                    let key = headers.get(i).unwrap_or("").to_string();
-                                    obj.insert(key, JValue::String(field.to_string()));
-                                }
-                                rows.push(JValue::Object(obj));
-                            }
-                            let out = serde_json::to_string(&rows)
-                                .map_err(|e| BasilError(format!("CSV_PARSE$: serialize failed: {}", e)))?;
-                            self.stack.push(Value::Str(out));
-                        }
+                        128 => self.stack.push(formats::csv_parse(&args)?), // CSV_PARSE$(csv_text$)
                         #[cfg(feature = "obj-csv")]
-                        129 => { // CSV_WRITE$(rows_json$)
-                            if argc != 1 { return Err(BasilError("CSV_WRITE$ expects 1 argument".into())); }
-                            let s = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let rows: JValue = serde_json::from_str(&s)
-                                .map_err(|e| BasilError(format!("CSV_WRITE$: invalid JSON: {}", e)))?;
-                            let arr = rows.as_array().ok_or_else(|| BasilError("CSV_WRITE$: expected JSON array of objects".into()))?;
-                            let mut headers: Vec<String> = Vec::new();
-                            let mut seen = std::collections::HashSet::new();
-                            if let Some(first) = arr.first().and_then(|v| v.as_object()) {
-                                for k in first.keys() {
-                                    headers.push(k.clone());
-                                    seen.insert(k.clone());
-                                }
-                            }
-                            for v in arr.iter() {
-                                if let Some(obj) = v.as_object() {
-                                    for k in obj.keys() {
-                                        if !seen.contains(k) {
-                                            headers.push(k.clone());
-                                            seen.insert(k.clone());
-                                        }
-                                    }
-                                }
-                            }
-                            let mut wtr = WriterBuilder::new().from_writer(vec![]);
-                            wtr.write_record(headers.iter())
-                                .map_err(|e| BasilError(format!("CSV_WRITE$: write headers failed: {}", e)))?;
-                            for v in arr.iter() {
-                                let obj = v.as_object().ok_or_else(|| BasilError("CSV_WRITE$: array items must be objects".into()))?;
-                                let mut row: Vec<String> = Vec::with_capacity(headers.len());
-                                for h in headers.iter() {
-                                    let cell = match obj.get(h) {
-                                        Some(JValue::String(s)) => s.clone(),
-                                        Some(JValue::Number(n)) => n.to_string(),
-                                        Some(JValue::Bool(b)) => if *b { "true".to_string() } else { "false".to_string() },
-                                        Some(JValue::Null) => String::new(),
-                                        Some(other) => serde_json::to_string(other).unwrap_or_default(),
-                                        None => String::new(),
-                                    };
-                                    row.push(cell);
-                                }
-                                wtr.write_record(&row)
-                                    .map_err(|e| BasilError(format!("CSV_WRITE$: write row failed: {}", e)))?;
-                            }
-                            let bytes = wtr.into_inner().map_err(|e| BasilError(format!("CSV_WRITE$: finalize failed: {}", e)))?;
-                            let out = String::from_utf8(bytes).map_err(|e| BasilError(format!("CSV_WRITE$: utf8 failed: {}", e)))?;
-                            self.stack.push(Value::Str(out));
-                        }
+                        129 => self.stack.push(formats::csv_write(&args)?), // CSV_WRITE$(rows_json$)
                         #[cfg(feature = "obj-sqlite")]
-                        130 => { // SQLITE_OPEN%(path$)
-                            if argc != 1 { return Err(BasilError("SQLITE_OPEN% expects 1 argument".into())); }
-                            let path = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let h = sqlite_utils::sqlite_open(&path);
-                            self.stack.push(Value::Int(h));
-                        }
+                        130 => self.stack.push(ext::sqlite_open(&args)?), // SQLITE_OPEN%(path$)
                         #[cfg(feature = "obj-sqlite")]
-                        131 => { // SQLITE_CLOSE(handle%)
-                            if argc != 1 { return Err(BasilError("SQLITE_CLOSE expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?;
-                            sqlite_utils::sqlite_close(h);
-                            self.stack.push(Value::Null);
-                        }
+                        131 => self.stack.push(ext::sqlite_close(&args)?), // SQLITE_CLOSE(handle%)
                         #[cfg(feature = "obj-sqlite")]
-                        132 => { // SQLITE_EXEC%(handle%, sql$)
-                            if argc != 2 { return Err(BasilError("SQLITE_EXEC% expects 2 arguments".into())); }
-                            let h = self.to_i64(&args[0])?;
-                            let sql = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let n = sqlite_utils::sqlite_exec(h, &sql);
-                            self.stack.push(Value::Int(n));
-                        }
+                        132 => self.stack.push(ext::sqlite_exec(&args)?), // SQLITE_EXEC%(handle%, sql$)
                         #[cfg(feature = "obj-sqlite")]
-                        133 => { // SQLITE_QUERY2D$(handle%, sql$)
-                            if argc != 2 { return Err(BasilError("SQLITE_QUERY2D$ expects 2 arguments".into())); }
-                            let h = self.to_i64(&args[0])?;
-                            let sql = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other) };
-                            let v = sqlite_utils::sqlite_query2d(h, &sql)?;
-                            self.stack.push(v);
-                        }
+                        133 => self.stack.push(ext::sqlite_query2d(&args)?), // SQLITE_QUERY2D$(handle%, sql$)
                         #[cfg(feature = "obj-sqlite")]
-                        134 => { // SQLITE_LAST_INSERT_ID%(handle%)
-                            if argc != 1 { return Err(BasilError("SQLITE_LAST_INSERT_ID% expects 1 argument".into())); }
-                            let h = self.to_i64(&args[0])?;
-                            let id = sqlite_utils::sqlite_last_insert_id(h);
-                            self.stack.push(Value::Int(id));
-                        }
+                        134 => self.stack.push(ext::sqlite_last_insert_id(&args)?), // SQLITE_LAST_INSERT_ID%(handle%)
                         // --- DAW helpers ---
                         #[cfg(feature = "obj-daw")]
-                        180 => { // DAW_STOP()
-                            if argc != 0 { return Err(BasilError("DAW_STOP expects 0 arguments".into())); }
-                            daw_utils::stop();
-                            self.stack.push(Value::Str(String::new()));
-                        }
+                        180 => self.stack.push(ext::daw_stop(&args)?), // DAW_STOP()
                         #[cfg(feature = "obj-daw")]
-                        181 => { // DAW_ERR$()
-                            if argc != 0 { return Err(BasilError("DAW_ERR$ expects 0 arguments".into())); }
-                            let s = daw_utils::get_err();
-                            self.stack.push(Value::Str(s));
-                        }
+                        181 => self.stack.push(ext::daw_err(&args)?), // DAW_ERR$()
                         #[cfg(feature = "obj-daw")]
-                        182 => { // AUDIO_RECORD%(inputSubstr$, outPath$, seconds%)
-                            if argc != 3 { return Err(BasilError("AUDIO_RECORD% expects 3 arguments".into())); }
-                            let a = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let b = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let secs = self.to_i64(&args[2])?;
-                            let rc = daw_utils::audio_record(&a, &b, secs);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        182 => self.stack.push(ext::audio_record(&args)?), // AUDIO_RECORD%(inputSubstr$, outPath$, seconds%)
                         #[cfg(feature = "obj-daw")]
-                        183 => { // AUDIO_PLAY%(outputSubstr$, filePath$)
-                            if argc != 2 { return Err(BasilError("AUDIO_PLAY% expects 2 arguments".into())); }
-                            let a = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let b = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let rc = daw_utils::audio_play(&a, &b);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        183 => self.stack.push(ext::audio_play(&args)?), // AUDIO_PLAY%(outputSubstr$, filePath$)
                         #[cfg(feature = "obj-daw")]
-                        184 => { // AUDIO_MONITOR%(inputSubstr$, outputSubstr$)
-                            if argc != 2 { return Err(BasilError("AUDIO_MONITOR% expects 2 arguments".into())); }
-                            let a = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let b = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let rc = daw_utils::audio_monitor(&a, &b);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        184 => self.stack.push(ext::audio_monitor(&args)?), // AUDIO_MONITOR%(inputSubstr$, outputSubstr$)
                         #[cfg(feature = "obj-daw")]
-                        185 => { // MIDI_CAPTURE%(portSubstr$, outJsonlPath$)
-                            if argc != 2 { return Err(BasilError("MIDI_CAPTURE% expects 2 arguments".into())); }
-                            let a = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let b = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let rc = daw_utils::midi_capture(&a, &b);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        185 => self.stack.push(ext::midi_capture(&args)?), // MIDI_CAPTURE%(portSubstr$, outJsonlPath$)
                         #[cfg(feature = "obj-daw")]
-                        186 => { // SYNTH_LIVE%(midiPortSubstr$, outputSubstr$, poly%)
-                            if argc != 3 { return Err(BasilError("SYNTH_LIVE% expects 3 arguments".into())); }
-                            let a = match &args[0] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let b = match &args[1] { Value::Str(s)=>s.clone(), other=>format!("{}", other)};
-                            let poly = self.to_i64(&args[2])?;
-                            let rc = daw_utils::synth_live(&a, &b, poly);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        186 => self.stack.push(ext::synth_live(&args)?), // SYNTH_LIVE%(midiPortSubstr$, outputSubstr$, poly%)
                         #[cfg(feature = "obj-daw")]
-                        187 => { // DAW_RESET
-                            if argc != 0 { return Err(BasilError("DAW_RESET expects 0 arguments".into())); }
-                            daw_utils::reset();
-                            self.stack.push(Value::Str(String::new()));
-                        }
+                        187 => self.stack.push(ext::daw_reset(&args)?), // DAW_RESET
                         // --- Audio low-level ---
                         #[cfg(feature = "obj-audio")]
                         190 => { // AUDIO_OUTPUTS$[]
                             if argc != 0 { return Err(BasilError("AUDIO_OUTPUTS$ expects 0 arguments".into())); }
                             let v = audio_utils::audio_outputs();
-                            let arr = VM::make_string_array(v);
+                            let arr = basil_builtins::string_array(v);
                             self.stack.push(arr);
                         }
                         #[cfg(feature = "obj-audio")]
                         191 => { // AUDIO_INPUTS$[]
                             if argc != 0 { return Err(BasilError("AUDIO_INPUTS$ expects 0 arguments".into())); }
                             let v = audio_utils::audio_inputs();
-                            let arr = VM::make_string_array(v);
+                            let arr = basil_builtins::string_array(v);
                             self.stack.push(arr);
                         }
                         #[cfg(feature = "obj-audio")]
@@ -2545,7 +2029,7 @@ impl VM {
                         210 => { // MIDI_PORTS$[]
                             if argc != 0 { return Err(BasilError("MIDI_PORTS$ expects 0 arguments".into())); }
                             let v = midi_utils::midi_ports();
-                            let arr = VM::make_string_array(v);
+                            let arr = basil_builtins::string_array(v);
                             self.stack.push(arr);
                         }
                         #[cfg(feature = "obj-midi")]
@@ -2566,7 +2050,7 @@ impl VM {
                             if argc != 1 { return Err(BasilError("MIDI_GET_EVENT$[] expects 1 argument".into())); }
                             let h = self.to_i64(&args[0])?;
                             let (s, d1, d2) = midi_utils::midi_get_event(h)?;
-                            let arr = VM::make_string_array(vec![s.to_string(), d1.to_string(), d2.to_string()]);
+                            let arr = basil_builtins::string_array(vec![s.to_string(), d1.to_string(), d2.to_string()]);
                             self.stack.push(arr);
                         }
                         #[cfg(feature = "obj-midi")]
@@ -2576,162 +2060,49 @@ impl VM {
                             let rc = match midi_utils::midi_close(h) { Ok(_)=>0, Err(e)=> { #[cfg(feature="obj-daw")] { daw_utils::set_err(format!("{}", e)); } 1 } };
                             self.stack.push(Value::Int(rc));
                         }
-                        138 => { // INTERNAL: STR2D_TO_ARRAY$(rowsxcols)
-                            if argc != 1 { return Err(BasilError("internal builtin 138 expects 1 argument".into())); }
-                            match &args[0] {
-                                Value::StrArray2D { rows, cols, data } => {
-                                    // build 2D string array with dims [rows, cols]
-                                    let dims = vec![*rows, *cols];
-                                    let mut arr_data: Vec<Value> = Vec::with_capacity(data.len());
-                                    for s in data.iter() { arr_data.push(Value::Str(s.clone())); }
-                                    let arr = Rc::new(ArrayObj { elem: ElemType::Str, dims, data: std::cell::RefCell::new(arr_data) });
-                                    self.stack.push(Value::Array(arr));
-                                }
-                                other => return Err(BasilError(format!("builtin 138 expects StrArray2D, got {}", self.type_of(other)))),
-                            }
-                        }
-                        139 => { // ARRAY_ROWS%(arr$())
-                            if argc != 1 { return Err(BasilError("ARRAY_ROWS% expects 1 argument".into())); }
-                            match &args[0] {
-                                Value::Array(rc) => {
-                                    let a = rc.as_ref();
-                                    if a.dims.len() != 2 { return Err(BasilError("ARRAY_ROWS%: expected 2-D array".into())); }
-                                    self.stack.push(Value::Int(a.dims[0] as i64));
-                                }
-                                _ => return Err(BasilError("ARRAY_ROWS%: expected array".into())),
-                            }
-                        }
-                        140 => { // ARRAY_COLS%(arr$())
-                            if argc != 1 { return Err(BasilError("ARRAY_COLS% expects 1 argument".into())); }
-                            match &args[0] {
-                                Value::Array(rc) => {
-                                    let a = rc.as_ref();
-                                    if a.dims.len() != 2 { return Err(BasilError("ARRAY_COLS%: expected 2-D array".into())); }
-                                    self.stack.push(Value::Int(a.dims[1] as i64));
-                                }
-                                _ => return Err(BasilError("ARRAY_COLS%: expected array".into())),
-                            }
-                        }
+                        138 => self.stack.push(basil_builtins::str2d_to_array(&args)?), // INTERNAL: STR2D_TO_ARRAY$(rowsxcols)
+                        139 => self.stack.push(basil_builtins::array_rows(&args)?), // ARRAY_ROWS%(arr$())
+                        140 => self.stack.push(basil_builtins::array_cols(&args)?), // ARRAY_COLS%(arr$())
                         #[cfg(feature = "obj-term")]
-                        230 => { // CLS / CLEAR / HOME
-                            if argc != 0 { return Err(BasilError("CLS expects 0 arguments".into())); }
-                            let rc = basil_objects::term::cls();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        230 => self.stack.push(ext::cls(&args)?), // CLS / CLEAR / HOME
                         #[cfg(feature = "obj-term")]
-                        231 => { // LOCATE(x%, y%)
-                            if argc != 2 { return Err(BasilError("LOCATE expects 2 arguments".into())); }
-                            let rc = basil_objects::term::locate(&args[0], &args[1]);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        231 => self.stack.push(ext::locate(&args)?), // LOCATE(x%, y%)
                         #[cfg(feature = "obj-term")]
-                        232 => { // COLOR(fg, bg)
-                            if argc != 2 { return Err(BasilError("COLOR expects 2 arguments".into())); }
-                            let rc = basil_objects::term::color(&args[0], &args[1]);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        232 => self.stack.push(ext::color(&args)?), // COLOR(fg, bg)
                         #[cfg(feature = "obj-term")]
-                        233 => { // COLOR_RESET
-                            if argc != 0 { return Err(BasilError("COLOR_RESET expects 0 arguments".into())); }
-                            let rc = basil_objects::term::color_reset();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        233 => self.stack.push(ext::color_reset(&args)?), // COLOR_RESET
                         #[cfg(feature = "obj-term")]
-                        234 => { // ATTR(bold%, underline%, reverse%)
-                            if argc != 3 { return Err(BasilError("ATTR expects 3 arguments".into())); }
-                            let rc = basil_objects::term::attr(&args[0], &args[1], &args[2]);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        234 => self.stack.push(ext::attr(&args)?), // ATTR(bold%, underline%, reverse%)
                         #[cfg(feature = "obj-term")]
-                        235 => { // ATTR_RESET
-                            if argc != 0 { return Err(BasilError("ATTR_RESET expects 0 arguments".into())); }
-                            let rc = basil_objects::term::attr_reset();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        235 => self.stack.push(ext::attr_reset(&args)?), // ATTR_RESET
                         #[cfg(feature = "obj-term")]
-                        236 => { // CURSOR_SAVE
-                            if argc != 0 { return Err(BasilError("CURSOR_SAVE expects 0 arguments".into())); }
-                            let rc = basil_objects::term::cursor_save();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        236 => self.stack.push(ext::cursor_save(&args)?), // CURSOR_SAVE
                         #[cfg(feature = "obj-term")]
-                        237 => { // CURSOR_RESTORE
-                            if argc != 0 { return Err(BasilError("CURSOR_RESTORE expects 0 arguments".into())); }
-                            let rc = basil_objects::term::cursor_restore();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        237 => self.stack.push(ext::cursor_restore(&args)?), // CURSOR_RESTORE
                         #[cfg(feature = "obj-term")]
-                        238 => { // TERM_COLS%()
-                            if argc != 0 { return Err(BasilError("TERM_COLS% expects 0 arguments".into())); }
-                            let n = basil_objects::term::term_cols();
-                            self.stack.push(Value::Int(n));
-                        }
+                        238 => self.stack.push(ext::term_cols(&args)?), // TERM_COLS%()
                         #[cfg(feature = "obj-term")]
-                        239 => { // TERM_ROWS%()
-                            if argc != 0 { return Err(BasilError("TERM_ROWS% expects 0 arguments".into())); }
-                            let n = basil_objects::term::term_rows();
-                            self.stack.push(Value::Int(n));
-                        }
+                        239 => self.stack.push(ext::term_rows(&args)?), // TERM_ROWS%()
                         #[cfg(feature = "obj-term")]
-                        241 => { // CURSOR_HIDE
-                            if argc != 0 { return Err(BasilError("CURSOR_HIDE expects 0 arguments".into())); }
-                            let rc = basil_objects::term::cursor_hide();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        241 => self.stack.push(ext::cursor_hide(&args)?), // CURSOR_HIDE
                         #[cfg(feature = "obj-term")]
-                        242 => { // CURSOR_SHOW
-                            if argc != 0 { return Err(BasilError("CURSOR_SHOW expects 0 arguments".into())); }
-                            let rc = basil_objects::term::cursor_show();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        242 => self.stack.push(ext::cursor_show(&args)?), // CURSOR_SHOW
                         #[cfg(feature = "obj-term")]
-                        243 => { // TERM_ERR$()
-                            if argc != 0 { return Err(BasilError("TERM_ERR$ expects 0 arguments".into())); }
-                            let s = basil_objects::term::term_err();
-                            self.stack.push(Value::Str(s));
-                        }
+                        243 => self.stack.push(ext::term_err(&args)?), // TERM_ERR$()
                         #[cfg(feature = "obj-term")]
-                        244 => { // TERM.INIT
-                            if argc != 0 { return Err(BasilError("TERM.INIT expects 0 arguments".into())); }
-                            let rc = basil_objects::term::term_init();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        244 => self.stack.push(ext::term_init(&args)?), // TERM.INIT
                         #[cfg(feature = "obj-term")]
-                        245 => { // TERM.END
-                            if argc != 0 { return Err(BasilError("TERM.END expects 0 arguments".into())); }
-                            let rc = basil_objects::term::term_end();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        245 => self.stack.push(ext::term_end(&args)?), // TERM.END
                         #[cfg(feature = "obj-term")]
-                        246 => { // TERM.RAW ON|OFF
-                            if argc != 1 { return Err(BasilError("TERM.RAW expects 1 argument (ON/OFF or 0/1)".into())); }
-                            let rc = basil_objects::term::term_raw(&args[0]);
-                            self.stack.push(Value::Int(rc));
-                        }
+                        246 => self.stack.push(ext::term_raw(&args)?), // TERM.RAW ON|OFF
                         #[cfg(feature = "obj-term")]
-                        247 => { // ALTSCREEN_ON
-                            if argc != 0 { return Err(BasilError("ALTSCREEN_ON expects 0 arguments".into())); }
-                            let rc = basil_objects::term::altscreen_on();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        247 => self.stack.push(ext::altscreen_on(&args)?), // ALTSCREEN_ON
                         #[cfg(feature = "obj-term")]
-                        248 => { // ALTSCREEN_OFF
-                            if argc != 0 { return Err(BasilError("ALTSCREEN_OFF expects 0 arguments".into())); }
-                            let rc = basil_objects::term::altscreen_off();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        248 => self.stack.push(ext::altscreen_off(&args)?), // ALTSCREEN_OFF
                         #[cfg(feature = "obj-term")]
-                        249 => { // TERM.FLUSH
-                            if argc != 0 { return Err(BasilError("TERM.FLUSH expects 0 arguments".into())); }
-                            let rc = basil_objects::term::term_flush();
-                            self.stack.push(Value::Int(rc));
-                        }
+                        249 => self.stack.push(ext::term_flush(&args)?), // TERM.FLUSH
                         #[cfg(feature = "obj-term")]
-                        250 => { // TERM.POLLKEY$()
-                            if argc != 0 { return Err(BasilError("TERM.POLLKEY$ expects 0 arguments".into())); }
-                            let s = basil_objects::term::term_pollkey_s();
-                            self.stack.push(Value::Str(s));
-                        }
+                        250 => self.stack.push(ext::term_pollkey(&args)?), // TERM.POLLKEY$()
                         251 => { // MAKE_LIST([...])
                             // args are already in call order
                             let list = Rc::new(std::cell::RefCell::new(args));
@@ -2799,7 +2170,7 @@ impl VM {
                 }
 
                 Op::Halt => {
-                    if self.frames.len() == 1 { break; }
+                    if self.frames.len() == 1 { self.settle_pending_async(); break; }
                     else { return Err(BasilError("HALT inside function".into())); }
                 }
                // other => { return Err(BasilError(format!("unhandled opcode {:?}", other))); }
@@ -2812,12 +2183,36 @@ impl VM {
         Ok(())
     }
 
+    // Profiler API: enable before run(); take_profile() stops the clock and returns the report
+    pub fn enable_profiler(&mut self) { self.profiler = Some(Box::new(profile::Profiler::new())); }
+    pub fn take_profile(&mut self) -> Option<profile::Report> { self.profiler.take().map(|p| p.finish()) }
+
+    // Coverage API (test mode): enable before run(); take_coverage() maps the counters onto the program
+    pub fn enable_coverage(&mut self) {
+        let top = self.frames[0].chunk.clone();
+        self.coverage = Some(Box::new(coverage::Recorder::new(top)));
+    }
+    pub fn take_coverage(&mut self) -> Option<coverage::FileCoverage> {
+        let rec = self.coverage.take()?;
+        Some(rec.finish(self.comments_map.as_ref()))
+    }
+    /// Exit code requested by STOP or EXIT while running in test mode (or EXIT in a web request).
+    pub fn exit_code(&self) -> Option<i32> { self.exit_code }
+
     // Debugger integration API
     pub fn set_debugger(&mut self, dbg: Arc<debug::Debugger>) { self.debugger = Some(dbg); }
     pub fn with_debugger(mut self, dbg: Arc<debug::Debugger>) -> Self { self.debugger = Some(dbg); self }
+    /// Map generated lines to their template file:line for breakpoints, stops and call stacks.
+    pub fn set_source_map(&mut self, map: SourceMap) { self.source_map = Some(map); }
+    // File and line the debugger sees for the current line
+    fn debug_location(&self) -> (String, usize) {
+        if let Some(loc) = self.source_map.as_ref().and_then(|m| m.lookup(self.current_line)) {
+            return (loc.file.to_string(), loc.line as usize);
+        }
+        (self.script_path.clone().unwrap_or_else(|| "<unknown>".into()), self.current_line as usize)
+    }
     pub fn get_call_stack(&self) -> Vec<debug::FrameInfo> {
-        let file = self.script_path.clone().unwrap_or_else(|| "<unknown>".into());
-        let line = self.current_line as usize;
+        let (file, line) = self.debug_location();
         vec![debug::FrameInfo { function: "<top>".into(), file, line }]
     }
     pub fn get_scopes(&self) -> Vec<debug::Scope> {
@@ -2849,25 +2244,7 @@ impl VM {
         let f = self.cur();
         let byte = *f.chunk.code.get(f.ip).ok_or_else(|| BasilError("ip out of range".into()))?;
         f.ip += 1;
-        let op = match byte {
-            1=>Op::Const, 2=>Op::LoadGlobal, 3=>Op::StoreGlobal,
-            11=>Op::LoadLocal, 12=>Op::StoreLocal,
-            20=>Op::Add, 21=>Op::Sub, 22=>Op::Mul, 23=>Op::Div, 24=>Op::Neg, 25=>Op::Mod,
-            30=>Op::Eq, 31=>Op::Ne, 32=>Op::Lt, 33=>Op::Le, 34=>Op::Gt, 35=>Op::Ge,
-            40=>Op::Jump, 41=>Op::JumpIfFalse, 42=>Op::JumpBack,
-            50=>Op::Call, 51=>Op::Ret,
-            60=>Op::Print, 61=>Op::Pop, 62=>Op::ToInt, 63=>Op::Builtin, 64=>Op::SetLine,
-            70=>Op::ArrMake, 71=>Op::ArrGet, 72=>Op::ArrSet,
-            80=>Op::NewObj, 81=>Op::GetProp, 82=>Op::SetProp, 83=>Op::CallMethod, 84=>Op::DescribeObj,
-            90=>Op::EnumNew, 91=>Op::EnumMoveNext, 92=>Op::EnumCurrent, 93=>Op::EnumDispose,
-            100=>Op::NewClass, 101=>Op::GetMember, 102=>Op::SetMember, 103=>Op::CallMember, 104=>Op::DestroyInstance,
-            105=>Op::ExecString, 106=>Op::EvalString,
-            110=>Op::Gosub, 111=>Op::GosubBack, 112=>Op::GosubRet, 113=>Op::GosubPop,
-            120=>Op::TryPush, 121=>Op::TryPop, 122=>Op::Raise, 123=>Op::Reraise, 124=>Op::Stop,
-            255=>Op::Halt,
-            _ => return Err(BasilError(format!("bad opcode {}", byte))),
-        };
-        Ok(op)
+        Op::from_u8(byte).ok_or_else(|| BasilError(format!("bad opcode {}", byte)))
     }
     fn read_u8(&mut self) -> Result<u8> {
         let f = self.cur();
@@ -2927,31 +2304,7 @@ impl VM {
         Ok(())
     }
 
-    fn type_of(&self, v: &Value) -> String {
-        match v {
-            Value::Null => "NULL".to_string(),
-            Value::Bool(_) => "BOOL".to_string(),
-            Value::Num(_) => "FLOAT".to_string(),
-            Value::Int(_) => "INTEGER".to_string(),
-            Value::Str(_) => "STRING".to_string(),
-            Value::Func(_) => "FUNCTION".to_string(),
-            Value::Array(arr_rc) => {
-                let arr = arr_rc.as_ref();
-                let base = match &arr.elem {
-                    ElemType::Num => "FLOAT".to_string(),
-                    ElemType::Int => "INTEGER".to_string(),
-                    ElemType::Str => "STRING".to_string(),
-                    ElemType::Obj(Some(tn)) => tn.clone(),
-                    ElemType::Obj(None) => "OBJECT".to_string(),
-                };
-                format!("{}[]", base)
-            }
-            Value::Object(rc) => rc.borrow().type_name().to_string(),
-            Value::List(_) => "LIST".to_string(),
-            Value::Dict(_) => "DICT".to_string(),
-            Value::StrArray2D { .. } => "STRING[][]".to_string(),
-        }
-    }
+    fn type_of(&self, v: &Value) -> String { basil_builtins::type_of(v) }
 
     fn resolve_class_candidates(&self, fname: &str) -> Vec<std::path::PathBuf> {
         use std::path::{Path, PathBuf};
@@ -3008,33 +2361,22 @@ impl VM {
                 return Ok((prog, cand.to_string_lossy().to_string()));
             }
         }
+        // A bundled executable carries its CLASS files (precompiled, by `bcc bundle`)
+        for cand in self.resolve_class_candidates(fname) {
+            let name = cand.to_string_lossy().to_string();
+            let Some(bytes) = basil_builtins::files::embedded(&name) else { continue };
+            let prog = if name.to_ascii_lowercase().ends_with(".basilx") {
+                deserialize_program(bytes).map_err(|_| BasilError("Bad .basilx file".into()))?
+            } else {
+                compile_basil(&parse_basil(&String::from_utf8_lossy(bytes))?)?
+            };
+            return Ok((prog, name));
+        }
         Err(BasilError("Class file not found.".into()))
     }
 }
 
-fn is_truthy(v: &Value) -> bool {
-    match v {
-        Value::Null => false,
-        Value::Bool(b) => *b,
-        Value::Num(n) => *n != 0.0,
-        Value::Int(i) => *i != 0,
-        Value::Str(s) => !s.is_empty(),
-        Value::Func(_) => true,
-        Value::Array(_) => true,
-        Value::Object(_) => true,
-        Value::StrArray2D { rows, cols, data } => (*rows > 0) && (*cols > 0) && (!data.is_empty()),
-        Value::List(rc) => !rc.borrow().is_empty(),
-        Value::Dict(rc) => !rc.borrow().is_empty(),
-    }
-}
-
-
-impl Drop for VM {
-    fn drop(&mut self) {
-        let keys: Vec<i64> = self.file_table.keys().copied().collect();
-        for k in keys { let _ = self.fh_close(k); }
-    }
-}
+fn is_truthy(v: &Value) -> bool { basil_builtins::is_truthy(v) }
 
 #[cfg(test)]
 mod tests {
@@ -3045,8 +2387,8 @@ mod tests {
         let mut a = MockInputProvider::new(123456);
         let mut b = MockInputProvider::new(123456);
         for _ in 0..50 {
-            assert_eq!(a.read_line(), b.read_line());
-            assert_eq!(a.read_char(), b.read_char());
+            assert_eq!(a.read_line("").unwrap(), b.read_line("").unwrap());
+            assert_eq!(a.read_char("").unwrap(), b.read_char("").unwrap());
         }
     }
 
@@ -3054,10 +2396,24 @@ mod tests {
     fn mock_provider_values_in_set() {
         let mut m = MockInputProvider::new(1);
         for _ in 0..50 {
-            let s = m.read_line();
+            let s = m.read_line("").unwrap();
             assert!(s == "" || s == "Y" || s == "N" || s == "0" || s == "1" || s == "9");
-            let c = m.read_char().unwrap();
+            let c = m.read_char("").unwrap().unwrap();
             assert!(c == '\r' || c == 'Y' || c == 'N' || c == '0' || c == '1' || c == '9');
         }
     }
+    #[test]
+    fn raise_in_a_called_func_unwinds_to_the_try() {
+        let src = concat!(
+            "FUNC Inner()\n  RAISE \"boom\";\nEND\n",
+            "FUNC Outer()\n  RETURN Inner() + 1;\nEND\n",
+            "TRY\n  LET x = Outer();\nCATCH e$\n  LET caught$ = e$;\nEND TRY\n",
+        );
+        let mut vm = VM::new(basil_compiler::compile(&basil_parser::parse(src).unwrap()).unwrap());
+        vm.run().unwrap();
+        let (names, vals) = vm.globals_snapshot();
+        let i = names.iter().position(|n| n.eq_ignore_ascii_case("caught$")).unwrap();
+        assert_eq!(format!("{}", vals[i]), "boom");
+        assert!(vm.call_lines.is_empty() && vm.frames.len() == 1);
+    }
 }
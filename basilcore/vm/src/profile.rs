@@ -0,0 +1,254 @@
+//! Execution profiler used by `basilc run --profile`.
+//!
+//! The VM reports line changes, FUNC calls/returns and builtin/object-method calls; the profiler
+//! turns them into wall-clock totals. Time is always charged to exactly one place, so per-line
+//! times are self times: a line that calls a FUNC is not charged for the FUNC's body, but it is
+//! charged for the builtins and object methods it calls.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+const MAIN: &str = "<main>";
+
+#[derive(Debug, Default, Clone)]
+pub struct LineStat {
+    pub hits: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FuncStat {
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CallStat {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+struct PFrame {
+    name: String,
+    // collapsed-stack path of this frame, e.g. "<main>;Outer;Inner"
+    path: String,
+    line: u32,
+    entered: Instant,
+    child: Duration,
+}
+
+pub struct Profiler {
+    started: Instant,
+    last: Instant,
+    stack: Vec<PFrame>,
+    pending: Option<(String, Instant)>,
+    lines: HashMap<u32, LineStat>,
+    funcs: HashMap<String, FuncStat>,
+    calls: HashMap<String, CallStat>,
+    stacks: HashMap<String, Duration>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self { Self::new() }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Profiler {
+            started: now,
+            last: now,
+            stack: vec![PFrame { name: MAIN.into(), path: MAIN.into(), line: 0, entered: now, child: Duration::ZERO }],
+            pending: None,
+            lines: HashMap::new(),
+            funcs: HashMap::new(),
+            calls: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    // Charge the time since the last event to the current line and stack
+    fn charge(&mut self, now: Instant) {
+        let dt = now - self.last;
+        self.last = now;
+        let top = self.stack.last().expect("profiler stack");
+        if top.line != 0 { self.lines.entry(top.line).or_default().time += dt; }
+        add_time(&mut self.stacks, &top.path, dt);
+    }
+
+    /// Called before every instruction; closes a builtin/method call that just returned.
+    #[inline]
+    pub(crate) fn tick(&mut self) {
+        if self.pending.is_some() { self.end_call(Instant::now()); }
+    }
+
+    fn end_call(&mut self, now: Instant) {
+        let Some((label, start)) = self.pending.take() else { return };
+        let dt = now - start;
+        self.last = now;
+        let top = self.stack.last().expect("profiler stack");
+        if top.line != 0 { self.lines.entry(top.line).or_default().time += dt; }
+        add_time(&mut self.stacks, &format!("{};{}", top.path, label), dt);
+        let st = self.calls.entry(label).or_default();
+        st.calls += 1;
+        st.time += dt;
+    }
+
+    pub(crate) fn line(&mut self, line: u32) {
+        let now = Instant::now();
+        self.charge(now);
+        if let Some(top) = self.stack.last_mut() { top.line = line; }
+        self.lines.entry(line).or_default().hits += 1;
+    }
+
+    pub(crate) fn enter(&mut self, name: Option<&str>) {
+        let now = Instant::now();
+        self.charge(now);
+        let name = name.unwrap_or("<anonymous>").to_string();
+        let path = format!("{};{}", self.stack.last().map(|f| f.path.as_str()).unwrap_or(MAIN), name);
+        self.stack.push(PFrame { name, path, line: 0, entered: now, child: Duration::ZERO });
+    }
+
+    pub(crate) fn leave(&mut self) {
+        if self.stack.len() <= 1 { return; }
+        let now = Instant::now();
+        self.charge(now);
+        let f = self.stack.pop().expect("profiler stack");
+        let incl = now - f.entered;
+        // Recursive calls would count the same time twice in the inclusive total
+        let recursive = self.stack.iter().any(|p| p.name == f.name);
+        let st = self.funcs.entry(f.name).or_default();
+        st.calls += 1;
+        st.exclusive += incl.saturating_sub(f.child);
+        if !recursive { st.inclusive += incl; }
+        if let Some(parent) = self.stack.last_mut() { parent.child += incl; }
+    }
+
+    pub(crate) fn builtin(&mut self, id: u8) {
+        let label = match id {
+            66 => "PARALLEL FOR EACH".to_string(),
+            67 => "ASYNC call".to_string(),
+            _ => match basil_compiler::builtin_name(id) {
+                Some(n) => n.to_string(),
+                None => format!("builtin #{}", id),
+            },
+        };
+        self.call_labeled(label);
+    }
+
+    pub(crate) fn method(&mut self, type_name: &str, method: &str) {
+        self.call_labeled(format!("{}.{}", type_name, method.to_ascii_uppercase()));
+    }
+
+    fn call_labeled(&mut self, label: String) {
+        let now = Instant::now();
+        self.end_call(now);
+        self.charge(now);
+        self.pending = Some((label, now));
+    }
+
+    /// Stop the clock and produce the report. Frames still open (after an error) are closed.
+    pub fn finish(mut self) -> Report {
+        let now = Instant::now();
+        self.end_call(now);
+        while self.stack.len() > 1 { self.leave(); }
+        self.charge(Instant::now());
+        let main = self.stack.pop().expect("profiler stack");
+        let total = self.last - self.started;
+        let st = self.funcs.entry(main.name).or_default();
+        st.calls = 1;
+        st.inclusive = total;
+        st.exclusive = total.saturating_sub(main.child);
+        Report { total, lines: self.lines, funcs: self.funcs, calls: self.calls, stacks: self.stacks }
+    }
+}
+
+fn add_time(map: &mut HashMap<String, Duration>, key: &str, dt: Duration) {
+    match map.get_mut(key) {
+        Some(t) => *t += dt,
+        None => { map.insert(key.to_string(), dt); }
+    }
+}
+
+/// Finished profile: per-line, per-FUNC and per-call statistics plus collapsed stacks.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub total: Duration,
+    pub lines: HashMap<u32, LineStat>,
+    pub funcs: HashMap<String, FuncStat>,
+    pub calls: HashMap<String, CallStat>,
+    pub stacks: HashMap<String, Duration>,
+}
+
+fn ms(d: Duration) -> f64 { d.as_secs_f64() * 1000.0 }
+
+impl Report {
+    /// Human-readable summary with the `top` most expensive entries of each table.
+    pub fn text(&self, top: usize) -> String {
+        let mut s = String::new();
+        let pct = |d: Duration| if self.total.is_zero() { 0.0 } else { 100.0 * d.as_secs_f64() / self.total.as_secs_f64() };
+        let _ = writeln!(s, "=== Basil profile: {:.3} ms total ===", ms(self.total));
+
+        let mut funcs: Vec<_> = self.funcs.iter().collect();
+        funcs.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        let _ = writeln!(s, "\nFunctions (by self time):");
+        let _ = writeln!(s, "  {:>10} {:>12} {:>12} {:>6}  name", "calls", "self ms", "total ms", "self%");
+        for (name, st) in funcs.iter().take(top) {
+            let _ = writeln!(s, "  {:>10} {:>12.3} {:>12.3} {:>5.1}%  {}", st.calls, ms(st.exclusive), ms(st.inclusive), pct(st.exclusive), name);
+        }
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+        let _ = writeln!(s, "\nLines (by self time):");
+        let _ = writeln!(s, "  {:>10} {:>12} {:>6}  line", "hits", "self ms", "self%");
+        for (line, st) in lines.iter().take(top) {
+            let _ = writeln!(s, "  {:>10} {:>12.3} {:>5.1}%  {}", st.hits, ms(st.time), pct(st.time), line);
+        }
+
+        if !self.calls.is_empty() {
+            let mut calls: Vec<_> = self.calls.iter().collect();
+            calls.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+            let _ = writeln!(s, "\nBuiltins and object methods:");
+            let _ = writeln!(s, "  {:>10} {:>12} {:>12}  name", "calls", "total ms", "avg us");
+            for (name, st) in calls.iter().take(top) {
+                let avg = st.time.as_secs_f64() * 1e6 / st.calls.max(1) as f64;
+                let _ = writeln!(s, "  {:>10} {:>12.3} {:>12.1}  {}", st.calls, ms(st.time), avg, name);
+            }
+        }
+        s
+    }
+
+    /// Collapsed stacks ("a;b;c <microseconds>" per line), the input format of flamegraph.pl and inferno.
+    pub fn collapsed(&self) -> String {
+        let mut rows: Vec<_> = self.stacks.iter().filter(|(_, d)| d.as_micros() > 0).collect();
+        rows.sort_by(|a, b| a.0.cmp(b.0));
+        let mut s = String::new();
+        for (path, d) in rows { let _ = writeln!(s, "{} {}", path, d.as_micros()); }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::VM;
+    use basil_compiler::compile;
+    use basil_parser::parse;
+
+    #[test]
+    fn counts_lines_funcs_and_builtins() {
+        let src = "FUNC Inner(n)\n RETURN LEN(\"abc\") + n;\nEND\n\
+                   LET t = 0;\nFOR i% = 1 TO 5 {\n LET t = t + Inner(i%);\n}\nNEXT\n";
+        let mut vm = VM::new(compile(&parse(src).unwrap()).unwrap());
+        vm.enable_profiler();
+        vm.run().unwrap();
+        let r = vm.take_profile().unwrap();
+        assert_eq!(r.funcs["Inner"].calls, 5);
+        assert_eq!(r.calls["LEN"].calls, 5);
+        assert_eq!(r.lines[&2].hits, 5);
+        let folded = r.collapsed();
+        assert!(folded.lines().all(|l| l.starts_with("<main>")), "{}", folded);
+        assert!(r.text(10).contains("Inner"));
+    }
+}
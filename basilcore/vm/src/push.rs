@@ -0,0 +1,478 @@
+//! WEBSOCKET@ and SSE_SEND: pushing data to the browser from a long-lived server (`basilc serve`).
+//!
+//! For a WebSocket the server answers the upgrade handshake (`accept_key`) and hands the TCP
+//! connection to the VM (`VM::set_websocket`). The page's top level runs first and registers
+//! handlers (`WEBSOCKET@.OnMessage`, `OnTimer`, `OnClose`); the VM then reads frames until either
+//! side closes, calling the handlers, answering pings and pinging a client that has gone quiet.
+//! Framing follows RFC 6455: client frames must be masked, fragmented messages are reassembled
+//! and text messages must be UTF-8.
+//!
+//! SSE_SEND writes one Server-Sent Events message to the response and flushes it; the first call
+//! switches the response to `text/event-stream`.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use basil_bytecode::{BasicObject, ObjectDescriptor, Value};
+use basil_common::{BasilError, Result};
+use sha1::{Digest, Sha1};
+
+use crate::web::{bare, int_arg, method, prop, str_arg};
+use crate::VM;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+const DEFAULT_PING_SECS: u64 = 30;
+
+const OP_CONT: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// `Sec-WebSocket-Accept` for a handshake's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key.trim(), GUID).as_bytes());
+    base64(&digest)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() { out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char); } else { out.push('='); }
+        }
+    }
+    out
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Parse one client frame from the front of `buf`: the frame and the bytes it used, `Ok(None)` if
+/// more bytes are needed, or the close code for a frame that breaks the protocol.
+pub(crate) fn parse_frame(buf: &[u8]) -> std::result::Result<Option<(Frame, usize)>, u16> {
+    if buf.len() < 2 { return Ok(None); }
+    let (fin, opcode) = (buf[0] & 0x80 != 0, buf[0] & 0x0F);
+    if buf[0] & 0x70 != 0 { return Err(1002); } // no extensions were negotiated
+    if buf[1] & 0x80 == 0 { return Err(1002); } // client frames must be masked
+    let (len, mut pos) = match buf[1] & 0x7F {
+        126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().expect("8 bytes")), 10),
+        126 | 127 => return Ok(None),
+        n => (n as u64, 2),
+    };
+    if opcode >= OP_CLOSE && (!fin || len > 125) { return Err(1002); }
+    if len > MAX_MESSAGE as u64 { return Err(1009); }
+    if buf.len() < pos + 4 + len as usize { return Ok(None); }
+    let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+    pos += 4;
+    let payload = buf[pos..pos + len as usize].iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+    Ok(Some((Frame { fin, opcode, payload }, pos + len as usize)))
+}
+
+/// A server-to-client frame (unmasked, unfragmented).
+pub(crate) fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 10);
+    out.push(0x80 | opcode);
+    match payload.len() {
+        n if n < 126 => out.push(n as u8),
+        n if n <= u16::MAX as usize => { out.push(126); out.extend_from_slice(&(n as u16).to_be_bytes()); }
+        n => { out.push(127); out.extend_from_slice(&(n as u64).to_be_bytes()); }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+pub(crate) enum Event {
+    Message(String),
+    Timer,
+    Closed,
+}
+
+/// The connection behind WEBSOCKET@, shared by the object and the VM's message loop.
+pub(crate) struct WsState {
+    conn: Option<TcpStream>,
+    // Bytes read but not yet parsed into frames
+    buf: Vec<u8>,
+    // Opcode and data of a fragmented message still arriving
+    partial: Option<(u8, Vec<u8>)>,
+    on_message: Option<String>,
+    on_close: Option<String>,
+    timer: Option<(Duration, String)>,
+    next_timer: Instant,
+    ping_every: Option<Duration>,
+    last_heard: Instant,
+    ping_sent: Option<Instant>,
+    close_code: Option<u16>,
+}
+
+impl WsState {
+    /// `buffered` holds bytes the server read past the handshake request.
+    pub(crate) fn new(conn: Option<TcpStream>, buffered: Vec<u8>) -> Self {
+        let now = Instant::now();
+        WsState {
+            conn,
+            buf: buffered,
+            partial: None,
+            on_message: None,
+            on_close: None,
+            timer: None,
+            next_timer: now,
+            ping_every: Some(Duration::from_secs(DEFAULT_PING_SECS)),
+            last_heard: now,
+            ping_sent: None,
+            close_code: None,
+        }
+    }
+
+    pub(crate) fn has_connection(&self) -> bool { self.conn.is_some() }
+
+    fn is_open(&self) -> bool { self.conn.is_some() && self.close_code.is_none() }
+
+    // Handlers to run once the top level is done; without any the connection is closed right away
+    pub(crate) fn has_handlers(&self) -> bool { self.on_message.is_some() || self.timer.is_some() }
+
+    pub(crate) fn on_close(&self) -> Option<String> { self.on_close.clone() }
+    pub(crate) fn on_message(&self) -> Option<String> { self.on_message.clone() }
+    pub(crate) fn timer(&self) -> Option<String> { self.timer.as_ref().map(|(_, t)| t.clone()) }
+    pub(crate) fn close_code(&self) -> u16 { self.close_code.unwrap_or(1005) }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> bool {
+        if !self.is_open() { return false; }
+        let conn = self.conn.as_mut().expect("open connection");
+        if conn.write_all(&encode_frame(opcode, payload)).and_then(|_| conn.flush()).is_err() {
+            self.close_code = Some(1006);
+            return false;
+        }
+        true
+    }
+
+    /// Send a close frame (once) and stop reading.
+    pub(crate) fn close(&mut self, code: u16, reason: &str) {
+        if !self.is_open() { return; }
+        let mut payload = code.to_be_bytes().to_vec();
+        // Control frames carry at most 125 bytes
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) { end -= 1; }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.send(OP_CLOSE, &payload);
+        self.close_code = Some(code);
+        if let Some(conn) = &self.conn { let _ = conn.shutdown(Shutdown::Write); }
+    }
+
+    // A frame from the client; Some(event) when the page has to see it
+    fn handle(&mut self, f: Frame) -> Option<Event> {
+        self.last_heard = Instant::now();
+        self.ping_sent = None;
+        match f.opcode {
+            OP_PING => { self.send(OP_PONG, &f.payload); None }
+            OP_PONG => None,
+            OP_CLOSE => {
+                let code = (f.payload.len() >= 2).then(|| u16::from_be_bytes([f.payload[0], f.payload[1]]));
+                // Echo the code back, as the closing handshake asks; 1005 means "no code given"
+                self.close(code.unwrap_or(1000), "");
+                self.close_code = Some(code.unwrap_or(1005));
+                Some(Event::Closed)
+            }
+            OP_TEXT | OP_BINARY if self.partial.is_none() => {
+                if f.fin { return self.message(f.opcode, f.payload); }
+                self.partial = Some((f.opcode, f.payload));
+                None
+            }
+            OP_CONT if self.partial.is_some() => {
+                let (opcode, mut data) = self.partial.take().expect("partial message");
+                data.extend_from_slice(&f.payload);
+                if data.len() > MAX_MESSAGE { self.close(1009, "message too big"); return Some(Event::Closed); }
+                if f.fin { return self.message(opcode, data); }
+                self.partial = Some((opcode, data));
+                None
+            }
+            _ => { self.close(1002, "protocol error"); Some(Event::Closed) }
+        }
+    }
+
+    fn message(&mut self, opcode: u8, data: Vec<u8>) -> Option<Event> {
+        match String::from_utf8(data) {
+            Ok(s) => Some(Event::Message(s)),
+            Err(e) if opcode == OP_BINARY => Some(Event::Message(String::from_utf8_lossy(e.as_bytes()).into_owned())),
+            Err(_) => { self.close(1007, "invalid UTF-8"); Some(Event::Closed) }
+        }
+    }
+
+    /// Wait for the next message, timer tick or the end of the connection.
+    pub(crate) fn next_event(&mut self) -> Event {
+        let mut chunk = [0u8; 8192];
+        loop {
+            if !self.is_open() { return Event::Closed; }
+            match parse_frame(&self.buf) {
+                Err(code) => { self.close(code, "protocol error"); return Event::Closed; }
+                Ok(Some((frame, used))) => {
+                    self.buf.drain(..used);
+                    if let Some(ev) = self.handle(frame) { return ev; }
+                    continue;
+                }
+                Ok(None) => {}
+            }
+            let now = Instant::now();
+            if self.timer.is_some() && now >= self.next_timer {
+                let every = self.timer.as_ref().map(|(d, _)| *d).unwrap_or_default();
+                self.next_timer = now + every;
+                return Event::Timer;
+            }
+            if let Some(every) = self.ping_every {
+                match self.ping_sent {
+                    // No pong (or anything else) for a whole interval: the client is gone
+                    Some(sent) if now >= sent + every => { self.close(1001, "ping timeout"); return Event::Closed; }
+                    None if now >= self.last_heard + every => { self.send(OP_PING, b"basil"); self.ping_sent = Some(now); }
+                    _ => {}
+                }
+            }
+            // Sleep in read() until data arrives or the next timer tick or ping is due
+            let mut wait = Duration::from_secs(3600);
+            if self.timer.is_some() { wait = wait.min(self.next_timer.saturating_duration_since(now)); }
+            if let Some(every) = self.ping_every {
+                let due = self.ping_sent.unwrap_or(self.last_heard) + every;
+                wait = wait.min(due.saturating_duration_since(now));
+            }
+            let conn = self.conn.as_mut().expect("open connection");
+            let _ = conn.set_read_timeout(Some(wait.max(Duration::from_millis(1))));
+            match conn.read(&mut chunk) {
+                Ok(0) => { self.close_code = Some(1006); return Event::Closed; }
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+                Err(_) => { self.close_code = Some(1006); return Event::Closed; }
+            }
+        }
+    }
+}
+
+pub(crate) struct WebSocketObj {
+    pub state: Rc<RefCell<WsState>>,
+}
+
+impl BasicObject for WebSocketObj {
+    fn type_name(&self) -> &str { "WEBSOCKET" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let st = self.state.borrow();
+        Ok(match bare(name).as_str() {
+            "ISOPEN" => Value::Bool(st.is_open()),
+            "CLOSECODE" => Value::Int(st.close_code.map(|c| c as i64).unwrap_or(0)),
+            "PINGINTERVAL" => Value::Int(st.ping_every.map(|d| d.as_secs() as i64).unwrap_or(0)),
+            _ => return Err(BasilError(format!("Unknown property '{}' on WEBSOCKET", name))),
+        })
+    }
+    fn set_prop(&mut self, name: &str, v: Value) -> Result<()> {
+        match bare(name).as_str() {
+            "PINGINTERVAL" => {
+                let secs = int_arg(&[v], 0, "WEBSOCKET.PingInterval%")?.unwrap_or(0);
+                self.state.borrow_mut().ping_every = (secs > 0).then(|| Duration::from_secs(secs as u64));
+                Ok(())
+            }
+            _ => Err(BasilError(format!("Property '{}' on WEBSOCKET is read-only", name))),
+        }
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        let mut st = self.state.borrow_mut();
+        match bare(method).as_str() {
+            "SEND" => {
+                let text = match args.first() {
+                    Some(Value::Str(s)) => s.clone(),
+                    Some(v @ (Value::List(_) | Value::Dict(_) | Value::Object(_))) => crate::web::json_text(v, "WEBSOCKET.Send")?,
+                    Some(v) => v.to_string(),
+                    None => return Err(BasilError("WEBSOCKET.Send expects a message".into())),
+                };
+                Ok(Value::Bool(st.send(OP_TEXT, text.as_bytes())))
+            }
+            "CLOSE" => {
+                let code = int_arg(args, 0, "WEBSOCKET.Close")?.unwrap_or(1000);
+                if !(1000..=4999).contains(&code) { return Err(BasilError(format!("WEBSOCKET.Close: invalid close code {}", code))); }
+                let reason = if args.len() > 1 { str_arg(args, 1, "WEBSOCKET.Close")? } else { String::new() };
+                st.close(code as u16, &reason);
+                Ok(Value::Null)
+            }
+            "ONMESSAGE" => { st.on_message = Some(str_arg(args, 0, "WEBSOCKET.OnMessage")?); Ok(Value::Null) }
+            "ONCLOSE" => { st.on_close = Some(str_arg(args, 0, "WEBSOCKET.OnClose")?); Ok(Value::Null) }
+            "ONTIMER" => {
+                let target = str_arg(args, 0, "WEBSOCKET.OnTimer")?;
+                let ms = int_arg(args, 1, "WEBSOCKET.OnTimer")?.unwrap_or(1000);
+                if ms <= 0 { return Err(BasilError("WEBSOCKET.OnTimer: the interval must be positive".into())); }
+                let every = Duration::from_millis(ms as u64);
+                st.next_timer = Instant::now() + every;
+                st.timer = Some((every, target));
+                Ok(Value::Null)
+            }
+            _ => Err(BasilError(format!("Unknown method '{}' on WEBSOCKET", method))),
+        }
+    }
+    fn descriptor(&self) -> ObjectDescriptor { websocket_descriptor() }
+}
+
+pub(crate) fn websocket_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "WEBSOCKET".to_string(),
+        version: "1.0".to_string(),
+        summary: "The WebSocket connection a page was opened on (basilc serve)".to_string(),
+        properties: vec![
+            prop("IsOpen", "Bool", false),
+            prop("CloseCode%", "Integer", false),
+            prop("PingInterval%", "Integer", true),
+        ],
+        methods: vec![
+            method("Send", &["message"], "Bool"),
+            method("Close", &["[code%]", "[reason$]"], "Void"),
+            method("OnMessage", &["target$"], "Void"),
+            method("OnTimer", &["target$", "intervalMs%"], "Void"),
+            method("OnClose", &["target$"], "Void"),
+        ],
+        examples: vec![
+            "WEBSOCKET@.OnMessage(\"Echo\")".to_string(),
+            "WEBSOCKET@.Send(\"{\\\"cpu\\\": 42}\")".to_string(),
+        ],
+    }
+}
+
+impl VM {
+    /// Answer this request over a WebSocket: `stream` is the connection after the server sent
+    /// `101 Switching Protocols`, `buffered` any bytes it already read past the request.
+    pub fn set_websocket(&mut self, stream: TcpStream, buffered: Vec<u8>) {
+        self.websocket = Some(Rc::new(RefCell::new(WsState::new(Some(stream), buffered))));
+    }
+
+    // Read frames and call the page's handlers until the connection closes
+    pub(crate) fn websocket_loop(&mut self) -> Result<()> {
+        let Some(ws) = self.websocket.clone() else { return Ok(()) };
+        if !ws.borrow().has_connection() { return Ok(()); }
+        if !ws.borrow().has_handlers() { ws.borrow_mut().close(1000, ""); }
+        loop {
+            // The borrow ends before a handler runs, so handlers can use WEBSOCKET@
+            let event = ws.borrow_mut().next_event();
+            let (target, arg) = match event {
+                Event::Message(m) => (ws.borrow().on_message(), Value::Str(m)),
+                Event::Timer => (ws.borrow().timer(), Value::Null),
+                Event::Closed => break,
+            };
+            let Some(target) = target else { continue };
+            if let Err(e) = self.call_target(&target, &arg) {
+                ws.borrow_mut().close(1011, "internal error");
+                return Err(e);
+            }
+        }
+        let (on_close, code) = { let st = ws.borrow(); (st.on_close(), st.close_code()) };
+        if let Some(target) = on_close { self.call_target(&target, &Value::Int(code as i64))?; }
+        Ok(())
+    }
+
+    /// SSE_SEND(data [, event$ [, id$]]): one Server-Sent Events message, flushed right away.
+    /// Returns FALSE once the client has disconnected.
+    pub(crate) fn sse_send(&mut self, args: &[Value]) -> Result<bool> {
+        let data = match args.first() {
+            Some(Value::Str(s)) => s.clone(),
+            Some(v @ (Value::List(_) | Value::Dict(_) | Value::Object(_))) => crate::web::json_text(v, "SSE_SEND")?,
+            Some(v) => v.to_string(),
+            None => return Err(BasilError("SSE_SEND expects 1 to 3 arguments".into())),
+        };
+        let field = |i: usize| -> Result<Option<String>> {
+            if args.len() <= i { return Ok(None); }
+            let v = str_arg(args, i, "SSE_SEND")?;
+            if v.contains(['\r', '\n']) { return Err(BasilError("SSE_SEND: event and id must not contain line breaks".into())); }
+            Ok(Some(v).filter(|v| !v.is_empty()))
+        };
+        let (event, id) = (field(1)?, field(2)?);
+        let response = self.response_state();
+        {
+            let mut st = response.borrow_mut();
+            if !st.sent {
+                st.set_header("Content-Type", "text/event-stream; charset=utf-8");
+                st.set_header("Cache-Control", "no-cache");
+            }
+        }
+        self.send_headers();
+        let mut msg = String::new();
+        if let Some(e) = event { msg.push_str(&format!("event: {}\n", e)); }
+        if let Some(i) = id { msg.push_str(&format!("id: {}\n", i)); }
+        for line in data.split('\n') { msg.push_str(&format!("data: {}\n", line.trim_end_matches('\r'))); }
+        msg.push('\n');
+        let sent = match &self.output {
+            Some(out) => { let mut out = out.borrow_mut(); out.write_all(msg.as_bytes()).and_then(|_| out.flush()) }
+            None => { let mut out = io::stdout(); out.write_all(msg.as_bytes()).and_then(|_| out.flush()) }
+        };
+        Ok(sent.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut out = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            n if n < 126 => out.push(0x80 | n as u8),
+            n => { out.push(0x80 | 126); out.extend_from_slice(&(n as u16).to_be_bytes()); }
+        }
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    #[test]
+    fn handshake_accept_key() {
+        // The example from RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn parses_masked_frames() {
+        let text = masked(OP_TEXT, true, b"Hello");
+        assert_eq!(parse_frame(&text[..3]), Ok(None));
+        assert_eq!(parse_frame(&text), Ok(Some((Frame { fin: true, opcode: OP_TEXT, payload: b"Hello".to_vec() }, text.len()))));
+        let long = vec![b'x'; 300];
+        let frame = masked(OP_BINARY, false, &long);
+        assert_eq!(parse_frame(&frame).unwrap().unwrap().0.payload, long);
+        // Unmasked client frames and fragmented control frames break the protocol
+        assert_eq!(parse_frame(&encode_frame(OP_TEXT, b"x")), Err(1002));
+        assert_eq!(parse_frame(&masked(OP_PING, false, b"")), Err(1002));
+    }
+
+    #[test]
+    fn encodes_server_frames() {
+        assert_eq!(encode_frame(OP_TEXT, b"Hi"), vec![0x81, 2, b'H', b'i']);
+        let big = encode_frame(OP_BINARY, &[0; 200]);
+        assert_eq!(&big[..4], &[0x82, 126, 0, 200]);
+        assert_eq!(encode_frame(OP_BINARY, &[0; 70_000])[1], 127);
+    }
+
+    #[test]
+    fn answers_pings_and_reassembles_fragments() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut input = masked(OP_PING, true, b"p1");
+        input.extend(masked(OP_TEXT, false, b"Hel"));
+        input.extend(masked(OP_CONT, true, b"lo"));
+        let mut ws = WsState::new(Some(server), input);
+        assert!(matches!(ws.next_event(), Event::Message(m) if m == "Hello"));
+        client.write_all(&masked(OP_CLOSE, true, &1000u16.to_be_bytes())).unwrap();
+        assert!(matches!(ws.next_event(), Event::Closed));
+        assert_eq!(ws.close_code(), 1000);
+        let mut got = Vec::new();
+        client.read_to_end(&mut got).unwrap();
+        let mut expect = encode_frame(OP_PONG, b"p1");
+        expect.extend(encode_frame(OP_CLOSE, &1000u16.to_be_bytes()));
+        assert_eq!(got, expect);
+    }
+}
@@ -0,0 +1,379 @@
+//! ROUTER@: URL routing for multi-page sites answered by one `routes.basil`.
+//!
+//! A router script declares routes (`ROUTE "GET", "/users/:id", "UserController.Show"`, which is
+//! sugar for `ROUTER@.Add`) and middleware (`ROUTER@.Use`). When routing is enabled
+//! (`VM::enable_routing`) the VM runs the script's top level, then matches the request against
+//! the table: middleware runs first (returning FALSE stops the request), then the handler. A
+//! target is either a FUNC name or `Class.Method`, where the class is a global object or is
+//! loaded from `controllers/Class.basil` next to the script. `:name` segments capture one path
+//! segment and `*name` the rest of the path; the values are available as `REQUEST@.Route$`.
+//! A handler's return value is the body: strings are printed, lists, dicts and objects are sent
+//! as JSON (obj-json).
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use basil_bytecode::{BasicObject, Chunk, Function, ObjectDescriptor, Op, Value};
+use basil_common::{BasilError, Result};
+
+use crate::web::{bare, method, prop, str_arg};
+use crate::{Frame, VM};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Seg {
+    Lit(String),
+    Param(String),
+    /// `*name`: the rest of the path, only as the last segment
+    Rest(String),
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    method: String,
+    segs: Vec<Seg>,
+    target: String,
+}
+
+#[derive(Debug, Clone)]
+struct Middleware {
+    prefix: Vec<String>,
+    target: String,
+}
+
+/// The route table shared by ROUTER@ and the VM.
+#[derive(Debug, Default)]
+pub(crate) struct RouterState {
+    routes: Vec<Route>,
+    middleware: Vec<Middleware>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Dispatch {
+    Found { target: String, params: Vec<(String, String)>, middleware: Vec<String> },
+    /// The path matched, but not with this method; the methods that would have matched
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+// Empty segments are ignored, so `/users/`, `users` and `//users` are the same path
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+impl RouterState {
+    fn add(&mut self, method: &str, pattern: &str, target: &str) -> Result<()> {
+        let method = method.trim().to_ascii_uppercase();
+        if method.is_empty() || !method.bytes().all(|b| b.is_ascii_alphabetic() || b == b'*') {
+            return Err(BasilError(format!("ROUTE: invalid method '{}'", method)));
+        }
+        let parts = segments(pattern);
+        let mut segs = Vec::with_capacity(parts.len());
+        for (i, p) in parts.iter().enumerate() {
+            segs.push(if let Some(n) = p.strip_prefix(':') {
+                if n.is_empty() { return Err(BasilError(format!("ROUTE {}: parameter without a name", pattern))); }
+                Seg::Param(n.to_string())
+            } else if let Some(n) = p.strip_prefix('*') {
+                if i + 1 != parts.len() { return Err(BasilError(format!("ROUTE {}: '*{}' must be the last segment", pattern, n))); }
+                Seg::Rest(if n.is_empty() { "path".to_string() } else { n.to_string() })
+            } else {
+                Seg::Lit(p.to_string())
+            });
+        }
+        let target = check_target(target, "ROUTE")?;
+        self.routes.push(Route { method, segs, target });
+        Ok(())
+    }
+
+    fn add_middleware(&mut self, target: &str, prefix: &str) -> Result<()> {
+        let target = check_target(target, "ROUTER.Use")?;
+        self.middleware.push(Middleware { prefix: segments(prefix).into_iter().map(str::to_string).collect(), target });
+        Ok(())
+    }
+
+    /// Match a request; routes are tried in the order they were declared.
+    pub(crate) fn dispatch(&self, method: &str, path: &str) -> Dispatch {
+        let method = method.to_ascii_uppercase();
+        let parts = segments(path);
+        let mut allowed: Vec<String> = Vec::new();
+        for r in &self.routes {
+            let Some(params) = match_segs(&r.segs, &parts) else { continue };
+            let method_ok = r.method == method || r.method == "ANY" || r.method == "*" || (method == "HEAD" && r.method == "GET");
+            if !method_ok {
+                if !allowed.contains(&r.method) { allowed.push(r.method.clone()); }
+                continue;
+            }
+            let middleware = self.middleware.iter()
+                .filter(|m| parts.len() >= m.prefix.len() && m.prefix.iter().zip(&parts).all(|(a, b)| a == b))
+                .map(|m| m.target.clone())
+                .collect();
+            return Dispatch::Found { target: r.target.clone(), params, middleware };
+        }
+        if allowed.is_empty() { Dispatch::NotFound } else { Dispatch::MethodNotAllowed(allowed) }
+    }
+}
+
+fn check_target(target: &str, what: &str) -> Result<String> {
+    let t = target.trim();
+    let ok = !t.is_empty() && t.split('.').count() <= 2 && t.split('.').all(|p| !p.is_empty() && !p.contains(['/', '\\']));
+    if !ok { return Err(BasilError(format!("{}: invalid target '{}' (expected FuncName or Class.Method)", what, target))); }
+    Ok(t.to_string())
+}
+
+fn match_segs(segs: &[Seg], parts: &[&str]) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    for (i, seg) in segs.iter().enumerate() {
+        match seg {
+            Seg::Rest(n) => {
+                params.push((n.clone(), parts.get(i..).unwrap_or(&[]).join("/")));
+                return Some(params);
+            }
+            Seg::Lit(l) => if parts.get(i) != Some(&l.as_str()) { return None; },
+            Seg::Param(n) => params.push((n.clone(), parts.get(i)?.to_string())),
+        }
+    }
+    (segs.len() == parts.len()).then_some(params)
+}
+
+pub(crate) struct RouterObj {
+    pub state: Rc<RefCell<RouterState>>,
+}
+
+impl BasicObject for RouterObj {
+    fn type_name(&self) -> &str { "ROUTER" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        match bare(name).as_str() {
+            "COUNT" => Ok(Value::Int(self.state.borrow().routes.len() as i64)),
+            _ => Err(BasilError(format!("Unknown property '{}' on ROUTER", name))),
+        }
+    }
+    fn set_prop(&mut self, name: &str, _v: Value) -> Result<()> {
+        Err(BasilError(format!("Property '{}' on ROUTER is read-only", name)))
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        let m = bare(method);
+        let mut st = self.state.borrow_mut();
+        match m.as_str() {
+            "ADD" => st.add(&str_arg(args, 0, "ROUTE")?, &str_arg(args, 1, "ROUTE")?, &str_arg(args, 2, "ROUTE")?)?,
+            "GET" | "POST" | "PUT" | "PATCH" | "DELETE" | "ANY" => {
+                let what = format!("ROUTER.{}", method);
+                st.add(&m, &str_arg(args, 0, &what)?, &str_arg(args, 1, &what)?)?
+            }
+            "USE" => {
+                let prefix = if args.len() > 1 { str_arg(args, 1, "ROUTER.Use")? } else { String::new() };
+                st.add_middleware(&str_arg(args, 0, "ROUTER.Use")?, &prefix)?
+            }
+            _ => return Err(BasilError(format!("Unknown method '{}' on ROUTER", method))),
+        }
+        Ok(Value::Null)
+    }
+    fn descriptor(&self) -> ObjectDescriptor { router_descriptor() }
+}
+
+pub(crate) fn router_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "ROUTER".to_string(),
+        version: "1.0".to_string(),
+        summary: "Routes and middleware of a routes.basil site".to_string(),
+        properties: vec![prop("Count%", "Integer", false)],
+        methods: vec![
+            method("Add", &["method$", "pattern$", "target$"], "Void"),
+            method("Get", &["pattern$", "target$"], "Void"),
+            method("Post", &["pattern$", "target$"], "Void"),
+            method("Put", &["pattern$", "target$"], "Void"),
+            method("Patch", &["pattern$", "target$"], "Void"),
+            method("Delete", &["pattern$", "target$"], "Void"),
+            method("Any", &["pattern$", "target$"], "Void"),
+            method("Use", &["target$", "[prefix$]"], "Void"),
+        ],
+        examples: vec![
+            "ROUTE \"GET\", \"/users/:id\", \"UserController.Show\"".to_string(),
+            "ROUTER@.Use(\"RequireLogin\", \"/admin\")".to_string(),
+        ],
+    }
+}
+
+impl VM {
+    /// Dispatch the request through ROUTER@ once the router script's top level has run.
+    pub fn enable_routing(&mut self) { self.routing = true; }
+
+    pub(crate) fn dispatch_route(&mut self) -> Result<()> {
+        let request = self.request_data();
+        // Controllers created below share this request and response
+        let response = self.response_state();
+        // PATH_INFO is already decoded; REQUEST_URI is not ('+' is literal in a path)
+        let path = if request.path_info.is_empty() {
+            crate::web::form_decode(&request.uri.split('?').next().unwrap_or("").replace('+', "%2B"))
+        } else {
+            request.path_info.clone()
+        };
+        let found = match &self.router {
+            Some(router) => router.borrow().dispatch(&request.method, &path),
+            None => Dispatch::NotFound,
+        };
+        let (target, params, middleware) = match found {
+            Dispatch::Found { target, params, middleware } => (target, params, middleware),
+            Dispatch::MethodNotAllowed(allowed) => {
+                response.borrow_mut().set_header("Allow", &allowed.join(", "));
+                return self.plain_error(405, "Method Not Allowed");
+            }
+            Dispatch::NotFound => return self.plain_error(404, "Not Found"),
+        };
+        *request.route.borrow_mut() = params.clone();
+        let params = Value::Dict(Rc::new(RefCell::new(params.into_iter().map(|(k, v)| (k, Value::Str(v))).collect())));
+        for m in middleware {
+            if matches!(self.call_target(&m, &params)?, Value::Bool(false)) { return Ok(()); }
+        }
+        let result = self.call_target(&target, &params)?;
+        let body = match result {
+            Value::Null => return Ok(()),
+            Value::Str(s) => s,
+            v @ (Value::List(_) | Value::Dict(_) | Value::Object(_) | Value::Array(_)) => {
+                let text = crate::web::json_text(&v, &target)?;
+                let mut st = response.borrow_mut();
+                if !st.sent && !st.headers.iter().any(|(n, v)| n.eq_ignore_ascii_case("Content-Type") && v.contains("json")) {
+                    st.set_header("Content-Type", "application/json; charset=utf-8");
+                }
+                text
+            }
+            other => other.to_string(),
+        };
+        self.write_body(&body);
+        Ok(())
+    }
+
+    fn plain_error(&mut self, status: u16, msg: &str) -> Result<()> {
+        {
+            let response = self.response_state();
+            let mut st = response.borrow_mut();
+            if st.sent { return Err(BasilError(format!("{} {}: output had already started", status, msg))); }
+            st.status = status;
+            st.reason = None;
+            st.set_header("Content-Type", "text/plain; charset=utf-8");
+        }
+        self.write_body(&format!("{} {}\n", status, msg));
+        Ok(())
+    }
+
+    fn write_body(&mut self, body: &str) {
+        self.send_headers();
+        match &self.output {
+            Some(out) => { let _ = out.borrow_mut().write_all(body.as_bytes()); }
+            None => { print!("{}", body); let _ = std::io::stdout().flush(); }
+        }
+    }
+
+    /// Call a handler: a FUNC by name, or Class.Method on a global object or a controllers/ class.
+    /// `arg` is passed when the handler takes a parameter (route params, a WebSocket message).
+    pub(crate) fn call_target(&mut self, target: &str, arg: &Value) -> Result<Value> {
+        let Some((class, meth)) = target.split_once('.') else {
+            let f = match self.global(target) {
+                Some(Value::Func(f)) => f,
+                _ => return Err(BasilError(format!("Handler '{}' is not a FUNC", target))),
+            };
+            let args = if f.arity == 0 { Vec::new() } else { vec![arg.clone()] };
+            if f.arity > 1 { return Err(BasilError(format!("Handler '{}' takes at most 1 parameter", target))); }
+            return self.call_function(&f, args);
+        };
+        let obj = match self.global(class).or_else(|| self.global(&format!("{}@", class))) {
+            Some(Value::Object(o)) => o,
+            _ => self.controller(class)?,
+        };
+        let arity = obj.borrow().descriptor().methods.iter()
+            .find(|m| bare(&m.name) == bare(meth))
+            .map(|m| m.arity)
+            .ok_or_else(|| BasilError(format!("Handler '{}': no method '{}' on {}", target, meth, class)))?;
+        let args = if arity == 0 { Vec::new() } else { vec![arg.clone()] };
+        let v = obj.borrow_mut().call(meth, &args)?;
+        Ok(v)
+    }
+
+    fn global(&self, name: &str) -> Option<Value> {
+        let i = self.global_names.iter().position(|n| n.eq_ignore_ascii_case(name))?;
+        Some(self.globals[i].clone())
+    }
+
+    fn controller(&mut self, class: &str) -> Result<basil_bytecode::ObjectRef> {
+        if let Some(o) = self.controllers.get(&class.to_ascii_uppercase()) { return Ok(o.clone()); }
+        let dir = self.script_path.as_deref().and_then(|p| Path::new(p).parent()).unwrap_or(Path::new("."));
+        let file = dir.join("controllers").join(class);
+        let (prog, path) = self.load_class_program(&file.to_string_lossy())
+            .map_err(|_| BasilError(format!("Controller '{}' not found (expected {}.basil)", class, file.display())))?;
+        let obj = self.instantiate_class(prog, path)?;
+        self.controllers.insert(class.to_ascii_uppercase(), obj.clone());
+        Ok(obj)
+    }
+
+    // Run a FUNC to completion on this VM's stack, as if called from a HALTed top level
+    fn call_function(&mut self, f: &Function, args: Vec<Value>) -> Result<Value> {
+        let mut halt = Chunk::default();
+        halt.push_op(Op::Halt);
+        let saved = std::mem::take(&mut self.frames);
+        let saved_lines = std::mem::take(&mut self.call_lines);
+        let base = self.stack.len();
+        self.frames.push(Frame { chunk: Rc::new(halt), ip: 0, base });
+        self.stack.extend(args);
+        self.frames.push(Frame { chunk: f.chunk.clone(), ip: 0, base });
+        let res = self.exec();
+        // An error leaves the callee's frames and call lines behind; drop them with the frames
+        self.frames = saved;
+        self.call_lines = saved_lines;
+        let ret = if self.stack.len() > base { self.stack.pop().unwrap_or(Value::Null) } else { Value::Null };
+        self.stack.truncate(base);
+        res.map(|_| ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> RouterState {
+        let mut r = RouterState::default();
+        r.add("GET", "/users", "Users.Index").unwrap();
+        r.add("GET", "/users/:id", "Users.Show").unwrap();
+        r.add("post", "/users/:id", "Users.Update").unwrap();
+        r.add("ANY", "/files/*rest", "Serve").unwrap();
+        r.add_middleware("Log", "").unwrap();
+        r.add_middleware("Auth", "/users").unwrap();
+        r
+    }
+
+    fn found(target: &str, params: &[(&str, &str)], middleware: &[&str]) -> Dispatch {
+        Dispatch::Found {
+            target: target.to_string(),
+            params: params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            middleware: middleware.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_params_and_rest() {
+        let r = table();
+        assert_eq!(r.dispatch("GET", "/users/"), found("Users.Index", &[], &["Log", "Auth"]));
+        assert_eq!(r.dispatch("get", "/users/42"), found("Users.Show", &[("id", "42")], &["Log", "Auth"]));
+        assert_eq!(r.dispatch("HEAD", "/users/a b"), found("Users.Show", &[("id", "a b")], &["Log", "Auth"]));
+        assert_eq!(r.dispatch("DELETE", "/files/a/b.txt"), found("Serve", &[("rest", "a/b.txt")], &["Log"]));
+        assert_eq!(r.dispatch("GET", "/files"), found("Serve", &[("rest", "")], &["Log"]));
+        assert_eq!(r.dispatch("GET", "/users/1/edit"), Dispatch::NotFound);
+        assert_eq!(r.dispatch("GET", "/usersx"), Dispatch::NotFound);
+    }
+
+    #[test]
+    fn wrong_method_lists_allowed() {
+        let r = table();
+        assert_eq!(r.dispatch("PUT", "/users/7"), Dispatch::MethodNotAllowed(vec!["GET".into(), "POST".into()]));
+        assert_eq!(r.dispatch("POST", "/users"), Dispatch::MethodNotAllowed(vec!["GET".into()]));
+    }
+
+    #[test]
+    fn rejects_bad_patterns_and_targets() {
+        let mut r = RouterState::default();
+        assert!(r.add("GET", "/a/*rest/b", "X").is_err());
+        assert!(r.add("GET", "/a/:", "X").is_err());
+        assert!(r.add("GET", "/a", "../x.Y").is_err());
+        assert!(r.add("GET", "/a", "A.B.C").is_err());
+        assert!(r.add("G T", "/a", "X").is_err());
+    }
+}
@@ -0,0 +1,678 @@
+//! SESSION@: server-side sessions for web pages, keyed by a signed cookie.
+//!
+//! The cookie holds a random ID plus an HMAC-SHA256 signature, so a forged or edited cookie is
+//! simply treated as "no session". Data lives in a store chosen by `BASIL_SESSION_STORE` or
+//! `SESSION@.Store$` (`files[:dir]` or `sqlite:path`). A session starts on first use, expires
+//! after `Lifetime%` seconds without a request, and is saved when the page ends. Session files
+//! and the generated signing key only go in a folder owned by the current user with mode 0700.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use basil_bytecode::{BasicObject, ObjectDescriptor, Value};
+use basil_common::{BasilError, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::web::{bare, int_arg, lookup, method, prop, str_arg, RequestData, ResponseState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_COOKIE: &str = "BASILSESSID";
+const DEFAULT_LIFETIME: i64 = 3600;
+const CSRF_FIELD: &str = "_csrf";
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() }
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) { return None; }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn random_hex(n: usize) -> Result<String> {
+    let mut buf = vec![0u8; n];
+    getrandom::getrandom(&mut buf).map_err(|e| BasilError(format!("SESSION: no randomness available: {}", e)))?;
+    Ok(hex(&buf))
+}
+
+fn mac(secret: &[u8], id: &str) -> HmacSha256 {
+    let mut m = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    m.update(id.as_bytes());
+    m
+}
+
+/// `id.signature` for the cookie.
+fn sign(secret: &[u8], id: &str) -> String {
+    format!("{}.{}", id, hex(&mac(secret, id).finalize().into_bytes()))
+}
+
+/// The ID in a cookie value, if its signature checks out.
+fn verify(secret: &[u8], cookie: &str) -> Option<String> {
+    let (id, sig) = cookie.split_once('.')?;
+    if id.len() != 32 || !id.bytes().all(|b| b.is_ascii_hexdigit()) { return None; }
+    mac(secret, id).verify_slice(&unhex(sig)?).ok()?;
+    Some(id.to_string())
+}
+
+// Constant-time comparison for CSRF tokens
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// --- Stores ---
+
+trait Store {
+    /// Encoded data and expiry time of a session
+    fn load(&self, id: &str) -> Result<Option<(String, i64)>>;
+    fn save(&self, id: &str, data: &str, expires: i64) -> Result<()>;
+    fn delete(&self, id: &str) -> Result<()>;
+    /// Drop sessions that expired before `now`
+    fn gc(&self, now: i64) -> Result<()>;
+    /// Where the generated signing key is kept when BASIL_SESSION_SECRET is not set
+    fn secret_path(&self) -> PathBuf;
+}
+
+struct FileStore { dir: PathBuf }
+
+impl FileStore {
+    fn file(&self, id: &str) -> PathBuf { self.dir.join(format!("sess_{}", id)) }
+}
+
+fn io_err(what: &str, e: std::io::Error) -> BasilError { BasilError(format!("SESSION store: {}: {}", what, e)) }
+
+impl Store for FileStore {
+    fn load(&self, id: &str) -> Result<Option<(String, i64)>> {
+        create_private_dir(&self.dir)?;
+        let Ok(text) = fs::read_to_string(self.file(id)) else { return Ok(None) };
+        let (expires, data) = text.split_once('\n').unwrap_or((&text, ""));
+        Ok(Some((data.to_string(), expires.trim().parse().unwrap_or(0))))
+    }
+    fn save(&self, id: &str, data: &str, expires: i64) -> Result<()> {
+        create_private_dir(&self.dir)?;
+        // Write then rename, so a concurrent request never reads half a file
+        let tmp = self.dir.join(format!(".sess_{}.{}", id, random_hex(4)?));
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        { use std::os::unix::fs::OpenOptionsExt; opts.mode(0o600); }
+        opts.open(&tmp).and_then(|mut f| f.write_all(format!("{}\n{}", expires, data).as_bytes()))
+            .map_err(|e| io_err(&tmp.display().to_string(), e))?;
+        fs::rename(&tmp, self.file(id)).map_err(|e| io_err(&self.file(id).display().to_string(), e))
+    }
+    fn delete(&self, id: &str) -> Result<()> {
+        let _ = fs::remove_file(self.file(id));
+        Ok(())
+    }
+    fn gc(&self, now: i64) -> Result<()> {
+        create_private_dir(&self.dir)?;
+        let Ok(entries) = fs::read_dir(&self.dir) else { return Ok(()) };
+        for e in entries.flatten() {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let Some(id) = name.strip_prefix("sess_") else { continue };
+            if matches!(self.load(id), Ok(Some((_, exp))) if exp < now) { let _ = fs::remove_file(e.path()); }
+        }
+        Ok(())
+    }
+    fn secret_path(&self) -> PathBuf { self.dir.join(".secret") }
+}
+
+/// Create `dir` owner-only if it is missing, and refuse one that another user could write to or
+/// read: session files and the signing secret live there.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    if fs::symlink_metadata(dir).is_err() {
+        if let Some(parent) = dir.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| io_err(&parent.display().to_string(), e))?;
+        }
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        { use std::os::unix::fs::DirBuilderExt; builder.mode(0o700); }
+        // Another request may have made it first; the checks below still apply
+        if let Err(e) = builder.create(dir) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists { return Err(io_err(&dir.display().to_string(), e)); }
+        }
+    }
+    check_private_dir(dir)
+}
+
+#[cfg(unix)]
+fn check_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::symlink_metadata(dir).map_err(|e| io_err(&dir.display().to_string(), e))?;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o777 != 0o700 {
+        return Err(BasilError(format!(
+            "SESSION store: {} must be a directory owned by this user with mode 0700 (chmod 700 it, or pick another with BASIL_SESSION_STORE=files:dir)",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private_dir(dir: &Path) -> Result<()> {
+    if fs::metadata(dir).map(|m| m.is_dir()).unwrap_or(false) { Ok(()) } else { Err(BasilError(format!("SESSION store: {} is not a directory", dir.display()))) }
+}
+
+/// The default session folder, private to the user running the pages: under `XDG_RUNTIME_DIR`,
+/// else `~/.cache`, else a per-user folder in the temp dir.
+fn default_session_dir() -> PathBuf {
+    let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if let Some(run) = var("XDG_RUNTIME_DIR") { return run.join("basil-sessions"); }
+    if let Some(home) = var("HOME") { return home.join(".cache").join("basil").join("sessions"); }
+    #[cfg(unix)]
+    // SAFETY: getuid has no preconditions and cannot fail
+    { std::env::temp_dir().join(format!("basil-sessions-{}", unsafe { libc::getuid() })) }
+    #[cfg(not(unix))]
+    { std::env::temp_dir().join("basil-sessions") }
+}
+
+#[cfg(feature = "obj-sqlite")]
+struct SqliteStore { path: String }
+
+#[cfg(feature = "obj-sqlite")]
+impl SqliteStore {
+    // The obj-sqlite helpers take plain SQL text, so quote values ourselves
+    fn quote(s: &str) -> String { format!("'{}'", s.replace('\'', "''")) }
+
+    fn with<T>(&self, f: impl FnOnce(i64) -> Result<T>) -> Result<T> {
+        use basil_objects::sqlite;
+        let h = sqlite::sqlite_open(&self.path);
+        if h == 0 { return Err(BasilError(format!("SESSION store: cannot open SQLite database {}", self.path))); }
+        let res = (|| {
+            self.exec(h, "PRAGMA busy_timeout = 5000")?;
+            self.exec(h, "CREATE TABLE IF NOT EXISTS basil_sessions (id TEXT PRIMARY KEY, data TEXT NOT NULL, expires INTEGER NOT NULL)")?;
+            f(h)
+        })();
+        sqlite::sqlite_close(h);
+        res
+    }
+
+    fn exec(&self, h: i64, sql: &str) -> Result<()> {
+        // PRAGMA busy_timeout returns a row, which execute() reports as an error; run it as a query
+        if sql.starts_with("PRAGMA") { return basil_objects::sqlite::sqlite_query2d(h, sql).map(|_| ()); }
+        if basil_objects::sqlite::sqlite_exec(h, sql) < 0 {
+            return Err(BasilError(format!("SESSION store: SQLite statement failed on {}", self.path)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "obj-sqlite")]
+impl Store for SqliteStore {
+    fn load(&self, id: &str) -> Result<Option<(String, i64)>> {
+        self.with(|h| {
+            let sql = format!("SELECT data, expires FROM basil_sessions WHERE id = {}", Self::quote(id));
+            match basil_objects::sqlite::sqlite_query2d(h, &sql)? {
+                Value::StrArray2D { rows, data, .. } if rows > 0 => Ok(Some((data[0].clone(), data[1].parse().unwrap_or(0)))),
+                _ => Ok(None),
+            }
+        })
+    }
+    fn save(&self, id: &str, data: &str, expires: i64) -> Result<()> {
+        self.with(|h| self.exec(h, &format!("INSERT OR REPLACE INTO basil_sessions (id, data, expires) VALUES ({}, {}, {})", Self::quote(id), Self::quote(data), expires)))
+    }
+    fn delete(&self, id: &str) -> Result<()> {
+        self.with(|h| self.exec(h, &format!("DELETE FROM basil_sessions WHERE id = {}", Self::quote(id))))
+    }
+    fn gc(&self, now: i64) -> Result<()> {
+        self.with(|h| self.exec(h, &format!("DELETE FROM basil_sessions WHERE expires < {}", now)))
+    }
+    // Not next to the database, whose folder may be shared: in the user's private session folder
+    fn secret_path(&self) -> PathBuf { default_session_dir().join(".secret") }
+}
+
+fn open_store(spec: &str) -> Result<Box<dyn Store>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind.trim().to_ascii_lowercase().as_str() {
+        "" | "files" => {
+            let dir = if arg.is_empty() { default_session_dir() } else { PathBuf::from(arg) };
+            Ok(Box::new(FileStore { dir }))
+        }
+        #[cfg(feature = "obj-sqlite")]
+        "sqlite" if !arg.is_empty() => Ok(Box::new(SqliteStore { path: arg.to_string() })),
+        #[cfg(not(feature = "obj-sqlite"))]
+        "sqlite" => Err(BasilError("SESSION: the sqlite store needs a build with the obj-sqlite feature".into())),
+        _ => Err(BasilError(format!("SESSION: unknown store '{}' (use files[:dir] or sqlite:path)", spec))),
+    }
+}
+
+// --- Data encoding: one entry per line, `<tag><key>=<value>` with %-escaped key and value ---
+
+fn esc(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' => out.push_str("%25"),
+            '\n' => out.push_str("%0A"),
+            '\r' => out.push_str("%0D"),
+            '=' => out.push_str("%3D"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unesc(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[derive(Default)]
+struct Data {
+    values: HashMap<String, Value>,
+    flash: Vec<(String, String)>,
+    csrf: Option<String>,
+}
+
+fn encode(d: &Data) -> String {
+    let mut keys: Vec<&String> = d.values.keys().collect();
+    keys.sort();
+    let mut out = String::new();
+    for k in keys {
+        let (tag, v) = match &d.values[k] {
+            Value::Int(i) => ('I', i.to_string()),
+            Value::Num(n) => ('N', n.to_string()),
+            Value::Bool(b) => ('B', (*b as u8).to_string()),
+            other => ('S', format!("{}", other)),
+        };
+        out.push_str(&format!("{}{}={}\n", tag, esc(k), esc(&v)));
+    }
+    for (k, m) in &d.flash { out.push_str(&format!("F{}={}\n", esc(k), esc(m))); }
+    if let Some(t) = &d.csrf { out.push_str(&format!("C={}\n", t)); }
+    out
+}
+
+fn decode(text: &str) -> Data {
+    let mut d = Data::default();
+    for line in text.lines() {
+        let mut chars = line.chars();
+        let Some(tag) = chars.next() else { continue };
+        let Some((k, v)) = chars.as_str().split_once('=') else { continue };
+        let (k, v) = (unesc(k), unesc(v));
+        match tag {
+            'S' => { d.values.insert(k, Value::Str(v)); }
+            'I' => { d.values.insert(k, Value::Int(v.parse().unwrap_or(0))); }
+            'N' => { d.values.insert(k, Value::Num(v.parse().unwrap_or(0.0))); }
+            'B' => { d.values.insert(k, Value::Bool(v == "1")); }
+            'F' => d.flash.push((k, v)),
+            'C' => d.csrf = Some(v),
+            _ => {}
+        }
+    }
+    d
+}
+
+// --- Session state shared by SESSION@ and the VM (which saves it when the page ends) ---
+
+struct Active {
+    id: String,
+    data: Data,
+    // Flash messages from the previous request; shown once
+    incoming_flash: Vec<(String, String)>,
+    is_new: bool,
+}
+
+pub(crate) struct SessionState {
+    store_spec: String,
+    lifetime: i64,
+    cookie_name: String,
+    secret_env: Option<String>,
+    secure: bool,
+    request: Rc<RequestData>,
+    response: Rc<RefCell<ResponseState>>,
+    active: Option<Active>,
+    destroyed: bool,
+}
+
+impl SessionState {
+    /// `var` looks up CGI/environment variables (BASIL_SESSION_STORE, BASIL_SESSION_LIFETIME,
+    /// BASIL_SESSION_SECRET, HTTPS).
+    pub(crate) fn new(var: impl Fn(&str) -> String, request: Rc<RequestData>, response: Rc<RefCell<ResponseState>>) -> Self {
+        let secret = var("BASIL_SESSION_SECRET");
+        SessionState {
+            store_spec: var("BASIL_SESSION_STORE"),
+            lifetime: var("BASIL_SESSION_LIFETIME").parse().ok().filter(|n| *n > 0).unwrap_or(DEFAULT_LIFETIME),
+            cookie_name: DEFAULT_COOKIE.to_string(),
+            secret_env: (!secret.is_empty()).then_some(secret),
+            secure: var("HTTPS").eq_ignore_ascii_case("on"),
+            request,
+            response,
+            active: None,
+            destroyed: false,
+        }
+    }
+
+    fn secret(&self, store: &dyn Store) -> Result<Vec<u8>> {
+        if let Some(s) = &self.secret_env { return Ok(s.as_bytes().to_vec()); }
+        let path = store.secret_path();
+        // Only trust a key in a folder no other user can write to
+        create_private_dir(path.parent().ok_or_else(|| BasilError("SESSION: bad secret path".into()))?)?;
+        if let Ok(s) = fs::read_to_string(&path) {
+            if s.trim().len() >= 32 { return Ok(s.trim().as_bytes().to_vec()); }
+        }
+        let key = random_hex(32)?;
+        // create_new: if another request raced us, use the key it wrote
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        { use std::os::unix::fs::OpenOptionsExt; opts.mode(0o600); }
+        match opts.open(&path) {
+            Ok(mut f) => { f.write_all(key.as_bytes()).map_err(|e| io_err(&path.display().to_string(), e))?; Ok(key.into_bytes()) }
+            Err(_) => fs::read_to_string(&path).map(|s| s.trim().as_bytes().to_vec()).map_err(|e| io_err(&path.display().to_string(), e)),
+        }
+    }
+
+    fn send_cookie(&mut self, id: &str, secret: &[u8], what: &str) -> Result<()> {
+        let mut resp = self.response.borrow_mut();
+        if resp.sent {
+            return Err(BasilError(format!("SESSION.{}: headers were already sent; start the session before any output", what)));
+        }
+        let mut c = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", self.cookie_name, sign(secret, id));
+        if self.secure { c.push_str("; Secure"); }
+        resp.push_cookie(&self.cookie_name, "/", c);
+        Ok(())
+    }
+
+    /// Load the session named by the request cookie, or start a new one.
+    fn start(&mut self, what: &str) -> Result<&mut Active> {
+        if self.active.is_none() {
+            if self.destroyed { return Err(BasilError(format!("SESSION.{}: the session was destroyed", what))); }
+            let store = open_store(&self.store_spec)?;
+            let secret = self.secret(store.as_ref())?;
+            let existing = lookup(&self.request.cookies, &self.cookie_name)
+                .and_then(|c| verify(&secret, c))
+                .map(|id| store.load(&id).map(|found| found.filter(|(_, exp)| *exp >= now()).map(|(text, _)| (id, text))))
+                .transpose()?
+                .flatten();
+            let active = match existing {
+                Some((id, text)) => {
+                    let mut data = decode(&text);
+                    let incoming_flash = std::mem::take(&mut data.flash);
+                    Active { id, data, incoming_flash, is_new: false }
+                }
+                None => {
+                    let id = random_hex(16)?;
+                    self.send_cookie(&id, &secret, what)?;
+                    Active { id, data: Data::default(), incoming_flash: Vec::new(), is_new: true }
+                }
+            };
+            self.active = Some(active);
+        }
+        Ok(self.active.as_mut().expect("session started"))
+    }
+
+    /// Save the session when the page ends (and now and then drop expired ones).
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        let Some(active) = &self.active else { return Ok(()) };
+        // A brand-new session with nothing in it is not worth a store entry
+        if active.is_new && active.data.values.is_empty() && active.data.flash.is_empty() && active.data.csrf.is_none() {
+            return Ok(());
+        }
+        let store = open_store(&self.store_spec)?;
+        store.save(&active.id, &encode(&active.data), now() + self.lifetime)?;
+        let mut dice = [0u8; 1];
+        if getrandom::getrandom(&mut dice).is_ok() && dice[0] < 3 { store.gc(now())?; }
+        Ok(())
+    }
+
+    fn regenerate(&mut self) -> Result<()> {
+        let store = open_store(&self.store_spec)?;
+        let secret = self.secret(store.as_ref())?;
+        let old = self.start("Regenerate")?.id.clone();
+        let id = random_hex(16)?;
+        self.send_cookie(&id, &secret, "Regenerate")?;
+        store.delete(&old)?;
+        let active = self.active.as_mut().expect("session started");
+        active.id = id;
+        // The new ID must be saved even if the session held nothing yet
+        active.is_new = false;
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        if let Some(active) = self.active.take() { open_store(&self.store_spec)?.delete(&active.id)?; }
+        let cookie_sent = lookup(&self.request.cookies, &self.cookie_name).is_some();
+        let mut resp = self.response.borrow_mut();
+        if cookie_sent && !resp.sent {
+            resp.push_cookie(&self.cookie_name, "/", format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax", self.cookie_name));
+        }
+        self.destroyed = true;
+        Ok(())
+    }
+
+    fn csrf_token(&mut self) -> Result<String> {
+        let active = self.start("CsrfToken$")?;
+        if active.data.csrf.is_none() { active.data.csrf = Some(random_hex(32)?); }
+        Ok(active.data.csrf.clone().unwrap_or_default())
+    }
+}
+
+pub(crate) struct SessionObj {
+    pub state: Rc<RefCell<SessionState>>,
+}
+
+fn scalar(v: &Value, what: &str) -> Result<Value> {
+    match v {
+        Value::Str(_) | Value::Int(_) | Value::Num(_) | Value::Bool(_) => Ok(v.clone()),
+        _ => Err(BasilError(format!("{}: session values must be strings, numbers or booleans", what))),
+    }
+}
+
+impl BasicObject for SessionObj {
+    fn type_name(&self) -> &str { "SESSION" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let mut st = self.state.borrow_mut();
+        Ok(match bare(name).as_str() {
+            "STORE" => Value::Str(st.store_spec.clone()),
+            "LIFETIME" => Value::Int(st.lifetime),
+            "COOKIENAME" => Value::Str(st.cookie_name.clone()),
+            "ID" => Value::Str(st.start("Id$")?.id.clone()),
+            "ISNEW" => Value::Bool(st.start("IsNew")?.is_new),
+            "COUNT" => Value::Int(st.start("Count%")?.data.values.len() as i64),
+            "KEYS" => {
+                let mut keys: Vec<String> = st.start("Keys")?.data.values.keys().cloned().collect();
+                keys.sort();
+                Value::List(Rc::new(RefCell::new(keys.into_iter().map(Value::Str).collect())))
+            }
+            _ => return Err(BasilError(format!("Unknown property '{}' on SESSION", name))),
+        })
+    }
+    fn set_prop(&mut self, name: &str, v: Value) -> Result<()> {
+        let mut st = self.state.borrow_mut();
+        let key = bare(name);
+        if matches!(key.as_str(), "STORE" | "LIFETIME" | "COOKIENAME") && st.active.is_some() {
+            return Err(BasilError(format!("SESSION.{}: set it before the session is first used", name)));
+        }
+        match key.as_str() {
+            "STORE" => { open_store(&format!("{}", v))?; st.store_spec = format!("{}", v); }
+            "LIFETIME" => {
+                st.lifetime = int_arg(&[v], 0, "SESSION.Lifetime%")?.filter(|n| *n > 0)
+                    .ok_or_else(|| BasilError("SESSION.Lifetime% must be a positive number of seconds".into()))?;
+            }
+            "COOKIENAME" => {
+                let n = format!("{}", v);
+                if n.is_empty() || !n.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+                    return Err(BasilError(format!("SESSION.CookieName$: invalid cookie name '{}'", n)));
+                }
+                st.cookie_name = n;
+            }
+            _ => return Err(BasilError(format!("Property '{}' on SESSION is read-only", name))),
+        }
+        Ok(())
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        let mut st = self.state.borrow_mut();
+        let m = bare(method);
+        match m.as_str() {
+            "START" => { st.start("Start")?; }
+            "GET" => {
+                let key = str_arg(args, 0, "SESSION.Get")?;
+                let found = st.start("Get")?.data.values.get(&key).cloned();
+                return Ok(found.or_else(|| args.get(1).cloned()).unwrap_or(Value::Str(String::new())));
+            }
+            "SET" => {
+                let key = str_arg(args, 0, "SESSION.Set")?;
+                let v = scalar(args.get(1).unwrap_or(&Value::Null), "SESSION.Set")?;
+                st.start("Set")?.data.values.insert(key, v);
+            }
+            "HAS" => {
+                let key = str_arg(args, 0, "SESSION.Has")?;
+                return Ok(Value::Bool(st.start("Has")?.data.values.contains_key(&key)));
+            }
+            "REMOVE" => {
+                let key = str_arg(args, 0, "SESSION.Remove")?;
+                st.start("Remove")?.data.values.remove(&key);
+            }
+            "CLEAR" => { st.start("Clear")?.data.values.clear(); }
+            "REGENERATE" => st.regenerate()?,
+            "DESTROY" => st.destroy()?,
+            "FLASH" => {
+                let key = str_arg(args, 0, "SESSION.Flash")?;
+                let msg = str_arg(args, 1, "SESSION.Flash")?;
+                st.start("Flash")?.data.flash.push((key, msg));
+            }
+            "HASFLASH" => {
+                let key = str_arg(args, 0, "SESSION.HasFlash")?;
+                let a = st.start("HasFlash")?;
+                return Ok(Value::Bool(a.incoming_flash.iter().chain(a.data.flash.iter()).any(|(k, _)| *k == key)));
+            }
+            "GETFLASH" => {
+                // Reading a message consumes it, whether it came from the last request or this one
+                let key = str_arg(args, 0, "SESSION.GetFlash$")?;
+                let dflt = if args.len() > 1 { str_arg(args, 1, "SESSION.GetFlash$")? } else { String::new() };
+                let a = st.start("GetFlash$")?;
+                let take = |list: &mut Vec<(String, String)>| list.iter().position(|(k, _)| *k == key).map(|i| list.remove(i).1);
+                let msg = take(&mut a.incoming_flash).or_else(|| take(&mut a.data.flash));
+                return Ok(Value::Str(msg.unwrap_or(dflt)));
+            }
+            "CSRFTOKEN" => return Ok(Value::Str(st.csrf_token()?)),
+            "CSRFFIELD" => {
+                let t = st.csrf_token()?;
+                return Ok(Value::Str(format!("<input type=\"hidden\" name=\"{}\" value=\"{}\">", CSRF_FIELD, t)));
+            }
+            "VERIFYCSRF" => {
+                let sent = if !args.is_empty() { str_arg(args, 0, "SESSION.VerifyCsrf")? } else {
+                    let req = st.request.clone();
+                    lookup(&req.form, CSRF_FIELD)
+                        .or_else(|| req.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("X-CSRF-Token")).map(|(_, v)| v.as_str()))
+                        .unwrap_or("").to_string()
+                };
+                let expected = st.start("VerifyCsrf")?.data.csrf.clone().unwrap_or_default();
+                return Ok(Value::Bool(!expected.is_empty() && same(&sent, &expected)));
+            }
+            _ => return Err(BasilError(format!("Unknown method '{}' on SESSION", method))),
+        }
+        Ok(Value::Null)
+    }
+    fn descriptor(&self) -> ObjectDescriptor { session_descriptor() }
+}
+
+pub(crate) fn session_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "SESSION".to_string(),
+        version: "1.0".to_string(),
+        summary: "Server-side session for web pages, identified by a signed cookie".to_string(),
+        properties: vec![
+            prop("Id$", "String", false),
+            prop("IsNew", "Bool", false),
+            prop("Count%", "Integer", false),
+            prop("Keys", "List", false),
+            prop("Store$", "String", true),
+            prop("Lifetime%", "Integer", true),
+            prop("CookieName$", "String", true),
+        ],
+        methods: vec![
+            method("Start", &[], "Void"),
+            method("Get", &["key$", "[default]"], "Any"),
+            method("Set", &["key$", "value"], "Void"),
+            method("Has", &["key$"], "Bool"),
+            method("Remove", &["key$"], "Void"),
+            method("Clear", &[], "Void"),
+            method("Regenerate", &[], "Void"),
+            method("Destroy", &[], "Void"),
+            method("Flash", &["key$", "message$"], "Void"),
+            method("HasFlash", &["key$"], "Bool"),
+            method("GetFlash$", &["key$", "[default$]"], "String"),
+            method("CsrfToken$", &[], "String"),
+            method("CsrfField$", &[], "String"),
+            method("VerifyCsrf", &["[token$]"], "Bool"),
+        ],
+        examples: vec![
+            "SESSION@.Set(\"user\", name$)".to_string(),
+            "SESSION@.Regenerate()   ' after a successful login".to_string(),
+            "<form method=\"post\"><?! SESSION@.CsrfField$() ?> ...".to_string(),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_ids_reject_tampering() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let id = "00112233445566778899aabbccddeeff";
+        let cookie = sign(secret, id);
+        assert_eq!(verify(secret, &cookie).as_deref(), Some(id));
+        let forged = format!("f{}", &cookie[1..]);
+        assert_eq!(verify(secret, &forged), None);
+        assert_eq!(verify(b"another key", &cookie), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn store_folder_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!("basil_sess_test_{}", random_hex(8).unwrap()));
+        let dir = root.join("sessions");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+        // A folder someone else could have made (or could write into) is refused, not trusted
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        let err = create_private_dir(&dir).unwrap_err();
+        assert!(err.0.contains("mode 0700"), "{}", err);
+        let store = FileStore { dir: dir.clone() };
+        assert!(store.load("00112233445566778899aabbccddeeff").is_err());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn data_round_trips() {
+        let mut d = Data::default();
+        d.values.insert("user=name".into(), Value::Str("50% off\nnext line +1".into()));
+        d.values.insert("n".into(), Value::Int(7));
+        d.values.insert("ok".into(), Value::Bool(true));
+        d.flash.push(("notice".into(), "Saved!".into()));
+        d.csrf = Some("abc".into());
+        let back = decode(&encode(&d));
+        assert_eq!(back.values.get("user=name"), Some(&Value::Str("50% off\nnext line +1".into())));
+        assert_eq!(back.values.get("n"), Some(&Value::Int(7)));
+        assert_eq!(back.values.get("ok"), Some(&Value::Bool(true)));
+        assert_eq!(back.flash, vec![("notice".to_string(), "Saved!".to_string())]);
+        assert_eq!(back.csrf.as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,562 @@
+//! Persistent VM snapshots: save a suspended program to bytes and continue it later.
+//!
+//! `VM::snapshot()` captures everything the interpreter needs to pick up where it left off:
+//! frames (with their code) and their callers' lines, the value stack, globals, FOR EACH
+//! enumerators, the GOSUB and TRY stacks and struct type descriptors. Arrays, lists and dicts keep their sharing, so two
+//! globals that pointed at the same list still do after `VM::restore()`.
+//!
+//! Objects are re-created by type name through the registry and their writable properties are
+//! copied back. Objects that wrap live state (CLASS instances, TASK, CHANNEL, PROMISE), objects
+//! whose type needs constructor arguments, and open file handles cannot be saved; snapshot()
+//! reports them instead of writing a broken file.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use basil_bytecode::{ArrayObj, Chunk, ElemType, Function, ObjectRef, Op, Program as BCProgram, Value};
+use basil_common::{BasilError, Result};
+
+use crate::{ArrEnum, Frame, HandlerEntry, VMFieldDesc, VMFieldKind, VMTypeDesc, VM};
+
+const MAGIC: &[u8; 4] = b"BSNP";
+const VERSION: u32 = 3;
+
+// Object types whose state lives outside the VM and cannot be rebuilt from properties
+const LIVE_TYPES: &[&str] = &["CLASS", "TASK", "CHANNEL", "PROMISE"];
+
+// Value tags
+const T_NULL: u8 = 0;
+const T_BOOL: u8 = 1;
+const T_NUM: u8 = 2;
+const T_INT: u8 = 3;
+const T_STR: u8 = 4;
+const T_FUNC: u8 = 5;
+const T_ARRAY: u8 = 6;
+const T_LIST: u8 = 7;
+const T_DICT: u8 = 8;
+const T_OBJECT: u8 = 9;
+const T_STRARRAY2D: u8 = 10;
+const T_REF: u8 = 11; // back-reference to an array/list/dict/object/chunk already written
+
+struct Writer<'a> {
+    vm: &'a VM,
+    buf: Vec<u8>,
+    // Rc address -> id, shared by containers, objects and chunks
+    ids: HashMap<usize, u32>,
+    next_id: u32,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, v: u8) { self.buf.push(v); }
+    fn u32(&mut self, v: u32) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    fn u64(&mut self, v: u64) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    fn str(&mut self, s: &str) { self.u32(s.len() as u32); self.buf.extend_from_slice(s.as_bytes()); }
+
+    // Writes a back-reference and returns true if `addr` was seen before; otherwise assigns it an id
+    fn seen(&mut self, addr: usize) -> bool {
+        if let Some(id) = self.ids.get(&addr).copied() {
+            self.u8(T_REF);
+            self.u32(id);
+            return true;
+        }
+        self.ids.insert(addr, self.next_id);
+        self.next_id += 1;
+        false
+    }
+
+    fn chunk(&mut self, c: &Rc<Chunk>) -> Result<()> {
+        if self.seen(Rc::as_ptr(c) as usize) { return Ok(()); }
+        self.u8(0);
+        self.u32(c.code.len() as u32);
+        self.buf.extend_from_slice(&c.code);
+        self.u32(c.consts.len() as u32);
+        for v in &c.consts { self.value(v)?; }
+        Ok(())
+    }
+
+    fn value(&mut self, v: &Value) -> Result<()> {
+        match v {
+            Value::Null => self.u8(T_NULL),
+            Value::Bool(b) => { self.u8(T_BOOL); self.u8(*b as u8); }
+            Value::Num(n) => { self.u8(T_NUM); self.buf.extend_from_slice(&n.to_le_bytes()); }
+            Value::Int(i) => { self.u8(T_INT); self.buf.extend_from_slice(&i.to_le_bytes()); }
+            Value::Str(s) => { self.u8(T_STR); self.str(s); }
+            Value::Func(f) => {
+                self.u8(T_FUNC);
+                self.u8(f.arity);
+                match &f.name { Some(n) => { self.u8(1); self.str(n); } None => self.u8(0) }
+                self.chunk(&f.chunk)?;
+            }
+            Value::Array(a) => {
+                if self.seen(Rc::as_ptr(a) as usize) { return Ok(()); }
+                self.u8(T_ARRAY);
+                match &a.elem {
+                    ElemType::Num => self.u8(0),
+                    ElemType::Int => self.u8(1),
+                    ElemType::Str => self.u8(2),
+                    ElemType::Obj(t) => {
+                        self.u8(3);
+                        match t { Some(t) => { self.u8(1); self.str(t); } None => self.u8(0) }
+                    }
+                }
+                self.u32(a.dims.len() as u32);
+                for d in &a.dims { self.u64(*d as u64); }
+                let data = a.data.borrow();
+                self.u32(data.len() as u32);
+                for x in data.iter() { self.value(x)?; }
+            }
+            Value::List(l) => {
+                if self.seen(Rc::as_ptr(l) as usize) { return Ok(()); }
+                self.u8(T_LIST);
+                let items = l.borrow();
+                self.u32(items.len() as u32);
+                for x in items.iter() { self.value(x)?; }
+            }
+            Value::Dict(d) => {
+                if self.seen(Rc::as_ptr(d) as usize) { return Ok(()); }
+                self.u8(T_DICT);
+                let map = d.borrow();
+                // Sorted keys keep snapshots of the same state byte-identical
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                self.u32(keys.len() as u32);
+                for k in keys { self.str(k); self.value(&map[k])?; }
+            }
+            Value::Object(o) => {
+                if self.seen(Rc::as_ptr(o) as *const () as usize) { return Ok(()); }
+                self.u8(T_OBJECT);
+                self.object(o)?;
+            }
+            Value::StrArray2D { rows, cols, data } => {
+                self.u8(T_STRARRAY2D);
+                self.u64(*rows as u64);
+                self.u64(*cols as u64);
+                self.u32(data.len() as u32);
+                for s in data { self.str(s); }
+            }
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, o: &ObjectRef) -> Result<()> {
+        let obj = o.borrow();
+        let tn = obj.type_name().to_string();
+        if LIVE_TYPES.iter().any(|t| t.eq_ignore_ascii_case(&tn)) || !self.vm.registry.has_type(&tn) {
+            return Err(BasilError(format!("snapshot: objects of type {} cannot be saved", tn)));
+        }
+        // restore() re-creates objects without constructor arguments; refuse types that need them
+        // now rather than when the snapshot is resumed
+        if let Err(e) = self.vm.registry.make(&tn, &[]) {
+            return Err(BasilError(format!("snapshot: objects of type {} cannot be saved: they need constructor arguments ({})", tn, e)));
+        }
+        let desc = obj.descriptor();
+        let mut props = Vec::new();
+        for p in desc.properties.iter().filter(|p| p.readable && p.writable) {
+            let v = obj.get_prop(&p.name)
+                .map_err(|e| BasilError(format!("snapshot: reading {}.{}: {}", tn, p.name, e)))?;
+            props.push((p.name.clone(), v));
+        }
+        drop(obj);
+        self.str(&tn);
+        self.u32(props.len() as u32);
+        for (name, v) in &props { self.str(name); self.value(v)?; }
+        Ok(())
+    }
+
+    fn field_kind(&mut self, k: &VMFieldKind) {
+        match k {
+            VMFieldKind::Int32 => self.u8(0),
+            VMFieldKind::Float64 => self.u8(1),
+            VMFieldKind::VarString => self.u8(2),
+            VMFieldKind::FixedString(n) => { self.u8(3); self.u64(*n as u64); }
+            VMFieldKind::Struct(t) => { self.u8(4); self.str(t); }
+        }
+    }
+}
+
+struct Reader<'a> {
+    vm: &'a VM,
+    data: &'a [u8],
+    pos: usize,
+    // id -> restored value (chunks are kept separately since they are not Values)
+    table: Vec<Slot>,
+}
+
+enum Slot {
+    Value(Value),
+    Chunk(Rc<Chunk>),
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.data.len() { return Err(BasilError("snapshot: unexpected end of data".into())); }
+        let s = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+    fn u8(&mut self) -> Result<u8> { Ok(self.take(1)?[0]) }
+    fn u32(&mut self) -> Result<u32> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+    fn u64(&mut self) -> Result<u64> { Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+    fn usize(&mut self) -> Result<usize> { Ok(self.u64()? as usize) }
+    fn str(&mut self) -> Result<String> {
+        let n = self.u32()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|e| BasilError(format!("snapshot: bad string: {}", e)))
+    }
+
+    fn back_ref(&mut self) -> Result<&Slot> {
+        let id = self.u32()? as usize;
+        self.table.get(id).ok_or_else(|| BasilError("snapshot: dangling reference".into()))
+    }
+
+    fn chunk(&mut self) -> Result<Rc<Chunk>> {
+        match self.u8()? {
+            T_REF => match self.back_ref()? {
+                Slot::Chunk(c) => Ok(c.clone()),
+                Slot::Value(_) => Err(BasilError("snapshot: reference is not code".into())),
+            },
+            0 => {
+                // Reserve the id before reading consts so ids line up with the writer
+                let id = self.table.len();
+                self.table.push(Slot::Value(Value::Null));
+                let n = self.u32()? as usize;
+                let code = self.take(n)?.to_vec();
+                let nconst = self.u32()? as usize;
+                let mut consts = Vec::with_capacity(nconst);
+                for _ in 0..nconst { consts.push(self.value()?); }
+                let c = Rc::new(Chunk { code, consts });
+                self.table[id] = Slot::Chunk(c.clone());
+                Ok(c)
+            }
+            t => Err(BasilError(format!("snapshot: bad chunk tag {}", t))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let tag = self.u8()?;
+        Ok(match tag {
+            T_NULL => Value::Null,
+            T_BOOL => Value::Bool(self.u8()? != 0),
+            T_NUM => Value::Num(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            T_INT => Value::Int(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            T_STR => Value::Str(self.str()?),
+            T_FUNC => {
+                let arity = self.u8()?;
+                let name = if self.u8()? != 0 { Some(self.str()?) } else { None };
+                let chunk = self.chunk()?;
+                Value::Func(Rc::new(Function { arity, name, chunk }))
+            }
+            T_ARRAY => {
+                let elem = match self.u8()? {
+                    0 => ElemType::Num,
+                    1 => ElemType::Int,
+                    2 => ElemType::Str,
+                    3 => ElemType::Obj(if self.u8()? != 0 { Some(self.str()?) } else { None }),
+                    t => return Err(BasilError(format!("snapshot: bad array element tag {}", t))),
+                };
+                let nd = self.u32()? as usize;
+                let mut dims = Vec::with_capacity(nd);
+                for _ in 0..nd { dims.push(self.usize()?); }
+                let arr = Rc::new(ArrayObj { elem, dims, data: RefCell::new(Vec::new()) });
+                self.table.push(Slot::Value(Value::Array(arr.clone())));
+                let n = self.u32()? as usize;
+                let mut data = Vec::with_capacity(n);
+                for _ in 0..n { data.push(self.value()?); }
+                *arr.data.borrow_mut() = data;
+                Value::Array(arr)
+            }
+            T_LIST => {
+                let list = Rc::new(RefCell::new(Vec::new()));
+                self.table.push(Slot::Value(Value::List(list.clone())));
+                let n = self.u32()? as usize;
+                for _ in 0..n { let v = self.value()?; list.borrow_mut().push(v); }
+                Value::List(list)
+            }
+            T_DICT => {
+                let map = Rc::new(RefCell::new(HashMap::new()));
+                self.table.push(Slot::Value(Value::Dict(map.clone())));
+                let n = self.u32()? as usize;
+                for _ in 0..n { let k = self.str()?; let v = self.value()?; map.borrow_mut().insert(k, v); }
+                Value::Dict(map)
+            }
+            T_OBJECT => {
+                let id = self.table.len();
+                self.table.push(Slot::Value(Value::Null));
+                let tn = self.str()?;
+                let obj = self.vm.registry.make(&tn, &[])
+                    .map_err(|e| BasilError(format!("snapshot: cannot re-create {} object: {}", tn, e)))?;
+                let n = self.u32()? as usize;
+                for _ in 0..n {
+                    let name = self.str()?;
+                    let v = self.value()?;
+                    obj.borrow_mut().set_prop(&name, v)
+                        .map_err(|e| BasilError(format!("snapshot: restoring {}.{}: {}", tn, name, e)))?;
+                }
+                self.table[id] = Slot::Value(Value::Object(obj.clone()));
+                Value::Object(obj)
+            }
+            T_STRARRAY2D => {
+                let rows = self.usize()?;
+                let cols = self.usize()?;
+                let n = self.u32()? as usize;
+                let mut data = Vec::with_capacity(n);
+                for _ in 0..n { data.push(self.str()?); }
+                Value::StrArray2D { rows, cols, data }
+            }
+            T_REF => match self.back_ref()? {
+                Slot::Value(v) => v.clone(),
+                Slot::Chunk(_) => return Err(BasilError("snapshot: reference is not a value".into())),
+            },
+            t => return Err(BasilError(format!("snapshot: bad value tag {}", t))),
+        })
+    }
+
+    fn field_kind(&mut self) -> Result<VMFieldKind> {
+        Ok(match self.u8()? {
+            0 => VMFieldKind::Int32,
+            1 => VMFieldKind::Float64,
+            2 => VMFieldKind::VarString,
+            3 => VMFieldKind::FixedString(self.usize()?),
+            4 => VMFieldKind::Struct(self.str()?),
+            t => return Err(BasilError(format!("snapshot: bad field kind {}", t))),
+        })
+    }
+}
+
+impl VM {
+    /// Serialize the complete program state. Usually called on a suspended VM (after STOP).
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        if !self.file_table.is_empty() {
+            return Err(BasilError("snapshot: open file handles cannot be saved; CLOSE them first".into()));
+        }
+        if self.pending_async.iter().any(|p| !p.borrow().get_prop("Done").map(|v| v == Value::Bool(true)).unwrap_or(true)) {
+            return Err(BasilError("snapshot: ASYNC calls are still running; AWAIT them first".into()));
+        }
+        let mut w = Writer { vm: self, buf: Vec::new(), ids: HashMap::new(), next_id: 0 };
+        w.buf.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.u8(self.suspended as u8);
+        w.u32(self.current_line);
+        match &self.script_path { Some(p) => { w.u8(1); w.str(p); } None => w.u8(0) }
+
+        w.u32(self.global_names.len() as u32);
+        for (name, v) in self.global_names.iter().zip(self.globals.iter()) {
+            w.str(name);
+            w.value(v).map_err(|e| BasilError(format!("{} (global {})", e, name)))?;
+        }
+        w.u32(self.stack.len() as u32);
+        for v in &self.stack { w.value(v)?; }
+        w.u32(self.frames.len() as u32);
+        for f in &self.frames {
+            w.chunk(&f.chunk)?;
+            w.u64(f.ip as u64);
+            w.u64(f.base as u64);
+        }
+        w.u32(self.call_lines.len() as u32);
+        for (depth, line) in &self.call_lines { w.u64(*depth as u64); w.u32(*line); }
+        w.u32(self.enums.len() as u32);
+        for e in &self.enums {
+            w.value(&Value::Array(e.arr.clone()))?;
+            w.u64(e.cur as i64 as u64);
+            w.u64(e.total as u64);
+        }
+        w.u32(self.gosub_stack.len() as u32);
+        for ip in &self.gosub_stack { w.u64(*ip as u64); }
+        w.u32(self._handlers.len() as u32);
+        for h in &self._handlers {
+            w.u64(h.handler_ip as u64);
+            w.u64(h.depth as u64);
+            w.u64(h.stack_len as u64);
+            w.u64(h.call_lines as u64);
+        }
+        match &self.current_exception { Some(m) => { w.u8(1); w.str(m); } None => w.u8(0) }
+        let mut types: Vec<(&String, &VMTypeDesc)> = self.struct_types.iter().collect();
+        types.sort_by(|a, b| a.0.cmp(b.0));
+        w.u32(types.len() as u32);
+        for (name, desc) in types {
+            w.str(name);
+            w.u32(desc.fields.len() as u32);
+            for f in &desc.fields { w.str(&f.name); w.field_kind(&f.kind); }
+        }
+        Ok(w.buf)
+    }
+
+    /// Rebuild a VM from `snapshot()` bytes. Call `resume()` on the result to continue a
+    /// program that was saved while suspended.
+    pub fn restore(bytes: &[u8]) -> Result<VM> {
+        if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+            return Err(BasilError("snapshot: not a Basil snapshot file".into()));
+        }
+        let ver = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if ver != VERSION {
+            return Err(BasilError(format!("snapshot: unsupported version {} (expected {})", ver, VERSION)));
+        }
+        // Start from an empty VM so the registry and defaults are set up as usual
+        let mut top = Chunk::default();
+        top.push_op(Op::Halt);
+        let mut vm = VM::new(BCProgram { chunk: top, globals: Vec::new() });
+
+        let mut r = Reader { vm: &vm, data: bytes, pos: 8, table: Vec::new() };
+        let suspended = r.u8()? != 0;
+        let current_line = r.u32()?;
+        let script_path = if r.u8()? != 0 { Some(r.str()?) } else { None };
+
+        let n = r.u32()? as usize;
+        let mut global_names = Vec::with_capacity(n);
+        let mut globals = Vec::with_capacity(n);
+        for _ in 0..n { global_names.push(r.str()?); globals.push(r.value()?); }
+        let n = r.u32()? as usize;
+        let mut stack = Vec::with_capacity(n);
+        for _ in 0..n { stack.push(r.value()?); }
+        let n = r.u32()? as usize;
+        let mut frames = Vec::with_capacity(n);
+        for _ in 0..n {
+            let chunk = r.chunk()?;
+            let ip = r.usize()?;
+            let base = r.usize()?;
+            frames.push(Frame { chunk, ip, base });
+        }
+        let n = r.u32()? as usize;
+        let mut call_lines = Vec::with_capacity(n);
+        for _ in 0..n { let depth = r.usize()?; call_lines.push((depth, r.u32()?)); }
+        let n = r.u32()? as usize;
+        let mut enums = Vec::with_capacity(n);
+        for _ in 0..n {
+            let arr = match r.value()? { Value::Array(a) => a, _ => return Err(BasilError("snapshot: bad enumerator".into())) };
+            let cur = r.u64()? as i64 as isize;
+            let total = r.usize()?;
+            enums.push(ArrEnum { arr, cur, total });
+        }
+        let n = r.u32()? as usize;
+        let mut gosub_stack = Vec::with_capacity(n);
+        for _ in 0..n { gosub_stack.push(r.usize()?); }
+        let n = r.u32()? as usize;
+        let mut handlers = Vec::with_capacity(n);
+        for _ in 0..n {
+            let (handler_ip, depth, stack_len, call_lines) = (r.usize()?, r.usize()?, r.usize()?, r.usize()?);
+            handlers.push(HandlerEntry { handler_ip, depth, stack_len, call_lines });
+        }
+        let current_exception = if r.u8()? != 0 { Some(r.str()?) } else { None };
+        let n = r.u32()? as usize;
+        let mut struct_types = HashMap::new();
+        for _ in 0..n {
+            let name = r.str()?;
+            let nf = r.u32()? as usize;
+            let mut fields = Vec::with_capacity(nf);
+            for _ in 0..nf { let fname = r.str()?; let kind = r.field_kind()?; fields.push(VMFieldDesc { name: fname, kind }); }
+            struct_types.insert(name, VMTypeDesc { fields });
+        }
+        if r.pos != bytes.len() {
+            return Err(BasilError("snapshot: trailing data".into()));
+        }
+        if frames.is_empty() {
+            return Err(BasilError("snapshot: no call frames".into()));
+        }
+
+        vm.suspended = suspended;
+        vm.current_line = current_line;
+        vm.script_path = script_path;
+        vm.global_names = global_names;
+        vm.globals = globals;
+        vm.stack = stack;
+        vm.frames = frames;
+        vm.call_lines = call_lines;
+        vm.enums = enums;
+        vm.gosub_stack = gosub_stack;
+        vm._handlers = handlers;
+        vm.current_exception = current_exception;
+        vm.struct_types = struct_types;
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basil_compiler::compile;
+    use basil_parser::parse;
+
+    fn global(vm: &VM, name: &str) -> Value {
+        let (names, vals) = vm.globals_snapshot();
+        let i = names.iter().position(|n| n.eq_ignore_ascii_case(name)).expect("global");
+        vals[i].clone()
+    }
+
+    #[test]
+    fn resumes_after_stop_from_bytes() {
+        let src = "FUNC Add(a, b)\n RETURN a + b;\nEND\n\
+                   LET l@ = [1, 2];\nLET alias@ = l@;\nLET d@ = {\"k\": \"v\"};\n\
+                   FOR i% = 1 TO 3 {\n IF i% = 2 THEN STOP;\n LET l@[1] = l@[1] + i%;\n}\nNEXT\n\
+                   LET s = Add(10, 5);\nLET n = alias@[1];\n";
+        let mut vm = VM::new(compile(&parse(src).unwrap()).unwrap());
+        vm.run().unwrap();
+        assert!(vm.is_suspended());
+        let bytes = vm.snapshot().unwrap();
+        drop(vm);
+
+        let mut vm = VM::restore(&bytes).unwrap();
+        assert!(vm.is_suspended());
+        vm.resume().unwrap();
+        assert_eq!(global(&vm, "s"), Value::Num(15.0));
+        // l@ and alias@ still share one list, and the loop picked up where it stopped: 1 + 1 + 2 + 3
+        assert_eq!(global(&vm, "n"), Value::Num(7.0));
+        match global(&vm, "d@") {
+            Value::Dict(d) => assert_eq!(d.borrow().get("k"), Some(&Value::Str("v".into()))),
+            other => panic!("expected dict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stopped_inside_a_func_returns_to_the_callers_line() {
+        let src = "FUNC Slow()\n STOP;\n RETURN 1;\nEND\nLET x = Slow();\n";
+        let mut vm = VM::new(compile(&parse(src).unwrap()).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.current_line(), 2);
+        let mut vm = VM::restore(&vm.snapshot().unwrap()).unwrap();
+        vm.resume().unwrap();
+        assert_eq!(global(&vm, "x"), Value::Num(1.0));
+        // RET put the VM back on the line of the call
+        assert_eq!(vm.current_line(), 5);
+    }
+
+    #[test]
+    fn live_objects_are_rejected() {
+        let src = "DIM ch@ AS CHANNEL();\nSTOP;\n";
+        let mut vm = VM::new(compile(&parse(src).unwrap()).unwrap());
+        vm.run().unwrap();
+        let err = vm.snapshot().unwrap_err();
+        assert!(err.0.contains("CHANNEL"), "{}", err);
+    }
+
+    struct NeedsSize(i64);
+
+    impl basil_bytecode::BasicObject for NeedsSize {
+        fn type_name(&self) -> &str { "SIZED" }
+        fn get_prop(&self, _: &str) -> Result<Value> { Ok(Value::Int(self.0)) }
+        fn set_prop(&mut self, _: &str, _: Value) -> Result<()> { Ok(()) }
+        fn call(&mut self, _: &str, _: &[Value]) -> Result<Value> { Ok(Value::Null) }
+        fn descriptor(&self) -> basil_bytecode::ObjectDescriptor { sized_descriptor() }
+    }
+
+    fn sized_descriptor() -> basil_bytecode::ObjectDescriptor {
+        basil_bytecode::ObjectDescriptor { type_name: "SIZED".into(), version: "1".into(), summary: String::new(), properties: Vec::new(), methods: Vec::new(), examples: Vec::new() }
+    }
+
+    #[test]
+    fn objects_needing_constructor_args_are_rejected_when_saved() {
+        let factory: fn(&[Value]) -> Result<ObjectRef> = |args| match args.first() {
+            Some(Value::Num(n)) => Ok(Rc::new(RefCell::new(NeedsSize(*n as i64)))),
+            _ => Err(BasilError("SIZED(n) needs a size".into())),
+        };
+        let mut vm = VM::new(compile(&parse("DIM s@ AS SIZED(3);\nSTOP;\n").unwrap()).unwrap());
+        vm.registry.register("SIZED", basil_objects::TypeInfo { factory, descriptor: sized_descriptor, constants: Vec::new });
+        vm.run().unwrap();
+        let err = vm.snapshot().unwrap_err();
+        assert!(err.0.contains("SIZED") && err.0.contains("constructor arguments"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(VM::restore(b"nope").is_err());
+    }
+}
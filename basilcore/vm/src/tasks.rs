@@ -0,0 +1,731 @@
+//! Concurrency support: SPAWN tasks, CHANNEL objects and value transfer between task VMs.
+//!
+//! `Value` is built on `Rc`/`RefCell`, so a value can never be shared between threads.
+//! Each task therefore runs in its own VM on a thread from a bounded worker pool, and
+//! everything that crosses a task boundary (arguments, globals, results, channel messages)
+//! is deep-copied through `SendValue`. CHANNEL and TASK objects are the only objects that
+//! can cross; both sides end up holding the same underlying queue or completion slot.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use basil_common::{Result, BasilError};
+use basil_bytecode::{
+    serialize_program, deserialize_program, ArrayObj, BasicObject, Chunk, ElemType, Function, MethodDesc,
+    ObjectDescriptor, ObjectRef, Op, Program as BCProgram, PropDesc, Value,
+};
+use basil_objects::{Registry, TypeInfo};
+
+use crate::{Frame, VM};
+
+/// A thread-safe deep copy of a `Value`.
+pub enum SendValue {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Int(i64),
+    Str(String),
+    List(Vec<SendValue>),
+    Dict(Vec<(String, SendValue)>),
+    Array { elem: ElemType, dims: Vec<usize>, data: Vec<SendValue> },
+    StrArray2D { rows: usize, cols: usize, data: Vec<String> },
+    Func { arity: u8, name: Option<String>, code: Vec<u8> },
+    Channel(Arc<ChannelShared>),
+    Task(Arc<TaskShared>),
+}
+
+impl SendValue {
+    /// Copy a value so it can be moved to another task. Fails for objects other than CHANNEL and TASK.
+    pub fn from_value(v: &Value) -> Result<SendValue> {
+        Ok(match v {
+            Value::Null => SendValue::Null,
+            Value::Bool(b) => SendValue::Bool(*b),
+            Value::Num(n) => SendValue::Num(*n),
+            Value::Int(i) => SendValue::Int(*i),
+            Value::Str(s) => SendValue::Str(s.clone()),
+            Value::List(rc) => {
+                let items = rc.borrow();
+                let mut out = Vec::with_capacity(items.len());
+                for it in items.iter() { out.push(SendValue::from_value(it)?); }
+                SendValue::List(out)
+            }
+            Value::Dict(rc) => {
+                let map = rc.borrow();
+                let mut out = Vec::with_capacity(map.len());
+                for (k, it) in map.iter() { out.push((k.clone(), SendValue::from_value(it)?)); }
+                SendValue::Dict(out)
+            }
+            Value::Array(arr) => {
+                let data = arr.data.borrow();
+                let mut out = Vec::with_capacity(data.len());
+                for it in data.iter() { out.push(SendValue::from_value(it)?); }
+                SendValue::Array { elem: arr.elem.clone(), dims: arr.dims.clone(), data: out }
+            }
+            Value::StrArray2D { rows, cols, data } => SendValue::StrArray2D { rows: *rows, cols: *cols, data: data.clone() },
+            Value::Func(f) => {
+                let code = serialize_program(&BCProgram { chunk: (*f.chunk).clone(), globals: Vec::new() });
+                SendValue::Func { arity: f.arity, name: f.name.clone(), code }
+            }
+            Value::Object(rc) => {
+                let obj = rc.borrow();
+                let tname = obj.type_name().to_ascii_uppercase();
+                let id = match obj.get_prop("ID%") { Ok(Value::Int(i)) => i, _ => -1 };
+                match tname.as_str() {
+                    "CHANNEL" => SendValue::Channel(lookup(channels(), id).ok_or_else(|| BasilError("CHANNEL is no longer available".into()))?),
+                    "TASK" => SendValue::Task(lookup(tasks(), id).ok_or_else(|| BasilError("TASK is no longer available".into()))?),
+                    _ => return Err(BasilError(format!("Cannot pass a {} object to another task; only values, CHANNEL and TASK objects can be shared.", tname))),
+                }
+            }
+        })
+    }
+
+    /// Rebuild a `Value` inside the receiving VM.
+    pub fn into_value(self) -> Value {
+        match self {
+            SendValue::Null => Value::Null,
+            SendValue::Bool(b) => Value::Bool(b),
+            SendValue::Num(n) => Value::Num(n),
+            SendValue::Int(i) => Value::Int(i),
+            SendValue::Str(s) => Value::Str(s),
+            SendValue::List(items) => Value::List(Rc::new(RefCell::new(items.into_iter().map(SendValue::into_value).collect()))),
+            SendValue::Dict(entries) => {
+                let map: HashMap<String, Value> = entries.into_iter().map(|(k, v)| (k, v.into_value())).collect();
+                Value::Dict(Rc::new(RefCell::new(map)))
+            }
+            SendValue::Array { elem, dims, data } => {
+                let data: Vec<Value> = data.into_iter().map(SendValue::into_value).collect();
+                Value::Array(Rc::new(ArrayObj { elem, dims, data: RefCell::new(data) }))
+            }
+            SendValue::StrArray2D { rows, cols, data } => Value::StrArray2D { rows, cols, data },
+            SendValue::Func { arity, name, code } => match deserialize_program(&code) {
+                Ok(p) => Value::Func(Rc::new(Function { arity, name, chunk: Rc::new(p.chunk) })),
+                Err(_) => Value::Null,
+            },
+            SendValue::Channel(shared) => Value::Object(Rc::new(RefCell::new(ChannelObj { shared, received: false, timed_out: false }))),
+            SendValue::Task(shared) => Value::Object(Rc::new(RefCell::new(TaskObj { shared, timed_out: false }))),
+        }
+    }
+}
+
+// --- Shared-state registries (id -> weak handle) so objects can be re-found from their ID% ---
+
+type Table<T> = Mutex<HashMap<i64, Weak<T>>>;
+
+static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+static CHANNELS: OnceLock<Table<ChannelShared>> = OnceLock::new();
+static TASKS: OnceLock<Table<TaskShared>> = OnceLock::new();
+
+fn channels() -> &'static Table<ChannelShared> { CHANNELS.get_or_init(|| Mutex::new(HashMap::new())) }
+fn tasks() -> &'static Table<TaskShared> { TASKS.get_or_init(|| Mutex::new(HashMap::new())) }
+
+fn remember<T>(table: &'static Table<T>, id: i64, shared: &Arc<T>) {
+    let mut t = table.lock().unwrap();
+    t.retain(|_, w| w.strong_count() > 0);
+    t.insert(id, Arc::downgrade(shared));
+}
+
+fn lookup<T>(table: &'static Table<T>, id: i64) -> Option<Arc<T>> {
+    table.lock().unwrap().get(&id).and_then(|w| w.upgrade())
+}
+
+fn arg_timeout(args: &[Value], what: &str) -> Result<Option<Duration>> {
+    match args.first() {
+        None => Ok(None),
+        Some(Value::Int(ms)) => Ok(Some(Duration::from_millis((*ms).max(0) as u64))),
+        Some(Value::Num(ms)) => Ok(Some(Duration::from_millis(ms.max(0.0) as u64))),
+        Some(_) => Err(BasilError(format!("{} timeout must be a number of milliseconds", what))),
+    }
+}
+
+// --- CHANNEL ---
+
+pub struct ChannelShared {
+    id: i64,
+    capacity: usize, // 0 = unbounded
+    state: Mutex<ChannelState>,
+    cv: Condvar,
+}
+
+struct ChannelState {
+    items: VecDeque<SendValue>,
+    closed: bool,
+}
+
+impl ChannelShared {
+    fn send(&self, v: SendValue) -> Result<()> {
+        let mut st = self.state.lock().unwrap();
+        while self.capacity > 0 && st.items.len() >= self.capacity && !st.closed {
+            st = self.cv.wait(st).unwrap();
+        }
+        if st.closed { return Err(BasilError("CHANNEL.Send on a closed channel".into())); }
+        st.items.push_back(v);
+        self.cv.notify_all();
+        Ok(())
+    }
+
+    // Err(true) = timed out, Err(false) = closed and drained
+    fn receive(&self, timeout: Option<Duration>) -> std::result::Result<SendValue, bool> {
+        let deadline = timeout.map(|d| Instant::now() + d);
+        let mut st = self.state.lock().unwrap();
+        loop {
+            if let Some(v) = st.items.pop_front() {
+                self.cv.notify_all();
+                return Ok(v);
+            }
+            if st.closed { return Err(false); }
+            match deadline {
+                None => { st = self.cv.wait(st).unwrap(); }
+                Some(dl) => {
+                    let now = Instant::now();
+                    if now >= dl { return Err(true); }
+                    st = self.cv.wait_timeout(st, dl - now).unwrap().0;
+                }
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cv.notify_all();
+    }
+}
+
+struct ChannelObj {
+    shared: Arc<ChannelShared>,
+    // outcome of the last Receive on this handle
+    received: bool,
+    timed_out: bool,
+}
+
+fn new_channel(capacity: usize) -> Arc<ChannelShared> {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let shared = Arc::new(ChannelShared { id, capacity, state: Mutex::new(ChannelState { items: VecDeque::new(), closed: false }), cv: Condvar::new() });
+    remember(channels(), id, &shared);
+    shared
+}
+
+fn channel_factory(args: &[Value]) -> Result<ObjectRef> {
+    let capacity = match args.first() {
+        None => 0,
+        Some(Value::Int(i)) => (*i).max(0) as usize,
+        Some(Value::Num(n)) => n.max(0.0) as usize,
+        Some(_) => return Err(BasilError("CHANNEL(capacity%) expects a number".into())),
+    };
+    Ok(Rc::new(RefCell::new(ChannelObj { shared: new_channel(capacity), received: false, timed_out: false })))
+}
+
+fn channel_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "CHANNEL".to_string(),
+        version: "1.0".to_string(),
+        summary: "Thread-safe message queue shared between SPAWNed tasks".to_string(),
+        properties: vec![
+            PropDesc { name: "Id%".to_string(), type_name: "Integer".to_string(), readable: true, writable: false },
+            PropDesc { name: "Count%".to_string(), type_name: "Integer".to_string(), readable: true, writable: false },
+            PropDesc { name: "Capacity%".to_string(), type_name: "Integer".to_string(), readable: true, writable: false },
+            PropDesc { name: "Closed".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "Received".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "TimedOut".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+        ],
+        methods: vec![
+            MethodDesc { name: "Send".to_string(), arity: 1, arg_names: vec!["value".to_string()], return_type: "Void".to_string() },
+            MethodDesc { name: "Receive".to_string(), arity: 1, arg_names: vec!["[timeoutMs%]".to_string()], return_type: "Any".to_string() },
+            MethodDesc { name: "Close".to_string(), arity: 0, arg_names: vec![], return_type: "Void".to_string() },
+        ],
+        examples: vec![
+            "DIM ch@ AS CHANNEL()".to_string(),
+            "ch@.Send(42)".to_string(),
+            "LET v = ch@.Receive(500)".to_string(),
+        ],
+    }
+}
+
+impl BasicObject for ChannelObj {
+    fn type_name(&self) -> &str { "CHANNEL" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        match name.to_ascii_uppercase().as_str() {
+            "ID%" | "ID" => Ok(Value::Int(self.shared.id)),
+            "COUNT%" | "COUNT" => Ok(Value::Int(self.shared.state.lock().unwrap().items.len() as i64)),
+            "CAPACITY%" | "CAPACITY" => Ok(Value::Int(self.shared.capacity as i64)),
+            "CLOSED" => Ok(Value::Bool(self.shared.state.lock().unwrap().closed)),
+            "RECEIVED" => Ok(Value::Bool(self.received)),
+            "TIMEDOUT" => Ok(Value::Bool(self.timed_out)),
+            other => Err(BasilError(format!("Unknown property '{}' on CHANNEL", other))),
+        }
+    }
+    fn set_prop(&mut self, name: &str, _v: Value) -> Result<()> {
+        Err(BasilError(format!("Property '{}' on CHANNEL is read-only", name)))
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        match method.to_ascii_uppercase().as_str() {
+            "SEND" => {
+                if args.len() != 1 { return Err(BasilError("CHANNEL.Send expects 1 argument".into())); }
+                self.shared.send(SendValue::from_value(&args[0])?)?;
+                Ok(Value::Null)
+            }
+            "RECEIVE" => {
+                let timeout = arg_timeout(args, "CHANNEL.Receive")?;
+                let r = self.shared.receive(timeout);
+                self.received = r.is_ok();
+                self.timed_out = r.as_ref().err().copied().unwrap_or(false);
+                Ok(r.map(SendValue::into_value).unwrap_or(Value::Null))
+            }
+            "CLOSE" => { self.shared.close(); Ok(Value::Null) }
+            other => Err(BasilError(format!("Unknown method '{}' on CHANNEL", other))),
+        }
+    }
+    fn descriptor(&self) -> ObjectDescriptor { channel_descriptor() }
+}
+
+// --- TASK ---
+
+pub struct TaskShared {
+    id: i64,
+    name: String,
+    // taken by whichever thread runs the task: a pool worker, or a waiter that got there first
+    seed: Mutex<Option<TaskSeed>>,
+    outcome: Mutex<Option<std::result::Result<SendValue, String>>>,
+    cv: Condvar,
+}
+
+impl TaskShared {
+    fn finish(&self, r: std::result::Result<SendValue, String>) {
+        *self.outcome.lock().unwrap() = Some(r);
+        self.cv.notify_all();
+    }
+
+    // Runs the task on the calling thread unless it has already been started.
+    fn run(&self) {
+        let seed = match self.seed.lock().unwrap().take() { Some(s) => s, None => return };
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_seed(seed)))
+            .unwrap_or_else(|_| Err("task panicked".to_string()));
+        self.finish(r);
+    }
+
+    // Blocks until the task finished (or the timeout elapsed) and returns a copy of its result.
+    // An untimed wait on a task still queued for a worker runs it here, so a chain of tasks
+    // waiting on each other can't stall the pool.
+    fn wait(&self, timeout: Option<Duration>) -> Option<std::result::Result<Value, String>> {
+        if timeout.is_none() { self.run(); }
+        let deadline = timeout.map(|d| Instant::now() + d);
+        let mut st = self.outcome.lock().unwrap();
+        loop {
+            if let Some(r) = st.as_ref() {
+                return Some(match r {
+                    Ok(v) => Ok(v.copy().into_value()),
+                    Err(e) => Err(e.clone()),
+                });
+            }
+            match deadline {
+                None => { st = self.cv.wait(st).unwrap(); }
+                Some(dl) => {
+                    let now = Instant::now();
+                    if now >= dl { return None; }
+                    st = self.cv.wait_timeout(st, dl - now).unwrap().0;
+                }
+            }
+        }
+    }
+}
+
+impl SendValue {
+    // Results may be collected by several waiters, so hand each one its own copy.
+    fn copy(&self) -> SendValue {
+        match self {
+            SendValue::Null => SendValue::Null,
+            SendValue::Bool(b) => SendValue::Bool(*b),
+            SendValue::Num(n) => SendValue::Num(*n),
+            SendValue::Int(i) => SendValue::Int(*i),
+            SendValue::Str(s) => SendValue::Str(s.clone()),
+            SendValue::List(items) => SendValue::List(items.iter().map(SendValue::copy).collect()),
+            SendValue::Dict(entries) => SendValue::Dict(entries.iter().map(|(k, v)| (k.clone(), v.copy())).collect()),
+            SendValue::Array { elem, dims, data } => SendValue::Array { elem: elem.clone(), dims: dims.clone(), data: data.iter().map(SendValue::copy).collect() },
+            SendValue::StrArray2D { rows, cols, data } => SendValue::StrArray2D { rows: *rows, cols: *cols, data: data.clone() },
+            SendValue::Func { arity, name, code } => SendValue::Func { arity: *arity, name: name.clone(), code: code.clone() },
+            SendValue::Channel(c) => SendValue::Channel(c.clone()),
+            SendValue::Task(t) => SendValue::Task(t.clone()),
+        }
+    }
+}
+
+struct TaskObj {
+    shared: Arc<TaskShared>,
+    // whether the last Wait on this handle ran out of time
+    timed_out: bool,
+}
+
+fn task_factory(_args: &[Value]) -> Result<ObjectRef> {
+    Err(BasilError("TASK objects are created with SPAWN FuncName(args)".into()))
+}
+
+fn task_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "TASK".to_string(),
+        version: "1.0".to_string(),
+        summary: "Handle to a function running in its own VM on a background thread".to_string(),
+        properties: vec![
+            PropDesc { name: "Id%".to_string(), type_name: "Integer".to_string(), readable: true, writable: false },
+            PropDesc { name: "Name$".to_string(), type_name: "String".to_string(), readable: true, writable: false },
+            PropDesc { name: "Done".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "Failed".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+            PropDesc { name: "Error$".to_string(), type_name: "String".to_string(), readable: true, writable: false },
+            PropDesc { name: "TimedOut".to_string(), type_name: "Bool".to_string(), readable: true, writable: false },
+        ],
+        methods: vec![
+            MethodDesc { name: "Wait".to_string(), arity: 1, arg_names: vec!["[timeoutMs%]".to_string()], return_type: "Any".to_string() },
+        ],
+        examples: vec![
+            "LET t@ = SPAWN Worker(10)".to_string(),
+            "LET r = WAIT t@".to_string(),
+        ],
+    }
+}
+
+impl BasicObject for TaskObj {
+    fn type_name(&self) -> &str { "TASK" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let st = self.shared.outcome.lock().unwrap();
+        match name.to_ascii_uppercase().as_str() {
+            "ID%" | "ID" => Ok(Value::Int(self.shared.id)),
+            "NAME$" | "NAME" => Ok(Value::Str(self.shared.name.clone())),
+            "DONE" => Ok(Value::Bool(st.is_some())),
+            "FAILED" => Ok(Value::Bool(matches!(*st, Some(Err(_))))),
+            "ERROR$" => Ok(Value::Str(match &*st { Some(Err(e)) => e.clone(), _ => String::new() })),
+            "TIMEDOUT" => Ok(Value::Bool(self.timed_out)),
+            other => Err(BasilError(format!("Unknown property '{}' on TASK", other))),
+        }
+    }
+    fn set_prop(&mut self, name: &str, _v: Value) -> Result<()> {
+        Err(BasilError(format!("Property '{}' on TASK is read-only", name)))
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        match method.to_ascii_uppercase().as_str() {
+            "WAIT" => {
+                let timeout = arg_timeout(args, "TASK.Wait")?;
+                let r = self.shared.wait(timeout);
+                self.timed_out = r.is_none();
+                match r {
+                    Some(Ok(v)) => Ok(v),
+                    Some(Err(e)) => Err(BasilError(format!("task {} failed: {}", self.shared.name, e))),
+                    None => Ok(Value::Null),
+                }
+            }
+            other => Err(BasilError(format!("Unknown method '{}' on TASK", other))),
+        }
+    }
+    fn descriptor(&self) -> ObjectDescriptor { task_descriptor() }
+}
+
+fn promise_factory(_args: &[Value]) -> Result<ObjectRef> {
+    Err(BasilError("PROMISE objects are returned by async object methods such as HTTP.GetAsync".into()))
+}
+
+/// Register the CHANNEL, TASK and PROMISE types with a VM's object registry.
+pub fn register(reg: &mut Registry) {
+    reg.register("CHANNEL", TypeInfo { factory: channel_factory, descriptor: channel_descriptor, constants: Vec::new });
+    reg.register("TASK", TypeInfo { factory: task_factory, descriptor: task_descriptor, constants: Vec::new });
+    reg.register("PROMISE", TypeInfo { factory: promise_factory, descriptor: basil_bytecode::promise::descriptor_static, constants: Vec::new });
+}
+
+// --- SPAWN / WAIT / PARALLEL FOR EACH ---
+
+// Everything a task needs to rebuild its own VM on the worker thread.
+struct TaskSeed {
+    func: SendValue,
+    args: Vec<SendValue>,
+    global_names: Vec<String>,
+    globals: Vec<SendValue>,
+    script_path: Option<String>,
+}
+
+impl VM {
+    // Globals are copied best-effort: values that cannot cross (e.g. open objects) start out NULL in the task.
+    fn task_seed(&self, func: &Value, args: &[Value]) -> Result<TaskSeed> {
+        let f = match func {
+            Value::Func(f) => f,
+            _ => return Err(BasilError("SPAWN expects a FUNC or SUB name".into())),
+        };
+        if f.arity as usize != args.len() {
+            return Err(BasilError(format!("SPAWN {}: expected {} arguments, got {}", f.name.clone().unwrap_or_default(), f.arity, args.len())));
+        }
+        let mut sargs = Vec::with_capacity(args.len());
+        for a in args { sargs.push(SendValue::from_value(a)?); }
+        let globals = self.globals.iter().map(|g| SendValue::from_value(g).unwrap_or(SendValue::Null)).collect();
+        Ok(TaskSeed {
+            func: SendValue::from_value(func)?,
+            args: sargs,
+            global_names: self.global_names.clone(),
+            globals,
+            script_path: self.script_path.clone(),
+        })
+    }
+
+    /// SPAWN: queue `func(args)` to run in a fresh VM on the worker pool and return a TASK handle.
+    pub(crate) fn spawn_task(&self, func: &Value, args: &[Value]) -> Result<Value> {
+        let shared = new_task(func, self.task_seed(func, args)?);
+        pool().submit(shared.clone())?;
+        Ok(Value::Object(Rc::new(RefCell::new(TaskObj { shared, timed_out: false }))))
+    }
+
+    /// PARALLEL FOR EACH: run `func(item)` for every element, at most one task per CPU at a time.
+    pub(crate) fn parallel_each(&self, func: &Value, items: Vec<Value>) -> Result<()> {
+        let width = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4).max(1);
+        for batch in items.chunks(width) {
+            let mut handles = Vec::with_capacity(batch.len());
+            for it in batch { handles.push(self.spawn_task(func, std::slice::from_ref(it))?); }
+            // let the whole batch finish before reporting the first failure
+            let mut first_err = None;
+            for h in handles {
+                if let Err(e) = wait_value(&h, None) { first_err.get_or_insert(e); }
+            }
+            if let Some(e) = first_err { return Err(BasilError(format!("PARALLEL FOR EACH: {}", e))); }
+        }
+        Ok(())
+    }
+}
+
+fn new_task(func: &Value, seed: TaskSeed) -> Arc<TaskShared> {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let name = match func { Value::Func(f) => f.name.clone().unwrap_or_else(|| "<anonymous>".into()), _ => String::new() };
+    let shared = Arc::new(TaskShared { id, name, seed: Mutex::new(Some(seed)), outcome: Mutex::new(None), cv: Condvar::new() });
+    remember(tasks(), id, &shared);
+    shared
+}
+
+// --- Worker pool ---
+
+/// Most task threads alive at once unless BASIL_TASK_THREADS says otherwise. Tasks spawned
+/// beyond it queue until a worker is free.
+const DEFAULT_TASK_THREADS: usize = 64;
+
+struct Pool {
+    limit: usize,
+    state: Mutex<PoolState>,
+    cv: Condvar,
+}
+
+struct PoolState {
+    queue: VecDeque<Arc<TaskShared>>,
+    workers: usize,
+    idle: usize,
+}
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+fn pool() -> &'static Pool {
+    POOL.get_or_init(|| {
+        let limit = std::env::var("BASIL_TASK_THREADS").ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_TASK_THREADS);
+        Pool::new(limit)
+    })
+}
+
+impl Pool {
+    fn new(limit: usize) -> Pool {
+        Pool { limit, state: Mutex::new(PoolState { queue: VecDeque::new(), workers: 0, idle: 0 }), cv: Condvar::new() }
+    }
+
+    // Queue a task, starting another worker if every current one is busy and the limit allows it.
+    fn submit(&'static self, task: Arc<TaskShared>) -> Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.queue.push_back(task);
+        if st.queue.len() > st.idle && st.workers < self.limit {
+            std::thread::Builder::new()
+                .name(format!("basil-task-{}", st.workers + 1))
+                .spawn(move || self.work())
+                .map_err(|e| BasilError(format!("SPAWN: could not start thread: {}", e)))?;
+            st.workers += 1;
+        }
+        self.cv.notify_one();
+        Ok(())
+    }
+
+    fn work(&self) {
+        let mut st = self.state.lock().unwrap();
+        loop {
+            if let Some(task) = st.queue.pop_front() {
+                drop(st);
+                task.run();
+                st = self.state.lock().unwrap();
+                continue;
+            }
+            st.idle += 1;
+            st = self.cv.wait(st).unwrap();
+            st.idle -= 1;
+        }
+    }
+}
+
+fn is_awaitable(obj: &ObjectRef) -> bool {
+    let b = obj.borrow();
+    b.type_name().eq_ignore_ascii_case("TASK") || b.type_name().eq_ignore_ascii_case("PROMISE")
+}
+
+impl VM {
+    // Remember ASYNC FUNC tasks and PROMISEs so they can be settled at program end.
+    pub(crate) fn track_async(&mut self, v: &Value) {
+        if let Value::Object(rc) = v {
+            if is_awaitable(rc) {
+                self.pending_async.retain(|p| !matches!(p.borrow().get_prop("DONE"), Ok(Value::Bool(true))));
+                self.pending_async.push(rc.clone());
+            }
+        }
+    }
+
+    /// Join what is still pending when the program reaches its end. There is no event loop in
+    /// the VM: each ASYNC FUNC call is a task on the worker pool and each PROMISE settles on the
+    /// Tokio runtime of the object that issued it, while the script keeps running on its own
+    /// thread. AWAIT blocks on individual results; this waits for the rest before the VM stops.
+    /// Work that failed without being awaited is reported as a warning.
+    pub(crate) fn settle_pending_async(&mut self) {
+        for p in std::mem::take(&mut self.pending_async) {
+            if matches!(p.borrow().get_prop("DONE"), Ok(Value::Bool(true))) { continue; }
+            let r = p.borrow_mut().call("WAIT", &[]);
+            if let Err(e) = r {
+                eprintln!("warning: unawaited async operation failed: {}", e);
+            }
+        }
+    }
+}
+
+/// WAIT/AWAIT: block on a TASK or PROMISE (returning its result) or on a LIST of them (returning
+/// a LIST of results). Any other value is already "settled" and comes back unchanged.
+pub(crate) fn wait_value(v: &Value, timeout: Option<Duration>) -> Result<Value> {
+    match v {
+        Value::Object(rc) if is_awaitable(rc) => {
+            let targs: Vec<Value> = timeout.map(|d| vec![Value::Int(d.as_millis() as i64)]).unwrap_or_default();
+            let r = rc.borrow_mut().call("WAIT", &targs);
+            r
+        }
+        Value::List(rc) => {
+            let items: Vec<Value> = rc.borrow().clone();
+            let mut out = Vec::with_capacity(items.len());
+            for it in &items { out.push(wait_value(it, timeout)?); }
+            Ok(Value::List(Rc::new(RefCell::new(out))))
+        }
+        other => Ok(other.clone()),
+    }
+}
+
+pub(crate) fn timeout_arg(v: Option<&Value>) -> Result<Option<Duration>> {
+    arg_timeout(v.map(std::slice::from_ref).unwrap_or(&[]), "WAIT")
+}
+
+// Runs on the worker thread: rebuild the VM, seed globals, call the function, copy the result out.
+fn run_seed(seed: TaskSeed) -> std::result::Result<SendValue, String> {
+    let f = match seed.func.into_value() {
+        Value::Func(f) => f,
+        _ => return Err("task function could not be loaded".into()),
+    };
+    let mut top = Chunk::default();
+    top.push_op(Op::Halt);
+    let mut vm = VM::new(BCProgram { chunk: top, globals: seed.global_names });
+    vm.globals = seed.globals.into_iter().map(SendValue::into_value).collect();
+    vm.script_path = seed.script_path;
+    for a in seed.args { vm.stack.push(a.into_value()); }
+    vm.frames.push(Frame { chunk: f.chunk.clone(), ip: 0, base: 0 });
+    vm.run().map_err(|e| format!("{} (line {})", e, vm.current_line))?;
+    let ret = vm.stack.pop().unwrap_or(Value::Null);
+    SendValue::from_value(&ret).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basil_parser::parse;
+    use basil_compiler::compile;
+
+    fn run_global(src: &str, name: &str) -> Result<Value> {
+        let prog = compile(&parse(src)?)?;
+        let mut vm = VM::new(prog);
+        vm.run()?;
+        let (names, vals) = vm.globals_snapshot();
+        let i = names.iter().position(|n| n.eq_ignore_ascii_case(name)).expect("global");
+        Ok(vals[i].clone())
+    }
+
+    #[test]
+    fn spawn_and_wait_returns_result() {
+        let src = "FUNC Sq(n)\n RETURN n * n;\nEND\nLET t@ = SPAWN Sq(9);\nLET r = WAIT t@;\n";
+        assert_eq!(run_global(src, "r").unwrap(), Value::Num(81.0));
+    }
+
+    #[test]
+    fn task_words_still_work_as_names() {
+        let src = "FUNC WAIT(n)\n RETURN n + 1;\nEND\nLET SPAWN = WAIT(1);\nLET PARALLEL = SPAWN + 1;\nLET r = WAIT(PARALLEL);\n";
+        assert_eq!(run_global(src, "r").unwrap(), Value::Num(4.0));
+        assert!(parse("LET WAIT = 5;\nWAIT = 5;\n").unwrap_err().0.contains("Use LET"));
+    }
+
+    #[test]
+    fn channel_crosses_tasks_and_parallel_for_each_joins() {
+        let src = "FUNC Sq(n)\n RETURN n * n;\nEND\nDIM ch@ AS CHANNEL();\n\
+                   PARALLEL FOR EACH n IN [1, 2, 3] {\n ch@.Send(Sq(n));\n}\nNEXT\n\
+                   LET c% = ch@.Count%;\nLET total = 0;\n\
+                   WHILE ch@.Count% > 0 BEGIN\n LET total = total + ch@.Receive();\nEND\n";
+        assert_eq!(run_global(src, "c%").unwrap(), Value::Int(3));
+        assert_eq!(run_global(src, "total").unwrap(), Value::Num(14.0));
+    }
+
+    #[test]
+    fn task_errors_surface_on_wait() {
+        let src = "FUNC Boom(n)\n RAISE \"bad \" + n;\nEND\nLET t@ = SPAWN Boom(1);\nLET r = WAIT t@;\n";
+        let err = run_global(src, "r").unwrap_err();
+        assert!(err.0.contains("bad 1"), "{}", err);
+    }
+
+    #[test]
+    fn wait_timeout_is_told_apart_from_a_null_result() {
+        let src = "SUB Slow(n)\n SLEEP 300;\nEND\nLET t@ = SPAWN Slow(1);\n\
+                   LET early = WAIT(t@, 10);\nLET late = t@.TimedOut;\n\
+                   LET r = WAIT t@;\nLET done = t@.TimedOut;\n";
+        assert_eq!(run_global(src, "early").unwrap(), Value::Null);
+        assert_eq!(run_global(src, "late").unwrap(), Value::Bool(true));
+        assert_eq!(run_global(src, "r").unwrap(), Value::Null);
+        assert_eq!(run_global(src, "done").unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn pool_caps_worker_threads() {
+        let src = "FUNC Slow(n)\n SLEEP 20;\n RETURN n * 2;\nEND\n";
+        let mut vm = VM::new(compile(&parse(src).unwrap()).unwrap());
+        vm.run().unwrap();
+        let (names, vals) = vm.globals_snapshot();
+        let f = vals[names.iter().position(|n| n.eq_ignore_ascii_case("SLOW")).unwrap()].clone();
+        let pool: &'static Pool = Box::leak(Box::new(Pool::new(2)));
+        let tasks: Vec<_> = (0..8).map(|i| {
+            let t = new_task(&f, vm.task_seed(&f, &[Value::Int(i)]).unwrap());
+            pool.submit(t.clone()).unwrap();
+            t
+        }).collect();
+        assert!(pool.state.lock().unwrap().workers <= 2);
+        for (i, t) in tasks.iter().enumerate() {
+            assert_eq!(t.wait(Some(Duration::from_secs(10))).unwrap().unwrap(), Value::Num(i as f64 * 2.0));
+        }
+        assert_eq!(pool.state.lock().unwrap().workers, 2);
+    }
+
+    #[test]
+    fn receive_times_out_with_null() {
+        let src = "DIM ch@ AS CHANNEL();\nLET v = ch@.Receive(10);\nLET t = ch@.TimedOut;\n";
+        assert_eq!(run_global(src, "v").unwrap(), Value::Null);
+        assert_eq!(run_global(src, "t").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn async_func_is_awaited() {
+        let src = "ASYNC FUNC Twice(n)\n RETURN n * 2;\nEND\nLET t@ = Twice(21);\nLET r = AWAIT t@;\nLET all = AWAIT [Twice(1), Twice(2)];\nLET p = AWAIT 5;\n";
+        assert_eq!(run_global(src, "r").unwrap(), Value::Num(42.0));
+        assert_eq!(run_global(src, "p").unwrap(), Value::Num(5.0));
+        match run_global(src, "all").unwrap() {
+            Value::List(l) => assert_eq!(*l.borrow(), vec![Value::Num(2.0), Value::Num(4.0)]),
+            other => panic!("expected list, got {:?}", other),
+        }
+    }
+}
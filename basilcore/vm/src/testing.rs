@@ -0,0 +1,216 @@
+//! Unit tests written in Basil: TEST blocks, SETUP/TEARDOWN and the ASSERT builtins.
+//!
+//! The parser turns `TEST "name" ... END TEST` into a SUB plus a TEST_REGISTER call, so running
+//! a script only registers its tests. `basilc test` then runs the top level (which defines FUNCs
+//! and shared globals) and calls `run_test` for each registered case, with SETUP before and
+//! TEARDOWN after every one. A failed ASSERT is a runtime error whose message starts with the
+//! builtin's name, which is how a failure is told apart from any other error.
+
+use std::rc::Rc;
+
+use basil_bytecode::Value;
+use basil_common::{BasilError, Result};
+
+use crate::web::str_arg;
+use crate::{is_truthy, Frame, VM};
+
+/// A registered TEST block.
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    /// Line of the `TEST` keyword
+    pub line: u32,
+    sub: String,
+}
+
+/// Outcome of one test. `line` is where the failure or error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestStatus {
+    Passed,
+    Failed { message: String, line: u32 },
+    Error { message: String, line: u32 },
+}
+
+#[derive(Default)]
+pub(crate) struct Suite {
+    tests: Vec<TestCase>,
+    setup: Option<String>,
+    teardown: Option<String>,
+}
+
+fn is_assertion(message: &str) -> bool {
+    message.starts_with("ASSERT")
+}
+
+// Strings are quoted in failure messages so "1" and 1 can be told apart
+fn show(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+impl VM {
+    /// The TEST blocks the program registered, in source order.
+    pub fn tests(&self) -> &[TestCase] { &self.suite.tests }
+
+    /// Run test `i` with SETUP before it and TEARDOWN after it (even when it fails).
+    /// Call after `run()` so the program's FUNCs and globals exist.
+    pub fn run_test(&mut self, i: usize) -> TestStatus {
+        let Some(case) = self.suite.tests.get(i).cloned() else {
+            return TestStatus::Error { message: format!("no test #{}", i), line: 0 };
+        };
+        let classify = |e: BasilError, line: u32, stage: &str| {
+            if is_assertion(&e.0) { TestStatus::Failed { message: format!("{}{}", stage, e.0), line } }
+            else { TestStatus::Error { message: format!("{}{}", stage, e.0), line } }
+        };
+        let mut status = TestStatus::Passed;
+        if let Some(setup) = self.suite.setup.clone() {
+            if let Err(e) = self.call_isolated(&setup) { status = classify(e, self.current_line, "SETUP: "); }
+        }
+        if status == TestStatus::Passed {
+            if let Err(e) = self.call_isolated(&case.sub) { status = classify(e, self.current_line, ""); }
+        }
+        if let Some(teardown) = self.suite.teardown.clone() {
+            if let Err(e) = self.call_isolated(&teardown) {
+                if status == TestStatus::Passed { status = classify(e, self.current_line, "TEARDOWN: "); }
+            }
+        }
+        status
+    }
+
+    // A test must not land in a TRY handler left over from the code that ran before it
+    fn call_isolated(&mut self, sub: &str) -> Result<()> {
+        let handlers = std::mem::take(&mut self._handlers);
+        let res = self.call_target(sub, &Value::Null);
+        self._handlers = handlers;
+        self.current_exception = None;
+        self.exit_code = None;
+        res.map(|_| ())
+    }
+
+    /// TEST_REGISTER(kind$, name$, sub$): emitted by the parser for TEST, SETUP and TEARDOWN blocks.
+    pub(crate) fn test_register(&mut self, args: &[Value]) -> Result<()> {
+        let kind = str_arg(args, 0, "TEST_REGISTER")?;
+        let name = str_arg(args, 1, "TEST_REGISTER")?;
+        let sub = str_arg(args, 2, "TEST_REGISTER")?;
+        match kind.as_str() {
+            "SETUP" => self.suite.setup = Some(sub),
+            "TEARDOWN" => self.suite.teardown = Some(sub),
+            _ => self.suite.tests.push(TestCase { name, line: self.current_line, sub }),
+        }
+        Ok(())
+    }
+
+    /// ASSERT(condition [, message$])
+    pub(crate) fn assert_true(&mut self, args: &[Value]) -> Result<()> {
+        if is_truthy(&args[0]) { return Ok(()); }
+        let note = if args.len() > 1 { format!(": {}", str_arg(args, 1, "ASSERT")?) } else { String::new() };
+        Err(BasilError(format!("ASSERT failed{}", note)))
+    }
+
+    /// ASSERT_EQ(actual, expected [, message$]), comparing numbers by value like `==`
+    pub(crate) fn assert_eq(&mut self, args: &[Value]) -> Result<()> {
+        let (actual, expected) = (&args[0], &args[1]);
+        let equal = match (actual, expected) {
+            (Value::Num(_) | Value::Int(_) | Value::Bool(_), Value::Num(_) | Value::Int(_) | Value::Bool(_)) => {
+                self.as_num(actual.clone())? == self.as_num(expected.clone())?
+            }
+            _ => actual == expected,
+        };
+        if equal { return Ok(()); }
+        let note = if args.len() > 2 { format!(" ({})", str_arg(args, 2, "ASSERT_EQ")?) } else { String::new() };
+        Err(BasilError(format!("ASSERT_EQ failed: expected {}, got {}{}", show(expected), show(actual), note)))
+    }
+
+    /// ASSERT_RAISES(expr [, expected$]): the compiler passes `expr` as a chunk that is run here on
+    /// the caller's frame. Passes if it raises an error (containing `expected$`, if given).
+    pub(crate) fn assert_raises(&mut self, args: &[Value]) -> Result<()> {
+        let Some(Value::Func(thunk)) = args.first() else {
+            return Err(BasilError("ASSERT_RAISES expects an expression".into()));
+        };
+        let expected = if args.len() > 1 { Some(str_arg(args, 1, "ASSERT_RAISES")?) } else { None };
+        let base = self.frames.last().map(|f| f.base).unwrap_or(0);
+        let depth = self.stack.len();
+        let line = self.current_line;
+        let frames = std::mem::take(&mut self.frames);
+        let handlers = std::mem::take(&mut self._handlers);
+        let call_lines = std::mem::take(&mut self.call_lines);
+        self.frames.push(Frame { chunk: Rc::clone(&thunk.chunk), ip: 0, base });
+        let res = self.exec();
+        // A raised error leaves the thunk's calls on call_lines; the saved stack replaces them
+        self.frames = frames;
+        self._handlers = handlers;
+        self.call_lines = call_lines;
+        self.stack.truncate(depth);
+        self.current_exception = None;
+        self.current_line = line;
+        match (res, expected) {
+            (Ok(()), _) => Err(BasilError("ASSERT_RAISES failed: no error was raised".into())),
+            (Err(e), Some(want)) if !e.0.contains(&want) => {
+                Err(BasilError(format!("ASSERT_RAISES failed: expected an error containing {:?}, got: {}", want, e.0)))
+            }
+            (Err(_), _) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basil_compiler::compile;
+    use basil_parser::parse;
+
+    fn program(src: &str) -> VM {
+        let ast = parse(src).expect("parse");
+        let prog = compile(&ast).expect("compile");
+        let mut vm = VM::new(prog);
+        vm.set_output(Box::new(std::io::sink()));
+        vm.run().expect("top level runs");
+        vm
+    }
+
+    #[test]
+    fn runs_tests_with_setup_and_teardown() {
+        let mut vm = program(concat!(
+            "LET total% = 0;\n",
+            "FUNC Add(a, b)\nBEGIN\n  RETURN a + b;\nEND\n",
+            "SETUP\n  LET total% = 10;\nEND SETUP\n",
+            "TEARDOWN\n  LET total% = -1;\nEND TEARDOWN\n",
+            "TEST \"adds\"\n  ASSERT_EQ(Add(2, 3), 5);\n  ASSERT(total% == 10, \"setup ran\");\nEND TEST\n",
+            "TEST \"fails\"\n  LET x = 1;\n  ASSERT_EQ(Add(x, 1), 3, \"sum\");\nEND TEST\n",
+            "TEST \"errors\"\n  LET y = NOPE(1);\nEND TEST\n",
+            "PRINT \"top level\";\n",
+        ));
+        let names: Vec<_> = vm.tests().iter().map(|t| (t.name.clone(), t.line)).collect();
+        assert_eq!(names, vec![("adds".to_string(), 12), ("fails".to_string(), 16), ("errors".to_string(), 20)]);
+        assert_eq!(vm.run_test(0), TestStatus::Passed);
+        assert_eq!(vm.run_test(1), TestStatus::Failed { message: "ASSERT_EQ failed: expected 3, got 2 (sum)".into(), line: 18 });
+        assert!(matches!(vm.run_test(2), TestStatus::Error { line: 21, .. }));
+    }
+
+    #[test]
+    fn assert_raises_sees_locals() {
+        let mut vm = program(concat!(
+            "FUNC Check(n)\nBEGIN\n  IF n < 0 THEN RAISE \"negative: \" + n;\n  RETURN n;\nEND\n",
+            "TEST \"raises\"\n  LET bad = -2;\n  ASSERT_RAISES(Check(bad), \"negative\");\nEND TEST\n",
+            "TEST \"does not raise\"\n  LET good = 2;\n  ASSERT_RAISES(Check(good));\nEND TEST\n",
+            "TEST \"wrong error\"\n  ASSERT_RAISES(Check(-1), \"positive\");\nEND TEST\n",
+        ));
+        assert_eq!(vm.run_test(0), TestStatus::Passed);
+        assert!(matches!(vm.run_test(1), TestStatus::Failed { ref message, .. } if message == "ASSERT_RAISES failed: no error was raised"));
+        assert!(matches!(vm.run_test(2), TestStatus::Failed { ref message, .. } if message.contains("expected an error containing \"positive\"")));
+    }
+
+    #[test]
+    fn caught_errors_leave_no_stale_call_lines() {
+        let mut vm = program(concat!(
+            "FUNC Check(n)\nBEGIN\n  IF n < 0 THEN RAISE \"negative\";\n  RETURN n;\nEND\n",
+            "TEST \"raises\"\n  ASSERT_RAISES(Check(-1));\n  LET done = 1;\nEND TEST\n",
+        ));
+        assert_eq!(vm.run_test(0), TestStatus::Passed);
+        assert!(vm.call_lines.is_empty());
+        // The test's own RET must not jump back to the ASSERT_RAISES line (7)
+        assert_eq!(vm.current_line(), 8);
+    }
+}
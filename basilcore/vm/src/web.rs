@@ -0,0 +1,599 @@
+//! REQUEST@ and RESPONSE@: the web request a page is answering and the response it is building.
+//!
+//! Both objects are seeded as globals when a program mentions them. REQUEST@ is a read-only view
+//! of the CGI variables and body (query and form parameters, `multipart/form-data` uploads,
+//! cookies and headers). RESPONSE@ collects the status, headers and cookies; when CGI headers are
+//! enabled (`VM::enable_cgi_headers`) the VM writes them as a CGI header block right before the
+//! first byte of PRINT output, or when the program ends if it printed nothing.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use basil_bytecode::{BasicObject, MethodDesc, ObjectDescriptor, PropDesc, Value};
+use basil_common::{BasilError, Result};
+
+/// Decode `application/x-www-form-urlencoded` text (`+` is a space, `%XX` a byte, UTF-8 result).
+pub(crate) use basil_builtins::text::form_decode;
+
+pub(crate) fn parse_urlencoded(s: &str) -> Vec<(String, String)> {
+    s.split('&').filter(|p| !p.is_empty()).map(|p| {
+        let (k, v) = p.split_once('=').unwrap_or((p, ""));
+        (form_decode(k), form_decode(v))
+    }).collect()
+}
+
+/// A file field of a `multipart/form-data` body.
+#[derive(Debug, Clone)]
+pub(crate) struct Upload {
+    pub field: String,
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+// Value of `key=...` in a header parameter list like `form-data; name="a"; filename="b.txt"`
+fn header_param(header: &str, key: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|p| {
+        let (k, v) = p.trim().split_once('=')?;
+        k.trim().eq_ignore_ascii_case(key).then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(hay: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || from >= hay.len() { return None; }
+    hay[from..].windows(needle.len()).position(|w| w == needle).map(|p| p + from)
+}
+
+/// Split a `multipart/form-data` body into plain fields and uploaded files.
+pub(crate) fn parse_multipart(body: &[u8], content_type: &str) -> (Vec<(String, String)>, Vec<Upload>) {
+    let mut fields = Vec::new();
+    let mut files = Vec::new();
+    let Some(boundary) = header_param(content_type, "boundary") else { return (fields, files) };
+    let delim = format!("--{}", boundary).into_bytes();
+    let Some(mut pos) = find(body, &delim, 0) else { return (fields, files) };
+    loop {
+        pos += delim.len();
+        // "--" after the delimiter ends the body
+        if body[pos..].starts_with(b"--") { break; }
+        let Some(head_end) = find(body, b"\r\n\r\n", pos) else { break };
+        let head = String::from_utf8_lossy(&body[pos..head_end]).into_owned();
+        let data_start = head_end + 4;
+        let Some(next) = find(body, &[b"\r\n".as_slice(), &delim].concat(), data_start) else { break };
+        let data = &body[data_start..next];
+        let mut disposition = String::new();
+        let mut ctype = String::new();
+        for line in head.lines() {
+            if let Some((n, v)) = line.split_once(':') {
+                if n.trim().eq_ignore_ascii_case("Content-Disposition") { disposition = v.trim().to_string(); }
+                if n.trim().eq_ignore_ascii_case("Content-Type") { ctype = v.trim().to_string(); }
+            }
+        }
+        if let Some(name) = header_param(&disposition, "name") {
+            match header_param(&disposition, "filename") {
+                Some(filename) => {
+                    // Browsers on Windows may send a full path; keep only the last component
+                    let filename = filename.rsplit(['/', '\\']).next().unwrap_or("").to_string();
+                    if !filename.is_empty() {
+                        let content_type = if ctype.is_empty() { "application/octet-stream".into() } else { ctype };
+                        files.push(Upload { field: name, filename, content_type, data: data.to_vec() });
+                    }
+                }
+                None => fields.push((name, String::from_utf8_lossy(data).into_owned())),
+            }
+        }
+        pos = next + 2;
+    }
+    (fields, files)
+}
+
+/// The request a VM is answering, handed to the inner VMs of CLASS instances it creates so their
+/// methods see the same REQUEST@, RESPONSE@ and SESSION@ and print into the same response.
+#[derive(Clone)]
+pub(crate) struct WebContext {
+    pub cgi_env: Option<HashMap<String, String>>,
+    pub output: Option<Rc<RefCell<Box<dyn std::io::Write>>>>,
+    pub request: Rc<RequestData>,
+    pub response: Rc<RefCell<ResponseState>>,
+    pub session: Rc<RefCell<crate::session::SessionState>>,
+    pub websocket: Option<Rc<RefCell<crate::push::WsState>>>,
+}
+
+/// Everything REQUEST@ exposes, parsed once when the page starts.
+pub(crate) struct RequestData {
+    pub method: String,
+    pub uri: String,
+    pub path_info: String,
+    pub query_string: String,
+    pub content_type: String,
+    pub remote_addr: String,
+    pub query: Vec<(String, String)>,
+    pub form: Vec<(String, String)>,
+    pub files: Vec<Upload>,
+    pub headers: Vec<(String, String)>,
+    pub cookies: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Path parameters of the matched ROUTE (`/users/:id` -> `id`), filled in by the router
+    pub route: RefCell<Vec<(String, String)>>,
+}
+
+impl RequestData {
+    /// Build from CGI variables (`var`), the `HTTP_*` variables and the raw body.
+    pub(crate) fn new(var: impl Fn(&str) -> String, http_vars: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        let content_type = var("CONTENT_TYPE");
+        let (form, files) = body_params(&content_type, &body);
+        let mut headers: Vec<(String, String)> = http_vars.into_iter().map(|(k, v)| {
+            // HTTP_ACCEPT_LANGUAGE -> Accept-Language
+            let name = k.trim_start_matches("HTTP_").split('_').map(|w| {
+                let w = w.to_ascii_lowercase();
+                let mut c = w.chars();
+                c.next().map(|f| f.to_ascii_uppercase().to_string() + c.as_str()).unwrap_or_default()
+            }).collect::<Vec<_>>().join("-");
+            (name, v)
+        }).collect();
+        if !content_type.is_empty() { headers.push(("Content-Type".into(), content_type.clone())); }
+        let clen = var("CONTENT_LENGTH");
+        if !clen.is_empty() { headers.push(("Content-Length".into(), clen)); }
+        headers.sort();
+        let cookies = var("HTTP_COOKIE").split(';').filter_map(|c| {
+            let (k, v) = c.trim().split_once('=')?;
+            Some((k.trim().to_string(), form_decode(v.trim().trim_matches('"'))))
+        }).collect();
+        let query_string = var("QUERY_STRING");
+        RequestData {
+            method: { let m = var("REQUEST_METHOD"); if m.is_empty() { "GET".into() } else { m.to_ascii_uppercase() } },
+            uri: var("REQUEST_URI"),
+            path_info: var("PATH_INFO"),
+            query: parse_urlencoded(&query_string),
+            query_string,
+            content_type,
+            remote_addr: var("REMOTE_ADDR"),
+            form,
+            files,
+            headers,
+            cookies,
+            body,
+            route: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+/// Form fields in a POST body, urlencoded or multipart.
+pub(crate) fn body_params(content_type: &str, body: &[u8]) -> (Vec<(String, String)>, Vec<Upload>) {
+    let ct = content_type.to_ascii_lowercase();
+    if ct.starts_with("application/x-www-form-urlencoded") {
+        (parse_urlencoded(&String::from_utf8_lossy(body)), Vec::new())
+    } else if ct.starts_with("multipart/form-data") {
+        parse_multipart(body, content_type)
+    } else {
+        (Vec::new(), Vec::new())
+    }
+}
+
+pub(crate) fn lookup<'a>(pairs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    pairs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+fn to_dict(pairs: &[(String, String)]) -> Value {
+    // Repeated names keep their first value, like Query$/Form$
+    let mut map = HashMap::new();
+    for (k, v) in pairs { map.entry(k.clone()).or_insert_with(|| Value::Str(v.clone())); }
+    Value::Dict(Rc::new(RefCell::new(map)))
+}
+
+pub(crate) fn str_arg(args: &[Value], i: usize, what: &str) -> Result<String> {
+    match args.get(i) {
+        Some(Value::Str(s)) => Ok(s.clone()),
+        Some(v) => Ok(format!("{}", v)),
+        None => Err(BasilError(format!("{} expects a string argument", what))),
+    }
+}
+
+pub(crate) fn int_arg(args: &[Value], i: usize, what: &str) -> Result<Option<i64>> {
+    match args.get(i) {
+        None => Ok(None),
+        Some(Value::Int(n)) => Ok(Some(*n)),
+        Some(Value::Num(n)) => Ok(Some(*n as i64)),
+        Some(_) => Err(BasilError(format!("{} expects a number", what))),
+    }
+}
+
+// Method and property names may be written with or without their type suffix
+pub(crate) fn bare(name: &str) -> String { name.trim_end_matches(['$', '%']).to_ascii_uppercase() }
+
+pub(crate) struct RequestObj {
+    pub data: Rc<RequestData>,
+}
+
+impl RequestObj {
+    fn upload(&self, args: &[Value], what: &str) -> Result<Option<&Upload>> {
+        let field = str_arg(args, 0, what)?;
+        Ok(self.data.files.iter().find(|f| f.field == field))
+    }
+}
+
+impl BasicObject for RequestObj {
+    fn type_name(&self) -> &str { "REQUEST" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let d = &self.data;
+        Ok(match bare(name).as_str() {
+            "METHOD" => Value::Str(d.method.clone()),
+            "URI" => Value::Str(d.uri.clone()),
+            "PATH" => Value::Str(d.path_info.clone()),
+            "QUERYSTRING" => Value::Str(d.query_string.clone()),
+            "CONTENTTYPE" => Value::Str(d.content_type.clone()),
+            "REMOTEADDR" => Value::Str(d.remote_addr.clone()),
+            "BODY" => Value::Str(String::from_utf8_lossy(&d.body).into_owned()),
+            "ISPOST" => Value::Bool(d.method == "POST"),
+            "QUERY" => to_dict(&d.query),
+            "FORM" => to_dict(&d.form),
+            "COOKIES" => to_dict(&d.cookies),
+            "HEADERS" => to_dict(&d.headers),
+            "ROUTEPARAMS" => to_dict(&d.route.borrow()),
+            "FILES" => Value::List(Rc::new(RefCell::new(d.files.iter().map(|f| Value::Str(f.field.clone())).collect()))),
+            _ => return Err(BasilError(format!("Unknown property '{}' on REQUEST", name))),
+        })
+    }
+    fn set_prop(&mut self, name: &str, _v: Value) -> Result<()> {
+        Err(BasilError(format!("Property '{}' on REQUEST is read-only", name)))
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        let d = &self.data;
+        let with_default = |found: Option<&str>| -> Result<Value> {
+            let dflt = if args.len() > 1 { str_arg(args, 1, method)? } else { String::new() };
+            Ok(Value::Str(found.map(str::to_string).unwrap_or(dflt)))
+        };
+        match bare(method).as_str() {
+            "QUERY" => with_default(lookup(&d.query, &str_arg(args, 0, "REQUEST.Query$")?)),
+            "FORM" => with_default(lookup(&d.form, &str_arg(args, 0, "REQUEST.Form$")?)),
+            "PARAM" => {
+                let n = str_arg(args, 0, "REQUEST.Param$")?;
+                let route = d.route.borrow();
+                with_default(lookup(&route, &n).or_else(|| lookup(&d.form, &n)).or_else(|| lookup(&d.query, &n)))
+            }
+            "ROUTE" => with_default(lookup(&d.route.borrow(), &str_arg(args, 0, "REQUEST.Route$")?)),
+            "COOKIE" => with_default(lookup(&d.cookies, &str_arg(args, 0, "REQUEST.Cookie$")?)),
+            "HEADER" => {
+                let n = str_arg(args, 0, "REQUEST.Header$")?;
+                with_default(d.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(&n)).map(|(_, v)| v.as_str()))
+            }
+            "HASFILE" => Ok(Value::Bool(self.upload(args, "REQUEST.HasFile")?.is_some())),
+            "FILENAME" => Ok(Value::Str(self.upload(args, "REQUEST.FileName$")?.map(|f| f.filename.clone()).unwrap_or_default())),
+            "FILETYPE" => Ok(Value::Str(self.upload(args, "REQUEST.FileType$")?.map(|f| f.content_type.clone()).unwrap_or_default())),
+            "FILESIZE" => Ok(Value::Int(self.upload(args, "REQUEST.FileSize%")?.map(|f| f.data.len() as i64).unwrap_or(0))),
+            "SAVEFILE" => {
+                let path = str_arg(args, 1, "REQUEST.SaveFile")?;
+                let Some(f) = self.upload(args, "REQUEST.SaveFile")? else {
+                    return Err(BasilError(format!("REQUEST.SaveFile: no file uploaded in field '{}'", str_arg(args, 0, "")?)));
+                };
+                std::fs::write(&path, &f.data).map_err(|e| BasilError(format!("REQUEST.SaveFile {}: {}", path, e)))?;
+                Ok(Value::Int(f.data.len() as i64))
+            }
+            _ => Err(BasilError(format!("Unknown method '{}' on REQUEST", method))),
+        }
+    }
+    fn descriptor(&self) -> ObjectDescriptor { request_descriptor() }
+}
+
+pub(crate) fn prop(name: &str, ty: &str, writable: bool) -> PropDesc {
+    PropDesc { name: name.to_string(), type_name: ty.to_string(), readable: true, writable }
+}
+
+pub(crate) fn method(name: &str, args: &[&str], ret: &str) -> MethodDesc {
+    MethodDesc { name: name.to_string(), arity: args.len() as u8, arg_names: args.iter().map(|a| a.to_string()).collect(), return_type: ret.to_string() }
+}
+
+pub(crate) fn request_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "REQUEST".to_string(),
+        version: "1.0".to_string(),
+        summary: "The web request the page is answering (CGI, basilc serve, basilc fcgi)".to_string(),
+        properties: vec![
+            prop("Method$", "String", false),
+            prop("Uri$", "String", false),
+            prop("Path$", "String", false),
+            prop("QueryString$", "String", false),
+            prop("ContentType$", "String", false),
+            prop("RemoteAddr$", "String", false),
+            prop("Body$", "String", false),
+            prop("IsPost", "Bool", false),
+            prop("Query", "Dict", false),
+            prop("Form", "Dict", false),
+            prop("Cookies", "Dict", false),
+            prop("Headers", "Dict", false),
+            prop("RouteParams", "Dict", false),
+            prop("Files", "List", false),
+        ],
+        methods: vec![
+            method("Query$", &["name$", "[default$]"], "String"),
+            method("Form$", &["name$", "[default$]"], "String"),
+            method("Param$", &["name$", "[default$]"], "String"),
+            method("Route$", &["name$", "[default$]"], "String"),
+            method("Cookie$", &["name$", "[default$]"], "String"),
+            method("Header$", &["name$", "[default$]"], "String"),
+            method("HasFile", &["field$"], "Bool"),
+            method("FileName$", &["field$"], "String"),
+            method("FileType$", &["field$"], "String"),
+            method("FileSize%", &["field$"], "Integer"),
+            method("SaveFile", &["field$", "path$"], "Integer"),
+        ],
+        examples: vec![
+            "PRINT \"Hello, \" + HTML$(REQUEST@.Query$(\"name\", \"stranger\"))".to_string(),
+            "IF REQUEST@.HasFile(\"avatar\") THEN REQUEST@.SaveFile(\"avatar\", \"uploads/\" + REQUEST@.FileName$(\"avatar\"))".to_string(),
+        ],
+    }
+}
+
+/// Status, headers and cookies of the response; shared by RESPONSE@ and the VM's PRINT.
+pub(crate) struct ResponseState {
+    pub status: u16,
+    pub reason: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub cookies: Vec<String>,
+    /// The VM writes the header block (CGI/server modes); otherwise headers are only recorded
+    pub emit: bool,
+    pub sent: bool,
+}
+
+impl ResponseState {
+    pub(crate) fn new(default_header: Option<&str>) -> Self {
+        let headers = default_header.unwrap_or("").lines().filter_map(|l| {
+            let (n, v) = l.split_once(':')?;
+            Some((n.trim().to_string(), v.trim().to_string()))
+        }).collect();
+        ResponseState { status: 200, reason: None, headers, cookies: Vec::new(), emit: default_header.is_some(), sent: false }
+    }
+
+    /// Queue a Set-Cookie value; a later cookie with the same name and path replaces the earlier one.
+    pub(crate) fn push_cookie(&mut self, name: &str, path: &str, cookie: String) {
+        let prefix = format!("{}=", name);
+        let path_attr = format!("; Path={};", path);
+        self.cookies.retain(|old| !(old.starts_with(&prefix) && format!("{};", old).contains(&path_attr)));
+        self.cookies.push(cookie);
+    }
+
+    pub(crate) fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    fn header(&self, name: &str) -> String {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.clone()).unwrap_or_default()
+    }
+
+    /// The CGI header block, including the blank line that ends it.
+    pub(crate) fn header_block(&self) -> String {
+        let mut s = String::new();
+        if self.status != 200 || self.reason.is_some() {
+            let reason = self.reason.clone().unwrap_or_else(|| reason_phrase(self.status).to_string());
+            s.push_str(&format!("Status: {} {}\r\n", self.status, reason).replace(" \r\n", "\r\n"));
+        }
+        for (n, v) in &self.headers { s.push_str(&format!("{}: {}\r\n", n, v)); }
+        for c in &self.cookies { s.push_str(&format!("Set-Cookie: {}\r\n", c)); }
+        s.push_str("\r\n");
+        s
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK", 201 => "Created", 202 => "Accepted", 204 => "No Content",
+        301 => "Moved Permanently", 302 => "Found", 303 => "See Other", 304 => "Not Modified",
+        307 => "Temporary Redirect", 308 => "Permanent Redirect",
+        400 => "Bad Request", 401 => "Unauthorized", 403 => "Forbidden", 404 => "Not Found",
+        405 => "Method Not Allowed", 409 => "Conflict", 410 => "Gone", 422 => "Unprocessable Content",
+        429 => "Too Many Requests", 500 => "Internal Server Error", 501 => "Not Implemented",
+        502 => "Bad Gateway", 503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// Header values must not smuggle in extra header lines
+fn clean(v: &str, what: &str) -> Result<String> {
+    if v.contains(['\r', '\n']) { return Err(BasilError(format!("{}: value must not contain line breaks", what))); }
+    Ok(v.to_string())
+}
+
+// Cookie values are percent-encoded where the cookie syntax does not allow the raw byte
+fn cookie_encode(v: &str) -> String {
+    let mut out = String::new();
+    for b in v.bytes() {
+        if b.is_ascii_graphic() && !matches!(b, b'"' | b',' | b';' | b'\\' | b'%') { out.push(b as char); }
+        else { out.push_str(&format!("%{:02X}", b)); }
+    }
+    out
+}
+
+/// JSON text for RESPONSE@.Json$ and router handler results. Objects with a `ToJson$` method (ORM
+/// rows and queries) serialize themselves; a string that already holds JSON is passed through.
+#[cfg(feature = "obj-json")]
+pub(crate) fn json_text(v: &Value, what: &str) -> Result<String> {
+    if let Value::Str(s) = v {
+        if serde_json::from_str::<serde_json::Value>(s).is_ok() { return Ok(s.clone()); }
+    }
+    serde_json::to_string(&to_json(v, what)?).map_err(|e| BasilError(format!("{}: {}", what, e)))
+}
+
+#[cfg(not(feature = "obj-json"))]
+pub(crate) fn json_text(_v: &Value, what: &str) -> Result<String> {
+    Err(BasilError(format!("{} requires the obj-json feature", what)))
+}
+
+#[cfg(feature = "obj-json")]
+fn to_json(v: &Value, what: &str) -> Result<serde_json::Value> {
+    match v {
+        Value::Object(o) if o.borrow().descriptor().methods.iter().any(|m| bare(&m.name) == "TOJSON") => {
+            let text = o.borrow_mut().call("ToJson$", &[])?;
+            serde_json::from_str(&text.to_string()).map_err(|e| BasilError(format!("{}: ToJson$ returned invalid JSON: {}", what, e)))
+        }
+        Value::List(items) => Ok(serde_json::Value::Array(items.borrow().iter().map(|it| to_json(it, what)).collect::<Result<_>>()?)),
+        Value::Dict(map) => {
+            let mut obj = serde_json::Map::new();
+            for (k, it) in map.borrow().iter() { obj.insert(k.clone(), to_json(it, what)?); }
+            Ok(serde_json::Value::Object(obj))
+        }
+        other => basil_builtins::formats::value_to_json(other),
+    }
+}
+
+pub(crate) struct ResponseObj {
+    pub state: Rc<RefCell<ResponseState>>,
+}
+
+impl ResponseObj {
+    fn unsent(&self, what: &str) -> Result<std::cell::RefMut<'_, ResponseState>> {
+        let st = self.state.borrow_mut();
+        if st.sent { return Err(BasilError(format!("RESPONSE.{}: headers were already sent (output has started)", what))); }
+        Ok(st)
+    }
+}
+
+impl BasicObject for ResponseObj {
+    fn type_name(&self) -> &str { "RESPONSE" }
+    fn get_prop(&self, name: &str) -> Result<Value> {
+        let st = self.state.borrow();
+        Ok(match bare(name).as_str() {
+            "STATUS" => Value::Int(st.status as i64),
+            "CONTENTTYPE" => Value::Str(st.header("Content-Type")),
+            "HEADERSSENT" => Value::Bool(st.sent),
+            _ => return Err(BasilError(format!("Unknown property '{}' on RESPONSE", name))),
+        })
+    }
+    fn set_prop(&mut self, name: &str, v: Value) -> Result<()> {
+        match bare(name).as_str() {
+            "STATUS" => { self.call("SetStatus", &[v])?; }
+            "CONTENTTYPE" => { self.call("SetHeader", &[Value::Str("Content-Type".into()), v])?; }
+            _ => return Err(BasilError(format!("Property '{}' on RESPONSE is read-only", name))),
+        }
+        Ok(())
+    }
+    fn call(&mut self, method: &str, args: &[Value]) -> Result<Value> {
+        match bare(method).as_str() {
+            "SETSTATUS" => {
+                let code = int_arg(args, 0, "RESPONSE.SetStatus")?.ok_or_else(|| BasilError("RESPONSE.SetStatus expects a status code".into()))?;
+                if !(100..=999).contains(&code) { return Err(BasilError(format!("RESPONSE.SetStatus: invalid status code {}", code))); }
+                let reason = if args.len() > 1 { Some(clean(&str_arg(args, 1, "RESPONSE.SetStatus")?, "RESPONSE.SetStatus")?) } else { None };
+                let mut st = self.unsent("SetStatus")?;
+                st.status = code as u16;
+                st.reason = reason;
+            }
+            "SETHEADER" | "ADDHEADER" => {
+                let name = clean(&str_arg(args, 0, "RESPONSE.SetHeader")?, "RESPONSE.SetHeader")?;
+                let value = clean(&str_arg(args, 1, "RESPONSE.SetHeader")?, "RESPONSE.SetHeader")?;
+                if name.is_empty() || name.contains(':') { return Err(BasilError(format!("RESPONSE.SetHeader: invalid header name '{}'", name))); }
+                let mut st = self.unsent("SetHeader")?;
+                if name.eq_ignore_ascii_case("Status") {
+                    drop(st);
+                    let code = value.split_whitespace().next().and_then(|c| c.parse::<i64>().ok()).unwrap_or(200);
+                    return self.call("SetStatus", &[Value::Int(code)]);
+                }
+                if bare(method) == "ADDHEADER" { st.headers.push((name, value)); } else { st.set_header(&name, &value); }
+            }
+            "REMOVEHEADER" => {
+                let name = str_arg(args, 0, "RESPONSE.RemoveHeader")?;
+                self.unsent("RemoveHeader")?.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+            }
+            "SETCOOKIE" => {
+                // SetCookie(name$, value$ [, maxAgeSeconds% [, path$ [, attributes$]]])
+                let name = str_arg(args, 0, "RESPONSE.SetCookie")?;
+                if name.is_empty() || name.bytes().any(|b| !b.is_ascii_graphic() || b"()<>@,;:\\\"/[]?={}".contains(&b)) {
+                    return Err(BasilError(format!("RESPONSE.SetCookie: invalid cookie name '{}'", name)));
+                }
+                let value = str_arg(args, 1, "RESPONSE.SetCookie")?;
+                let max_age = int_arg(args, 2, "RESPONSE.SetCookie")?.unwrap_or(-1);
+                let path = if args.len() > 3 { clean(&str_arg(args, 3, "RESPONSE.SetCookie")?, "RESPONSE.SetCookie")? } else { "/".into() };
+                let attrs = if args.len() > 4 { clean(&str_arg(args, 4, "RESPONSE.SetCookie")?, "RESPONSE.SetCookie")? } else { "HttpOnly; SameSite=Lax".into() };
+                let mut c = format!("{}={}; Path={}", name, cookie_encode(&value), if path.is_empty() { "/" } else { &path });
+                if max_age >= 0 { c.push_str(&format!("; Max-Age={}", max_age)); }
+                if !attrs.trim().is_empty() { c.push_str("; "); c.push_str(attrs.trim()); }
+                self.unsent("SetCookie")?.push_cookie(&name, if path.is_empty() { "/" } else { &path }, c);
+            }
+            "DELETECOOKIE" => {
+                let name = str_arg(args, 0, "RESPONSE.DeleteCookie")?;
+                let path = if args.len() > 1 { str_arg(args, 1, "RESPONSE.DeleteCookie")? } else { "/".into() };
+                return self.call("SetCookie", &[Value::Str(name), Value::Str(String::new()), Value::Int(0), Value::Str(path), Value::Str(String::new())]);
+            }
+            "REDIRECT" => {
+                let url = clean(&str_arg(args, 0, "RESPONSE.Redirect")?, "RESPONSE.Redirect")?;
+                let code = int_arg(args, 1, "RESPONSE.Redirect")?.unwrap_or(302);
+                if !(300..=399).contains(&code) { return Err(BasilError(format!("RESPONSE.Redirect: {} is not a redirect status", code))); }
+                let mut st = self.unsent("Redirect")?;
+                st.status = code as u16;
+                st.reason = None;
+                st.set_header("Location", &url);
+            }
+            "JSON" => {
+                // Json$(value [, status%]): sets the JSON content type and returns the text to PRINT
+                let v = args.first().ok_or_else(|| BasilError("RESPONSE.Json$ expects a value".into()))?;
+                let text = json_text(v, "RESPONSE.Json$")?;
+                if args.len() > 1 { self.call("SetStatus", &args[1..2])?; }
+                self.unsent("Json$")?.set_header("Content-Type", "application/json; charset=utf-8");
+                return Ok(Value::Str(text));
+            }
+            _ => return Err(BasilError(format!("Unknown method '{}' on RESPONSE", method))),
+        }
+        Ok(Value::Null)
+    }
+    fn descriptor(&self) -> ObjectDescriptor { response_descriptor() }
+}
+
+pub(crate) fn response_descriptor() -> ObjectDescriptor {
+    ObjectDescriptor {
+        type_name: "RESPONSE".to_string(),
+        version: "1.0".to_string(),
+        summary: "Status, headers and cookies of the page's response; sent before the first output".to_string(),
+        properties: vec![
+            prop("Status%", "Integer", true),
+            prop("ContentType$", "String", true),
+            prop("HeadersSent", "Bool", false),
+        ],
+        methods: vec![
+            method("SetStatus", &["code%", "[reason$]"], "Void"),
+            method("SetHeader", &["name$", "value$"], "Void"),
+            method("AddHeader", &["name$", "value$"], "Void"),
+            method("RemoveHeader", &["name$"], "Void"),
+            method("SetCookie", &["name$", "value$", "[maxAge%]", "[path$]", "[attributes$]"], "Void"),
+            method("DeleteCookie", &["name$", "[path$]"], "Void"),
+            method("Redirect", &["url$", "[status%]"], "Void"),
+            method("Json$", &["value", "[status%]"], "String"),
+        ],
+        examples: vec![
+            "RESPONSE@.SetCookie(\"theme\", \"dark\", 86400)".to_string(),
+            "RESPONSE@.Redirect(\"/login.basil\")".to_string(),
+            "RESPONSE@.ContentType$ = \"application/json\"".to_string(),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_multipart_fields_and_files() {
+        let ct = "multipart/form-data; boundary=XyZ";
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"C:\\\\tmp\\\\a.txt\"\r\nContent-Type: text/plain\r\n\r\nline1\r\nline2\r\n\
+--XyZ--\r\n";
+        let (fields, files) = parse_multipart(body, ct);
+        assert_eq!(fields, vec![("title".to_string(), "Hello".to_string())]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "a.txt");
+        assert_eq!(files[0].content_type, "text/plain");
+        assert_eq!(files[0].data, b"line1\r\nline2");
+    }
+
+    #[test]
+    fn response_header_block() {
+        let state = Rc::new(RefCell::new(ResponseState::new(Some("Content-Type: text/html; charset=utf-8"))));
+        let mut r = ResponseObj { state: state.clone() };
+        r.call("SetCookie", &[Value::Str("sid".into()), Value::Str("a b;c".into()), Value::Int(60)]).unwrap();
+        r.call("Redirect", &[Value::Str("/next".into())]).unwrap();
+        assert!(r.call("SetHeader", &[Value::Str("X".into()), Value::Str("a\r\nEvil: 1".into())]).is_err());
+        let block = state.borrow().header_block();
+        assert_eq!(block, "Status: 302 Found\r\nContent-Type: text/html; charset=utf-8\r\nLocation: /next\r\n\
+Set-Cookie: sid=a%20b%3Bc; Path=/; Max-Age=60; HttpOnly; SameSite=Lax\r\n\r\n");
+        state.borrow_mut().sent = true;
+        assert!(r.call("SetStatus", &[Value::Int(404)]).is_err());
+    }
+}
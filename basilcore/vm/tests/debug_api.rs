@@ -53,3 +53,42 @@ fn breakpoint_and_output_events() {
     assert!(evs.iter().any(|e| e.starts_with("Output:Hello")));
     assert!(evs.contains(&"Exited".to_string()));
 }
+
+#[test]
+fn breakpoints_use_template_source_map() {
+    // Generated line 2 came from line 7 of a template
+    let mut chunk = Chunk::default();
+    chunk.push_op(Op::SetLine); chunk.push_u16(1);
+    chunk.push_op(Op::SetLine); chunk.push_u16(2);
+    chunk.push_op(Op::Halt);
+    let prog = BCProgram { chunk, globals: vec![] };
+    let mut map = basil_common::SourceMap::default();
+    let file = map.file_index("page.basil");
+    map.lines.push(basil_common::MappedLine { file, line: 3, col: 1 });
+    map.lines.push(basil_common::MappedLine { file, line: 7, col: 5 });
+
+    let dbg = Debugger::new();
+    let rx = dbg.subscribe();
+    let dbg_for_thread = dbg.clone();
+    let handle = thread::spawn(move || {
+        let mut stops = Vec::new();
+        while let Ok(ev) = rx.recv() {
+            match ev {
+                DebugEvent::StoppedBreakpoint { file, line } => { stops.push((file, line)); dbg_for_thread.resume(); }
+                DebugEvent::Exited => break,
+                _ => {}
+            }
+        }
+        stops
+    });
+
+    let mut vm = VM::new(prog);
+    vm.set_script_path("page.basil.generated".to_string());
+    vm.set_source_map(map);
+    dbg.set_breakpoint("page.basil".to_string(), 7);
+    vm.set_debugger(dbg.clone());
+    vm.run().expect("vm run");
+
+    let stops = handle.join().unwrap();
+    assert_eq!(stops, vec![("page.basil".to_string(), 7)]);
+}
@@ -0,0 +1,136 @@
+//! The shared build cache. Every build with the same toolchain, target, features, profile and
+//! dependency source uses one Cargo target dir, so the runtime crates are compiled once and
+//! each further program only compiles itself. A `Cargo.lock` is kept per target dir too, so a
+//! new program resolves to the same dependency versions (and needs no registry access).
+//!
+//! The cache lives in `BCC_CACHE_DIR`, or else in the user's cache directory: `~/.cache/bcc`
+//! on Linux, `~/Library/Caches/bcc` on macOS and `%LOCALAPPDATA%\bcc` on Windows.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use backend_rs::{BuildOptions, DepSource};
+use sha2::{Digest, Sha256};
+
+// Written into each shared target dir: what it was built for, for `bcc cache list`
+const KEY_FILE: &str = "bcc-key.txt";
+
+pub fn root() -> PathBuf {
+    if let Some(dir) = env::var_os("BCC_CACHE_DIR").filter(|d| !d.is_empty()) { return PathBuf::from(dir); }
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home.map(|h| h.join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()).map(PathBuf::from).or_else(|| home.map(|h| h.join(".cache")))
+    };
+    base.unwrap_or_else(|| env::current_dir().expect("cwd").join(".basil")).join("bcc")
+}
+
+// `rustc -vV` as Cargo will run it in `project` (a rust-toolchain file may pick another one)
+fn toolchain(project: &Path) -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    Command::new(rustc).arg("-vV").current_dir(project).output().ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown toolchain".to_string())
+}
+
+fn describe(opts: &BuildOptions, bundle: bool, toolchain: &str) -> String {
+    let deps = match &opts.dep_source {
+        DepSource::CratesIo => format!("crates-io ={}", opts.pinned_version),
+        DepSource::LocalPath(p) => format!("local {}", p.canonicalize().unwrap_or(p.clone()).display()),
+        DepSource::Vendor(p) => format!("vendor {} ={}", p.canonicalize().unwrap_or(p.clone()).display(), opts.pinned_version),
+    };
+    format!(
+        "kind: {}\ntoolchain: {}\ntarget: {}\nfeatures: {}\nprofile: opt-level {}, lto {}\ndeps: {}\n",
+        if bundle { "bundle (basil-vm)" } else { "aot (libbasilrt)" },
+        toolchain.lines().next().unwrap_or(""),
+        opts.target.as_deref().unwrap_or("host"),
+        if opts.features.is_empty() { "(none)".to_string() } else { opts.features.join(", ") },
+        opts.opt_level.unwrap_or(3),
+        opts.lto.as_deref().unwrap_or("thin"),
+        deps,
+    )
+}
+
+/// The shared target dir for one set of build options, and the lock file kept with it.
+pub struct TargetDir { pub dir: PathBuf, lock: PathBuf }
+
+impl TargetDir {
+    pub fn for_build(opts: &BuildOptions, bundle: bool, project: &Path) -> io::Result<TargetDir> {
+        let toolchain = toolchain(project);
+        let description = describe(opts, bundle, &toolchain);
+        let mut hasher = Sha256::new();
+        hasher.update(description.as_bytes());
+        hasher.update(toolchain.as_bytes());
+        let key: String = hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+        let root = root();
+        let dir = root.join("targets").join(&key);
+        fs::create_dir_all(&dir)?;
+        let key_file = dir.join(KEY_FILE);
+        if !key_file.exists() { fs::write(&key_file, description)?; }
+        Ok(TargetDir { dir, lock: root.join("locks").join(format!("{}.lock", key)) })
+    }
+
+    /// Start `project` from the lock of an earlier build with the same options, if there is one.
+    pub fn seed_lock(&self, project: &Path) {
+        if self.lock.exists() { let _ = fs::copy(&self.lock, project.join("Cargo.lock")); }
+    }
+
+    /// Keep `project`'s lock for the next build with the same options.
+    pub fn save_lock(&self, project: &Path) {
+        let lock = project.join("Cargo.lock");
+        if !lock.exists() { return; }
+        if let Some(dir) = self.lock.parent() { let _ = fs::create_dir_all(dir); }
+        let _ = fs::copy(lock, &self.lock);
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else { return 0 };
+    entries.filter_map(|e| e.ok()).map(|e| match e.file_type() {
+        Ok(t) if t.is_dir() => dir_size(&e.path()),
+        Ok(_) => e.metadata().map(|m| m.len()).unwrap_or(0),
+        Err(_) => 0,
+    }).sum()
+}
+
+fn target_dirs() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root().join("targets")) else { return Vec::new() };
+    let mut dirs: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).filter(|p| p.is_dir()).collect();
+    dirs.sort();
+    dirs
+}
+
+/// `bcc cache list`: every shared target dir, its size and what it was built for.
+pub fn list() {
+    let dirs = target_dirs();
+    println!("cache: {}", root().display());
+    if dirs.is_empty() { println!("no shared target dirs yet"); return; }
+    for dir in dirs {
+        let key = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        println!("\n{}  {:.1} MB", key, dir_size(&dir) as f64 / (1024.0 * 1024.0));
+        let description = fs::read_to_string(dir.join(KEY_FILE)).unwrap_or_default();
+        for line in description.lines() { println!("  {}", line); }
+    }
+}
+
+/// `bcc cache clean`: delete the shared target dirs, their locks and the `warm` projects.
+/// Returns how many target dirs were removed.
+pub fn clean() -> usize {
+    let root = root();
+    let count = target_dirs().len();
+    for sub in ["targets", "locks", "warm"] {
+        let dir = root.join(sub);
+        if dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&dir) { eprintln!("warning: cannot remove {}: {}", dir.display(), e); }
+        }
+    }
+    count
+}
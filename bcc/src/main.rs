@@ -1,61 +1,92 @@
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use basil_frontend::parse_program;
 use basil_ir::lower_to_ir;
-use backend_rs::{emit_project, BuildOptions, DepSource};
+use backend_rs::{emit_bundle, emit_project, is_wasi, BuildOptions, DepSource};
+
+mod cache;
 
 fn print_help() {
-    println!("bcc aot <input.basil> [options]\n\nOptions:\n  -o <outdir>                Output dir for final exe (unused; prints project path)\n  --name <prog>              Package/binary name\n  --features <spec>          @auto (default) | @all | obj-audio,obj-midi,...\n  --target <triple>          Rust target triple\n  --opt <0|1|2|3>            Optimize level (default 3)\n  --lto <off|thin|fat>       Link-time optimization (default thin)\n  --emit-project <dir>       Emit Cargo project only, don’t build\n  --dep-source <mode>        crates-io (default) | local | vendor\n  --local-runtime <dir>      Repo root containing crates/libbasilrt (for --dep-source local)\n  --vendor-dir <dir>         Directory containing a cargo vendor bundle (for --dep-source vendor)\n  --keep-build               Keep temp build directory\n  --quiet                    Less output\n  -h, --help                 Show this help\n");
+    println!("bcc aot <input.basil> [options]      Compile to Rust (or C) and build a native exe\nbcc bundle <input.basil> [options]   Build an exe that runs the program's bytecode on the embedded VM\nbcc cache [dir|list|warm|clean]      Manage the shared build cache (see 'bcc cache --help')\n\nOptions:\n  -o <outdir>                Output dir for final exe (unused; prints project path)\n  --name <prog>              Package/binary name\n  --backend <rust|c>         (aot) Emit Rust built with Cargo (default), or C built with $CC/cc\n  --features <spec>          @auto (default) | @all | obj-audio,obj-midi,...\n  --target <triple>          Rust target triple; wasm32-wasi builds a .wasm (C: builds with <triple>-gcc)\n  --opt <0|1|2|3>            Optimize level (default 3)\n  --lto <off|thin|fat>       Link-time optimization (default thin)\n  --emit-project <dir>       Emit Cargo (or C) project only, don’t build\n  --dep-source <mode>        crates-io (default) | local | vendor\n  --local-runtime <dir>      Repo root containing crates/libbasilrt (for --dep-source local)\n  --vendor-dir <dir>         Directory containing a cargo vendor bundle (for --dep-source vendor)\n  --offline                  Build without network access (implied by --dep-source vendor)\n  --keep-build               Keep temp build directory\n  --embed <path>             (bundle) Embed a data file or directory, read by READFILE$/FOPEN/CLASS() when missing on disk\n  --quiet                    Less output\n  -h, --help                 Show this help\n");
+}
+
+fn print_cache_help() {
+    println!("bcc cache [command]\n\nCommands:\n  dir                        Print the cache directory (set BCC_CACHE_DIR to move it)\n  list                       List the shared target dirs with what they were built for (default)\n  warm [options] [--bundle]  Prebuild the runtime for these build options, so the next build only compiles the program\n  clean [--projects]         Delete the shared target dirs; --projects also deletes ./.basil/targets\n\n'warm' takes the same --features/--target/--opt/--lto/--dep-source/--local-runtime/--vendor-dir/--offline options as 'bcc aot'.\n");
 }
 
 fn main() {
     let mut args: Vec<String> = env::args().skip(1).collect();
     if args.is_empty() || args[0] == "-h" || args[0] == "--help" { print_help(); return; }
     let cmd = args.remove(0);
-    if cmd != "aot" { eprintln!("error: unknown command '{}'. Use 'bcc aot <file>'.", cmd); std::process::exit(2); }
-    if args.is_empty() { eprintln!("error: missing <input.basil>"); std::process::exit(2); }
-
-    let input = args.remove(0);
-    let input_path = PathBuf::from(&input);
-    let src = match fs::read_to_string(&input_path) { Ok(s) => s, Err(e) => { eprintln!("error: {}", e); std::process::exit(1); } };
-
-    // Defaults
-    let mut name: Option<String> = None;
-    let mut features_spec: Option<String> = None; // default @auto
-    let mut target: Option<String> = None;
-    let mut opt_level: Option<u8> = None;
-    let mut lto: Option<String> = None;
-    let mut emit_project_dir: Option<PathBuf> = None;
-    let mut keep_build = false;
-    let mut quiet = false;
+    match cmd.as_str() {
+        "aot" => cmd_compile(args, false),
+        "bundle" => cmd_compile(args, true),
+        "cache" => cmd_cache(args),
+        _ => { eprintln!("error: unknown command '{}'. Use 'bcc aot <file>', 'bcc bundle <file>' or 'bcc cache'.", cmd); std::process::exit(2); }
+    }
+}
 
+// Options shared by `bcc aot`, `bcc bundle` and `bcc cache warm`
+#[derive(Default)]
+struct Flags {
+    name: Option<String>,
+    backend: Option<String>, // rust (default) | c
+    features_spec: Option<String>, // default @auto
+    target: Option<String>,
+    opt_level: Option<u8>,
+    lto: Option<String>,
+    emit_project_dir: Option<PathBuf>,
+    keep_build: bool,
+    quiet: bool,
+    offline: bool,
+    embeds: Vec<String>,
     // Dependency/source selection
-    let mut dep_source_choice: Option<String> = None; // crates-io | local | vendor
-    let mut local_runtime_root: Option<PathBuf> = None;
-    let mut vendor_dir: Option<PathBuf> = None;
+    dep_source_choice: Option<String>, // crates-io | local | vendor
+    local_runtime_root: Option<PathBuf>,
+    vendor_dir: Option<PathBuf>,
+    // Words for a subcommand, such as `bcc cache warm --bundle`
+    rest: Vec<String>,
+}
 
-    // Parse flags
+fn parse_flags(args: &[String]) -> Flags {
+    let mut f = Flags::default();
     let mut i = 0;
     while i < args.len() {
         match args[i].as_str() {
             "-o" => { i+=1; /* planned: output dir for exe */ i+=1; },
-            "--name" => { i+=1; name = args.get(i).cloned(); i+=1; },
-            "--features" => { i+=1; features_spec = args.get(i).cloned(); i+=1; },
-            "--target" => { i+=1; target = args.get(i).cloned(); i+=1; },
-            "--opt" => { i+=1; opt_level = args.get(i).and_then(|s| s.parse::<u8>().ok()); i+=1; },
-            "--lto" => { i+=1; lto = args.get(i).cloned(); i+=1; },
-            "--emit-project" => { i+=1; emit_project_dir = args.get(i).map(|s| PathBuf::from(s)); i+=1; },
-            "--dep-source" => { i+=1; dep_source_choice = args.get(i).cloned(); i+=1; },
-            "--local-runtime" => { i+=1; local_runtime_root = args.get(i).map(|s| PathBuf::from(s)); i+=1; },
-            "--vendor-dir" => { i+=1; vendor_dir = args.get(i).map(|s| PathBuf::from(s)); i+=1; },
-            "--keep-build" => { keep_build = true; i+=1; },
-            "--quiet" => { quiet = true; i+=1; },
+            "--name" => { i+=1; f.name = args.get(i).cloned(); i+=1; },
+            "--backend" => { i+=1; f.backend = args.get(i).cloned(); i+=1; },
+            "--features" => { i+=1; f.features_spec = args.get(i).cloned(); i+=1; },
+            // Rust 1.84 renamed wasm32-wasi to wasm32-wasip1
+            "--target" => { i+=1; f.target = args.get(i).map(|t| if t == "wasm32-wasi" { "wasm32-wasip1".to_string() } else { t.clone() }); i+=1; },
+            "--opt" => { i+=1; f.opt_level = args.get(i).and_then(|s| s.parse::<u8>().ok()); i+=1; },
+            "--lto" => { i+=1; f.lto = args.get(i).cloned(); i+=1; },
+            "--emit-project" => { i+=1; f.emit_project_dir = args.get(i).map(PathBuf::from); i+=1; },
+            "--dep-source" => { i+=1; f.dep_source_choice = args.get(i).cloned(); i+=1; },
+            "--local-runtime" => { i+=1; f.local_runtime_root = args.get(i).map(PathBuf::from); i+=1; },
+            "--vendor-dir" => { i+=1; f.vendor_dir = args.get(i).map(PathBuf::from); i+=1; },
+            "--offline" => { f.offline = true; i+=1; },
+            "--keep-build" => { f.keep_build = true; i+=1; },
+            "--embed" => { i+=1; if let Some(p) = args.get(i) { f.embeds.push(p.clone()); } i+=1; },
+            "--quiet" => { f.quiet = true; i+=1; },
+            other if !other.starts_with('-') || other == "--bundle" || other == "--projects" => { f.rest.push(other.to_string()); i+=1; },
             other => { eprintln!("warning: unknown option '{}' (ignored)", other); i+=1; }
         }
     }
+    f
+}
+
+fn cmd_compile(mut args: Vec<String>, bundle: bool) {
+    if args.is_empty() { eprintln!("error: missing <input.basil>"); std::process::exit(2); }
+
+    let input = args.remove(0);
+    let input_path = PathBuf::from(&input);
+    let src = match fs::read_to_string(&input_path) { Ok(s) => s, Err(e) => { eprintln!("error: {}", e); std::process::exit(1); } };
+    let flags = parse_flags(&args);
+    for extra in &flags.rest { eprintln!("warning: unknown option '{}' (ignored)", extra); }
 
     // Parse via shared frontend
     let program = match parse_program(&src) {
@@ -63,12 +94,173 @@ fn main() {
         Err(e) => { eprintln!("parse error: {}", e); std::process::exit(1); }
     };
 
+    if !flags.embeds.is_empty() && !bundle { eprintln!("warning: --embed only applies to 'bcc bundle' (ignored)"); }
+    let c_backend = match flags.backend.as_deref() {
+        None | Some("rust") => false,
+        Some("c") if bundle => { eprintln!("warning: --backend only applies to 'bcc aot' (ignored)"); false }
+        Some("c") => true,
+        Some(other) => { eprintln!("error: unknown --backend '{}' (expected rust or c)", other); std::process::exit(2); }
+    };
+    // A bundle carries the CLASS files the program names, so their features count too
+    let script_dir = input_path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let classes = if bundle { class_files(&src, script_dir) } else { Vec::new() };
+    let scanned = classes.iter().fold(src.clone(), |all, c| all + "\n" + &c.source);
+
     // Feature detection
-    let auto_features = autodetect_features(&src);
-    let curated_default = vec!["audio".to_string(), "midi".to_string(), "daw".to_string(), "term".to_string()];
-    let (rt_features, obj_crates) = match features_spec.as_deref() {
+    let wasi = is_wasi(flags.target.as_deref());
+    let (rt_features, obj_crates) = select_features(flags.features_spec.as_deref(), autodetect_features(&scanned), bundle || wasi);
+    if wasi { check_wasi_features(flags.target.as_deref().unwrap_or_default(), &rt_features); }
+
+    // Early validation: if src refers to AUDIO_/MIDI_/DAW_/TERM_ but feature missing. The C
+    // runtime has no features; backend_c::check names the builtins it lacks instead.
+    if let Some(miss) = first_missing_required_feature(&scanned, &rt_features).filter(|_| !c_backend) {
+        eprintln!("error: {} requires feature '{}'\nhelp: Add '#USE {}' or run with: --features obj-{}",
+            miss.hint, miss.required_obj, miss.suggest_use, miss.cli_name);
+        std::process::exit(1);
+    }
+
+    let artifact = if bundle {
+        // The whole language runs on the VM, so anything basilc compiles can be bundled
+        let prog = match basil_compiler::compile(&program) {
+            Ok(p) => p,
+            Err(e) => { eprintln!("error: {}", e); std::process::exit(1); }
+        };
+        let mut files = Vec::new();
+        for c in &classes {
+            match c.embedded() {
+                Ok(f) => files.push(f),
+                Err(e) => { eprintln!("error: {}", e); std::process::exit(1); }
+            }
+        }
+        for e in &flags.embeds {
+            if let Err(err) = collect_embeds(Path::new(e), script_dir, &mut files) { eprintln!("error: --embed {}: {}", e, err); std::process::exit(1); }
+        }
+        Artifact::Bundle { program: basil_bytecode::serialize_program(&prog), files }
+    } else {
+        // Lower to IR; constructs bcc can't compile yet are errors rather than silently dropped
+        let mut module = match lower_to_ir(&program) {
+            Ok(m) => m,
+            Err(e) => { eprintln!("error: {}", e); std::process::exit(1); }
+        };
+        // SSA, constant propagation, DCE and loop-invariant hoisting; typed values become native Rust
+        basil_ir::optimize(&mut module);
+        if c_backend {
+            if let Err(e) = backend_c::check(&module) { eprintln!("error: {}", e); std::process::exit(1); }
+            Artifact::C(module)
+        } else {
+            Artifact::Aot(module)
+        }
+    };
+
+    // Emit project
+    let opts = build_options(&flags, rt_features, obj_crates);
+    let base_dir = std::env::current_dir().expect("cwd");
+    let emitted = match &artifact {
+        Artifact::Aot(module) => emit_project(&base_dir, &input_path, module, &opts).map(|p| p.root),
+        Artifact::Bundle { program, files } => emit_bundle(&base_dir, &input_path, program, files, &opts).map(|p| p.root),
+        Artifact::C(module) => backend_c::emit_project(&base_dir, &input_path, module, &opts).map(|p| p.root),
+    };
+    let root = match emitted {
+        Ok(root) => root,
+        Err(e) => { eprintln!("error: failed to emit project: {}", e); std::process::exit(1); }
+    };
+
+    if flags.emit_project_dir.is_some() {
+        if !flags.quiet { println!("project written to {}", root.display()); }
+        return;
+    }
+
+    let built_exe = if let Artifact::C(_) = artifact {
+        match backend_c::build(&root, &opts) {
+            Ok(exe) => exe,
+            Err(e) => { eprintln!("error: {}", e); std::process::exit(1); }
+        }
+    } else {
+        cargo_build(&root, &opts, bundle, flags.offline)
+    };
+
+    // Move the executable into the current directory and rename to match source file name
+    let src_stem = input_path.file_stem().and_then(|s| s.to_str()).unwrap_or("prog");
+    let out_name = exe_file_name(src_stem, opts.target.as_deref());
+    let out_path = base_dir.join(out_name);
+
+    // Overwrite if it exists
+    if out_path.exists() { let _ = fs::remove_file(&out_path); }
+
+    // Try rename (move). If it fails (e.g., cross-filesystem), fall back to copy then remove
+    let moved_ok = fs::rename(&built_exe, &out_path).is_ok();
+    if !moved_ok {
+        match fs::copy(&built_exe, &out_path) {
+            Ok(_) => { let _ = fs::remove_file(&built_exe); }
+            Err(e) => { eprintln!("error: failed to write executable to {}: {}", out_path.display(), e); std::process::exit(1); }
+        }
+    }
+
+    // On Unix, ensure the output file is executable (chmod 755)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = fs::metadata(&out_path) {
+            let mut perm = meta.permissions();
+            perm.set_mode(0o755);
+            let _ = fs::set_permissions(&out_path, perm);
+        }
+    }
+
+    if !flags.quiet { println!("executable: {}", out_path.display()); }
+}
+
+fn cmd_cache(args: Vec<String>) {
+    let sub = args.first().cloned().unwrap_or_else(|| "list".to_string());
+    let flags = parse_flags(args.get(1..).unwrap_or(&[]));
+    match sub.as_str() {
+        "-h" | "--help" => print_cache_help(),
+        "dir" => println!("{}", cache::root().display()),
+        "list" => cache::list(),
+        "clean" => {
+            let removed = cache::clean();
+            println!("removed {} shared target dir(s) from {}", removed, cache::root().display());
+            if flags.rest.iter().any(|a| a == "--projects") {
+                let projects = std::env::current_dir().expect("cwd").join(".basil").join("targets");
+                if projects.exists() && fs::remove_dir_all(&projects).is_ok() { println!("removed {}", projects.display()); }
+            }
+        }
+        "warm" => {
+            // An empty program with the same dependencies compiles everything but the program itself
+            let bundle = flags.rest.iter().any(|a| a == "--bundle");
+            let wasi = is_wasi(flags.target.as_deref());
+            let (rt_features, obj_crates) = select_features(flags.features_spec.as_deref(), Vec::new(), bundle || wasi);
+            if wasi { check_wasi_features(flags.target.as_deref().unwrap_or_default(), &rt_features); }
+            let mut opts = build_options(&flags, rt_features, obj_crates);
+            opts.name = Some("bcc_warm".to_string());
+            let stub = Path::new("warm.basil");
+            let dir = cache::root().join("warm");
+            let emitted = if bundle {
+                let prog = basil_compiler::compile(&parse_program("").expect("empty program parses")).expect("empty program compiles");
+                emit_bundle(&dir, stub, &basil_bytecode::serialize_program(&prog), &[], &opts)
+            } else {
+                let module = lower_to_ir(&parse_program("").expect("empty program parses")).expect("empty program lowers");
+                emit_project(&dir, stub, &module, &opts)
+            };
+            let emitted = match emitted {
+                Ok(p) => p,
+                Err(e) => { eprintln!("error: failed to emit project: {}", e); std::process::exit(1); }
+            };
+            let exe = cargo_build(&emitted.root, &opts, bundle, flags.offline);
+            let _ = fs::remove_file(exe);
+            if !flags.quiet { println!("ok: runtime built for features [{}]", opts.features.join(", ")); }
+        }
+        other => { eprintln!("error: unknown cache command '{}'", other); print_cache_help(); std::process::exit(2); }
+    }
+}
+
+// The runtime features (and object crates) a build turns on for a `--features` spec. A minimal
+// build (a bundle, or any WASI build) only turns on what the script uses.
+fn select_features(spec: Option<&str>, auto_features: Vec<String>, minimal: bool) -> (Vec<String>, Vec<String>) {
+    let curated_default = if minimal { Vec::new() } else { vec!["audio".to_string(), "midi".to_string(), "daw".to_string(), "term".to_string()] };
+    match spec {
         None | Some("@auto") => {
-            let set = to_set(curated_default.iter().cloned().chain(auto_features.clone()));
+            let set = to_set(curated_default.iter().cloned().chain(auto_features));
             let rt_vec: Vec<String> = set.iter().cloned().collect();
             (rt_vec, map_rt_to_crates(&set))
         }
@@ -77,42 +269,49 @@ fn main() {
             (all.clone(), map_rt_to_crates(&to_set(all.into_iter())))
         }
         Some(list) => {
-            let objs = list.split(|c| c==',' || c==' ').filter(|s| !s.is_empty());
+            let objs = list.split([',', ' ']).filter(|s| !s.is_empty());
             let mut rt = Vec::new(); let mut objc = Vec::new();
             for o in objs { if let Some((r, oc)) = map_obj_to_rt(o) { rt.push(r); objc.push(oc); } }
             let rt = to_set(rt.into_iter()).into_iter().collect();
             let objc = to_set(objc.into_iter()).into_iter().collect();
             (rt, objc)
         }
-    };
-
-    // Early validation: if src refers to AUDIO_/MIDI_/DAW_/TERM_ but feature missing
-    if let Some(miss) = first_missing_required_feature(&src, &rt_features) {
-        eprintln!("error: {} requires feature '{}'\nhelp: Add '#USE {}' or run with: --features obj-{}",
-            miss.hint, miss.required_obj, miss.suggest_use, miss.cli_name);
-        std::process::exit(1);
     }
+}
 
-    // Lower to IR
-    let module = lower_to_ir(&program);
+// Features whose objects need something a WASI sandbox doesn't have
+const WASI_UNSUPPORTED: &[(&str, &str)] = &[
+    ("audio", "audio devices"), ("midi", "MIDI devices"), ("daw", "audio or MIDI devices"),
+    ("term", "terminal"), ("curl", "network sockets"), ("sqlite", "C toolchain for the bundled SQLite"),
+];
 
+fn check_wasi_features(target: &str, rt_features: &[String]) {
+    for (f, missing) in WASI_UNSUPPORTED {
+        if rt_features.iter().any(|x| x == f) {
+            eprintln!("error: feature 'obj-{}' is not available for {} (WASI has no {})\nhelp: drop it from --features or #USE, or build for a native target", f, target, missing);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn build_options(flags: &Flags, rt_features: Vec<String>, obj_crates: Vec<String>) -> BuildOptions {
     // Resolve dependency source mode
-    let dep_source = match dep_source_choice.as_deref() {
+    let dep_source = match flags.dep_source_choice.as_deref() {
         Some("local") => {
-            let root = local_runtime_root.clone().unwrap_or_else(|| std::env::current_dir().expect("cwd"));
+            let root = flags.local_runtime_root.clone().unwrap_or_else(|| std::env::current_dir().expect("cwd"));
             DepSource::LocalPath(root)
         }
         Some("vendor") => {
-            let vd = vendor_dir.clone().unwrap_or_else(|| {
+            let vd = flags.vendor_dir.clone().unwrap_or_else(|| {
                 eprintln!("error: --dep-source vendor requires --vendor-dir <dir>");
                 std::process::exit(2);
             });
             DepSource::Vendor(vd)
         }
         Some("crates-io") | None => {
-            if dep_source_choice.is_none() {
+            if flags.dep_source_choice.is_none() {
                 // If user provided vendor-dir without dep-source, assume vendor
-                if let Some(vd) = vendor_dir.clone() { DepSource::Vendor(vd) } else if let Some(root) = local_runtime_root.clone() { DepSource::LocalPath(root) } else { DepSource::CratesIo }
+                if let Some(vd) = flags.vendor_dir.clone() { DepSource::Vendor(vd) } else if let Some(root) = flags.local_runtime_root.clone() { DepSource::LocalPath(root) } else { DepSource::CratesIo }
             } else {
                 DepSource::CratesIo
             }
@@ -123,84 +322,130 @@ fn main() {
         }
     };
 
-    // Emit project
-    let mut opts = BuildOptions::default();
-    opts.name = name;
-    opts.target = target.clone();
-    opts.opt_level = opt_level;
-    opts.lto = lto;
-    opts.features = rt_features.clone();
-    opts.obj_crates = obj_crates.clone();
-    opts.emit_project_dir = emit_project_dir.clone();
-    opts.keep_build = keep_build;
-    opts.quiet = quiet;
-    opts.dep_source = dep_source;
-    opts.pinned_version = "0.1.0".to_string();
+    BuildOptions {
+        name: flags.name.clone(),
+        target: flags.target.clone(),
+        opt_level: flags.opt_level,
+        lto: flags.lto.clone(),
+        features: rt_features,
+        obj_crates,
+        emit_project_dir: flags.emit_project_dir.clone(),
+        keep_build: flags.keep_build,
+        quiet: flags.quiet,
+        dep_source,
+        pinned_version: "0.1.0".to_string(),
+    }
+}
 
-    let base_dir = std::env::current_dir().expect("cwd");
-    let emitted = match emit_project(&base_dir, &input_path, &module, &opts) {
-        Ok(p) => p,
-        Err(e) => { eprintln!("error: failed to emit project: {}", e); std::process::exit(1); }
+// Build an emitted project in the shared target dir for its options and return the executable.
+// Builds with the same toolchain, target, features and profile reuse the compiled runtime.
+fn cargo_build(root: &Path, opts: &BuildOptions, bundle: bool, offline: bool) -> PathBuf {
+    let shared = match cache::TargetDir::for_build(opts, bundle, root) {
+        Ok(t) => t,
+        Err(e) => { eprintln!("error: cannot prepare build cache in {}: {}", cache::root().display(), e); std::process::exit(1); }
     };
+    // Use --locked only for the project's own lock from an earlier build, and never for local
+    // paths; a lock seeded from the cache still names another program as its root package
+    let have_lock = root.join("Cargo.lock").exists();
+    let use_locked = have_lock && !matches!(opts.dep_source, DepSource::LocalPath(_));
+    if !have_lock { shared.seed_lock(root); }
 
-    if emit_project_dir.is_some() {
-        if !quiet { println!("project written to {}", emitted.root.display()); }
-        return;
-    }
-
-    // Build with cargo
     let mut cmd = Command::new("cargo");
-    cmd.arg("build").arg("--release").current_dir(&emitted.root);
-    // Use --locked only when appropriate: for crates-io/vendor sources if lock exists; never for local path
-    let have_lock = emitted.root.join("Cargo.lock").exists();
-    let use_locked = have_lock && !matches!(opts.dep_source, DepSource::LocalPath(_));
+    cmd.arg("build").arg("--release").current_dir(root);
     if use_locked { cmd.arg("--locked"); }
-    if let Some(t) = target { cmd.arg("--target").arg(t); }
-    // Offline when using vendor
-    let is_vendor = matches!(opts.dep_source, DepSource::Vendor(_));
-    if is_vendor { cmd.arg("--offline"); }
-    // Keep build outputs inside the emitted project
-    cmd.env("CARGO_TARGET_DIR", emitted.root.join("target"));
-    if !quiet { println!("building with Cargo in {}", emitted.root.display()); }
+    if let Some(t) = &opts.target { cmd.arg("--target").arg(t); }
+    // Offline when asked, and always with vendored sources
+    if offline || matches!(opts.dep_source, DepSource::Vendor(_)) { cmd.arg("--offline"); }
+    cmd.env("CARGO_TARGET_DIR", &shared.dir);
+    if !opts.quiet { println!("building with Cargo in {} (target dir {})", root.display(), shared.dir.display()); }
     let status = match cmd.status() { Ok(s) => s, Err(e) => { eprintln!("error: failed to run cargo: {}", e); std::process::exit(1); } };
     if !status.success() { eprintln!("error: cargo build failed"); std::process::exit(1); }
+    shared.save_lock(root);
 
-    if !quiet { println!("ok: project at {}", emitted.root.display()); }
-    // Determine built executable path inside the emitted project
+    if !opts.quiet { println!("ok: project at {}", root.display()); }
+    // Determine built executable path inside the shared target dir
     let bin_name = opts.name.clone().unwrap_or_else(|| "basil_prog".to_string());
-    let bin_dir = if let Some(tgt) = &opts.target { emitted.root.join("target").join(tgt).join("release") } else { emitted.root.join("target").join("release") };
-    let built_exe = if cfg!(windows) { bin_dir.join(format!("{}.exe", bin_name)) } else { bin_dir.join(&bin_name) };
+    let bin_dir = if let Some(tgt) = &opts.target { shared.dir.join(tgt).join("release") } else { shared.dir.join("release") };
+    bin_dir.join(exe_file_name(&bin_name, opts.target.as_deref()))
+}
 
-    // Move the executable into the current directory and rename to match source file name
-    let base_dir = std::env::current_dir().expect("cwd");
-    let src_stem = input_path.file_stem().and_then(|s| s.to_str()).unwrap_or("prog");
-    let out_name = if cfg!(windows) { format!("{}.exe", src_stem) } else { src_stem.to_string() };
-    let out_path = base_dir.join(out_name);
+// A built program's file name: a WASI module is `<name>.wasm`, a Windows exe `<name>.exe`
+fn exe_file_name(name: &str, target: Option<&str>) -> String {
+    if is_wasi(target) { format!("{}.wasm", name) } else if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() }
+}
 
-    // Overwrite if it exists
-    if out_path.exists() { let _ = fs::remove_file(&out_path); }
+enum Artifact {
+    Aot(basil_ir::Module),
+    // The same IR, for the C backend
+    C(basil_ir::Module),
+    // Serialized bytecode plus the files embedded next to it, by the name the program uses
+    Bundle { program: Vec<u8>, files: Vec<(String, Vec<u8>)> },
+}
 
-    // Try rename (move). If it fails (e.g., cross-filesystem), fall back to copy then remove
-    let moved_ok = fs::rename(&built_exe, &out_path).is_ok();
-    if !moved_ok {
-        match fs::copy(&built_exe, &out_path) {
-            Ok(_) => { let _ = fs::remove_file(&built_exe); }
-            Err(e) => { eprintln!("error: failed to write executable to {}: {}", out_path.display(), e); std::process::exit(1); }
-        }
+// A CLASS file named by a string literal, found where `basilc run` would look for it
+struct ClassFile { name: String, path: PathBuf, source: String }
+
+impl ClassFile {
+    // Basil sources are embedded precompiled, under the .basilx name the VM also tries
+    fn embedded(&self) -> Result<(String, Vec<u8>), String> {
+        let is_source = self.path.extension().is_some_and(|e| e.eq_ignore_ascii_case("basil"));
+        if !is_source { return fs::read(&self.path).map(|b| (self.name.clone(), b)).map_err(|e| format!("{}: {}", self.path.display(), e)); }
+        let ast = parse_program(&self.source).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let prog = basil_compiler::compile(&ast).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let name = Path::new(&self.name).with_extension("basilx").to_string_lossy().replace('\\', "/");
+        Ok((name, basil_bytecode::serialize_program(&prog)))
     }
+}
 
-    // On Unix, ensure the output file is executable (chmod 755)
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        if let Ok(meta) = fs::metadata(&out_path) {
-            let mut perm = meta.permissions();
-            perm.set_mode(0o755);
-            let _ = fs::set_permissions(&out_path, perm);
-        }
+// CLASS("...") literals in `src` and in the class files they name, resolved next to the script
+// first and then from the current directory. Files that can't be found are left to run time.
+fn class_files(src: &str, script_dir: &Path) -> Vec<ClassFile> {
+    let mut found: Vec<ClassFile> = Vec::new();
+    let mut pending: Vec<String> = class_literals(src);
+    while let Some(name) = pending.pop() {
+        if found.iter().any(|c| c.name == name) { continue; }
+        let base = PathBuf::from(&name);
+        let tries: Vec<PathBuf> = if base.extension().is_none() { vec![base.with_extension("basil"), base.with_extension("basilx")] } else { vec![base.clone()] };
+        let path = tries.iter().flat_map(|t| [script_dir.join(t), t.clone()]).find(|p| p.is_file());
+        let Some(path) = path else { eprintln!("warning: CLASS file {} not found; it will be loaded from disk at run time", name); continue };
+        let source = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("basilx")) { String::new() } else { fs::read_to_string(&path).unwrap_or_default() };
+        pending.extend(class_literals(&source));
+        // Without an extension the VM tries .basil, then .basilx: keep the one that was found
+        let name = if base.extension().is_some() { name } else { base.with_extension(path.extension().unwrap_or_default()).to_string_lossy().to_string() };
+        found.push(ClassFile { name, path, source });
     }
+    found
+}
 
-    if !quiet { println!("executable: {}", out_path.display()); }
+fn class_literals(src: &str) -> Vec<String> {
+    let upper = src.to_ascii_uppercase();
+    let mut out = Vec::new();
+    let mut at = 0;
+    while let Some(i) = upper[at..].find("CLASS(") {
+        let rest = src[at + i + 6..].trim_start();
+        at += i + 6;
+        let Some(lit) = rest.strip_prefix('"') else { continue };
+        if let Some(end) = lit.find('"') { out.push(lit[..end].to_string()); }
+    }
+    out
+}
+
+// A file, or every file under a directory, keyed by its path relative to the script's
+// directory (which is where `basilc run` runs it from), or as given if it is elsewhere
+fn collect_embeds(path: &Path, script_dir: &Path, out: &mut Vec<(String, Vec<u8>)>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)?.filter_map(|e| e.ok().map(|e| e.path())).collect();
+        entries.sort();
+        for e in entries { collect_embeds(&e, script_dir, out)?; }
+        return Ok(());
+    }
+    let data = fs::read(path)?;
+    let rel = match (path.canonicalize(), script_dir.canonicalize()) {
+        (Ok(p), Ok(d)) => p.strip_prefix(&d).ok().map(Path::to_path_buf),
+        _ => None,
+    };
+    out.push((rel.as_deref().unwrap_or(path).to_string_lossy().replace('\\', "/"), data));
+    Ok(())
 }
 
 fn to_set<I: Iterator<Item=String>>(it: I) -> std::collections::BTreeSet<String> {
@@ -215,6 +460,7 @@ fn map_rt_to_crates(rt: &std::collections::BTreeSet<String>) -> Vec<String> {
             "midi"  => v.push("basil-obj-midi".to_string()),
             "daw"   => v.push("basil-obj-daw".to_string()),
             "term"  => v.push("basil-obj-term".to_string()),
+            "base64" | "json" | "csv" | "zip" | "curl" | "sqlite" => v.push(format!("basil-obj-{}", r)),
             _ => {}
         }
     }
@@ -227,6 +473,13 @@ fn map_obj_to_rt(obj: &str) -> Option<(String,String)> {
         "obj-midi"  => Some(("midi".into(),  "basil-obj-midi".into())),
         "obj-daw"   => Some(("daw".into(),   "basil-obj-daw".into())),
         "obj-term"  => Some(("term".into(),  "basil-obj-term".into())),
+        "obj-bmx"    => Some(("bmx".into(),    "basil-obj-bmx".into())),
+        "obj-base64" => Some(("base64".into(), "basil-obj-base64".into())),
+        "obj-json"   => Some(("json".into(),   "basil-obj-json".into())),
+        "obj-csv"    => Some(("csv".into(),    "basil-obj-csv".into())),
+        "obj-zip"    => Some(("zip".into(),    "basil-obj-zip".into())),
+        "obj-curl"   => Some(("curl".into(),   "basil-obj-curl".into())),
+        "obj-sqlite" => Some(("sqlite".into(), "basil-obj-sqlite".into())),
         _ => None,
     }
 }
@@ -253,12 +506,19 @@ fn autodetect_features(src: &str) -> Vec<String> {
             if uses.contains("midi")  { v.push("midi".into()); }
             if uses.contains("daw")   { v.push("daw".into()); }
             if uses.contains("term")  { v.push("term".into()); }
+            for f in ["base64", "json", "csv", "zip", "curl", "sqlite"] {
+                if uses.contains(f) { v.push(f.into()); }
+            }
         }
     }
     if src.contains("AUDIO_") { v.push("audio".into()); }
     if src.contains("MIDI_")  { v.push("midi".into()); }
     if src.contains("DAW_")   { v.push("daw".into()); }
     if src.contains("TERM_")  { v.push("term".into()); }
+    // Builtins whose code sits behind a runtime feature
+    for (prefix, f) in [("BASE64_", "base64"), ("JSON_", "json"), ("CSV_", "csv"), ("ZIP_", "zip"), ("HTTP_", "curl"), ("SQLITE_", "sqlite")] {
+        if src.contains(prefix) { v.push(f.into()); }
+    }
     // Dedup
     let set = to_set(v.into_iter());
     set.into_iter().collect()
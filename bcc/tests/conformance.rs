@@ -0,0 +1,224 @@
+// Conformance: every program in tests/conformance is compiled with `bcc aot` and must print
+// exactly what the VM prints for it, including the message of an uncaught runtime error. The C
+// backend is held to the same, for every program but the one using objects.
+// tests/bundle holds a program for `bcc bundle`, checked against the output `basilc run` gave.
+// A few are also built for wasm32-wasi and run under wasmtime or Node, when both are at hand.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::rc::Rc;
+
+use basil_vm::VM;
+
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.borrow_mut().extend_from_slice(buf); Ok(buf.len()) }
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+// (stdout, stderr) of running `src` in the VM, with errors reported as `basilc run` reports them
+fn run_vm(src: &str) -> (String, String) {
+    let ast = basil_parser::parse(src).expect("parse");
+    let prog = basil_compiler::compile(&ast).expect("compile");
+    let buf = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new(prog);
+    vm.set_output(Box::new(Capture(buf.clone())));
+    let err = match vm.run() {
+        Ok(()) => String::new(),
+        Err(e) => format!("runtime error at line {}: {}\n", vm.current_line(), e),
+    };
+    let out = String::from_utf8_lossy(&buf.borrow()).to_string();
+    (out, err)
+}
+
+fn build_aot(bcc: &Path, src: &Path, features: &str, target: Option<&str>, workdir: &Path) -> PathBuf {
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("repo root").to_path_buf();
+    let stem = src.file_stem().and_then(|s| s.to_str()).expect("stem");
+    let out = Command::new(bcc)
+        .args(["aot", src.to_str().expect("utf-8 path"), "--features", features, "--dep-source", "local"])
+        .arg("--local-runtime").arg(&repo)
+        .args(["--opt", "0", "--lto", "off", "--name", stem, "--quiet"])
+        .args(target.map(|t| ["--target", t]).into_iter().flatten())
+        .env("CARGO_NET_OFFLINE", "true")
+        // Shared by every fixture in this run, so the runtime is compiled once
+        .env("BCC_CACHE_DIR", workdir.join("cache"))
+        .current_dir(workdir)
+        .output()
+        .expect("run bcc");
+    assert!(out.status.success(), "bcc failed for {}:\n{}", src.display(), String::from_utf8_lossy(&out.stderr));
+    workdir.join(match target {
+        Some(_) => format!("{}.wasm", stem),
+        None if cfg!(windows) => format!("{}.exe", stem),
+        None => stem.to_string(),
+    })
+}
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance");
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir).expect("fixtures").filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|x| x == "basil")).collect();
+    files.sort();
+    assert!(!files.is_empty(), "no conformance programs in {}", dir.display());
+    files
+}
+
+fn assert_same_as_vm(exe: &Path, file: &Path, workdir: &Path) {
+    let run = Command::new(exe).current_dir(workdir).output().expect("run compiled program");
+    assert_output_is_vms(&run, file);
+}
+
+fn assert_output_is_vms(run: &Output, file: &Path) {
+    let src = fs::read_to_string(file).expect("read fixture");
+    let (want_out, want_err) = run_vm(&src);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), want_out, "stdout differs for {}", file.display());
+    assert_eq!(String::from_utf8_lossy(&run.stderr), want_err, "stderr differs for {}", file.display());
+    assert_eq!(run.status.success(), want_err.is_empty(), "exit status differs for {}", file.display());
+}
+
+#[test]
+fn aot_output_matches_vm() {
+    let bcc = PathBuf::from(env!("CARGO_BIN_EXE_bcc"));
+    let files = fixtures();
+    let workdir = env::temp_dir().join(format!("bcc_conformance_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&workdir).expect("workdir");
+    for file in &files {
+        let features = if file.file_stem().is_some_and(|s| s == "objects") { "obj-bmx" } else { "" };
+        let exe = build_aot(&bcc, file, features, None, &workdir);
+        assert_same_as_vm(&exe, file, &workdir);
+    }
+    let _ = fs::remove_dir_all(&workdir);
+}
+
+#[test]
+fn c_backend_output_matches_vm() {
+    if Command::new("cc").arg("--version").output().map(|o| !o.status.success()).unwrap_or(true) {
+        eprintln!("skipping: no C compiler (cc) on PATH");
+        return;
+    }
+    let bcc = PathBuf::from(env!("CARGO_BIN_EXE_bcc"));
+    let workdir = env::temp_dir().join(format!("bcc_conformance_c_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&workdir).expect("workdir");
+    for file in &fixtures() {
+        let stem = file.file_stem().and_then(|s| s.to_str()).expect("stem");
+        let out = Command::new(&bcc)
+            .arg("aot").arg(file)
+            .args(["--backend", "c", "--opt", "0", "--lto", "off", "--name", stem, "--quiet"])
+            .env_remove("CC")
+            .current_dir(&workdir)
+            .output()
+            .expect("run bcc");
+        // Objects only exist in the Rust runtime, and bcc says so instead of building
+        if stem == "objects" {
+            assert!(!out.status.success(), "the C backend built {}", file.display());
+            assert!(String::from_utf8_lossy(&out.stderr).contains("is not supported by the C backend"), "{}", String::from_utf8_lossy(&out.stderr));
+            continue;
+        }
+        assert!(out.status.success(), "bcc --backend c failed for {}:\n{}", file.display(), String::from_utf8_lossy(&out.stderr));
+        let exe = workdir.join(if cfg!(windows) { format!("{}.exe", stem) } else { stem.to_string() });
+        assert_same_as_vm(&exe, file, &workdir);
+    }
+    let _ = fs::remove_dir_all(&workdir);
+}
+
+#[test]
+fn bundle_runs_with_embedded_files() {
+    let bcc = PathBuf::from(env!("CARGO_BIN_EXE_bcc"));
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("repo root").to_path_buf();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("bundle");
+    let workdir = env::temp_dir().join(format!("bcc_bundle_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    let rundir = workdir.join("run");
+    fs::create_dir_all(&rundir).expect("workdir");
+
+    let out = Command::new(&bcc)
+        .arg("bundle").arg(fixture.join("app.basil"))
+        .arg("--embed").arg(fixture.join("data"))
+        .args(["--dep-source", "local"]).arg("--local-runtime").arg(&repo)
+        .args(["--opt", "0", "--lto", "off", "--name", "app", "--quiet"])
+        .env("CARGO_NET_OFFLINE", "true")
+        .env("BCC_CACHE_DIR", workdir.join("cache"))
+        .current_dir(&workdir)
+        .output()
+        .expect("run bcc");
+    assert!(out.status.success(), "bcc bundle failed:\n{}", String::from_utf8_lossy(&out.stderr));
+
+    // Run somewhere without the class and data files, so they can only come from the executable
+    let exe = workdir.join(if cfg!(windows) { "app.exe" } else { "app" });
+    let run = Command::new(&exe).current_dir(&rundir).output().expect("run bundled program");
+    let want = fs::read_to_string(fixture.join("app.expected")).expect("expected output");
+    assert_eq!(String::from_utf8_lossy(&run.stdout), want);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let _ = fs::remove_dir_all(&workdir);
+}
+
+// How to run a .wasm here: wasmtime, else Node 20+ through tests/wasi/run.js
+fn wasi_runner(wasm: &Path) -> Option<Command> {
+    let works = |prog: &str| Command::new(prog).arg("--version").output().is_ok_and(|o| o.status.success());
+    if works("wasmtime") {
+        let mut cmd = Command::new("wasmtime");
+        cmd.args(["run", "--dir=."]).arg(wasm);
+        return Some(cmd);
+    }
+    let node = Command::new("node").arg("--version").output().ok().filter(|o| o.status.success())?;
+    let major: u32 = String::from_utf8_lossy(&node.stdout).trim().trim_start_matches('v').split('.').next()?.parse().ok()?;
+    if major < 20 { return None; }
+    let mut cmd = Command::new("node");
+    cmd.arg("--no-warnings").arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("wasi").join("run.js")).arg(wasm);
+    Some(cmd)
+}
+
+fn wasi_target_installed() -> bool {
+    Command::new("rustc").args(["--print", "target-libdir", "--target", "wasm32-wasip1"]).output().ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| fs::read_dir(String::from_utf8_lossy(&o.stdout).trim()).ok())
+        .is_some_and(|mut d| d.next().is_some())
+}
+
+#[test]
+fn wasi_builds_match_vm() {
+    if !wasi_target_installed() {
+        eprintln!("skipping: the wasm32-wasip1 standard library isn't installed (rustup target add wasm32-wasip1)");
+        return;
+    }
+    if wasi_runner(Path::new("probe.wasm")).is_none() {
+        eprintln!("skipping: no WASI runtime (wasmtime, or Node 20+) on PATH");
+        return;
+    }
+    let bcc = PathBuf::from(env!("CARGO_BIN_EXE_bcc"));
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("repo root").to_path_buf();
+    let workdir = env::temp_dir().join(format!("bcc_wasi_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()));
+    fs::create_dir_all(&workdir).expect("workdir");
+
+    // File I/O goes through the preopened working directory, errors through stderr and the exit status
+    for file in fixtures().iter().filter(|p| p.file_stem().is_some_and(|s| ["builtins", "exceptions", "runtime_error"].contains(&s.to_str().unwrap_or("")))) {
+        let wasm = build_aot(&bcc, file, "", Some("wasm32-wasi"), &workdir);
+        let run = wasi_runner(&wasm).expect("runner").current_dir(&workdir).output().expect("run wasm program");
+        assert_output_is_vms(&run, file);
+    }
+
+    // The VM itself, reading stdin for INPUT$ and INPUTC$
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("wasi");
+    let out = Command::new(&bcc)
+        .arg("bundle").arg(fixture.join("echo.basil"))
+        .args(["--target", "wasm32-wasi", "--dep-source", "local"]).arg("--local-runtime").arg(&repo)
+        .args(["--opt", "0", "--lto", "off", "--name", "echo", "--quiet"])
+        .env("CARGO_NET_OFFLINE", "true")
+        .env("BCC_CACHE_DIR", workdir.join("cache"))
+        .current_dir(&workdir)
+        .output()
+        .expect("run bcc");
+    assert!(out.status.success(), "bcc bundle --target wasm32-wasi failed:\n{}", String::from_utf8_lossy(&out.stderr));
+    let mut child = wasi_runner(&workdir.join("echo.wasm")).expect("runner")
+        .current_dir(&workdir).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().expect("run wasm bundle");
+    child.stdin.take().expect("stdin").write_all(b"Ada\nyz").expect("write stdin");
+    let run = child.wait_with_output().expect("wait for wasm bundle");
+    let want = fs::read_to_string(fixture.join("echo.expected")).expect("expected output");
+    assert_eq!(String::from_utf8_lossy(&run.stdout), want);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let _ = fs::remove_dir_all(&workdir);
+}
@@ -0,0 +1,8 @@
+// Runs a wasm32-wasip1 program under Node (20+) when wasmtime is not around, with the working
+// directory preopened: `node --no-warnings run.js app.wasm [args...]`
+const { WASI } = require('node:wasi');
+const fs = require('node:fs');
+const file = process.argv[2];
+const wasi = new WASI({ version: 'preview1', args: [file, ...process.argv.slice(3)], env: process.env, preopens: { '.': '.' } });
+WebAssembly.instantiate(fs.readFileSync(file), wasi.getImportObject())
+  .then(({ instance }) => { process.exitCode = wasi.start(instance); });
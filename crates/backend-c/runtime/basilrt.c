@@ -0,0 +1,1686 @@
+/*
+ * basilrt: the runtime for C emitted by bcc's C backend. See basilrt.h.
+ *
+ * Each operation and builtin here is a port of its counterpart in libbasilrt and
+ * basil-builtins, with the same results and the same error messages.
+ */
+
+#if !defined(_WIN32) && !defined(_POSIX_C_SOURCE)
+#define _POSIX_C_SOURCE 200809L
+#endif
+
+#include "basilrt.h"
+
+#include <errno.h>
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+
+#ifdef _WIN32
+#include <direct.h>
+#include <windows.h>
+#ifndef S_ISDIR
+#define S_ISDIR(m) (((m) & _S_IFMT) == _S_IFDIR)
+#endif
+#else
+#include <dirent.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+#endif
+
+int rt_pending = 0;
+uint32_t rt_cur_line = 0;
+
+static rt_val pending_msg;
+
+/* ---- memory ---- */
+
+static void *xalloc(size_t n) {
+    void *p = malloc(n ? n : 1);
+    if (!p) { fputs("out of memory\n", stderr); abort(); }
+    return p;
+}
+
+static void *xrealloc(void *p, size_t n) {
+    p = realloc(p, n ? n : 1);
+    if (!p) { fputs("out of memory\n", stderr); abort(); }
+    return p;
+}
+
+void rt_free(rt_val v) {
+    size_t i;
+    switch (v.tag) {
+    case RT_STR:
+        free(v.as.s);
+        break;
+    case RT_ARRAY:
+        for (i = 0; i < v.as.a->len; i++) rt_release(v.as.a->data[i]);
+        free(v.as.a->data);
+        free(v.as.a);
+        break;
+    case RT_LIST:
+        for (i = 0; i < v.as.l->len; i++) rt_release(v.as.l->items[i]);
+        free(v.as.l->items);
+        free(v.as.l);
+        break;
+    case RT_DICT:
+        for (i = 0; i < v.as.d->len; i++) { rt_release(v.as.d->keys[i]); rt_release(v.as.d->vals[i]); }
+        free(v.as.d->keys);
+        free(v.as.d->vals);
+        free(v.as.d);
+        break;
+    default:
+        break;
+    }
+}
+
+void rt_push(rt_stack *s, int v) {
+    if (s->len == s->cap) {
+        s->cap = s->cap ? s->cap * 2 : 8;
+        s->items = xrealloc(s->items, s->cap * sizeof(int));
+    }
+    s->items[s->len++] = v;
+}
+
+int rt_pop(rt_stack *s) { return s->len ? s->items[--s->len] : -1; }
+
+void rt_stack_free(rt_stack *s) { free(s->items); s->items = NULL; s->len = s->cap = 0; }
+
+/* ---- strings ---- */
+
+rt_val rt_str_new(const char *s, size_t len) {
+    rt_str *str = xalloc(sizeof(rt_str) + len + 1);
+    rt_val v;
+    str->refs = 1;
+    str->len = len;
+    if (len) memcpy(str->data, s, len);
+    str->data[len] = '\0';
+    v.tag = RT_STR;
+    v.as.s = str;
+    return v;
+}
+
+rt_val rt_str_const(const char *s, size_t len) {
+    rt_val v = rt_str_new(s, len);
+    v.as.s->refs = -1;
+    return v;
+}
+
+static rt_val cstr(const char *s) { return rt_str_new(s, strlen(s)); }
+
+static rt_val empty_str(void) {
+    static rt_val empty;
+    if (empty.tag != RT_STR) empty = rt_str_const("", 0);
+    return empty;
+}
+
+/* A growing byte buffer that becomes a string */
+typedef struct { char *p; size_t len, cap; } buf;
+
+static void buf_put(buf *b, const char *s, size_t n) {
+    if (b->len + n + 1 > b->cap) {
+        size_t cap = b->cap ? b->cap : 32;
+        while (cap < b->len + n + 1) cap *= 2;
+        b->p = xrealloc(b->p, cap);
+        b->cap = cap;
+    }
+    if (n) memcpy(b->p + b->len, s, n);
+    b->len += n;
+}
+
+static void buf_puts(buf *b, const char *s) { buf_put(b, s, strlen(s)); }
+static void buf_putc(buf *b, char c) { buf_put(b, &c, 1); }
+
+static void buf_printf(buf *b, const char *fmt, ...) {
+    char small[256];
+    va_list ap;
+    int n;
+    va_start(ap, fmt);
+    n = vsnprintf(small, sizeof small, fmt, ap);
+    va_end(ap);
+    if (n < 0) return;
+    if ((size_t)n < sizeof small) { buf_put(b, small, (size_t)n); return; }
+    {
+        char *big = xalloc((size_t)n + 1);
+        va_start(ap, fmt);
+        vsnprintf(big, (size_t)n + 1, fmt, ap);
+        va_end(ap);
+        buf_put(b, big, (size_t)n);
+        free(big);
+    }
+}
+
+static rt_val buf_take(buf *b) {
+    rt_val v = rt_str_new(b->p ? b->p : "", b->len);
+    free(b->p);
+    b->p = NULL;
+    b->len = b->cap = 0;
+    return v;
+}
+
+/* An f64 the way Rust's `{}` shows it: the shortest digits that read back as the same number,
+   written out without an exponent */
+static void fmt_num(buf *b, double n) {
+    char tmp[40];
+    char digits[24];
+    int prec, exp, nd = 0, i;
+    const char *p;
+    if (isnan(n)) { buf_puts(b, "NaN"); return; }
+    if (isinf(n)) { buf_puts(b, n > 0 ? "inf" : "-inf"); return; }
+    if (n == 0) { buf_puts(b, signbit(n) ? "-0" : "0"); return; }
+    for (prec = 1; prec <= 17; prec++) {
+        snprintf(tmp, sizeof tmp, "%.*e", prec - 1, n);
+        if (strtod(tmp, NULL) == n) break;
+    }
+    p = tmp;
+    if (*p == '-') { buf_putc(b, '-'); p++; }
+    for (; *p && *p != 'e'; p++) if (*p != '.') digits[nd++] = *p;
+    exp = atoi(p + 1);
+    while (nd > 1 && digits[nd - 1] == '0') nd--;
+    if (exp < 0) {
+        buf_puts(b, "0.");
+        for (i = -1; i > exp; i--) buf_putc(b, '0');
+        buf_put(b, digits, (size_t)nd);
+    } else if (nd <= exp + 1) {
+        buf_put(b, digits, (size_t)nd);
+        for (i = nd; i <= exp; i++) buf_putc(b, '0');
+    } else {
+        buf_put(b, digits, (size_t)exp + 1);
+        buf_putc(b, '.');
+        buf_put(b, digits + exp + 1, (size_t)(nd - exp - 1));
+    }
+}
+
+static const char *elem_name(rt_elem e) { return e == RT_ELEM_NUM ? "Num" : e == RT_ELEM_INT ? "Int" : "Str"; }
+
+/* PRINT form, as the VM's `Display` for Value */
+static void fmt_val(buf *b, rt_val v) {
+    size_t i;
+    switch (v.tag) {
+    case RT_NULL: buf_puts(b, "null"); break;
+    case RT_BOOL: buf_puts(b, v.as.b ? "true" : "false"); break;
+    case RT_INT: buf_printf(b, "%" PRId64, v.as.i); break;
+    case RT_NUM: fmt_num(b, v.as.n); break;
+    case RT_STR: buf_put(b, v.as.s->data, v.as.s->len); break;
+    case RT_ARRAY:
+        buf_printf(b, "<array %s ", elem_name(v.as.a->elem));
+        for (i = 0; i < v.as.a->rank; i++) buf_printf(b, i ? "x%zu" : "%zu", v.as.a->dims[i]);
+        buf_putc(b, '>');
+        break;
+    case RT_LIST:
+        buf_putc(b, '[');
+        for (i = 0; i < v.as.l->len; i++) { if (i) buf_puts(b, ", "); fmt_val(b, v.as.l->items[i]); }
+        buf_putc(b, ']');
+        break;
+    case RT_DICT:
+        buf_putc(b, '{');
+        for (i = 0; i < v.as.d->len; i++) {
+            if (i) buf_puts(b, ", ");
+            buf_putc(b, '"'); fmt_val(b, v.as.d->keys[i]); buf_puts(b, "\": ");
+            fmt_val(b, v.as.d->vals[i]);
+        }
+        buf_putc(b, '}');
+        break;
+    }
+}
+
+/* The VM's `Debug` for Value, which some error messages show */
+static void fmt_debug(buf *b, rt_val v) {
+    size_t i;
+    switch (v.tag) {
+    case RT_NULL: buf_puts(b, "Null"); break;
+    case RT_BOOL: buf_puts(b, v.as.b ? "Bool(true)" : "Bool(false)"); break;
+    case RT_INT: buf_printf(b, "Int(%" PRId64 ")", v.as.i); break;
+    case RT_NUM: buf_puts(b, "Num("); fmt_num(b, v.as.n); buf_putc(b, ')'); break;
+    case RT_STR: buf_puts(b, "Str(\""); fmt_val(b, v); buf_puts(b, "\")"); break;
+    case RT_ARRAY: buf_puts(b, "Array(..)"); break;
+    case RT_LIST:
+        buf_puts(b, "List(");
+        for (i = 0; i < v.as.l->len; i++) { if (i) buf_puts(b, ", "); fmt_debug(b, v.as.l->items[i]); }
+        buf_putc(b, ')');
+        break;
+    case RT_DICT:
+        buf_puts(b, "Dict{");
+        for (i = 0; i < v.as.d->len; i++) {
+            if (i) buf_puts(b, ", ");
+            fmt_val(b, v.as.d->keys[i]); buf_puts(b, ": "); fmt_debug(b, v.as.d->vals[i]);
+        }
+        buf_putc(b, '}');
+        break;
+    }
+}
+
+/* Argument as text; non-strings use their PRINT form. The result is owned. */
+static rt_val text(rt_val v) {
+    buf b = {0};
+    if (v.tag == RT_STR) return rt_retain(v);
+    fmt_val(&b, v);
+    return buf_take(&b);
+}
+
+static const char *type_of(rt_val v) {
+    switch (v.tag) {
+    case RT_NULL: return "NULL";
+    case RT_BOOL: return "BOOL";
+    case RT_NUM: return "FLOAT";
+    case RT_INT: return "INTEGER";
+    case RT_STR: return "STRING";
+    case RT_ARRAY: return v.as.a->elem == RT_ELEM_NUM ? "FLOAT[]" : v.as.a->elem == RT_ELEM_INT ? "INTEGER[]" : "STRING[]";
+    case RT_LIST: return "LIST";
+    case RT_DICT: return "DICT";
+    }
+    return "NULL";
+}
+
+rt_val rt_concat(size_t n, const rt_val *parts) {
+    buf b = {0};
+    size_t i;
+    for (i = 0; i < n; i++) fmt_val(&b, parts[i]);
+    return buf_take(&b);
+}
+
+bool rt_str_eq(rt_val a, rt_val b) {
+    return a.as.s->len == b.as.s->len && memcmp(a.as.s->data, b.as.s->data, a.as.s->len) == 0;
+}
+
+/* Decode the code point at s[*i] (valid UTF-8) and advance past it */
+static uint32_t next_char(const char *s, size_t *i) {
+    const unsigned char *u = (const unsigned char *)s + *i;
+    if (u[0] < 0x80) { *i += 1; return u[0]; }
+    if (u[0] < 0xE0) { *i += 2; return ((uint32_t)(u[0] & 0x1F) << 6) | (u[1] & 0x3F); }
+    if (u[0] < 0xF0) { *i += 3; return ((uint32_t)(u[0] & 0x0F) << 12) | ((uint32_t)(u[1] & 0x3F) << 6) | (u[2] & 0x3F); }
+    *i += 4;
+    return ((uint32_t)(u[0] & 0x07) << 18) | ((uint32_t)(u[1] & 0x3F) << 12) | ((uint32_t)(u[2] & 0x3F) << 6) | (u[3] & 0x3F);
+}
+
+static void put_char(buf *b, uint32_t c) {
+    char out[4];
+    if (c < 0x80) { out[0] = (char)c; buf_put(b, out, 1); }
+    else if (c < 0x800) { out[0] = (char)(0xC0 | (c >> 6)); out[1] = (char)(0x80 | (c & 0x3F)); buf_put(b, out, 2); }
+    else if (c < 0x10000) {
+        out[0] = (char)(0xE0 | (c >> 12)); out[1] = (char)(0x80 | ((c >> 6) & 0x3F)); out[2] = (char)(0x80 | (c & 0x3F));
+        buf_put(b, out, 3);
+    } else {
+        out[0] = (char)(0xF0 | (c >> 18)); out[1] = (char)(0x80 | ((c >> 12) & 0x3F));
+        out[2] = (char)(0x80 | ((c >> 6) & 0x3F)); out[3] = (char)(0x80 | (c & 0x3F));
+        buf_put(b, out, 4);
+    }
+}
+
+static size_t char_count(rt_val s) {
+    size_t i, n = 0;
+    for (i = 0; i < s.as.s->len; i++) if (((unsigned char)s.as.s->data[i] & 0xC0) != 0x80) n++;
+    return n;
+}
+
+/* Byte offset of character `c` of `s`, or its length if it has fewer characters */
+static size_t char_offset(rt_val s, size_t c) {
+    size_t i = 0;
+    while (c > 0 && i < s.as.s->len) { next_char(s.as.s->data, &i); c--; }
+    return i;
+}
+
+/* Bytes read from a file as text, with invalid UTF-8 replaced by U+FFFD like
+   `String::from_utf8_lossy` */
+static rt_val from_utf8_lossy(const char *s, size_t n) {
+    const unsigned char *u = (const unsigned char *)s;
+    buf b = {0};
+    size_t i = 0;
+    while (i < n) {
+        unsigned char c = u[i];
+        size_t need, k;
+        unsigned char lo = 0x80, hi = 0xBF;
+        if (c < 0x80) { buf_putc(&b, (char)c); i++; continue; }
+        if (c >= 0xC2 && c <= 0xDF) need = 1;
+        else if (c >= 0xE0 && c <= 0xEF) { need = 2; if (c == 0xE0) lo = 0xA0; if (c == 0xED) hi = 0x9F; }
+        else if (c >= 0xF0 && c <= 0xF4) { need = 3; if (c == 0xF0) lo = 0x90; if (c == 0xF4) hi = 0x8F; }
+        else { put_char(&b, 0xFFFD); i++; continue; }
+        for (k = 1; k <= need; k++) {
+            if (i + k >= n || u[i + k] < lo || u[i + k] > hi) break;
+            lo = 0x80; hi = 0xBF;
+        }
+        if (k <= need) { put_char(&b, 0xFFFD); i += k; continue; }
+        buf_put(&b, s + i, need + 1);
+        i += need + 1;
+    }
+    return buf_take(&b);
+}
+
+/* ---- errors ---- */
+
+static void set_error(int kind, rt_val msg) {
+    rt_release(pending_msg);
+    pending_msg = msg;
+    rt_pending = kind;
+}
+
+void rt_error(const char *fmt, ...) {
+    char small[512];
+    va_list ap;
+    int n;
+    va_start(ap, fmt);
+    n = vsnprintf(small, sizeof small, fmt, ap);
+    va_end(ap);
+    if (n < 0) n = 0;
+    if ((size_t)n < sizeof small) { set_error(RT_ERROR, rt_str_new(small, (size_t)n)); return; }
+    {
+        char *big = xalloc((size_t)n + 1);
+        va_start(ap, fmt);
+        vsnprintf(big, (size_t)n + 1, fmt, ap);
+        va_end(ap);
+        set_error(RT_ERROR, rt_str_new(big, (size_t)n));
+        free(big);
+    }
+}
+
+static void error_buf(buf *b) { set_error(RT_ERROR, buf_take(b)); }
+
+void rt_raise(rt_val v) { set_error(RT_RAISED, text(v)); }
+
+void rt_reraise(bool active, rt_val exc) {
+    if (active) set_error(RT_RAISED, rt_retain(exc));
+    else rt_error("Reraise without active exception");
+}
+
+rt_val rt_take_error(void) {
+    rt_val msg = pending_msg;
+    pending_msg = rt_null();
+    rt_pending = 0;
+    return msg.tag == RT_STR ? msg : empty_str();
+}
+
+static void close_all(void);
+
+void rt_fail(void) {
+    fflush(stdout);
+    if (rt_cur_line > 0) fprintf(stderr, "runtime error at line %" PRIu32 ": %s\n", rt_cur_line, pending_msg.as.s->data);
+    else fprintf(stderr, "runtime error: %s\n", pending_msg.as.s->data);
+    close_all();
+    exit(1);
+}
+
+static bool to_i64(rt_val v, int64_t *out);
+
+void rt_exit(rt_val code) {
+    int64_t c;
+    if (!to_i64(code, &c)) return;
+    rt_finish();
+    exit((int)c);
+}
+
+void rt_finish(void) {
+    fflush(stdout);
+    close_all();
+}
+
+/* ---- printing ---- */
+
+void rt_print(rt_val v) {
+    if (v.tag == RT_STR) { fwrite(v.as.s->data, 1, v.as.s->len, stdout); return; }
+    {
+        buf b = {0};
+        fmt_val(&b, v);
+        fwrite(b.p, 1, b.len, stdout);
+        free(b.p);
+    }
+}
+
+void rt_print_num(double n) { rt_print(rt_num(n)); }
+void rt_print_int(int64_t i) { printf("%" PRId64, i); }
+void rt_print_bool(bool b) { fputs(b ? "true" : "false", stdout); }
+
+/* ---- operators ---- */
+
+bool rt_truthy(rt_val v) {
+    switch (v.tag) {
+    case RT_NULL: return false;
+    case RT_BOOL: return v.as.b;
+    case RT_NUM: return v.as.n != 0.0;
+    case RT_INT: return v.as.i != 0;
+    case RT_STR: return v.as.s->len > 0;
+    case RT_ARRAY: return true;
+    case RT_LIST: return v.as.l->len > 0;
+    case RT_DICT: return v.as.d->len > 0;
+    }
+    return false;
+}
+
+/* Rust's `as i64`: truncate, saturating, with NaN as 0 */
+int64_t rt_trunc(double n) {
+    if (isnan(n)) return 0;
+    if (n >= 9223372036854775807.0) return INT64_MAX;
+    if (n <= -9223372036854775808.0) return INT64_MIN;
+    return (int64_t)n;
+}
+
+static bool numeric(rt_val v) { return v.tag == RT_NUM || v.tag == RT_INT || v.tag == RT_BOOL; }
+
+static bool as_num(rt_val v, double *out) {
+    switch (v.tag) {
+    case RT_NUM: *out = v.as.n; return true;
+    case RT_INT: *out = (double)v.as.i; return true;
+    case RT_BOOL: *out = v.as.b ? 1.0 : 0.0; return true;
+    default: rt_error("expected number"); return false;
+    }
+}
+
+static bool to_i64(rt_val v, int64_t *out) {
+    if (v.tag == RT_INT) { *out = v.as.i; return true; }
+    if (v.tag == RT_NUM) { *out = rt_trunc(v.as.n); return true; }
+    rt_error("expected numeric value, got %s", type_of(v));
+    return false;
+}
+
+#define NUM_PAIR(a, b) double x, y; if (!as_num(a, &x) || !as_num(b, &y)) return rt_null()
+
+rt_val rt_add(rt_val a, rt_val b) {
+    if (a.tag == RT_STR || b.tag == RT_STR) { rt_val parts[2]; parts[0] = a; parts[1] = b; return rt_concat(2, parts); }
+    { NUM_PAIR(a, b); return rt_num(x + y); }
+}
+
+rt_val rt_sub(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_num(x - y); }
+rt_val rt_mul(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_num(x * y); }
+rt_val rt_div(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_num(x / y); }
+rt_val rt_modulo(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_num(fmod(x, y)); }
+
+rt_val rt_neg(rt_val a) {
+    double x;
+    if (!as_num(a, &x)) return rt_null();
+    return rt_num(-x);
+}
+
+/* The VM's `==` on values: numbers of different variants are different, lists and dicts
+   compare by contents and arrays by identity */
+static bool same(rt_val a, rt_val b) {
+    size_t i, j;
+    if (a.tag != b.tag) return false;
+    switch (a.tag) {
+    case RT_NULL: return true;
+    case RT_BOOL: return a.as.b == b.as.b;
+    case RT_INT: return a.as.i == b.as.i;
+    case RT_NUM: return a.as.n == b.as.n;
+    case RT_STR: return rt_str_eq(a, b);
+    case RT_ARRAY: return a.as.a == b.as.a;
+    case RT_LIST:
+        if (a.as.l->len != b.as.l->len) return false;
+        for (i = 0; i < a.as.l->len; i++) if (!same(a.as.l->items[i], b.as.l->items[i])) return false;
+        return true;
+    case RT_DICT:
+        if (a.as.d->len != b.as.d->len) return false;
+        for (i = 0; i < a.as.d->len; i++) {
+            for (j = 0; j < b.as.d->len; j++) if (rt_str_eq(a.as.d->keys[i], b.as.d->keys[j])) break;
+            if (j == b.as.d->len || !same(a.as.d->vals[i], b.as.d->vals[j])) return false;
+        }
+        return true;
+    }
+    return false;
+}
+
+rt_val rt_eq(rt_val a, rt_val b) {
+    if (numeric(a) && numeric(b)) { NUM_PAIR(a, b); return rt_bool(x == y); }
+    return rt_bool(same(a, b));
+}
+
+rt_val rt_ne(rt_val a, rt_val b) {
+    if (numeric(a) && numeric(b)) { NUM_PAIR(a, b); return rt_bool(x != y); }
+    return rt_bool(!same(a, b));
+}
+
+rt_val rt_lt(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_bool(x < y); }
+rt_val rt_le(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_bool(x <= y); }
+rt_val rt_gt(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_bool(x > y); }
+rt_val rt_ge(rt_val a, rt_val b) { NUM_PAIR(a, b); return rt_bool(x >= y); }
+
+rt_val rt_to_int(rt_val v) {
+    if (v.tag == RT_INT) return v;
+    if (v.tag == RT_NUM) return rt_int(rt_trunc(v.as.n));
+    rt_error("ToInt expects a numeric value");
+    return rt_null();
+}
+
+double rt_unbox_num(rt_val v) {
+    switch (v.tag) {
+    case RT_NUM: return v.as.n;
+    case RT_INT: return (double)v.as.i;
+    case RT_BOOL: return v.as.b ? 1.0 : 0.0;
+    default: return NAN;
+    }
+}
+
+int64_t rt_unbox_int(rt_val v) {
+    if (v.tag == RT_INT) return v.as.i;
+    if (v.tag == RT_NUM) return rt_trunc(v.as.n);
+    return 0;
+}
+
+bool rt_unbox_bool(rt_val v) { return rt_truthy(v); }
+
+/* ---- arrays ---- */
+
+static rt_val new_array_of(rt_elem elem, size_t rank, const size_t *dims) {
+    rt_array *a = xalloc(sizeof(rt_array));
+    rt_val v;
+    size_t i, total = 1;
+    a->refs = 1;
+    a->elem = elem;
+    a->rank = rank;
+    for (i = 0; i < rank; i++) { a->dims[i] = dims[i]; total *= dims[i]; }
+    a->len = total;
+    a->data = xalloc(total * sizeof(rt_val));
+    for (i = 0; i < total; i++) a->data[i] = elem == RT_ELEM_NUM ? rt_num(0) : elem == RT_ELEM_INT ? rt_int(0) : empty_str();
+    v.tag = RT_ARRAY;
+    v.as.a = a;
+    return v;
+}
+
+rt_val rt_new_array(rt_elem elem, size_t n, const rt_val *uppers) {
+    size_t dims[4], i;
+    if (n == 0 || n > 4) { rt_error("array rank must be 1..4"); return rt_null(); }
+    for (i = 0; i < n; i++) {
+        int64_t u;
+        if (uppers[i].tag == RT_INT) u = uppers[i].as.i;
+        else if (uppers[i].tag == RT_NUM) u = rt_trunc(uppers[i].as.n);
+        else { rt_error("array dimension must be numeric"); return rt_null(); }
+        if (u < 0) { rt_error("array dimension upper bound must be >= 0"); return rt_null(); }
+        dims[i] = (size_t)u + 1;
+    }
+    return new_array_of(elem, n, dims);
+}
+
+/* Row-major offset of `idxs` in `a`, with the VM's bounds checks */
+static bool offset(rt_array *a, size_t n, const rt_val *idxs, size_t *out) {
+    size_t lin = 0, k;
+    if (n != a->rank) { rt_error("array rank mismatch"); return false; }
+    for (k = 0; k < n; k++) {
+        int64_t i;
+        if (idxs[k].tag == RT_INT) i = idxs[k].as.i;
+        else if (idxs[k].tag == RT_NUM) i = rt_trunc(idxs[k].as.n);
+        else { rt_error("array index must be numeric"); return false; }
+        if (i < 0 || (uint64_t)i >= a->dims[k]) { rt_error("array index out of bounds"); return false; }
+        lin = lin * a->dims[k] + (size_t)i;
+    }
+    *out = lin;
+    return true;
+}
+
+rt_val rt_array_get(rt_val arr, size_t n, const rt_val *idxs) {
+    size_t lin;
+    if (arr.tag != RT_ARRAY) { rt_error("array access on non-array or not DIMed"); return rt_null(); }
+    if (!offset(arr.as.a, n, idxs, &lin)) return rt_null();
+    return rt_retain(arr.as.a->data[lin]);
+}
+
+void rt_array_set(rt_val arr, size_t n, const rt_val *idxs, rt_val v) {
+    size_t lin;
+    rt_val stored;
+    if (arr.tag != RT_ARRAY) { rt_error("array write on non-array or not DIMed"); return; }
+    if (!offset(arr.as.a, n, idxs, &lin)) return;
+    switch (arr.as.a->elem) {
+    case RT_ELEM_NUM:
+    case RT_ELEM_INT:
+        if (v.tag != RT_NUM && v.tag != RT_INT) {
+            buf b = {0};
+            buf_puts(&b, "cannot store non-numeric ");
+            fmt_debug(&b, v);
+            buf_puts(&b, arr.as.a->elem == RT_ELEM_NUM ? " into numeric array" : " into integer array");
+            error_buf(&b);
+            return;
+        }
+        stored = arr.as.a->elem == RT_ELEM_NUM ? rt_num(rt_unbox_num(v)) : rt_int(rt_unbox_int(v));
+        break;
+    default:
+        stored = text(v);
+        break;
+    }
+    rt_set(&arr.as.a->data[lin], stored);
+}
+
+static rt_val string_array(rt_val *items, size_t n) {
+    rt_val v = new_array_of(RT_ELEM_STR, 1, &n);
+    size_t i;
+    for (i = 0; i < n; i++) v.as.a->data[i] = items[i];
+    return v;
+}
+
+/* ---- lists and dictionaries ---- */
+
+static void list_push(rt_list *l, rt_val v) {
+    if (l->len == l->cap) {
+        l->cap = l->cap ? l->cap * 2 : 4;
+        l->items = xrealloc(l->items, l->cap * sizeof(rt_val));
+    }
+    l->items[l->len++] = v;
+}
+
+rt_val rt_list_new(size_t n, const rt_val *items) {
+    rt_list *l = xalloc(sizeof(rt_list));
+    rt_val v;
+    size_t i;
+    l->refs = 1;
+    l->len = l->cap = 0;
+    l->items = NULL;
+    for (i = 0; i < n; i++) list_push(l, rt_retain(items[i]));
+    v.tag = RT_LIST;
+    v.as.l = l;
+    return v;
+}
+
+static rt_val *dict_find(rt_dict *d, rt_val key) {
+    size_t i;
+    for (i = 0; i < d->len; i++) if (rt_str_eq(d->keys[i], key)) return &d->vals[i];
+    return NULL;
+}
+
+/* Store `v` (owned) under `key` (borrowed) */
+static void dict_insert(rt_dict *d, rt_val key, rt_val v) {
+    rt_val *slot = dict_find(d, key);
+    if (slot) { rt_set(slot, v); return; }
+    if (d->len == d->cap) {
+        d->cap = d->cap ? d->cap * 2 : 4;
+        d->keys = xrealloc(d->keys, d->cap * sizeof(rt_val));
+        d->vals = xrealloc(d->vals, d->cap * sizeof(rt_val));
+    }
+    d->keys[d->len] = rt_retain(key);
+    d->vals[d->len] = v;
+    d->len++;
+}
+
+rt_val rt_dict_new(size_t n, const rt_val *keys, const rt_val *vals) {
+    rt_dict *d = xalloc(sizeof(rt_dict));
+    rt_val v;
+    size_t i;
+    d->refs = 1;
+    d->len = d->cap = 0;
+    d->keys = d->vals = NULL;
+    for (i = 0; i < n; i++) dict_insert(d, keys[i], rt_retain(vals[i]));
+    v.tag = RT_DICT;
+    v.as.d = d;
+    return v;
+}
+
+static bool dict_key(rt_val v) {
+    if (v.tag == RT_STR) return true;
+    rt_error("Dictionary key must be string, got %s", type_of(v));
+    return false;
+}
+
+static void missing_key(rt_val key) {
+    buf b = {0};
+    buf_puts(&b, "Dictionary missing key: \"");
+    fmt_val(&b, key);
+    buf_putc(&b, '"');
+    error_buf(&b);
+}
+
+rt_val rt_index_get(rt_val target, rt_val index) {
+    if (target.tag == RT_LIST) {
+        int64_t i;
+        if (!to_i64(index, &i)) return rt_null();
+        if (i <= 0 || (uint64_t)i > target.as.l->len) { rt_error("List index out of range: %" PRId64, i); return rt_null(); }
+        return rt_retain(target.as.l->items[i - 1]);
+    }
+    if (target.tag == RT_DICT) {
+        rt_val *slot;
+        if (!dict_key(index)) return rt_null();
+        slot = dict_find(target.as.d, index);
+        if (!slot) { missing_key(index); return rt_null(); }
+        return rt_retain(*slot);
+    }
+    rt_error("Attempted [] on a non-list/dict value.");
+    return rt_null();
+}
+
+void rt_index_set(rt_val target, rt_val index, rt_val v) {
+    if (target.tag == RT_LIST) {
+        int64_t i;
+        if (!to_i64(index, &i)) return;
+        if (i <= 0 || (uint64_t)i > target.as.l->len) { rt_error("List index out of range: %" PRId64, i); return; }
+        rt_set(&target.as.l->items[i - 1], rt_retain(v));
+        return;
+    }
+    if (target.tag == RT_DICT) {
+        if (!dict_key(index)) return;
+        dict_insert(target.as.d, index, rt_retain(v));
+        return;
+    }
+    rt_error("Attempted [] on a non-list/dict value.");
+}
+
+rt_val rt_prop_get(rt_val target, rt_val name) {
+    if (target.tag == RT_DICT) {
+        rt_val *slot = dict_find(target.as.d, name);
+        if (!slot) { missing_key(name); return rt_null(); }
+        return rt_retain(*slot);
+    }
+    rt_error("GETPROP on non-object/dict (got TYPE=%s)", type_of(target));
+    return rt_null();
+}
+
+void rt_prop_set(rt_val target, rt_val name, rt_val v) {
+    if (target.tag == RT_DICT) { dict_insert(target.as.d, name, rt_retain(v)); return; }
+    rt_error("SETPROP on non-object/dict");
+}
+
+rt_val rt_items(rt_val v) {
+    switch (v.tag) {
+    case RT_ARRAY: return rt_list_new(v.as.a->len, v.as.a->data);
+    case RT_LIST: return rt_list_new(v.as.l->len, v.as.l->items);
+    case RT_DICT: return rt_list_new(v.as.d->len, v.as.d->keys);
+    default:
+        rt_error("FOR EACH expects an array or iterable object after IN (got TYPE=%s).", type_of(v));
+        return rt_null();
+    }
+}
+
+/* ---- builtins: strings ---- */
+
+static bool arity(const char *name, bool ok, const char *expects) {
+    if (!ok) rt_error("%s expects %s", name, expects);
+    return ok;
+}
+
+static bool str_arg(rt_val v, const char *name, const char *what) {
+    if (v.tag == RT_STR) return true;
+    rt_error("%s %s must be string", name, what);
+    return false;
+}
+
+static bool num_arg(rt_val v, const char *name, const char *what, int64_t *out) {
+    if (v.tag == RT_INT) { *out = v.as.i; return true; }
+    if (v.tag == RT_NUM) { *out = rt_trunc(v.as.n); return true; }
+    rt_error("%s %s must be numeric", name, what);
+    return false;
+}
+
+/* Characters [from, from + count) of `s` */
+static rt_val substr(rt_val s, size_t from, size_t count) {
+    size_t a = char_offset(s, from), b = a, i = 0;
+    while (i < count && b < s.as.s->len) { next_char(s.as.s->data, &b); i++; }
+    return rt_str_new(s.as.s->data + a, b - a);
+}
+
+RT_BUILTIN(rt_bi_len) {
+    rt_val v;
+    if (!arity("LEN", n == 1, "1 argument")) return rt_null();
+    v = args[0];
+    switch (v.tag) {
+    case RT_STR: return rt_int((int64_t)char_count(v));
+    case RT_ARRAY: return rt_int((int64_t)v.as.a->len);
+    case RT_LIST: return rt_int((int64_t)v.as.l->len);
+    case RT_DICT: return rt_int((int64_t)v.as.d->len);
+    default: {
+        /* Anything else counts the characters of its PRINT form */
+        rt_val t = text(v);
+        int64_t len = (int64_t)char_count(t);
+        rt_release(t);
+        return rt_int(len);
+    }
+    }
+}
+
+RT_BUILTIN(rt_bi_mid) {
+    int64_t start, len = -1;
+    if (!arity("MID$", n == 2 || n == 3, "2 or 3 arguments")) return rt_null();
+    if (!str_arg(args[0], "MID$", "arg 1") || !num_arg(args[1], "MID$", "start", &start)) return rt_null();
+    if (n == 3) {
+        if (!num_arg(args[2], "MID$", "length", &len)) return rt_null();
+        if (len <= 0) return rt_str_new("", 0);
+    }
+    return substr(args[0], start <= 1 ? 0 : (size_t)(start - 1), n == 3 ? (size_t)len : SIZE_MAX);
+}
+
+RT_BUILTIN(rt_bi_left) {
+    int64_t count;
+    if (!arity("LEFT$", n == 2, "2 arguments")) return rt_null();
+    if (!str_arg(args[0], "LEFT$", "arg 1") || !num_arg(args[1], "LEFT$", "count", &count)) return rt_null();
+    return substr(args[0], 0, count <= 0 ? 0 : (size_t)count);
+}
+
+RT_BUILTIN(rt_bi_right) {
+    int64_t count;
+    size_t total, take;
+    if (!arity("RIGHT$", n == 2, "2 arguments")) return rt_null();
+    if (!str_arg(args[0], "RIGHT$", "arg 1") || !num_arg(args[1], "RIGHT$", "count", &count)) return rt_null();
+    if (count <= 0) return rt_str_new("", 0);
+    total = char_count(args[0]);
+    take = (uint64_t)count < total ? (size_t)count : total;
+    return substr(args[0], total - take, take);
+}
+
+RT_BUILTIN(rt_bi_instr) {
+    rt_val hay, needle;
+    int64_t start = 0;
+    size_t total, byte, i;
+    if (!arity("INSTR", n == 2 || n == 3, "2 or 3 arguments")) return rt_null();
+    if (!str_arg(args[0], "INSTR", "arg 1") || !str_arg(args[1], "INSTR", "arg 2")) return rt_null();
+    if (n == 3) { if (!num_arg(args[2], "INSTR", "start", &start)) return rt_null(); if (start < 0) start = 0; }
+    hay = args[0];
+    needle = args[1];
+    total = char_count(hay);
+    if (needle.as.s->len == 0) return rt_int((uint64_t)start < total ? start : (int64_t)total);
+    if ((uint64_t)start > total) return rt_int(0);
+    byte = char_offset(hay, (size_t)start);
+    for (i = byte; i + needle.as.s->len <= hay.as.s->len; i++) {
+        if (memcmp(hay.as.s->data + i, needle.as.s->data, needle.as.s->len) == 0) {
+            size_t c = 0, k;
+            for (k = 0; k < i; k++) if (((unsigned char)hay.as.s->data[k] & 0xC0) != 0x80) c++;
+            return rt_int((int64_t)c);
+        }
+    }
+    return rt_int(0);
+}
+
+RT_BUILTIN(rt_bi_type) {
+    if (!arity("TYPE$", n == 1, "1 argument")) return rt_null();
+    return cstr(type_of(args[0]));
+}
+
+RT_BUILTIN(rt_bi_html) {
+    rt_val s;
+    buf b = {0};
+    size_t i;
+    if (!arity("HTML", n == 1, "1 argument")) return rt_null();
+    s = text(args[0]);
+    for (i = 0; i < s.as.s->len; i++) {
+        char c = s.as.s->data[i];
+        switch (c) {
+        case '&': buf_puts(&b, "&amp;"); break;
+        case '<': buf_puts(&b, "&lt;"); break;
+        case '>': buf_puts(&b, "&gt;"); break;
+        case '"': buf_puts(&b, "&quot;"); break;
+        case '\'': buf_puts(&b, "&#39;"); break;
+        default: buf_putc(&b, c); break;
+        }
+    }
+    rt_release(s);
+    return buf_take(&b);
+}
+
+/* Simple case mapping for ASCII, Latin-1, Latin Extended-A, Greek and Cyrillic; other
+   characters are left as they are */
+static uint32_t to_upper(uint32_t c) {
+    if (c >= 'a' && c <= 'z') return c - 32;
+    if (c < 0x80) return c;
+    if ((c >= 0xE0 && c <= 0xFE && c != 0xF7)) return c - 32;
+    if (c == 0xFF) return 0x178;
+    if (c == 0xB5) return 0x39C;
+    if (c >= 0x100 && c <= 0x17F) {
+        if ((c >= 0x139 && c <= 0x148) || (c >= 0x179 && c <= 0x17E)) return (c & 1) ? c : c - 1;
+        if (c == 0x131 || c == 0x138 || c == 0x149 || c == 0x178 || c == 0x17F) return c == 0x131 ? 'I' : c == 0x17F ? 'S' : c;
+        return (c & 1) ? c - 1 : c;
+    }
+    if (c >= 0x3B1 && c <= 0x3C9) return c == 0x3C2 ? 0x3A3 : c - 32;
+    if (c >= 0x430 && c <= 0x44F) return c - 32;
+    if (c >= 0x450 && c <= 0x45F) return c - 80;
+    return c;
+}
+
+static uint32_t to_lower(uint32_t c) {
+    if (c >= 'A' && c <= 'Z') return c + 32;
+    if (c < 0x80) return c;
+    if ((c >= 0xC0 && c <= 0xDE && c != 0xD7)) return c + 32;
+    if (c == 0x178) return 0xFF;
+    if (c >= 0x100 && c <= 0x17F) {
+        if ((c >= 0x139 && c <= 0x148) || (c >= 0x179 && c <= 0x17E)) return (c & 1) ? c + 1 : c;
+        if (c == 0x130) return 'i';
+        if (c == 0x131 || c == 0x138 || c == 0x149 || c == 0x17F) return c;
+        return (c & 1) ? c : c + 1;
+    }
+    if (c >= 0x391 && c <= 0x3A9 && c != 0x3A2) return c + 32;
+    if (c >= 0x410 && c <= 0x42F) return c + 32;
+    if (c >= 0x400 && c <= 0x40F) return c + 80;
+    return c;
+}
+
+static rt_val map_case(const char *name, size_t n, const rt_val *args, bool upper) {
+    buf b = {0};
+    size_t i = 0;
+    rt_val s;
+    if (!arity(name, n == 1, "1 argument") || !str_arg(args[0], name, "arg")) return rt_null();
+    s = args[0];
+    while (i < s.as.s->len) {
+        uint32_t c = next_char(s.as.s->data, &i);
+        if (upper && c == 0xDF) { buf_puts(&b, "SS"); continue; }
+        put_char(&b, upper ? to_upper(c) : to_lower(c));
+    }
+    return buf_take(&b);
+}
+
+RT_BUILTIN(rt_bi_ucase) { return map_case("UCASE$", n, args, true); }
+RT_BUILTIN(rt_bi_lcase) { return map_case("LCASE$", n, args, false); }
+
+/* Unicode White_Space, what Rust's `trim` strips */
+static bool is_space(uint32_t c) {
+    return (c >= 0x09 && c <= 0x0D) || c == 0x20 || c == 0x85 || c == 0xA0 || c == 0x1680
+        || (c >= 0x2000 && c <= 0x200A) || c == 0x2028 || c == 0x2029 || c == 0x202F || c == 0x205F || c == 0x3000;
+}
+
+static void trim_bounds(const char *s, size_t len, size_t *from, size_t *to) {
+    size_t i = 0, start = 0, end = 0;
+    bool seen = false;
+    while (i < len) {
+        size_t at = i;
+        uint32_t c = next_char(s, &i);
+        if (!is_space(c)) { if (!seen) { start = at; seen = true; } end = i; }
+    }
+    *from = start;
+    *to = seen ? end : start;
+}
+
+RT_BUILTIN(rt_bi_trim) {
+    size_t from, to;
+    if (!arity("TRIM$", n == 1, "1 argument") || !str_arg(args[0], "TRIM$", "arg")) return rt_null();
+    trim_bounds(args[0].as.s->data, args[0].as.s->len, &from, &to);
+    return rt_str_new(args[0].as.s->data + from, to - from);
+}
+
+RT_BUILTIN(rt_bi_chr) {
+    int64_t c;
+    buf b = {0};
+    if (!arity("CHR$", n == 1, "1 argument") || !num_arg(args[0], "CHR$", "arg", &c)) return rt_null();
+    if (c < 0 || c > 0x10FFFF || (c >= 0xD800 && c <= 0xDFFF)) return rt_str_new("", 0);
+    put_char(&b, (uint32_t)c);
+    return buf_take(&b);
+}
+
+RT_BUILTIN(rt_bi_asc) {
+    size_t i = 0;
+    if (!arity("ASC%", n == 1, "1 argument") || !str_arg(args[0], "ASC%", "arg")) return rt_null();
+    if (args[0].as.s->len == 0) return rt_int(0);
+    return rt_int((int64_t)next_char(args[0].as.s->data, &i));
+}
+
+/* Every `from` in `s` replaced by `to` */
+static rt_val replace(rt_val s, const char *from, const char *to) {
+    buf b = {0};
+    size_t i = 0, fl = strlen(from);
+    while (i < s.as.s->len) {
+        if (i + fl <= s.as.s->len && memcmp(s.as.s->data + i, from, fl) == 0) { buf_puts(&b, to); i += fl; }
+        else buf_putc(&b, s.as.s->data[i++]);
+    }
+    return buf_take(&b);
+}
+
+RT_BUILTIN(rt_bi_escape) {
+    if (!arity("ESCAPE$", n == 1, "1 argument") || !str_arg(args[0], "ESCAPE$", "arg")) return rt_null();
+    return replace(args[0], "'", "''");
+}
+
+RT_BUILTIN(rt_bi_unescape) {
+    if (!arity("UNESCAPE$", n == 1, "1 argument") || !str_arg(args[0], "UNESCAPE$", "arg")) return rt_null();
+    return replace(args[0], "''", "'");
+}
+
+RT_BUILTIN(rt_bi_urlencode) {
+    buf b = {0};
+    size_t i;
+    if (!arity("URLENCODE$", n == 1, "1 argument") || !str_arg(args[0], "URLENCODE$", "arg")) return rt_null();
+    for (i = 0; i < args[0].as.s->len; i++) {
+        unsigned char c = (unsigned char)args[0].as.s->data[i];
+        if ((c >= 'A' && c <= 'Z') || (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || c == '-' || c == '_' || c == '.' || c == '~') buf_putc(&b, (char)c);
+        else if (c == ' ') buf_putc(&b, '+');
+        else buf_printf(&b, "%%%02X", c);
+    }
+    return buf_take(&b);
+}
+
+static int hex_digit(char c) {
+    if (c >= '0' && c <= '9') return c - '0';
+    if (c >= 'a' && c <= 'f') return c - 'a' + 10;
+    if (c >= 'A' && c <= 'F') return c - 'A' + 10;
+    return -1;
+}
+
+RT_BUILTIN(rt_bi_urldecode) {
+    buf b = {0};
+    rt_val out;
+    const char *s;
+    size_t i = 0, len;
+    if (!arity("URLDECODE$", n == 1, "1 argument") || !str_arg(args[0], "URLDECODE$", "arg")) return rt_null();
+    s = args[0].as.s->data;
+    len = args[0].as.s->len;
+    while (i < len) {
+        if (s[i] == '+') buf_putc(&b, ' ');
+        else if (s[i] == '%' && i + 2 < len) {
+            int h = hex_digit(s[i + 1]), l = hex_digit(s[i + 2]);
+            if (h >= 0 && l >= 0) { buf_putc(&b, (char)(h << 4 | l)); i += 3; continue; }
+            buf_putc(&b, '%');
+        } else buf_putc(&b, s[i]);
+        i++;
+    }
+    out = from_utf8_lossy(b.p ? b.p : "", b.len);
+    free(b.p);
+    return out;
+}
+
+RT_BUILTIN(rt_bi_string) {
+    int64_t count, code;
+    rt_val unit;
+    buf b = {0};
+    size_t i;
+    if (!arity("STRING$", n == 2, "2 arguments") || !to_i64(args[0], &count)) return rt_null();
+    count = count <= 0 ? 0 : count > 1000000 ? 1000000 : count;
+    if (args[1].tag == RT_STR) unit = rt_retain(args[1]);
+    else {
+        buf u = {0};
+        if (!to_i64(args[1], &code)) return rt_null();
+        put_char(&u, (uint32_t)code & 0xFF);
+        unit = buf_take(&u);
+    }
+    for (i = 0; i < (size_t)count; i++) buf_put(&b, unit.as.s->data, unit.as.s->len);
+    rt_release(unit);
+    return buf_take(&b);
+}
+
+RT_BUILTIN(rt_bi_input) {
+    buf b = {0};
+    int c;
+    if (n > 1) { rt_error("INPUT$ expects 0 or 1 argument"); return rt_null(); }
+    if (n == 1) rt_print(args[0]);
+    fflush(stdout);
+    while ((c = getchar()) != EOF) {
+        buf_putc(&b, (char)c);
+        if (c == '\n') break;
+    }
+    if (ferror(stdin)) { free(b.p); rt_error("INPUT$ read error: %s (os error %d)", strerror(errno), errno); return rt_null(); }
+    while (b.len > 0 && (b.p[b.len - 1] == '\n' || b.p[b.len - 1] == '\r')) b.len--;
+    return buf_take(&b);
+}
+
+/* ---- builtins: the process ---- */
+
+/* An OS error the way Rust's io::Error shows it */
+static const char *os_error(int err) {
+    static char msg[256];
+    snprintf(msg, sizeof msg, "%s (os error %d)", strerror(err), err);
+    return msg;
+}
+
+RT_BUILTIN(rt_bi_sleep) {
+    int64_t ms;
+    if (!arity("SLEEP", n == 1, "1 argument") || !to_i64(args[0], &ms)) return rt_null();
+    if (ms < 0) ms = 0;
+    fflush(stdout);
+#ifdef _WIN32
+    Sleep((DWORD)ms);
+#else
+    {
+        struct timespec ts;
+        ts.tv_sec = (time_t)(ms / 1000);
+        ts.tv_nsec = (long)(ms % 1000) * 1000000L;
+        while (nanosleep(&ts, &ts) != 0 && errno == EINTR) {}
+    }
+#endif
+    return rt_int(0);
+}
+
+RT_BUILTIN(rt_bi_env) {
+    rt_val name;
+    const char *value;
+    if (!arity("ENV$", n == 1, "1 argument")) return rt_null();
+    name = text(args[0]);
+    value = getenv(name.as.s->data);
+    rt_release(name);
+    return cstr(value ? value : "");
+}
+
+static void set_env(const char *name, const char *value) {
+#ifdef _WIN32
+    _putenv_s(name, value);
+#else
+    setenv(name, value, 1);
+#endif
+}
+
+RT_BUILTIN(rt_bi_setenv) {
+    rt_val name, value;
+    if (!arity("SETENV", n == 3, "3 arguments (name$, value, exportFlag)")) return rt_null();
+    name = text(args[0]);
+    value = text(args[1]);
+    set_env(name.as.s->data, value.as.s->data);
+#ifdef _WIN32
+    /* Exporting only reaches beyond this process on Windows, via `setx` */
+    if ((args[2].tag == RT_BOOL && args[2].as.b) || (args[2].tag == RT_INT && args[2].as.i != 0) || (args[2].tag == RT_NUM && args[2].as.n != 0.0)) {
+        buf cmd = {0};
+        rt_val line;
+        int status;
+        buf_printf(&cmd, "setx \"%s\" \"%s\"", name.as.s->data, value.as.s->data);
+        line = buf_take(&cmd);
+        status = system(line.as.s->data);
+        rt_release(line);
+        rt_release(name);
+        rt_release(value);
+        return rt_bool(status == 0);
+    }
+#endif
+    rt_release(name);
+    rt_release(value);
+    return rt_bool(true);
+}
+
+RT_BUILTIN(rt_bi_shell) {
+    rt_val cmd;
+    int status;
+    if (!arity("SHELL", n == 1, "1 argument")) return rt_null();
+    cmd = text(args[0]);
+    fflush(stdout);
+    status = system(cmd.as.s->data);
+    rt_release(cmd);
+    if (status == -1) return rt_int(-1);
+#ifdef _WIN32
+    return rt_int(status);
+#else
+    return rt_int(WIFEXITED(status) ? WEXITSTATUS(status) : -1);
+#endif
+}
+
+static bool is_dir(const char *path) {
+    struct stat st;
+    return stat(path, &st) == 0 && S_ISDIR(st.st_mode);
+}
+
+static int make_dir(const char *path) {
+#ifdef _WIN32
+    return _mkdir(path);
+#else
+    return mkdir(path, 0777);
+#endif
+}
+
+static bool is_sep(char c) {
+#ifdef _WIN32
+    return c == '/' || c == '\\';
+#else
+    return c == '/';
+#endif
+}
+
+RT_BUILTIN(rt_bi_mkdirs) {
+    rt_val path;
+    char *p;
+    size_t i;
+    bool ok;
+    if (!arity("MKDIRS%", n == 1, "1 argument")) return rt_null();
+    path = text(args[0]);
+    p = xalloc(path.as.s->len + 1);
+    memcpy(p, path.as.s->data, path.as.s->len + 1);
+    /* Each ancestor first, then the directory itself */
+    for (i = 1; i < path.as.s->len; i++) {
+        if (is_sep(p[i]) && !is_sep(p[i - 1])) { p[i] = '\0'; if (!is_dir(p)) make_dir(p); p[i] = path.as.s->data[i]; }
+    }
+    ok = path.as.s->len > 0 && (is_dir(p) || make_dir(p) == 0 || is_dir(p));
+    free(p);
+    rt_release(path);
+    return rt_int(ok ? 1 : 0);
+}
+
+/* Reads a whole file; returns false with errno set if it can't */
+static bool read_all(const char *path, buf *out) {
+    FILE *f = fopen(path, "rb");
+    char chunk[8192];
+    size_t got;
+    if (!f) return false;
+    if (is_dir(path)) { fclose(f); errno = EISDIR; return false; }
+    while ((got = fread(chunk, 1, sizeof chunk, f)) > 0) buf_put(out, chunk, got);
+    if (ferror(f)) { int err = errno; fclose(f); free(out->p); out->p = NULL; errno = err; return false; }
+    fclose(f);
+    return true;
+}
+
+RT_BUILTIN(rt_bi_loadenv) {
+    rt_val arg;
+    const char *file = ".env";
+    char *name = NULL;
+    buf contents = {0};
+    size_t from, to, line_no = 0, at = 0;
+    if (!arity("LOADENV%", n <= 1, "0 or 1 argument")) return rt_null();
+    arg = n == 1 ? text(args[0]) : empty_str();
+    trim_bounds(arg.as.s->data, arg.as.s->len, &from, &to);
+    if (to > from) {
+        name = xalloc(to - from + 1);
+        memcpy(name, arg.as.s->data + from, to - from);
+        name[to - from] = '\0';
+        file = name;
+    }
+    rt_release(arg);
+    if (!read_all(file, &contents)) {
+        fflush(stdout);
+        fprintf(stderr, "warning: LOADENV%% could not read %s: %s\n", file, os_error(errno));
+        free(name);
+        return rt_int(0);
+    }
+    while (at < contents.len) {
+        char *line = contents.p + at, *eq;
+        size_t len = 0, ks, ke, vs, ve;
+        while (at + len < contents.len && line[len] != '\n') len++;
+        at += len + 1;
+        line_no++;
+        trim_bounds(line, len, &from, &to);
+        if (to == from || line[from] == '#' || line[from] == ';') continue;
+        line[to] = '\0';
+        eq = memchr(line + from, '=', to - from);
+        if (!eq) {
+            fflush(stdout);
+            fprintf(stderr, "warning: LOADENV%% %s:%zu: invalid line (expected name=value or comment)\n", file, line_no);
+            continue;
+        }
+        *eq = '\0';
+        trim_bounds(line + from, (size_t)(eq - line) - from, &ks, &ke);
+        trim_bounds(eq + 1, (size_t)(line + to - eq - 1), &vs, &ve);
+        if (ke == ks) {
+            fflush(stdout);
+            fprintf(stderr, "warning: LOADENV%% %s:%zu: missing key before '='\n", file, line_no);
+            continue;
+        }
+        line[from + ke] = '\0';
+        eq[1 + ve] = '\0';
+        {
+            char *val = eq + 1 + vs;
+            size_t vlen = ve - vs;
+            if (vlen >= 2 && ((val[0] == '"' && val[vlen - 1] == '"') || (val[0] == '\'' && val[vlen - 1] == '\''))) { val[vlen - 1] = '\0'; val++; }
+            set_env(line + from + ks, val);
+        }
+    }
+    free(contents.p);
+    free(name);
+    return rt_int(1);
+}
+
+static rt_val array_dim(const char *name, size_t n, const rt_val *args, size_t which) {
+    if (!arity(name, n == 1, "1 argument")) return rt_null();
+    if (args[0].tag != RT_ARRAY) { rt_error("%s: expected array", name); return rt_null(); }
+    if (args[0].as.a->rank != 2) { rt_error("%s: expected 2-D array", name); return rt_null(); }
+    return rt_int((int64_t)args[0].as.a->dims[which]);
+}
+
+RT_BUILTIN(rt_bi_array_rows) { return array_dim("ARRAY_ROWS%", n, args, 0); }
+RT_BUILTIN(rt_bi_array_cols) { return array_dim("ARRAY_COLS%", n, args, 1); }
+
+/* ---- builtins: whole files ---- */
+
+RT_BUILTIN(rt_bi_readfile) {
+    rt_val path, out;
+    buf data = {0};
+    if (!arity("READFILE$", n == 1, "1 argument")) return rt_null();
+    path = text(args[0]);
+    if (!read_all(path.as.s->data, &data)) {
+        rt_error("READFILE$ %s: %s", path.as.s->data, os_error(errno));
+        rt_release(path);
+        return rt_null();
+    }
+    rt_release(path);
+    out = from_utf8_lossy(data.p ? data.p : "", data.len);
+    free(data.p);
+    return out;
+}
+
+static rt_val write_with(const char *name, size_t n, const rt_val *args, const char *mode) {
+    rt_val path, data;
+    FILE *f;
+    if (!arity(name, n == 2, "2 arguments")) return rt_null();
+    path = text(args[0]);
+    data = text(args[1]);
+    f = fopen(path.as.s->data, mode);
+    if (!f || fwrite(data.as.s->data, 1, data.as.s->len, f) != data.as.s->len) rt_error("%s %s: %s", name, path.as.s->data, os_error(errno));
+    if (f) fclose(f);
+    rt_release(path);
+    rt_release(data);
+    return rt_null();
+}
+
+RT_BUILTIN(rt_bi_writefile) { return write_with("WRITEFILE", n, args, "wb"); }
+RT_BUILTIN(rt_bi_appendfile) { return write_with("APPENDFILE", n, args, "ab"); }
+
+RT_BUILTIN(rt_bi_copy) {
+    rt_val src, dst;
+    buf data = {0};
+    FILE *f = NULL;
+    if (!arity("COPY", n == 2, "2 arguments")) return rt_null();
+    src = text(args[0]);
+    dst = text(args[1]);
+    if (!read_all(src.as.s->data, &data) || !(f = fopen(dst.as.s->data, "wb")) || fwrite(data.p, 1, data.len, f) != data.len) {
+        rt_error("COPY %s -> %s: %s", src.as.s->data, dst.as.s->data, os_error(errno));
+    }
+    if (f) fclose(f);
+    free(data.p);
+    rt_release(src);
+    rt_release(dst);
+    return rt_null();
+}
+
+RT_BUILTIN(rt_bi_move) {
+    rt_val src, dst;
+    if (!arity("MOVE", n == 2, "2 arguments")) return rt_null();
+    src = text(args[0]);
+    dst = text(args[1]);
+    if (rename(src.as.s->data, dst.as.s->data) != 0) rt_error("MOVE %s -> %s: %s", src.as.s->data, dst.as.s->data, os_error(errno));
+    rt_release(src);
+    rt_release(dst);
+    return rt_null();
+}
+
+RT_BUILTIN(rt_bi_rename) {
+    rt_val src, name;
+    buf dst = {0};
+    size_t cut;
+    if (!arity("RENAME", n == 2, "2 arguments")) return rt_null();
+    src = text(args[0]);
+    name = text(args[1]);
+    /* The new name is relative to the file's directory */
+    for (cut = src.as.s->len; cut > 0 && !is_sep(src.as.s->data[cut - 1]); cut--) {}
+    buf_put(&dst, src.as.s->data, cut);
+    buf_put(&dst, name.as.s->data, name.as.s->len);
+    buf_putc(&dst, '\0');
+    if (rename(src.as.s->data, dst.p) != 0) rt_error("RENAME %s -> %s: %s", src.as.s->data, dst.p, os_error(errno));
+    free(dst.p);
+    rt_release(src);
+    rt_release(name);
+    return rt_null();
+}
+
+RT_BUILTIN(rt_bi_delete) {
+    rt_val path;
+    if (!arity("DELETE", n == 1, "1 argument")) return rt_null();
+    path = text(args[0]);
+#ifdef _WIN32
+    if (remove(path.as.s->data) != 0) rt_error("DELETE %s: %s", path.as.s->data, os_error(errno));
+#else
+    if (unlink(path.as.s->data) != 0) rt_error("DELETE %s: %s", path.as.s->data, os_error(errno));
+#endif
+    rt_release(path);
+    return rt_null();
+}
+
+/* `*`/`?` wildcard match; case-insensitive on Windows */
+static bool glob_match(const char *p, const char *s) {
+    for (;;) {
+        if (*p == '\0') return *s == '\0';
+        if (*p == '*') {
+            for (;;) { if (glob_match(p + 1, s)) return true; if (*s == '\0') return false; s++; }
+        }
+        if (*s == '\0') return false;
+        if (*p != '?') {
+#ifdef _WIN32
+            if (to_lower((unsigned char)*p) != to_lower((unsigned char)*s)) return false;
+#else
+            if (*p != *s) return false;
+#endif
+        }
+        p++;
+        s++;
+    }
+}
+
+static int cmp_str(const void *a, const void *b) {
+    const rt_val *x = a, *y = b;
+    size_t n = x->as.s->len < y->as.s->len ? x->as.s->len : y->as.s->len;
+    int c = memcmp(x->as.s->data, y->as.s->data, n);
+    return c ? c : (x->as.s->len > y->as.s->len) - (x->as.s->len < y->as.s->len);
+}
+
+RT_BUILTIN(rt_bi_dir) {
+    rt_val patt, out;
+    buf dir = {0};
+    const char *pat;
+    rt_val *names = NULL;
+    size_t count = 0, cap = 0, cut;
+    if (!arity("DIR$", n == 1, "1 argument")) return rt_null();
+    patt = text(args[0]);
+    for (cut = patt.as.s->len; cut > 0 && !is_sep(patt.as.s->data[cut - 1]); cut--) {}
+    if (cut == 0) buf_putc(&dir, '.');
+    else buf_put(&dir, patt.as.s->data, cut > 1 ? cut - 1 : 1);
+    buf_putc(&dir, '\0');
+    pat = patt.as.s->data + cut;
+    {
+#ifdef _WIN32
+        WIN32_FIND_DATAA fd;
+        HANDLE h;
+        buf all = {0};
+        buf_puts(&all, dir.p);
+        buf_puts(&all, "\\*");
+        buf_putc(&all, '\0');
+        h = FindFirstFileA(all.p, &fd);
+        free(all.p);
+        if (h == INVALID_HANDLE_VALUE) {
+            rt_error("DIR$: %s: %s", dir.p, os_error(ENOENT));
+            free(dir.p);
+            rt_release(patt);
+            return rt_null();
+        }
+        do {
+            const char *name = fd.cFileName;
+            if (fd.dwFileAttributes & FILE_ATTRIBUTE_DIRECTORY) continue;
+#else
+        DIR *d = opendir(dir.p);
+        struct dirent *ent;
+        if (!d) {
+            rt_error("DIR$: %s: %s", dir.p, os_error(errno));
+            free(dir.p);
+            rt_release(patt);
+            return rt_null();
+        }
+        while ((ent = readdir(d)) != NULL) {
+            const char *name = ent->d_name;
+            buf full = {0};
+            bool file;
+            struct stat st;
+            buf_puts(&full, dir.p);
+            buf_putc(&full, '/');
+            buf_puts(&full, name);
+            buf_putc(&full, '\0');
+            file = stat(full.p, &st) == 0 && S_ISREG(st.st_mode);
+            free(full.p);
+            if (!file) continue;
+#endif
+            if (!glob_match(pat, name)) continue;
+            if (count == cap) { cap = cap ? cap * 2 : 16; names = xrealloc(names, cap * sizeof(rt_val)); }
+            names[count++] = cstr(name);
+#ifdef _WIN32
+        } while (FindNextFileA(h, &fd));
+        FindClose(h);
+#else
+        }
+        closedir(d);
+#endif
+    }
+    if (count > 1) qsort(names, count, sizeof(rt_val), cmp_str);
+    out = string_array(names, count);
+    free(names);
+    free(dir.p);
+    rt_release(patt);
+    return out;
+}
+
+/* ---- builtins: file handles ---- */
+
+/* An open file; `last` is the last operation (0 none, 1 read, 2 write), since C streams need a
+   seek between reading and writing */
+typedef struct { int64_t id; FILE *f; bool readable, writable; size_t owner; int last; } handle;
+
+static handle *handles;
+static size_t nhandles, caphandles;
+static int64_t next_handle = 1;
+static size_t depth = 1;
+
+void rt_enter(void) { depth++; }
+
+static void close_at(size_t i) {
+    fclose(handles[i].f);
+    memmove(&handles[i], &handles[i + 1], (nhandles - i - 1) * sizeof(handle));
+    nhandles--;
+}
+
+void rt_leave(void) {
+    size_t i = nhandles;
+    while (i > 0) { i--; if (handles[i].owner == depth) close_at(i); }
+    depth--;
+}
+
+static void close_all(void) { while (nhandles > 0) close_at(nhandles - 1); }
+
+static handle *get_handle(rt_val h) {
+    int64_t id;
+    size_t i;
+    buf b = {0};
+    if (!to_i64(h, &id)) return NULL;
+    for (i = 0; i < nhandles; i++) if (handles[i].id == id) return &handles[i];
+    buf_printf(&b, "InvalidHandle (wanted %" PRId64 ", have [", id);
+    for (i = 0; i < nhandles; i++) buf_printf(&b, i ? ", %" PRId64 : "%" PRId64, handles[i].id);
+    buf_puts(&b, "])");
+    error_buf(&b);
+    return NULL;
+}
+
+static void switch_to(handle *h, int op) {
+    if (h->last != 0 && h->last != op) fseek(h->f, 0, SEEK_CUR);
+    h->last = op;
+}
+
+RT_BUILTIN(rt_bi_fopen) {
+    rt_val path, mode;
+    char m;
+    bool plus, readable, writable;
+    const char *cmode;
+    FILE *f;
+    if (!arity("FOPEN", n == 2, "2 arguments")) return rt_null();
+    path = text(args[0]);
+    mode = text(args[1]);
+    if (memchr(path.as.s->data, '\0', path.as.s->len)) {
+        rt_release(path); rt_release(mode);
+        rt_error("FOPEN: invalid NUL in path");
+        return rt_null();
+    }
+    m = mode.as.s->len ? (char)(mode.as.s->data[0] | 0x20) : '\0';
+    plus = memchr(mode.as.s->data, '+', mode.as.s->len) != NULL;
+    if (m == 'r') { readable = true; writable = plus; cmode = plus ? "r+b" : "rb"; }
+    else if (m == 'w') { readable = plus; writable = true; cmode = plus ? "w+b" : "wb"; }
+    else if (m == 'a') { readable = plus; writable = true; cmode = plus ? "a+b" : "ab"; }
+    else {
+        rt_error("FOPEN: invalid mode '%s'; expected r/w/a variants", mode.as.s->data);
+        rt_release(path); rt_release(mode);
+        return rt_null();
+    }
+    f = fopen(path.as.s->data, cmode);
+    rt_release(path);
+    rt_release(mode);
+    /* Non-throwing failure: -1 signals the open error */
+    if (!f) return rt_int(-1);
+    if (nhandles == caphandles) { caphandles = caphandles ? caphandles * 2 : 8; handles = xrealloc(handles, caphandles * sizeof(handle)); }
+    handles[nhandles].id = next_handle;
+    handles[nhandles].f = f;
+    handles[nhandles].readable = readable;
+    handles[nhandles].writable = writable;
+    handles[nhandles].owner = depth;
+    handles[nhandles].last = 0;
+    nhandles++;
+    return rt_int(next_handle++);
+}
+
+RT_BUILTIN(rt_bi_fclose) {
+    int64_t id;
+    size_t i;
+    if (!arity("FCLOSE", n == 1, "1 argument") || !to_i64(args[0], &id)) return rt_null();
+    for (i = 0; i < nhandles; i++) if (handles[i].id == id) { close_at(i); break; }
+    return rt_bool(true);
+}
+
+RT_BUILTIN(rt_bi_fflush) {
+    handle *h;
+    if (!arity("FFLUSH", n == 1, "1 argument") || !(h = get_handle(args[0]))) return rt_null();
+    if (fflush(h->f) != 0) { rt_error("FFLUSH error: %s", os_error(errno)); return rt_null(); }
+    return rt_bool(true);
+}
+
+RT_BUILTIN(rt_bi_feof) {
+    handle *h;
+    int c;
+    if (!arity("FEOF", n == 1, "1 argument") || !(h = get_handle(args[0]))) return rt_null();
+    if (!h->readable) { rt_error("FEOF read: %s", os_error(EBADF)); return rt_null(); }
+    switch_to(h, 1);
+    c = fgetc(h->f);
+    if (c == EOF) { if (ferror(h->f)) { rt_error("FEOF read: %s", os_error(errno)); return rt_null(); } return rt_bool(true); }
+    ungetc(c, h->f);
+    return rt_bool(false);
+}
+
+RT_BUILTIN(rt_bi_ftell) {
+    handle *h;
+    long pos;
+    if (!arity("FTELL&", n == 1, "1 argument") || !(h = get_handle(args[0]))) return rt_null();
+    pos = ftell(h->f);
+    if (pos < 0) { rt_error("FTELL: %s", os_error(errno)); return rt_null(); }
+    return rt_int(pos);
+}
+
+RT_BUILTIN(rt_bi_fseek) {
+    handle *h;
+    int64_t off, wh;
+    int whence;
+    if (!arity("FSEEK", n == 3, "3 arguments")) return rt_null();
+    if (!to_i64(args[0], &off) || !to_i64(args[1], &off) || !to_i64(args[2], &wh) || !(h = get_handle(args[0]))) return rt_null();
+    if (wh == 0) whence = SEEK_SET;
+    else if (wh == 1) whence = SEEK_CUR;
+    else if (wh == 2) whence = SEEK_END;
+    else { rt_error("FSEEK: whence must be 0,1,2"); return rt_null(); }
+    if (fseek(h->f, (long)off, whence) != 0) { rt_error("FSEEK: %s", os_error(errno)); return rt_null(); }
+    h->last = 0;
+    return rt_bool(true);
+}
+
+RT_BUILTIN(rt_bi_fread) {
+    handle *h;
+    int64_t count;
+    char *data;
+    size_t got;
+    rt_val out;
+    if (!arity("FREAD$", n == 2, "2 arguments")) return rt_null();
+    if (!to_i64(args[1], &count)) return rt_null();
+    if (count <= 0) return rt_str_new("", 0);
+    if (!(h = get_handle(args[0]))) return rt_null();
+    if (!h->readable) { rt_error("FREAD$: handle not opened for reading"); return rt_null(); }
+    switch_to(h, 1);
+    data = xalloc((size_t)count);
+    got = fread(data, 1, (size_t)count, h->f);
+    if (got == 0 && ferror(h->f)) { free(data); rt_error("FREAD$: %s", os_error(errno)); return rt_null(); }
+    out = from_utf8_lossy(data, got);
+    free(data);
+    return out;
+}
+
+RT_BUILTIN(rt_bi_freadline) {
+    handle *h;
+    buf line = {0};
+    int c;
+    rt_val out;
+    if (!arity("FREADLINE$", n == 1, "1 argument") || !(h = get_handle(args[0]))) return rt_null();
+    if (!h->readable) { rt_error("FREADLINE$: handle not opened for reading"); return rt_null(); }
+    switch_to(h, 1);
+    while ((c = fgetc(h->f)) != EOF && c != '\n') buf_putc(&line, (char)c);
+    if (c == EOF && ferror(h->f)) { free(line.p); rt_error("FREADLINE$: %s", os_error(errno)); return rt_null(); }
+    if (line.len > 0 && line.p[line.len - 1] == '\r') line.len--;
+    out = from_utf8_lossy(line.p ? line.p : "", line.len);
+    free(line.p);
+    return out;
+}
+
+static rt_val fwrite_with(const char *name, size_t n, const rt_val *args, bool newline) {
+    handle *h;
+    rt_val data;
+    bool ok;
+    if (!arity(name, n == 2, "2 arguments") || !(h = get_handle(args[0]))) return rt_null();
+    if (!h->writable) { rt_error("%s: handle not opened for writing", name); return rt_null(); }
+    switch_to(h, 2);
+    data = text(args[1]);
+    ok = fwrite(data.as.s->data, 1, data.as.s->len, h->f) == data.as.s->len && (!newline || fputc('\n', h->f) != EOF);
+    rt_release(data);
+    if (!ok) { rt_error("%s: %s", name, os_error(errno)); return rt_null(); }
+    return rt_bool(true);
+}
+
+RT_BUILTIN(rt_bi_fwrite) { return fwrite_with("FWRITE", n, args, false); }
+RT_BUILTIN(rt_bi_fwriteln) { return fwrite_with("FWRITELN", n, args, true); }
@@ -0,0 +1,191 @@
+/*
+ * basilrt: the runtime for C emitted by bcc's C backend (`bcc aot --backend c`).
+ *
+ * Values are tagged `rt_val`s. Strings, arrays, lists and dictionaries live on the heap with a
+ * reference count: `rt_retain` and `rt_release` adjust it, and everything else here borrows
+ * its arguments and returns a value the caller owns. Every operation mirrors the VM's, error
+ * messages included, so a compiled program prints what `basilc run` prints.
+ *
+ * Errors don't unwind: an operation that fails records the error and returns a null value,
+ * and the emitted code checks `rt_failed()` after it, the way the Rust backend uses `?`.
+ */
+
+#ifndef BASILRT_H
+#define BASILRT_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum { RT_NULL, RT_BOOL, RT_INT, RT_NUM, RT_STR, RT_ARRAY, RT_LIST, RT_DICT } rt_tag;
+
+/* Element type of a DIMed array */
+typedef enum { RT_ELEM_NUM, RT_ELEM_INT, RT_ELEM_STR } rt_elem;
+
+typedef struct rt_str rt_str;
+typedef struct rt_array rt_array;
+typedef struct rt_list rt_list;
+typedef struct rt_dict rt_dict;
+
+typedef struct {
+    rt_tag tag;
+    union { bool b; int64_t i; double n; rt_str *s; rt_array *a; rt_list *l; rt_dict *d; long *refs; } as;
+} rt_val;
+
+/* UTF-8 text, NUL-terminated for the C library; `len` is in bytes */
+struct rt_str { long refs; size_t len; char data[]; };
+
+/* Row-major elements; `dims` are the lengths (upper bound + 1) of each of `rank` dimensions */
+struct rt_array { long refs; rt_elem elem; size_t rank; size_t dims[4]; size_t len; rt_val *data; };
+
+struct rt_list { long refs; size_t len, cap; rt_val *items; };
+
+/* Entries in insertion order */
+struct rt_dict { long refs; size_t len, cap; rt_val *keys; rt_val *vals; };
+
+/* A growable stack of block numbers, for TRY handlers and GOSUB return addresses */
+typedef struct { size_t len, cap; int *items; } rt_stack;
+
+static inline rt_val rt_null(void) { rt_val v; v.tag = RT_NULL; v.as.i = 0; return v; }
+static inline rt_val rt_bool(bool b) { rt_val v; v.tag = RT_BOOL; v.as.b = b; return v; }
+static inline rt_val rt_int(int64_t i) { rt_val v; v.tag = RT_INT; v.as.i = i; return v; }
+static inline rt_val rt_num(double n) { rt_val v; v.tag = RT_NUM; v.as.n = n; return v; }
+
+/* A negative count marks a value that lives until the program ends (string constants) */
+static inline rt_val rt_retain(rt_val v) {
+    if (v.tag >= RT_STR && *v.as.refs >= 0) ++*v.as.refs;
+    return v;
+}
+void rt_free(rt_val v);
+static inline void rt_release(rt_val v) {
+    if (v.tag >= RT_STR && *v.as.refs >= 0 && --*v.as.refs == 0) rt_free(v);
+}
+/* Store an owned value in a variable, releasing what it held */
+static inline void rt_set(rt_val *slot, rt_val v) { rt_val old = *slot; *slot = v; rt_release(old); }
+
+void rt_push(rt_stack *s, int v);
+int rt_pop(rt_stack *s);
+void rt_stack_free(rt_stack *s);
+
+/* ---- errors and program state ---- */
+
+extern int rt_pending;      /* 0, or RT_ERROR / RT_RAISED while an error is on its way out */
+extern uint32_t rt_cur_line;
+enum { RT_ERROR = 1, RT_RAISED = 2 };
+
+static inline bool rt_failed(void) { return rt_pending != 0; }
+/* Only errors raised by RAISE can be caught by TRY; the rest end the program */
+static inline bool rt_raised(void) { return rt_pending == RT_RAISED; }
+/* Record the source line being executed, for runtime error messages */
+static inline void rt_line(uint32_t n) { rt_cur_line = n; }
+
+void rt_error(const char *fmt, ...);
+void rt_raise(rt_val v);
+void rt_reraise(bool active, rt_val exc);
+/* The pending error's message, which is then cleared (the CATCH variable) */
+rt_val rt_take_error(void);
+/* Report the pending error the way `basilc run` does and exit with status 1 */
+void rt_fail(void);
+/* EXIT code: only returns if `code` isn't a number */
+void rt_exit(rt_val code);
+/* Flush output and close files at the end of the program */
+void rt_finish(void);
+
+/* Entered a FUNC or SUB; files it opens are closed by the matching rt_leave */
+void rt_enter(void);
+void rt_leave(void);
+
+/* ---- values ---- */
+
+rt_val rt_str_new(const char *s, size_t len);
+rt_val rt_str_const(const char *s, size_t len);
+rt_val rt_concat(size_t n, const rt_val *parts);
+bool rt_str_eq(rt_val a, rt_val b);
+bool rt_truthy(rt_val v);
+int64_t rt_trunc(double n);
+
+void rt_print(rt_val v);
+void rt_print_num(double n);
+void rt_print_int(int64_t i);
+void rt_print_bool(bool b);
+
+rt_val rt_add(rt_val a, rt_val b);
+rt_val rt_sub(rt_val a, rt_val b);
+rt_val rt_mul(rt_val a, rt_val b);
+rt_val rt_div(rt_val a, rt_val b);
+rt_val rt_modulo(rt_val a, rt_val b);
+rt_val rt_neg(rt_val a);
+rt_val rt_eq(rt_val a, rt_val b);
+rt_val rt_ne(rt_val a, rt_val b);
+rt_val rt_lt(rt_val a, rt_val b);
+rt_val rt_le(rt_val a, rt_val b);
+rt_val rt_gt(rt_val a, rt_val b);
+rt_val rt_ge(rt_val a, rt_val b);
+rt_val rt_to_int(rt_val v);
+
+/* Take a typed value back out of a `rt_val` an operation the IR typed returned */
+double rt_unbox_num(rt_val v);
+int64_t rt_unbox_int(rt_val v);
+bool rt_unbox_bool(rt_val v);
+
+rt_val rt_new_array(rt_elem elem, size_t n, const rt_val *uppers);
+rt_val rt_array_get(rt_val arr, size_t n, const rt_val *idxs);
+void rt_array_set(rt_val arr, size_t n, const rt_val *idxs, rt_val v);
+
+rt_val rt_list_new(size_t n, const rt_val *items);
+rt_val rt_dict_new(size_t n, const rt_val *keys, const rt_val *vals);
+rt_val rt_index_get(rt_val target, rt_val index);
+void rt_index_set(rt_val target, rt_val index, rt_val v);
+rt_val rt_prop_get(rt_val target, rt_val name);
+void rt_prop_set(rt_val target, rt_val name, rt_val v);
+rt_val rt_items(rt_val v);
+
+/* ---- builtins, by the name in basil_ir::BUILTINS ---- */
+
+#define RT_BUILTIN(f) rt_val f(size_t n, const rt_val *args)
+RT_BUILTIN(rt_bi_len);        /* LEN */
+RT_BUILTIN(rt_bi_mid);        /* MID$ */
+RT_BUILTIN(rt_bi_left);       /* LEFT$ */
+RT_BUILTIN(rt_bi_right);      /* RIGHT$ */
+RT_BUILTIN(rt_bi_instr);      /* INSTR */
+RT_BUILTIN(rt_bi_type);       /* TYPE$ */
+RT_BUILTIN(rt_bi_html);       /* HTML$, HTML */
+RT_BUILTIN(rt_bi_ucase);      /* UCASE$ */
+RT_BUILTIN(rt_bi_lcase);      /* LCASE$ */
+RT_BUILTIN(rt_bi_trim);       /* TRIM$ */
+RT_BUILTIN(rt_bi_chr);        /* CHR$ */
+RT_BUILTIN(rt_bi_asc);        /* ASC% */
+RT_BUILTIN(rt_bi_escape);     /* ESCAPE$ */
+RT_BUILTIN(rt_bi_unescape);   /* UNESCAPE$ */
+RT_BUILTIN(rt_bi_urlencode);  /* URLENCODE$ */
+RT_BUILTIN(rt_bi_urldecode);  /* URLDECODE$ */
+RT_BUILTIN(rt_bi_string);     /* STRING$ */
+RT_BUILTIN(rt_bi_input);      /* INPUT$ */
+RT_BUILTIN(rt_bi_sleep);      /* SLEEP */
+RT_BUILTIN(rt_bi_env);        /* ENV$ */
+RT_BUILTIN(rt_bi_setenv);     /* SETENV */
+RT_BUILTIN(rt_bi_shell);      /* SHELL */
+RT_BUILTIN(rt_bi_mkdirs);     /* MKDIRS% */
+RT_BUILTIN(rt_bi_loadenv);    /* LOADENV% */
+RT_BUILTIN(rt_bi_array_rows); /* ARRAY_ROWS% */
+RT_BUILTIN(rt_bi_array_cols); /* ARRAY_COLS% */
+RT_BUILTIN(rt_bi_readfile);   /* READFILE$ */
+RT_BUILTIN(rt_bi_writefile);  /* WRITEFILE */
+RT_BUILTIN(rt_bi_appendfile); /* APPENDFILE */
+RT_BUILTIN(rt_bi_copy);       /* COPY */
+RT_BUILTIN(rt_bi_move);       /* MOVE */
+RT_BUILTIN(rt_bi_rename);     /* RENAME */
+RT_BUILTIN(rt_bi_delete);     /* DELETE */
+RT_BUILTIN(rt_bi_dir);        /* DIR$ */
+RT_BUILTIN(rt_bi_fopen);      /* FOPEN */
+RT_BUILTIN(rt_bi_fclose);     /* FCLOSE */
+RT_BUILTIN(rt_bi_fflush);     /* FFLUSH */
+RT_BUILTIN(rt_bi_feof);       /* FEOF */
+RT_BUILTIN(rt_bi_ftell);      /* FTELL& */
+RT_BUILTIN(rt_bi_fseek);      /* FSEEK */
+RT_BUILTIN(rt_bi_fread);      /* FREAD$ */
+RT_BUILTIN(rt_bi_freadline);  /* FREADLINE$ */
+RT_BUILTIN(rt_bi_fwrite);     /* FWRITE */
+RT_BUILTIN(rt_bi_fwriteln);   /* FWRITELN */
+
+#endif
@@ -0,0 +1,764 @@
+//! C backend emitter for bcc (`bcc aot --backend c`): turns the IR into C99 linked against a
+//! small C runtime (`runtime/basilrt.c`) and builds it with the system C compiler, so a program
+//! can be compiled where there is no Rust toolchain.
+//!
+//! The runtime ports libbasilrt and the shared builtins, error messages included, so a program
+//! built this way prints what `basilc run` prints. Objects and the builtins behind runtime
+//! features (JSON_, SQLITE_, terminal...) are Rust-only; [`check`] reports them by line.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use backend_rs::{compute_hash_key, BuildOptions};
+use basil_ir::{BinOp, BlockId, ElemKind, Expr, Function, Instr, Module, Terminator, Ty, ValueId, Var};
+
+const RUNTIME_H: &str = include_str!("../runtime/basilrt.h");
+const RUNTIME_C: &str = include_str!("../runtime/basilrt.c");
+
+/// Builtins the C runtime has, by their name in `basil_ir::BUILTINS`, with the C function
+const BUILTINS: &[(&str, &str)] = &[
+    ("LEN", "rt_bi_len"), ("MID$", "rt_bi_mid"), ("LEFT$", "rt_bi_left"), ("RIGHT$", "rt_bi_right"),
+    ("INSTR", "rt_bi_instr"), ("INPUT$", "rt_bi_input"), ("TYPE$", "rt_bi_type"), ("HTML$", "rt_bi_html"),
+    ("HTML", "rt_bi_html"), ("UCASE$", "rt_bi_ucase"), ("LCASE$", "rt_bi_lcase"), ("TRIM$", "rt_bi_trim"),
+    ("CHR$", "rt_bi_chr"), ("ASC%", "rt_bi_asc"), ("ESCAPE$", "rt_bi_escape"), ("UNESCAPE$", "rt_bi_unescape"),
+    ("URLENCODE$", "rt_bi_urlencode"), ("URLDECODE$", "rt_bi_urldecode"), ("SLEEP", "rt_bi_sleep"),
+    ("STRING$", "rt_bi_string"), ("ENV$", "rt_bi_env"), ("SETENV", "rt_bi_setenv"), ("SHELL", "rt_bi_shell"),
+    ("LOADENV%", "rt_bi_loadenv"), ("MKDIRS%", "rt_bi_mkdirs"), ("ARRAY_ROWS%", "rt_bi_array_rows"),
+    ("ARRAY_COLS%", "rt_bi_array_cols"), ("FOPEN", "rt_bi_fopen"), ("FCLOSE", "rt_bi_fclose"),
+    ("FFLUSH", "rt_bi_fflush"), ("FEOF", "rt_bi_feof"), ("FTELL&", "rt_bi_ftell"), ("FSEEK", "rt_bi_fseek"),
+    ("FREAD$", "rt_bi_fread"), ("FREADLINE$", "rt_bi_freadline"), ("FWRITE", "rt_bi_fwrite"),
+    ("FWRITELN", "rt_bi_fwriteln"), ("READFILE$", "rt_bi_readfile"), ("WRITEFILE", "rt_bi_writefile"),
+    ("APPENDFILE", "rt_bi_appendfile"), ("COPY", "rt_bi_copy"), ("MOVE", "rt_bi_move"),
+    ("RENAME", "rt_bi_rename"), ("DELETE", "rt_bi_delete"), ("DIR$", "rt_bi_dir"),
+];
+
+fn c_builtin(name: &str) -> Option<&'static str> {
+    BUILTINS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+/// Reject what the C runtime can't run: objects, object arrays and feature builtins. The error
+/// names the construct and its line, like the errors of `basil_ir::lower_to_ir`.
+pub fn check(module: &Module) -> Result<(), String> {
+    for f in module.functions.iter().chain(std::iter::once(&module.main)) {
+        let mut line = 0;
+        for b in &f.blocks {
+            for i in &b.instrs {
+                if let Instr::Line(n) = i { line = *n; }
+                for e in i.exprs() { check_expr(e, line)?; }
+            }
+            if let Some(e) = b.term.expr() { check_expr(e, line)?; }
+        }
+    }
+    Ok(())
+}
+
+fn check_expr(e: &Expr, line: u32) -> Result<(), String> {
+    let what = match e {
+        Expr::NewObject { type_name, .. } => Some(format!("NEW {}", type_name)),
+        Expr::MethodCall { method, .. } => Some(format!("method call .{}", method)),
+        Expr::NewArray { elem: ElemKind::Obj(_), .. } => Some("an OBJECT array".to_string()),
+        Expr::Builtin { name, .. } if c_builtin(name).is_none() => Some(format!("builtin {}", name)),
+        _ => None,
+    };
+    if let Some(what) = what {
+        let msg = format!("{} is not supported by the C backend; build with --backend rust", what);
+        return Err(if line > 0 { format!("line {}: {}", line, msg) } else { msg });
+    }
+    for c in e.children() { check_expr(c, line)?; }
+    Ok(())
+}
+
+pub struct EmittedProject { pub root: PathBuf, pub main_c: PathBuf }
+
+/// Write `main.c` and the runtime into `.basil/targets/<hash>` under `base_dir`, or into
+/// `opts.emit_project_dir`. The module must have passed [`check`].
+pub fn emit_project(base_dir: &Path, src_path: &Path, module: &Module, opts: &BuildOptions) -> std::io::Result<EmittedProject> {
+    let hash = compute_hash_key(&format!("c\n{}\n{:?}", src_path.display(), module), opts);
+    let root = if let Some(ref dir) = opts.emit_project_dir { dir.clone() } else { base_dir.join(".basil").join("targets").join(&hash) };
+    fs::create_dir_all(&root)?;
+
+    let main_c = root.join("main.c");
+    fs::write(&main_c, render_main_c(src_path, module))?;
+    fs::write(root.join("basilrt.h"), RUNTIME_H)?;
+    fs::write(root.join("basilrt.c"), RUNTIME_C)?;
+    Ok(EmittedProject { root, main_c })
+}
+
+/// The C compiler to build with: `$CC`, else `<target>-gcc` for a `--target` triple, else `cc`.
+pub fn compiler(opts: &BuildOptions) -> String {
+    match std::env::var("CC") {
+        Ok(cc) if !cc.trim().is_empty() => cc,
+        _ => match &opts.target { Some(t) => format!("{}-gcc", t), None => "cc".to_string() },
+    }
+}
+
+/// Compile the project emitted in `root` and return the executable, which is left there.
+pub fn build(root: &Path, opts: &BuildOptions) -> Result<PathBuf, String> {
+    let cc = compiler(opts);
+    let name = opts.name.clone().unwrap_or_else(|| "basil_prog".to_string());
+    let exe = root.join(if cfg!(windows) { format!("{}.exe", name) } else { name });
+    let opt = opts.opt_level.unwrap_or(3).min(3);
+
+    // `$CC` may carry flags of its own, such as `gcc -m32`
+    let mut words = cc.split_whitespace();
+    let mut cmd = Command::new(words.next().unwrap_or("cc"));
+    cmd.args(words).arg("-std=c99").arg(format!("-O{}", opt));
+    if matches!(opts.lto.as_deref(), None | Some("thin") | Some("fat")) { cmd.arg("-flto"); }
+    cmd.arg("-o").arg(&exe).args(["main.c", "basilrt.c", "-lm"]).current_dir(root);
+    if !opts.quiet { println!("building with {} in {}", cc, root.display()); }
+    let status = cmd.status().map_err(|e| format!("failed to run C compiler '{}': {}", cc, e))?;
+    if !status.success() { return Err(format!("C compiler '{}' failed", cc)); }
+    if !opts.quiet { println!("ok: project at {}", root.display()); }
+    Ok(exe)
+}
+
+// String literals, interned into the `S` table main.c fills in at startup
+#[derive(Default)]
+struct Strings { index: HashMap<String, usize>, list: Vec<String> }
+
+impl Strings {
+    fn get(&mut self, s: &str) -> String {
+        let next = self.list.len();
+        let i = *self.index.entry(s.to_string()).or_insert(next);
+        if i == next { self.list.push(s.to_string()); }
+        format!("S[{}]", i)
+    }
+}
+
+fn render_main_c(src_path: &Path, module: &Module) -> String {
+    let mut strings = Strings::default();
+    let mut protos = String::new();
+    let mut bodies = String::new();
+    for (i, f) in module.functions.iter().enumerate() {
+        let name = format!("f{}", i);
+        let _ = writeln!(protos, "{};", signature(&name, f));
+        bodies.push_str(&FnEmitter::new(f, &mut strings).render(&name, true));
+    }
+    let _ = writeln!(protos, "{};", signature("basil_main", &module.main));
+    bodies.push_str(&FnEmitter::new(&module.main, &mut strings).render("basil_main", false));
+
+    let nglobals = module.globals.len().max(1);
+    let mut init = String::new();
+    for (i, s) in strings.list.iter().enumerate() {
+        let _ = writeln!(init, "    S[{}] = rt_str_const({}, {});", i, c_str(s), s.len());
+    }
+    let table = if strings.list.is_empty() { String::new() } else { format!("static rt_val S[{}];\n", strings.list.len()) };
+    format!(r#"/* AUTOGENERATED by bcc — DO NOT EDIT */
+/* Source: {src} */
+
+#include <math.h>
+#include <stdlib.h>
+
+#include "basilrt.h"
+
+{table}
+{protos}{bodies}
+int main(void) {{
+    rt_val g[{nglobals}];
+    size_t i;
+    for (i = 0; i < {nglobals}; i++) g[i] = rt_null();
+{init}    rt_release(basil_main(g));
+    if (rt_failed()) rt_fail();
+    for (i = 0; i < {nglobals}; i++) rt_release(g[i]);
+    rt_finish();
+    return 0;
+}}
+"#, src = comment(&src_path.display().to_string()))
+}
+
+fn signature(name: &str, f: &Function) -> String {
+    let mut s = format!("static rt_val {}(rt_val *g", name);
+    for p in 0..f.params { let _ = write!(s, ", rt_val l{}", p); }
+    s.push(')');
+    s
+}
+
+// Text safe inside a C comment
+fn comment(s: &str) -> String { s.replace("*/", "* /") }
+
+// A C string literal with the bytes of `s`. Anything but printable ASCII is an octal escape,
+// and so are `?` (trigraphs) and the quote and backslash.
+fn c_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' | b'?' => { let _ = write!(out, "\\{:03o}", b); }
+            0x20..=0x7e => out.push(b as char),
+            _ => { let _ = write!(out, "\\{:03o}", b); }
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn num_lit(n: f64) -> String {
+    if n.is_nan() { "NAN".into() }
+    else if n.is_infinite() { if n > 0.0 { "INFINITY".into() } else { "(-INFINITY)".into() } }
+    else if n.is_sign_negative() { format!("({:?})", n) }
+    else { format!("{:?}", n) }
+}
+
+fn int_lit(i: i64) -> String {
+    if i == i64::MIN { "INT64_MIN".into() } else if i < 0 { format!("(INT64_C({}))", i) } else { format!("INT64_C({})", i) }
+}
+
+// Types held in a C variable of their own; the rest (strings included) are `rt_val`
+fn native(t: Ty) -> bool { matches!(t, Ty::Int | Ty::Num | Ty::Bool) }
+
+fn c_type(t: Ty) -> &'static str {
+    match t { Ty::Int => "int64_t", Ty::Num => "double", Ty::Bool => "bool", _ => "rt_val" }
+}
+
+fn box_code(code: &str, t: Ty) -> String {
+    match t {
+        Ty::Int => format!("rt_int({})", code),
+        Ty::Num => format!("rt_num({})", code),
+        Ty::Bool => format!("rt_bool({})", code),
+        _ => code.to_string(),
+    }
+}
+
+fn unbox_code(code: &str, t: Ty) -> String {
+    match t {
+        Ty::Int => format!("rt_unbox_int({})", code),
+        Ty::Num => format!("rt_unbox_num({})", code),
+        Ty::Bool => format!("rt_unbox_bool({})", code),
+        _ => code.to_string(),
+    }
+}
+
+// `code` of type `from` as a `to`: the same, boxed into a `rt_val`, or a `rt_val` the IR proved to be a `to`
+fn coerce(code: &str, from: Ty, to: Ty) -> String {
+    if from == to || (!native(from) && !native(to)) { return code.to_string(); }
+    let val = box_code(code, from);
+    if native(to) { unbox_code(&val, to) } else { val }
+}
+
+fn place(v: Var) -> String {
+    match v { Var::Local(i) => format!("l{}", i), Var::Global(i) => format!("g[{}]", i) }
+}
+
+// An evaluated expression: C code of its static type. A `rt_val` the code owns is a temp,
+// released when the statement ends unless something takes it over; the rest are borrowed.
+struct Operand { code: String, ty: Ty, owned: bool }
+
+impl Operand {
+    fn new(code: impl Into<String>, ty: Ty) -> Self { Operand { code: code.into(), ty, owned: false } }
+}
+
+// Renders one function. Blocks become labels joined by `goto`; the jumps whose target is only
+// known at run time (a TRY handler, a GOSUB's RETURN) go through a `switch` on `bb`. Every
+// operation that can fail is followed by a check that releases the statement's temps and
+// leaves for `fail`, which continues at the innermost TRY handler for a RAISE and otherwise
+// returns with the error still pending, as the Rust backend's `?` does.
+struct FnEmitter<'a> {
+    f: &'a Function,
+    strings: &'a mut Strings,
+    out: String,
+    indent: usize,
+    temps: usize,
+    // Owned temps of the statement being rendered, in the order they were made
+    live: Vec<String>,
+    uses_exc: bool,
+    uses_reraise: bool,
+}
+
+impl<'a> FnEmitter<'a> {
+    fn new(f: &'a Function, strings: &'a mut Strings) -> Self {
+        FnEmitter { f, strings, out: String::new(), indent: 1, temps: 0, live: Vec::new(), uses_exc: false, uses_reraise: false }
+    }
+
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent { self.out.push_str("    "); }
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    // Release the temps made since `mark`
+    fn release_to(&mut self, mark: usize) {
+        let gone: Vec<String> = self.live.drain(mark..).collect();
+        for t in gone.iter().rev() { self.line(&format!("rt_release({});", t)); }
+    }
+
+    fn check(&mut self) {
+        let mut s = String::from("if (rt_failed()) { ");
+        for t in self.live.iter().rev() { let _ = write!(s, "rt_release({}); ", t); }
+        s.push_str("goto fail; }");
+        self.line(&s);
+    }
+
+    // A statement in braces of its own when it declares temps
+    fn stmt(&mut self, body: impl FnOnce(&mut Self)) {
+        let (start, temps) = (self.out.len(), self.temps);
+        self.indent += 1;
+        body(self);
+        self.release_to(0);
+        self.indent -= 1;
+        if self.temps == temps {
+            let inner = self.out.split_off(start);
+            for l in inner.lines() { self.out.push_str(l.strip_prefix("    ").unwrap_or(l)); self.out.push('\n'); }
+        } else {
+            let inner = self.out.split_off(start);
+            self.line("{");
+            self.out.push_str(&inner);
+            self.line("}");
+        }
+    }
+
+    // The result of a runtime call that can fail, as a temp of type `ty`
+    fn fallible(&mut self, call: String, ty: Ty) -> Operand {
+        let t = self.temp();
+        self.line(&format!("{} {} = {};", c_type(ty), t, unbox_code(&call, ty)));
+        self.check();
+        if native(ty) { return Operand::new(t, ty); }
+        self.live.push(t.clone());
+        Operand { code: t, ty, owned: true }
+    }
+
+    fn owned_temp(&mut self, code: String, ty: Ty) -> Operand {
+        let t = self.temp();
+        self.line(&format!("rt_val {} = {};", t, code));
+        self.live.push(t.clone());
+        Operand { code: t, ty, owned: true }
+    }
+
+    // The operand as a `rt_val` to pass on borrowed
+    fn val(op: &Operand) -> String { box_code(&op.code, op.ty) }
+
+    // The operand as a `rt_val` someone else will release: a temp is handed over, anything
+    // else gets a reference of its own
+    fn owned(&mut self, op: Operand) -> String {
+        if native(op.ty) { return box_code(&op.code, op.ty); }
+        if op.owned {
+            if let Some(i) = self.live.iter().rposition(|t| *t == op.code) { self.live.remove(i); }
+            return op.code;
+        }
+        format!("rt_retain({})", op.code)
+    }
+
+    fn vals(&mut self, es: &[Expr]) -> String {
+        let ops: Vec<Operand> = es.iter().map(|e| self.expr(e)).collect();
+        if ops.is_empty() { return "0, NULL".to_string(); }
+        format!("{}, (rt_val[]){{{}}}", ops.len(), ops.iter().map(Self::val).collect::<Vec<_>>().join(", "))
+    }
+
+    fn as_f64(op: &Operand) -> String {
+        match op.ty {
+            Ty::Int => format!("(double){}", op.code),
+            Ty::Bool => format!("({} ? 1.0 : 0.0)", op.code),
+            _ => op.code.clone(),
+        }
+    }
+
+    fn truthy(op: &Operand) -> String {
+        match op.ty {
+            Ty::Bool => op.code.clone(),
+            Ty::Num => format!("({} != 0.0)", op.code),
+            Ty::Int => format!("({} != 0)", op.code),
+            _ => format!("rt_truthy({})", op.code),
+        }
+    }
+
+    // The pieces of a chain of string concatenations, left to right
+    fn concat_parts<'e>(&self, e: &'e Expr, out: &mut Vec<&'e Expr>) {
+        match e {
+            Expr::Binary(BinOp::Add, a, b) if e.ty(&self.f.values) == Ty::Str => {
+                self.concat_parts(a, out);
+                self.concat_parts(b, out);
+            }
+            other => out.push(other),
+        }
+    }
+
+    // Evaluate `e`, emitting the statements it needs, left to right as the VM does
+    fn expr(&mut self, e: &Expr) -> Operand {
+        let vals = &self.f.values;
+        let ty = e.ty(vals);
+        match e {
+            Expr::Null => Operand::new("rt_null()", Ty::Dyn),
+            Expr::Bool(b) => Operand::new(b.to_string(), Ty::Bool),
+            Expr::Num(n) => Operand::new(num_lit(*n), Ty::Num),
+            Expr::Int(i) => Operand::new(int_lit(*i), Ty::Int),
+            Expr::Str(s) => Operand::new(self.strings.get(s), Ty::Str),
+            // Slots are copied so a FUNC call later in the statement can't change what was read
+            Expr::Var(v) => self.owned_temp(format!("rt_retain({})", place(*v)), Ty::Dyn),
+            Expr::Value(v) => Operand::new(format!("v{}", v), vals[*v]),
+            Expr::Neg(a) => {
+                let a = self.expr(a);
+                if a.ty.is_numeric() { Operand::new(format!("(-{})", Self::as_f64(&a)), Ty::Num) }
+                else { self.fallible(format!("rt_neg({})", Self::val(&a)), ty) }
+            }
+            Expr::Not(a) => {
+                let a = self.expr(a);
+                Operand::new(format!("(!{})", Self::truthy(&a)), Ty::Bool)
+            }
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                // The right side only runs when the left doesn't decide the result
+                let t = self.temp();
+                let mark = self.live.len();
+                let a = self.expr(a);
+                self.line(&format!("bool {} = {};", t, Self::truthy(&a)));
+                self.release_to(mark);
+                self.line(&format!("if ({}{}) {{", if matches!(e, Expr::And(..)) { "" } else { "!" }, t));
+                self.indent += 1;
+                let b = self.expr(b);
+                self.line(&format!("{} = {};", t, Self::truthy(&b)));
+                self.release_to(mark);
+                self.indent -= 1;
+                self.line("}");
+                Operand::new(t, Ty::Bool)
+            }
+            Expr::Binary(op, a, b) => {
+                if *op == BinOp::Add && ty == Ty::Str {
+                    let mut parts = Vec::new();
+                    self.concat_parts(e, &mut parts);
+                    let ops: Vec<Operand> = parts.into_iter().map(|p| self.expr(p)).collect();
+                    let list = ops.iter().map(Self::val).collect::<Vec<_>>().join(", ");
+                    return self.owned_temp(format!("rt_concat({}, (rt_val[]){{{}}})", ops.len(), list), Ty::Str);
+                }
+                let (a, b) = (self.expr(a), self.expr(b));
+                if a.ty.is_numeric() && b.ty.is_numeric() {
+                    let (x, y) = (Self::as_f64(&a), Self::as_f64(&b));
+                    let code = match op {
+                        BinOp::Mod => format!("fmod({}, {})", x, y),
+                        _ => {
+                            let sym = match op {
+                                BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/", BinOp::Mod => "%",
+                                BinOp::Eq => "==", BinOp::Ne => "!=", BinOp::Lt => "<", BinOp::Le => "<=", BinOp::Gt => ">", BinOp::Ge => ">=",
+                            };
+                            format!("({} {} {})", x, sym, y)
+                        }
+                    };
+                    return Operand::new(code, ty);
+                }
+                if a.ty == Ty::Str && b.ty == Ty::Str && matches!(op, BinOp::Eq | BinOp::Ne) {
+                    let not = if *op == BinOp::Eq { "" } else { "!" };
+                    return Operand::new(format!("{}rt_str_eq({}, {})", not, a.code, b.code), Ty::Bool);
+                }
+                let f = match op {
+                    BinOp::Add => "add", BinOp::Sub => "sub", BinOp::Mul => "mul", BinOp::Div => "div", BinOp::Mod => "modulo",
+                    BinOp::Eq => "eq", BinOp::Ne => "ne", BinOp::Lt => "lt", BinOp::Le => "le", BinOp::Gt => "gt", BinOp::Ge => "ge",
+                };
+                self.fallible(format!("rt_{}({}, {})", f, Self::val(&a), Self::val(&b)), ty)
+            }
+            Expr::ToInt(a) => {
+                let a = self.expr(a);
+                match a.ty {
+                    Ty::Int => a,
+                    Ty::Num => Operand::new(format!("rt_trunc({})", a.code), Ty::Int),
+                    _ => self.fallible(format!("rt_to_int({})", Self::val(&a)), Ty::Int),
+                }
+            }
+            Expr::Call { func, args } => {
+                let ops: Vec<Operand> = args.iter().map(|a| self.expr(a)).collect();
+                let mut call = format!("f{}(g", func);
+                for op in ops { let code = self.owned(op); let _ = write!(call, ", {}", code); }
+                call.push(')');
+                self.fallible(call, Ty::Dyn)
+            }
+            Expr::Builtin { name, args } => {
+                let f = c_builtin(name).unwrap_or_else(|| panic!("builtin {} was rejected by check", name));
+                let args = self.vals(args);
+                self.fallible(format!("{}({})", f, args), ty)
+            }
+            Expr::NewArray { elem, dims } => {
+                let elem = match elem {
+                    ElemKind::Num => "RT_ELEM_NUM",
+                    ElemKind::Int => "RT_ELEM_INT",
+                    ElemKind::Str => "RT_ELEM_STR",
+                    ElemKind::Obj(_) => panic!("object arrays are rejected by check"),
+                };
+                let dims = self.vals(dims);
+                self.fallible(format!("rt_new_array({}, {})", elem, dims), ty)
+            }
+            Expr::ArrayGet { array, indices } => {
+                let array = self.expr(array);
+                let indices = self.vals(indices);
+                self.fallible(format!("rt_array_get({}, {})", Self::val(&array), indices), ty)
+            }
+            Expr::List(items) => {
+                let items = self.vals(items);
+                self.owned_temp(format!("rt_list_new({})", items), Ty::Dyn)
+            }
+            Expr::Dict(entries) => {
+                if entries.is_empty() { return self.owned_temp("rt_dict_new(0, NULL, NULL)".to_string(), Ty::Dyn); }
+                let keys: Vec<String> = entries.iter().map(|(k, _)| self.strings.get(k)).collect();
+                let ops: Vec<Operand> = entries.iter().map(|(_, v)| self.expr(v)).collect();
+                let vs = ops.iter().map(Self::val).collect::<Vec<_>>().join(", ");
+                self.owned_temp(format!("rt_dict_new({}, (rt_val[]){{{}}}, (rt_val[]){{{}}})", ops.len(), keys.join(", "), vs), Ty::Dyn)
+            }
+            Expr::Index { target, index } => {
+                let (target, index) = (self.expr(target), self.expr(index));
+                self.fallible(format!("rt_index_get({}, {})", Self::val(&target), Self::val(&index)), ty)
+            }
+            Expr::Member { target, name } => {
+                let target = self.expr(target);
+                let name = self.strings.get(name);
+                self.fallible(format!("rt_prop_get({}, {})", Self::val(&target), name), ty)
+            }
+            Expr::Items(a) => {
+                let a = self.expr(a);
+                self.fallible(format!("rt_items({})", Self::val(&a)), ty)
+            }
+            Expr::Exception => {
+                self.uses_exc = true;
+                Operand::new("exc", Ty::Str)
+            }
+            Expr::MethodCall { .. } | Expr::NewObject { .. } => panic!("objects are rejected by check"),
+        }
+    }
+
+    fn instr(&mut self, ins: &Instr) {
+        let vals = &self.f.values;
+        match ins {
+            Instr::Line(n) => self.line(&format!("rt_line({});", n)),
+            Instr::Assign(v, e) => self.stmt(|s| {
+                let op = s.expr(e);
+                let code = s.owned(op);
+                s.line(&format!("rt_set(&{}, {});", place(*v), code));
+            }),
+            Instr::Def(v, e) => {
+                let to = vals[*v];
+                self.stmt(|s| {
+                    let op = s.expr(e);
+                    if native(to) {
+                        s.line(&format!("v{} = {};", v, coerce(&op.code, op.ty, to)));
+                    } else {
+                        let code = s.owned(op);
+                        s.line(&format!("rt_set(&v{}, {});", v, code));
+                    }
+                });
+            }
+            Instr::Print(e) => self.stmt(|s| {
+                let op = s.expr(e);
+                let f = match op.ty { Ty::Num => "rt_print_num", Ty::Int => "rt_print_int", Ty::Bool => "rt_print_bool", _ => "rt_print" };
+                s.line(&format!("{}({});", f, op.code));
+            }),
+            Instr::Eval(e) => self.stmt(|s| { s.expr(e); }),
+            Instr::ArraySet { array, indices, value } => self.stmt(|s| {
+                let array = s.expr(array);
+                let indices = s.vals(indices);
+                let value = s.expr(value);
+                s.line(&format!("rt_array_set({}, {}, {});", Self::val(&array), indices, Self::val(&value)));
+                s.check();
+            }),
+            Instr::IndexSet { target, index, value } => self.stmt(|s| {
+                let (target, index, value) = (s.expr(target), s.expr(index), s.expr(value));
+                s.line(&format!("rt_index_set({}, {}, {});", Self::val(&target), Self::val(&index), Self::val(&value)));
+                s.check();
+            }),
+            Instr::PropSet { target, name, value } => self.stmt(|s| {
+                let target = s.expr(target);
+                let name = s.strings.get(name);
+                let value = s.expr(value);
+                s.line(&format!("rt_prop_set({}, {}, {});", Self::val(&target), name, Self::val(&value)));
+                s.check();
+            }),
+            Instr::TryPush(h) => self.line(&format!("rt_push(&handlers, {});", h)),
+            Instr::TryPop => self.line("(void)rt_pop(&handlers);"),
+        }
+    }
+
+    // Jump from block `from` to `to`, first giving `to`'s phis the values that come from `from`.
+    // The copies read every source before writing any phi, as phis take their args all at once.
+    fn edge(&mut self, from: BlockId, to: BlockId) {
+        let vals = &self.f.values;
+        let copies: Vec<(ValueId, ValueId)> = self.f.blocks[to].phis.iter()
+            .filter_map(|p| p.args.iter().find(|(b, _)| *b == from).map(|(_, a)| (p.value, *a)))
+            .collect();
+        let read = |phi: ValueId, arg: ValueId| {
+            let code = coerce(&format!("v{}", arg), vals[arg], vals[phi]);
+            if native(vals[phi]) || native(vals[arg]) { code } else { format!("rt_retain({})", code) }
+        };
+        let write = |phi: ValueId, code: &str| {
+            if native(vals[phi]) { format!("v{} = {};", phi, code) } else { format!("rt_set(&v{}, {});", phi, code) }
+        };
+        let lines: Vec<String> = match copies[..] {
+            [] => Vec::new(),
+            [(phi, arg)] => vec![write(phi, &read(phi, arg))],
+            _ => {
+                let mut s = String::from("{ ");
+                for (i, &(phi, arg)) in copies.iter().enumerate() { let _ = write!(s, "{} p{} = {}; ", c_type(vals[phi]), i, read(phi, arg)); }
+                for (i, &(phi, _)) in copies.iter().enumerate() { let _ = write!(s, "{} ", write(phi, &format!("p{}", i))); }
+                s.push('}');
+                vec![s]
+            }
+        };
+        for l in lines { self.line(&l); }
+        self.line(&format!("goto b{};", to));
+    }
+
+    fn terminator(&mut self, id: BlockId) {
+        let f = self.f;
+        match &f.blocks[id].term {
+            Terminator::Jump(to) => self.edge(id, *to),
+            Terminator::Branch { cond, then_to, else_to } => self.stmt(|s| {
+                let op = s.expr(cond);
+                let mut cond = Self::truthy(&op);
+                if !s.live.is_empty() {
+                    let t = s.temp();
+                    s.line(&format!("bool {} = {};", t, cond));
+                    s.release_to(0);
+                    cond = t;
+                }
+                s.line(&format!("if ({}) {{", cond));
+                s.indent += 1;
+                s.edge(id, *then_to);
+                s.indent -= 1;
+                s.line("} else {");
+                s.indent += 1;
+                s.edge(id, *else_to);
+                s.indent -= 1;
+                s.line("}");
+            }),
+            Terminator::Return(e) => self.stmt(|s| {
+                let op = s.expr(e);
+                let code = s.owned(op);
+                s.line(&format!("rt_set(&result, {});", code));
+                s.release_to(0);
+                s.line("goto done;");
+            }),
+            Terminator::Gosub { target, ret } => {
+                self.line("if (gosubs.len >= 4096) { rt_error(\"GOSUB stack overflow (depth limit 4096)\"); goto fail; }");
+                self.line(&format!("rt_push(&gosubs, {});", ret));
+                self.edge(id, *target);
+            }
+            Terminator::GosubReturn => {
+                self.line("if (gosubs.len == 0) { rt_error(\"RETURN without GOSUB\"); goto fail; }");
+                self.line("bb = rt_pop(&gosubs);");
+                // A return block with phis takes this block's values on the way back
+                let arms: Vec<BlockId> = f.blocks.iter().enumerate()
+                    .filter(|(_, b)| b.phis.iter().any(|p| p.args.iter().any(|(from, _)| *from == id)))
+                    .map(|(r, _)| r).collect();
+                if arms.is_empty() {
+                    self.line("goto dispatch;");
+                } else {
+                    self.line("switch (bb) {");
+                    for r in arms {
+                        self.line(&format!("case {}:", r));
+                        self.indent += 1;
+                        self.edge(id, r);
+                        self.indent -= 1;
+                    }
+                    self.line("default: goto dispatch;");
+                    self.line("}");
+                }
+            }
+            Terminator::GosubReturnTo(to) => {
+                self.line("if (gosubs.len == 0) { rt_error(\"RETURN without GOSUB\"); goto fail; }");
+                self.line("(void)rt_pop(&gosubs);");
+                self.edge(id, *to);
+            }
+            Terminator::Raise(e) => self.stmt(|s| {
+                let op = s.expr(e);
+                s.line(&format!("rt_raise({});", Self::val(&op)));
+                s.release_to(0);
+                s.line("goto fail;");
+            }),
+            Terminator::Reraise => {
+                self.uses_exc = true;
+                self.uses_reraise = true;
+                self.line("rt_reraise(has_exc, exc);");
+                self.line("goto fail;");
+            }
+            // rt_exit only comes back if the code isn't a number
+            Terminator::Exit(e) => self.stmt(|s| {
+                let op = s.expr(e);
+                s.line(&format!("rt_exit({});", Self::val(&op)));
+                s.release_to(0);
+                s.line("goto fail;");
+            }),
+            Terminator::Halt => self.line("goto done;"),
+        }
+    }
+
+    fn render(mut self, c_name: &str, routine: bool) -> String {
+        let f = self.f;
+        // Blocks entered through the `switch`: TRY handlers and GOSUB return blocks
+        let mut dispatch: BTreeSet<BlockId> = BTreeSet::new();
+        let mut labels: BTreeSet<BlockId> = BTreeSet::new();
+        let (mut uses_handlers, mut uses_gosub) = (false, false);
+        for b in &f.blocks {
+            for i in &b.instrs {
+                if let Instr::TryPush(h) = i { dispatch.insert(*h); uses_handlers = true; }
+            }
+            match &b.term {
+                Terminator::Jump(t) | Terminator::GosubReturnTo(t) => { labels.insert(*t); }
+                Terminator::Branch { then_to, else_to, .. } => { labels.insert(*then_to); labels.insert(*else_to); }
+                Terminator::Gosub { target, ret } => { labels.insert(*target); dispatch.insert(*ret); }
+                _ => {}
+            }
+            uses_gosub |= matches!(b.term, Terminator::Gosub { .. } | Terminator::GosubReturn | Terminator::GosubReturnTo(_));
+        }
+        labels.extend(dispatch.iter().copied());
+
+        for id in 0..f.blocks.len() {
+            if labels.contains(&id) { let _ = writeln!(self.out, "b{}: ;", id); }
+            for ins in &f.blocks[id].instrs { self.instr(ins); }
+            self.terminator(id);
+        }
+        // Only the labels something jumps to, so the C compiles without warnings
+        if self.out.contains("goto fail;") {
+            self.out.push_str("fail:\n");
+            if uses_handlers {
+                self.uses_exc = true;
+                self.out.push_str("    if (rt_raised() && handlers.len > 0) {\n");
+                self.out.push_str("        rt_set(&exc, rt_take_error());\n");
+                if self.uses_reraise { self.out.push_str("        has_exc = true;\n"); }
+                self.out.push_str("        bb = handlers.items[handlers.len - 1];\n");
+                self.out.push_str("        goto dispatch;\n");
+                self.out.push_str("    }\n");
+            }
+        }
+        let uses_dispatch = self.out.contains("goto dispatch;");
+        if uses_dispatch {
+            self.out.push_str("    goto done;\ndispatch:\n    switch (bb) {\n");
+            for b in &dispatch { let _ = writeln!(self.out, "    case {}: goto b{};", b, b); }
+            self.out.push_str("    default: abort();\n    }\n");
+        }
+        if self.out.contains("goto done;") { self.out.push_str("done:\n"); }
+
+        let mut head = String::new();
+        let _ = writeln!(head, "\n/* {} */\n{} {{", comment(&f.name), signature(c_name, f));
+        for l in f.params..f.locals.len() { let _ = writeln!(head, "    rt_val l{} = rt_null();", l); }
+        let mut defined: Vec<ValueId> = f.blocks.iter()
+            .flat_map(|b| b.phis.iter().map(|p| p.value).chain(b.instrs.iter().filter_map(|i| match i { Instr::Def(v, _) => Some(*v), _ => None })))
+            .collect();
+        defined.sort();
+        for &v in &defined {
+            let init = match f.values[v] { Ty::Int | Ty::Num => "0", Ty::Bool => "false", _ => "rt_null()" };
+            let _ = writeln!(head, "    {} v{} = {};", c_type(f.values[v]), v, init);
+        }
+        head.push_str("    rt_val result = rt_null();\n");
+        head.push_str("    (void)g;\n");
+        if uses_dispatch || uses_gosub { head.push_str("    int bb = 0;\n"); }
+        if uses_handlers { head.push_str("    rt_stack handlers = {0, 0, NULL};\n"); }
+        if uses_gosub { head.push_str("    rt_stack gosubs = {0, 0, NULL};\n"); }
+        if self.uses_exc {
+            let empty = self.strings.get("");
+            let _ = writeln!(head, "    rt_val exc = {};", empty);
+        }
+        if self.uses_reraise { head.push_str("    bool has_exc = false;\n"); }
+        if routine { head.push_str("    rt_enter();\n"); }
+
+        let mut tail = String::new();
+        for l in 0..f.locals.len() { let _ = writeln!(tail, "    rt_release(l{});", l); }
+        for &v in &defined {
+            if !native(f.values[v]) { let _ = writeln!(tail, "    rt_release(v{});", v); }
+        }
+        if self.uses_exc { tail.push_str("    rt_release(exc);\n"); }
+        if uses_handlers { tail.push_str("    rt_stack_free(&handlers);\n"); }
+        if uses_gosub { tail.push_str("    rt_stack_free(&gosubs);\n"); }
+        if routine { tail.push_str("    rt_leave();\n"); }
+        tail.push_str("    return result;\n}\n");
+        format!("{}{}{}", head, self.out, tail)
+    }
+}
@@ -1,9 +1,10 @@
-//! Rust backend emitter for bcc: turns a tiny IR into a Cargo project.
+//! Rust backend emitter for bcc: turns the IR into a Cargo project linked against libbasilrt,
+//! or wraps compiled bytecode in a Cargo project that runs it on the embedded VM (`bcc bundle`).
 
 use std::{fs, path::{Path, PathBuf}};
 use sha2::{Digest, Sha256};
-use basil_ir::{Module, Instr, Expr};
-use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use basil_ir::{BinOp, BlockId, ElemKind, Expr, Function, Instr, Module, Terminator, Ty, ValueId, Var};
 
 #[derive(Debug, Clone)]
 pub enum DepSource {
@@ -33,6 +34,12 @@ pub struct BuildOptions {
     pub pinned_version: String,
 }
 
+/// A WASI target such as `wasm32-wasip1`. Its std can only abort on panic, and the program
+/// runs in a sandbox with stdio and preopened directories but no terminal, devices or sockets.
+pub fn is_wasi(target: Option<&str>) -> bool {
+    target.is_some_and(|t| t.starts_with("wasm32-wasi"))
+}
+
 pub fn compute_hash_key(src: &str, opts: &BuildOptions) -> String {
     let mut hasher = Sha256::new();
     hasher.update(src.as_bytes());
@@ -75,43 +82,84 @@ pub fn emit_project(base_dir: &Path, src_path: &Path, module: &Module, opts: &Bu
     let main_body = render_main_rs(src_path, module);
     fs::write(&main_rs, main_body)?;
 
-    // If vendor mode: copy vendor dir and write .cargo/config.toml
-    if let DepSource::Vendor(vendor_src) = &opts.dep_source {
-        let vend_dst = root.join("vendor");
-        copy_dir_all(vendor_src, &vend_dst)?;
-        let cargo_cfg_dir = root.join(".cargo");
-        fs::create_dir_all(&cargo_cfg_dir)?;
-        let cfg = "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n[source.vendored-sources]\ndirectory = \"vendor\"\n";
-        fs::write(cargo_cfg_dir.join("config.toml"), cfg)?;
-    }
-
+    write_vendor_config(&root, opts)?;
     Ok(EmittedProject { root, main_rs, cargo_toml })
 }
 
-fn render_cargo_toml(opts: &BuildOptions) -> String {
-    let name = opts.name.clone().unwrap_or_else(|| "basil_prog".into());
-    let opt = opts.opt_level.unwrap_or(3);
-    let lto = opts.lto.clone().unwrap_or_else(|| "thin".into());
+/// Emit a Cargo project whose executable runs `program` (a serialized bytecode `Program`) on
+/// basil-vm, with `files` (name, contents) readable through READFILE$, FOPEN and CLASS().
+/// `opts.features` are runtime feature names; each turns on the VM's matching `obj-*` feature.
+pub fn emit_bundle(base_dir: &Path, src_path: &Path, program: &[u8], files: &[(String, Vec<u8>)], opts: &BuildOptions) -> std::io::Result<EmittedProject> {
+    let mut hasher = Sha256::new();
+    hasher.update(program);
+    for (name, data) in files { hasher.update(name.as_bytes()); hasher.update(b"\0"); hasher.update(data); }
+    let key = format!("bundle\n{}\n{}", src_path.display(), hex::encode(hasher.finalize()));
+    let hash = compute_hash_key(&key, opts);
+    let root = if let Some(ref dir) = opts.emit_project_dir { dir.clone() } else { base_dir.join(".basil").join("targets").join(&hash) };
 
-    let mut features_list = String::new();
-    for (i, f) in opts.features.iter().enumerate() {
-        if i > 0 { features_list.push_str(", "); }
-        features_list.push('"'); features_list.push_str(f); features_list.push('"');
+    let src_dir = root.join("src");
+    let embed_dir = src_dir.join("embed");
+    fs::create_dir_all(&embed_dir)?;
+
+    let cargo_toml = root.join("Cargo.toml");
+    let vm_features = quoted_list(opts.features.iter().map(|f| format!("obj-{}", f)));
+    let deps = [
+        runtime_dep(opts, "basil-vm", &["basilcore", "vm"], &vm_features),
+        runtime_dep(opts, "basil-bytecode", &["basilcore", "bytecode"], ""),
+        runtime_dep(opts, "basil-builtins", &["basilcore", "builtins"], ""),
+    ].join("\n");
+    // The VM turns a panicking SPAWN task into an error, which needs unwinding; WASI has no
+    // threads to spawn, and its std only aborts
+    let panic = if is_wasi(opts.target.as_deref()) { "abort" } else { "unwind" };
+    fs::write(&cargo_toml, cargo_toml_with(opts, &deps, panic))?;
+
+    fs::write(src_dir.join("program.basilx"), program)?;
+    let mut table = String::new();
+    for (i, (name, data)) in files.iter().enumerate() {
+        fs::write(embed_dir.join(format!("{}.bin", i)), data)?;
+        let _ = writeln!(table, "    ({:?}, include_bytes!(\"embed/{}.bin\")),", name, i);
     }
+    let main_rs = src_dir.join("main.rs");
+    fs::write(&main_rs, render_bundle_main_rs(src_path, &table))?;
 
-    let mut obj_lines = String::new();
-    for c in &opts.obj_crates {
-        obj_lines.push_str(&format!("# {c} = \"={}\"\n", opts.pinned_version));
+    write_vendor_config(&root, opts)?;
+    Ok(EmittedProject { root, main_rs, cargo_toml })
+}
+
+// Vendor mode: point Cargo at the vendor dir. A project emitted for elsewhere (--emit-project)
+// gets its own copy; one bcc builds right away uses the vendor dir where it is.
+fn write_vendor_config(root: &Path, opts: &BuildOptions) -> std::io::Result<()> {
+    if let DepSource::Vendor(vendor_src) = &opts.dep_source {
+        let directory = if opts.emit_project_dir.is_some() {
+            copy_dir_all(vendor_src, &root.join("vendor"))?;
+            "vendor".to_string()
+        } else {
+            let abs = vendor_src.canonicalize()?.to_string_lossy().to_string();
+            abs.strip_prefix("\\\\?\\").unwrap_or(&abs).replace('\\', "/")
+        };
+        let cargo_cfg_dir = root.join(".cargo");
+        fs::create_dir_all(&cargo_cfg_dir)?;
+        let cfg = format!("[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n[source.vendored-sources]\ndirectory = \"{}\"\n", directory);
+        fs::write(cargo_cfg_dir.join("config.toml"), cfg)?;
     }
+    Ok(())
+}
 
-    let lib_dep = match &opts.dep_source {
+fn quoted_list<I: Iterator<Item = String>>(items: I) -> String {
+    items.map(|f| format!("\"{}\"", f)).collect::<Vec<_>>().join(", ")
+}
+
+// A dependency on one of the runtime crates: pinned from crates.io (or the vendor dir), or by
+// path into the local repo, where `rel` is the crate's directory under the repo root
+fn runtime_dep(opts: &BuildOptions, name: &str, rel: &[&str], features: &str) -> String {
+    match &opts.dep_source {
         DepSource::CratesIo | DepSource::Vendor(_) => {
-            format!("libbasilrt = {{ version = \"={}\", features = [ {} ] }}", opts.pinned_version, features_list)
+            format!("{} = {{ version = \"={}\", features = [ {} ] }}", name, opts.pinned_version, features)
         }
         DepSource::LocalPath(root) => {
             // Use absolute, sanitized path for Cargo on Windows and Unix
             let abs_root = root.canonicalize().unwrap_or(root.clone());
-            let mut p = abs_root.join("crates").join("libbasilrt").to_string_lossy().to_string();
+            let mut p = rel.iter().fold(abs_root, |p, part| p.join(part)).to_string_lossy().to_string();
             if cfg!(windows) {
                 if let Some(stripped) = p.strip_prefix("\\\\?\\") { p = stripped.to_string(); }
                 if let Some(stripped) = p.strip_prefix("//?/") { p = stripped.to_string(); }
@@ -119,9 +167,24 @@ fn render_cargo_toml(opts: &BuildOptions) -> String {
             } else {
                 p = p.replace('\\', "/");
             }
-            format!("libbasilrt = {{ path = \"{}\", features = [ {} ] }}", p, features_list)
+            format!("{} = {{ path = \"{}\", features = [ {} ] }}", name, p, features)
         }
-    };
+    }
+}
+
+fn render_cargo_toml(opts: &BuildOptions) -> String {
+    let mut obj_lines = String::new();
+    for c in &opts.obj_crates {
+        obj_lines.push_str(&format!("# {c} = \"={}\"\n", opts.pinned_version));
+    }
+    let lib_dep = runtime_dep(opts, "libbasilrt", &["crates", "libbasilrt"], &quoted_list(opts.features.iter().cloned()));
+    cargo_toml_with(opts, &format!("{}\n{}", lib_dep, obj_lines), "abort")
+}
+
+fn cargo_toml_with(opts: &BuildOptions, deps: &str, panic: &str) -> String {
+    let name = opts.name.clone().unwrap_or_else(|| "basil_prog".into());
+    let opt = opts.opt_level.unwrap_or(3);
+    let lto = opts.lto.clone().unwrap_or_else(|| "thin".into());
 
     format!(r#"
 [package]
@@ -130,14 +193,13 @@ version = "0.1.0"
 edition = "2021"
 
 [dependencies]
-{lib_dep}
-{obj_lines}
+{deps}
 
 [profile.release]
 opt-level = {opt}
 lto = "{lto}"
 codegen-units = 1
-panic = "abort"
+panic = "{panic}"
 
 # Make this a workspace root to avoid inheriting an ancestor workspace
 [workspace]
@@ -164,207 +226,349 @@ fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
 }
 
 fn render_main_rs(src_path: &Path, module: &Module) -> String {
-    // Render body by walking instructions with a variable mapping context
-    let mut body = String::new();
-    let mut vars = HashMap::new();
-    let mut declared: HashSet<String> = HashSet::new();
-    // Pre-scan assignments to decide which variables require mut
-    let mut counts: HashMap<String, usize> = HashMap::new();
-    collect_assign_counts(&module.main.body, &mut counts);
-    let mutated: HashSet<String> = counts.into_iter().filter(|(_, c)| *c > 1).map(|(v, _)| v).collect();
-    render_instrs(&module.main.body, &mutated, &mut vars, &mut declared, &mut body, 0);
-
-    format!(r#"#![allow(unused_variables, unused_assignments)]
+    let mut out = String::new();
+    for (i, f) in module.functions.iter().enumerate() {
+        render_function(&mut out, &format!("f{}", i), f, true);
+    }
+    render_function(&mut out, "basil_main", &module.main, false);
+
+    format!(r#"#![allow(unused_mut, unused_variables, unused_assignments, unreachable_code, unused_parens)]
 
 // AUTOGENERATED by bcc — DO NOT EDIT
 // Source: {src}
 
 use libbasilrt as rt;
+use rt::Val;
+{out}
+fn main() {{
+    let mut g: Vec<Val> = vec![Val::Null; {nglobals}];
+    if let Err(e) = basil_main(&mut g) {{ rt::fail(e); }}
+}}
+"#, src = src_path.display(), nglobals = module.globals.len())
+}
 
-#[inline(always)]
-#[allow(dead_code)]
-fn make_val_str(s: String) -> rt::Val {{ rt::Val::Str(rt::Str::from_string(s)) }}
+fn render_bundle_main_rs(src_path: &Path, table: &str) -> String {
+    format!(r#"// AUTOGENERATED by bcc bundle — DO NOT EDIT
+// Source: {src}
 
-fn basil_main() -> rt::RtResult<()> {{
-{body}
-    Ok(())
-}}
+static PROGRAM: &[u8] = include_bytes!("program.basilx");
+static FILES: &[(&str, &[u8])] = &[
+{table}];
 
 fn main() {{
-    if let Err(e) = basil_main() {{
-        eprintln!("{{}}", e);
+    basil_builtins::files::embed(FILES);
+    let program = match basil_bytecode::deserialize_program(PROGRAM) {{
+        Ok(p) => p,
+        Err(e) => {{ eprintln!("error: bad embedded program: {{}}", e); std::process::exit(1); }}
+    }};
+    let mut vm = basil_vm::VM::new(program);
+    if let Err(e) = vm.run() {{
+        let line = vm.current_line();
+        if line > 0 {{ eprintln!("runtime error at line {{}}: {{}}", line, e); }}
+        else {{ eprintln!("runtime error: {{}}", e); }}
         std::process::exit(1);
     }}
 }}
-"#, src = src_path.display(), body = body)
+"#, src = src_path.display())
 }
 
-fn collect_assign_counts(instrs: &Vec<Instr>, counts: &mut HashMap<String, usize>) {
-    // Count assignments using a canonical, case-insensitive variable key so that
-    // Basil variables like "A%" and "a%" are treated as the same variable.
-    let canon = |s: &str| -> String { s.to_ascii_lowercase() };
-    for ins in instrs {
-        match ins {
-            Instr::Assign { var, .. } => {
-                let key = canon(var);
-                let e = counts.entry(key).or_insert(0);
-                *e += 1;
-            }
-            Instr::If { then_body, else_body, .. } => {
-                collect_assign_counts(then_body, counts);
-                collect_assign_counts(else_body, counts);
-            }
-            Instr::For { body, .. } => {
-                collect_assign_counts(body, counts);
-            }
-            _ => {}
-        }
+// Every function runs its blocks through a dispatcher: a block returns the next block (or the
+// function's result), and a RAISE that escapes one continues at the innermost TRY handler.
+// Routines hold a frame guard so the files they open are closed when they return. SSA values
+// are Rust locals of their own type, so typed arithmetic is plain `f64`/`i64` code.
+fn render_function(out: &mut String, rust_name: &str, f: &Function, routine: bool) {
+    let _ = writeln!(out);
+    let _ = write!(out, "// {}\nfn {}(g: &mut [Val]", f.name, rust_name);
+    for p in 0..f.params { let _ = write!(out, ", mut l{}: Val", p); }
+    let _ = writeln!(out, ") -> rt::RtResult<Val> {{");
+    if routine { out.push_str("    let _frame = rt::enter();\n"); }
+    for l in f.params..f.locals.len() { let _ = writeln!(out, "    let mut l{}: Val = Val::Null;", l); }
+    let mut defined: Vec<ValueId> = f.blocks.iter()
+        .flat_map(|b| b.phis.iter().map(|p| p.value).chain(b.instrs.iter().filter_map(|i| match i { Instr::Def(v, _) => Some(*v), _ => None })))
+        .collect();
+    defined.sort();
+    for v in defined {
+        let (ty, init) = match f.values[v] {
+            Ty::Int => ("i64", "0"),
+            Ty::Num => ("f64", "0.0"),
+            Ty::Bool => ("bool", "false"),
+            Ty::Str => ("String", "String::new()"),
+            _ => ("Val", "Val::Null"),
+        };
+        let _ = writeln!(out, "    let mut v{}: {} = {};", v, ty, init);
+    }
+    out.push_str("    let mut bb: usize = 0;\n");
+    out.push_str("    let mut handlers: Vec<usize> = Vec::new();\n");
+    out.push_str("    let mut gosubs: Vec<usize> = Vec::new();\n");
+    out.push_str("    let mut exc: Option<String> = None;\n");
+    out.push_str("    loop {\n");
+    out.push_str("        let step = (|| -> rt::RtResult<rt::Flow> {\n");
+    out.push_str("            match bb {\n");
+    for id in 0..f.blocks.len() {
+        let _ = writeln!(out, "                {} => {{", id);
+        render_block(out, f, id);
+        out.push_str("                }\n");
     }
+    out.push_str("                _ => unreachable!(\"bad block\"),\n");
+    out.push_str("            }\n");
+    out.push_str("        })();\n");
+    out.push_str("        match step {\n");
+    out.push_str("            Ok(rt::Flow::Goto(next)) => bb = next,\n");
+    out.push_str("            Ok(rt::Flow::Return(v)) => return Ok(v),\n");
+    out.push_str("            Err(e) if e.is_raise() && !handlers.is_empty() => {\n");
+    out.push_str("                exc = Some(e.message().to_string());\n");
+    out.push_str("                bb = handlers[handlers.len() - 1];\n");
+    out.push_str("            }\n");
+    out.push_str("            Err(e) => return Err(e),\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
 }
 
-fn render_instrs(instrs: &Vec<Instr>, mutated: &HashSet<String>, vars: &mut HashMap<String, String>, declared: &mut HashSet<String>, out: &mut String, mut indent: usize) {
-    // vars: maps canonical (lowercased) Basil var name -> sanitized Rust ident
-    let ind = |n: usize| -> String { " ".repeat(n * 4) };
-    let canon = |s: &str| -> String { s.to_ascii_lowercase() };
-    for ins in instrs {
-        match ins {
-            Instr::Print(e) => {
-                let s_code = render_expr_as_string(e, vars);
-                out.push_str(&format!("{}rt::print(&make_val_str({}))?;\n", ind(indent), s_code));
-            }
-            Instr::For { var, start, end, step, body } => {
-                let rust_var = sanitize_var(var);
-                let start_c = render_expr_as_i64(start, vars);
-                let end_c = render_expr_as_i64(end, vars);
-                let step_c = step.as_ref().map(|e| render_expr_as_i64(e, vars)).unwrap_or_else(|| "1".to_string());
-                out.push_str(&format!("{}{{\n", ind(indent)));
-                indent += 1;
-                out.push_str(&format!("{}let mut {}: i64 = {};\n", ind(indent), rust_var, start_c));
-                out.push_str(&format!("{}let end_: i64 = {};\n", ind(indent), end_c));
-                out.push_str(&format!("{}let step_: i64 = {};\n", ind(indent), step_c));
-                out.push_str(&format!("{}while if step_ >= 0 {{ {} <= end_ }} else {{ {} >= end_ }} {{\n", ind(indent), rust_var, rust_var));
-                indent += 1;
-                // extend var map with canonical key for the loop var (do not add to declared set)
-                let key = canon(var);
-                let prev = vars.insert(key.clone(), rust_var.clone());
-                render_instrs(body, mutated, vars, declared, out, indent);
-                // restore
-                if let Some(prev_name) = prev { vars.insert(key, prev_name); } else { vars.remove(&canon(var)); }
-                out.push_str(&format!("{}{} = {}.saturating_add(step_);\n", ind(indent), rust_var, rust_var));
-                indent -= 1;
-                out.push_str(&format!("{}}}\n", ind(indent)));
-                indent -= 1;
-                out.push_str(&format!("{}}}\n", ind(indent)));
-            }
-            Instr::Assign { var, expr } => {
-                let rust_var = sanitize_var(var);
-                let val = render_expr_as_i64(expr, vars);
-                let key = canon(var);
-                if declared.contains(&rust_var) || vars.contains_key(&key) {
-                    out.push_str(&format!("{}{} = {};\n", ind(indent), rust_var, val));
-                } else {
-                    vars.insert(key.clone(), rust_var.clone());
-                    declared.insert(rust_var.clone());
-                    let mut_kw = if mutated.contains(&key) { "mut " } else { "" };
-                    out.push_str(&format!("{}let {}{}: i64 = {};\n", ind(indent), mut_kw, rust_var, val));
-                }
-            }
-            Instr::If { cond, then_body, else_body } => {
-                let c = render_expr_as_bool(cond, vars);
-                out.push_str(&format!("{}if {} {{\n", ind(indent), c));
-                indent += 1;
-                render_instrs(then_body, mutated, vars, declared, out, indent);
-                indent -= 1;
-                if !else_body.is_empty() {
-                    out.push_str(&format!("{}}} else {{\n", ind(indent)));
-                    indent += 1;
-                    render_instrs(else_body, mutated, vars, declared, out, indent);
-                    indent -= 1;
+fn render_block(out: &mut String, f: &Function, id: BlockId) {
+    let ind = "                    ";
+    let b = &f.blocks[id];
+    let vals = &f.values;
+    for ins in &b.instrs {
+        let line = match ins {
+            Instr::Line(n) => format!("rt::line({});", n),
+            Instr::Assign(v, e) => format!("{{ let v = {}; {} = v; }}", boxed(e, vals), place(*v)),
+            Instr::Def(v, e) => format!("v{} = {};", v, coerce(expr(e, vals), e.ty(vals), vals[*v])),
+            Instr::Print(e) => format!("rt::print(&{})?;", operand(e, vals)),
+            Instr::Eval(e) => format!("let _ = {};", expr(e, vals)),
+            Instr::ArraySet { array, indices, value } => format!("rt::array_set(&{}, &[{}], {})?;", val_ref(array, vals), boxed_list(indices, vals), boxed(value, vals)),
+            Instr::IndexSet { target, index, value } => format!("rt::index_set(&{}, &{}, {})?;", val_ref(target, vals), val_ref(index, vals), boxed(value, vals)),
+            Instr::PropSet { target, name, value } => format!("rt::prop_set(&{}, {:?}, {})?;", val_ref(target, vals), name, boxed(value, vals)),
+            Instr::TryPush(h) => format!("handlers.push({});", h),
+            Instr::TryPop => "handlers.pop();".to_string(),
+        };
+        let _ = writeln!(out, "{}{}", ind, line);
+    }
+    let goto = |to: BlockId| edge(f, id, to);
+    let term = match &b.term {
+        Terminator::Jump(to) => goto(*to),
+        Terminator::Branch { cond, then_to, else_to } => format!("if {} {{ {} }} else {{ {} }}", truthy(cond, vals), goto(*then_to), goto(*else_to)),
+        Terminator::Return(e) => format!("Ok(rt::Flow::Return({}))", boxed(e, vals)),
+        Terminator::Gosub { target, ret } => format!("if gosubs.len() >= 4096 {{ return Err(rt::RtError::new(\"GOSUB stack overflow (depth limit 4096)\")); }} gosubs.push({}); {}", ret, goto(*target)),
+        Terminator::GosubReturn => {
+            // A return block with phis takes this block's values on the way back
+            let mut arms = String::new();
+            for (r, block) in f.blocks.iter().enumerate() {
+                if block.phis.iter().any(|p| p.args.iter().any(|(from, _)| *from == id)) {
+                    let _ = write!(arms, "{} => {{ {} }} ", r, goto(r));
                 }
-                out.push_str(&format!("{}}}\n", ind(indent)));
             }
-            Instr::ExprStmt(e) => {
-                // Evaluate for side effects if it's a known call; otherwise ignore
-                if let Expr::Call(name, args) = e {
-                    if name.as_str() == "AUDIO_PLAY%" {
-                        let a0 = render_expr_as_string(&args[0], vars);
-                        let a1 = render_expr_as_string(&args[1], vars);
-                        out.push_str(&format!("{}let _ = rt::features::daw::audio_play(&{}, &{});\n", ind(indent), a0, a1));
-                    } else if name.as_str() == "DAW_STOP" {
-                        out.push_str(&format!("{}rt::features::daw::stop();\n", ind(indent)));
-                    } else if name.as_str() == "DAW_RESET" {
-                        out.push_str(&format!("{}rt::features::daw::reset();\n", ind(indent)));
-                    }
-                }
+            if arms.is_empty() {
+                "match gosubs.pop() { Some(ret) => Ok(rt::Flow::Goto(ret)), None => Err(rt::RtError::new(\"RETURN without GOSUB\")) }".to_string()
+            } else {
+                format!("match gosubs.pop() {{ Some(ret) => match ret {{ {}_ => Ok(rt::Flow::Goto(ret)) }}, None => Err(rt::RtError::new(\"RETURN without GOSUB\")) }}", arms)
             }
         }
-    }
+        Terminator::GosubReturnTo(to) => format!("if gosubs.pop().is_none() {{ return Err(rt::RtError::new(\"RETURN without GOSUB\")); }} {}", goto(*to)),
+        Terminator::Raise(e) => format!("Err(rt::raise(&{}))", val_ref(e, vals)),
+        Terminator::Reraise => "Err(rt::reraise(&exc))".to_string(),
+        Terminator::Exit(e) => format!("rt::exit(&{})", val_ref(e, vals)),
+        Terminator::Halt => "Ok(rt::Flow::Return(Val::Null))".to_string(),
+    };
+    let _ = writeln!(out, "{}{}", ind, term);
 }
 
-fn sanitize_var(name: &str) -> String {
+// Jump from block `from` to `to`, first giving `to`'s phis the values that come from `from`.
+// The copies read every source before writing any phi, as phis take their args all at once.
+fn edge(f: &Function, from: BlockId, to: BlockId) -> String {
+    let vals = &f.values;
+    let copies: Vec<(ValueId, ValueId)> = f.blocks[to].phis.iter()
+        .filter_map(|p| p.args.iter().find(|(b, _)| *b == from).map(|(_, a)| (p.value, *a)))
+        .collect();
     let mut s = String::new();
-    for ch in name.chars() {
-        if ch.is_ascii_alphanumeric() || ch == '_' { s.push(ch.to_ascii_lowercase()); }
-        else if ch == '%' || ch == '$' { s.push_str("_v"); }
-        else { s.push('_'); }
+    match copies[..] {
+        [] => {}
+        [(phi, arg)] => { let _ = write!(s, "v{} = {}; ", phi, coerce(expr(&Expr::Value(arg), vals), vals[arg], vals[phi])); }
+        _ => {
+            for (i, (phi, arg)) in copies.iter().enumerate() {
+                let _ = write!(s, "let t{} = {}; ", i, coerce(expr(&Expr::Value(*arg), vals), vals[*arg], vals[*phi]));
+            }
+            for (i, (phi, _)) in copies.iter().enumerate() { let _ = write!(s, "v{} = t{}; ", phi, i); }
+        }
     }
-    if s.is_empty() { s.push_str("v"); }
-    if s.chars().next().unwrap().is_ascii_digit() { s.insert(0, '_'); }
+    let _ = write!(s, "Ok(rt::Flow::Goto({}))", to);
     s
 }
 
-fn render_expr_as_string(e: &Expr, vars: &HashMap<String, String>) -> String {
-    let canon = |s: &str| -> String { s.to_ascii_lowercase() };
+fn place(v: Var) -> String {
+    match v { Var::Local(i) => format!("l{}", i), Var::Global(i) => format!("g[{}]", i) }
+}
+
+fn str_lit(s: &str) -> String { format!("String::from({:?})", s) }
+
+fn num_lit(n: f64) -> String {
+    if n.is_nan() { "f64::NAN".into() }
+    else if n.is_infinite() { if n > 0.0 { "f64::INFINITY".into() } else { "f64::NEG_INFINITY".into() } }
+    else { format!("{:?}f64", n) }
+}
+
+// Types held in a Rust local of their own; the rest are `Val`
+fn native(t: Ty) -> bool { matches!(t, Ty::Int | Ty::Num | Ty::Bool | Ty::Str) }
+
+fn box_code(code: String, t: Ty) -> String {
+    match t {
+        Ty::Int => format!("Val::Int({})", code),
+        Ty::Num => format!("Val::Num({})", code),
+        Ty::Bool => format!("Val::Bool({})", code),
+        Ty::Str => format!("Val::Str({})", code),
+        _ => code,
+    }
+}
+
+fn unbox_code(code: String, t: Ty) -> String {
+    match t {
+        Ty::Int => format!("rt::unbox_int({})", code),
+        Ty::Num => format!("rt::unbox_num({})", code),
+        Ty::Bool => format!("rt::unbox_bool({})", code),
+        Ty::Str => format!("rt::unbox_str({})", code),
+        _ => code,
+    }
+}
+
+// `code` of type `from` as a `to`: the same, boxed into a `Val`, or a `Val` the IR proved to be a `to`
+fn coerce(code: String, from: Ty, to: Ty) -> String {
+    if from == to || (!native(from) && !native(to)) { return code; }
+    let val = box_code(code, from);
+    if native(to) { unbox_code(val, to) } else { val }
+}
+
+// The expression as an owned `Val`
+fn boxed(e: &Expr, vals: &[Ty]) -> String { box_code(expr(e, vals), e.ty(vals)) }
+
+fn boxed_list(es: &[Expr], vals: &[Ty]) -> String {
+    es.iter().map(|e| boxed(e, vals)).collect::<Vec<_>>().join(", ")
+}
+
+// Something to borrow: an SSA value is used in place instead of being cloned, and a string
+// literal stays a `&str`
+fn operand(e: &Expr, vals: &[Ty]) -> String {
     match e {
-        Expr::Str(s) => format!("\"{}\".to_string()", escape_str(s)),
-        Expr::Int(i) => format!("{}.to_string()", i),
-        Expr::Bool(b) => format!("{}.to_string()", b),
-        Expr::Var(name) => {
-            let v = vars.get(&canon(name)).cloned().unwrap_or_else(|| sanitize_var(name));
-            format!("{}.to_string()", v)
-        }
-        Expr::Add(a, b) => {
-            let ac = render_expr_as_string(a, vars);
-            let bc = render_expr_as_string(b, vars);
-            format!("{{ let mut s = {}; s.push_str(&{}); s }}", ac, bc)
-        }
-        Expr::Call(name, _args) if name.as_str() == "DAW_ERR$" => {
-            "rt::features::daw::get_err()".to_string()
-        }
-        _ => "String::new()".to_string(),
+        Expr::Value(v) => format!("v{}", v),
+        Expr::Str(s) => format!("{:?}", s),
+        other => expr(other, vals),
+    }
+}
+
+// A `Val` to borrow
+fn val_ref(e: &Expr, vals: &[Ty]) -> String {
+    if native(e.ty(vals)) { boxed(e, vals) } else { operand(e, vals) }
+}
+
+// A numeric expression as `f64`, the way the runtime widens Int and Bool
+fn as_f64(e: &Expr, vals: &[Ty]) -> String {
+    match e {
+        Expr::Int(i) => return num_lit(*i as f64),
+        Expr::Bool(b) => return num_lit(if *b { 1.0 } else { 0.0 }),
+        _ => {}
+    }
+    match e.ty(vals) {
+        Ty::Int => format!("({} as f64)", operand(e, vals)),
+        Ty::Bool => format!("(if {} {{ 1.0 }} else {{ 0.0 }})", operand(e, vals)),
+        _ => operand(e, vals),
+    }
+}
+
+fn truthy(e: &Expr, vals: &[Ty]) -> String {
+    match e.ty(vals) {
+        Ty::Bool => operand(e, vals),
+        Ty::Num => format!("({} != 0.0)", operand(e, vals)),
+        Ty::Int => format!("({} != 0)", operand(e, vals)),
+        Ty::Str => format!("!{}.is_empty()", operand(e, vals)),
+        _ => format!("rt::truthy(&{})", val_ref(e, vals)),
     }
 }
 
-fn render_expr_as_bool(e: &Expr, vars: &HashMap<String, String>) -> String {
+// The pieces of a chain of string concatenations, left to right
+fn concat_parts(e: &Expr, vals: &[Ty], out: &mut Vec<String>) {
     match e {
-        Expr::Ne(a, b) => {
-            let ac = render_expr_as_i64(a, vars);
-            let bc = render_expr_as_i64(b, vars);
-            format!("{} != {}", ac, bc)
+        Expr::Binary(BinOp::Add, a, b) if e.ty(vals) == Ty::Str => {
+            concat_parts(a, vals, out);
+            concat_parts(b, vals, out);
         }
-        _ => "false".to_string(),
+        other => out.push(operand(other, vals)),
     }
 }
 
-fn render_expr_as_i64(e: &Expr, vars: &HashMap<String, String>) -> String {
-    let canon = |s: &str| -> String { s.to_ascii_lowercase() };
+// Every expression renders to an owned value of its static type (`Expr::ty`). Slots are cloned
+// rather than borrowed so a FUNC call later in the same expression can take `g` mutably.
+fn expr(e: &Expr, vals: &[Ty]) -> String {
+    let ty = e.ty(vals);
     match e {
-        Expr::Int(i) => format!("{}", i),
-        Expr::Var(name) => vars.get(&canon(name)).cloned().unwrap_or_else(|| sanitize_var(name)),
-        Expr::Call(name, args) if name.as_str() == "AUDIO_PLAY%" => {
-            let a0 = render_expr_as_string(&args[0], vars);
-            let a1 = render_expr_as_string(&args[1], vars);
-            format!("rt::features::daw::audio_play(&{}, &{})", a0, a1)
+        Expr::Null => "Val::Null".into(),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Num(n) => num_lit(*n),
+        Expr::Int(i) => format!("{}i64", i),
+        Expr::Str(s) => str_lit(s),
+        Expr::Var(v) => format!("{}.clone()", place(*v)),
+        Expr::Value(v) => if matches!(vals[*v], Ty::Int | Ty::Num | Ty::Bool) { format!("v{}", v) } else { format!("v{}.clone()", v) },
+        Expr::Neg(a) if a.ty(vals).is_numeric() => format!("(-{})", as_f64(a, vals)),
+        Expr::Neg(a) => unbox_code(format!("rt::neg(&{})?", val_ref(a, vals)), ty),
+        Expr::Not(a) => format!("(!{})", truthy(a, vals)),
+        Expr::And(a, b) => format!("({} && {})", truthy(a, vals), truthy(b, vals)),
+        Expr::Or(a, b) => format!("({} || {})", truthy(a, vals), truthy(b, vals)),
+        Expr::Binary(op, a, b) => {
+            let (ta, tb) = (a.ty(vals), b.ty(vals));
+            if *op == BinOp::Add && ty == Ty::Str {
+                let mut parts = Vec::new();
+                concat_parts(e, vals, &mut parts);
+                return format!("format!(\"{}\", {})", "{}".repeat(parts.len()), parts.join(", "));
+            }
+            if ta.is_numeric() && tb.is_numeric() {
+                let sym = match op {
+                    BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/", BinOp::Mod => "%",
+                    BinOp::Eq => "==", BinOp::Ne => "!=", BinOp::Lt => "<", BinOp::Le => "<=", BinOp::Gt => ">", BinOp::Ge => ">=",
+                };
+                return format!("({} {} {})", as_f64(a, vals), sym, as_f64(b, vals));
+            }
+            if ta == Ty::Str && tb == Ty::Str && matches!(op, BinOp::Eq | BinOp::Ne) {
+                return format!("({} {} {})", operand(a, vals), if *op == BinOp::Eq { "==" } else { "!=" }, operand(b, vals));
+            }
+            let f = match op {
+                BinOp::Add => "add", BinOp::Sub => "sub", BinOp::Mul => "mul", BinOp::Div => "div", BinOp::Mod => "modulo",
+                BinOp::Eq => "eq", BinOp::Ne => "ne", BinOp::Lt => "lt", BinOp::Le => "le", BinOp::Gt => "gt", BinOp::Ge => "ge",
+            };
+            unbox_code(format!("rt::{}(&{}, &{})?", f, val_ref(a, vals), val_ref(b, vals)), ty)
         }
-        Expr::Call(name, args) if name.as_str() == "SYNTH_LIVE%" => {
-            let a0 = render_expr_as_string(&args[0], vars);
-            let a1 = render_expr_as_string(&args[1], vars);
-            let a2 = render_expr_as_i64(&args[2], vars);
-            format!("rt::features::daw::synth_live(&{}, &{}, {})", a0, a1, a2)
+        Expr::ToInt(a) => match a.ty(vals) {
+            Ty::Int => expr(a, vals),
+            Ty::Num => format!("({}.trunc() as i64)", operand(a, vals)),
+            _ => format!("rt::unbox_int(rt::to_int(&{})?)", val_ref(a, vals)),
+        },
+        Expr::Call { func, args } => {
+            let mut s = String::from("{ ");
+            for (i, a) in args.iter().enumerate() { let _ = write!(s, "let a{} = {}; ", i, boxed(a, vals)); }
+            let _ = write!(s, "f{}(g", func);
+            for i in 0..args.len() { let _ = write!(s, ", a{}", i); }
+            s.push_str(")? }");
+            s
         }
-        // Best-effort fallback for unexpected types
-        _ => "0".to_string(),
+        Expr::Builtin { name, args } => unbox_code(format!("rt::builtin({:?}, &[{}])?", name, boxed_list(args, vals)), ty),
+        Expr::NewArray { elem, dims } => {
+            let elem = match elem {
+                ElemKind::Num => "rt::ElemType::Num".to_string(),
+                ElemKind::Int => "rt::ElemType::Int".to_string(),
+                ElemKind::Str => "rt::ElemType::Str".to_string(),
+                ElemKind::Obj(None) => "rt::ElemType::Obj(None)".to_string(),
+                ElemKind::Obj(Some(t)) => format!("rt::ElemType::Obj(Some({}))", str_lit(t)),
+            };
+            format!("rt::new_array({}, &[{}])?", elem, boxed_list(dims, vals))
+        }
+        Expr::ArrayGet { array, indices } => unbox_code(format!("rt::array_get(&{}, &[{}])?", val_ref(array, vals), boxed_list(indices, vals)), ty),
+        Expr::List(items) => format!("rt::list(vec![{}])", boxed_list(items, vals)),
+        Expr::Dict(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("({}, {})", str_lit(k), boxed(v, vals))).collect();
+            format!("rt::dict(vec![{}])", entries.join(", "))
+        }
+        Expr::Index { target, index } => format!("rt::index_get(&{}, &{})?", val_ref(target, vals), val_ref(index, vals)),
+        Expr::Member { target, name } => format!("rt::prop_get(&{}, {:?})?", val_ref(target, vals), name),
+        Expr::MethodCall { target, method, args } => format!("rt::call_method(&{}, {:?}, &[{}])?", val_ref(target, vals), method, boxed_list(args, vals)),
+        Expr::NewObject { type_name, args } => format!("rt::new_object({:?}, &[{}])?", type_name, boxed_list(args, vals)),
+        Expr::Items(a) => format!("rt::items(&{})?", val_ref(a, vals)),
+        Expr::Exception => "exc.clone().unwrap_or_default()".into(),
     }
 }
-
-fn escape_str(s: &str) -> String { s.replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r") }
@@ -1,123 +1,965 @@
-//! Minimal IR for bcc AOT path. Intentionally tiny to support hello-world and
-//! a few simple statements; can be expanded incrementally.
+//! IR for the bcc AOT path.
+//!
+//! `lower_to_ir` turns a parsed program into one [`Function`] per FUNC/SUB plus `main` for the
+//! top level. A function is a list of basic blocks, each ending in a [`Terminator`], so GOTO,
+//! GOSUB, loops and TRY all become plain jumps. Variables are resolved to local and global slots
+//! the way the bytecode compiler resolves them, and expressions keep the VM's dynamic semantics:
+//! a backend evaluates them with the runtime's `Value`. Anything bcc can't compile yet is an
+//! error naming the construct and its line, never a silent no-op.
+//!
+//! [`optimize`] then rewrites each function into SSA form ([`ssa`]): variables no other routine
+//! can see become typed [`Expr::Value`]s with phis at the joins, and the passes in [`opt`] fold
+//! constants, drop dead code and hoist loop-invariant arithmetic. A backend stores a value whose
+//! [`Ty`] is known natively (an `i64` for `Int`, say) and everything else as a runtime `Value`.
+
+use std::collections::{HashMap, HashSet};
 
 use basil_frontend::ast;
+use basil_frontend::{BasilError, Result};
 
-#[derive(Debug, Clone)]
-pub enum Ty { Int, Bool, Str, ObjHandle }
+pub mod opt;
+pub mod ssa;
 
-#[derive(Debug, Clone)]
-pub enum Instr {
-    Print(Box<Expr>),
-    For { var: String, start: Expr, end: Expr, step: Option<Expr>, body: Vec<Instr> },
-    Assign { var: String, expr: Expr },
-    If { cond: Expr, then_body: Vec<Instr>, else_body: Vec<Instr> },
-    ExprStmt(Expr),
+pub type BlockId = usize;
+pub type FuncId = usize;
+pub type ValueId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Var { Local(usize), Global(usize) }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp { Add, Sub, Mul, Div, Mod, Eq, Ne, Lt, Le, Gt, Ge }
+
+/// Static type of an SSA value. `Int`, `Num`, `Bool` and `Str` are values the VM would hold as
+/// that `Value` variant on every path; arrays keep their element type so reads from them are
+/// typed too. `Dyn` is anything else, including a variable that may still be unassigned (Null).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty { Int, Num, Bool, Str, NumArray, IntArray, StrArray, Dyn }
+
+impl Ty {
+    /// Int, Num and Bool: arithmetic on them can't fail and gives a Num
+    pub fn is_numeric(self) -> bool { matches!(self, Ty::Int | Ty::Num | Ty::Bool) }
+
+    /// The type of a variable that holds `a` on some paths and `b` on others
+    pub fn join(self, b: Ty) -> Ty { if self == b { self } else { Ty::Dyn } }
 }
 
-#[derive(Debug, Clone)]
+/// Element type of a DIMed array, from the name's suffix (`%` Int, `$` Str, `@` objects)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElemKind { Num, Int, Str, Obj(Option<String>) }
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
-    Int(i64),
+    Null,
     Bool(bool),
+    Num(f64),
+    Int(i64),
     Str(String),
-    Var(String),
-    Add(Box<Expr>, Box<Expr>),
-    Ne(Box<Expr>, Box<Expr>),
-    Call(String, Vec<Expr>),
+    Var(Var),
+    // An SSA value, once `ssa::build` has promoted the variable it came from
+    Value(ValueId),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    // Short-circuiting, producing a Bool
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    // Truncate to Int, as assigning to a `%` variable does
+    ToInt(Box<Expr>),
+    Call { func: FuncId, args: Vec<Expr> },
+    // One of `BUILTINS`, or SETENV/SHELL for the statements of that name
+    Builtin { name: &'static str, args: Vec<Expr> },
+    NewArray { elem: ElemKind, dims: Vec<Expr> },
+    ArrayGet { array: Box<Expr>, indices: Vec<Expr> },
+    List(Vec<Expr>),
+    Dict(Vec<(String, Expr)>),
+    // `target[index]` on a list (1-based) or dict
+    Index { target: Box<Expr>, index: Box<Expr> },
+    Member { target: Box<Expr>, name: String },
+    MethodCall { target: Box<Expr>, method: String, args: Vec<Expr> },
+    NewObject { type_name: String, args: Vec<Expr> },
+    // What FOR EACH walks: a list snapshot of an array's elements, a list, or a dict's keys
+    Items(Box<Expr>),
+    // The message of the exception being handled, for the CATCH variable
+    Exception,
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Function {
-    pub body: Vec<Instr>,
+impl Expr {
+    /// Static type, given the types of the function's SSA values. An operation the runtime may
+    /// reject still has the type it produces when it succeeds (a comparison is always a Bool).
+    pub fn ty(&self, values: &[Ty]) -> Ty {
+        match self {
+            Expr::Bool(_) => Ty::Bool,
+            Expr::Num(_) => Ty::Num,
+            Expr::Int(_) => Ty::Int,
+            Expr::Str(_) | Expr::Exception => Ty::Str,
+            Expr::Value(v) => values[*v],
+            Expr::Neg(_) => Ty::Num,
+            Expr::Not(_) | Expr::And(..) | Expr::Or(..) => Ty::Bool,
+            Expr::Binary(BinOp::Add, a, b) => {
+                let (ta, tb) = (a.ty(values), b.ty(values));
+                if ta == Ty::Str || tb == Ty::Str { Ty::Str }
+                else if ta.is_numeric() && tb.is_numeric() { Ty::Num }
+                else { Ty::Dyn }
+            }
+            Expr::Binary(BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod, _, _) => Ty::Num,
+            Expr::Binary(..) => Ty::Bool,
+            Expr::ToInt(_) => Ty::Int,
+            Expr::Builtin { name, .. } => builtin_ty(name),
+            Expr::NewArray { elem: ElemKind::Num, .. } => Ty::NumArray,
+            Expr::NewArray { elem: ElemKind::Int, .. } => Ty::IntArray,
+            Expr::NewArray { elem: ElemKind::Str, .. } => Ty::StrArray,
+            Expr::ArrayGet { array, .. } => match array.ty(values) {
+                Ty::NumArray => Ty::Num,
+                Ty::IntArray => Ty::Int,
+                Ty::StrArray => Ty::Str,
+                _ => Ty::Dyn,
+            },
+            _ => Ty::Dyn,
+        }
+    }
+
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Neg(a) | Expr::Not(a) | Expr::ToInt(a) | Expr::Items(a) => vec![a],
+            Expr::Binary(_, a, b) | Expr::And(a, b) | Expr::Or(a, b) => vec![a, b],
+            Expr::Call { args, .. } | Expr::Builtin { args, .. } | Expr::NewObject { args, .. } => args.iter().collect(),
+            Expr::NewArray { dims, .. } => dims.iter().collect(),
+            Expr::ArrayGet { array, indices } => std::iter::once(&**array).chain(indices).collect(),
+            Expr::List(items) => items.iter().collect(),
+            Expr::Dict(entries) => entries.iter().map(|(_, v)| v).collect(),
+            Expr::Index { target, index } => vec![target, index],
+            Expr::Member { target, .. } => vec![target],
+            Expr::MethodCall { target, args, .. } => std::iter::once(&**target).chain(args).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Neg(a) | Expr::Not(a) | Expr::ToInt(a) | Expr::Items(a) => vec![a],
+            Expr::Binary(_, a, b) | Expr::And(a, b) | Expr::Or(a, b) => vec![a, b],
+            Expr::Call { args, .. } | Expr::Builtin { args, .. } | Expr::NewObject { args, .. } => args.iter_mut().collect(),
+            Expr::NewArray { dims, .. } => dims.iter_mut().collect(),
+            Expr::ArrayGet { array, indices } => std::iter::once(&mut **array).chain(indices).collect(),
+            Expr::List(items) => items.iter_mut().collect(),
+            Expr::Dict(entries) => entries.iter_mut().map(|(_, v)| v).collect(),
+            Expr::Index { target, index } => vec![target, index],
+            Expr::Member { target, .. } => vec![target],
+            Expr::MethodCall { target, args, .. } => std::iter::once(&mut **target).chain(args).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Call `f` on every node, children before their parent
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        for c in self.children_mut() { c.visit_mut(f); }
+        f(self);
+    }
+
+    /// Call `f` with every SSA value the expression reads
+    pub fn for_each_value(&self, f: &mut impl FnMut(ValueId)) {
+        if let Expr::Value(v) = self { f(*v); }
+        for c in self.children() { c.for_each_value(f); }
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Module { pub main: Function }
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    // Source line, for runtime error messages
+    Line(u32),
+    Assign(Var, Expr),
+    // Define an SSA value; each value has exactly one Def (or Phi)
+    Def(ValueId, Expr),
+    Print(Expr),
+    // Evaluate for side effects and drop the result
+    Eval(Expr),
+    ArraySet { array: Expr, indices: Vec<Expr>, value: Expr },
+    IndexSet { target: Expr, index: Expr, value: Expr },
+    PropSet { target: Expr, name: String, value: Expr },
+    // Enter a TRY: a RAISE until the matching TryPop continues at the handler block
+    TryPush(BlockId),
+    TryPop,
+}
 
-fn lower_expr(e: &ast::Expr) -> Expr {
-    match e {
-        ast::Expr::Number(n) => Expr::Int(*n as i64),
-        ast::Expr::Str(s) => Expr::Str(s.clone()),
-        ast::Expr::Bool(b) => Expr::Bool(*b),
-        ast::Expr::Var(name) => Expr::Var(name.clone()),
-        ast::Expr::Binary { op, lhs, rhs } => {
-            use ast::BinOp::*;
-            match op {
-                Add => Expr::Add(Box::new(lower_expr(lhs)), Box::new(lower_expr(rhs))),
-                Ne  => Expr::Ne(Box::new(lower_expr(lhs)), Box::new(lower_expr(rhs))),
-                _ => Expr::Str(format!("{:?}", e)),
-            }
+impl Instr {
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Instr::Assign(_, e) | Instr::Def(_, e) | Instr::Print(e) | Instr::Eval(e) => vec![e],
+            Instr::ArraySet { array, indices, value } => std::iter::once(array).chain(indices).chain(std::iter::once(value)).collect(),
+            Instr::IndexSet { target, index, value } => vec![target, index, value],
+            Instr::PropSet { target, value, .. } => vec![target, value],
+            Instr::Line(_) | Instr::TryPush(_) | Instr::TryPop => Vec::new(),
         }
-        ast::Expr::Call { callee, args } => {
-            if let ast::Expr::Var(name) = &**callee {
-                let args2: Vec<Expr> = args.iter().map(|a| lower_expr(a)).collect();
-                Expr::Call(name.clone(), args2)
-            } else {
-                Expr::Str(format!("{:?}", e))
-            }
+    }
+
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Instr::Assign(_, e) | Instr::Def(_, e) | Instr::Print(e) | Instr::Eval(e) => vec![e],
+            Instr::ArraySet { array, indices, value } => std::iter::once(array).chain(indices).chain(std::iter::once(value)).collect(),
+            Instr::IndexSet { target, index, value } => vec![target, index, value],
+            Instr::PropSet { target, value, .. } => vec![target, value],
+            Instr::Line(_) | Instr::TryPush(_) | Instr::TryPop => Vec::new(),
         }
-        _ => Expr::Str(format!("{:?}", e)),
     }
 }
 
-fn lower_stmt(stmt: &ast::Stmt, out: &mut Vec<Instr>) {
-    match stmt {
-        ast::Stmt::Print { expr } => {
-            out.push(Instr::Print(Box::new(lower_expr(expr))));
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch { cond: Expr, then_to: BlockId, else_to: BlockId },
+    Return(Expr),
+    Gosub { target: BlockId, ret: BlockId },
+    GosubReturn,
+    // RETURN TO label: drop the GOSUB return address and jump
+    GosubReturnTo(BlockId),
+    Raise(Expr),
+    // Raise the exception being handled again, to the enclosing TRY
+    Reraise,
+    Exit(Expr),
+    Halt,
+}
+
+impl Terminator {
+    pub fn expr(&self) -> Option<&Expr> {
+        match self {
+            Terminator::Branch { cond: e, .. } | Terminator::Return(e) | Terminator::Raise(e) | Terminator::Exit(e) => Some(e),
+            _ => None,
         }
-        ast::Stmt::Let { name, indices, init } => {
-            if indices.is_none() {
-                out.push(Instr::Assign { var: name.clone(), expr: lower_expr(init) });
-            }
+    }
+
+    pub fn expr_mut(&mut self) -> Option<&mut Expr> {
+        match self {
+            Terminator::Branch { cond: e, .. } | Terminator::Return(e) | Terminator::Raise(e) | Terminator::Exit(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Blocks this terminator jumps to directly. A GOSUB's return block is reached through the
+    /// subroutine's RETURN instead, see `ssa::successors`.
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(b) | Terminator::GosubReturnTo(b) => vec![b],
+            Terminator::Branch { then_to, else_to, .. } => vec![then_to, else_to],
+            Terminator::Gosub { target, ret } => vec![target, ret],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// `value` is the arg of whichever predecessor block control came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phi {
+    pub value: ValueId,
+    pub args: Vec<(BlockId, ValueId)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub phis: Vec<Phi>,
+    pub instrs: Vec<Instr>,
+    pub term: Terminator,
+}
+
+/// A FUNC/SUB, or the top level. Parameters are locals `0..params`; execution starts at block 0.
+/// `values` holds the type of each SSA value and is empty until `ssa::build` runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: usize,
+    pub locals: Vec<String>,
+    pub blocks: Vec<Block>,
+    pub is_sub: bool,
+    pub values: Vec<Ty>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub globals: Vec<String>,
+    pub functions: Vec<Function>,
+    pub main: Function,
+}
+
+/// Builtins bcc can compile (after alias resolution). All but INPUT$ run the VM's own code from
+/// `basil-builtins`; the ones from a feature (JSON_, SQLITE_, terminal...) need it in the runtime.
+pub const BUILTINS: &[&str] = &[
+    "LEN", "MID$", "LEFT$", "RIGHT$", "INSTR", "INPUT$", "TYPE$", "HTML$", "HTML", "UCASE$", "LCASE$",
+    "TRIM$", "CHR$", "ASC%", "ESCAPE$", "UNESCAPE$", "URLENCODE$", "URLDECODE$", "SLEEP", "STRING$",
+    "ENV$", "LOADENV%", "MKDIRS%", "ARRAY_ROWS%", "ARRAY_COLS%",
+    "FOPEN", "FCLOSE", "FFLUSH", "FEOF", "FTELL&", "FSEEK", "FREAD$", "FREADLINE$", "FWRITE", "FWRITELN",
+    "READFILE$", "WRITEFILE", "APPENDFILE", "COPY", "MOVE", "RENAME", "DELETE", "DIR$",
+    "BASE64_ENCODE$", "BASE64_DECODE$", "JSON_PARSE$", "JSON_STRINGIFY$", "CSV_PARSE$", "CSV_WRITE$",
+    "ZIP_EXTRACT_ALL", "ZIP_COMPRESS_FILE", "ZIP_COMPRESS_DIR", "ZIP_LIST$", "HTTP_GET$", "HTTP_POST$",
+    "SQLITE_OPEN%", "SQLITE_CLOSE", "SQLITE_EXEC%", "SQLITE_QUERY2D$", "SQLITE_LAST_INSERT_ID%",
+    "CLS", "CLEAR", "HOME", "LOCATE", "COLOR", "COLOR_RESET", "ATTR", "ATTR_RESET", "CURSOR_SAVE",
+    "CURSOR_RESTORE", "TERM_COLS%", "TERM_ROWS%", "CURSOR_HIDE", "CURSOR_SHOW", "TERM_ERR$",
+    "TERM.INIT", "TERM.END", "TERM.RAW", "ALTSCREEN_ON", "ALTSCREEN_OFF", "TERM.FLUSH", "TERM.POLLKEY$",
+    "DAW_STOP", "DAW_ERR$", "AUDIO_RECORD%", "AUDIO_PLAY%", "AUDIO_MONITOR%", "MIDI_CAPTURE%",
+    "SYNTH_LIVE%", "DAW_RESET",
+];
+
+/// What a builtin returns when it succeeds, for the ones that always return the same type
+pub fn builtin_ty(name: &str) -> Ty {
+    match name {
+        "LEN" | "INSTR" | "ASC%" => Ty::Int,
+        "MID$" | "LEFT$" | "RIGHT$" | "INPUT$" | "TYPE$" | "HTML$" | "HTML" | "UCASE$" | "LCASE$" | "TRIM$"
+        | "CHR$" | "ESCAPE$" | "UNESCAPE$" | "URLENCODE$" | "URLDECODE$" | "STRING$" => Ty::Str,
+        _ => Ty::Dyn,
+    }
+}
+
+// Builtins that only exist in feature builds of the compiler, so `builtin_id` doesn't know them
+const FEATURE_PREFIXES: &[&str] = &["AUDIO_", "MIDI_", "DAW_", "SYNTH_", "WAV_", "TERM_", "CURSOR_", "ALTSCREEN_", "BASE64_", "ZIP_", "HTTP_", "JSON_", "CSV_", "SQLITE_"];
+const FEATURE_NAMES: &[&str] = &["CLS", "CLEAR", "HOME", "LOCATE", "COLOR", "COLOR_RESET", "ATTR", "ATTR_RESET"];
+
+fn builtin(uname: &str) -> Option<&'static str> {
+    let uname = if uname == "INPUT" { "INPUT$" } else { uname };
+    BUILTINS.iter().copied().find(|b| *b == uname)
+}
+
+fn is_vm_builtin(uname: &str) -> bool {
+    basil_compiler::builtin_id(uname).is_some()
+        || FEATURE_PREFIXES.iter().any(|p| uname.starts_with(p))
+        || FEATURE_NAMES.contains(&uname)
+}
+
+struct Routine { id: FuncId, arity: usize, is_sub: bool, is_async: bool }
+
+struct FnBuilder {
+    in_func: bool,
+    locals: Vec<String>,
+    lmap: HashMap<String, usize>,
+    blocks: Vec<(Vec<Instr>, Option<Terminator>)>,
+    // None after a terminator: the next statement starts an unreachable block
+    cur: Option<BlockId>,
+    labels: HashMap<String, (BlockId, bool)>,
+    // (test, exit) of the enclosing WHILE loops, for CONTINUE and BREAK
+    loops: Vec<(BlockId, BlockId)>,
+    with: Vec<Var>,
+}
+
+impl FnBuilder {
+    fn new(in_func: bool) -> Self {
+        FnBuilder { in_func, locals: Vec::new(), lmap: HashMap::new(), blocks: vec![(Vec::new(), None)], cur: Some(0), labels: HashMap::new(), loops: Vec::new(), with: Vec::new() }
+    }
+
+    fn bind_local(&mut self, name: &str) -> Var {
+        if let Some(&i) = self.lmap.get(name) { return Var::Local(i); }
+        let i = self.locals.len();
+        self.locals.push(name.to_string());
+        self.lmap.insert(name.to_string(), i);
+        Var::Local(i)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        self.blocks.len() - 1
+    }
+
+    fn current(&mut self) -> BlockId {
+        match self.cur {
+            Some(b) => b,
+            None => { let b = self.new_block(); self.cur = Some(b); b }
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        let b = self.current();
+        self.blocks[b].0.push(instr);
+    }
+
+    fn end(&mut self, term: Terminator) {
+        let b = self.current();
+        self.blocks[b].1 = Some(term);
+        self.cur = None;
+    }
+
+    // Continue in block `b`, falling through into it from the current block
+    fn at(&mut self, b: BlockId) {
+        if self.cur.is_some() { self.end(Terminator::Jump(b)); }
+        self.cur = Some(b);
+    }
+
+    fn label(&mut self, name: &str) -> BlockId {
+        if let Some(&(b, _)) = self.labels.get(name) { return b; }
+        let b = self.new_block();
+        self.labels.insert(name.to_string(), (b, false));
+        b
+    }
+
+    fn finish(self, name: &str, params: usize, is_sub: bool, fallthrough: Terminator) -> Result<Function> {
+        let mut undefined: Vec<&String> = self.labels.iter().filter(|(_, (_, defined))| !defined).map(|(n, _)| n).collect();
+        undefined.sort();
+        if let Some(label) = undefined.first() { return Err(BasilError(format!("Undefined label: {}", label))); }
+        let blocks = self.blocks.into_iter()
+            .map(|(instrs, term)| Block { phis: Vec::new(), instrs, term: term.unwrap_or_else(|| fallthrough.clone()) })
+            .collect();
+        Ok(Function { name: name.to_string(), params, locals: self.locals, blocks, is_sub, values: Vec::new() })
+    }
+}
+
+struct Lower {
+    globals: Vec<String>,
+    gmap: HashMap<String, usize>,
+    routines: HashMap<String, Routine>,
+    functions: Vec<Option<Function>>,
+    line: u32,
+    temps: usize,
+}
+
+impl Lower {
+    fn error(&self, msg: String) -> BasilError {
+        if self.line > 0 { BasilError(format!("line {}: {}", self.line, msg)) } else { BasilError(msg) }
+    }
+
+    fn unsupported(&self, what: &str) -> BasilError {
+        self.error(format!("{} is not supported by bcc yet", what))
+    }
+
+    fn gslot(&mut self, name: &str) -> usize {
+        if let Some(&i) = self.gmap.get(name) { return i; }
+        self.globals.push(name.to_string());
+        self.gmap.insert(name.to_string(), self.globals.len() - 1);
+        self.globals.len() - 1
+    }
+
+    // A variable read: a local of the enclosing FUNC, else a global
+    fn read(&mut self, f: &FnBuilder, name: &str) -> Var {
+        if let Some(&i) = f.lmap.get(name) { return Var::Local(i); }
+        Var::Global(self.gslot(name))
+    }
+
+    // LET inside a FUNC writes an existing local, else a global the program already used, else
+    // binds a new local (as the bytecode compiler decides it, so the two agree)
+    fn write(&mut self, f: &mut FnBuilder, name: &str) -> Var {
+        if !f.in_func { return Var::Global(self.gslot(name)); }
+        if let Some(&i) = f.lmap.get(name) { return Var::Local(i); }
+        if self.gmap.contains_key(name) && !self.routines.contains_key(&name.to_ascii_uppercase()) {
+            return Var::Global(self.gslot(name));
         }
-        ast::Stmt::If { cond, then_branch, else_branch } => {
-            // Lower then branch
-            let mut then_vec = Vec::new();
-            match &**then_branch {
-                ast::Stmt::Block(stmts) => { for s in stmts { lower_stmt(s, &mut then_vec); } }
-                other => { lower_stmt(other, &mut then_vec); }
+        f.bind_local(name)
+    }
+
+    // DIM, CATCH variables and hidden temporaries are always local to a FUNC
+    fn declare(&mut self, f: &mut FnBuilder, name: &str) -> Var {
+        if f.in_func { f.bind_local(name) } else { Var::Global(self.gslot(name)) }
+    }
+
+    fn temp(&mut self, f: &mut FnBuilder) -> Var {
+        self.temps += 1;
+        let name = format!("\u{0001}TMP#{}", self.temps);
+        self.declare(f, &name)
+    }
+
+    fn exprs(&mut self, f: &FnBuilder, es: &[ast::Expr]) -> Result<Vec<Expr>> {
+        es.iter().map(|e| self.expr(f, e)).collect()
+    }
+
+    fn expr(&mut self, f: &FnBuilder, e: &ast::Expr) -> Result<Expr> {
+        Ok(match e {
+            ast::Expr::Number(n) => Expr::Num(*n),
+            ast::Expr::Str(s) => Expr::Str(s.clone()),
+            ast::Expr::Bool(b) => Expr::Bool(*b),
+            ast::Expr::Var(name) => match name.to_ascii_uppercase().as_str() {
+                "PRO" => Expr::Int(1),
+                "NOT_PRO" => Expr::Int(0),
+                _ => Expr::Var(self.read(f, name)),
+            },
+            ast::Expr::UnaryNeg(inner) => Expr::Neg(Box::new(self.expr(f, inner)?)),
+            ast::Expr::UnaryNot(inner) => Expr::Not(Box::new(self.expr(f, inner)?)),
+            ast::Expr::Binary { op, lhs, rhs } => {
+                let (l, r) = (Box::new(self.expr(f, lhs)?), Box::new(self.expr(f, rhs)?));
+                let op = match op {
+                    ast::BinOp::And => return Ok(Expr::And(l, r)),
+                    ast::BinOp::Or => return Ok(Expr::Or(l, r)),
+                    ast::BinOp::Add => BinOp::Add, ast::BinOp::Sub => BinOp::Sub, ast::BinOp::Mul => BinOp::Mul,
+                    ast::BinOp::Div => BinOp::Div, ast::BinOp::Mod => BinOp::Mod,
+                    ast::BinOp::Eq => BinOp::Eq, ast::BinOp::Ne => BinOp::Ne, ast::BinOp::Lt => BinOp::Lt,
+                    ast::BinOp::Le => BinOp::Le, ast::BinOp::Gt => BinOp::Gt, ast::BinOp::Ge => BinOp::Ge,
+                };
+                Expr::Binary(op, l, r)
             }
-            // Lower else branch (optional)
-            let mut else_vec = Vec::new();
-            if let Some(eb) = else_branch {
-                match &**eb {
-                    ast::Stmt::Block(stmts) => { for s in stmts { lower_stmt(s, &mut else_vec); } }
-                    other => { lower_stmt(other, &mut else_vec); }
+            ast::Expr::Call { callee, args } => self.call(f, callee, args)?,
+            ast::Expr::MemberGet { target, name } => {
+                if matches!(&**target, ast::Expr::Var(t) if t.eq_ignore_ascii_case("TERM")) {
+                    return Err(self.unsupported(&format!("TERM.{}", name)));
                 }
+                Expr::Member { target: Box::new(self.expr(f, target)?), name: name.clone() }
             }
-            out.push(Instr::If { cond: lower_expr(cond), then_body: then_vec, else_body: else_vec });
+            ast::Expr::MemberCall { target, method, args } => {
+                if matches!(&**target, ast::Expr::Var(t) if t.eq_ignore_ascii_case("TERM")) {
+                    return Err(self.unsupported(&format!("TERM.{}", method)));
+                }
+                Expr::MethodCall { target: Box::new(self.expr(f, target)?), method: method.clone(), args: self.exprs(f, args)? }
+            }
+            ast::Expr::ImplicitThis => match f.with.last() {
+                Some(v) => Expr::Var(*v),
+                None => return Err(BasilError(format!("Leading '.' member requires a WITH block (at line {})", self.line))),
+            },
+            ast::Expr::NewObject { type_name, args } => Expr::NewObject { type_name: type_name.clone(), args: self.exprs(f, args)? },
+            ast::Expr::NewClass { .. } => return Err(self.unsupported("CLASS(file)")),
+            ast::Expr::Eval(_) => return Err(self.unsupported("EVAL")),
+            ast::Expr::List(items) => Expr::List(self.exprs(f, items)?),
+            ast::Expr::Dict(entries) => {
+                let mut out = Vec::with_capacity(entries.len());
+                for (k, v) in entries { out.push((k.clone(), self.expr(f, v)?)); }
+                Expr::Dict(out)
+            }
+            ast::Expr::IndexSquare { target, index } => Expr::Index { target: Box::new(self.expr(f, target)?), index: Box::new(self.expr(f, index)?) },
+        })
+    }
+
+    // NAME(args) is a builtin, a FUNC call or an array read, in the compiler's order
+    fn call(&mut self, f: &FnBuilder, callee: &ast::Expr, args: &[ast::Expr]) -> Result<Expr> {
+        let ast::Expr::Var(name) = callee else {
+            return Err(self.unsupported("calling a computed value"));
+        };
+        let uname = name.to_ascii_uppercase();
+        if let Some(b) = builtin(&uname) {
+            return Ok(Expr::Builtin { name: b, args: self.exprs(f, args)? });
         }
-        ast::Stmt::ExprStmt(e) => {
-            out.push(Instr::ExprStmt(lower_expr(e)));
+        if uname == "DESCRIBE$" || is_vm_builtin(&uname) {
+            return Err(self.unsupported(&format!("builtin {}", uname)));
         }
-        ast::Stmt::For { var, start, end, step, body } => {
-            // Lower body
-            let mut inner = Vec::new();
-            if let ast::Stmt::Block(stmts) = &**body {
-                for s in stmts { lower_stmt(s, &mut inner); }
-            } else {
-                lower_stmt(body, &mut inner);
+        if let Some(r) = self.routines.get(&uname) {
+            let (id, arity) = (r.id, r.arity);
+            if r.is_async { return Err(self.unsupported("calling an ASYNC FUNC")); }
+            if r.is_sub {
+                return Err(self.error("SUB call has no value; cannot be used in an expression. Call it as a statement: NAME(...);".into()));
+            }
+            if arity != args.len() {
+                return Err(self.error(format!("arity mismatch calling {}: expected {}, got {}", name, arity, args.len())));
             }
-            out.push(Instr::For {
-                var: var.clone(),
-                start: lower_expr(start),
-                end: lower_expr(end),
-                step: step.as_ref().map(|e| lower_expr(e)),
-                body: inner,
-            });
+            return Ok(Expr::Call { func: id, args: self.exprs(f, args)? });
         }
-        ast::Stmt::Block(stmts) => {
-            for s in stmts { lower_stmt(s, out); }
+        if (1..=4).contains(&args.len()) {
+            let array = Expr::Var(self.read(f, name));
+            return Ok(Expr::ArrayGet { array: Box::new(array), indices: self.exprs(f, args)? });
         }
-        _ => {
-            // Ignore unsupported statements for now
+        Err(self.error(format!("unknown function {}", name)))
+    }
+
+    fn body(&mut self, f: &mut FnBuilder, s: &ast::Stmt) -> Result<()> {
+        match s {
+            ast::Stmt::Block(stmts) => stmts.iter().try_for_each(|s| self.stmt(f, s)),
+            other => self.stmt(f, other),
         }
     }
+
+    fn stmts(&mut self, f: &mut FnBuilder, stmts: &[ast::Stmt]) -> Result<()> {
+        stmts.iter().try_for_each(|s| self.stmt(f, s))
+    }
+
+    fn stmt(&mut self, f: &mut FnBuilder, s: &ast::Stmt) -> Result<()> {
+        match s {
+            ast::Stmt::Line(n) => {
+                self.line = *n;
+                f.emit(Instr::Line(*n));
+            }
+            ast::Stmt::Block(stmts) => self.stmts(f, stmts)?,
+            ast::Stmt::Let { name, indices: None, init } => {
+                let mut value = self.expr(f, init)?;
+                if name.ends_with('%') { value = Expr::ToInt(Box::new(value)); }
+                let var = self.write(f, name);
+                f.emit(Instr::Assign(var, value));
+            }
+            ast::Stmt::Let { name, indices: Some(idxs), init } => {
+                if idxs.is_empty() {
+                    // name$() = rows from SQLITE_QUERY2D$, like the VM's builtin 138
+                    let value = Expr::Builtin { name: "STR2D_TO_ARRAY$", args: vec![self.expr(f, init)?] };
+                    let var = self.write(f, name);
+                    f.emit(Instr::Assign(var, value));
+                    return Ok(());
+                }
+                let array = Expr::Var(self.read(f, name));
+                let indices = self.exprs(f, idxs)?;
+                let value = self.expr(f, init)?;
+                f.emit(Instr::ArraySet { array, indices, value });
+            }
+            ast::Stmt::Dim { name, dims } => {
+                let elem = if name.ends_with('%') { ElemKind::Int } else if name.ends_with('$') { ElemKind::Str } else { ElemKind::Num };
+                let dims = self.exprs(f, dims)?;
+                let var = self.declare(f, name);
+                f.emit(Instr::Assign(var, Expr::NewArray { elem, dims }));
+            }
+            ast::Stmt::DimObjectArray { name, dims, type_name } => {
+                let dims = self.exprs(f, dims)?;
+                let var = self.declare(f, name);
+                f.emit(Instr::Assign(var, Expr::NewArray { elem: ElemKind::Obj(type_name.clone()), dims }));
+            }
+            ast::Stmt::DimObject { name, type_name, args } => {
+                let value = Expr::NewObject { type_name: type_name.clone(), args: self.exprs(f, args)? };
+                let var = self.declare(f, name);
+                f.emit(Instr::Assign(var, value));
+            }
+            ast::Stmt::DimFixedStr { .. } => return Err(self.unsupported("fixed-length strings")),
+            ast::Stmt::TypeDef { .. } => return Err(self.unsupported("TYPE ... END TYPE")),
+            ast::Stmt::SetProp { target, prop, value } => {
+                let target = self.expr(f, target)?;
+                let value = self.expr(f, value)?;
+                f.emit(Instr::PropSet { target, name: prop.clone(), value });
+            }
+            ast::Stmt::SetIndexSquare { target, index, value } => {
+                let (target, index, value) = (self.expr(f, target)?, self.expr(f, index)?, self.expr(f, value)?);
+                f.emit(Instr::IndexSet { target, index, value });
+            }
+            ast::Stmt::Describe { .. } => return Err(self.unsupported("DESCRIBE")),
+            ast::Stmt::Exec { .. } => return Err(self.unsupported("EXEC")),
+            ast::Stmt::Stop => return Err(self.unsupported("STOP")),
+            ast::Stmt::Print { expr } => {
+                let e = self.expr(f, expr)?;
+                f.emit(Instr::Print(e));
+            }
+            ast::Stmt::SetEnv { name, value, export } => {
+                let args = vec![Expr::Str(name.clone()), self.expr(f, value)?, Expr::Bool(*export)];
+                f.emit(Instr::Eval(Expr::Builtin { name: "SETENV", args }));
+            }
+            ast::Stmt::Shell { cmd } => {
+                let args = vec![self.expr(f, cmd)?];
+                f.emit(Instr::Eval(Expr::Builtin { name: "SHELL", args }));
+            }
+            ast::Stmt::Exit(code) => {
+                let code = match code { Some(e) => self.expr(f, e)?, None => Expr::Int(0) };
+                f.end(Terminator::Exit(code));
+            }
+            ast::Stmt::ExprStmt(e) => {
+                // A SUB is only called as a statement
+                if let ast::Expr::Call { callee, args } = e {
+                    if let ast::Expr::Var(name) = &**callee {
+                        if let Some(r) = self.routines.get(&name.to_ascii_uppercase()).filter(|r| r.is_sub) {
+                            let (id, arity) = (r.id, r.arity);
+                            if arity != args.len() {
+                                return Err(self.error(format!("procedure '{}' expects {} arguments but {} given", name, arity, args.len())));
+                            }
+                            let args = self.exprs(f, args)?;
+                            f.emit(Instr::Eval(Expr::Call { func: id, args }));
+                            return Ok(());
+                        }
+                    }
+                }
+                let e = self.expr(f, e)?;
+                f.emit(Instr::Eval(e));
+            }
+            // RETURN at the top level is ignored, as in the VM
+            ast::Stmt::Return(e) => {
+                if f.in_func {
+                    let value = match e { Some(e) => self.expr(f, e)?, None => Expr::Null };
+                    f.end(Terminator::Return(value));
+                }
+            }
+            ast::Stmt::ReturnFromGosub(None) => f.end(Terminator::GosubReturn),
+            ast::Stmt::ReturnFromGosub(Some(label)) => {
+                let b = f.label(label);
+                f.end(Terminator::GosubReturnTo(b));
+            }
+            ast::Stmt::Label(name) => {
+                let b = f.label(name);
+                if f.labels[name].1 { return Err(BasilError(format!("Duplicate label: {}", name))); }
+                f.labels.get_mut(name).expect("label").1 = true;
+                f.at(b);
+            }
+            ast::Stmt::Goto(name) => {
+                let b = f.label(name);
+                f.end(Terminator::Jump(b));
+            }
+            ast::Stmt::Gosub(name) => {
+                let target = f.label(name);
+                let ret = f.new_block();
+                f.end(Terminator::Gosub { target, ret });
+                f.at(ret);
+            }
+            ast::Stmt::If { cond, then_branch, else_branch } => {
+                let cond = self.expr(f, cond)?;
+                let (then_to, else_to, join) = (f.new_block(), f.new_block(), f.new_block());
+                f.end(Terminator::Branch { cond, then_to, else_to });
+                f.at(then_to);
+                self.body(f, then_branch)?;
+                f.end(Terminator::Jump(join));
+                f.at(else_to);
+                if let Some(e) = else_branch { self.body(f, e)?; }
+                f.at(join);
+            }
+            ast::Stmt::While { cond, body } => {
+                let (test, run, exit) = (f.new_block(), f.new_block(), f.new_block());
+                f.at(test);
+                let cond = self.expr(f, cond)?;
+                f.end(Terminator::Branch { cond, then_to: run, else_to: exit });
+                f.at(run);
+                f.loops.push((test, exit));
+                self.body(f, body)?;
+                f.loops.pop();
+                f.end(Terminator::Jump(test));
+                f.at(exit);
+            }
+            ast::Stmt::Break => match f.loops.last() {
+                Some(&(_, exit)) => f.end(Terminator::Jump(exit)),
+                None => return Err(BasilError("BREAK used outside of loop".into())),
+            },
+            ast::Stmt::Continue => match f.loops.last() {
+                Some(&(test, _)) => f.end(Terminator::Jump(test)),
+                None => return Err(BasilError("CONTINUE used outside of loop".into())),
+            },
+            // FOR re-evaluates its end and step on every pass, and like the VM doesn't make
+            // itself a BREAK/CONTINUE target (those belong to the enclosing WHILE)
+            ast::Stmt::For { var, start, end, step, body } => {
+                let v = self.read(f, var);
+                let to_int = |e: Expr| if var.ends_with('%') { Expr::ToInt(Box::new(e)) } else { e };
+                let start = self.expr(f, start)?;
+                f.emit(Instr::Assign(v, to_int(start)));
+                let step = match step { Some(e) => self.expr(f, e)?, None => Expr::Num(1.0) };
+                let end = self.expr(f, end)?;
+                let (head, up, down, run, exit) = (f.new_block(), f.new_block(), f.new_block(), f.new_block(), f.new_block());
+                f.at(head);
+                let cond = Expr::Binary(BinOp::Ge, Box::new(step.clone()), Box::new(Expr::Num(0.0)));
+                f.end(Terminator::Branch { cond, then_to: up, else_to: down });
+                f.at(up);
+                let cond = Expr::Binary(BinOp::Le, Box::new(Expr::Var(v)), Box::new(end.clone()));
+                f.end(Terminator::Branch { cond, then_to: run, else_to: exit });
+                f.at(down);
+                let cond = Expr::Binary(BinOp::Ge, Box::new(Expr::Var(v)), Box::new(end));
+                f.end(Terminator::Branch { cond, then_to: run, else_to: exit });
+                f.at(run);
+                self.body(f, body)?;
+                let next = Expr::Binary(BinOp::Add, Box::new(Expr::Var(v)), Box::new(step));
+                f.emit(Instr::Assign(v, to_int(next)));
+                f.end(Terminator::Jump(head));
+                f.at(exit);
+            }
+            ast::Stmt::ForEach { var, enumerable, body } => {
+                let items = Expr::Items(Box::new(self.expr(f, enumerable)?));
+                let (list, i) = (self.temp(f), self.temp(f));
+                f.emit(Instr::Assign(list, items));
+                f.emit(Instr::Assign(i, Expr::Int(0)));
+                let v = self.read(f, var);
+                let (head, run, exit) = (f.new_block(), f.new_block(), f.new_block());
+                f.at(head);
+                f.emit(Instr::Assign(i, Expr::Binary(BinOp::Add, Box::new(Expr::Var(i)), Box::new(Expr::Int(1)))));
+                let len = Expr::Builtin { name: "LEN", args: vec![Expr::Var(list)] };
+                f.end(Terminator::Branch { cond: Expr::Binary(BinOp::Le, Box::new(Expr::Var(i)), Box::new(len)), then_to: run, else_to: exit });
+                f.at(run);
+                let mut item = Expr::Index { target: Box::new(Expr::Var(list)), index: Box::new(Expr::Var(i)) };
+                if var.ends_with('%') { item = Expr::ToInt(Box::new(item)); }
+                f.emit(Instr::Assign(v, item));
+                self.body(f, body)?;
+                f.end(Terminator::Jump(head));
+                f.at(exit);
+            }
+            ast::Stmt::ParallelForEach { .. } => return Err(self.unsupported("PARALLEL FOR EACH")),
+            ast::Stmt::SelectCase { selector, arms, else_body } => {
+                let sel = self.expr(f, selector)?;
+                let t = self.temp(f);
+                f.emit(Instr::Assign(t, sel));
+                let done = f.new_block();
+                for arm in arms {
+                    let cond = self.case_cond(f, t, &arm.patterns)?;
+                    let (run, next) = (f.new_block(), f.new_block());
+                    f.end(Terminator::Branch { cond, then_to: run, else_to: next });
+                    f.at(run);
+                    self.stmts(f, &arm.body)?;
+                    f.end(Terminator::Jump(done));
+                    f.at(next);
+                }
+                if let Some(body) = else_body { self.stmts(f, body)?; }
+                f.at(done);
+            }
+            ast::Stmt::With { target, body } => {
+                let target = self.expr(f, target)?;
+                let t = self.temp(f);
+                f.emit(Instr::Assign(t, target));
+                f.with.push(t);
+                let res = self.stmts(f, body);
+                f.with.pop();
+                res?;
+            }
+            ast::Stmt::Try { try_body, catch_var, catch_body, finally_body } => {
+                let (handler, after) = (f.new_block(), f.new_block());
+                f.emit(Instr::TryPush(handler));
+                self.stmts(f, try_body)?;
+                f.emit(Instr::TryPop);
+                if let Some(fin) = finally_body { self.stmts(f, fin)?; }
+                f.end(Terminator::Jump(after));
+                // The handler runs outside the TRY, so a RAISE in CATCH or FINALLY goes outward
+                f.at(handler);
+                f.emit(Instr::TryPop);
+                match catch_body {
+                    Some(body) => {
+                        if let Some(name) = catch_var {
+                            let v = self.declare(f, name);
+                            f.emit(Instr::Assign(v, Expr::Exception));
+                        }
+                        self.stmts(f, body)?;
+                        if let Some(fin) = finally_body { self.stmts(f, fin)?; }
+                        f.end(Terminator::Jump(after));
+                    }
+                    None => {
+                        if let Some(fin) = finally_body { self.stmts(f, fin)?; }
+                        f.end(Terminator::Reraise);
+                    }
+                }
+                f.at(after);
+            }
+            ast::Stmt::Raise(Some(e)) => {
+                let e = self.expr(f, e)?;
+                f.end(Terminator::Raise(e));
+            }
+            ast::Stmt::Raise(None) => f.end(Terminator::Reraise),
+            ast::Stmt::Func { .. } => return Err(self.error("FUNC and SUB must be defined at the top level".into())),
+        }
+        Ok(())
+    }
+
+    fn case_cond(&mut self, f: &FnBuilder, sel: Var, patterns: &[ast::CasePattern]) -> Result<Expr> {
+        let sel = || Box::new(Expr::Var(sel));
+        let mut cond: Option<Expr> = None;
+        for p in patterns {
+            let one = match p {
+                ast::CasePattern::Value(v) => Expr::Binary(BinOp::Eq, sel(), Box::new(self.expr(f, v)?)),
+                ast::CasePattern::Range { lo, hi } => Expr::And(
+                    Box::new(Expr::Binary(BinOp::Ge, sel(), Box::new(self.expr(f, lo)?))),
+                    Box::new(Expr::Binary(BinOp::Le, sel(), Box::new(self.expr(f, hi)?))),
+                ),
+                ast::CasePattern::Compare { op, rhs } => {
+                    let rhs = ast::Expr::Binary { op: *op, lhs: Box::new(ast::Expr::Bool(false)), rhs: Box::new(rhs.clone()) };
+                    match self.expr(f, &rhs)? {
+                        Expr::Binary(op, _, rhs) => Expr::Binary(op, sel(), rhs),
+                        _ => return Err(self.error("CASE IS needs a comparison operator".into())),
+                    }
+                }
+            };
+            cond = Some(match cond { None => one, Some(prev) => Expr::Or(Box::new(prev), Box::new(one)) });
+        }
+        Ok(cond.unwrap_or(Expr::Bool(false)))
+    }
+
+    fn function(&mut self, name: &str, params: &[String], body: &[ast::Stmt]) -> Result<()> {
+        let r = &self.routines[&name.to_ascii_uppercase()];
+        let (id, is_sub) = (r.id, r.is_sub);
+        let mut f = FnBuilder::new(true);
+        for p in params { f.bind_local(p); }
+        self.stmts(&mut f, body)?;
+        self.functions[id] = Some(f.finish(name, params.len(), is_sub, Terminator::Return(Expr::Null))?);
+        Ok(())
+    }
+}
+
+/// Lower a parsed program to IR. FUNCs are lowered where they appear, so variable resolution
+/// inside them sees the same globals the bytecode compiler would at that point.
+pub fn lower_to_ir(prog: &ast::Program) -> Result<Module> {
+    let mut l = Lower { globals: Vec::new(), gmap: HashMap::new(), routines: HashMap::new(), functions: Vec::new(), line: 0, temps: 0 };
+    for s in prog {
+        if let ast::Stmt::Func { kind, name, params, .. } = s {
+            let id = l.functions.len();
+            l.functions.push(None);
+            let routine = Routine { id, arity: params.len(), is_sub: *kind == ast::FuncKind::Sub, is_async: *kind == ast::FuncKind::AsyncFunc };
+            l.routines.insert(name.to_ascii_uppercase(), routine);
+        }
+    }
+    let mut main = FnBuilder::new(false);
+    for s in prog {
+        match s {
+            ast::Stmt::Func { name, params, body, .. } => l.function(name, params, body)?,
+            other => l.stmt(&mut main, other)?,
+        }
+    }
+    let main = main.finish("main", 0, false, Terminator::Halt)?;
+    let functions = l.functions.into_iter().map(|f| f.expect("every FUNC is lowered")).collect();
+    Ok(Module { globals: l.globals, functions, main })
+}
+
+/// Rewrite every function into SSA form and run the optimization passes, see [`opt::run`].
+pub fn optimize(module: &mut Module) {
+    // FUNCs only promote their locals; the top level promotes the globals no FUNC uses
+    let private = ssa::private_globals(module);
+    for f in module.functions.iter_mut() { ssa::build(f, &HashSet::new()); }
+    ssa::build(&mut module.main, &private);
+    for f in module.functions.iter_mut().chain(std::iter::once(&mut module.main)) { opt::run(f); }
 }
 
-/// Lower from AST to our IR with basic support for Print and For/Next and string concatenation.
-pub fn lower_to_ir(prog: &ast::Program) -> Module {
-    let mut m = Module::default();
-    for stmt in prog { lower_stmt(stmt, &mut m.main.body); }
-    m
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(src: &str) -> Result<Module> {
+        lower_to_ir(&basil_frontend::parse_program(src).expect("parse"))
+    }
+
+    #[test]
+    fn resolves_function_variables_like_the_compiler() {
+        let m = lower(concat!(
+            "LET total = 0;\n",
+            "FUNC Add(a, b)\nBEGIN\n  LET total = total + a;\n  LET tmp = a + b;\n  RETURN tmp;\nEND\n",
+            "PRINT Add(1, 2);\n",
+        )).unwrap();
+        assert_eq!(m.globals, vec!["total".to_string()]);
+        let add = &m.functions[0];
+        assert_eq!(add.locals, vec!["a".to_string(), "b".to_string(), "tmp".to_string()]);
+        let assigns: Vec<Var> = add.blocks.iter().flat_map(|b| &b.instrs).filter_map(|i| match i { Instr::Assign(v, _) => Some(*v), _ => None }).collect();
+        assert_eq!(assigns, vec![Var::Global(0), Var::Local(2)]);
+    }
+
+    #[test]
+    fn unsupported_constructs_are_errors() {
+        for (src, want) in [
+            ("LET x = EVAL(\"1+1\");\n", "EVAL is not supported"),
+            ("EXEC(\"PRINT 1;\");\n", "EXEC is not supported"),
+            ("LET k$ = INKEY$();\n", "builtin INKEY$ is not supported"),
+            ("LET x = NOPE();\n", "unknown function NOPE"),
+            ("GOTO nowhere;\n", "Undefined label: nowhere"),
+        ] {
+            let err = lower(src).unwrap_err();
+            assert!(err.0.contains(want), "{}: {}", src.trim(), err.0);
+        }
+    }
+
+    fn optimized(src: &str) -> Module {
+        let mut m = lower(src).unwrap();
+        optimize(&mut m);
+        m
+    }
+
+    fn instrs(f: &Function) -> impl Iterator<Item = (BlockId, &Instr)> {
+        f.blocks.iter().enumerate().flat_map(|(b, block)| block.instrs.iter().map(move |i| (b, i)))
+    }
+
+    #[test]
+    fn numeric_loops_become_typed_values() {
+        let m = optimized("LET s = 0;\nFOR i = 1 TO 10\n  LET s = s + i * 2;\nNEXT\nPRINTLN s;\n");
+        let main = &m.main;
+        assert!(instrs(main).all(|(_, i)| !matches!(i, Instr::Assign(..))), "{:?}", main.blocks);
+        let phis: Vec<_> = main.blocks.iter().flat_map(|b| &b.phis).collect();
+        assert_eq!(phis.len(), 2);
+        assert!(phis.iter().all(|p| main.values[p.value] == Ty::Num));
+    }
+
+    #[test]
+    fn constant_branches_are_folded() {
+        let m = optimized("LET debug = 0;\nIF debug THEN PRINTLN \"debug\"; ELSE PRINTLN \"quiet\";\n");
+        let main = &m.main;
+        assert!(main.blocks.iter().all(|b| !matches!(b.term, Terminator::Branch { .. })));
+        let printed: Vec<&Instr> = instrs(main).map(|(_, i)| i).filter(|i| matches!(i, Instr::Print(_))).collect();
+        assert_eq!(printed.len(), 1);
+        assert!(matches!(printed[0], Instr::Print(Expr::Str(s)) if s == "quiet\n"), "{:?}", printed);
+    }
+
+    #[test]
+    fn loop_invariant_arithmetic_is_hoisted() {
+        let m = optimized(concat!(
+            "FUNC Total(a$)\nBEGIN\n  LET n = LEN(a$);\n  LET s = 0;\n",
+            "  FOR i = 1 TO 10\n    LET s = s + n * 2;\n  NEXT\n  RETURN s;\nEND\n",
+            "PRINTLN Total(\"abc\");\n",
+        ));
+        let f = &m.functions[0];
+        let block_of = |want: &dyn Fn(&Expr) -> bool| instrs(f).find(|(_, i)| matches!(i, Instr::Def(_, e) if want(e))).map(|(b, _)| b);
+        let len = block_of(&|e| matches!(e, Expr::Builtin { name: "LEN", .. }));
+        let mul = block_of(&|e| matches!(e, Expr::Binary(BinOp::Mul, ..)));
+        assert!(len.is_some());
+        assert_eq!(mul, len, "{:?}", f.blocks);
+    }
+
+    #[test]
+    fn variables_assigned_inside_try_stay_in_slots() {
+        let m = optimized("LET stage = 0;\nTRY\n  LET stage = 1;\n  RAISE \"boom\";\nCATCH e$\n  PRINTLN stage;\nEND TRY\n");
+        let slot = m.globals.iter().position(|g| g == "stage").unwrap();
+        assert!(instrs(&m.main).any(|(_, i)| matches!(i, Instr::Assign(Var::Global(g), _) if *g == slot)));
+    }
 }
@@ -0,0 +1,374 @@
+//! Optimization passes over SSA-form functions (see [`crate::ssa`]).
+//!
+//! Every pass keeps the VM's behaviour, error messages included: an expression is only folded,
+//! dropped or moved when it can't fail and has no side effects, and folding computes exactly what
+//! the runtime would (arithmetic on Int and Bool gives a Num, `+` with a string concatenates the
+//! printed forms).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ssa;
+use crate::{BinOp, BlockId, Expr, Function, Instr, Terminator, Ty, ValueId};
+
+/// Constant propagation and dead-code elimination to a fixed point, then loop-invariant code
+/// motion, then retyping the values the passes touched.
+pub fn run(f: &mut Function) {
+    for _ in 0..8 {
+        let folded = const_prop(f);
+        let removed = dce(f);
+        if !folded && !removed { break; }
+    }
+    if licm(f) { dce(f); }
+    ssa::infer_types(f);
+}
+
+fn is_literal(e: &Expr) -> bool {
+    matches!(e, Expr::Null | Expr::Bool(_) | Expr::Num(_) | Expr::Int(_) | Expr::Str(_))
+}
+
+fn num_of(e: &Expr) -> Option<f64> {
+    match e {
+        Expr::Num(n) => Some(*n),
+        Expr::Int(i) => Some(*i as f64),
+        Expr::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+fn truth(e: &Expr) -> Option<bool> {
+    match e {
+        Expr::Null => Some(false),
+        Expr::Bool(b) => Some(*b),
+        Expr::Num(n) => Some(*n != 0.0),
+        Expr::Int(i) => Some(*i != 0),
+        Expr::Str(s) => Some(!s.is_empty()),
+        _ => None,
+    }
+}
+
+// The literal as PRINT shows it
+fn display(e: &Expr) -> Option<String> {
+    match e {
+        Expr::Null => Some("null".into()),
+        Expr::Bool(b) => Some(b.to_string()),
+        Expr::Num(n) => Some(n.to_string()),
+        Expr::Int(i) => Some(i.to_string()),
+        Expr::Str(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// The value of a node whose operands are already folded, when it is a known constant
+fn fold(e: &Expr) -> Option<Expr> {
+    match e {
+        Expr::Neg(a) => num_of(a).map(|n| Expr::Num(-n)),
+        Expr::Not(a) => truth(a).map(|t| Expr::Bool(!t)),
+        Expr::And(a, b) => match truth(a)? {
+            false => Some(Expr::Bool(false)),
+            true => truth(b).map(Expr::Bool),
+        },
+        Expr::Or(a, b) => match truth(a)? {
+            true => Some(Expr::Bool(true)),
+            false => truth(b).map(Expr::Bool),
+        },
+        Expr::ToInt(a) => match &**a {
+            Expr::Int(i) => Some(Expr::Int(*i)),
+            Expr::Num(n) => Some(Expr::Int(n.trunc() as i64)),
+            _ => None,
+        },
+        Expr::Binary(op, a, b) => {
+            if !is_literal(a) || !is_literal(b) { return None; }
+            if *op == BinOp::Add && (matches!(**a, Expr::Str(_)) || matches!(**b, Expr::Str(_))) {
+                return Some(Expr::Str(display(a)? + &display(b)?));
+            }
+            match (op, num_of(a), num_of(b)) {
+                (BinOp::Eq, Some(x), Some(y)) => Some(Expr::Bool(x == y)),
+                (BinOp::Ne, Some(x), Some(y)) => Some(Expr::Bool(x != y)),
+                // Anything else compares as values: same variant and contents
+                (BinOp::Eq, _, _) => Some(Expr::Bool(a == b)),
+                (BinOp::Ne, _, _) => Some(Expr::Bool(a != b)),
+                (_, Some(x), Some(y)) => Some(match op {
+                    BinOp::Add => Expr::Num(x + y),
+                    BinOp::Sub => Expr::Num(x - y),
+                    BinOp::Mul => Expr::Num(x * y),
+                    BinOp::Div => Expr::Num(x / y),
+                    BinOp::Mod => Expr::Num(x % y),
+                    BinOp::Lt => Expr::Bool(x < y),
+                    BinOp::Le => Expr::Bool(x <= y),
+                    BinOp::Gt => Expr::Bool(x > y),
+                    BinOp::Ge => Expr::Bool(x >= y),
+                    BinOp::Eq | BinOp::Ne => unreachable!("handled above"),
+                }),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn fold_expr(e: &mut Expr, consts: &HashMap<ValueId, Expr>) -> bool {
+    let mut changed = false;
+    e.visit_mut(&mut |e| {
+        if let Expr::Value(v) = e {
+            if let Some(c) = consts.get(v) { *e = c.clone(); changed = true; }
+        } else if let Some(c) = fold(e) {
+            *e = c;
+            changed = true;
+        }
+    });
+    changed
+}
+
+/// Fold constant expressions, replace values defined as constants by the constant, and turn
+/// branches on a constant into jumps. Returns whether anything changed.
+pub fn const_prop(f: &mut Function) -> bool {
+    let mut any = false;
+    loop {
+        let mut consts: HashMap<ValueId, Expr> = HashMap::new();
+        for block in &f.blocks {
+            for i in &block.instrs {
+                if let Instr::Def(v, e) = i { if is_literal(e) { consts.insert(*v, e.clone()); } }
+            }
+        }
+        // A phi of one constant on every path is that constant
+        for block in f.blocks.iter_mut() {
+            let mut defs = Vec::new();
+            block.phis.retain(|phi| {
+                let first = phi.args.first().and_then(|(_, a)| consts.get(a));
+                match first {
+                    Some(c) if phi.args.iter().all(|(_, a)| consts.get(a) == Some(c)) => {
+                        defs.push(Instr::Def(phi.value, c.clone()));
+                        false
+                    }
+                    _ => true,
+                }
+            });
+            for d in defs.into_iter().rev() {
+                if let Instr::Def(v, e) = &d { consts.insert(*v, e.clone()); }
+                block.instrs.insert(0, d);
+            }
+        }
+        let mut changed = false;
+        for block in f.blocks.iter_mut() {
+            for i in block.instrs.iter_mut() {
+                let keep = if let Instr::Def(v, _) = i { consts.contains_key(v) } else { false };
+                if keep { continue; }
+                for e in i.exprs_mut() { changed |= fold_expr(e, &consts); }
+            }
+            if let Some(e) = block.term.expr_mut() { changed |= fold_expr(e, &consts); }
+            if let Terminator::Branch { cond, then_to, else_to } = &block.term {
+                if let Some(t) = truth(cond) {
+                    block.term = Terminator::Jump(if t { *then_to } else { *else_to });
+                    changed = true;
+                }
+            }
+        }
+        if !changed { return any; }
+        any = true;
+    }
+}
+
+/// Whether evaluating `e` can neither fail nor have a side effect, so it may be dropped or
+/// evaluated early. Allocating a list or dict counts as pure here; `licm` excludes them.
+pub fn is_pure(e: &Expr, values: &[Ty]) -> bool {
+    let num = |a: &Expr| a.ty(values).is_numeric();
+    let ok = match e {
+        Expr::Null | Expr::Bool(_) | Expr::Num(_) | Expr::Int(_) | Expr::Str(_) | Expr::Value(_) | Expr::Var(_) | Expr::Exception => true,
+        Expr::Not(_) | Expr::And(..) | Expr::Or(..) | Expr::List(_) | Expr::Dict(_) => true,
+        // rt::eq and rt::ne compare any two values
+        Expr::Binary(BinOp::Eq | BinOp::Ne, _, _) => true,
+        Expr::Binary(BinOp::Add, a, b) => (num(a) && num(b)) || a.ty(values) == Ty::Str || b.ty(values) == Ty::Str,
+        Expr::Binary(_, a, b) => num(a) && num(b),
+        Expr::Neg(a) => num(a),
+        Expr::ToInt(a) => matches!(a.ty(values), Ty::Int | Ty::Num),
+        _ => false,
+    };
+    ok && e.children().into_iter().all(|c| is_pure(c, values))
+}
+
+// Prune phi args from blocks that no longer jump to the phi's block
+fn prune_phis(f: &mut Function) {
+    let preds = ssa::predecessors(&ssa::successors(f));
+    for (b, block) in f.blocks.iter_mut().enumerate() {
+        for phi in block.phis.iter_mut() { phi.args.retain(|(p, _)| preds[b].contains(p)); }
+    }
+}
+
+// Append a block to its predecessor when that is the only way in and it jumps straight there
+fn merge_blocks(f: &mut Function) {
+    loop {
+        let preds = ssa::predecessors(&ssa::successors(f));
+        let handlers: HashSet<BlockId> = f.blocks.iter().flat_map(|b| &b.instrs).filter_map(|i| match i { Instr::TryPush(h) => Some(*h), _ => None }).collect();
+        let found = f.blocks.iter().enumerate().find_map(|(b, block)| match block.term {
+            Terminator::Jump(s) if s != b && s != 0 && preds[s] == [b] && !handlers.contains(&s) && f.blocks[s].phis.is_empty() => Some((b, s)),
+            _ => None,
+        });
+        let Some((b, s)) = found else { return };
+        let next = std::mem::replace(&mut f.blocks[s].term, Terminator::Halt);
+        let instrs = std::mem::take(&mut f.blocks[s].instrs);
+        f.blocks[b].instrs.extend(instrs);
+        f.blocks[b].term = next;
+        for block in f.blocks.iter_mut() {
+            for phi in block.phis.iter_mut() {
+                for (p, _) in phi.args.iter_mut() { if *p == s { *p = b; } }
+            }
+        }
+    }
+}
+
+/// Remove unreachable blocks, values nothing uses whose definition is pure, phis nothing uses,
+/// and line markers that are immediately superseded, and merge straight-line chains of blocks.
+/// Returns whether anything changed.
+pub fn dce(f: &mut Function) -> bool {
+    let before = (f.blocks.len(), f.blocks.iter().map(|b| b.instrs.len() + b.phis.len()).sum::<usize>());
+    merge_blocks(f);
+    ssa::remove_unreachable(f);
+    prune_phis(f);
+    ssa::remove_trivial_phis(f);
+
+    // Live values: read by an instruction or terminator that stays, or by a live value
+    let mut live: HashSet<ValueId> = HashSet::new();
+    let mut defs: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+    for block in &f.blocks {
+        for phi in &block.phis { defs.insert(phi.value, phi.args.iter().map(|(_, a)| *a).collect()); }
+        for i in &block.instrs {
+            match i {
+                Instr::Def(v, e) if is_pure(e, &f.values) => {
+                    let mut uses = Vec::new();
+                    e.for_each_value(&mut |u| uses.push(u));
+                    defs.insert(*v, uses);
+                }
+                other => for e in other.exprs() { e.for_each_value(&mut |u| { live.insert(u); }); },
+            }
+        }
+        if let Some(e) = block.term.expr() { e.for_each_value(&mut |u| { live.insert(u); }); }
+    }
+    let mut work: Vec<ValueId> = live.iter().copied().collect();
+    while let Some(v) = work.pop() {
+        for &u in defs.get(&v).map(|d| d.as_slice()).unwrap_or(&[]) {
+            if live.insert(u) { work.push(u); }
+        }
+    }
+    for block in f.blocks.iter_mut() {
+        block.phis.retain(|phi| live.contains(&phi.value));
+        let values = &f.values;
+        block.instrs.retain(|i| match i {
+            Instr::Def(v, e) => live.contains(v) || !is_pure(e, values),
+            _ => true,
+        });
+        let mut i = 0;
+        while i + 1 < block.instrs.len() {
+            if matches!(block.instrs[i], Instr::Line(_)) && matches!(block.instrs[i + 1], Instr::Line(_)) {
+                block.instrs.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+    before != (f.blocks.len(), f.blocks.iter().map(|b| b.instrs.len() + b.phis.len()).sum::<usize>())
+}
+
+/// Move loop-invariant arithmetic out of loops: definitions and subexpressions that are pure
+/// and only read values from outside the loop are computed once, before it. Only loops entered
+/// from a single block that jumps straight to the header are touched. Returns whether anything
+/// moved.
+pub fn licm(f: &mut Function) -> bool {
+    let succs = ssa::successors(f);
+    let preds = ssa::predecessors(&succs);
+    let idom = ssa::dominators(&succs);
+
+    // Natural loops, one per header, innermost (smallest) first
+    let mut loops: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+    for (b, ss) in succs.iter().enumerate() {
+        if idom[b].is_none() { continue; }
+        for &h in ss {
+            if !ssa::dominates(&idom, h, b) { continue; }
+            let body = loops.entry(h).or_insert_with(|| HashSet::from([h]));
+            let mut work = vec![b];
+            while let Some(x) = work.pop() {
+                if body.insert(x) { work.extend(preds[x].iter().copied()); }
+            }
+        }
+    }
+    let mut loops: Vec<(BlockId, HashSet<BlockId>)> = loops.into_iter().collect();
+    loops.sort_by_key(|(h, body)| (body.len(), *h));
+
+    let mut def_block: Vec<Option<BlockId>> = vec![None; f.values.len()];
+    for (b, block) in f.blocks.iter().enumerate() {
+        for phi in &block.phis { def_block[phi.value] = Some(b); }
+        for i in &block.instrs { if let Instr::Def(v, _) = i { def_block[*v] = Some(b); } }
+    }
+
+    let mut moved = false;
+    for (h, body) in &loops {
+        let outside: Vec<BlockId> = preds[*h].iter().copied().filter(|p| !body.contains(p)).collect();
+        let [pre] = outside[..] else { continue };
+        if f.blocks[pre].term != Terminator::Jump(*h) { continue; }
+        let mut order: Vec<BlockId> = body.iter().copied().collect();
+        order.sort();
+
+        // Whole definitions first, repeating so chains of invariant values all move
+        loop {
+            let mut hoisted = false;
+            for &b in &order {
+                let mut i = 0;
+                while i < f.blocks[b].instrs.len() {
+                    let movable = match &f.blocks[b].instrs[i] {
+                        Instr::Def(_, e) => invariant(e, &f.values, &def_block, body),
+                        _ => false,
+                    };
+                    if movable {
+                        let def = f.blocks[b].instrs.remove(i);
+                        if let Instr::Def(v, _) = &def { def_block[*v] = Some(pre); }
+                        f.blocks[pre].instrs.push(def);
+                        hoisted = true;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            if !hoisted { break; }
+            moved = true;
+        }
+
+        // Then invariant parts of what stays, each into a new value
+        let mut hoist: Vec<Instr> = Vec::new();
+        let Function { blocks, values, .. } = f;
+        for &b in &order {
+            let block = &mut blocks[b];
+            let mut exprs: Vec<&mut Expr> = block.instrs.iter_mut().flat_map(|i| i.exprs_mut()).collect();
+            exprs.extend(block.term.expr_mut());
+            for e in exprs { hoist_parts(e, values, &mut def_block, body, pre, &mut hoist); }
+        }
+        if !hoist.is_empty() {
+            blocks[pre].instrs.extend(hoist);
+            moved = true;
+        }
+    }
+    moved
+}
+
+// Pure, reads only values defined outside `body`, and worth a value of its own
+fn invariant(e: &Expr, values: &[Ty], def_block: &[Option<BlockId>], body: &HashSet<BlockId>) -> bool {
+    fn reads_outside(e: &Expr, def_block: &[Option<BlockId>], body: &HashSet<BlockId>) -> bool {
+        match e {
+            Expr::Var(_) | Expr::Exception | Expr::List(_) | Expr::Dict(_) => false,
+            Expr::Value(v) => def_block[*v].is_some_and(|b| !body.contains(&b)),
+            other => other.children().into_iter().all(|c| reads_outside(c, def_block, body)),
+        }
+    }
+    !is_literal(e) && !matches!(e, Expr::Value(_)) && is_pure(e, values) && reads_outside(e, def_block, body)
+}
+
+fn hoist_parts(e: &mut Expr, values: &mut Vec<Ty>, def_block: &mut Vec<Option<BlockId>>, body: &HashSet<BlockId>, pre: BlockId, out: &mut Vec<Instr>) {
+    if invariant(e, values, def_block, body) {
+        let v = values.len();
+        values.push(e.ty(values));
+        def_block.push(Some(pre));
+        out.push(Instr::Def(v, std::mem::replace(e, Expr::Value(v))));
+        return;
+    }
+    // Short-circuit operands only run sometimes; leave them where they are
+    if matches!(e, Expr::And(..) | Expr::Or(..)) { return; }
+    for c in e.children_mut() { hoist_parts(c, values, def_block, body, pre, out); }
+}
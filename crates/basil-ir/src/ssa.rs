@@ -0,0 +1,378 @@
+//! SSA construction for basil-ir functions.
+//!
+//! `build` promotes every variable nothing outside the function can see (a FUNC's locals, and
+//! the globals no FUNC or SUB touches in the top level) from a slot to SSA values: each
+//! assignment defines a new value, reads name the value that reaches them, and blocks where
+//! different values meet get a [`Phi`]. Phis go on the iterated dominance frontier (Cytron et
+//! al.), and a variable that may be read before it is assigned starts as a Null value, so its
+//! type is only known when every path assigns it.
+//!
+//! A variable assigned while a TRY handler may be active stays a slot: a RAISE can leave the
+//! block anywhere, and the handler must see the variable as it was at that point.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{BlockId, Expr, Function, Instr, Module, Phi, Terminator, Ty, ValueId, Var};
+
+/// Globals that no FUNC or SUB reads or writes, which the top level may promote
+pub fn private_globals(m: &Module) -> HashSet<usize> {
+    let mut shared = HashSet::new();
+    for f in &m.functions {
+        for b in &f.blocks {
+            let mut exprs: Vec<&Expr> = b.term.expr().into_iter().collect();
+            for i in &b.instrs {
+                if let Instr::Assign(Var::Global(g), _) = i { shared.insert(*g); }
+                exprs.extend(i.exprs());
+            }
+            for e in exprs { visit_vars(e, &mut |v| { if let Var::Global(g) = v { shared.insert(g); } }); }
+        }
+    }
+    (0..m.globals.len()).filter(|g| !shared.contains(g)).collect()
+}
+
+fn visit_vars(e: &Expr, f: &mut impl FnMut(Var)) {
+    if let Expr::Var(v) = e { f(*v); }
+    for c in e.children() { visit_vars(c, f); }
+}
+
+/// Control-flow successors of every block. Besides the terminator's targets, a TRY handler is
+/// entered from the block that pushed it, and a GOSUB's RETURN may go to any GOSUB's return
+/// block. A GOSUB also lists its own return block, which only adds a path no value flows along.
+pub fn successors(f: &Function) -> Vec<Vec<BlockId>> {
+    let rets: Vec<BlockId> = f.blocks.iter().filter_map(|b| match b.term { Terminator::Gosub { ret, .. } => Some(ret), _ => None }).collect();
+    f.blocks.iter().map(|b| {
+        let mut out: Vec<BlockId> = match &b.term {
+            Terminator::Jump(t) | Terminator::GosubReturnTo(t) => vec![*t],
+            Terminator::Branch { then_to, else_to, .. } => vec![*then_to, *else_to],
+            Terminator::Gosub { target, ret } => vec![*target, *ret],
+            Terminator::GosubReturn => rets.clone(),
+            _ => Vec::new(),
+        };
+        out.extend(b.instrs.iter().filter_map(|i| match i { Instr::TryPush(h) => Some(*h), _ => None }));
+        let mut seen = HashSet::new();
+        out.retain(|s| seen.insert(*s));
+        out
+    }).collect()
+}
+
+pub fn predecessors(succs: &[Vec<BlockId>]) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); succs.len()];
+    for (b, ss) in succs.iter().enumerate() {
+        for &s in ss { preds[s].push(b); }
+    }
+    preds
+}
+
+/// Reachable blocks in reverse postorder from the entry block
+pub fn reverse_postorder(succs: &[Vec<BlockId>]) -> Vec<BlockId> {
+    let mut order = Vec::with_capacity(succs.len());
+    let mut seen = vec![false; succs.len()];
+    let mut stack = vec![(0usize, 0usize)];
+    seen[0] = true;
+    while let Some(&mut (b, ref mut next)) = stack.last_mut() {
+        if let Some(&s) = succs[b].get(*next) {
+            *next += 1;
+            if !seen[s] { seen[s] = true; stack.push((s, 0)); }
+        } else {
+            order.push(b);
+            stack.pop();
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// Immediate dominator of every reachable block (the entry block is its own), computed with
+/// Cooper, Harvey and Kennedy's iterative algorithm. Unreachable blocks get None.
+pub fn dominators(succs: &[Vec<BlockId>]) -> Vec<Option<BlockId>> {
+    let preds = predecessors(succs);
+    let rpo = reverse_postorder(succs);
+    let mut index = vec![usize::MAX; succs.len()];
+    for (i, &b) in rpo.iter().enumerate() { index[b] = i; }
+    let mut idom: Vec<Option<BlockId>> = vec![None; succs.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in rpo.iter().skip(1) {
+            let mut new: Option<BlockId> = None;
+            for &p in &preds[b] {
+                if idom[p].is_none() { continue; }
+                new = Some(match new {
+                    None => p,
+                    Some(mut a) => {
+                        let mut c = p;
+                        while a != c {
+                            while index[a] > index[c] { a = idom[a].expect("processed"); }
+                            while index[c] > index[a] { c = idom[c].expect("processed"); }
+                        }
+                        a
+                    }
+                });
+            }
+            if new.is_some() && idom[b] != new { idom[b] = new; changed = true; }
+        }
+    }
+    idom
+}
+
+pub fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+    loop {
+        if a == b { return true; }
+        match idom[b] {
+            Some(p) if p != b => b = p,
+            _ => return false,
+        }
+    }
+}
+
+/// Drop blocks the entry can't reach and renumber the rest, keeping the entry as block 0
+pub fn remove_unreachable(f: &mut Function) {
+    let succs = successors(f);
+    let mut reach = vec![false; f.blocks.len()];
+    for b in reverse_postorder(&succs) { reach[b] = true; }
+    if reach.iter().all(|r| *r) { return; }
+    let mut remap = vec![usize::MAX; f.blocks.len()];
+    let mut n = 0;
+    for (b, r) in reach.iter().enumerate() {
+        if *r { remap[b] = n; n += 1; }
+    }
+    let blocks = std::mem::take(&mut f.blocks);
+    for (b, mut block) in blocks.into_iter().enumerate() {
+        if !reach[b] { continue; }
+        for t in block.term.targets_mut() { *t = remap[*t]; }
+        for i in block.instrs.iter_mut() {
+            if let Instr::TryPush(h) = i { *h = remap[*h]; }
+        }
+        for phi in block.phis.iter_mut() {
+            phi.args.retain(|(p, _)| reach[*p]);
+            for (p, _) in phi.args.iter_mut() { *p = remap[*p]; }
+        }
+        f.blocks.push(block);
+    }
+}
+
+// Handler-stack depths a point may run at, as a bit set: bit n is depth n, bit 15 is 15 or more
+const DEPTH_0: u16 = 1;
+
+fn push_depth(m: u16) -> u16 { ((m & 0x7FFF) << 1) | (m & 0x8000) }
+
+fn pop_depth(m: u16) -> u16 {
+    // Popping an empty stack leaves it empty, as `handlers.pop()` does
+    let mut r = ((m & 0x7FFE) >> 1) | (m & DEPTH_0);
+    if m & 0x8000 != 0 { r |= 0xC000; }
+    r
+}
+
+// Variables assigned somewhere a TRY handler may be active
+fn assigned_in_try(f: &Function, succs: &[Vec<BlockId>]) -> HashSet<Var> {
+    let mut entry = vec![0u16; f.blocks.len()];
+    entry[0] = DEPTH_0;
+    let mut work = vec![0usize];
+    let mut out = HashSet::new();
+    while let Some(b) = work.pop() {
+        let mut m = entry[b];
+        let mut flow = |to: BlockId, m: u16, work: &mut Vec<usize>| {
+            if entry[to] | m != entry[to] { entry[to] |= m; work.push(to); }
+        };
+        for i in &f.blocks[b].instrs {
+            match i {
+                Instr::TryPush(h) => { m = push_depth(m); flow(*h, m, &mut work); }
+                Instr::TryPop => m = pop_depth(m),
+                Instr::Assign(v, _) if m & !DEPTH_0 != 0 => { out.insert(*v); }
+                _ => {}
+            }
+        }
+        let handlers: Vec<BlockId> = f.blocks[b].instrs.iter().filter_map(|i| match i { Instr::TryPush(h) => Some(*h), _ => None }).collect();
+        for &s in &succs[b] {
+            if !handlers.contains(&s) { flow(s, m, &mut work); }
+        }
+    }
+    out
+}
+
+/// Rewrite `f` into SSA form and infer the type of every value. `globals` are the globals this
+/// function may promote (see [`private_globals`]); its locals are always candidates.
+pub fn build(f: &mut Function, globals: &HashSet<usize>) {
+    remove_unreachable(f);
+    let succs = successors(f);
+    debug_assert!(succs.iter().all(|s| !s.contains(&0)), "nothing jumps back to the entry block");
+    let no_promote = assigned_in_try(f, &succs);
+    let promotable = |v: &Var| !no_promote.contains(v) && match v { Var::Local(_) => true, Var::Global(g) => globals.contains(g) };
+
+    // Every promoted variable, with the blocks that assign it
+    let mut vars: Vec<Var> = Vec::new();
+    let mut def_blocks: HashMap<Var, Vec<BlockId>> = HashMap::new();
+    for l in 0..f.locals.len() { let v = Var::Local(l); if promotable(&v) { vars.push(v); def_blocks.insert(v, vec![0]); } }
+    for &g in globals { let v = Var::Global(g); if promotable(&v) { vars.push(v); def_blocks.insert(v, vec![0]); } }
+    vars.sort_by_key(|v| match v { Var::Local(i) => (0, *i), Var::Global(i) => (1, *i) });
+    for (b, block) in f.blocks.iter().enumerate() {
+        for i in &block.instrs {
+            if let Instr::Assign(v, _) = i {
+                if let Some(ds) = def_blocks.get_mut(v) { if ds.last() != Some(&b) { ds.push(b); } }
+            }
+        }
+    }
+    if vars.is_empty() { return; }
+
+    let idom = dominators(&succs);
+    let preds = predecessors(&succs);
+    let mut frontier: Vec<HashSet<BlockId>> = vec![HashSet::new(); f.blocks.len()];
+    for (b, ps) in preds.iter().enumerate() {
+        if ps.len() < 2 { continue; }
+        for &p in ps {
+            let mut runner = p;
+            while Some(runner) != idom[b] {
+                frontier[runner].insert(b);
+                match idom[runner] { Some(up) if up != runner => runner = up, _ => break }
+            }
+        }
+    }
+
+    // Phis on the iterated dominance frontier of each variable's assignments
+    let mut next: ValueId = f.values.len();
+    let mut phi_var: HashMap<ValueId, Var> = HashMap::new();
+    for &v in &vars {
+        let mut has_phi: HashSet<BlockId> = HashSet::new();
+        let mut work = def_blocks[&v].clone();
+        while let Some(b) = work.pop() {
+            for &d in &frontier[b] {
+                if has_phi.insert(d) {
+                    f.blocks[d].phis.push(Phi { value: next, args: Vec::new() });
+                    phi_var.insert(next, v);
+                    next += 1;
+                    work.push(d);
+                }
+            }
+        }
+    }
+
+    // The values variables start with: the argument for a parameter, else Null
+    let mut entry: Vec<Instr> = Vec::new();
+    let mut current: HashMap<Var, Vec<ValueId>> = HashMap::new();
+    for &v in &vars {
+        let init = match v { Var::Local(i) if i < f.params => Expr::Var(v), _ => Expr::Null };
+        entry.push(Instr::Def(next, init));
+        current.insert(v, vec![next]);
+        next += 1;
+    }
+    f.blocks[0].instrs.splice(0..0, entry);
+
+    // Rename along the dominator tree
+    let mut children: Vec<Vec<BlockId>> = vec![Vec::new(); f.blocks.len()];
+    for (b, d) in idom.iter().enumerate().skip(1) {
+        if let Some(p) = *d { children[p].push(b); }
+    }
+    enum Step { Enter(BlockId), Leave(Vec<Var>) }
+    let mut stack = vec![Step::Enter(0)];
+    while let Some(step) = stack.pop() {
+        let b = match step {
+            Step::Leave(pushed) => { for v in pushed { current.get_mut(&v).expect("promoted").pop(); } continue; }
+            Step::Enter(b) => b,
+        };
+        let mut pushed: Vec<Var> = Vec::new();
+        for phi in &f.blocks[b].phis {
+            let v = phi_var[&phi.value];
+            current.get_mut(&v).expect("promoted").push(phi.value);
+            pushed.push(v);
+        }
+        let read = |e: &mut Expr, current: &HashMap<Var, Vec<ValueId>>| e.visit_mut(&mut |e| {
+            if let Expr::Var(v) = e {
+                if let Some(stack) = current.get(v) { *e = Expr::Value(*stack.last().expect("defined at entry")); }
+            }
+        });
+        let skip = if b == 0 { vars.len() } else { 0 };
+        for i in f.blocks[b].instrs.iter_mut().skip(skip) {
+            for e in i.exprs_mut() { read(e, &current); }
+            if let Instr::Assign(v, e) = i {
+                if current.contains_key(v) {
+                    let var = *v;
+                    *i = Instr::Def(next, std::mem::replace(e, Expr::Null));
+                    current.get_mut(&var).expect("promoted").push(next);
+                    pushed.push(var);
+                    next += 1;
+                }
+            }
+        }
+        if let Some(e) = f.blocks[b].term.expr_mut() { read(e, &current); }
+        for &s in &succs[b] {
+            for phi in f.blocks[s].phis.iter_mut() {
+                let v = phi_var[&phi.value];
+                phi.args.push((b, *current[&v].last().expect("defined at entry")));
+            }
+        }
+        stack.push(Step::Leave(pushed));
+        for &c in children[b].iter().rev() { stack.push(Step::Enter(c)); }
+    }
+
+    f.values.resize(next, Ty::Dyn);
+    remove_trivial_phis(f);
+    infer_types(f);
+}
+
+/// Replace phis whose args are all one value (or the phi itself) by that value
+pub fn remove_trivial_phis(f: &mut Function) {
+    loop {
+        let mut subst: HashMap<ValueId, ValueId> = HashMap::new();
+        for block in f.blocks.iter_mut() {
+            block.phis.retain(|phi| {
+                let mut same: Option<ValueId> = None;
+                for &(_, a) in &phi.args {
+                    if a == phi.value || Some(a) == same { continue; }
+                    if same.is_some() { return true; }
+                    same = Some(a);
+                }
+                match same {
+                    Some(s) => { subst.insert(phi.value, s); false }
+                    None => true,
+                }
+            });
+        }
+        if subst.is_empty() { return; }
+        replace_values(f, &subst);
+    }
+}
+
+/// Rename uses of SSA values: every read of a key in `subst` reads its value instead
+pub fn replace_values(f: &mut Function, subst: &HashMap<ValueId, ValueId>) {
+    let find = |mut v: ValueId| { while let Some(&n) = subst.get(&v) { if n == v { break; } v = n; } v };
+    for block in f.blocks.iter_mut() {
+        for phi in block.phis.iter_mut() {
+            for (_, a) in phi.args.iter_mut() { *a = find(*a); }
+        }
+        let fix = |e: &mut Expr| e.visit_mut(&mut |e| if let Expr::Value(v) = e { *v = find(*v); });
+        for i in block.instrs.iter_mut() { for e in i.exprs_mut() { fix(e); } }
+        if let Some(e) = block.term.expr_mut() { fix(e); }
+    }
+}
+
+/// Recompute the type of every value from its definition. Phis are typed optimistically: a
+/// loop-carried value keeps its type unless something assigns it a different one.
+pub fn infer_types(f: &mut Function) {
+    // `known[v]` is false until some definition of v has been typed; `ty` is meaningless until then
+    let mut known = vec![false; f.values.len()];
+    let mut ty = vec![Ty::Dyn; f.values.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut set = |v: ValueId, t: Ty, known: &mut Vec<bool>, ty: &mut Vec<Ty>| {
+            let new = if known[v] { ty[v].join(t) } else { t };
+            if !known[v] || ty[v] != new { known[v] = true; ty[v] = new; changed = true; }
+        };
+        for block in &f.blocks {
+            for phi in &block.phis {
+                for &(_, a) in &phi.args {
+                    if known[a] { set(phi.value, ty[a], &mut known, &mut ty); }
+                }
+            }
+            for i in &block.instrs {
+                if let Instr::Def(v, e) = i {
+                    let mut ready = true;
+                    e.for_each_value(&mut |u| ready &= known[u]);
+                    if ready { let t = e.ty(&ty); set(*v, t, &mut known, &mut ty); }
+                }
+            }
+        }
+    }
+    f.values = ty;
+}
@@ -1,55 +1,364 @@
-//! Minimal runtime API used by AOT-emitted Rust. This is a local development
-//! crate; published builds will use the crates.io version.
+//! Runtime used by AOT-emitted Rust. Values are the VM's own `Value`, every operation here
+//! mirrors the VM instruction of the same name, error messages included, and builtins are the
+//! VM's own implementations from `basil-builtins`, so a compiled program behaves like
+//! `basilc run`. This is a local development crate; published builds will use the crates.io
+//! version.
 
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
 
-#[derive(Clone)]
-pub struct Str(String);
+use basil_builtins::files::FileTable;
+use basil_common::BasilError;
 
-impl Str {
-    pub fn from_static(s: &'static str) -> Str { Str(s.to_string()) }
-    pub fn from_string(s: String) -> Str { Str(s) }
+pub use basil_bytecode::{ArrayObj, ElemType, Value as Val};
+
+/// A runtime error. Only errors raised by RAISE can be caught by TRY; the rest end the program.
+#[derive(Debug)]
+pub struct RtError { msg: String, raised: bool }
+
+impl RtError {
+    pub fn new(msg: impl Into<String>) -> Self { RtError { msg: msg.into(), raised: false } }
+    pub fn is_raise(&self) -> bool { self.raised }
+    pub fn message(&self) -> &str { &self.msg }
+}
+
+impl fmt::Display for RtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.msg) }
+}
+
+impl std::error::Error for RtError {}
+
+impl From<BasilError> for RtError {
+    fn from(e: BasilError) -> Self { RtError::new(e.0) }
+}
+
+pub type RtResult<T> = Result<T, RtError>;
+
+/// Where a basic block goes next, returned by the block dispatcher of every emitted function.
+pub enum Flow { Goto(usize), Return(Val) }
+
+thread_local! {
+    static LINE: Cell<u32> = const { Cell::new(0) };
+    // Call depth, 1 in the main program, so a function's files close when it returns as in the VM
+    static DEPTH: Cell<usize> = const { Cell::new(1) };
+    static FILES: RefCell<FileTable> = RefCell::new(FileTable::new());
+    #[cfg(feature = "objects")]
+    static REGISTRY: basil_objects::Registry = {
+        let mut reg = basil_objects::Registry::new();
+        basil_objects::register_objects(&mut reg);
+        reg
+    };
 }
 
-impl fmt::Display for Str {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) }
+/// Record the source line being executed, for runtime error messages.
+pub fn line(n: u32) { LINE.with(|l| l.set(n)); }
+
+/// Entered a FUNC or SUB; files it opens are closed when the returned guard drops.
+pub fn enter() -> FrameGuard {
+    DEPTH.with(|d| d.set(d.get() + 1));
+    FrameGuard(())
+}
+
+pub struct FrameGuard(());
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|d| { let v = d.get(); d.set(v - 1); v });
+        FILES.with(|f| f.borrow_mut().close_owned_by(depth));
+    }
 }
 
-#[derive(Clone)]
-pub enum Val { Int(i64), Bool(bool), Str(Str) /* , Obj(ObjHandle) */ }
+/// Report an uncaught error the way `basilc run` does and exit with status 1.
+pub fn fail(e: RtError) -> ! {
+    let _ = io::stdout().flush();
+    let line = LINE.with(|l| l.get());
+    if line > 0 { eprintln!("runtime error at line {}: {}", line, e); } else { eprintln!("runtime error: {}", e); }
+    std::process::exit(1);
+}
+
+pub fn raise(v: &Val) -> RtError { RtError { msg: v.to_string(), raised: true } }
+
+pub fn reraise(exc: &Option<String>) -> RtError {
+    match exc {
+        Some(msg) => RtError { msg: msg.clone(), raised: true },
+        None => RtError::new("Reraise without active exception"),
+    }
+}
+
+pub fn exit(code: &Val) -> RtResult<Flow> {
+    let code = to_i64(code)? as i32;
+    let _ = io::stdout().flush();
+    std::process::exit(code);
+}
+
+/// Print anything that displays like the VM's `Value`: a `Val`, or a typed value (`f64`,
+/// `i64`, `bool`, `String`) the emitted code keeps unboxed.
+pub fn print(v: &impl fmt::Display) -> RtResult<()> {
+    let mut out = io::stdout();
+    let _ = write!(out, "{}", v);
+    let _ = out.flush();
+    Ok(())
+}
+
+// ---- operators ----
+
+pub fn type_of(v: &Val) -> String { basil_builtins::type_of(v) }
+
+pub fn truthy(v: &Val) -> bool { basil_builtins::is_truthy(v) }
+
+fn as_num(v: &Val) -> RtResult<f64> {
+    match v {
+        Val::Num(n) => Ok(*n),
+        Val::Int(i) => Ok(*i as f64),
+        Val::Bool(b) => Ok(if *b { 1.0 } else { 0.0 }),
+        _ => Err(RtError::new("expected number")),
+    }
+}
+
+fn to_i64(v: &Val) -> RtResult<i64> { Ok(basil_builtins::to_i64(v)?) }
+
+pub fn add(a: &Val, b: &Val) -> RtResult<Val> {
+    match (a, b) {
+        (Val::Str(_), _) | (_, Val::Str(_)) => Ok(Val::Str(format!("{}{}", a, b))),
+        _ => Ok(Val::Num(as_num(a)? + as_num(b)?)),
+    }
+}
+
+pub fn sub(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Num(as_num(a)? - as_num(b)?)) }
+pub fn mul(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Num(as_num(a)? * as_num(b)?)) }
+pub fn div(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Num(as_num(a)? / as_num(b)?)) }
+pub fn modulo(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Num(as_num(a)? % as_num(b)?)) }
+pub fn neg(a: &Val) -> RtResult<Val> { Ok(Val::Num(-as_num(a)?)) }
+pub fn not(a: &Val) -> Val { Val::Bool(!truthy(a)) }
+
+fn numeric(v: &Val) -> bool { matches!(v, Val::Num(_) | Val::Int(_) | Val::Bool(_)) }
+
+pub fn eq(a: &Val, b: &Val) -> RtResult<Val> {
+    if numeric(a) && numeric(b) { return Ok(Val::Bool(as_num(a)? == as_num(b)?)); }
+    Ok(Val::Bool(a == b))
+}
+
+pub fn ne(a: &Val, b: &Val) -> RtResult<Val> {
+    if numeric(a) && numeric(b) { return Ok(Val::Bool(as_num(a)? != as_num(b)?)); }
+    Ok(Val::Bool(a != b))
+}
+
+pub fn lt(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Bool(as_num(a)? < as_num(b)?)) }
+pub fn le(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Bool(as_num(a)? <= as_num(b)?)) }
+pub fn gt(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Bool(as_num(a)? > as_num(b)?)) }
+pub fn ge(a: &Val, b: &Val) -> RtResult<Val> { Ok(Val::Bool(as_num(a)? >= as_num(b)?)) }
+
+pub fn to_int(v: &Val) -> RtResult<Val> {
+    match v {
+        Val::Int(i) => Ok(Val::Int(*i)),
+        Val::Num(n) => Ok(Val::Int(n.trunc() as i64)),
+        _ => Err(RtError::new("ToInt expects a numeric value")),
+    }
+}
 
-impl fmt::Display for Val {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Val::Int(i) => write!(f, "{}", i),
-            Val::Bool(b) => write!(f, "{}", b),
-            Val::Str(s) => write!(f, "{}", s),
+// ---- typed values ----
+// The emitted code keeps values whose type the IR knows in Rust locals of that type. These take
+// them back out of a `Val` an operation returned, for operations the IR proved return that type.
+
+pub fn unbox_num(v: Val) -> f64 {
+    match v { Val::Num(n) => n, Val::Int(i) => i as f64, Val::Bool(b) => if b { 1.0 } else { 0.0 }, _ => f64::NAN }
+}
+
+pub fn unbox_int(v: Val) -> i64 {
+    match v { Val::Int(i) => i, Val::Num(n) => n.trunc() as i64, _ => 0 }
+}
+
+pub fn unbox_bool(v: Val) -> bool { truthy(&v) }
+
+pub fn unbox_str(v: Val) -> String {
+    match v { Val::Str(s) => s, other => other.to_string() }
+}
+
+// ---- arrays ----
+
+pub fn new_array(elem: ElemType, uppers: &[Val]) -> RtResult<Val> {
+    if uppers.is_empty() || uppers.len() > 4 { return Err(RtError::new("array rank must be 1..4")); }
+    let mut dims = Vec::with_capacity(uppers.len());
+    let mut total: usize = 1;
+    for u in uppers {
+        let u = match u { Val::Int(i) => *i, Val::Num(n) => n.trunc() as i64, _ => return Err(RtError::new("array dimension must be numeric")) };
+        if u < 0 { return Err(RtError::new("array dimension upper bound must be >= 0")); }
+        dims.push(u as usize + 1);
+        total = total.saturating_mul(u as usize + 1);
+    }
+    let default = match &elem {
+        ElemType::Num => Val::Num(0.0),
+        ElemType::Int => Val::Int(0),
+        ElemType::Str => Val::Str(String::new()),
+        ElemType::Obj(Some(_)) => Val::Dict(Rc::new(RefCell::new(HashMap::new()))),
+        ElemType::Obj(None) => Val::Null,
+    };
+    Ok(Val::Array(Rc::new(ArrayObj { elem, dims, data: RefCell::new(vec![default; total]) })))
+}
+
+// Row-major offset of `idxs` in `arr`, with the VM's bounds checks
+fn offset(arr: &ArrayObj, idxs: &[Val]) -> RtResult<usize> {
+    if idxs.len() != arr.dims.len() { return Err(RtError::new("array rank mismatch")); }
+    let mut lin = 0usize;
+    for (len, idx) in arr.dims.iter().zip(idxs) {
+        let i = match idx { Val::Int(i) => *i, Val::Num(n) => n.trunc() as i64, _ => return Err(RtError::new("array index must be numeric")) };
+        if i < 0 || i as usize >= *len { return Err(RtError::new("array index out of bounds")); }
+        lin = lin * len + i as usize;
+    }
+    Ok(lin)
+}
+
+pub fn array_get(arr: &Val, idxs: &[Val]) -> RtResult<Val> {
+    let Val::Array(arr) = arr else { return Err(RtError::new("array access on non-array or not DIMed")) };
+    let lin = offset(arr, idxs)?;
+    let v = arr.data.borrow()[lin].clone();
+    Ok(v)
+}
+
+pub fn array_set(arr: &Val, idxs: &[Val], v: Val) -> RtResult<()> {
+    let Val::Array(arr) = arr else { return Err(RtError::new("array write on non-array or not DIMed")) };
+    let lin = offset(arr, idxs)?;
+    let v = match &arr.elem {
+        ElemType::Num => match v { Val::Num(n) => Val::Num(n), Val::Int(i) => Val::Num(i as f64), other => return Err(RtError::new(format!("cannot store non-numeric {:?} into numeric array", other))) },
+        ElemType::Int => match v { Val::Int(i) => Val::Int(i), Val::Num(n) => Val::Int(n.trunc() as i64), other => return Err(RtError::new(format!("cannot store non-numeric {:?} into integer array", other))) },
+        ElemType::Str => match v { Val::Str(s) => Val::Str(s), other => Val::Str(other.to_string()) },
+        ElemType::Obj(Some(tname)) => match v {
+            Val::Object(rc) => {
+                let got = rc.borrow().type_name().to_string();
+                if !got.eq_ignore_ascii_case(tname) { return Err(RtError::new(format!("Expected {} in typed object array, got {}.", tname, got))); }
+                Val::Object(rc)
+            }
+            Val::Null => Val::Null,
+            other => return Err(RtError::new(format!("cannot store non-object {:?} into typed OBJECT[] array", other))),
+        },
+        ElemType::Obj(None) => match v {
+            Val::Object(_) | Val::Null => v,
+            other => return Err(RtError::new(format!("cannot store non-object {:?} into OBJECT[] array", other))),
+        },
+    };
+    arr.data.borrow_mut()[lin] = v;
+    Ok(())
+}
+
+// ---- lists and dictionaries ----
+
+pub fn list(items: Vec<Val>) -> Val { Val::List(Rc::new(RefCell::new(items))) }
+
+pub fn dict(entries: Vec<(String, Val)>) -> Val { Val::Dict(Rc::new(RefCell::new(entries.into_iter().collect()))) }
+
+fn dict_key(v: &Val) -> RtResult<String> {
+    match v {
+        Val::Str(s) => Ok(s.clone()),
+        other => Err(RtError::new(format!("Dictionary key must be string, got {}", type_of(other)))),
+    }
+}
+
+pub fn index_get(target: &Val, index: &Val) -> RtResult<Val> {
+    match target {
+        Val::List(rc) => {
+            let idx = to_i64(index)?;
+            let v = rc.borrow();
+            if idx <= 0 || idx as usize > v.len() { return Err(RtError::new(format!("List index out of range: {}", idx))); }
+            Ok(v[idx as usize - 1].clone())
         }
+        Val::Dict(rc) => {
+            let key = dict_key(index)?;
+            let m = rc.borrow();
+            m.get(&key).cloned().ok_or_else(|| RtError::new(format!("Dictionary missing key: \"{}\"", key)))
+        }
+        _ => Err(RtError::new("Attempted [] on a non-list/dict value.")),
     }
 }
 
-#[derive(Debug)]
-pub struct RtError(String);
+pub fn index_set(target: &Val, index: &Val, v: Val) -> RtResult<()> {
+    match target {
+        Val::List(rc) => {
+            let idx = to_i64(index)?;
+            let mut items = rc.borrow_mut();
+            if idx <= 0 || idx as usize > items.len() { return Err(RtError::new(format!("List index out of range: {}", idx))); }
+            items[idx as usize - 1] = v;
+            Ok(())
+        }
+        Val::Dict(rc) => {
+            let key = dict_key(index)?;
+            rc.borrow_mut().insert(key, v);
+            Ok(())
+        }
+        _ => Err(RtError::new("Attempted [] on a non-list/dict value.")),
+    }
+}
 
-impl fmt::Display for RtError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) }
+/// What FOR EACH walks: the elements of an array or list, or the keys of a dictionary.
+pub fn items(v: &Val) -> RtResult<Val> {
+    match v {
+        Val::Array(arr) => Ok(list(arr.data.borrow().clone())),
+        Val::List(rc) => Ok(list(rc.borrow().clone())),
+        Val::Dict(rc) => Ok(list(rc.borrow().keys().map(|k| Val::Str(k.clone())).collect())),
+        other => Err(RtError::new(format!("FOR EACH expects an array or iterable object after IN (got TYPE={}).", type_of(other)))),
+    }
 }
 
-impl std::error::Error for RtError {}
+// ---- objects ----
 
-pub type RtResult<T> = Result<T, RtError>;
+pub fn new_object(type_name: &str, args: &[Val]) -> RtResult<Val> {
+    #[cfg(feature = "objects")]
+    { Ok(Val::Object(REGISTRY.with(|reg| reg.make(type_name, args))?)) }
+    #[cfg(not(feature = "objects"))]
+    {
+        let _ = args;
+        Err(RtError::new(format!("Type '{}' not available; rebuild with appropriate Cargo features.", type_name)))
+    }
+}
 
-pub fn print(v: &Val) -> RtResult<()> { print!("{}", v); Ok(()) }
-pub fn println(v: &Val) -> RtResult<()> { println!("{}", v); Ok(()) }
+pub fn prop_get(target: &Val, name: &str) -> RtResult<Val> {
+    match target {
+        Val::Object(rc) => Ok(rc.borrow().get_prop(name)?),
+        Val::Dict(rc) => rc.borrow().get(name).cloned().ok_or_else(|| RtError::new(format!("Dictionary missing key: \"{}\"", name))),
+        other => Err(RtError::new(format!("GETPROP on non-object/dict (got TYPE={})", type_of(other)))),
+    }
+}
 
-pub fn input_line(prompt: &Str) -> Str {
-    use std::io::{self, Write};
-    print!("{}", prompt);
-    let _ = io::stdout().flush();
-    let mut s = String::new();
-    let _ = io::stdin().read_line(&mut s);
-    if s.ends_with('\n') { s.pop(); if s.ends_with('\r') { s.pop(); } }
-    Str::from_string(s)
+pub fn prop_set(target: &Val, name: &str, v: Val) -> RtResult<()> {
+    match target {
+        Val::Object(rc) => Ok(rc.borrow_mut().set_prop(name, v)?),
+        Val::Dict(rc) => { rc.borrow_mut().insert(name.to_string(), v); Ok(()) }
+        _ => Err(RtError::new("SETPROP on non-object/dict")),
+    }
+}
+
+pub fn call_method(target: &Val, method: &str, args: &[Val]) -> RtResult<Val> {
+    match target {
+        Val::Object(rc) => Ok(rc.borrow_mut().call(method, args)?),
+        _ => Err(RtError::new("CALLMETHOD on non-object")),
+    }
+}
+
+// ---- builtins ----
+
+/// Call builtin `name` (as listed in `basil_ir::BUILTINS`) with evaluated arguments.
+pub fn builtin(name: &str, args: &[Val]) -> RtResult<Val> {
+    match name {
+        "INPUT$" => {
+            if args.len() > 1 { return Err(RtError::new("INPUT$ expects 0 or 1 argument")); }
+            if let Some(p) = args.first() { print(p)?; }
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).map_err(|e| RtError::new(format!("INPUT$ read error: {}", e)))?;
+            while line.ends_with('\n') || line.ends_with('\r') { line.pop(); }
+            Ok(Val::Str(line))
+        }
+        "STR2D_TO_ARRAY$" => Ok(basil_builtins::str2d_to_array(args)?),
+        _ => {
+            let depth = DEPTH.with(|d| d.get());
+            if let Some(r) = FILES.with(|f| f.borrow_mut().call(name, args, depth)) { return Ok(r?); }
+            match basil_builtins::lookup(name) {
+                Some(f) => Ok(f(args)?),
+                None => Err(RtError::new(format!("builtin {} is not available in this build", name))),
+            }
+        }
+    }
 }
 
 pub mod features {